@@ -0,0 +1,233 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use ring::digest;
+
+use crate::kvstore::{AnyStore, GcStats, KVFileStore};
+use crate::prelude::*;
+
+// A content-addressed store of individual files, so that identical files in different
+// wheels (or different envs' copies of the same wheel) can share disk space. When
+// WriteTreeFS has one, every file it writes goes into the store first, and then gets
+// linked into place from there.
+//
+// Entries are keyed by the file's sha256 plus whether it's executable, since hardlinks
+// share permissions. They're only ever written once, so deleting one (e.g. in GC) is
+// always safe: anything that was linked to it keeps its own link to the data, or its
+// own copy-on-write clone of it.
+
+/// How files get from the blob store into an env.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkMode {
+    /// Don't use the blob store at all; every env gets its own copy.
+    #[default]
+    Copy,
+    /// Hardlink to the blob. Uses no extra space, but every env sees the same file, so
+    /// if something edits one in place, it changes everywhere.
+    Hardlink,
+    /// Copy-on-write clone of the blob (FICLONE on Linux, clonefile on macOS). Only
+    /// works on filesystems that support it, like btrfs, XFS, and APFS.
+    Reflink,
+}
+
+pub struct BlobStore {
+    files: KVFileStore,
+    mode: LinkMode,
+}
+
+impl BlobStore {
+    pub fn new(base: &Path, mode: LinkMode) -> Result<BlobStore> {
+        if mode == LinkMode::Copy {
+            bail!("a blob store needs a link mode other than 'copy'");
+        }
+        Ok(BlobStore {
+            files: KVFileStore::new(base)?,
+            mode,
+        })
+    }
+
+    pub fn store(&self) -> AnyStore {
+        AnyStore::File(&self.files)
+    }
+
+    pub fn gc(&self, max_age: Duration) -> Result<GcStats> {
+        self.files.gc(max_age)
+    }
+
+    /// Puts `data` into the store (if it isn't there already), and then links it to
+    /// `dest`, which must not exist yet.
+    pub fn write(
+        &self,
+        dest: &Path,
+        data: &mut dyn Read,
+        executable: bool,
+    ) -> Result<()> {
+        let mut tmp = self.files.tempfile()?;
+        let mut hasher = digest::Context::new(&digest::SHA256);
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = crate::util::retry_interrupted(|| data.read(&mut buf))?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            tmp.write_all(&buf[..n])?;
+        }
+        let mut key = hasher.finish().as_ref().to_vec();
+        key.push(executable as u8);
+
+        let handle = self.files.lock(&key.as_slice())?;
+        if !handle.path().exists() {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = if executable { 0o755 } else { 0o644 };
+                tmp.as_file()
+                    .set_permissions(fs::Permissions::from_mode(mode))?;
+            }
+            tmp.as_file().sync_data()?;
+            tmp.persist(handle.path())?;
+        }
+        link_blob(handle.path(), dest, self.mode)
+    }
+}
+
+fn link_blob(blob: &Path, dest: &Path, mode: LinkMode) -> Result<()> {
+    match mode {
+        LinkMode::Copy => (),
+        LinkMode::Hardlink => match fs::hard_link(blob, dest) {
+            Ok(()) => return Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(err.into())
+            }
+            // different filesystems, or too many links already (an empty __init__.py
+            // can get there surprisingly fast)
+            Err(err) => trace!("couldn't hardlink {}: {err}", dest.display()),
+        },
+        LinkMode::Reflink => match reflink(blob, dest) {
+            Ok(()) => return Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(err.into())
+            }
+            Err(err) => trace!("couldn't reflink {}: {err}", dest.display()),
+        },
+    }
+    let mut src = File::open(blob)?;
+    let mut dest = create_new_like(&src, dest)?;
+    io::copy(&mut src, &mut dest)?;
+    Ok(())
+}
+
+// Creates dest with the same permissions as src
+fn create_new_like(src: &File, dest: &Path) -> io::Result<File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(src.metadata()?.permissions().mode());
+    }
+    #[cfg(not(unix))]
+    let _ = src;
+    options.open(dest)
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(blob: &Path, dest: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    // _IOW(0x94, 9, int), from linux/fs.h
+    const FICLONE: u32 = 0x40049409;
+    let src = File::open(blob)?;
+    let dest_file = create_new_like(&src, dest)?;
+    if unsafe { libc::ioctl(dest_file.as_raw_fd(), FICLONE as _, src.as_raw_fd()) } != 0
+    {
+        let err = io::Error::last_os_error();
+        drop(dest_file);
+        fs::remove_file(dest)?;
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn reflink(blob: &Path, dest: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    extern "C" {
+        fn clonefile(
+            src: *const libc::c_char,
+            dst: *const libc::c_char,
+            flags: u32,
+        ) -> libc::c_int;
+    }
+    let blob = CString::new(blob.as_os_str().as_bytes())?;
+    let dest = CString::new(dest.as_os_str().as_bytes())?;
+    if unsafe { clonefile(blob.as_ptr(), dest.as_ptr(), 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn reflink(_blob: &Path, _dest: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflinks aren't supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_blob_store() {
+        let tmp = tempfile::tempdir().unwrap();
+        for mode in [LinkMode::Hardlink, LinkMode::Reflink] {
+            let dir = tmp.path().join(format!("{mode:?}"));
+            let blobs = BlobStore::new(&dir.join("blobs"), mode).unwrap();
+            fs::create_dir_all(&dir).unwrap();
+            blobs
+                .write(&dir.join("a"), &mut &b"same"[..], false)
+                .unwrap();
+            blobs
+                .write(&dir.join("b"), &mut &b"same"[..], false)
+                .unwrap();
+            blobs
+                .write(&dir.join("c"), &mut &b"same"[..], true)
+                .unwrap();
+            blobs
+                .write(&dir.join("d"), &mut &b"different"[..], false)
+                .unwrap();
+            // same contents and permissions -> one blob; executable -> another
+            assert_eq!(blobs.store().stats().unwrap().entries, 3);
+            // (this one still leaves its blob behind, for GC to clean up)
+            assert!(blobs
+                .write(&dir.join("d"), &mut &b"again"[..], false)
+                .is_err());
+            for (name, contents) in [
+                ("a", "same"),
+                ("b", "same"),
+                ("c", "same"),
+                ("d", "different"),
+            ] {
+                assert_eq!(fs::read_to_string(dir.join(name)).unwrap(), contents);
+            }
+
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::{MetadataExt, PermissionsExt};
+                let meta = |name: &str| fs::metadata(dir.join(name)).unwrap();
+                assert_eq!(meta("c").permissions().mode() & 0o111, 0o111);
+                assert_eq!(meta("a").permissions().mode() & 0o111, 0);
+                if mode == LinkMode::Hardlink {
+                    assert_eq!(meta("a").ino(), meta("b").ino());
+                    assert_ne!(meta("a").ino(), meta("c").ino());
+                }
+            }
+        }
+    }
+}
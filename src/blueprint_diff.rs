@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+
+use crate::prelude::*;
+use crate::resolve::Blueprint;
+
+// What changed between two Blueprints, package by package, for telling the user what
+// 'posy update' (or anything else that re-locks) actually did. We only look at names
+// and versions: a new hash for the same version, or the metadata coming from a
+// different wheel, doesn't count as a change anyone needs to hear about. If someone
+// left a note on the old pin, we show it too, since it's probably why the pin was
+// where it was.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionChange {
+    Added(Version),
+    Removed(Version),
+    Changed { old: Version, new: Version },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageChange {
+    pub name: PackageName,
+    pub change: VersionChange,
+    // the old blueprint's annotation for this package, if any
+    pub note: Option<String>,
+}
+
+impl Display for PackageChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = self.name.as_given();
+        match &self.change {
+            VersionChange::Added(version) => write!(f, "{name}: (new) -> {version}"),
+            VersionChange::Removed(version) => {
+                write!(f, "{name}: {version} -> (removed)")
+            }
+            VersionChange::Changed { old, new } => write!(f, "{name}: {old} -> {new}"),
+        }?;
+        if let Some(note) = &self.note {
+            write!(f, " (was noted: {note})")?;
+        }
+        Ok(())
+    }
+}
+
+// Every package a blueprint pins -- the pybi, wheels, and local trees -- keyed by
+// normalized name, so the output comes out sorted.
+fn versions(blueprint: &Blueprint) -> BTreeMap<&str, (&PackageName, &Version)> {
+    let pybi = std::iter::once((&blueprint.pybi.name, &blueprint.pybi.version));
+    let wheels = blueprint
+        .wheels
+        .iter()
+        .map(|(pin, _)| (&pin.name, &pin.version));
+    let local = blueprint
+        .local
+        .iter()
+        .map(|(pin, _)| (&pin.name, &pin.version));
+    pybi.chain(wheels)
+        .chain(local)
+        .map(|(name, version)| (name.normalized(), (name, version)))
+        .collect()
+}
+
+/// Everything that's different between `old` and `new`, sorted by package name.
+pub fn diff(old: &Blueprint, new: &Blueprint) -> Vec<PackageChange> {
+    let notes = &old.annotations;
+    let old = versions(old);
+    let mut new = versions(new);
+    let mut changes = Vec::new();
+    for (key, (name, old_version)) in old {
+        let change = match new.remove(key) {
+            None => VersionChange::Removed(old_version.clone()),
+            Some((_, new_version)) if new_version != old_version => {
+                VersionChange::Changed {
+                    old: old_version.clone(),
+                    new: new_version.clone(),
+                }
+            }
+            Some(_) => continue,
+        };
+        changes.push(PackageChange {
+            name: name.clone(),
+            change,
+            note: notes.get(key).cloned(),
+        });
+    }
+    for (name, version) in new.into_values() {
+        changes.push(PackageChange {
+            name: name.clone(),
+            change: VersionChange::Added(version.clone()),
+            note: None,
+        });
+    }
+    changes.sort_by(|a, b| a.name.normalized().cmp(b.name.normalized()));
+    changes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resolve::{
+        PinnedPackage, WheelResolveMetadata, WheelResolveMetadataInner,
+    };
+
+    fn blueprint(pybi: &str, wheels: &[(&str, &str)]) -> Blueprint {
+        let pin = |name: &str, version: &str| PinnedPackage {
+            name: name.parse().unwrap(),
+            version: version.try_into().unwrap(),
+            hashes: vec![],
+            url: None,
+        };
+        let metadata = WheelResolveMetadata {
+            provenance: "test".into(),
+            inner: WheelResolveMetadataInner {
+                requires_dist: vec![],
+                requires_python: Default::default(),
+                extras: Default::default(),
+            },
+        };
+        Blueprint {
+            pybi: pin("cpython", pybi),
+            wheels: wheels
+                .iter()
+                .map(|(name, version)| (pin(name, version), metadata.clone()))
+                .collect(),
+            local: vec![],
+            marker_expressions: Default::default(),
+            python_fallback: None,
+            annotations: Default::default(),
+            claimed_publishers: Default::default(),
+            platform_tags: Default::default(),
+            source_builds: Default::default(),
+            warnings: Vec::new(),
+            policy: Default::default(),
+            python_flags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = blueprint(
+            "3.10.8",
+            &[
+                ("trio", "0.21.0"),
+                ("attrs", "22.2.0"),
+                ("Sniff_IO", "1.3.0"),
+            ],
+        );
+        let new = blueprint(
+            "3.10.8",
+            &[
+                ("sniff-io", "1.3.0"),
+                ("trio", "0.22.0"),
+                ("outcome", "1.2.0"),
+            ],
+        );
+        let mut old = old;
+        old.annotations
+            .insert("trio".into(), "0.22 needs a newer outcome".into());
+        old.annotations
+            .insert("sniff-io".into(), "unchanged, so not shown".into());
+        let lines = diff(&old, &new)
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                "attrs: 22.2.0 -> (removed)",
+                "outcome: (new) -> 1.2.0",
+                "trio: 0.21.0 -> 0.22.0 (was noted: 0.22 needs a newer outcome)",
+            ]
+        );
+
+        let newer_python = blueprint("3.11.1", &[]);
+        assert_eq!(
+            diff(&blueprint("3.10.8", &[]), &newer_python),
+            vec![PackageChange {
+                name: "cpython".parse().unwrap(),
+                change: VersionChange::Changed {
+                    old: "3.10.8".try_into().unwrap(),
+                    new: "3.11.1".try_into().unwrap(),
+                },
+                note: None,
+            }]
+        );
+        assert!(diff(&old, &old).is_empty());
+    }
+}
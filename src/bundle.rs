@@ -0,0 +1,536 @@
+use std::io;
+use std::path::Path;
+
+use crate::package_db::{ArtifactInfo, PackageDB, WheelBuilder};
+use crate::prelude::*;
+use crate::resolve::{Blueprint, PinnedPackage};
+use crate::trampolines::{FindPython, ScriptPlatform, TrampolineMaker};
+use crate::tree::{NiceSymlinkPaths, WriteTree};
+
+// Bundling a Blueprint into a PEP 441 zipapp (a .pyz file).
+//
+// A zipapp is just a zip file with a __main__.py at the top, optionally with a #!
+// line glued onto the front. Python puts the zip file itself on sys.path, and
+// zipimport can handle any pure-Python code we put in there. So the basic plan is
+// easy: take every wheel in the blueprint, unpack its purelib/platlib contents into
+// the root of the zip, and add a __main__.py that calls the requested entry point.
+//
+// The hard part is everything that *isn't* pure Python:
+//
+// - The interpreter itself: we don't try to bundle the pybi. The zipapp runs on
+//   whatever python the user invokes it with (or whatever the #! line finds).
+// - Binary wheels: zipimport can't load extension modules, and anyway we'd have to
+//   pick a single platform. So we only use pure-Python wheels (py3-none-any and
+//   friends). If a package only has an sdist, we try building it, and use the result
+//   if it turns out to be pure.
+// - Everything else: by default it's an error. With `exclude_binary`, we leave those
+//   packages out of the bundle with a warning, and it's up to the user to make sure
+//   they're importable from the python that ends up running the zipapp (e.g. by
+//   installing them there some other way).
+// - Scripts, headers, and other .data/ directories get dropped, since there's nowhere
+//   to put them. The only "script" in a zipapp is __main__.py.
+
+pub struct ZipappOptions<'a> {
+    /// Either "module:object", or the name of a console_scripts entry point in one of
+    /// the bundled wheels.
+    pub entry_point: &'a str,
+    /// The interpreter to put in the #! line, or None to leave it off entirely.
+    pub shebang: Option<&'a str>,
+    pub exclude_binary: bool,
+}
+
+pub struct ZipappReport {
+    pub bundled: Vec<PinnedPackage>,
+    pub excluded: Vec<PinnedPackage>,
+}
+
+// Wheel::unpack wants somewhere to put every category of file; we put everything
+// except libraries under a prefix that ZipappTree throws away.
+const LIB_PREFIX: &str = "lib";
+
+fn bundle_paths() -> HashMap<String, NicePathBuf> {
+    HashMap::from([
+        ("purelib".into(), LIB_PREFIX.try_into().unwrap()),
+        ("platlib".into(), LIB_PREFIX.try_into().unwrap()),
+        ("scripts".into(), "discard/scripts".try_into().unwrap()),
+        ("headers".into(), "discard/headers".try_into().unwrap()),
+        ("data".into(), "discard/data".try_into().unwrap()),
+    ])
+}
+
+struct ZipappTree<W: Write + Seek> {
+    z: zip::ZipWriter<W>,
+    // which package wrote each path, so that we can give a useful error if two wheels
+    // try to write the same file
+    owners: HashMap<String, String>,
+    // directories can legitimately be shared between wheels (e.g. namespace packages)
+    dirs: HashSet<String>,
+    current: String,
+}
+
+impl<W: Write + Seek> ZipappTree<W> {
+    fn new(w: W) -> ZipappTree<W> {
+        ZipappTree {
+            z: zip::ZipWriter::new(w),
+            owners: HashMap::new(),
+            dirs: HashSet::new(),
+            current: String::new(),
+        }
+    }
+
+    fn options(executable: bool) -> zip::write::FileOptions {
+        zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .unix_permissions(if executable { 0o755 } else { 0o644 })
+    }
+
+    // Returns None for paths that shouldn't end up in the zipapp
+    fn zip_path(&self, path: &NicePathBuf) -> Option<NicePathBuf> {
+        if path.pieces().first().map(|p| p.as_str()) == Some(LIB_PREFIX) {
+            Some(path.slice(1..))
+        } else {
+            trace!("not bundling {path}");
+            None
+        }
+    }
+
+    fn claim(&mut self, path: &NicePathBuf) -> Result<()> {
+        let key = path.to_string();
+        if let Some(prev) = self.owners.get(&key) {
+            bail!(
+                "{prev} and {} both contain {key}; can't bundle them together",
+                self.current
+            );
+        }
+        self.owners.insert(key, self.current.clone());
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> WriteTree for ZipappTree<W> {
+    fn mkdir(&mut self, path: &NicePathBuf) -> Result<()> {
+        if let Some(path) = self.zip_path(path) {
+            // the lib directory itself maps onto the zip root, which doesn't need
+            // creating
+            let key = path.to_string();
+            if !path.pieces().is_empty() && !self.dirs.contains(&key) {
+                self.z.add_directory(&key, Self::options(true))?;
+                self.dirs.insert(key);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_file(
+        &mut self,
+        path: &NicePathBuf,
+        data: &mut dyn Read,
+        executable: bool,
+    ) -> Result<()> {
+        if let Some(path) = self.zip_path(path) {
+            context!("Bundling {path}");
+            self.claim(&path)?;
+            self.z
+                .start_file(path.to_string(), Self::options(executable))?;
+            io::copy(data, &mut self.z)?;
+        }
+        Ok(())
+    }
+
+    fn write_symlink(&mut self, _symlink: &NiceSymlinkPaths) -> Result<()> {
+        bail!("symlinks can't be bundled into a zipapp");
+    }
+}
+
+fn main_py(module: &str, object: Option<&str>) -> Vec<u8> {
+    match object {
+        Some(object) => indoc::formatdoc! {r###"
+             # -*- coding: utf-8 -*-
+             # Generated by posy
+             import sys
+             import {module}
+             if __name__ == "__main__":
+                 sys.exit({module}.{object}())
+        "###},
+        // "python -m" semantics: run the module (or the package's __main__) as
+        // __main__, so its 'if __name__ == "__main__":' block actually runs
+        None => indoc::formatdoc! {r###"
+             # -*- coding: utf-8 -*-
+             # Generated by posy
+             import runpy
+             runpy.run_module("{module}", run_name="__main__", alter_sys=True)
+        "###},
+    }
+    .into()
+}
+
+// Returns the wheel to bundle for this pin, or None if it doesn't have a pure-Python
+// wheel (and we couldn't build one).
+fn pure_wheel_for(
+    db: &PackageDB,
+    pure_platform: &WheelPlatform,
+    wheel_builder: &WheelBuilder,
+    pin: &PinnedPackage,
+) -> Result<Option<Wheel>> {
+    let ais = db.artifacts_for_version(&pin.name, &pin.version)?;
+    let pinned = |ai: &&ArtifactInfo| match &ai.hash {
+        Some(hash) => pin.hashes.contains(hash),
+        None => false,
+    };
+    let best = ais
+        .iter()
+        .filter(pinned)
+        .filter_map(|ai| {
+            let name = ai.name.inner_as::<WheelName>()?;
+            pure_platform
+                .binary_compatibility(name)
+                .map(|score| (ai, score))
+        })
+        .max_by_key(|(_, score)| *score);
+    if let Some((ai, _)) = best {
+        context!("Fetching {}", ai.url);
+        return Ok(Some(db.get_artifact::<Wheel>(ai)?));
+    }
+    if let Some(sdist_ai) = ais.iter().filter(pinned).find(|ai| ai.is::<Sdist>()) {
+        // unwrap is OK b/c we know this is an sdist
+        match db
+            .get_locally_built_binary::<Wheel>(sdist_ai, wheel_builder, pure_platform)
+            .unwrap()
+        {
+            Ok(wheel) => return Ok(Some(wheel)),
+            Err(err) => {
+                debug!("building {} didn't give a pure wheel: {err}", sdist_ai.name)
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn find_console_script(
+    wheels: &[Wheel],
+    name: &str,
+) -> Result<Option<(String, Option<String>)>> {
+    for wheel in wheels {
+        if let Some(entries) = wheel.entry_points()?.get("console_scripts") {
+            if let Some(entry) = entries.iter().find(|e| e.name == name) {
+                return Ok(Some((entry.module.clone(), entry.object.clone())));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Writes a PEP 441 zipapp to `dest`, containing all the wheels in `blueprint` plus a
+/// __main__.py that runs the requested entry point. See the comment at the top of
+/// this file for how non-pure-Python packages are handled.
+pub fn write_zipapp(
+    db: &PackageDB,
+    blueprint: &Blueprint,
+    options: &ZipappOptions,
+    dest: &Path,
+) -> Result<ZipappReport> {
+    context!("Bundling zipapp {}", dest.display());
+    let pure_platform = WheelPlatform::pure_python(&blueprint.pybi.version)?;
+    // XX TODO: we could build these as regular (non-editable) wheels and bundle them
+    if let Some((pin, _)) = blueprint.local.first() {
+        bail!(
+            "can't bundle {} from the local tree at {} (yet)",
+            pin.name.as_given(),
+            pin.tree.path.display()
+        );
+    }
+    let wheel_builder = WheelBuilder::new(
+        db,
+        &blueprint.pybi.name,
+        &blueprint.pybi.version,
+        PybiPlatform::native_platforms()?,
+        &[],
+    )?;
+
+    let mut wheels = Vec::new();
+    let mut report = ZipappReport {
+        bundled: Vec::new(),
+        excluded: Vec::new(),
+    };
+    for (pin, _) in &blueprint.wheels {
+        db.cancellation_token().check()?;
+        match pure_wheel_for(db, &pure_platform, &wheel_builder, pin)? {
+            Some(wheel) => {
+                wheels.push(wheel);
+                report.bundled.push(pin.clone());
+            }
+            None => report.excluded.push(pin.clone()),
+        }
+    }
+    if !report.excluded.is_empty() {
+        let names = report
+            .excluded
+            .iter()
+            .map(|pin| format!("{} {}", pin.name.as_given(), pin.version))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if options.exclude_binary {
+            warn!(
+                "leaving out packages with no pure-Python wheels: {names}. These will \
+                 need to be installed separately wherever the zipapp runs."
+            );
+        } else {
+            bail!(
+                "can't bundle packages that don't have pure-Python wheels: {names}\n\
+                 (use --exclude-binary to leave them out of the zipapp)"
+            );
+        }
+    }
+
+    let (module, object) =
+        if let Some((module, object)) = options.entry_point.split_once(':') {
+            (module.trim().to_string(), Some(object.trim().to_string()))
+        } else if let Some(found) = find_console_script(&wheels, options.entry_point)? {
+            found
+        } else {
+            bail!(
+            "{:?} isn't a 'module:object' reference or a console script in any bundled \
+             package",
+            options.entry_point
+        );
+        };
+
+    let mut tree = ZipappTree::new(io::Cursor::new(Vec::<u8>::new()));
+    // The trampolines all get discarded along with the rest of scripts/, so the
+    // cheapest one will do.
+    let trampoline_maker =
+        TrampolineMaker::new(FindPython::FromEnv, ScriptPlatform::Unix);
+    let paths = bundle_paths();
+    for wheel in &wheels {
+        tree.current = wheel.name().distribution.as_given().to_string();
+        wheel.unpack(&paths, &trampoline_maker, &mut tree)?;
+    }
+    tree.current = "posy's generated __main__.py".into();
+    let main = main_py(&module, object.as_deref());
+    tree.write_file(
+        &format!("{LIB_PREFIX}/__main__.py").as_str().try_into()?,
+        &mut main.as_slice(),
+        false,
+    )?;
+    let zip_bytes = tree.z.finish()?.into_inner();
+
+    // write to a tempfile + rename, so we never leave a half-written zipapp behind
+    let dest_dir = match dest.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    let mut out = tempfile::NamedTempFile::new_in(dest_dir)?;
+    if let Some(shebang) = options.shebang {
+        writeln!(out, "#!{shebang}")?;
+    }
+    out.write_all(&zip_bytes)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(out.path(), std::fs::Permissions::from_mode(0o755))?;
+    }
+    out.persist(dest)?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resolve::{WheelResolveMetadata, WheelResolveMetadataInner};
+    use crate::test_util::{index_page as page, with_index_db};
+    use std::io::Cursor;
+
+    fn wheel_bytes(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut z = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in files {
+            z.start_file(*name, Default::default()).unwrap();
+            z.write_all(contents.as_bytes()).unwrap();
+        }
+        z.finish().unwrap().into_inner()
+    }
+
+    fn sha256(data: &[u8]) -> ArtifactHash {
+        let digest = ring::digest::digest(&ring::digest::SHA256, data);
+        format!("sha256={}", data_encoding::HEXLOWER.encode(digest.as_ref()))
+            .parse()
+            .unwrap()
+    }
+
+    fn pin(name: &str, hash: &ArtifactHash) -> (PinnedPackage, WheelResolveMetadata) {
+        let pin = PinnedPackage {
+            name: name.parse().unwrap(),
+            version: "1.0".try_into().unwrap(),
+            hashes: vec![hash.clone()],
+            url: None,
+        };
+        let metadata = WheelResolveMetadata {
+            provenance: "test".into(),
+            inner: WheelResolveMetadataInner {
+                requires_dist: vec![],
+                requires_python: Default::default(),
+                extras: Default::default(),
+            },
+        };
+        (pin, metadata)
+    }
+
+    fn blueprint(wheels: Vec<(PinnedPackage, WheelResolveMetadata)>) -> Blueprint {
+        Blueprint {
+            pybi: PinnedPackage {
+                name: "cpython".parse().unwrap(),
+                version: "3.11.2".try_into().unwrap(),
+                hashes: vec![],
+                url: None,
+            },
+            wheels,
+            local: vec![],
+            marker_expressions: Default::default(),
+            python_fallback: None,
+            annotations: Default::default(),
+            claimed_publishers: Default::default(),
+            platform_tags: Default::default(),
+            source_builds: Default::default(),
+            warnings: Vec::new(),
+            policy: Default::default(),
+            python_flags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_write_zipapp() {
+        let foo = wheel_bytes(&[
+            ("foo/__init__.py", ""),
+            ("foo/__main__.py", "print('hi')\n"),
+            ("foo-1.0.data/scripts/foo-script", "#!python\n"),
+            (
+                "foo-1.0.dist-info/METADATA",
+                "Metadata-Version: 2.1\nName: foo\nVersion: 1.0\n",
+            ),
+            (
+                "foo-1.0.dist-info/WHEEL",
+                "Wheel-Version: 1.0\nRoot-Is-Purelib: true\n",
+            ),
+            (
+                "foo-1.0.dist-info/entry_points.txt",
+                "[console_scripts]\nfoo-cli = foo\nfoo-main = foo.cli:main\n",
+            ),
+            ("foo-1.0.dist-info/RECORD", ""),
+        ]);
+        let foo_hash = sha256(&foo);
+        let bar_hash = sha256(b"not really a wheel");
+        let foo_link = format!(r#"href="foo-1.0-py3-none-any.whl#{foo_hash}""#);
+        let bar_link = format!(
+            r#"href="bar-1.0-cp311-cp311-manylinux_2_17_x86_64.whl#{bar_hash}""#
+        );
+        let foo_response = http::Response::builder().body(foo).unwrap();
+        let routes = vec![
+            ("/simple/foo/", page(&[&foo_link])),
+            ("/simple/foo/foo-1.0-py3-none-any.whl", foo_response),
+            ("/simple/bar/", page(&[&bar_link])),
+        ];
+        with_index_db(routes, |db| {
+            let tmp = tempfile::tempdir().unwrap();
+            let dest = tmp.path().join("app.pyz");
+            let read_zipapp = || {
+                let bytes = std::fs::read(&dest).unwrap();
+                assert!(bytes.starts_with(b"#!/usr/bin/env python3\n"));
+                zip::ZipArchive::new(Cursor::new(bytes)).unwrap()
+            };
+            let options = |entry_point, exclude_binary| ZipappOptions {
+                entry_point,
+                shebang: Some("/usr/bin/env python3"),
+                exclude_binary,
+            };
+
+            // bar only has a binary wheel, so it can't go in...
+            let both = blueprint(vec![pin("foo", &foo_hash), pin("bar", &bar_hash)]);
+            let err = write_zipapp(db, &both, &options("foo-cli", false), &dest)
+                .err()
+                .unwrap();
+            assert!(format!("{err:#}").contains("bar 1.0"));
+            assert!(!dest.exists());
+            // ...unless we're told to leave it out
+            let report =
+                write_zipapp(db, &both, &options("foo-cli", true), &dest).unwrap();
+            assert_eq!(report.bundled[0].name, "foo".parse().unwrap());
+            assert_eq!(report.excluded[0].name, "bar".parse().unwrap());
+
+            let mut z = read_zipapp();
+            let mut names = z.file_names().map(String::from).collect::<Vec<_>>();
+            names.sort();
+            assert_eq!(
+                names,
+                vec![
+                    "__main__.py",
+                    "foo-1.0.dist-info/INSTALLER",
+                    "foo-1.0.dist-info/METADATA",
+                    "foo-1.0.dist-info/RECORD",
+                    "foo-1.0.dist-info/WHEEL",
+                    "foo-1.0.dist-info/entry_points.txt",
+                    "foo/__init__.py",
+                    "foo/__main__.py",
+                ]
+            );
+            let mut main = String::new();
+            z.by_name("__main__.py")
+                .unwrap()
+                .read_to_string(&mut main)
+                .unwrap();
+            // a console script without an object runs the module like 'python -m'
+            assert!(main.contains(
+                r#"runpy.run_module("foo", run_name="__main__", alter_sys=True)"#
+            ));
+
+            let just_foo = blueprint(vec![pin("foo", &foo_hash)]);
+            write_zipapp(db, &just_foo, &options("foo-main", false), &dest).unwrap();
+            let mut main = String::new();
+            read_zipapp()
+                .by_name("__main__.py")
+                .unwrap()
+                .read_to_string(&mut main)
+                .unwrap();
+            assert!(main.contains("import foo.cli\n"));
+            assert!(main.contains("sys.exit(foo.cli.main())"));
+
+            assert!(
+                write_zipapp(db, &just_foo, &options("nope", false), &dest).is_err()
+            );
+        });
+    }
+
+    #[test]
+    fn test_zipapp_tree() {
+        let mut tree = ZipappTree::new(Cursor::new(Vec::new()));
+        tree.current = "foo".into();
+        tree.mkdir(&"lib/foo".try_into().unwrap()).unwrap();
+        tree.write_file(&"lib/foo/x.py".try_into().unwrap(), &mut &b""[..], false)
+            .unwrap();
+        // anything outside lib/ gets dropped
+        tree.write_file(
+            &"discard/scripts/foo".try_into().unwrap(),
+            &mut &b""[..],
+            true,
+        )
+        .unwrap();
+        tree.current = "bar".into();
+        // shared directories are fine, shared files aren't
+        tree.mkdir(&"lib/foo".try_into().unwrap()).unwrap();
+        let err = tree
+            .write_file(&"lib/foo/x.py".try_into().unwrap(), &mut &b""[..], false)
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("foo and bar both contain foo/x.py"));
+        let symlink = NiceSymlinkPaths {
+            source: "lib/foo/link".try_into().unwrap(),
+            target: "x.py".into(),
+        };
+        assert!(tree.write_symlink(&symlink).is_err());
+
+        let z = zip::ZipArchive::new(tree.z.finish().unwrap()).unwrap();
+        let mut names = z.file_names().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["foo/", "foo/x.py"]);
+    }
+}
@@ -0,0 +1,47 @@
+use clap::Args;
+
+use super::lock::{make_lockfile, write_lockfile, LockMode};
+use super::{EnvArgs, PlatformArgs, Session};
+use crate::config_edit::ConfigEditor;
+use crate::prelude::*;
+
+#[derive(Args)]
+pub struct AddArgs {
+    /// What to add, e.g. 'numpy >= 1.24'. Replaces any requirement the project already
+    /// has on the same package.
+    #[arg(required = true, value_name = "REQUIREMENT")]
+    requirements: Vec<String>,
+    #[command(flatten)]
+    env: EnvArgs,
+    #[command(flatten)]
+    platform: PlatformArgs,
+}
+
+impl AddArgs {
+    pub fn run(self, session: &Session) -> Result<()> {
+        let project = session.require_project()?;
+        let mut editor = ConfigEditor::open(&project.root)?;
+        let mut changed = Vec::new();
+        for req in &self.requirements {
+            let req: UserRequirement = req.parse()?;
+            changed.push(req.name.clone());
+            match editor.add_requirement(&req)? {
+                Some(old) => info!("Replacing {old:?} with {:?}", req.to_string()),
+                None => info!("Adding {:?}", req.to_string()),
+            }
+        }
+        // resolve before saving anything, so if the new requirements can't be
+        // satisfied, nothing changes
+        let new_project = editor.project()?;
+        let lockfile = make_lockfile(
+            session,
+            &new_project,
+            &self.env,
+            &self.platform,
+            LockMode::Incremental(&changed),
+        )?;
+        editor.save(session.transcript.as_deref())?;
+        info!("Wrote {}", editor.path().display());
+        write_lockfile(session, &new_project, &lockfile)
+    }
+}
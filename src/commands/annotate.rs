@@ -0,0 +1,28 @@
+use clap::Args;
+
+use super::lock::write_lockfile;
+use super::Session;
+use crate::prelude::*;
+
+#[derive(Args)]
+pub struct AnnotateArgs {
+    /// The package whose pin this is about.
+    #[arg(value_name = "PACKAGE")]
+    package: String,
+    /// What to say, e.g. 'capped below 2.0 until #123 is fixed'. Leave it out to remove
+    /// the note that's there now.
+    #[arg(value_name = "NOTE")]
+    note: Option<String>,
+}
+
+impl AnnotateArgs {
+    pub fn run(self, session: &Session) -> Result<()> {
+        let project = session.require_project()?;
+        let mut lockfile = project
+            .read_lockfile()?
+            .ok_or_else(|| eyre!("there's no posy.lock yet; try 'posy lock' first"))?;
+        let name: PackageName = self.package.parse()?;
+        lockfile.annotate(&name, self.note.as_deref())?;
+        write_lockfile(session, project, &lockfile)
+    }
+}
@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+
+use clap::Args;
+
+use super::{BriefArgs, PlatformArgs, Session};
+use crate::bundle::{write_zipapp, ZipappOptions};
+use crate::prelude::*;
+
+#[derive(Args)]
+pub struct BundleArgs {
+    #[command(flatten)]
+    brief: BriefArgs,
+    #[command(flatten)]
+    platform: PlatformArgs,
+    /// Write a PEP 441 zipapp to this path (e.g. 'app.pyz').
+    #[arg(long, value_name = "PATH")]
+    zipapp: PathBuf,
+    /// What to run: either 'module:function', or the name of a console script
+    /// provided by one of the requirements.
+    #[arg(long, value_name = "ENTRY-POINT")]
+    entry_point: String,
+    /// Interpreter for the zipapp's #! line. Pass an empty string to leave it off.
+    #[arg(long, default_value = "/usr/bin/env python3")]
+    shebang: String,
+    /// Leave out packages that don't have pure-Python wheels, instead of failing.
+    /// They'll have to be installed separately wherever the zipapp runs.
+    #[arg(long)]
+    exclude_binary: bool,
+}
+
+impl BundleArgs {
+    pub fn run(self, session: &Session) -> Result<()> {
+        let db = session.package_db()?;
+        let brief = self.brief.brief(session)?;
+        let blueprint = brief.resolve(&db, &self.platform.platforms()?, None, &[])?;
+        let options = ZipappOptions {
+            entry_point: &self.entry_point,
+            shebang: if self.shebang.is_empty() {
+                None
+            } else {
+                Some(&self.shebang)
+            },
+            exclude_binary: self.exclude_binary,
+        };
+        let report = write_zipapp(&db, &blueprint, &options, &self.zipapp)?;
+        info!(
+            "Wrote {} with {} packages",
+            self.zipapp.display(),
+            report.bundled.len()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use clap::{Args, Subcommand};
+
+use super::Session;
+use crate::kvstore::{gc_cutoff, AnyStore, GcStats, Prune, StoreStats, LAST_USED_DAYS};
+use crate::output::{human_size, parse_size, Table};
+use crate::package_db::PackageDB;
+use crate::prelude::*;
+
+#[derive(Args)]
+pub struct CacheCommandArgs {
+    #[command(subcommand)]
+    command: CacheCommand,
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Show how many entries each cache has, how much space they take up, and how
+    /// many were last used less than 1, 7, or 30 days ago (see 'posy gc').
+    Info,
+    /// Delete cache entries. With no options, empties every cache; it's all
+    /// re-downloaded or rebuilt as needed. Caches that another posy process is using
+    /// are left alone.
+    Clean(CleanArgs),
+}
+
+#[derive(Args)]
+struct CleanArgs {
+    /// Only clean this cache (see 'posy cache info' for the names). Can be given
+    /// multiple times.
+    #[arg(long = "store", value_name = "NAME")]
+    stores: Vec<String>,
+    /// Only delete entries that haven't been used in this many days.
+    #[arg(long, value_name = "DAYS")]
+    max_age: Option<u64>,
+    /// Delete the least recently used entries until each cache fits in this much
+    /// space, e.g. "500M" or "2G".
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_size: Option<u64>,
+}
+
+impl CacheCommandArgs {
+    pub fn run(self, session: &Session) -> Result<()> {
+        match self.command {
+            CacheCommand::Info => info(session),
+            CacheCommand::Clean(args) => clean(session, args),
+        }
+    }
+}
+
+fn all_stores<'a>(
+    db: &'a PackageDB,
+    session: &'a Session,
+) -> Vec<(&'static str, AnyStore<'a>)> {
+    let mut stores = db.cache_stores();
+    stores.extend(session.env_forest.stores());
+    stores
+}
+
+fn info(session: &Session) -> Result<()> {
+    let db = session.package_db()?;
+    let stores = all_stores(&db, session)
+        .into_iter()
+        .map(|(name, store)| Ok((name, store.stats()?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut header = vec!["store".to_string(), "entries".into(), "size".into()];
+    header.extend(LAST_USED_DAYS.iter().map(|days| format!("<{days}d")));
+    header.push("older".into());
+    let mut table = Table::new(header);
+    let row = |name: &str, stats: &StoreStats| {
+        let mut row = vec![
+            name.to_string(),
+            stats.entries.to_string(),
+            human_size(stats.bytes),
+        ];
+        row.extend(stats.last_used.iter().map(|count| count.to_string()));
+        row
+    };
+    let mut total = StoreStats::default();
+    for (name, stats) in &stores {
+        table.push_row(row(name, stats));
+        total += *stats;
+    }
+    table.push_row(row("total", &total));
+    table.print();
+    Ok(())
+}
+
+fn clean(session: &Session, args: CleanArgs) -> Result<()> {
+    let db = session.package_db()?;
+    let stores = all_stores(&db, session);
+    for name in &args.stores {
+        if !stores.iter().any(|(n, _)| n == name) {
+            let names: Vec<_> = stores.iter().map(|(n, _)| *n).collect();
+            bail!("no cache called {name:?} (options: {})", names.join(", "));
+        }
+    }
+    let prune = match (args.max_age, args.max_size) {
+        (None, None) => Prune {
+            unused_since: None,
+            max_bytes: Some(0),
+        },
+        (max_age, max_bytes) => Prune {
+            unused_since: max_age
+                .map(|days| gc_cutoff(Duration::from_secs(days * 24 * 60 * 60))),
+            max_bytes,
+        },
+    };
+    let mut total = GcStats::default();
+    for (name, store) in stores {
+        if !args.stores.is_empty() && !args.stores.iter().any(|n| n == name) {
+            continue;
+        }
+        let stats = store.prune(&prune)?;
+        if stats.skipped_stores > 0 {
+            warn!("{name} is in use by another posy process, so we left it alone");
+        } else {
+            debug!("{name}: removed {}, kept {}", stats.removed, stats.kept);
+        }
+        total += stats;
+    }
+    info!("Removed {} entries, kept {}", total.removed, total.kept);
+    Ok(())
+}
@@ -0,0 +1,231 @@
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+
+use super::{check_size_budget, Session};
+use crate::output::{human_size, Table};
+use crate::prelude::*;
+use crate::util::tree_size;
+
+#[derive(Args)]
+pub struct EnvCommandArgs {
+    #[command(subcommand)]
+    command: EnvCommand,
+}
+
+#[derive(Subcommand)]
+enum EnvCommand {
+    /// Remove packages that were installed into the project's Python behind posy's
+    /// back (e.g. with 'pip install --break-system-packages'), by restoring a pristine
+    /// copy of it.
+    CleanForeign(CleanForeignArgs),
+    /// Copy the project's environment into a standalone directory that runs without
+    /// posy, e.g. to zip up and ship to another machine
+    Export(ExportArgs),
+    /// Print a digest that identifies the project's environment on this machine, e.g.
+    /// to use as a CI cache key. It changes exactly when the installed files would.
+    Identity(IdentityArgs),
+    /// Put back everything from a 'posy env snapshot' file, so installing the
+    /// environment doesn't have to download or unpack its python or packages (it
+    /// still needs the package index, or posy's cache of it, to look them up)
+    Restore(RestoreArgs),
+    /// List the commands the project's environment provides, from its packages' entry
+    /// points
+    Scripts(ScriptsArgs),
+    /// Install the project's environment, and save everything it's made of into one
+    /// file, e.g. to bake into a CI image and 'posy env restore' there
+    Snapshot(SnapshotArgs),
+}
+
+#[derive(Args)]
+struct CleanForeignArgs {}
+
+#[derive(Args)]
+struct ExportArgs {
+    /// Where to put it (must be empty or not exist yet)
+    dest: PathBuf,
+}
+
+#[derive(Args)]
+struct IdentityArgs {}
+
+#[derive(Args)]
+struct RestoreArgs {
+    /// The file 'posy env snapshot' wrote
+    file: PathBuf,
+}
+
+#[derive(Args)]
+struct ScriptsArgs {
+    /// Print them as JSON on stdout, instead of as a table.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct SnapshotArgs {
+    /// Where to write it (a .tar.gz)
+    file: PathBuf,
+}
+
+impl EnvCommandArgs {
+    pub fn run(self, session: &Session) -> Result<()> {
+        match self.command {
+            EnvCommand::CleanForeign(args) => args.run(session),
+            EnvCommand::Export(args) => args.run(session),
+            EnvCommand::Identity(args) => args.run(session),
+            EnvCommand::Restore(args) => args.run(session),
+            EnvCommand::Scripts(args) => args.run(session),
+            EnvCommand::Snapshot(args) => args.run(session),
+        }
+    }
+}
+
+impl CleanForeignArgs {
+    fn run(self, session: &Session) -> Result<()> {
+        let project = session.require_project()?;
+        let lockfile = project.read_lockfile()?.ok_or_else(|| {
+            eyre!(
+                "no lockfile at {}; run 'posy lock' first",
+                project.lockfile_path().display()
+            )
+        })?;
+        let db = session.package_db()?;
+        db.use_locked_builds(&lockfile.builds)?;
+        let platforms = PybiPlatform::native_platforms()?;
+        let blueprint = lockfile.blueprint_for(platforms);
+        let removed = session
+            .env_forest
+            .clean_foreign(&db, blueprint, platforms)?;
+        if removed.is_empty() {
+            info!("No foreign packages found");
+        } else {
+            info!(
+                "Restored {} {}, removing: {}",
+                blueprint.pybi.name.as_given(),
+                blueprint.pybi.version,
+                removed.join(", ")
+            );
+        }
+        Ok(())
+    }
+}
+
+impl ExportArgs {
+    fn run(self, session: &Session) -> Result<()> {
+        let project = session.require_project()?;
+        let lockfile = project.read_lockfile()?.ok_or_else(|| {
+            eyre!(
+                "no lockfile at {}; run 'posy lock' first",
+                project.lockfile_path().display()
+            )
+        })?;
+        let db = session.package_db()?;
+        db.use_locked_builds(&lockfile.builds)?;
+        let platforms = PybiPlatform::native_platforms()?;
+        let blueprint = lockfile.blueprint_for(platforms);
+        let env = session.env_forest.get_env(&db, blueprint, platforms, &[])?;
+        env.export(&self.dest)?;
+        check_size_budget(&project.config, "The exported environment", || {
+            tree_size(&self.dest)
+        })?;
+        info!(
+            "Exported to {}; run it with {}",
+            self.dest.display(),
+            self.dest
+                .join(env.python.strip_prefix(&env.pybi_root)?)
+                .display()
+        );
+        Ok(())
+    }
+}
+
+impl IdentityArgs {
+    fn run(self, session: &Session) -> Result<()> {
+        let project = session.require_project()?;
+        let lockfile = project.read_lockfile()?.ok_or_else(|| {
+            eyre!(
+                "no lockfile at {}; run 'posy lock' first",
+                project.lockfile_path().display()
+            )
+        })?;
+        let platforms = PybiPlatform::native_platforms()?;
+        let blueprint = lockfile.blueprint_for(platforms);
+        // XX TODO: this is the most-preferred native platform, not necessarily the one
+        // the pybi we'd install is for, so a machine that could share an env with
+        // another might still get a different identity
+        let platform = platforms
+            .first()
+            .ok_or_else(|| eyre!("no native platforms?"))?;
+        println!("{}", blueprint.identity(platform.core_tag()));
+        Ok(())
+    }
+}
+
+impl RestoreArgs {
+    fn run(self, session: &Session) -> Result<()> {
+        let restored = session.env_forest.restore_snapshot(&self.file)?;
+        info!(
+            "Restored {restored} store entries from {}",
+            self.file.display()
+        );
+        Ok(())
+    }
+}
+
+impl ScriptsArgs {
+    fn run(self, session: &Session) -> Result<()> {
+        let project = session.require_project()?;
+        let lockfile = project.read_lockfile()?.ok_or_else(|| {
+            eyre!(
+                "no lockfile at {}; run 'posy lock' first",
+                project.lockfile_path().display()
+            )
+        })?;
+        let db = session.package_db()?;
+        db.use_locked_builds(&lockfile.builds)?;
+        let platforms = PybiPlatform::native_platforms()?;
+        let blueprint = lockfile.blueprint_for(platforms);
+        let env = session.env_forest.get_env(&db, blueprint, platforms, &[])?;
+        let scripts = env.scripts()?;
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&scripts)?);
+            return Ok(());
+        }
+        let mut table = Table::new(["script", "package", "entry point", "kind"]);
+        for script in scripts {
+            table.push_row([
+                script.name,
+                script.package,
+                script.entry_point,
+                if script.gui { "gui" } else { "console" }.into(),
+            ]);
+        }
+        table.print();
+        Ok(())
+    }
+}
+
+impl SnapshotArgs {
+    fn run(self, session: &Session) -> Result<()> {
+        let project = session.require_project()?;
+        let lockfile = project.read_lockfile()?.ok_or_else(|| {
+            eyre!(
+                "no lockfile at {}; run 'posy lock' first",
+                project.lockfile_path().display()
+            )
+        })?;
+        let db = session.package_db()?;
+        db.use_locked_builds(&lockfile.builds)?;
+        let platforms = PybiPlatform::native_platforms()?;
+        let blueprint = lockfile.blueprint_for(platforms);
+        let env = session.env_forest.get_env(&db, blueprint, platforms, &[])?;
+        let entries = session.env_forest.snapshot(&env, &self.file)?;
+        info!(
+            "Saved {entries} store entries to {} ({})",
+            self.file.display(),
+            human_size(std::fs::metadata(&self.file)?.len())
+        );
+        Ok(())
+    }
+}
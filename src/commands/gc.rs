@@ -0,0 +1,32 @@
+use std::time::Duration;
+
+use clap::Args;
+
+use super::Session;
+use crate::prelude::*;
+
+#[derive(Args)]
+pub struct GcArgs {
+    /// Delete anything that hasn't been used in this many days.
+    #[arg(long, value_name = "DAYS", default_value_t = 30)]
+    max_age: u64,
+}
+
+impl GcArgs {
+    pub fn run(self, session: &Session) -> Result<()> {
+        let max_age = Duration::from_secs(self.max_age * 24 * 60 * 60);
+        let mut stats = session.env_forest.gc(max_age)?;
+        stats += session.package_db()?.gc(max_age)?;
+        info!(
+            "Removed {} unused entries, kept {}",
+            stats.removed, stats.kept
+        );
+        if stats.skipped_stores > 0 {
+            warn!(
+                "{} stores are in use by other posy processes, and were left alone",
+                stats.skipped_stores
+            );
+        }
+        Ok(())
+    }
+}
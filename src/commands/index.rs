@@ -0,0 +1,118 @@
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+
+use super::Session;
+use crate::prelude::*;
+
+#[derive(Args)]
+pub struct IndexCommandArgs {
+    #[command(subcommand)]
+    command: IndexCommand,
+}
+
+#[derive(Subcommand)]
+enum IndexCommand {
+    /// Create or refresh an index snapshot (for --index-snapshot) containing an
+    /// allowlist of packages. Meant to be run on a schedule.
+    Sync(SyncArgs),
+}
+
+#[derive(Args)]
+struct SyncArgs {
+    /// File listing the packages to include, one name per line. Anything else already
+    /// in the snapshot gets dropped.
+    #[arg(long, value_name = "FILE")]
+    allowlist: PathBuf,
+    /// Also save metadata for each package's N newest releases.
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    metadata_versions: usize,
+    /// The snapshot directory to create or update.
+    #[arg(value_name = "DIR")]
+    dir: PathBuf,
+}
+
+impl IndexCommandArgs {
+    pub fn run(self, session: &Session) -> Result<()> {
+        match self.command {
+            IndexCommand::Sync(args) => args.run(session),
+        }
+    }
+}
+
+impl SyncArgs {
+    fn run(self, session: &Session) -> Result<()> {
+        let packages = {
+            context!("Reading allowlist from {}", self.allowlist.display());
+            parse_allowlist(&std::fs::read_to_string(&self.allowlist)?)?
+        };
+        // Even if we were asked to use a snapshot, this is where we make them, so we
+        // always want the real indexes.
+        let db = session.network_package_db()?;
+        let report =
+            db.sync_index_snapshot(&self.dir, &packages, self.metadata_versions)?;
+        info!(
+            "Synced {} packages into {} (saved metadata for {} new releases, dropped {} \
+             packages no longer on the allowlist)",
+            report.synced,
+            self.dir.display(),
+            report.metadata_saved,
+            report.dropped,
+        );
+        if !report.failed.is_empty() {
+            let names = report
+                .failed
+                .iter()
+                .map(|(name, _)| name.as_given().to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            bail!(
+                "couldn't sync some packages (kept their old pages, if any): {names}"
+            );
+        }
+        Ok(())
+    }
+}
+
+// Same comment syntax as a requirements file, but only bare package names.
+fn parse_allowlist(text: &str) -> Result<Vec<PackageName>> {
+    let mut seen = HashSet::new();
+    let mut packages = Vec::new();
+    for line in text.lines() {
+        let line = match line.split_once('#') {
+            Some((before, _)) => before,
+            None => line,
+        }
+        .trim();
+        if line.is_empty() {
+            continue;
+        }
+        let name: PackageName = line.parse()?;
+        if seen.insert(name.normalized().to_string()) {
+            packages.push(name);
+        }
+    }
+    Ok(packages)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn test_parse_allowlist() {
+        let packages = parse_allowlist(indoc! {"
+            # web stuff
+            Django
+            requests  # and its deps, which need listing too
+
+            django
+        "})
+        .unwrap();
+        let names = packages.iter().map(|p| p.as_given()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["Django", "requests"]);
+
+        assert!(parse_allowlist("requests >= 2\n").is_err());
+    }
+}
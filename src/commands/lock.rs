@@ -0,0 +1,333 @@
+use clap::Args;
+
+use super::{EnvArgs, PlatformArgs, Session};
+use crate::dep_tree::DepTree;
+use crate::prelude::*;
+use crate::project::{Lockfile, Project};
+use crate::resolve::{Blueprint, Brief};
+
+#[derive(Args)]
+pub struct LockArgs {
+    #[command(flatten)]
+    env: EnvArgs,
+    #[command(flatten)]
+    platform: PlatformArgs,
+    /// Only re-lock the packages that dependency group GROUP needs, holding every
+    /// other pin where it is. (The group has to be in the lock already; see --group.)
+    #[arg(long, value_name = "GROUP")]
+    only_group: Option<String>,
+    /// Don't pin anything or touch posy.lock; just fetch metadata and list every
+    /// package the requirements could pull in, with the version ranges asked for
+    #[arg(long, conflicts_with = "only_group")]
+    metadata_only: bool,
+    /// With --metadata-only, print the report as JSON
+    #[arg(long, requires = "metadata_only")]
+    json: bool,
+    /// Switch to the current resolver heuristics. Otherwise, re-locking resolves the
+    /// same way the old posy.lock was, even if posy's defaults have changed since.
+    #[arg(long, conflicts_with_all = ["only_group", "metadata_only"])]
+    refresh_policies: bool,
+}
+
+impl LockArgs {
+    pub fn run(self, session: &Session) -> Result<()> {
+        let project = session.require_project()?;
+        if self.metadata_only {
+            let brief = self
+                .env
+                .brief(session, project.config.requirements.clone())?;
+            let db = session.package_db()?;
+            let report = brief.explore(&db, &self.platform.platforms()?)?;
+            if self.json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!("{report}");
+            }
+            return Ok(());
+        }
+        let mode = match &self.only_group {
+            Some(group) => LockMode::Subtree(group),
+            None if self.refresh_policies => LockMode::RefreshPolicies,
+            None => LockMode::PreferOld,
+        };
+        let lockfile =
+            make_lockfile(session, project, &self.env, &self.platform, mode)?;
+        write_lockfile(session, project, &lockfile)
+    }
+}
+
+/// How much of the old lockfile to keep.
+#[derive(Debug, Clone, Copy)]
+pub enum LockMode<'a> {
+    /// Prefer the old pins, but let anything move if the requirements need it.
+    PreferOld,
+    /// Like PreferOld, but with the current resolver policy instead of the one the old
+    /// lockfile was made with (see ResolverPolicy).
+    RefreshPolicies,
+    /// We just changed the requirements on these packages, so try to keep everything
+    /// else exactly where it was (see Brief::resolve_incremental).
+    Incremental(&'a [PackageName]),
+    /// Upgrade these packages, or everything if None (see Brief::resolve_upgrade).
+    Upgrade(Option<&'a [PackageName]>),
+    /// Re-resolve only what this dependency group needs, pinning everything else
+    /// exactly where it was.
+    Subtree(&'a str),
+}
+
+// For LockMode::Subtree: the requirements that aren't moving, i.e., the project's own
+// plus every other group in the lock. We can't just take the brief's requirements
+// minus the group's, because if the project asks for the same thing the group does,
+// that has to stay put too.
+fn held_requirements(
+    project: &Project,
+    env: &EnvArgs,
+    group: &str,
+) -> Result<Vec<UserRequirement>> {
+    let normalized = |group: &str| -> Result<String> {
+        Ok(PackageName::try_from(group)?.normalized().to_owned())
+    };
+    // make sure it exists
+    project.dependency_groups.requirements(group)?;
+    let target = normalized(group)?;
+    let mut held = project.config.requirements.clone();
+    let mut found = false;
+    for other in &env.groups {
+        if normalized(other)? == target {
+            found = true;
+        } else {
+            held.extend(project.dependency_groups.requirements(other)?);
+        }
+    }
+    if !found {
+        bail!("{group:?} isn't part of this lock; did you forget --group {group}?");
+    }
+    Ok(held)
+}
+
+// An exact constraint for every package in `old` that something in `held` needs.
+// Whatever's left over is only there because of the requirements that aren't held, so
+// that's what gets to move.
+fn held_pins(
+    old: &Blueprint,
+    brief: &Brief,
+    held: &[UserRequirement],
+) -> Result<Vec<UserRequirement>> {
+    let needed = DepTree::new(old, held, &brief.local_requirements)?.pinned_names()?;
+    old.wheels
+        .iter()
+        .filter(|(pin, _)| needed.contains(&pin.name))
+        .map(|(pin, _)| format!("{} == {}", pin.name.as_given(), pin.version).parse())
+        .collect()
+}
+
+/// Resolves `project`'s requirements into a new lockfile. (`project` isn't
+/// necessarily `session.project`, for commands that are about to change the config.)
+pub fn make_lockfile(
+    session: &Session,
+    project: &Project,
+    env: &EnvArgs,
+    platform: &PlatformArgs,
+    mode: LockMode,
+) -> Result<Lockfile> {
+    for note in &project.poetry_notes {
+        warn!(class = "poetry-constraint", "{note}");
+    }
+    let brief = env.brief(session, project.config.requirements.clone())?;
+    let mut old = project.read_lockfile()?;
+    if let (Some(old), LockMode::RefreshPolicies) = (&mut old, mode) {
+        if old.refresh_policies() {
+            info!("Switching to the current resolver policy");
+        }
+    }
+
+    let db = session.package_db()?;
+    // so re-locking doesn't move the build environments either
+    if let Some(old) = &old {
+        db.use_locked_builds(&old.builds)?;
+    }
+    let platforms = platform.platforms()?;
+    // Passing in the old blueprint means packages stay where they were pinned,
+    // unless the new requirements force a change.
+    let like = old.as_ref().map(|lockfile| &lockfile.blueprint);
+    let blueprint = match (like, mode) {
+        (Some(like), LockMode::Incremental(changed)) => {
+            match brief.resolve_incremental(&db, &platforms, like, changed, &[]) {
+                Ok(blueprint) => blueprint,
+                Err(err) => {
+                    info!("{err:#}; re-resolving everything instead");
+                    brief.resolve(&db, &platforms, Some(like), &[])?
+                }
+            }
+        }
+        (Some(like), LockMode::Upgrade(Some(targets))) => {
+            match brief.resolve_upgrade(&db, &platforms, like, targets, true, &[]) {
+                Ok(blueprint) => blueprint,
+                Err(err) => {
+                    info!("{err:#}; letting other packages move too");
+                    brief.resolve_upgrade(&db, &platforms, like, targets, false, &[])?
+                }
+            }
+        }
+        (Some(like), LockMode::Subtree(group)) => {
+            let held = held_requirements(project, env, group)?;
+            let held = held_pins(like, &brief, &held)?;
+            debug!("holding {} pins", held.len());
+            let pybi = &like.pybi;
+            let python = format!("{} == {}", pybi.name.as_given(), pybi.version);
+            let constrained = Brief {
+                python: python.parse()?,
+                python_fallbacks: Vec::new(),
+                constraints: brief.constraints.iter().cloned().chain(held).collect(),
+                ..brief.clone()
+            };
+            // no hints, so the subtree gets the newest versions that fit around
+            // everything else (but still the way the rest was resolved)
+            constrained
+                .resolve_with_policy(&db, &platforms, &like.policy, &[])
+                .wrap_err("couldn't re-lock without moving any other pins")?
+        }
+        // upgrading everything means forgetting every pin, but not which policy the
+        // lock was made with (that's what --refresh-policies is for)
+        (Some(like), LockMode::Upgrade(None)) => {
+            brief.resolve_with_policy(&db, &platforms, &like.policy, &[])?
+        }
+        (None, LockMode::Upgrade(None)) => brief.resolve(&db, &platforms, None, &[])?,
+        _ => brief.resolve(&db, &platforms, like, &[])?,
+    };
+    let mut lockfile = Lockfile::new(brief.clone(), blueprint);
+    if !project.config.lock_platforms.is_empty() {
+        let lock_platforms = project
+            .config
+            .lock_platforms
+            .iter()
+            .map(|tag| PybiPlatform::new(tag))
+            .collect::<Vec<_>>();
+        let like = match mode {
+            LockMode::Upgrade(None) => None,
+            // XX TODO: resolve_set doesn't know how to hold or upgrade specific
+            // packages, so for those the per-platform blueprints just prefer their old
+            // pins
+            _ => old.as_ref().map(|lockfile| &lockfile.platforms),
+        };
+        lockfile.set_platforms(brief.resolve_set(
+            &db,
+            &lock_platforms.iter().collect::<Vec<_>>(),
+            like,
+            &lockfile.blueprint.policy,
+            &[],
+        )?);
+    }
+    let main_platform = match platform.requested_tags().as_slice() {
+        [] => "this machine".to_string(),
+        tags => tags.join("/"),
+    };
+    for (name, tags) in lockfile.source_builds(&main_platform) {
+        warn!(
+            "{name} has no wheels for {}, so it'll be built from source there",
+            tags.join(", ")
+        );
+    }
+    if let Some(old) = &old {
+        for (name, note) in lockfile.carry_annotations(old) {
+            warn!("{name}'s pin changed, so dropping the note on it: {note}");
+        }
+    }
+    lockfile.record_builds(&db, old.as_ref())?;
+    if project.config.kind.requires_hashes() {
+        lockfile.check_hashes()?;
+    }
+    // if we were asked to lock for some other platform, then presumably that's
+    // where it's going to be installed, so list those files too
+    let mut audit_platforms = project.config.audit_platforms.clone();
+    for tag in platform.requested_tags() {
+        if !audit_platforms.contains(&tag) {
+            audit_platforms.push(tag);
+        }
+    }
+    lockfile.list_artifacts(&db, &audit_platforms)?;
+    Ok(lockfile)
+}
+
+pub fn write_lockfile(
+    session: &Session,
+    project: &Project,
+    lockfile: &Lockfile,
+) -> Result<()> {
+    if let Some(transcript) = &session.transcript {
+        transcript.will_write(&project.lockfile_path())?;
+    }
+    project.write_lockfile(lockfile)?;
+    info!("Wrote {}", project.lockfile_path().display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resolve::{
+        PinnedPackage, WheelResolveMetadata, WheelResolveMetadataInner,
+    };
+
+    #[test]
+    fn test_held_pins() {
+        let pin = |name: &str, version: &str| PinnedPackage {
+            name: name.parse().unwrap(),
+            version: version.try_into().unwrap(),
+            hashes: vec![],
+            url: None,
+        };
+        let wheel = |name: &str, version: &str, requires_dist: &[&str]| {
+            let metadata = WheelResolveMetadata {
+                provenance: "test".into(),
+                inner: WheelResolveMetadataInner {
+                    requires_dist: requires_dist
+                        .iter()
+                        .map(|r| r.parse().unwrap())
+                        .collect(),
+                    requires_python: Default::default(),
+                    extras: Default::default(),
+                },
+            };
+            (pin(name, version), metadata)
+        };
+        let old = Blueprint {
+            pybi: pin("cpython", "3.11.1"),
+            wheels: vec![
+                wheel("trio", "0.22.0", &["attrs"]),
+                wheel("attrs", "22.2.0", &[]),
+                wheel("sphinx", "6.1.0", &["jinja2", "attrs"]),
+                wheel("jinja2", "3.1.2", &[]),
+            ],
+            local: vec![],
+            marker_expressions: Default::default(),
+            python_fallback: None,
+            annotations: Default::default(),
+            claimed_publishers: Default::default(),
+            platform_tags: Default::default(),
+            source_builds: Default::default(),
+            warnings: Vec::new(),
+            policy: Default::default(),
+            python_flags: Vec::new(),
+        };
+        let brief: Brief = serde_json::from_str(
+            r#"{"python": "cpython >= 3", "requirements": ["trio", "sphinx >= 5"]}"#,
+        )
+        .unwrap();
+        let held_pins = |held: &[&str]| {
+            let held = held.iter().map(|r| r.parse().unwrap()).collect::<Vec<_>>();
+            held_pins(&old, &brief, &held)
+                .unwrap()
+                .iter()
+                .map(|r| r.to_string())
+                .collect::<Vec<_>>()
+        };
+        // re-locking a group with sphinx in it: attrs stays put, because trio needs it
+        // too
+        assert_eq!(
+            held_pins(&["trio"]),
+            vec!["trio == 0.22.0", "attrs == 22.2.0"]
+        );
+        // ...but if the project asks for sphinx itself, that stays put too
+        assert_eq!(held_pins(&["trio", "sphinx >= 5"]).len(), 4);
+    }
+}
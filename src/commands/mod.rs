@@ -0,0 +1,693 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{Args, Subcommand};
+
+use crate::blob_store::{BlobStore, LinkMode};
+use crate::env::{Env, EnvForest};
+use crate::kvstore::KVDirStore;
+use crate::output::{human_size, Table};
+use crate::package_db::{
+    AttestationPolicy, IndexStrategy, OfflineMisses, PackageDB, SimpleApiSnapshot,
+    YankedPolicy,
+};
+use crate::prelude::*;
+use crate::project::{Project, ProjectConfig, ProjectKind, SizeBudgetPolicy};
+use crate::resolve::{AllowPre, Brief, ResolutionStrategy};
+use crate::transcript::Transcript;
+
+mod add;
+mod annotate;
+mod bundle;
+mod cache;
+mod env;
+mod gc;
+mod index;
+mod lock;
+mod outdated;
+mod remove;
+mod rollback;
+mod run;
+mod support_matrix;
+mod sync;
+mod tree;
+mod update;
+mod urls;
+mod verify_env;
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Add requirements to the current project, and update posy.lock to match
+    Add(add::AddArgs),
+    /// Attach a note to a package's pin in posy.lock, explaining why it's there. It
+    /// stays until the pin moves.
+    Annotate(annotate::AnnotateArgs),
+    /// Bundle a pure-Python application into a single-file zipapp
+    Bundle(bundle::BundleArgs),
+    /// Look at what's in posy's caches
+    Cache(cache::CacheCommandArgs),
+    /// Inspect and repair installed environments
+    Env(env::EnvCommandArgs),
+    /// Delete cached downloads and installed packages that haven't been used lately
+    Gc(gc::GcArgs),
+    /// Manage local index snapshots
+    Index(index::IndexCommandArgs),
+    /// Resolve the current project's requirements, and write them to posy.lock without
+    /// installing anything
+    Lock(lock::LockArgs),
+    /// Show which pinned packages have newer versions, and whether getting them needs
+    /// just a re-lock or changes to the project's requirements. Doesn't change anything.
+    Outdated(outdated::OutdatedArgs),
+    /// Remove requirements from the current project, and update posy.lock to match
+    Remove(remove::RemoveArgs),
+    /// Undo the most recent change posy made to the current project (e.g. a 'posy
+    /// lock' or a failed install). Run again to go back further.
+    Rollback(rollback::RollbackArgs),
+    /// Run a command in the current project's environment
+    Run(run::RunArgs),
+    /// Try resolving the current project's requirements with each Python version
+    /// (3.8 through 3.13 by default), and show which ones work
+    SupportMatrix(support_matrix::SupportMatrixArgs),
+    /// Install exactly what posy.lock says, failing if it's out of date (or, for a
+    /// library, re-resolving instead)
+    Sync(sync::SyncArgs),
+    /// Show which package pulled in which, according to posy.lock
+    Tree(tree::TreeArgs),
+    /// Upgrade some packages (or all of them) to their newest versions, update
+    /// posy.lock, and show what changed
+    Update(update::UpdateArgs),
+    /// Print the URL, hash, and size (if known) of every file that installing an
+    /// environment would download, one per line
+    Urls(urls::UrlsArgs),
+    /// Check that the installed environment still matches posy.lock, without changing
+    /// anything
+    VerifyEnv(verify_env::VerifyEnvArgs),
+}
+
+impl Command {
+    pub fn run(self, session: &Session) -> Result<()> {
+        match self {
+            Command::Add(args) => args.run(session),
+            Command::Annotate(args) => args.run(session),
+            Command::Bundle(args) => args.run(session),
+            Command::Cache(args) => args.run(session),
+            Command::Env(args) => args.run(session),
+            Command::Gc(args) => args.run(session),
+            Command::Index(args) => args.run(session),
+            Command::Lock(args) => args.run(session),
+            Command::Outdated(args) => args.run(session),
+            Command::Remove(args) => args.run(session),
+            Command::Rollback(args) => args.run(session),
+            Command::Run(args) => args.run(session),
+            Command::SupportMatrix(args) => args.run(session),
+            Command::Sync(args) => args.run(session),
+            Command::Tree(args) => args.run(session),
+            Command::Update(args) => args.run(session),
+            Command::Urls(args) => args.run(session),
+            Command::VerifyEnv(args) => args.run(session),
+        }
+    }
+
+    // for transcript filenames
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Add(_) => "add",
+            Command::Annotate(_) => "annotate",
+            Command::Bundle(_) => "bundle",
+            Command::Cache(_) => "cache",
+            Command::Env(_) => "env",
+            Command::Gc(_) => "gc",
+            Command::Index(_) => "index",
+            Command::Lock(_) => "lock",
+            Command::Outdated(_) => "outdated",
+            Command::Remove(_) => "remove",
+            Command::Rollback(_) => "rollback",
+            Command::Run(_) => "run",
+            Command::SupportMatrix(_) => "support-matrix",
+            Command::Sync(_) => "sync",
+            Command::Tree(_) => "tree",
+            Command::Update(_) => "update",
+            Command::Urls(_) => "urls",
+            Command::VerifyEnv(_) => "verify-env",
+        }
+    }
+}
+
+/// Global options for where we find packages.
+#[derive(Args, Default)]
+pub struct IndexArgs {
+    /// Look up packages in a directory of saved simple API pages, instead of on the
+    /// network.
+    #[arg(long, value_name = "DIR", global = true)]
+    index_snapshot: Option<PathBuf>,
+    /// With --index-snapshot, refuse to use pages saved more than HOURS ago.
+    #[arg(long, value_name = "HOURS", global = true, requires = "index_snapshot")]
+    snapshot_max_age: Option<u64>,
+    /// What to do when several indexes have the same package: 'merge' uses files from
+    /// all of them, 'first-match' only uses the first index that has it. [default: the
+    /// project's 'index-strategy' setting, or 'merge']
+    #[arg(long, value_enum, value_name = "STRATEGY", global = true)]
+    index_strategy: Option<IndexStrategy>,
+    /// Whether to use files the index says were yanked: 'forbid', 'allow-pinned' (only
+    /// if the lock file already has them), or 'allow'. [default: the project's
+    /// 'yanked' setting, or 'allow-pinned']
+    #[arg(long, value_enum, value_name = "POLICY", global = true)]
+    yanked: Option<YankedPolicy>,
+    /// Whether to read the PEP 740 attestations that some indexes publish for their
+    /// files: 'ignore', 'record' (if there are any, make sure they match the file, and
+    /// record who they say published it in the lock file), or 'require'. Their
+    /// signatures aren't verified. [default: the project's 'attestations' setting, or
+    /// 'ignore']
+    #[arg(long, value_enum, value_name = "POLICY", global = true)]
+    attestations: Option<AttestationPolicy>,
+    /// Never touch the network; use only what's already in posy's cache.
+    #[arg(long, global = true)]
+    offline: bool,
+}
+
+/// Everything that a command needs to get started: the on-disk stores that
+/// PackageDB borrows from, global options, and the project we're running in (if any).
+pub struct Session {
+    pub env_forest: EnvForest,
+    pub project: Option<Project>,
+    pub build_store: KVDirStore,
+    // has to come after build_store, so it's dropped after it
+    _build_tmp: tempfile::TempDir,
+    index_args: IndexArgs,
+    // shared by every PackageDB we hand out, so we can report all the misses at the end
+    offline: Option<Arc<OfflineMisses>>,
+    // only when we're in a project, since that's where transcripts get saved
+    pub transcript: Option<Arc<Transcript>>,
+}
+
+impl Session {
+    pub fn new(index_args: IndexArgs) -> Result<Session> {
+        let build_tmp = tempfile::TempDir::new()?;
+        let project = Project::find(&std::env::current_dir()?)?;
+        if let Some(project) = &project {
+            debug!("using project at {}", project.root.display());
+            // before anything makes a PybiPlatform
+            crate::platform_tags::set_custom_tag_priorities(
+                &project.config.platform_tags,
+            )
+            .wrap_err("bad 'platform-tags' in project config")?;
+        }
+        let mut env_forest = EnvForest::new(Path::new("posy-test-forest"))?;
+        if let Some(project) = &project {
+            env_forest.rename_colliding_scripts =
+                project.config.rename_colliding_scripts;
+        }
+        if project.as_ref().map_or(false, |p| p.config.share_wheels) {
+            env_forest.shared_wheels = Some(KVDirStore::new(
+                &PROJECT_DIRS.cache_dir().join("unpacked-wheels"),
+            )?);
+        }
+        let link_mode = project
+            .as_ref()
+            .map_or(LinkMode::Copy, |p| p.config.link_mode);
+        if link_mode != LinkMode::Copy {
+            env_forest.blobs = Some(BlobStore::new(
+                &PROJECT_DIRS.cache_dir().join("blobs"),
+                link_mode,
+            )?);
+        }
+        let transcript = project.as_ref().map(|_| Arc::new(Transcript::default()));
+        env_forest.transcript = transcript.clone();
+        Ok(Session {
+            env_forest,
+            project,
+            build_store: KVDirStore::new(build_tmp.path())?,
+            _build_tmp: build_tmp,
+            offline: index_args.offline.then(Default::default),
+            index_args,
+            transcript,
+        })
+    }
+
+    /// Saves a record of everything `command` changed so far, for 'posy rollback'.
+    pub fn save_transcript(&self, command: &str) -> Result<()> {
+        if let (Some(project), Some(transcript)) = (&self.project, &self.transcript) {
+            if let Some(path) = transcript.save(&project.root, command)? {
+                debug!("wrote transcript to {}", path.display());
+            }
+        }
+        Ok(())
+    }
+
+    /// If we failed because we were offline and didn't have something cached, replaces
+    /// `err` with a list of everything that was missing.
+    pub fn explain_error(&self, err: eyre::Report) -> eyre::Report {
+        match &self.offline {
+            Some(misses) => {
+                let missing = misses.take();
+                if missing.is_empty() {
+                    err
+                } else {
+                    debug!("original error: {err:#}");
+                    PosyError::OfflineCacheMiss { missing }.into()
+                }
+            }
+            None => err,
+        }
+    }
+
+    pub fn require_project(&self) -> Result<&Project> {
+        self.project.as_ref().ok_or_else(|| {
+            eyre!(
+                "couldn't find a posy.toml or pyproject.toml in this directory or any \
+                 of its parents"
+            )
+        })
+    }
+
+    // Outside of any project, we act like an app: whatever you're doing, you probably
+    // want it to keep working the way it did last time.
+    pub fn project_kind(&self) -> ProjectKind {
+        self.project
+            .as_ref()
+            .map(|project| project.config.kind)
+            .unwrap_or_default()
+    }
+
+    pub fn package_db(&self) -> Result<PackageDB> {
+        if let Some(dir) = &self.index_args.index_snapshot {
+            let max_age = self
+                .index_args
+                .snapshot_max_age
+                .map(|hours| Duration::from_secs(hours * 3600));
+            return PackageDB::from_snapshot(
+                SimpleApiSnapshot::open(dir, max_age)?,
+                PROJECT_DIRS.cache_dir(),
+                &self.env_forest,
+                &self.build_store,
+                self.offline.clone(),
+            );
+        }
+        self.network_package_db()
+    }
+
+    // Like package_db, but always talks to the real indexes, even with --index-snapshot.
+    pub fn network_package_db(&self) -> Result<PackageDB> {
+        let mut db = PackageDB::new(
+            &[
+                Url::parse("https://pybi.vorpus.org")?,
+                Url::parse("https://pypi.org/simple/")?,
+            ],
+            PROJECT_DIRS.cache_dir(),
+            // PackageDB needs a place to install packages, in case it has to build
+            // some sdists. Using a shared env_forest is efficient, because it means
+            // different builds can share the same package installs.
+            &self.env_forest,
+            // This is the temporary directory we use for sdist builds. It's also a
+            // content-addressed store, so if we want to build the same package twice
+            // (e.g. first to get metadata, and then to get a wheel), we can re-use the
+            // same build directory.
+            &self.build_store,
+            self.offline.clone(),
+        )?;
+        if let Some(project) = &self.project {
+            db.index_strategy = project.config.index_strategy;
+            db.set_index_pins(&project.config.index_pins)?;
+            db.set_prefer_local_versions(&project.config.prefer_local_versions)?;
+            db.set_no_metadata_files(&project.config.no_metadata_files)?;
+            db.yanked_policy = project.config.yanked;
+            db.attestation_policy = project.config.attestations;
+            db.build_pip_shim = project.config.build_pip_shim;
+        }
+        if let Some(strategy) = self.index_args.index_strategy {
+            db.index_strategy = strategy;
+        }
+        if let Some(policy) = self.index_args.yanked {
+            db.yanked_policy = policy;
+        }
+        if let Some(policy) = self.index_args.attestations {
+            db.attestation_policy = policy;
+        }
+        Ok(db)
+    }
+}
+
+// What we use if neither the command line nor the project config say otherwise.
+// "cpython_unofficial" is the package name for the test pybis at pybi.vorpus.org.
+const DEFAULT_PYTHON: &str = "cpython_unofficial >= 3";
+
+/// Command-line arguments that control how we turn requirements into a Brief.
+#[derive(Args)]
+pub struct EnvArgs {
+    /// Which Python interpreter to use. Give it more than once for fallbacks, to try
+    /// in order if the first can't satisfy the requirements. [default: the project's
+    /// 'python' setting, or 'cpython_unofficial >= 3']
+    #[arg(long, value_name = "REQUIREMENT")]
+    python: Vec<String>,
+    /// Allow pre-releases of PACKAGE. Use ':all:' to allow them for everything.
+    #[arg(long = "pre", value_name = "PACKAGE")]
+    allow_pre: Vec<String>,
+    /// Which versions to pick when several would work: 'highest', or 'lowest' to
+    /// check that your requirements' lower bounds really work. [default: the
+    /// project's 'resolution' setting, or 'highest']
+    #[arg(long, value_enum, value_name = "STRATEGY")]
+    resolution: Option<ResolutionStrategy>,
+    /// Also install the requirements from this dependency group in pyproject.toml
+    /// (PEP 735).
+    #[arg(long = "group", value_name = "GROUP")]
+    groups: Vec<String>,
+    /// Restrict package versions using the requirements in FILE, without installing
+    /// anything extra (like pip's '-c constraints.txt').
+    #[arg(short = 'c', long = "constraints", value_name = "FILE")]
+    constraints: Vec<PathBuf>,
+    /// Install the Python project at PATH in editable mode, so changes to its source
+    /// show up without reinstalling.
+    #[arg(short = 'e', long = "editable", value_name = "PATH")]
+    editable: Vec<String>,
+    /// Fail if any package would have to be built from its sdist on the platform
+    /// we're locking for, instead of finding out when it gets installed there.
+    #[arg(long)]
+    no_source_builds: bool,
+}
+
+impl EnvArgs {
+    pub fn brief(
+        &self,
+        session: &Session,
+        mut requirements: Vec<UserRequirement>,
+    ) -> Result<Brief> {
+        let allow_pre = if self.allow_pre.iter().any(|p| p == ":all:") {
+            AllowPre::All
+        } else {
+            AllowPre::Some(
+                self.allow_pre
+                    .iter()
+                    .map(|p| p.parse())
+                    .collect::<Result<_>>()?,
+            )
+        };
+        if !self.groups.is_empty() {
+            let project = session.project.as_ref().ok_or_else(|| {
+                eyre!("--group needs a pyproject.toml, but we aren't in a project")
+            })?;
+            for group in &self.groups {
+                requirements.extend(project.dependency_groups.requirements(group)?);
+            }
+        }
+        let mut constraints = Vec::new();
+        for path in &self.constraints {
+            context!("Reading constraints from {}", path.display());
+            constraints.extend(parse_constraints(&std::fs::read_to_string(path)?)?);
+        }
+        let cwd = std::env::current_dir()?;
+        let mut local_requirements = Vec::new();
+        for path in &self.editable {
+            local_requirements
+                .extend(LocalRequirement::parse(&format!("-e {path}"), &cwd)?);
+        }
+        let (python, python_fallbacks) =
+            match (self.python.split_first(), &session.project) {
+                (Some((python, fallbacks)), _) => (
+                    python.parse()?,
+                    fallbacks.iter().map(|p| p.parse()).collect::<Result<_>>()?,
+                ),
+                (None, Some(project)) => (
+                    match &project.config.python {
+                        Some(python) => python.clone(),
+                        None => DEFAULT_PYTHON.parse()?,
+                    },
+                    project.config.python_fallbacks.clone(),
+                ),
+                (None, None) => (DEFAULT_PYTHON.parse()?, Vec::new()),
+            };
+        let strategy = match (self.resolution, &session.project) {
+            (Some(strategy), _) => strategy,
+            (None, Some(project)) => project.config.resolution,
+            (None, None) => Default::default(),
+        };
+        Ok(Brief {
+            python,
+            python_fallbacks,
+            requirements,
+            allow_pre,
+            strategy,
+            keep_pinned_prereleases: session.project_kind().keep_pinned_prereleases(),
+            constraints,
+            local_requirements,
+            no_source_builds: self.no_source_builds,
+            python_flags: session
+                .project
+                .as_ref()
+                .map(|project| project.config.python_flags.clone())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+// Constraints files use the same format as pip's, except that we only understand
+// plain requirements -- no options like '-r other.txt' or '--hash'.
+fn parse_constraints(text: &str) -> Result<Vec<UserRequirement>> {
+    let mut constraints = Vec::new();
+    for line in text.lines() {
+        let line = match line.split_once('#') {
+            Some((before, _)) => before,
+            None => line,
+        }
+        .trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('-') {
+            bail!("unsupported line in constraints file: {line:?}");
+        }
+        constraints.push(line.parse()?);
+    }
+    Ok(constraints)
+}
+
+// Sorts requirements from the command line into regular ones, and local source trees
+// like './mypkg' or 'mypkg @ file:///path/to/mypkg'.
+fn parse_requirement_args(
+    args: &[String],
+) -> Result<(Vec<UserRequirement>, Vec<LocalRequirement>)> {
+    let cwd = std::env::current_dir()?;
+    let mut requirements = Vec::new();
+    let mut local_requirements = Vec::new();
+    for arg in args {
+        match LocalRequirement::parse(arg, &cwd)? {
+            Some(local) => local_requirements.push(local),
+            None => requirements.push(arg.parse()?),
+        }
+    }
+    Ok((requirements, local_requirements))
+}
+
+/// Command-line arguments that describe a Brief.
+#[derive(Args)]
+pub struct BriefArgs {
+    #[command(flatten)]
+    env: EnvArgs,
+    /// Packages to install, e.g. 'requests >= 2', './mypkg', or
+    /// 'mypkg @ file:///path/to/mypkg'.
+    #[arg(value_name = "REQUIREMENT")]
+    requirements: Vec<String>,
+}
+
+impl BriefArgs {
+    pub fn brief(&self, session: &Session) -> Result<Brief> {
+        let (requirements, local_requirements) =
+            parse_requirement_args(&self.requirements)?;
+        let mut brief = self.env.brief(session, requirements)?;
+        brief.local_requirements.extend(local_requirements);
+        Ok(brief)
+    }
+}
+
+/// Command-line arguments for picking which platforms to resolve for.
+#[derive(Args)]
+pub struct PlatformArgs {
+    /// Resolve for the pybi platform TAG instead of the machine we're running on, e.g.
+    /// 'macosx_11_0_arm64', 'win_amd64', or 'emscripten_3_1_32_wasm32' (or how
+    /// sysconfig spells them, like 'emscripten-3.1.32-wasm32'). Can be repeated;
+    /// earlier ones are preferred.
+    #[arg(long = "platform", value_name = "TAG", value_parser = parse_platform)]
+    platforms: Vec<PybiPlatform>,
+}
+
+fn parse_platform(tag: &str) -> std::result::Result<PybiPlatform, String> {
+    // sysconfig-style tags like 'macosx-11.0-arm64' are fine, but a full wheel tag
+    // like 'cp311-cp311-win_amd64' is a common mistake
+    static PYTHON_TAG_RE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"^[a-z]+[0-9]+-[^-]+-[^-]+$").unwrap());
+    if PYTHON_TAG_RE.is_match(tag) || tag.contains(char::is_whitespace) {
+        return Err(format!(
+            "expected a platform tag like 'macosx_11_0_arm64', not {tag:?}"
+        ));
+    }
+    Ok(PybiPlatform::new(tag))
+}
+
+impl PlatformArgs {
+    /// The core tags of any platforms given with --platform.
+    pub fn requested_tags(&self) -> Vec<String> {
+        self.platforms
+            .iter()
+            .map(|p| p.core_tag().to_string())
+            .collect()
+    }
+
+    pub fn platforms(&self) -> Result<Vec<&PybiPlatform>> {
+        if self.platforms.is_empty() {
+            Ok(PybiPlatform::native_platforms()?.to_vec())
+        } else {
+            Ok(self.platforms.iter().collect())
+        }
+    }
+}
+
+/// Shows what went into `env`, and where each package came from.
+pub fn print_install_summary(env: &Env) {
+    if !tracing::enabled!(tracing::Level::INFO) || env.installed.is_empty() {
+        return;
+    }
+    let mut table = Table::new(["package", "version", "source", "from"]);
+    for installed in &env.installed {
+        table.push_row([
+            installed.name.as_given().to_string(),
+            installed.version.to_string(),
+            installed.source.to_string(),
+            installed.artifact.clone(),
+        ]);
+    }
+    table.print();
+    if let Some(summary) = crate::output::warning_summary(&env.warnings) {
+        info!("Installed with {summary}");
+    }
+}
+
+/// If the project has a size-budget, measures `what` and warns or fails (per its
+/// size-budget-policy) if it's over. (Measuring an env means walking all of it, so we
+/// only do it when there's a budget to check.)
+pub fn check_size_budget<F>(
+    config: &ProjectConfig,
+    what: &str,
+    measure: F,
+) -> Result<()>
+where
+    F: FnOnce() -> Result<u64>,
+{
+    let budget = match config.size_budget {
+        Some(budget) => budget,
+        None => return Ok(()),
+    };
+    let size = measure()?;
+    if size <= budget {
+        debug!("{what} is {}, within the size-budget", human_size(size));
+        return Ok(());
+    }
+    let message = format!(
+        "{what} is {}, over the project's size-budget of {}",
+        human_size(size),
+        human_size(budget)
+    );
+    match config.size_budget_policy {
+        SizeBudgetPolicy::Warn => warn!(class = "size-budget", "{message}"),
+        SizeBudgetPolicy::Error => bail!(message),
+    }
+    Ok(())
+}
+
+/// Runs `cmd` inside `env`. On Unix, this replaces the current process, so it only
+/// returns if something goes wrong.
+pub fn exec_in_env(env: &Env, mut cmd: std::process::Command) -> Result<()> {
+    let program = cmd.get_program().to_owned();
+    cmd = env.wrap_command(cmd)?;
+
+    let not_found = |err: std::io::Error| match err.kind() {
+        std::io::ErrorKind::NotFound => eyre!(
+            "couldn't find {program:?} in the environment or on $PATH (try 'posy env \
+             scripts' to see what the environment provides)",
+        ),
+        _ => err.into(),
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // exec() keeps our pid, and resets our signal handlers, so signals (Ctrl-C,
+        // kill from a process supervisor, ...) go straight to the command
+        Err(not_found(cmd.exec()))?;
+        unreachable!();
+    }
+    #[cfg(windows)]
+    {
+        // There's no exec on Windows, so we have to stick around until the command
+        // exits. It goes in a job object (see ProcessTree), so if we get killed,
+        // nothing it started is left behind.
+        crate::interrupt::leave_to_child();
+        let mut tree = match crate::process_tree::ProcessTree::spawn(&mut cmd) {
+            Ok(tree) => tree,
+            Err(err) => match err.downcast::<std::io::Error>() {
+                Ok(err) => return Err(not_found(err)),
+                Err(err) => return Err(err),
+            },
+        };
+        let status = tree.wait(&Default::default())?;
+        // unwrap() is safe b/c this branch only runs on windows, and Windows doesn't
+        // have special exit statuses; that's a special thing for Unix signals.
+        std::process::exit(status.code().unwrap());
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        not_supported
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn test_parse_constraints() {
+        let constraints = parse_constraints(indoc! {"
+            # pinned for reasons
+            trio == 0.22.0
+            attrs >= 22  # trailing comment
+
+            numpy < 2; python_version < '3.9'
+        "})
+        .unwrap();
+        let names = constraints
+            .iter()
+            .map(|c| c.name.normalized().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["trio", "attrs", "numpy"]);
+        assert!(constraints[2].env_marker_expr.is_some());
+
+        assert!(parse_constraints("-r other.txt\n").is_err());
+        assert!(parse_constraints("not a requirement!\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_platform() {
+        assert_eq!(
+            parse_platform("macosx_11_0_arm64").unwrap().core_tag(),
+            "macosx_11_0_arm64"
+        );
+        assert_eq!(
+            parse_platform("manylinux2014_x86_64").unwrap().core_tag(),
+            "manylinux_2_17_x86_64"
+        );
+        assert_eq!(
+            parse_platform("emscripten-3.1.32-wasm32")
+                .unwrap()
+                .core_tag(),
+            "emscripten_3_1_32_wasm32"
+        );
+        assert_eq!(
+            parse_platform("macosx-11.0-arm64").unwrap().core_tag(),
+            "macosx_11_0_arm64"
+        );
+        // full wheel tags are a common mistake
+        assert!(parse_platform("cp311-cp311-win_amd64").is_err());
+        assert!(parse_platform("py3-none-any").is_err());
+        assert!(parse_platform("win amd64").is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use clap::Args;
+
+use super::{EnvArgs, PlatformArgs, Session};
+use crate::output::Table;
+use crate::package_db::PackageDB;
+use crate::prelude::*;
+use crate::resolve::{Blueprint, Brief};
+
+#[derive(Args)]
+pub struct OutdatedArgs {
+    #[command(flatten)]
+    env: EnvArgs,
+    #[command(flatten)]
+    platform: PlatformArgs,
+}
+
+// For each pin, there are two versions worth knowing about: the newest one we could
+// move to without touching the project's requirements (i.e. what a fresh resolve
+// picks, which is what 'posy update' would do), and the newest one on the index at
+// all. If those are different, then something -- maybe one of the project's
+// requirements, maybe some other package's -- is holding it back, and getting there
+// will take more than re-locking.
+fn upgrade_path(
+    pinned: &Version,
+    compatible: Option<&Version>,
+    latest: Option<&Version>,
+) -> &'static str {
+    let lock_only = compatible.map_or(false, |c| c > pinned);
+    let best = compatible.map_or(pinned, |c| c.max(pinned));
+    let needs_edits = latest.map_or(false, |l| l > best);
+    match (lock_only, needs_edits) {
+        (false, false) => "up to date",
+        (true, false) => "posy update",
+        (false, true) => "requirement changes",
+        (true, true) => "posy update; newest needs requirement changes",
+    }
+}
+
+// The newest version on the index that we'd consider at all: skipping yanked
+// releases (unless the yanked policy says otherwise), and pre-releases unless they're
+// allowed. (Compatibility with the
+// platform or Python version is the resolver's problem.)
+fn newest_version<'a>(
+    db: &'a PackageDB,
+    brief: &Brief,
+    name: &PackageName,
+) -> Result<Option<&'a Version>> {
+    let artifacts = db.available_artifacts(name)?;
+    let all_pre = artifacts.keys().all(|version| version.is_prerelease());
+    let allow_pre = all_pre || brief.allow_pre.allow_pre_for(name);
+    Ok(artifacts
+        .iter()
+        .filter(|(version, _)| allow_pre || !version.is_prerelease())
+        .filter(|(_, ais)| ais.iter().any(|ai| db.yanked_policy.allows(ai, false)))
+        .map(|(version, _)| version)
+        .max())
+}
+
+fn pins(blueprint: &Blueprint) -> Vec<(&PackageName, &Version)> {
+    let mut pins = std::iter::once((&blueprint.pybi.name, &blueprint.pybi.version))
+        .chain(
+            blueprint
+                .wheels
+                .iter()
+                .map(|(pin, _)| (&pin.name, &pin.version)),
+        )
+        .collect::<Vec<_>>();
+    pins.sort_by(|a, b| a.0.normalized().cmp(b.0.normalized()));
+    pins
+}
+
+impl OutdatedArgs {
+    pub fn run(self, session: &Session) -> Result<()> {
+        let project = session.require_project()?;
+        let lockfile = project
+            .read_lockfile()?
+            .ok_or_else(|| eyre!("no posy.lock yet; run 'posy lock' first"))?;
+        let brief = self
+            .env
+            .brief(session, project.config.requirements.clone())?;
+        let db = session.package_db()?;
+        let platforms = self.platform.platforms()?;
+        // no hints, so everything goes as high as it can
+        let fresh = brief.resolve(&db, &platforms, None, &[])?;
+        let compatible = pins(&fresh);
+
+        let mut table =
+            Table::new(["package", "pinned", "compatible", "latest", "upgrade"]);
+        // local trees aren't on any index, so there's nothing to compare them to
+        for (name, pinned) in pins(&lockfile.blueprint) {
+            let compatible = compatible
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, version)| *version);
+            let latest = newest_version(&db, &brief, name)?;
+            let show = |v: Option<&Version>| v.map_or("-".into(), |v| v.to_string());
+            table.push_row([
+                name.as_given().to_string(),
+                pinned.to_string(),
+                show(compatible),
+                show(latest),
+                upgrade_path(pinned, compatible, latest).to_string(),
+            ]);
+        }
+        table.print();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_upgrade_path() {
+        let v = |s: &str| -> Version { s.try_into().unwrap() };
+        let path = |pinned: &str, compatible: &str, latest: &str| {
+            upgrade_path(&v(pinned), Some(&v(compatible)), Some(&v(latest)))
+        };
+        assert_eq!(path("1.0", "1.0", "1.0"), "up to date");
+        assert_eq!(path("1.0", "1.1", "1.1"), "posy update");
+        assert_eq!(path("1.0", "1.0", "2.0"), "requirement changes");
+        assert_eq!(
+            path("1.0", "1.1", "2.0"),
+            "posy update; newest needs requirement changes"
+        );
+        // pinned to something that's since been yanked, or a pre-release
+        assert_eq!(path("1.1", "1.0", "1.0"), "up to date");
+        assert_eq!(upgrade_path(&v("1.0"), None, None), "up to date");
+    }
+}
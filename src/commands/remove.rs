@@ -0,0 +1,51 @@
+use clap::Args;
+
+use super::lock::{make_lockfile, write_lockfile, LockMode};
+use super::{EnvArgs, PlatformArgs, Session};
+use crate::config_edit::ConfigEditor;
+use crate::prelude::*;
+
+#[derive(Args)]
+pub struct RemoveArgs {
+    /// Packages to stop requiring. (Whatever else still needs them stays installed.)
+    #[arg(required = true, value_name = "PACKAGE")]
+    packages: Vec<String>,
+    #[command(flatten)]
+    env: EnvArgs,
+    #[command(flatten)]
+    platform: PlatformArgs,
+}
+
+impl RemoveArgs {
+    pub fn run(self, session: &Session) -> Result<()> {
+        let project = session.require_project()?;
+        let mut editor = ConfigEditor::open(&project.root)?;
+        let mut changed = Vec::new();
+        for package in &self.packages {
+            let name: PackageName = package.parse()?;
+            changed.push(name.clone());
+            let removed = editor.remove_requirement(&name)?;
+            if removed.is_empty() {
+                bail!(
+                    "{} doesn't list {} as a requirement",
+                    editor.path().display(),
+                    name.as_given()
+                );
+            }
+            for old in removed {
+                info!("Removing {old:?}");
+            }
+        }
+        let new_project = editor.project()?;
+        let lockfile = make_lockfile(
+            session,
+            &new_project,
+            &self.env,
+            &self.platform,
+            LockMode::Incremental(&changed),
+        )?;
+        editor.save(session.transcript.as_deref())?;
+        info!("Wrote {}", editor.path().display());
+        write_lockfile(session, &new_project, &lockfile)
+    }
+}
@@ -0,0 +1,35 @@
+use clap::Args;
+
+use super::Session;
+use crate::prelude::*;
+use crate::transcript::{rollback, Mutation};
+
+#[derive(Args)]
+pub struct RollbackArgs {}
+
+impl RollbackArgs {
+    pub fn run(self, session: &Session) -> Result<()> {
+        let project = session.require_project()?;
+        match rollback(&project.root)? {
+            Some(undone) => {
+                for mutation in &undone.mutations {
+                    match mutation {
+                        Mutation::Created { path } => {
+                            debug!("removed {}", path.display())
+                        }
+                        Mutation::Wrote { path, .. } => {
+                            info!("restored {}", path.display())
+                        }
+                    }
+                }
+                info!(
+                    "Rolled back 'posy {}' ({} changes)",
+                    undone.command,
+                    undone.mutations.len()
+                );
+            }
+            None => info!("Nothing to roll back"),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use clap::Args;
+
+use super::{
+    exec_in_env, parse_requirement_args, print_install_summary, EnvArgs, Session,
+};
+use crate::prelude::*;
+use crate::workspace::ProjectWorkspace;
+
+#[derive(Args)]
+pub struct RunArgs {
+    #[command(flatten)]
+    env: EnvArgs,
+    /// Use the project's environment called NAME, e.g. "docs" or "test". It keeps the
+    /// same package versions from one run to the next, until the requirements change.
+    #[arg(long = "env", value_name = "NAME")]
+    env_name: Option<String>,
+    /// Install REQUIREMENT too, on top of the project's own requirements.
+    #[arg(long = "with", value_name = "REQUIREMENT")]
+    with: Vec<String>,
+    /// The command to run, and its arguments. Commands from the environment's packages
+    /// win over anything else on $PATH. A '--' right after the command is dropped, so
+    /// 'posy run black -- --check .' works too.
+    #[arg(
+        required = true,
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        value_name = "COMMAND"
+    )]
+    command: Vec<String>,
+}
+
+impl RunArgs {
+    pub fn run(self, session: &Session) -> Result<()> {
+        let project = session.require_project()?;
+        let mut requirements = project.config.requirements.clone();
+        let (with, local_requirements) = parse_requirement_args(&self.with)?;
+        requirements.extend(with);
+        let mut brief = self.env.brief(session, requirements)?;
+        brief.local_requirements.extend(local_requirements);
+
+        let db = session.package_db()?;
+        let platforms = PybiPlatform::native_platforms()?;
+        let env = match &self.env_name {
+            Some(name) => {
+                let mut workspace = ProjectWorkspace::new(&project.root);
+                workspace.transcript = session.transcript.clone();
+                workspace.get_env(name, &brief, &db, &session.env_forest, platforms)?
+            }
+            None => {
+                let blueprint = brief.resolve(&db, platforms, None, &[])?;
+                session
+                    .env_forest
+                    .get_env(&db, &blueprint, platforms, &[])?
+            }
+        };
+        print_install_summary(&env);
+
+        // exec_in_env doesn't come back, so this is our last chance
+        session.save_transcript("run")?;
+
+        let (program, args) = split_command(&self.command);
+        let mut cmd = match env.find_script(program) {
+            Some(path) => std::process::Command::new(path),
+            None => std::process::Command::new(program),
+        };
+        cmd.args(args);
+        exec_in_env(&env, cmd)
+    }
+}
+
+// 'posy run SCRIPT -- ARGS' means the same as 'posy run SCRIPT ARGS'. To pass a
+// literal '--' as the first argument, double it.
+fn split_command(command: &[String]) -> (&str, &[String]) {
+    // unwrap is safe b/c clap makes sure there's at least one entry
+    let (program, args) = command.split_first().unwrap();
+    match args.split_first() {
+        Some((first, rest)) if first == "--" => (program, rest),
+        _ => (program, args),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_command() {
+        let split = |command: &[&str]| {
+            let command = command.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            let (program, args) = split_command(&command);
+            (program.to_string(), args.to_vec())
+        };
+        assert_eq!(split(&["black"]), ("black".into(), vec![]));
+        assert_eq!(
+            split(&["black", "--check", "."]),
+            ("black".into(), vec!["--check".into(), ".".into()])
+        );
+        assert_eq!(
+            split(&["black", "--", "--check"]),
+            ("black".into(), vec!["--check".into()])
+        );
+        assert_eq!(
+            split(&["echo", "--", "--"]),
+            ("echo".into(), vec!["--".to_string()])
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use clap::Args;
+
+use super::{EnvArgs, PlatformArgs, Session};
+use crate::output::Table;
+use crate::prelude::*;
+
+#[derive(Args)]
+pub struct SupportMatrixArgs {
+    #[command(flatten)]
+    env: EnvArgs,
+    #[command(flatten)]
+    platform: PlatformArgs,
+    /// Which Python versions to try, e.g. '3.12'. Can be repeated. [default: 3.8
+    /// through 3.13]
+    #[arg(long = "python-version", value_name = "X.Y")]
+    python_versions: Vec<String>,
+}
+
+const DEFAULT_PYTHON_VERSIONS: &[&str] =
+    &["3.8", "3.9", "3.10", "3.11", "3.12", "3.13"];
+
+// Same interpreter as `python` (e.g. "cpython_unofficial"), but pinned to one minor
+// version, and whatever range the project asked for is ignored -- the whole point is
+// to find out which versions it could ask for.
+fn python_for_version(
+    python: &PythonRequirement,
+    version: &str,
+) -> Result<PythonRequirement> {
+    if python.url.is_some() {
+        bail!("can't try other Python versions when the project pins a specific pybi");
+    }
+    if !Regex::new(r"^[0-9]+\.[0-9]+$").unwrap().is_match(version) {
+        bail!("expected a Python version like '3.12', not {version:?}");
+    }
+    format!("{} == {version}.*", python.name.as_given()).parse()
+}
+
+impl SupportMatrixArgs {
+    pub fn run(self, session: &Session) -> Result<()> {
+        let project = session.require_project()?;
+        let brief = self
+            .env
+            .brief(session, project.config.requirements.clone())?;
+        let versions = if self.python_versions.is_empty() {
+            DEFAULT_PYTHON_VERSIONS
+                .iter()
+                .map(|v| v.to_string())
+                .collect()
+        } else {
+            self.python_versions
+        };
+        let pythons = versions
+            .iter()
+            .map(|version| python_for_version(&brief.python, version))
+            .collect::<Result<Vec<_>>>()?;
+
+        // One PackageDB for all of them, so each resolve after the first mostly hits
+        // the metadata and project pages that the earlier ones already fetched.
+        let db = session.package_db()?;
+        let platforms = self.platform.platforms()?;
+        let mut table = Table::new(["python", "result", "details"]);
+        let mut supported = Vec::new();
+        for (version, python) in versions.iter().zip(pythons) {
+            let mut brief = brief.clone();
+            brief.python = python;
+            brief.python_fallbacks.clear();
+            match brief.resolve(&db, &platforms, None, &[]) {
+                Ok(blueprint) => {
+                    table.push_row([
+                        version.clone(),
+                        "ok".into(),
+                        format!(
+                            "{} {}, {} packages",
+                            blueprint.pybi.name.as_given(),
+                            blueprint.pybi.version,
+                            blueprint.wheels.len() + blueprint.local.len(),
+                        ),
+                    ]);
+                    supported.push(version.as_str());
+                }
+                Err(err) => {
+                    debug!("resolving with Python {version} failed: {err:?}");
+                    let reason = err.root_cause().to_string();
+                    let reason = reason.lines().next().unwrap_or_default().to_string();
+                    table.push_row([version.clone(), "fails".into(), reason]);
+                }
+            }
+        }
+        table.print();
+        if supported.is_empty() {
+            bail!(
+                "couldn't resolve the requirements with any of those Python versions"
+            );
+        }
+        info!("Resolves with Python {}", supported.join(", "));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_python_for_version() {
+        let python: PythonRequirement = "cpython_unofficial >= 3.10".parse().unwrap();
+        assert_eq!(
+            python_for_version(&python, "3.8").unwrap().to_string(),
+            "cpython_unofficial == 3.8.*"
+        );
+        assert!(python_for_version(&python, "3").is_err());
+        assert!(python_for_version(&python, "3.8; rm -rf").is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use std::path::PathBuf;
+
+use clap::Args;
+
+use super::{check_size_budget, print_install_summary, EnvArgs, Session};
+use crate::output::{human_size, Table};
+use crate::package_db::PackageDB;
+use crate::prelude::*;
+use crate::project::ProjectConfig;
+use crate::resolve::Blueprint;
+
+#[derive(Args)]
+pub struct SyncArgs {
+    // Same options as `posy lock`, and the lockfile only counts as up to date if they
+    // match what it was made with.
+    #[command(flatten)]
+    env: EnvArgs,
+    /// Show the files the environment needs, and how big they are, without installing
+    /// anything.
+    #[arg(long)]
+    dry_run: bool,
+    /// Write a JSON report to FILE of which file we installed for each package, which
+    /// others we could have used, and why we didn't. ('-v' logs the same thing.)
+    #[arg(long, value_name = "FILE", conflicts_with = "dry_run")]
+    selection_report: Option<PathBuf>,
+}
+
+impl SyncArgs {
+    pub fn run(self, session: &Session) -> Result<()> {
+        let project = session.require_project()?;
+        let brief = self
+            .env
+            .brief(session, project.config.requirements.clone())?;
+        let lockfile = project.read_lockfile()?.ok_or_else(|| {
+            eyre!(
+                "no lockfile at {}; run 'posy lock' first",
+                project.lockfile_path().display()
+            )
+        })?;
+        let platforms = PybiPlatform::native_platforms()?;
+        let fresh = lockfile.check_fresh(&brief, platforms);
+
+        let db = session.package_db()?;
+        db.use_locked_builds(&lockfile.builds)?;
+        let locked = lockfile.blueprint_for(platforms);
+        let resolved;
+        let blueprint = match fresh {
+            Ok(()) => locked,
+            Err(err) if project.config.kind.requires_fresh_lock() => return Err(err),
+            Err(_) => {
+                // see ProjectKind::requires_fresh_lock
+                info!(
+                    "{} is out of date, so installing a fresh resolve instead (run \
+                     'posy lock' to update it)",
+                    project.lockfile_path().display()
+                );
+                resolved = brief.resolve(&db, platforms, Some(locked), &[])?;
+                &resolved
+            }
+        };
+        if self.dry_run {
+            return dry_run(&project.config, &db, blueprint, platforms);
+        }
+        let env = session.env_forest.get_env(&db, blueprint, platforms, &[])?;
+        print_install_summary(&env);
+        if let Some(path) = &self.selection_report {
+            context!("Writing selection report to {}", path.display());
+            std::fs::write(path, serde_json::to_string_pretty(&env.selections)?)?;
+        }
+        check_size_budget(&project.config, "The environment", || env.disk_usage())
+    }
+}
+
+// We only know download sizes, and only if the index tells us (PEP 700), so this is a
+// lower bound: unpacked wheels are bigger, and sdists get built into who knows what.
+// But if even the downloads are over budget, there's no point installing.
+fn dry_run(
+    config: &ProjectConfig,
+    db: &PackageDB,
+    blueprint: &Blueprint,
+    platforms: &[&PybiPlatform],
+) -> Result<()> {
+    let downloads = blueprint.artifact_urls(db, platforms)?;
+    let mut table = Table::new(["package", "version", "file", "size"]);
+    let mut total = 0;
+    let mut unknown = 0;
+    for download in &downloads {
+        let size = match download.size {
+            Some(size) => {
+                total += size;
+                human_size(size)
+            }
+            None => {
+                unknown += 1;
+                "?".into()
+            }
+        };
+        table.push_row([
+            download.name.as_given().to_string(),
+            download.version.to_string(),
+            download.filename.clone(),
+            size,
+        ]);
+    }
+    table.print();
+    if unknown > 0 {
+        info!(
+            "Downloads: at least {} ({unknown} files of unknown size)",
+            human_size(total)
+        );
+    } else {
+        info!("Downloads: {}", human_size(total));
+    }
+    check_size_budget(config, "Just downloading the files", || Ok(total))
+}
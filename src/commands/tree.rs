@@ -0,0 +1,36 @@
+use clap::Args;
+
+use super::{PlatformArgs, Session};
+use crate::dep_tree::DepTree;
+use crate::prelude::*;
+
+#[derive(Args)]
+pub struct TreeArgs {
+    /// Print the tree as JSON, for other tools to consume
+    #[arg(long)]
+    json: bool,
+    #[command(flatten)]
+    platform: PlatformArgs,
+}
+
+impl TreeArgs {
+    pub fn run(self, session: &Session) -> Result<()> {
+        let project = session.require_project()?;
+        let lockfile = project
+            .read_lockfile()?
+            .ok_or_else(|| eyre!("no posy.lock yet; run 'posy lock' first"))?;
+        let platforms = self.platform.platforms()?;
+        let brief = &lockfile.brief;
+        let tree = DepTree::new(
+            lockfile.blueprint_for(&platforms),
+            &brief.requirements,
+            &brief.local_requirements,
+        )?;
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&tree)?);
+        } else {
+            println!("{tree}");
+        }
+        Ok(())
+    }
+}
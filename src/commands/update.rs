@@ -0,0 +1,60 @@
+use clap::Args;
+
+use super::lock::{make_lockfile, write_lockfile, LockMode};
+use super::{EnvArgs, PlatformArgs, Session};
+use crate::blueprint_diff;
+use crate::prelude::*;
+
+#[derive(Args)]
+pub struct UpdateArgs {
+    /// Packages to upgrade to their newest usable versions. Everything else stays
+    /// where posy.lock has it, if possible. [default: upgrade everything]
+    #[arg(value_name = "PACKAGE")]
+    packages: Vec<String>,
+    #[command(flatten)]
+    env: EnvArgs,
+    #[command(flatten)]
+    platform: PlatformArgs,
+}
+
+impl UpdateArgs {
+    pub fn run(self, session: &Session) -> Result<()> {
+        let project = session.require_project()?;
+        let old = project.read_lockfile()?;
+        let targets = self
+            .packages
+            .iter()
+            .map(|p| p.parse())
+            .collect::<Result<Vec<PackageName>>>()?;
+        if let Some(old) = &old {
+            let blueprint = &old.blueprint;
+            for name in &targets {
+                let pinned = blueprint.pybi.name == *name
+                    || blueprint.wheels.iter().any(|(pin, _)| pin.name == *name);
+                if !pinned {
+                    warn!("{} isn't in posy.lock", name.as_given());
+                }
+            }
+        }
+        let mode = if targets.is_empty() {
+            LockMode::Upgrade(None)
+        } else {
+            LockMode::Upgrade(Some(&targets))
+        };
+        let lockfile =
+            make_lockfile(session, project, &self.env, &self.platform, mode)?;
+        match &old {
+            Some(old) => {
+                let changes = blueprint_diff::diff(&old.blueprint, &lockfile.blueprint);
+                if changes.is_empty() {
+                    info!("Everything is already up to date");
+                }
+                for change in changes {
+                    info!("{change}");
+                }
+            }
+            None => info!("No posy.lock yet, so locking from scratch"),
+        }
+        write_lockfile(session, project, &lockfile)
+    }
+}
@@ -0,0 +1,28 @@
+use clap::Args;
+
+use super::{BriefArgs, PlatformArgs, Session};
+use crate::prelude::*;
+
+#[derive(Args)]
+pub struct UrlsArgs {
+    #[command(flatten)]
+    brief: BriefArgs,
+    #[command(flatten)]
+    platform: PlatformArgs,
+}
+
+impl UrlsArgs {
+    pub fn run(self, session: &Session) -> Result<()> {
+        let db = session.package_db()?;
+        let platforms = self.platform.platforms()?;
+        let brief = self.brief.brief(session)?;
+        let blueprint = brief.resolve(&db, &platforms, None, &[])?;
+        for download in blueprint.artifact_urls(&db, &platforms)? {
+            match download.size {
+                Some(size) => println!("{} {} {}", download.url, download.hash, size),
+                None => println!("{} {}", download.url, download.hash),
+            }
+        }
+        Ok(())
+    }
+}
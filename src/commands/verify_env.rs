@@ -0,0 +1,50 @@
+use clap::Args;
+
+use super::Session;
+use crate::prelude::*;
+
+#[derive(Args)]
+pub struct VerifyEnvArgs {
+    /// Print the report as JSON on stdout, instead of as warnings.
+    #[arg(long)]
+    json: bool,
+}
+
+impl VerifyEnvArgs {
+    pub fn run(self, session: &Session) -> Result<()> {
+        let project = session.require_project()?;
+        let lockfile = project.read_lockfile()?.ok_or_else(|| {
+            eyre!(
+                "no lockfile at {}; nothing to verify against",
+                project.lockfile_path().display()
+            )
+        })?;
+
+        let db = session.package_db()?;
+        let platforms = PybiPlatform::native_platforms()?;
+        let blueprint = lockfile.blueprint_for(platforms);
+        let report = session.env_forest.verify_env(&db, blueprint, platforms)?;
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            for problem in &report.problems {
+                warn!("{problem}");
+            }
+        }
+        if !report.ok {
+            bail!(
+                "environment doesn't match {} ({} problems)",
+                project.lockfile_path().display(),
+                report.problems.len()
+            );
+        }
+        info!(
+            "Environment matches {}: {} plus {} packages",
+            project.lockfile_path().display(),
+            report.pybi,
+            report.checked_wheels
+        );
+        Ok(())
+    }
+}
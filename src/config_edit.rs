@@ -0,0 +1,298 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml_edit::{Array, Document, Item, Table};
+
+use crate::prelude::*;
+use crate::project::{config_path, Project};
+use crate::transcript::Transcript;
+
+// Editing a project's requirements in place, for 'posy add' and 'posy remove'. We go
+// through toml_edit's Document instead of serde, so everything we don't touch --
+// comments, key order, other tools' tables -- comes back out exactly the way the user
+// wrote it.
+
+pub struct ConfigEditor {
+    root: PathBuf,
+    path: PathBuf,
+    doc: Document,
+    // in pyproject.toml, our settings live under [tool.posy]; in posy.toml, they're at
+    // the top level
+    in_pyproject: bool,
+}
+
+// The name an existing entry in the requirements array refers to, if we can tell.
+// Entries that don't parse get left alone; the error will come when the config gets
+// loaded.
+fn entry_name(value: &toml_edit::Value) -> Option<PackageName> {
+    let req: UserRequirement = value.as_str()?.parse().ok()?;
+    Some(req.name.clone())
+}
+
+impl ConfigEditor {
+    pub fn open(project_root: &Path) -> Result<ConfigEditor> {
+        let path = config_path(project_root).ok_or_else(|| {
+            eyre!(
+                "no posy.toml or pyproject.toml in {}",
+                project_root.display()
+            )
+        })?;
+        context!("Reading {}", path.display());
+        let doc = fs::read_to_string(&path)?.parse::<Document>()?;
+        Ok(ConfigEditor {
+            root: project_root.into(),
+            in_pyproject: path.file_name() == Some("pyproject.toml".as_ref()),
+            path,
+            doc,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn posy_table(&mut self) -> Result<&mut Table> {
+        if !self.in_pyproject {
+            return Ok(self.doc.as_table_mut());
+        }
+        let mut new_tool = Table::new();
+        // no empty [tool] header, just [tool.posy]
+        new_tool.set_implicit(true);
+        let tool = self
+            .doc
+            .entry("tool")
+            .or_insert(Item::Table(new_tool))
+            .as_table_mut()
+            .ok_or_else(|| eyre!("'tool' in pyproject.toml isn't a table"))?;
+        tool.entry("posy")
+            .or_insert(Item::Table(Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| eyre!("'tool.posy' in pyproject.toml isn't a table"))
+    }
+
+    // Until [tool.posy] has requirements of its own, a Poetry project gets its
+    // requirements from [tool.poetry.dependencies] (see Project::from_pyproject_toml).
+    // Once we write ours, those stop counting, so copy them over first instead of
+    // silently dropping them.
+    fn poetry_requirements(&self) -> Result<Vec<UserRequirement>> {
+        let deps = self
+            .doc
+            .get("tool")
+            .and_then(|tool| tool.get("poetry"))
+            .and_then(|poetry| poetry.get("dependencies"))
+            .and_then(|deps| deps.as_table_like());
+        match deps {
+            Some(deps) if self.in_pyproject => Ok(crate::poetry::requirements(deps)?.0),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn requirements(&mut self) -> Result<&mut Array> {
+        if !self.posy_table()?.contains_key("requirements") {
+            let seed = self.poetry_requirements()?;
+            if !seed.is_empty() {
+                info!("Copying the requirements from [tool.poetry.dependencies]");
+            }
+            let seed = seed.iter().map(|req| req.to_string()).collect::<Array>();
+            self.posy_table()?
+                .insert("requirements", toml_edit::value(seed));
+        }
+        self.posy_table()?
+            .get_mut("requirements")
+            // unwrap is safe because we just made sure it's there
+            .unwrap()
+            .as_array_mut()
+            .ok_or_else(|| eyre!("'requirements' should be an array of strings"))
+    }
+
+    // If the array was all on one line, then after adding and removing stuff it can
+    // end up with odd spacing, so tidy it up. If it's one-per-line, we leave it alone,
+    // since fmt() would squash it onto one line.
+    //
+    // XX TODO: for one-per-line arrays, copy the indentation from the existing entries
+    // when adding one
+    fn edit_requirements<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Array) -> T,
+    {
+        let requirements = self.requirements()?;
+        let one_line = !requirements.to_string().contains('\n');
+        let result = f(requirements);
+        if one_line {
+            requirements.fmt();
+        }
+        Ok(result)
+    }
+
+    /// Adds `req` to the project's requirements. If there's already one for the same
+    /// package, replaces it (in the same spot), and returns what it used to be.
+    pub fn add_requirement(&mut self, req: &UserRequirement) -> Result<Option<String>> {
+        self.edit_requirements(|requirements| {
+            let existing = requirements
+                .iter()
+                .position(|value| entry_name(value).as_ref() == Some(&req.name));
+            match existing {
+                Some(i) => {
+                    let old = requirements.replace(i, req.to_string());
+                    Some(old.as_str().unwrap_or_default().to_owned())
+                }
+                None => {
+                    requirements.push(req.to_string());
+                    None
+                }
+            }
+        })
+    }
+
+    /// Removes any requirements on `name`, and returns what they were.
+    pub fn remove_requirement(&mut self, name: &PackageName) -> Result<Vec<String>> {
+        self.edit_requirements(|requirements| {
+            let mut removed = Vec::new();
+            let mut i = 0;
+            while i < requirements.len() {
+                // unwrap is safe because i is in range
+                if entry_name(requirements.get(i).unwrap()).as_ref() == Some(name) {
+                    let old = requirements.remove(i);
+                    removed.push(old.as_str().unwrap_or_default().to_owned());
+                } else {
+                    i += 1;
+                }
+            }
+            removed
+        })
+    }
+
+    /// The project, as it'll be once we save our changes.
+    pub fn project(&self) -> Result<Project> {
+        context!("Checking the new contents of {}", self.path.display());
+        // unwrap is safe because config_path only returns things with file names
+        let file_name = self.path.file_name().unwrap().to_string_lossy();
+        Project::from_config(&self.root, &file_name, &self.doc.to_string())
+    }
+
+    pub fn save(&self, transcript: Option<&Transcript>) -> Result<()> {
+        context!("Writing {}", self.path.display());
+        if let Some(transcript) = transcript {
+            transcript.will_write(&self.path)?;
+        }
+        let mut out = tempfile::NamedTempFile::new_in(&self.root)?;
+        out.write_all(self.doc.to_string().as_bytes())?;
+        out.persist(&self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use indoc::indoc;
+
+    fn open_with(file_name: &str, contents: &str) -> (tempfile::TempDir, ConfigEditor) {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::write(tmp.path().join(file_name), contents).unwrap();
+        let editor = ConfigEditor::open(tmp.path()).unwrap();
+        (tmp, editor)
+    }
+
+    fn req(s: &str) -> UserRequirement {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_edit_posy_toml() {
+        let (_tmp, mut editor) = open_with(
+            "posy.toml",
+            indoc! {r#"
+                # our app
+                kind = "app"
+                requirements = ["trio", "Attrs >= 22"]  # keep these sorted
+
+                [index-pins]
+                foo = "https://example.com/simple/"
+            "#},
+        );
+        assert_eq!(editor.add_requirement(&req("numpy >= 1.24")).unwrap(), None);
+        assert_eq!(
+            editor.add_requirement(&req("attrs >= 23")).unwrap(),
+            Some("Attrs >= 22".into())
+        );
+        assert_eq!(
+            editor.remove_requirement(&"TRIO".parse().unwrap()).unwrap(),
+            vec!["trio"]
+        );
+        assert!(editor
+            .remove_requirement(&"scipy".parse().unwrap())
+            .unwrap()
+            .is_empty());
+        editor.save(None).unwrap();
+
+        insta::assert_snapshot!(fs::read_to_string(editor.path()).unwrap(), @r###"
+        # our app
+        kind = "app"
+        requirements = ["attrs >= 23", "numpy >= 1.24"]  # keep these sorted
+
+        [index-pins]
+        foo = "https://example.com/simple/"
+        "###);
+        let project = editor.project().unwrap();
+        assert_eq!(project.config.requirements.len(), 2);
+    }
+
+    #[test]
+    fn test_edit_pyproject_toml() {
+        let (_tmp, mut editor) = open_with(
+            "pyproject.toml",
+            indoc! {r#"
+                [project]
+                name = "whatever"
+                dependencies = ["not-ours"]
+            "#},
+        );
+        editor.add_requirement(&req("numpy")).unwrap();
+        editor.save(None).unwrap();
+        let written = fs::read_to_string(editor.path()).unwrap();
+        assert!(written.starts_with("[project]\n"));
+        assert!(written.contains("[tool.posy]\nrequirements = [\"numpy\"]\n"));
+        assert!(!written.contains("[tool]"));
+        let project = editor.project().unwrap();
+        assert_eq!(project.config.requirements, vec![req("numpy")]);
+
+        let (_tmp, mut editor) =
+            open_with("pyproject.toml", "[tool.posy]\nrequirements = 'oops'\n");
+        assert!(editor.add_requirement(&req("numpy")).is_err());
+
+        // on a Poetry project, the Poetry requirements come along
+        let (_tmp, mut editor) = open_with(
+            "pyproject.toml",
+            indoc! {r#"
+                [tool.poetry.dependencies]
+                python = "^3.9"
+                requests = "^2.28"
+                attrs = "*"
+            "#},
+        );
+        editor.add_requirement(&req("numpy")).unwrap();
+        assert_eq!(
+            editor
+                .remove_requirement(&"attrs".parse().unwrap())
+                .unwrap(),
+            vec!["attrs"]
+        );
+        editor.save(None).unwrap();
+        let written = fs::read_to_string(editor.path()).unwrap();
+        assert!(written.contains(
+            "[tool.posy]\nrequirements = [\"requests >= 2.28, < 3\", \"numpy\"]\n"
+        ));
+        let project = editor.project().unwrap();
+        assert_eq!(
+            project.config.requirements,
+            vec![req("requests >= 2.28, < 3"), req("numpy")]
+        );
+
+        // posy.toml wins if there's both
+        let (tmp, _) = open_with("pyproject.toml", "");
+        fs::write(tmp.path().join("posy.toml"), "").unwrap();
+        let editor = ConfigEditor::open(tmp.path()).unwrap();
+        assert!(editor.path().ends_with("posy.toml"));
+    }
+}
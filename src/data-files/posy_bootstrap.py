@@ -0,0 +1,15 @@
+# Imported from _posy_bootstrap.pth in the pybi's purelib, while site.py is processing
+# site-packages. This used to be a sitecustomize.py, but then any package that shipped
+# its own sitecustomize would either shadow us or be shadowed by us.
+#
+# Python only runs this once per process (it's a module), and site.addsitedir skips
+# directories that are already on sys.path, so it's safe if we somehow get here twice.
+import os, sys, site
+
+if "POSY_PYTHON_PACKAGES" in os.environ:
+    paths = os.environ["POSY_PYTHON_PACKAGES"].split(os.pathsep)
+    for path in paths:
+        site.addsitedir(path)
+else:
+    sys.stderr.write("This Python is managed by, and should be launched by, Posy.\n")
+    sys.stderr.write("Unexpected things may happen if you continue.\n")
@@ -0,0 +1,481 @@
+use std::collections::VecDeque;
+
+use crate::dep_tree::without_marker;
+use crate::prelude::*;
+use crate::resolve::WheelResolveMetadataInner;
+
+// For 'posy lock --metadata-only': everything the requirements could pull in, and what
+// version ranges get asked for, without committing to any pins. We walk down the graph
+// breadth-first, and for each package we read the metadata of whichever version we'd
+// pick right now -- the most-preferred one that satisfies every requirement on it seen
+// so far. That's not a real resolution: a requirement we only find later can rule out
+// the version we already looked at. When that happens we flag the package instead of
+// going back and redoing everything underneath it, which could go on forever.
+
+/// Where the walk gets its information; see Brief::explore.
+pub trait ExploreSource {
+    /// Versions of `name` we could install, most-preferred first.
+    fn candidates(&self, name: &PackageName) -> Result<Vec<Version>>;
+    fn release_metadata(
+        &self,
+        name: &PackageName,
+        version: &Version,
+    ) -> Result<WheelResolveMetadataInner>;
+    /// The parts of the metadata that are just for people reading the report.
+    fn release_info(
+        &self,
+        name: &PackageName,
+        version: &Version,
+    ) -> Result<ReleaseInfo>;
+    fn marker_applies(
+        &self,
+        expr: &marker::EnvMarkerExpr,
+        extra: Option<&Extra>,
+    ) -> Result<bool>;
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReleaseInfo {
+    /// License-Expression if there is one, otherwise the free-text License.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub classifiers: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RequiredBy {
+    /// e.g. "sphinx 6.1.0", or "sphinx[docs] 6.1.0"; None for the top-level
+    /// requirements.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub by: Option<String>,
+    /// As the dependent wrote it, minus the environment marker.
+    pub requirement: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReportedPackage {
+    pub name: String,
+    /// Every specifier anyone asked for (including constraints), all together.
+    pub range: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extras: Vec<String>,
+    /// The version whose metadata we read, or None if nothing fit.
+    pub explored: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requires_python: Option<String>,
+    /// Set if `explored` doesn't actually fit `range` (see above).
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+    #[serde(flatten)]
+    pub info: ReleaseInfo,
+    pub required_by: Vec<RequiredBy>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DependencyReport {
+    /// The python whose environment markers we evaluated.
+    pub python: String,
+    pub packages: Vec<ReportedPackage>,
+}
+
+#[derive(Default)]
+struct Found {
+    specifiers: Vec<Specifier>,
+    extras: Vec<Extra>,
+    // None until we've tried; Some(None) if no version fit
+    explored: Option<Option<(Version, WheelResolveMetadataInner)>>,
+    // which of the base requirements (None) and extras we've followed so far
+    followed: HashSet<Option<Extra>>,
+    required_by: Vec<RequiredBy>,
+}
+
+struct Explorer<'a> {
+    source: &'a dyn ExploreSource,
+    constraints: HashMap<PackageName, Vec<Specifier>>,
+    // in the order we found them
+    order: Vec<PackageName>,
+    found: HashMap<PackageName, Found>,
+    queue: VecDeque<(PackageName, Option<Extra>)>,
+}
+
+impl<'a> Explorer<'a> {
+    fn require(
+        &mut self,
+        req: &Requirement,
+        requirement: String,
+        by: Option<String>,
+    ) -> Result<()> {
+        if !self.found.contains_key(&req.name) {
+            self.order.push(req.name.clone());
+        }
+        let found = self.found.entry(req.name.clone()).or_default();
+        for spec in &req.specifiers.0 {
+            if !found.specifiers.contains(spec) {
+                found.specifiers.push(spec.clone());
+            }
+        }
+        found.required_by.push(RequiredBy { by, requirement });
+        self.queue.push_back((req.name.clone(), None));
+        for extra in &req.extras {
+            if !found.extras.contains(extra) {
+                found.extras.push(extra.clone());
+            }
+            self.queue
+                .push_back((req.name.clone(), Some(extra.clone())));
+        }
+        Ok(())
+    }
+
+    fn range(&self, name: &PackageName) -> Specifiers {
+        let mut specifiers = self.found[name].specifiers.clone();
+        if let Some(constraint) = self.constraints.get(name) {
+            specifiers.extend(constraint.iter().cloned());
+        }
+        Specifiers(specifiers)
+    }
+
+    fn visit(&mut self, name: PackageName, extra: Option<Extra>) -> Result<()> {
+        if self.found[&name].explored.is_none() {
+            let range = self.range(&name);
+            let mut explored = None;
+            for version in self.source.candidates(&name)? {
+                if range.satisfied_by(&version)? {
+                    context!("fetching metadata for {} {}", name.as_given(), version);
+                    let metadata = self.source.release_metadata(&name, &version)?;
+                    explored = Some((version, metadata));
+                    break;
+                }
+            }
+            // unwrap is safe b/c we only queue packages after adding them
+            self.found.get_mut(&name).unwrap().explored = Some(explored);
+        }
+        let found = self.found.get_mut(&name).unwrap();
+        if !found.followed.insert(extra.clone()) {
+            return Ok(());
+        }
+        let (version, metadata) = match &found.explored {
+            Some(Some((version, metadata))) => (version.clone(), metadata.clone()),
+            _ => return Ok(()),
+        };
+        if let Some(extra) = &extra {
+            if !metadata.extras.contains(extra) {
+                warn!(
+                    "{} {} has no extra [{}]",
+                    name.as_given(),
+                    version,
+                    extra.as_given()
+                );
+                return Ok(());
+            }
+        }
+        let by = match &extra {
+            None => format!("{} {}", name.as_given(), version),
+            Some(extra) => {
+                format!("{}[{}] {}", name.as_given(), extra.as_given(), version)
+            }
+        };
+        for req in &metadata.requires_dist {
+            if let Some(expr) = &req.env_marker_expr {
+                let applies = self.source.marker_applies(expr, extra.as_ref())?;
+                // the extra's own requirements are the ones that only apply because
+                // of it; the rest we followed already, without it
+                let base = extra.is_some() && self.source.marker_applies(expr, None)?;
+                if !applies || base {
+                    continue;
+                }
+            } else if extra.is_some() {
+                continue;
+            }
+            self.require(req, without_marker(req), Some(by.clone()))?;
+        }
+        Ok(())
+    }
+}
+
+impl DependencyReport {
+    /// Walks the graph under `requirements` and `local` (usually the Brief's).
+    pub fn explore(
+        source: &dyn ExploreSource,
+        python: String,
+        requirements: &[UserRequirement],
+        local: &[LocalRequirement],
+        constraints: &[UserRequirement],
+    ) -> Result<DependencyReport> {
+        let mut explorer = Explorer {
+            source,
+            constraints: HashMap::new(),
+            order: Vec::new(),
+            found: HashMap::new(),
+            queue: VecDeque::new(),
+        };
+        for constraint in constraints {
+            if let Some(expr) = &constraint.env_marker_expr {
+                if !source.marker_applies(expr, None)? {
+                    continue;
+                }
+            }
+            explorer
+                .constraints
+                .entry(constraint.name.clone())
+                .or_default()
+                .extend(constraint.specifiers.0.iter().cloned());
+        }
+        for req in requirements {
+            if let Some(expr) = &req.env_marker_expr {
+                if !source.marker_applies(expr, None)? {
+                    continue;
+                }
+            }
+            explorer.require(req, without_marker(req), None)?;
+        }
+        for req in local {
+            let as_requirement = Requirement {
+                name: req.name.clone(),
+                extras: req.extras.clone(),
+                specifiers: Default::default(),
+                env_marker_expr: None,
+            };
+            explorer.require(&as_requirement, req.to_string(), None)?;
+        }
+        while let Some((name, extra)) = explorer.queue.pop_front() {
+            explorer.visit(name, extra)?;
+        }
+
+        let mut packages = Vec::new();
+        for name in &explorer.order {
+            let range = explorer.range(name);
+            let found = &explorer.found[name];
+            let explored = found.explored.as_ref().and_then(|e| e.as_ref());
+            let stale = match explored {
+                Some((version, _)) => !range.satisfied_by(version)?,
+                None => false,
+            };
+            let info = match explored {
+                Some((version, _)) => source.release_info(name, version)?,
+                None => Default::default(),
+            };
+            packages.push(ReportedPackage {
+                name: name.as_given().to_string(),
+                range: range.to_string(),
+                extras: found
+                    .extras
+                    .iter()
+                    .map(|e| e.as_given().to_string())
+                    .collect(),
+                explored: explored.map(|(version, _)| version.to_string()),
+                requires_python: explored
+                    .map(|(_, metadata)| metadata.requires_python.to_string())
+                    .filter(|rp| !rp.is_empty()),
+                stale,
+                info,
+                required_by: found.required_by.clone(),
+            });
+        }
+        Ok(DependencyReport { python, packages })
+    }
+}
+
+impl Display for DependencyReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (unpinned)", self.python)?;
+        for package in &self.packages {
+            write!(f, "\n{}", package.name)?;
+            if !package.extras.is_empty() {
+                write!(f, "[{}]", package.extras.join(","))?;
+            }
+            if package.range.is_empty() {
+                write!(f, " (any version)")?;
+            } else {
+                write!(f, " {}", package.range)?;
+            }
+            match &package.explored {
+                Some(version) => write!(f, ", explored {version}")?,
+                None => write!(f, ", no version fits!")?,
+            }
+            if package.stale {
+                write!(f, " (outside the range; its dependencies may be off)")?;
+            }
+            // the free-text License field is sometimes the whole license file
+            if let Some(license) = &package.info.license {
+                write!(f, "\n  license: {}", license.lines().next().unwrap_or(""))?;
+            }
+            // all the classifiers would be a lot; the JSON has them
+            for classifier in &package.info.classifiers {
+                if classifier.starts_with("License ::") {
+                    write!(f, "\n  {classifier}")?;
+                }
+            }
+            for required_by in &package.required_by {
+                match &required_by.by {
+                    Some(by) => write!(f, "\n  - {by}: {}", required_by.requirement)?,
+                    None => write!(
+                        f,
+                        "\n  - your requirements: {}",
+                        required_by.requirement
+                    )?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct FakeIndex {
+        // name -> [(version, requires_dist)], most-preferred first
+        releases: HashMap<&'static str, Vec<(&'static str, Vec<&'static str>)>>,
+    }
+
+    impl ExploreSource for FakeIndex {
+        fn candidates(&self, name: &PackageName) -> Result<Vec<Version>> {
+            Ok(self.releases[name.normalized()]
+                .iter()
+                .map(|(version, _)| version.parse().unwrap())
+                .collect())
+        }
+
+        fn release_metadata(
+            &self,
+            name: &PackageName,
+            version: &Version,
+        ) -> Result<WheelResolveMetadataInner> {
+            let (_, requires_dist) = self.releases[name.normalized()]
+                .iter()
+                .find(|(v, _)| v.parse::<Version>().unwrap() == *version)
+                .unwrap();
+            Ok(WheelResolveMetadataInner {
+                requires_dist: requires_dist
+                    .iter()
+                    .map(|r| r.parse().unwrap())
+                    .collect(),
+                requires_python: ">= 3.8".parse().unwrap(),
+                extras: ["socks".parse().unwrap()].into_iter().collect(),
+            })
+        }
+
+        fn release_info(
+            &self,
+            name: &PackageName,
+            _version: &Version,
+        ) -> Result<ReleaseInfo> {
+            Ok(match name.normalized() {
+                "requests" => ReleaseInfo {
+                    license: Some("Apache 2.0".into()),
+                    classifiers: vec![
+                        "License :: OSI Approved :: Apache Software License".into(),
+                        "Programming Language :: Python :: 3".into(),
+                    ],
+                },
+                _ => Default::default(),
+            })
+        }
+
+        fn marker_applies(
+            &self,
+            expr: &marker::EnvMarkerExpr,
+            extra: Option<&Extra>,
+        ) -> Result<bool> {
+            // no real environment; only "extra == ..." markers in here
+            Ok(extra.map(|e| expr.to_string().contains(e.normalized())) == Some(true))
+        }
+    }
+
+    #[test]
+    fn test_explore() {
+        let index = FakeIndex {
+            releases: [
+                (
+                    "requests",
+                    vec![(
+                        "2.31.0",
+                        vec![
+                            "urllib3 >= 1.21.1, < 3",
+                            "idna >= 2.5",
+                            "pysocks >= 1.5.6; extra == 'socks'",
+                        ],
+                    )],
+                ),
+                ("urllib3", vec![("2.0.4", vec![]), ("1.26.16", vec![])]),
+                ("idna", vec![("3.4", vec![])]),
+                ("pysocks", vec![("1.7.1", vec![])]),
+                ("boto3", vec![("1.28.0", vec!["botocore >= 1.31"])]),
+                ("botocore", vec![("1.31.0", vec!["urllib3 < 1.27"])]),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let requirements =
+            vec!["requests[socks]".parse().unwrap(), "boto3".parse().unwrap()];
+        let constraints = vec!["idna < 4".parse().unwrap()];
+        let report = DependencyReport::explore(
+            &index,
+            "cpython 3.11.4".into(),
+            &requirements,
+            &[],
+            &constraints,
+        )
+        .unwrap();
+
+        let names = report
+            .packages
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["requests", "boto3", "urllib3", "idna", "pysocks", "botocore"]
+        );
+
+        let requests = &report.packages[0];
+        assert_eq!(requests.extras, ["socks"]);
+        assert_eq!(requests.explored.as_deref(), Some("2.31.0"));
+        assert_eq!(requests.requires_python.as_deref(), Some(">= 3.8"));
+        assert_eq!(requests.info.license.as_deref(), Some("Apache 2.0"));
+        assert_eq!(requests.info.classifiers.len(), 2);
+        let json = serde_json::to_value(requests).unwrap();
+        assert_eq!(json["license"], "Apache 2.0");
+        assert!(serde_json::to_value(&report.packages[1])
+            .unwrap()
+            .get("classifiers")
+            .is_none());
+        let text = report.to_string();
+        assert!(text.contains(
+            "\n  license: Apache 2.0\n  License :: OSI Approved :: Apache Software License"
+        ));
+        assert!(!text.contains("Programming Language"));
+
+        // we'd already looked at urllib3 by the time botocore's cap showed up
+        let urllib3 = &report.packages[2];
+        assert_eq!(urllib3.range, ">= 1.21.1, < 3, < 1.27");
+        assert_eq!(urllib3.explored.as_deref(), Some("2.0.4"));
+        assert!(urllib3.stale);
+        assert_eq!(
+            urllib3.required_by,
+            [
+                RequiredBy {
+                    by: Some("requests 2.31.0".into()),
+                    requirement: "urllib3 >= 1.21.1, < 3".into(),
+                },
+                RequiredBy {
+                    by: Some("botocore 1.31.0".into()),
+                    requirement: "urllib3 < 1.27".into(),
+                },
+            ]
+        );
+
+        assert_eq!(report.packages[3].range, ">= 2.5, < 4");
+        assert!(!report.packages[3].stale);
+        assert_eq!(
+            report.packages[4].required_by[0].by.as_deref(),
+            Some("requests[socks] 2.31.0")
+        );
+    }
+}
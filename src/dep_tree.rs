@@ -0,0 +1,362 @@
+use crate::prelude::*;
+use crate::resolve::{Blueprint, WheelResolveMetadata};
+
+// Who pulled in what, for 'posy tree'. The resolver doesn't keep the dependency graph
+// around, but a Blueprint has everything we need to rebuild it: each package's
+// requires_dist, plus the value of every environment marker the resolver looked at. So
+// we walk down from the top-level requirements, following the same edges the resolver
+// did. Like 'cargo tree', a package that's already been shown with all its
+// dependencies gets marked as a repeat instead of expanded again -- which also takes
+// care of cycles.
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TreeNode {
+    pub name: String,
+    /// None if the blueprint doesn't pin this package, which would mean it's broken.
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extras: Vec<String>,
+    /// The requirement that pulled this package in, as its dependent wrote it (minus
+    /// the environment marker).
+    pub requirement: String,
+    /// If we only needed this because of one of the dependent's extras, which one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub via_extra: Option<String>,
+    /// Already shown (with dependencies) somewhere above this.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub repeat: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<TreeNode>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DepTree {
+    pub python: String,
+    pub requirements: Vec<TreeNode>,
+}
+
+struct Walker<'a> {
+    blueprint: &'a Blueprint,
+    expanded: HashSet<(String, Vec<String>)>,
+}
+
+impl<'a> Walker<'a> {
+    fn metadata(
+        &self,
+        name: &PackageName,
+    ) -> Option<(&'a Version, &'a WheelResolveMetadata)> {
+        let wheels = self
+            .blueprint
+            .wheels
+            .iter()
+            .map(|(pin, m)| (&pin.name, &pin.version, m));
+        let local = self
+            .blueprint
+            .local
+            .iter()
+            .map(|(pin, m)| (&pin.name, &pin.version, m));
+        wheels
+            .chain(local)
+            .find(|(pin_name, _, _)| *pin_name == name)
+            .map(|(_, version, m)| (version, m))
+    }
+
+    fn node(
+        &mut self,
+        name: &PackageName,
+        extras: &[Extra],
+        requirement: String,
+        via_extra: Option<&Extra>,
+    ) -> Result<TreeNode> {
+        let found = self.metadata(name);
+        let mut extra_keys = extras
+            .iter()
+            .map(|e| e.normalized().to_string())
+            .collect::<Vec<_>>();
+        extra_keys.sort_unstable();
+        let repeat = found.is_some()
+            && !self
+                .expanded
+                .insert((name.normalized().to_string(), extra_keys));
+        let mut node = TreeNode {
+            name: name.as_given().to_string(),
+            version: found.map(|(version, _)| version.to_string()),
+            extras: extras.iter().map(|e| e.as_given().to_string()).collect(),
+            requirement,
+            via_extra: via_extra.map(|e| e.as_given().to_string()),
+            repeat,
+            dependencies: Vec::new(),
+        };
+        let metadata = match found {
+            Some((_, metadata)) if !repeat => metadata,
+            _ => return Ok(node),
+        };
+        for req in &metadata.inner.requires_dist {
+            // the base requirements, then the ones that only apply because of an extra
+            let via = match &req.env_marker_expr {
+                None => None,
+                Some(expr) => {
+                    if self.blueprint.marker_applies(expr, None)? {
+                        None
+                    } else {
+                        let mut via = None;
+                        for extra in extras {
+                            if self.blueprint.marker_applies(expr, Some(extra))? {
+                                via = Some(extra);
+                                break;
+                            }
+                        }
+                        match via {
+                            Some(extra) => Some(extra),
+                            None => continue,
+                        }
+                    }
+                }
+            };
+            node.dependencies.push(self.node(
+                &req.name,
+                &req.extras,
+                without_marker(req),
+                via,
+            )?);
+        }
+        Ok(node)
+    }
+}
+
+pub fn without_marker(req: &Requirement) -> String {
+    let mut req = req.clone();
+    req.env_marker_expr = None;
+    req.to_string()
+}
+
+impl DepTree {
+    /// Rebuilds the tree of dependencies under `requirements` and `local` (usually the
+    /// Brief's) from what's recorded in `blueprint`.
+    pub fn new(
+        blueprint: &Blueprint,
+        requirements: &[UserRequirement],
+        local: &[LocalRequirement],
+    ) -> Result<DepTree> {
+        let mut walker = Walker {
+            blueprint,
+            expanded: HashSet::new(),
+        };
+        let mut roots = Vec::new();
+        for req in requirements {
+            if let Some(expr) = &req.env_marker_expr {
+                if !blueprint.marker_applies(expr, None)? {
+                    continue;
+                }
+            }
+            roots.push(walker.node(
+                &req.name,
+                &req.extras,
+                without_marker(req),
+                None,
+            )?);
+        }
+        for req in local {
+            roots.push(walker.node(&req.name, &req.extras, req.to_string(), None)?);
+        }
+        Ok(DepTree {
+            python: format!(
+                "{} {}",
+                blueprint.pybi.name.as_given(),
+                blueprint.pybi.version
+            ),
+            requirements: roots,
+        })
+    }
+
+    /// Every package in the tree that the blueprint has a pin for.
+    pub fn pinned_names(&self) -> Result<HashSet<PackageName>> {
+        fn walk(node: &TreeNode, out: &mut HashSet<PackageName>) -> Result<()> {
+            if node.version.is_some() {
+                out.insert(node.name.parse()?);
+            }
+            for child in &node.dependencies {
+                walk(child, out)?;
+            }
+            Ok(())
+        }
+        let mut names = HashSet::new();
+        for node in &self.requirements {
+            walk(node, &mut names)?;
+        }
+        Ok(names)
+    }
+}
+
+fn write_node(
+    f: &mut std::fmt::Formatter<'_>,
+    node: &TreeNode,
+    depth: usize,
+) -> std::fmt::Result {
+    write!(f, "\n{}{}", "  ".repeat(depth), node.name)?;
+    if !node.extras.is_empty() {
+        write!(f, "[{}]", node.extras.join(","))?;
+    }
+    match &node.version {
+        Some(version) => write!(f, " {version}")?,
+        None => write!(f, " (not pinned!)")?,
+    }
+    if let Some(extra) = &node.via_extra {
+        write!(f, " (for [{extra}])")?;
+    }
+    if node.repeat {
+        write!(f, " (*)")?;
+    }
+    for child in &node.dependencies {
+        write_node(f, child, depth + 1)?;
+    }
+    Ok(())
+}
+
+impl Display for DepTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.python)?;
+        for node in &self.requirements {
+            write_node(f, node, 0)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resolve::{PinnedPackage, WheelResolveMetadataInner};
+    use indoc::indoc;
+
+    fn pin(name: &str, version: &str) -> PinnedPackage {
+        PinnedPackage {
+            name: name.parse().unwrap(),
+            version: version.try_into().unwrap(),
+            hashes: vec![],
+            url: None,
+        }
+    }
+
+    fn wheel(
+        name: &str,
+        version: &str,
+        requires_dist: &[&str],
+    ) -> (PinnedPackage, WheelResolveMetadata) {
+        let metadata = WheelResolveMetadata {
+            provenance: "test".into(),
+            inner: WheelResolveMetadataInner {
+                requires_dist: requires_dist
+                    .iter()
+                    .map(|r| r.parse().unwrap())
+                    .collect(),
+                requires_python: Default::default(),
+                extras: Default::default(),
+            },
+        };
+        (pin(name, version), metadata)
+    }
+
+    #[test]
+    fn test_dep_tree() {
+        let blueprint = Blueprint {
+            pybi: pin("cpython", "3.11.1"),
+            wheels: vec![
+                wheel(
+                    "trio",
+                    "0.22.0",
+                    &[
+                        "attrs >= 20",
+                        "sniffio",
+                        "exceptiongroup; python_version < '3.11'",
+                        "outcome",
+                    ],
+                ),
+                wheel("attrs", "22.2.0", &[]),
+                wheel("sniffio", "1.3.0", &[]),
+                wheel("outcome", "1.2.0", &["attrs >= 19"]),
+                wheel(
+                    "Black",
+                    "23.1.0",
+                    &[
+                        "click >= 8",
+                        "ipython >= 7; extra == 'jupyter'",
+                        "tokenize-rt; python_version >= '3' and extra == 'jupyter'",
+                        "colorama; platform_system == 'Windows'",
+                    ],
+                ),
+                wheel("click", "8.1.3", &[]),
+                wheel("ipython", "8.10.0", &[]),
+                wheel("tokenize-rt", "5.0.0", &[]),
+            ],
+            local: vec![],
+            marker_expressions: [
+                ("python_version < '3.11'", false),
+                ("python_version >= '3'", true),
+                ("platform_system == 'Windows'", false),
+            ]
+            .into_iter()
+            .map(|(expr, value)| (expr.try_into().unwrap(), value))
+            .collect(),
+            python_fallback: None,
+            annotations: Default::default(),
+            claimed_publishers: Default::default(),
+            platform_tags: Default::default(),
+            source_builds: Default::default(),
+            warnings: Vec::new(),
+            policy: Default::default(),
+            python_flags: Vec::new(),
+        };
+        let requirements = ["trio", "black[jupyter]", "pywin32; os_name == 'nt'"]
+            .into_iter()
+            .map(|r| r.parse().unwrap())
+            .collect::<Vec<_>>();
+        let tree = DepTree::new(&blueprint, &requirements, &[]).unwrap();
+        assert_eq!(
+            tree.to_string(),
+            indoc! {"
+                cpython 3.11.1
+                trio 0.22.0
+                  attrs 22.2.0
+                  sniffio 1.3.0
+                  outcome 1.2.0
+                    attrs 22.2.0 (*)
+                black[jupyter] 23.1.0
+                  click 8.1.3
+                  ipython 8.10.0 (for [jupyter])
+                  tokenize-rt 5.0.0 (for [jupyter])"
+            }
+        );
+
+        let json = serde_json::to_value(&tree).unwrap();
+        let outcome = &json["requirements"][0]["dependencies"][2];
+        assert_eq!(outcome["requirement"], "outcome");
+        assert_eq!(outcome["dependencies"][0]["requirement"], "attrs >= 19");
+        assert_eq!(outcome["dependencies"][0]["repeat"], true);
+        assert!(json["requirements"][0].get("repeat").is_none());
+        assert_eq!(json["requirements"][1]["extras"][0], "jupyter");
+
+        let mut names = tree
+            .pinned_names()
+            .unwrap()
+            .iter()
+            .map(|n| n.normalized().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "attrs",
+                "black",
+                "click",
+                "ipython",
+                "outcome",
+                "sniffio",
+                "tokenize-rt",
+                "trio"
+            ]
+        );
+    }
+}
@@ -1,18 +1,28 @@
 use std::borrow::Cow;
+use std::cmp::Reverse;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
-use crate::kvstore::KVDirStore;
+use crate::blob_store::BlobStore;
+use crate::kvstore::{AnyStore, GcStats, KVDirStore, PathKey, StagedDir};
 use crate::package_db::{ArtifactInfo, PackageDB, WheelBuilder};
-use crate::resolve::{PinnedPackage, WheelResolveMetadata};
-use crate::trampolines::{FindPython, ScriptPlatform, TrampolineMaker};
+use crate::resolve::{LocalPin, PinnedPackage, WheelResolveMetadata};
+use crate::trampolines::{
+    is_windows_trampoline, unwrap_unix_trampoline, unwrap_windows_trampoline,
+    FindPython, ScriptPlatform, TrampolineMaker,
+};
+use crate::transcript::Transcript;
 use crate::tree::WriteTreeFS;
+use crate::util::tree_size;
 use crate::{platform_tags::PybiPlatform, prelude::*, resolve::Blueprint};
 
 // site.py as $stdlib/site.py
-// imports sitecustomize, which can use site.addsitedir to add directories that will be
-// processed for .pth files
-//   probably put it in $purelib?
+// processes .pth files in $purelib, and we put one there whose 'import' line runs
+// data-files/posy_bootstrap.py, which uses site.addsitedir to add the env's
+// directories (and process *their* .pth files). We used to do this from a
+// sitecustomize.py, but that collides with packages that ship their own.
 // can we disable user site stuff?
 // looks like user site stuff is processed before we have a chance to run. it's even
 // processed before regular global site-packages is, so even a .pth file won't be soon
@@ -31,11 +41,100 @@ use crate::{platform_tags::PybiPlatform, prelude::*, resolve::Blueprint};
 //     or I guess can use distlib's launchers, with either #!/usr/bin/env python.exe for
 //     find-on-path, or #!./python.exe for relative path
 
+// has to be importable, and unlikely to clash with anything real
+const BOOTSTRAP_MODULE: &str = "_posy_bootstrap";
+
 pub struct EnvForest {
     store: KVDirStore,
+    // If two packages ship scripts with the same name, also make the shadowed one
+    // available as <script>-<package>
+    pub rename_colliding_scripts: bool,
+    // Optional machine-wide store of unpacked wheels, shared between forests. If set,
+    // each wheel gets unpacked there once, and then forests get hardlinked copies, so
+    // deleting a forest still cleans up after itself but we don't waste time and disk
+    // unpacking numpy for the tenth time.
+    pub shared_wheels: Option<KVDirStore>,
+    // Optional machine-wide store of individual files, so that wheels we unpack share
+    // disk space with every identical file we've unpacked before, even from different
+    // wheels. See blob_store.rs.
+    pub blobs: Option<BlobStore>,
+    // if set, everything we add to the forest gets recorded here, so it can be undone
+    pub transcript: Option<Arc<Transcript>>,
+}
+
+/// Why select_pinned_binary picked what it did for one pin, so that "why did I get the
+/// musllinux wheel?" has an answer. Logged at debug level while installing, and what
+/// 'posy sync --selection-report' writes out.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Selection {
+    pub package: String,
+    pub version: Version,
+    /// None if nothing would do, e.g. because we're building from the sdist instead
+    pub chosen: Option<String>,
+    /// The platform we picked it for (its most preferred tag), and the chosen file's
+    /// best tag for that platform
+    pub platform: Option<String>,
+    pub matched_tag: Option<String>,
+    /// Every binary for this version, including the chosen one
+    pub candidates: Vec<Candidate>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Candidate {
+    pub filename: String,
+    /// As they appear in the filename, e.g. "py2.py3-none-any"
+    pub tags: String,
+    pub verdict: Verdict,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Verdict {
+    Chosen,
+    /// Would have worked, but we found something we liked better first
+    Outranked,
+    /// None of its tags work on any of the platforms we're installing for
+    WrongTags,
+    MissingHash,
+    /// Its hash isn't one of the pin's, so it's not the file that got locked
+    NotLocked,
+    Yanked,
 }
 
-fn pick_pinned_binary<'a, 'b, T: BinaryArtifact>(
+impl Display for Verdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Verdict::Chosen => "chosen",
+            Verdict::Outranked => "outranked",
+            Verdict::WrongTags => "wrong tags",
+            Verdict::MissingHash => "missing hash",
+            Verdict::NotLocked => "not in lock file",
+            Verdict::Yanked => "yanked",
+        })
+    }
+}
+
+impl Display for Selection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}: ", self.package, self.version)?;
+        match (&self.chosen, &self.platform, &self.matched_tag) {
+            (Some(chosen), Some(platform), Some(tag)) => {
+                write!(f, "picked {chosen} (tag {tag} for platform {platform})")?
+            }
+            _ => write!(f, "no usable binaries")?,
+        }
+        for candidate in &self.candidates {
+            if candidate.verdict != Verdict::Chosen {
+                write!(f, "\n  {}: {}", candidate.verdict, candidate.filename)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn pick_pinned_binary<'a, 'b, T: BinaryArtifact>(
     db: &'a PackageDB,
     platforms: &[&'b T::Platform],
     pin: &PinnedPackage,
@@ -43,45 +142,186 @@ fn pick_pinned_binary<'a, 'b, T: BinaryArtifact>(
 where
     T::Name: BinaryName,
 {
-    for platform in platforms {
-        let mut scored_candidates = db
-            .artifacts_for_version(&pin.name, &pin.version)?
+    match select_pinned_binary::<T>(db, platforms, pin)? {
+        (Some(found), _) => Ok(found),
+        (None, _) => Err(PosyError::NoCompatibleBinaries {
+            name: pin.name.as_given().to_owned(),
+            version: pin.version.to_owned(),
+        })?,
+    }
+}
+
+/// Like pick_pinned_binary, but also explains the choice. Returns None instead of
+/// failing if there's nothing we can use.
+pub fn select_pinned_binary<'a, 'b, T: BinaryArtifact>(
+    db: &'a PackageDB,
+    platforms: &[&'b T::Platform],
+    pin: &PinnedPackage,
+) -> Result<(Option<(&'a ArtifactInfo, &'b T::Platform)>, Selection)>
+where
+    T::Name: BinaryName,
+{
+    let artifacts = match &pin.url {
+        Some(url) => std::slice::from_ref(db.direct_artifact(url)?),
+        None => db.artifacts_for_version(&pin.name, &pin.version)?,
+    };
+    let binaries = artifacts
+        .iter()
+        .filter_map(|ai| Some((ai, ai.name.inner_as::<T::Name>()?)))
+        .collect::<Vec<_>>();
+    let mut verdicts = vec![None; binaries.len()];
+    let mut found = None;
+    'platforms: for platform in platforms {
+        let mut scored_candidates = binaries
             .iter()
-            .filter_map(|ai| {
-                if let Some(name) = ai.name.inner_as::<T::Name>() {
-                    if let Some(score) =
-                        platform.max_compatibility(name.all_tags().iter())
-                    {
-                        return Some((ai, score));
-                    }
-                }
-                None
+            .enumerate()
+            .filter_map(|(i, (ai, name))| {
+                Some((i, *ai, platform.binary_compatibility(*name)?))
             })
             .collect::<Vec<_>>();
-        scored_candidates.sort_unstable_by_key(|(_, score)| *score);
-        for (ai, _) in scored_candidates {
-            if ai.hash.is_none() {
-                warn!("best scoring artifact {} has no hash", ai.name);
+        // higher scores are better, so best first
+        scored_candidates.sort_unstable_by_key(|(_, _, score)| Reverse(*score));
+        for (i, ai, _) in scored_candidates {
+            // already turned down for an earlier platform, for reasons that don't
+            // depend on the platform
+            if verdicts[i].is_some() {
+                continue;
+            }
+            let verdict = if ai.hash.is_none() {
+                warn!(
+                    class = "unhashed-artifact",
+                    "best scoring artifact {} has no hash", ai.name
+                );
+                Verdict::MissingHash
             } else if !pin.hashes.contains(ai.hash.as_ref().unwrap()) {
-                warn!("best scoring artifact {} does not appear in lock file (maybe need to update pins?)", ai.name);
+                warn!(class = "unlocked-artifact", "best scoring artifact {} does not appear in lock file (maybe need to update pins?)", ai.name);
+                Verdict::NotLocked
+            } else if !db.yanked_policy.allows(ai, true) {
+                let why = ai.yanked.describe();
+                warn!(class = "yanked", "not using {}, which {why}", ai.name);
+                Verdict::Yanked
             } else {
-                return Ok((ai, platform));
+                if ai.yanked.yanked {
+                    let why = ai.yanked.describe();
+                    warn!(class = "yanked", "using {}, which {why}", ai.name);
+                }
+                Verdict::Chosen
+            };
+            verdicts[i] = Some(verdict);
+            if verdict == Verdict::Chosen {
+                found = Some((ai, *platform));
+                break 'platforms;
             }
         }
     }
-    Err(PosyError::NoCompatibleBinaries {
-        name: pin.name.as_given().to_owned(),
-        version: pin.version.to_owned(),
-    })?
+
+    let mut selection = Selection {
+        package: pin.name.as_given().to_owned(),
+        version: pin.version.clone(),
+        chosen: None,
+        platform: None,
+        matched_tag: None,
+        candidates: Vec::new(),
+    };
+    if let Some((ai, platform)) = found {
+        let name = ai.name.inner_as::<T::Name>().unwrap();
+        selection.chosen = Some(ai.name.to_string());
+        selection.platform = platform.tags().next().cloned();
+        selection.matched_tag = name
+            .all_tags()
+            .into_iter()
+            .filter(|tag| platform.compatibility(tag).is_some())
+            .max_by_key(|tag| platform.compatibility(tag));
+    }
+    for ((ai, name), verdict) in binaries.iter().zip(verdicts) {
+        let verdict = verdict.unwrap_or_else(|| {
+            if platforms
+                .iter()
+                .any(|p| p.binary_compatibility(*name).is_some())
+            {
+                Verdict::Outranked
+            } else {
+                Verdict::WrongTags
+            }
+        });
+        selection.candidates.push(Candidate {
+            filename: ai.name.to_string(),
+            tags: name.compressed_tags(),
+            verdict,
+        });
+    }
+    Ok((found, selection))
 }
 
 impl EnvForest {
+    pub fn gc(&self, max_age: std::time::Duration) -> Result<GcStats> {
+        let mut stats = self.store.gc(max_age)?;
+        // other forests might still be linked to stuff in here, but since they're
+        // hardlinks, deleting our copy doesn't hurt them
+        if let Some(shared) = &self.shared_wheels {
+            stats += shared.gc(max_age)?;
+        }
+        if let Some(blobs) = &self.blobs {
+            stats += blobs.gc(max_age)?;
+        }
+        Ok(stats)
+    }
+
+    pub fn stores(&self) -> Vec<(&'static str, AnyStore)> {
+        let mut stores = vec![("envs", AnyStore::Dir(&self.store))];
+        if let Some(shared) = &self.shared_wheels {
+            stores.push(("unpacked-wheels", AnyStore::Dir(shared)));
+        }
+        if let Some(blobs) = &self.blobs {
+            stores.push(("blobs", blobs.store()));
+        }
+        stores
+    }
+
     pub fn new(base: &Path) -> Result<EnvForest> {
         Ok(EnvForest {
             store: KVDirStore::new(base)?,
+            rename_colliding_scripts: false,
+            shared_wheels: None,
+            blobs: None,
+            transcript: None,
         })
     }
 
+    fn record_created(&self, path: &Path) {
+        if let Some(transcript) = &self.transcript {
+            transcript.created(path);
+        }
+    }
+
+    // Stages the entry for `key`, unless there's a good one already. One that `check`
+    // doesn't like -- e.g. because an older posy got killed halfway through writing
+    // it, or something deleted files out of it -- gets thrown out and redone.
+    fn stage_checked<K, C, F>(
+        &self,
+        key: &K,
+        check: C,
+        mut fill: F,
+    ) -> Result<StagedDir>
+    where
+        K: PathKey,
+        C: FnOnce(&Path) -> Result<()>,
+        F: FnMut(&Path) -> Result<()>,
+    {
+        let staged = self.store.stage(key, &mut fill)?;
+        if staged.is_new() {
+            return Ok(staged);
+        }
+        match check(staged.path()) {
+            Ok(()) => Ok(staged),
+            Err(err) => {
+                warn_damaged(staged.path(), &err);
+                self.store.lock(key)?.remove()?;
+                self.store.stage(key, fill)
+            }
+        }
+    }
+
     fn munge_unpacked_pybi(path: &Path, metadata: &PybiCoreMetadata) -> Result<()> {
         let stdlib = path.join(metadata.path("stdlib")?.to_native());
         fs::write(
@@ -89,9 +329,15 @@ impl EnvForest {
             include_bytes!("data-files/EXTERNALLY-MANAGED"),
         )?;
         let purelib = path.join(metadata.path("purelib")?.to_native());
+        // .pth files run in alphabetical order, so the leading _ gets us in ahead of
+        // most others
         fs::write(
-            purelib.join("sitecustomize.py"),
-            include_bytes!("data-files/sitecustomize.py"),
+            purelib.join(format!("{BOOTSTRAP_MODULE}.py")),
+            include_bytes!("data-files/posy_bootstrap.py"),
+        )?;
+        fs::write(
+            purelib.join(format!("{BOOTSTRAP_MODULE}.pth")),
+            format!("import {BOOTSTRAP_MODULE}\n"),
         )?;
         let site_py = fs::read(stdlib.join("site.py"))?;
         static USER_SITE_RE: Lazy<regex::bytes::Regex> = Lazy::new(|| {
@@ -115,21 +361,65 @@ impl EnvForest {
         pybi_platforms: &[&PybiPlatform],
         build_stack: &[&PackageName],
     ) -> Result<Env> {
+        let _timer = crate::stats::time_phase("install");
+        let warnings = crate::output::warnings_mark();
+        let mut selections = Vec::new();
+        let (found, selection) =
+            select_pinned_binary::<Pybi>(db, pybi_platforms, &blueprint.pybi)?;
+        debug!("{selection}");
+        selections.push(selection);
         let (pybi_ai, pybi_platform) =
-            pick_pinned_binary::<Pybi>(db, pybi_platforms, &blueprint.pybi)?;
+            found.ok_or_else(|| PosyError::NoCompatibleBinaries {
+                name: blueprint.pybi.name.as_given().to_owned(),
+                version: blueprint.pybi.version.to_owned(),
+            })?;
         let pybi_hash = pybi_ai.require_hash()?;
-        let pybi_root = self.store.get_or_set(&pybi_hash, |path| {
-            let pybi = db.get_artifact::<Pybi>(pybi_ai)?;
-            context!("Unpacking {}", pybi_ai.name);
-            pybi.unpack(&mut WriteTreeFS::new(path))?;
-            let (_, pybi_metadata) = pybi.metadata()?;
-            EnvForest::munge_unpacked_pybi(path, &pybi_metadata)?;
-            Ok(())
-        })?;
-        let pybi_metadata: PybiCoreMetadata =
-            fs::read(pybi_root.join("pybi-info").join("METADATA"))?
-                .as_slice()
-                .try_into()?;
+        // Nothing new goes into the store until the pybi and all the wheels are
+        // unpacked and checked over, so if we fail partway, we don't leave half an env
+        // behind. (See KVDirStore::stage.)
+        let staged_pybi = self.stage_checked(
+            &pybi_hash,
+            |path| unpacked_pybi_metadata(path).map(|_| ()),
+            |path| {
+                let pybi = db.get_artifact::<Pybi>(pybi_ai)?;
+                context!("Unpacking {}", pybi_ai.name);
+                pybi.unpack(&mut WriteTreeFS::new(path))?;
+                let (_, pybi_metadata) = pybi.metadata()?;
+                EnvForest::munge_unpacked_pybi(path, &pybi_metadata)?;
+                Ok(())
+            },
+        )?;
+        let pybi_metadata = unpacked_pybi_metadata(staged_pybi.path())?;
+        let foreign = foreign_dist_infos(staged_pybi.path(), &pybi_metadata)?;
+        if !foreign.is_empty() {
+            warn!(
+                class = "foreign-package",
+                "found packages in {} that posy didn't install: {}\n\
+                 These can shadow or break the packages in your environment. To remove \
+                 them, run 'posy env clean-foreign'.",
+                staged_pybi.dest().display(),
+                foreign
+                    .iter()
+                    .map(|(dist_info, _)| dist_info.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        // With a universal2 pybi, which arch we're installing for is down to which
+        // platform we picked it for, and so is platform_machine. The blueprint's markers
+        // were evaluated for whatever it was resolved for, so make sure that's us.
+        let env_marker_vars =
+            pybi_platform.environment_marker_variables(&pybi_metadata);
+        for (expr, &value) in &blueprint.marker_expressions {
+            if expr.0.eval(&env_marker_vars)? != value {
+                bail!(
+                    "this lock was resolved for a different platform than {} (there, \
+                     '{expr}' was {value}); re-lock with --platform {}",
+                    pybi_platform.core_tag(),
+                    pybi_platform.core_tag(),
+                );
+            }
+        }
         let wheel_platform = pybi_platform.wheel_platform(&pybi_metadata)?;
         let pybi_platform_slice = [pybi_platform];
         let wheel_builder = WheelBuilder::new(
@@ -140,7 +430,8 @@ impl EnvForest {
             build_stack,
         )?;
         let trampoline_maker =
-            TrampolineMaker::new(FindPython::FromEnv, ScriptPlatform::Both);
+            TrampolineMaker::new(FindPython::FromEnv, ScriptPlatform::Both)
+                .for_platform(&wheel_platform);
 
         let paths: HashMap<String, NicePathBuf> = HashMap::from([
             ("scripts".into(), "bin".try_into().unwrap()),
@@ -149,120 +440,144 @@ impl EnvForest {
             ("data".into(), ".".try_into().unwrap()),
         ]);
 
+        let mut staged_wheels = Vec::new();
         let mut wheel_roots = Vec::new();
+        let mut installed = Vec::new();
 
-        for (pin, expected_metadata) in &blueprint.wheels {
+        // First pass: pick an artifact for each pin. Anything that has to be built
+        // from an sdist gets handled right here, because building needs the
+        // PackageDB, which can't leave this thread. Binary wheels get queued up to be
+        // downloaded and unpacked in parallel below. (Running two get_or_sets on the
+        // same key at once is fine; the store's locking serializes them.)
+        let mut picked = Vec::new();
+        let mut to_unpack = Vec::new();
+        for (i, (pin, _)) in blueprint.wheels.iter().enumerate() {
+            db.cancellation_token().check()?;
             context!("installing {} {}", pin.name.as_given(), pin.version);
-            let (ai, wheel_root) =
-                match pick_pinned_binary::<Wheel>(db, &[&wheel_platform], pin) {
-                    Ok((wheel_ai, _)) => {
-                        // we're using a binary wheel
-                        context!("using binary wheel from {}", wheel_ai.url);
-                        let wheel_hash = wheel_ai.require_hash()?;
-                        let wheel_root =
-                            self.store.get_or_set(&wheel_hash, |path| {
-                                let wheel = {
-                                    context!("Fetching {}", wheel_ai.url);
-                                    db.get_artifact::<Wheel>(wheel_ai)?
-                                };
-                                wheel.unpack(
-                                    &paths,
-                                    &trampoline_maker,
-                                    WriteTreeFS::new(path),
-                                )?;
-                                Ok(())
-                            })?;
-                        (wheel_ai, wheel_root)
-                    }
-                    Err(err) => {
-                        match err.downcast_ref::<PosyError>() {
-                            Some(PosyError::NoCompatibleBinaries { .. }) => (),
-                            _ => return Err(err),
-                        };
-                        // couldn't find a compatible wheel; see if we have an sdist
-                        if let Some(sdist_ai) = db
-                            .artifacts_for_version(&pin.name, &pin.version)?
-                            .iter()
-                            .find(|ai| ai.is::<Sdist>())
-                        {
-                            context!("using sdist from {}", sdist_ai.url);
-                            let sdist_hash = sdist_ai.require_hash()?;
-                            let handle = self.store.lock(&sdist_hash)?;
-                            fs::create_dir_all(&handle)?;
-                            // first check if we already have any unpacked wheels
-                            // that we can use
-                            let mut candidates = Vec::new();
-                            for entry in fs::read_dir(&handle)? {
-                                let entry = entry?;
-                                let name = match entry.file_name().into_string() {
-                                    Ok(name) => name,
-                                    Err(_) => continue,
-                                };
-                                if !name.ends_with(".whl") {
-                                    continue;
-                                }
-                                let wheel_name: WheelName = name.as_str().try_into()?;
-                                if let Some(score) = wheel_platform
-                                    .max_compatibility(wheel_name.all_tags())
-                                {
-                                    candidates.push((score, name));
+            let (found, selection) =
+                select_pinned_binary::<Wheel>(db, &[&wheel_platform], pin)?;
+            debug!("{selection}");
+            selections.push(selection);
+            let found = match found {
+                Some((wheel_ai, _)) => {
+                    to_unpack.push((i, pin, wheel_ai));
+                    None
+                }
+                None => {
+                    // couldn't find a compatible wheel; see if we have an sdist
+                    let artifacts =
+                        db.artifacts_for_version(&pin.name, &pin.version)?;
+                    if let Some(sdist_ai) = pin.pinned_sdist(artifacts) {
+                        context!("using sdist from {}", sdist_ai.url);
+                        let sdist_hash = sdist_ai.require_hash()?;
+                        let handle = self.store.lock(&sdist_hash)?;
+                        fs::create_dir_all(&handle)?;
+                        // first check if we already have any unpacked wheels
+                        // that we can use
+                        let cached =
+                            match best_unpacked_wheel(&handle, &wheel_platform)? {
+                                Some(wheel_root) => {
+                                    discard_if_damaged(wheel_root, pin)?
                                 }
-                            }
-                            if let Some((_, name)) =
-                                candidates.iter().max_by_key(|(score, _)| score)
-                            {
-                                (sdist_ai, handle.join(name))
-                            } else {
-                                // couldn't find one already installed... try to
-                                // build one and install it
-                                // unwrap is ok b/c we know we're passing an sdist
-                                // ai here
-                                let local_wheel = db
-                                    .get_locally_built_binary::<Wheel>(
-                                        sdist_ai,
-                                        &wheel_builder,
-                                        &wheel_platform,
-                                    )
-                                    .unwrap()?;
-                                let tmp = handle.tempdir()?;
-                                local_wheel.unpack(
-                                    &paths,
-                                    &trampoline_maker,
-                                    WriteTreeFS::new(&tmp),
-                                )?;
-                                let wheel_root =
-                                    handle.join(local_wheel.name().to_string());
-                                fs::rename(tmp.into_path(), &wheel_root)?;
-                                (sdist_ai, wheel_root)
-                            }
+                                None => None,
+                            };
+                        if let Some(wheel_root) = cached {
+                            Some((
+                                sdist_ai,
+                                StagedDir::existing(wheel_root),
+                                InstallSource::Cached,
+                            ))
                         } else {
-                            bail!("no compatible wheel or sdist found");
+                            // couldn't find one already installed... try to
+                            // build one and install it
+                            // unwrap is ok b/c we know we're passing an sdist
+                            // ai here
+                            let local_wheel = db
+                                .get_locally_built_binary::<Wheel>(
+                                    sdist_ai,
+                                    &wheel_builder,
+                                    &wheel_platform,
+                                )
+                                .unwrap()?;
+                            let tmp = handle.tempdir()?;
+                            local_wheel.unpack(
+                                &paths,
+                                &trampoline_maker,
+                                WriteTreeFS::new(&tmp).with_blobs(self.blobs.as_ref()),
+                            )?;
+                            let staged = handle.stage(
+                                tmp,
+                                handle.join(local_wheel.name().to_string()),
+                            );
+                            Some((sdist_ai, staged, InstallSource::Built))
                         }
+                    } else {
+                        bail!("no compatible wheel or sdist found");
                     }
+                }
+            };
+            picked.push(found);
+        }
+
+        let fetcher = db.fetcher();
+        let unpacked = parallel_map(&to_unpack, |(i, pin, wheel_ai)| -> Result<_> {
+            context!("installing {} {}", pin.name.as_given(), pin.version);
+            context!("using binary wheel from {}", wheel_ai.url);
+            let wheel_hash = wheel_ai.require_hash()?;
+            let check = |path: &Path| -> Result<()> {
+                unpacked_metadata(path, &pin.name, &pin.version)?;
+                Ok(())
+            };
+            let mut source = InstallSource::Cached;
+            let staged = self.stage_checked(&wheel_hash, check, |path| {
+                let mut unpack = |path: &Path| -> Result<()> {
+                    source = InstallSource::Downloaded;
+                    let wheel = {
+                        context!("Fetching {}", wheel_ai.url);
+                        fetcher.get_artifact::<Wheel>(wheel_ai)?
+                    };
+                    wheel.unpack(
+                        &paths,
+                        &trampoline_maker,
+                        WriteTreeFS::new(path).with_blobs(self.blobs.as_ref()),
+                    )?;
+                    Ok(())
                 };
+                match &self.shared_wheels {
+                    Some(shared) => {
+                        let mut shared_root =
+                            shared.get_or_set(&wheel_hash, &mut unpack)?;
+                        if let Err(err) = check(&shared_root) {
+                            warn_damaged(&shared_root, &err);
+                            shared.lock(&wheel_hash)?.remove()?;
+                            shared_root =
+                                shared.get_or_set(&wheel_hash, &mut unpack)?;
+                        }
+                        // no record_created, since other projects' envs are using the
+                        // shared store too; rolling back only undoes our own links
+                        link_tree(&shared_root, path, false)
+                    }
+                    None => unpack(path),
+                }
+            })?;
+            Ok((*i, (*wheel_ai, staged, source)))
+        });
+        for result in unpacked {
+            let (i, found) = result?;
+            picked[i] = Some(found);
+        }
 
-            // OK, we have an installed wheel. Find its metadata so we can confirm it's
+        for ((pin, expected_metadata), found) in blueprint.wheels.iter().zip(picked) {
+            context!("installing {} {}", pin.name.as_given(), pin.version);
+            // unwrap is safe b/c every pin got filled in by one of the passes above
+            let (ai, staged, source) = found.unwrap();
+
+            // OK, we have an unpacked wheel. Find its metadata so we can confirm it's
             // consistent with what the blueprint was expecting.
-            let mut top_levels = Vec::new();
-            let lib = wheel_root.join("lib");
-            for entry in fs::read_dir(&lib)? {
-                let entry = entry?;
-                if let Ok(name) = entry.file_name().into_string() {
-                    top_levels.push(name);
-                }
-            }
-            let dist_info = Wheel::find_special_wheel_dir(
-                top_levels,
-                &pin.name,
-                &pin.version,
-                ".dist-info",
-            )?
-            .ok_or(eyre!(".dist-info/ missing"))?;
-            let found_metadata: WheelCoreMetadata =
-                fs::read(lib.join(&dist_info).join("METADATA"))?
-                    .as_slice()
-                    .try_into()?;
-            let found_metadata = WheelResolveMetadata::from(ai, &found_metadata);
+            let found_metadata = WheelResolveMetadata::from(
+                ai,
+                &unpacked_metadata(staged.path(), &pin.name, &pin.version)?,
+            );
 
             if found_metadata.inner != expected_metadata.inner {
                 bail!(
@@ -287,7 +602,97 @@ impl EnvForest {
                 );
             }
 
-            wheel_roots.push(wheel_root);
+            staged_wheels.push((pin.name.clone(), staged));
+            crate::events::emit(crate::events::Event::Installing {
+                package: pin.name.as_given().to_owned(),
+                version: pin.version.to_string(),
+                artifact: ai.name.to_string(),
+                source: source.to_string(),
+            });
+            installed.push(Installed {
+                name: pin.name.clone(),
+                version: pin.version.clone(),
+                artifact: ai.name.to_string(),
+                source,
+            });
+        }
+
+        // Everything checks out, so now it can all go in the store.
+        let (pybi_root, created) = staged_pybi.commit()?;
+        if created {
+            self.record_created(&pybi_root);
+        }
+        for (name, staged) in staged_wheels {
+            let (wheel_root, created) = staged.commit()?;
+            if created {
+                self.record_created(&wheel_root);
+            }
+            wheel_roots.push((name, wheel_root));
+        }
+
+        // XX TODO: local trees aren't staged along with everything else, since
+        // local_wheel_root replaces their entries in place
+
+        for (pin, expected_metadata) in &blueprint.local {
+            context!(
+                "installing {} from {}",
+                pin.name.as_given(),
+                pin.tree.path.display()
+            );
+            let (wheel_root, source) = self.local_wheel_root(
+                &wheel_builder,
+                &wheel_platform,
+                pin,
+                expected_metadata,
+                &paths,
+                &trampoline_maker,
+            )?;
+            wheel_roots.push((pin.name.clone(), wheel_root));
+            installed.push(Installed {
+                name: pin.name.clone(),
+                version: pin.version.clone(),
+                artifact: format!(
+                    "{}{}",
+                    if pin.tree.editable { "-e " } else { "" },
+                    pin.tree.path.display()
+                ),
+                source,
+            });
+        }
+
+        // Sort so that which package wins a conflict doesn't depend on what order the
+        // resolver happened to produce things in.
+        wheel_roots.sort_by(|(a, _), (b, _)| a.normalized().cmp(b.normalized()));
+        let conflicts = if blueprint.local.is_empty() {
+            self.cached_file_conflicts(&wheel_roots)?
+        } else {
+            // local trees get rebuilt in place, so their roots can change without
+            // their paths changing
+            find_file_conflicts(&wheel_roots)?
+        };
+        let (script_conflicts, other_conflicts): (Vec<_>, Vec<_>) = conflicts
+            .into_iter()
+            .partition(|conflict| conflict.path.starts_with("bin"));
+        for conflict in &other_conflicts {
+            warn!(class = "file-conflict", "{conflict}");
+        }
+        let renamed_scripts =
+            if self.rename_colliding_scripts && !script_conflicts.is_empty() {
+                Some(self.renamed_scripts(&wheel_roots, &script_conflicts)?)
+            } else {
+                None
+            };
+        for conflict in &script_conflicts {
+            if renamed_scripts.is_some() {
+                warn!(
+                    class = "file-conflict",
+                    "{conflict} (the one from {} is available as {})",
+                    conflict.loser.as_given(),
+                    renamed_script_name(&conflict.path, &conflict.loser),
+                );
+            } else {
+                warn!(class = "file-conflict", "{conflict}");
+            }
         }
 
         let pybi_bin = pybi_root.join(pybi_metadata.path("scripts")?.to_native());
@@ -299,32 +704,899 @@ impl EnvForest {
         let python = pybi_bin.join(python_basename);
         let pythonw = pybi_bin.join(pythonw_basename);
 
+        let mut store_paths = vec![pybi_root.clone()];
+        store_paths.extend(wheel_roots.iter().map(|(_, root)| root.clone()));
+        // unwrap is safe b/c renamed_scripts returns the bin/ dir inside its entry
+        store_paths.extend(
+            renamed_scripts
+                .iter()
+                .map(|bin| bin.parent().unwrap().into()),
+        );
+
         let mut bin_dirs = Vec::<PathBuf>::new();
         bin_dirs.push(pybi_bin);
-        bin_dirs.extend(wheel_roots.iter().map(|root| root.join("bin")));
+        bin_dirs.extend(wheel_roots.iter().map(|(_, root)| root.join("bin")));
+        bin_dirs.extend(renamed_scripts);
 
-        let lib_dirs = wheel_roots.iter().map(|root| root.join("lib")).collect();
+        let lib_dirs = wheel_roots
+            .iter()
+            .map(|(_, root)| root.join("lib"))
+            .collect();
 
         Ok(Env {
             platform_core_tag: pybi_platform.core_tag().into(),
+            pybi_root,
             wheel_platform,
             python,
             pythonw,
             bin_dirs,
             lib_dirs,
+            installed,
+            selections,
+            store_paths,
+            python_flags: blueprint.python_flags.clone(),
+            rosetta: pybi_platform.needs_rosetta(),
+            warnings: crate::output::warnings_since(warnings),
         })
     }
 }
 
+/// One way that the env for a blueprint, as it exists in the EnvForest, doesn't match
+/// the blueprint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "problem", rename_all = "kebab-case")]
+pub enum EnvProblem {
+    /// Never installed, or GC'ed since
+    #[serde(rename_all = "kebab-case")]
+    Missing { name: PackageName, version: Version },
+    #[serde(rename_all = "kebab-case")]
+    WrongPybi { expected: String, found: String },
+    #[serde(rename_all = "kebab-case")]
+    MetadataMismatch {
+        name: PackageName,
+        version: Version,
+        expected_from: String,
+        found_from: String,
+    },
+    /// A package that isn't in the blueprint, e.g. because someone ran 'pip install
+    /// --break-system-packages' inside the env
+    #[serde(rename_all = "kebab-case")]
+    ExtraPackage {
+        dist_info: String,
+        location: PathBuf,
+    },
+}
+
+impl Display for EnvProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EnvProblem::Missing { name, version } => {
+                write!(f, "{} {} is not installed", name.as_given(), version)
+            }
+            EnvProblem::WrongPybi { expected, found } => {
+                write!(f, "expected python {expected}, but found {found}")
+            }
+            EnvProblem::MetadataMismatch {
+                name,
+                version,
+                expected_from,
+                found_from,
+            } => write!(
+                f,
+                "{} {}: installed metadata (from {found_from}) doesn't match the \
+                 metadata used when resolving (from {expected_from})",
+                name.as_given(),
+                version,
+            ),
+            EnvProblem::ExtraPackage {
+                dist_info,
+                location,
+            } => write!(
+                f,
+                "unexpected package {dist_info} in {}",
+                location.display()
+            ),
+        }
+    }
+}
+
+/// What EnvForest::verify_env found. Serializes to JSON for tools that want to watch
+/// for drift.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct EnvReport {
+    pub ok: bool,
+    pub pybi: String,
+    pub checked_wheels: usize,
+    pub problems: Vec<EnvProblem>,
+}
+
+impl EnvForest {
+    fn existing(&self, hash: &ArtifactHash) -> Option<PathBuf> {
+        let lock = self.store.lock_if_exists(hash)?;
+        if lock.exists() {
+            Some(lock.to_path_buf())
+        } else {
+            None
+        }
+    }
+
+    /// Checks the env that get_env would give for `blueprint`, without downloading,
+    /// building, or fixing anything. Has to pick artifacts exactly the way get_env
+    /// does, or we'd be checking the wrong files.
+    pub fn verify_env(
+        &self,
+        db: &PackageDB,
+        blueprint: &Blueprint,
+        pybi_platforms: &[&PybiPlatform],
+    ) -> Result<EnvReport> {
+        let mut report = EnvReport {
+            ok: false,
+            pybi: format!(
+                "{} {}",
+                blueprint.pybi.name.as_given(),
+                blueprint.pybi.version
+            ),
+            checked_wheels: 0,
+            problems: Vec::new(),
+        };
+
+        let (pybi_ai, pybi_platform) =
+            pick_pinned_binary::<Pybi>(db, pybi_platforms, &blueprint.pybi)?;
+        let pybi_root = match self.existing(pybi_ai.require_hash()?) {
+            Some(root) => root,
+            None => {
+                // without the pybi, we don't even know which wheels it would use
+                report.problems.push(EnvProblem::Missing {
+                    name: blueprint.pybi.name.clone(),
+                    version: blueprint.pybi.version.clone(),
+                });
+                return Ok(report);
+            }
+        };
+        let pybi_metadata: PybiCoreMetadata =
+            fs::read(pybi_root.join("pybi-info").join("METADATA"))?
+                .as_slice()
+                .try_into()?;
+        if pybi_metadata.name != blueprint.pybi.name
+            || pybi_metadata.version != blueprint.pybi.version
+        {
+            report.problems.push(EnvProblem::WrongPybi {
+                expected: report.pybi.clone(),
+                found: format!(
+                    "{} {}",
+                    pybi_metadata.name.as_given(),
+                    pybi_metadata.version
+                ),
+            });
+        }
+        for (dist_info, location) in foreign_dist_infos(&pybi_root, &pybi_metadata)? {
+            report.problems.push(EnvProblem::ExtraPackage {
+                dist_info,
+                location,
+            });
+        }
+
+        let wheel_platform = pybi_platform.wheel_platform(&pybi_metadata)?;
+        for (pin, expected_metadata) in &blueprint.wheels {
+            context!("checking {} {}", pin.name.as_given(), pin.version);
+            let found = match pick_pinned_binary::<Wheel>(db, &[&wheel_platform], pin) {
+                Ok((wheel_ai, _)) => self
+                    .existing(wheel_ai.require_hash()?)
+                    .map(|root| (wheel_ai, root)),
+                Err(err) => {
+                    match err.downcast_ref::<PosyError>() {
+                        Some(PosyError::NoCompatibleBinaries { .. }) => (),
+                        _ => return Err(err),
+                    };
+                    let artifacts =
+                        db.artifacts_for_version(&pin.name, &pin.version)?;
+                    let sdist_ai = pin
+                        .pinned_sdist(artifacts)
+                        .ok_or_else(|| eyre!("no compatible wheel or sdist found"))?;
+                    match self.existing(sdist_ai.require_hash()?) {
+                        Some(dir) => best_unpacked_wheel(&dir, &wheel_platform)?
+                            .map(|root| (sdist_ai, root)),
+                        None => None,
+                    }
+                }
+            };
+            let (ai, wheel_root) = match found {
+                Some(found) => found,
+                None => {
+                    report.problems.push(EnvProblem::Missing {
+                        name: pin.name.clone(),
+                        version: pin.version.clone(),
+                    });
+                    continue;
+                }
+            };
+            report.checked_wheels += 1;
+
+            let lib = wheel_root.join("lib");
+            let mut own_dist_info = None;
+            for dist_info in dist_info_dirs(&lib)? {
+                let ours = matches!(
+                    Wheel::find_special_wheel_dir(
+                        [&dist_info],
+                        &pin.name,
+                        &pin.version,
+                        ".dist-info",
+                    ),
+                    Ok(Some(_))
+                );
+                if ours {
+                    own_dist_info = Some(dist_info);
+                } else {
+                    report.problems.push(EnvProblem::ExtraPackage {
+                        dist_info,
+                        location: lib.clone(),
+                    });
+                }
+            }
+            let own_dist_info = match own_dist_info {
+                Some(d) => d,
+                None => {
+                    report.problems.push(EnvProblem::Missing {
+                        name: pin.name.clone(),
+                        version: pin.version.clone(),
+                    });
+                    continue;
+                }
+            };
+            let found_metadata: WheelCoreMetadata =
+                fs::read(lib.join(&own_dist_info).join("METADATA"))?
+                    .as_slice()
+                    .try_into()?;
+            let found_metadata = WheelResolveMetadata::from(ai, &found_metadata);
+            if found_metadata.inner != expected_metadata.inner {
+                report.problems.push(EnvProblem::MetadataMismatch {
+                    name: pin.name.clone(),
+                    version: pin.version.clone(),
+                    expected_from: expected_metadata.provenance.clone(),
+                    found_from: found_metadata.provenance,
+                });
+            }
+        }
+
+        // XX TODO: check the packages in blueprint.local too
+        report.ok = report.problems.is_empty();
+        Ok(report)
+    }
+
+    /// If someone has installed extra packages into `blueprint`'s pybi behind our
+    /// back, puts the pybi's store entry back the way it was by unpacking a fresh copy.
+    /// Returns the packages that got removed.
+    ///
+    /// XX TODO: pip can also mess with the wheels we installed (e.g. 'pip install -U'
+    /// uninstalls them from the forest); verify_env catches that but we don't fix it
+    pub fn clean_foreign(
+        &self,
+        db: &PackageDB,
+        blueprint: &Blueprint,
+        pybi_platforms: &[&PybiPlatform],
+    ) -> Result<Vec<String>> {
+        let (pybi_ai, _) =
+            pick_pinned_binary::<Pybi>(db, pybi_platforms, &blueprint.pybi)?;
+        let handle = self.store.lock(pybi_ai.require_hash()?)?;
+        if !handle.exists() {
+            return Ok(Vec::new());
+        }
+        let pybi_metadata: PybiCoreMetadata =
+            fs::read(handle.join("pybi-info").join("METADATA"))?
+                .as_slice()
+                .try_into()?;
+        let foreign = foreign_dist_infos(&handle, &pybi_metadata)?;
+        if foreign.is_empty() {
+            return Ok(Vec::new());
+        }
+        // Deleting the .dist-info directories isn't enough: pip could have overwritten
+        // or deleted files that belong to the pybi, too. Starting over is the only way
+        // to be sure. (Usually the pybi is still in our download cache.)
+        context!("Restoring {}", pybi_ai.name);
+        let pybi = db.get_artifact::<Pybi>(pybi_ai)?;
+        let tmp = handle.tempdir()?;
+        pybi.unpack(&mut WriteTreeFS::new(tmp.path()))?;
+        let (_, fresh_metadata) = pybi.metadata()?;
+        EnvForest::munge_unpacked_pybi(tmp.path(), &fresh_metadata)?;
+        // XX TODO: like local_wheel_root, this pulls the rug out from under anything
+        // currently running from this pybi
+        fs::remove_dir_all(&*handle)?;
+        fs::rename(tmp.into_path(), &*handle)?;
+        Ok(foreign
+            .into_iter()
+            .map(|(dist_info, _)| dist_info)
+            .collect())
+    }
+}
+
+impl EnvForest {
+    // Packages from local trees get unpacked into the tree's entry in the forest. An
+    // editable install only points back at the tree, so we can keep re-using it until
+    // the package's metadata changes (e.g. the user adds a dependency). Anything else
+    // has to be rebuilt every time, since we can't tell whether the tree changed.
+    fn local_wheel_root(
+        &self,
+        wheel_builder: &WheelBuilder,
+        wheel_platform: &WheelPlatform,
+        pin: &LocalPin,
+        expected_metadata: &WheelResolveMetadata,
+        paths: &HashMap<String, NicePathBuf>,
+        trampoline_maker: &TrampolineMaker,
+    ) -> Result<(PathBuf, InstallSource)> {
+        let handle = self.store.lock(&pin.tree.store_key().as_slice())?;
+        fs::create_dir_all(&handle)?;
+        let wheel_root = handle.join("unpacked");
+        let matches_expected = |wheel_root: &Path| -> Result<bool> {
+            let found = unpacked_metadata(wheel_root, &pin.name, &pin.version)?;
+            Ok(WheelResolveMetadata::from_local(&pin.tree, &found).inner
+                == expected_metadata.inner)
+        };
+
+        if pin.tree.editable && wheel_root.exists() {
+            match matches_expected(&wheel_root) {
+                Ok(true) => return Ok((wheel_root, InstallSource::Cached)),
+                Ok(false) => (),
+                Err(err) => debug!("not re-using {}: {err}", wheel_root.display()),
+            }
+        }
+
+        let wheel =
+            wheel_builder.local_tree_wheel(&pin.name, &pin.tree, wheel_platform)?;
+        let tmp = handle.tempdir()?;
+        wheel.unpack(paths, trampoline_maker, WriteTreeFS::new(&tmp))?;
+        if !matches_expected(tmp.path())? {
+            bail!(
+                "{}'s metadata has changed since it was resolved; re-resolve (e.g. with \
+                 'posy lock') to pick up the changes",
+                pin.tree.path.display()
+            );
+        }
+        // XX TODO: if another env is running out of the old copy, this pulls the rug
+        // out from under it
+        if wheel_root.exists() {
+            fs::remove_dir_all(&wheel_root)?;
+        }
+        fs::rename(tmp.into_path(), &wheel_root)?;
+        self.record_created(&wheel_root);
+        Ok((wheel_root, InstallSource::Built))
+    }
+}
+
+// Reads the core metadata out of an unpacked wheel.
+fn unpacked_metadata(
+    wheel_root: &Path,
+    name: &PackageName,
+    version: &Version,
+) -> Result<WheelCoreMetadata> {
+    let mut top_levels = Vec::new();
+    let lib = wheel_root.join("lib");
+    for entry in fs::read_dir(&lib)? {
+        if let Ok(entry_name) = entry?.file_name().into_string() {
+            top_levels.push(entry_name);
+        }
+    }
+    let dist_info =
+        Wheel::find_special_wheel_dir(top_levels, name, version, ".dist-info")?
+            .ok_or(eyre!(".dist-info/ missing"))?;
+    fs::read(lib.join(&dist_info).join("METADATA"))?
+        .as_slice()
+        .try_into()
+}
+
+// Reads the metadata of a pybi we unpacked, after checking that we got as far as
+// setting it up.
+fn unpacked_pybi_metadata(pybi_root: &Path) -> Result<PybiCoreMetadata> {
+    let metadata: PybiCoreMetadata =
+        fs::read(pybi_root.join("pybi-info").join("METADATA"))?
+            .as_slice()
+            .try_into()?;
+    let purelib = pybi_root.join(metadata.path("purelib")?.to_native());
+    if !purelib.join(format!("{BOOTSTRAP_MODULE}.pth")).exists() {
+        bail!("{BOOTSTRAP_MODULE}.pth is missing");
+    }
+    Ok(metadata)
+}
+
+fn warn_damaged(path: &Path, err: &eyre::Report) {
+    warn!(
+        class = "damaged-install",
+        "{} looks incomplete or damaged ({err:#}); reinstalling it",
+        path.display()
+    );
+}
+
+// Packages in the pybi's site-packages that didn't come with the pybi, e.g. from 'pip
+// install --break-system-packages'. The pybi can legitimately ship some packages
+// (e.g. pip), but those are all listed in its RECORD. Returns (dist-info name, the
+// directory it's in).
+fn foreign_dist_infos(
+    pybi_root: &Path,
+    pybi_metadata: &PybiCoreMetadata,
+) -> Result<Vec<(String, PathBuf)>> {
+    let pybi_record =
+        String::from_utf8(fs::read(pybi_root.join("pybi-info").join("RECORD"))?)?;
+    let mut found = Vec::new();
+    for category in ["purelib", "platlib"] {
+        let lib = pybi_metadata.path(category)?;
+        let location = pybi_root.join(lib.to_native());
+        for dist_info in dist_info_dirs(&location)? {
+            let prefix = format!("{lib}/{dist_info}/");
+            // purelib and platlib are often the same directory
+            let seen = found.iter().any(|(d, l)| d == &dist_info && l == &location);
+            if !seen && !pybi_record.lines().any(|line| line.starts_with(&prefix)) {
+                found.push((dist_info, location.clone()));
+            }
+        }
+    }
+    Ok(found)
+}
+
+// The names of all the *.dist-info directories directly inside `dir` (if it exists),
+// sorted.
+fn dist_info_dirs(dir: &Path) -> Result<Vec<String>> {
+    let mut found = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(found),
+        Err(err) => return Err(err.into()),
+    };
+    for entry in entries {
+        if let Ok(name) = entry?.file_name().into_string() {
+            if name.ends_with(".dist-info") {
+                found.push(name);
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+// Wheels we built from an sdist get unpacked into the sdist's entry in the forest, one
+// subdirectory per wheel (named after the wheel). Finds the best one for our platform.
+fn best_unpacked_wheel(
+    sdist_dir: &Path,
+    wheel_platform: &WheelPlatform,
+) -> Result<Option<PathBuf>> {
+    let mut candidates = Vec::new();
+    for entry in fs::read_dir(sdist_dir)? {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if !name.ends_with(".whl") {
+            continue;
+        }
+        let wheel_name: WheelName = name.as_str().try_into()?;
+        if let Some(score) = wheel_platform.binary_compatibility(&wheel_name) {
+            candidates.push((score, name));
+        }
+    }
+    Ok(candidates
+        .into_iter()
+        .max_by_key(|(score, _)| *score)
+        .map(|(_, name)| sdist_dir.join(name)))
+}
+
+// For a wheel we built and unpacked earlier: if it's damaged, removes it, so we'll
+// build a new one.
+fn discard_if_damaged(
+    wheel_root: PathBuf,
+    pin: &PinnedPackage,
+) -> Result<Option<PathBuf>> {
+    match unpacked_metadata(&wheel_root, &pin.name, &pin.version) {
+        Ok(_) => Ok(Some(wheel_root)),
+        Err(err) => {
+            warn_damaged(&wheel_root, &err);
+            fs::remove_dir_all(&wheel_root)?;
+            Ok(None)
+        }
+    }
+}
+
+/// How get_env got hold of a package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallSource {
+    /// Already unpacked in the EnvForest from some earlier install
+    Cached,
+    /// Fetched a wheel (possibly from our http cache) and unpacked it
+    Downloaded,
+    /// Built a wheel from an sdist
+    Built,
+}
+
+impl Display for InstallSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                InstallSource::Cached => "cached",
+                InstallSource::Downloaded => "downloaded",
+                InstallSource::Built => "built",
+            }
+        )
+    }
+}
+
+/// An entry point script provided by one of an Env's packages; see Env::scripts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct EnvScript {
+    pub name: String,
+    pub package: String,
+    /// "module:object", or just "module", as written in entry_points.txt
+    pub entry_point: String,
+    /// From gui_scripts, rather than console_scripts (only matters on Windows)
+    pub gui: bool,
+}
+
+/// One package that went into an Env.
+#[derive(Debug, Clone)]
+pub struct Installed {
+    pub name: PackageName,
+    pub version: Version,
+    /// The file we installed from (a wheel, or the sdist we built one from), or the
+    /// local source tree
+    pub artifact: String,
+    pub source: InstallSource,
+}
+
+impl EnvForest {
+    // Makes a directory with copies of the scripts that lost a conflict, under new
+    // names, so they're still reachable. It's keyed on exactly what goes in it, so
+    // envs with the same conflicts share it.
+    fn renamed_scripts(
+        &self,
+        roots: &[(PackageName, PathBuf)],
+        conflicts: &[FileConflict],
+    ) -> Result<PathBuf> {
+        let mut copies = Vec::new();
+        for conflict in conflicts {
+            // unwrap is safe b/c find_file_conflicts only reports packages from roots
+            let (_, root) = roots
+                .iter()
+                .find(|(name, _)| name == &conflict.loser)
+                .unwrap();
+            copies.push((
+                root.join(&conflict.path),
+                renamed_script_name(&conflict.path, &conflict.loser),
+            ));
+        }
+        let mut key = Vec::<u8>::new();
+        for (source, new_name) in &copies {
+            key.extend(source.to_string_lossy().as_bytes());
+            key.push(0);
+            key.extend(new_name.as_bytes());
+            key.push(0);
+        }
+        let dir = self.store.get_or_set(&key.as_slice(), |path| {
+            let bin = path.join("bin");
+            fs::create_dir_all(&bin)?;
+            for (source, new_name) in &copies {
+                fs::copy(source, bin.join(new_name))?;
+            }
+            Ok(())
+        })?;
+        Ok(dir.join("bin"))
+    }
+
+    // Store entries never change once they're written, so the same roots always have
+    // the same conflicts, and we only have to read every file in the env once per
+    // distinct env, instead of on every 'posy run'.
+    fn cached_file_conflicts(
+        &self,
+        roots: &[(PackageName, PathBuf)],
+    ) -> Result<Vec<FileConflict>> {
+        let mut key = b"file-conflicts\0".to_vec();
+        for (name, root) in roots {
+            key.extend(name.normalized().as_bytes());
+            key.push(0);
+            key.extend(root.to_string_lossy().as_bytes());
+            key.push(0);
+        }
+        let dir = self.store.get_or_set(&key.as_slice(), |path| {
+            let conflicts = find_file_conflicts(roots)?;
+            fs::write(path.join("conflicts.json"), serde_json::to_vec(&conflicts)?)?;
+            Ok(())
+        })?;
+        let cached = fs::read(dir.join("conflicts.json"))?;
+        Ok(serde_json::from_slice(&cached)?)
+    }
+}
+
+// 'posy env snapshot' files are gzipped tarballs: SNAPSHOT_MANIFEST, plus every store
+// entry the env uses, under "entries/" at the same path it has in the store. Restoring
+// one puts each entry back at that path, so it's found under the same key, and
+// installing the env afterwards doesn't have to download or unpack the pybi or any
+// wheels. It still needs the index (or posy's cached copy of its pages) to look up
+// which artifacts the pins point at, though -- those aren't in the snapshot.
+const SNAPSHOT_MANIFEST: &str = "posy-snapshot.json";
+const SNAPSHOT_FORMAT: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotManifest {
+    format: u32,
+    // relative to the store
+    entries: Vec<NicePathBuf>,
+}
+
+impl EnvForest {
+    /// Writes everything `env` (which must have come from this forest) is made of to
+    /// a snapshot file at `dest`. Returns how many entries that was.
+    pub fn snapshot(&self, env: &Env, dest: &Path) -> Result<usize> {
+        context!("Writing environment snapshot to {}", dest.display());
+        let mut entries = Vec::new();
+        for path in &env.store_paths {
+            let rel = self.store.relative(path).ok_or_else(|| {
+                eyre!("{} isn't in this environment forest", path.display())
+            })?;
+            let rel: NicePathBuf = rel
+                .to_string_lossy()
+                .replace('\\', "/")
+                .as_str()
+                .try_into()?;
+            if !entries.contains(&rel) {
+                entries.push(rel);
+            }
+        }
+        let manifest = SnapshotManifest {
+            format: SNAPSHOT_FORMAT,
+            entries,
+        };
+
+        let gz = flate2::write::GzEncoder::new(
+            fs::File::create(dest)?,
+            flate2::Compression::fast(),
+        );
+        let mut builder = tar::Builder::new(gz);
+        // pybis have symlinks (e.g. bin/python3 -> python3.11), and they should stay
+        // that way
+        builder.follow_symlinks(false);
+        let manifest_json = serde_json::to_vec_pretty(&manifest)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest_json.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(
+            &mut header,
+            SNAPSHOT_MANIFEST,
+            manifest_json.as_slice(),
+        )?;
+        for rel in &manifest.entries {
+            let rel = rel.to_native();
+            builder.append_dir_all(
+                Path::new("entries").join(&rel),
+                self.store.base().join(&rel),
+            )?;
+        }
+        builder.into_inner()?.finish()?.sync_all()?;
+        Ok(manifest.entries.len())
+    }
+
+    /// Puts everything from a snapshot file (see snapshot()) into this forest, skipping
+    /// entries it already has. Returns how many entries it added.
+    pub fn restore_snapshot(&self, src: &Path) -> Result<usize> {
+        context!("Restoring environment snapshot from {}", src.display());
+        // unpacked next to the store, so moving entries into place is just a rename
+        let tmp = self.store.tempdir()?;
+        let gz = flate2::read::GzDecoder::new(fs::File::open(src)?);
+        tar::Archive::new(gz).unpack(tmp.path())?;
+        let manifest: SnapshotManifest =
+            serde_json::from_slice(&fs::read(tmp.path().join(SNAPSHOT_MANIFEST))?)?;
+        if manifest.format != SNAPSHOT_FORMAT {
+            bail!(
+                "snapshot has format {}, but this posy only understands format \
+                 {SNAPSHOT_FORMAT}",
+                manifest.format
+            );
+        }
+        let mut restored = 0;
+        for rel in &manifest.entries {
+            let rel = rel.to_native();
+            if self
+                .store
+                .import(&rel, &tmp.path().join("entries").join(&rel))?
+            {
+                self.record_created(&self.store.base().join(&rel));
+                restored += 1;
+            }
+        }
+        Ok(restored)
+    }
+}
+
+// bin/fixit -> fixit-somepkg, bin/fixit.exe -> fixit-somepkg.exe
+fn renamed_script_name(path: &Path, package: &PackageName) -> String {
+    let file_name = path
+        .file_name()
+        .map(|f| f.to_string_lossy())
+        .unwrap_or_default();
+    let (stem, ext) = match file_name.strip_suffix(".exe") {
+        Some(stem) => (stem, ".exe"),
+        None => (&*file_name, ""),
+    };
+    format!("{stem}-{}{ext}", package.normalized())
+}
+
+// How many wheels we download and unpack at once. This is mostly network-bound, so
+// there's no need to match the number of CPUs.
+const MAX_PARALLEL_UNPACKS: usize = 8;
+
+// Like items.iter().map(f).collect(), but spread across a few threads. The current
+// tracing span gets entered on each worker, so context!() still shows up in errors.
+fn parallel_map<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let span = tracing::Span::current();
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<R>> = items.iter().map(|_| None).collect();
+    std::thread::scope(|scope| {
+        let workers = (0..MAX_PARALLEL_UNPACKS.min(items.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let _entered = span.enter();
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        match items.get(i) {
+                            Some(item) => done.push((i, f(item))),
+                            None => break done,
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            match worker.join() {
+                Ok(done) => {
+                    for (i, result) in done {
+                        results[i] = Some(result);
+                    }
+                }
+                Err(panic) => std::panic::resume_unwind(panic),
+            }
+        }
+    });
+    // unwrap is safe b/c the workers don't stop until every index has been claimed
+    results.into_iter().map(|r| r.unwrap()).collect()
+}
+
+/// Two packages in the same env that both ship the same file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileConflict {
+    pub path: PathBuf,
+    pub winner: PackageName,
+    pub loser: PackageName,
+}
+
+impl Display for FileConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} and {} both contain {}; the copy from {} will be used",
+            self.winner.as_given(),
+            self.loser.as_given(),
+            self.path.display(),
+            self.winner.as_given(),
+        )
+    }
+}
+
+// Recreates the tree at `src` in `dst`, with hardlinks instead of copies where
+// possible. Hardlinks rather than symlinks, so that if the source gets GC'ed, the copy
+// in `dst` keeps working. Empty directories don't get recreated, but unpacked wheels
+// never need them.
+//
+// XX TODO: this means that if someone edits a file in one env, it changes in every
+// env that shares it. Reflinks would be nicer, on filesystems that have them.
+// Hardlinks (or copies) every file in `src` into `dst`. If a file's already there,
+// that's an error -- unless `first_wins`, for stacking several trees into one, where
+// the first one wins, same as on sys.path.
+fn link_tree(src: &Path, dst: &Path, first_wins: bool) -> Result<()> {
+    let mut files = Vec::new();
+    walk_files(src, Path::new(""), &mut files)?;
+    for relative in files {
+        // big envs are a lot of files, and export copies them if it has to
+        crate::interrupt::check()?;
+        let (from, to) = (src.join(&relative), dst.join(&relative));
+        if to.symlink_metadata().is_ok() {
+            if first_wins {
+                continue;
+            }
+            bail!("{} already exists", to.display());
+        }
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // e.g. if they're on different filesystems
+        if fs::hard_link(&from, &to).is_err() {
+            fs::copy(&from, &to)?;
+        }
+    }
+    Ok(())
+}
+
+fn walk_files(root: &Path, relative: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(root.join(relative))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            walk_files(root, &path, out)?;
+        } else {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Every wheel gets unpacked into its own root, and then we stack them up on
+// $PATH/sys.path. So if two wheels contain the same file, nothing fails at install
+// time -- whichever root comes first silently shadows the other. Which is super
+// confusing to debug, so we look for that here.
+//
+// Identical files are fine, and actually pretty common (e.g. the __init__.py that
+// old-style namespace packages all ship copies of).
+fn find_file_conflicts(roots: &[(PackageName, PathBuf)]) -> Result<Vec<FileConflict>> {
+    let mut owners: HashMap<PathBuf, usize> = HashMap::new();
+    let mut conflicts = Vec::new();
+    for (i, (name, root)) in roots.iter().enumerate() {
+        context!("checking {} for conflicting files", name.as_given());
+        crate::interrupt::check()?;
+        let mut files = Vec::new();
+        for subdir in ["bin", "lib"] {
+            if root.join(subdir).is_dir() {
+                walk_files(root, Path::new(subdir), &mut files)?;
+            }
+        }
+        for path in files {
+            if let Some(&prev) = owners.get(&path) {
+                let (prev_name, prev_root) = &roots[prev];
+                if fs::read(prev_root.join(&path))? != fs::read(root.join(&path))? {
+                    conflicts.push(FileConflict {
+                        path,
+                        winner: prev_name.clone(),
+                        loser: name.clone(),
+                    });
+                }
+            } else {
+                owners.insert(path, i);
+            }
+        }
+    }
+    Ok(conflicts)
+}
+
 pub struct Env {
     // XX TODO for GC support: hold a lock to prevent anything from being GC'ed out from
     // under us
     pub platform_core_tag: String,
     pub wheel_platform: WheelPlatform,
+    pub pybi_root: PathBuf,
     pub python: PathBuf,
     pub pythonw: PathBuf,
     pub bin_dirs: Vec<PathBuf>,
     pub lib_dirs: Vec<PathBuf>,
+    pub installed: Vec<Installed>,
+    // how we picked the pybi and each binary wheel; see Selection
+    pub selections: Vec<Selection>,
+    // Everything in the EnvForest that the env is made of: the pybi, every wheel's
+    // root, and so on. What EnvForest::snapshot saves.
+    pub store_paths: Vec<PathBuf>,
+    // Extra interpreter flags (e.g. -I) for the env's trampolines to pass to python,
+    // from Blueprint::python_flags. These aren't baked into the trampolines themselves, because unpacked wheels are
+    // shared between envs.
+    pub python_flags: Vec<String>,
+    // An x86_64 env on an Apple silicon Mac, so commands have to be started under
+    // Rosetta 2 (see exec_in_env).
+    pub rosetta: bool,
+    // whatever we warned about while putting it together (hashless files, file
+    // conflicts, ...)
+    pub warnings: Vec<crate::output::Warning>,
 }
 
 impl Env {
@@ -345,9 +1617,252 @@ impl Env {
             "POSY_PYTHON_PACKAGES",
             std::env::join_paths(&self.lib_dirs)?,
         ));
+        // The trampolines paste this straight into a command line, so anything that
+        // would need quoting is out.
+        for flag in &self.python_flags {
+            if flag.is_empty()
+                || flag.contains(|c: char| {
+                    c.is_whitespace() || "\"'\\`$*?[]{}~;&|<>()".contains(c)
+                })
+            {
+                bail!("unsupported interpreter flag {flag:?}");
+            }
+        }
+        vars.push(("POSY_PYTHON_FLAGS", self.python_flags.join(" ").into()));
+        // For the trampolines, which might get run from somewhere that isn't already
+        // x86_64 (e.g. a shell with these variables set).
+        let arch = if self.rosetta { "x86_64" } else { "" };
+        vars.push(("POSY_PYTHON_ARCH", arch.into()));
 
         Ok(vars)
     }
+
+    /// Sets `cmd` up to run in this env: env_vars(), plus starting it under Rosetta 2 if
+    /// the env needs that. The Rosetta wrapper only keeps the program and args, so do
+    /// this before setting anything else on `cmd`.
+    pub fn wrap_command(
+        &self,
+        mut cmd: std::process::Command,
+    ) -> Result<std::process::Command> {
+        if self.rosetta {
+            // Otherwise a universal2 python starts up as arm64, and then can't load the
+            // x86_64 extension modules we installed. Whatever it runs after that
+            // inherits the arch, so this covers scripts and subprocesses too.
+            let mut wrapped = std::process::Command::new("/usr/bin/arch");
+            wrapped
+                .arg("-x86_64")
+                .arg(cmd.get_program())
+                .args(cmd.get_args());
+            cmd = wrapped;
+        }
+        // env_vars() gives us the magic environment variables needed to run a command
+        // in our new environment.
+        cmd.envs(self.env_vars()?);
+        Ok(cmd)
+    }
+
+    /// How much space the env's files take up: the pybi, plus every package's lib and
+    /// bin directories. Most of these are hardlinks shared with other envs, so this
+    /// isn't what the env costs in the forest, but it's about what an export takes.
+    pub fn disk_usage(&self) -> Result<u64> {
+        context!("Measuring the environment at {}", self.pybi_root.display());
+        let mut roots = vec![&self.pybi_root];
+        roots.extend(&self.lib_dirs);
+        roots.extend(&self.bin_dirs);
+        roots.sort();
+        roots.dedup();
+        let mut total = 0;
+        for root in &roots {
+            // e.g. the pybi's bin directory is inside the pybi
+            if roots
+                .iter()
+                .any(|other| other != root && root.starts_with(other))
+            {
+                continue;
+            }
+            total += tree_size(root)?;
+        }
+        Ok(total)
+    }
+
+    /// The file that running `name` in this env would start, if it's one of the env's
+    /// own commands (a package's script, or python itself). Like a $PATH lookup, but
+    /// only in `bin_dirs`.
+    pub fn find_script(&self, name: &str) -> Option<PathBuf> {
+        if name.is_empty()
+            || name.contains('/')
+            || name.contains(std::path::MAIN_SEPARATOR)
+        {
+            return None;
+        }
+        let filename = if cfg!(windows) && Path::new(name).extension().is_none() {
+            format!("{name}.exe")
+        } else {
+            name.to_string()
+        };
+        self.bin_dirs
+            .iter()
+            .map(|dir| dir.join(&filename))
+            .find(|path| path.is_file())
+    }
+
+    /// Every console and GUI script that the env's packages declare in their
+    /// entry_points.txt, sorted by name. If two packages have a script with the same
+    /// name, both are listed, and the first one is what's on $PATH.
+    pub fn scripts(&self) -> Result<Vec<EnvScript>> {
+        let mut scripts = Vec::new();
+        for lib_dir in &self.lib_dirs {
+            for dist_info in dist_info_dirs(lib_dir)? {
+                let path = lib_dir.join(&dist_info).join("entry_points.txt");
+                let contents = match fs::read_to_string(&path) {
+                    Ok(contents) => contents,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(err) => return Err(err.into()),
+                };
+                context!("Reading {}", path.display());
+                // "typing_extensions-4.5.0.dist-info" -> "typing_extensions", and then
+                // back to how the blueprint spells it, if we can
+                let stem = dist_info.trim_end_matches(".dist-info");
+                let stem = stem.split_once('-').map_or(stem, |(name, _)| name);
+                let package = match stem.parse::<PackageName>() {
+                    Ok(name) => self
+                        .installed
+                        .iter()
+                        .find(|installed| installed.name == name)
+                        .map_or(name.as_given(), |installed| installed.name.as_given())
+                        .to_string(),
+                    Err(_) => stem.to_string(),
+                };
+                let entry_points = parse_entry_points(&contents)?;
+                for (section, gui) in
+                    [("console_scripts", false), ("gui_scripts", true)]
+                {
+                    for entry in entry_points.get(section).into_iter().flatten() {
+                        scripts.push(EnvScript {
+                            name: entry.name.clone(),
+                            package: package.clone(),
+                            entry_point: match &entry.object {
+                                Some(object) => format!("{}:{object}", entry.module),
+                                None => entry.module.clone(),
+                            },
+                            gui,
+                        });
+                    }
+                }
+            }
+        }
+        // stable, so same-named scripts stay in $PATH order
+        scripts.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(scripts)
+    }
+
+    /// Materializes this env into `dest` as a standalone Python installation, that runs
+    /// without posy or any environment variables and doesn't care where it lives -- so
+    /// you can zip it up and ship it to some other machine. Files are hardlinked out of
+    /// the forest where possible, so it's cheap.
+    ///
+    /// Everything gets merged into the pybi's own layout: packages go into its purelib,
+    /// and scripts go next to python, where their trampolines look for it.
+    pub fn export(&self, dest: &Path) -> Result<()> {
+        context!("Exporting environment to {}", dest.display());
+        if dest.exists() && fs::read_dir(dest)?.next().is_some() {
+            bail!("{} already exists and isn't empty", dest.display());
+        }
+        for installed in &self.installed {
+            if let Some(path) = installed.artifact.strip_prefix("-e ") {
+                let name = installed.name.as_given();
+                warn!("{name} is editable, so the export still points back at {path}");
+            }
+        }
+        let metadata: PybiCoreMetadata =
+            fs::read(self.pybi_root.join("pybi-info").join("METADATA"))?
+                .as_slice()
+                .try_into()?;
+        let purelib = dest.join(metadata.path("purelib")?.to_native());
+        let scripts = dest.join(metadata.path("scripts")?.to_native());
+
+        link_tree(&self.pybi_root, dest, false)?;
+        // The bootstrap hook is how forest envs find their packages; exported ones
+        // have them right there in site-packages, and it would only complain that
+        // $POSY_PYTHON_PACKAGES isn't set. Unlinking doesn't touch the forest's copy.
+        for ext in ["py", "pth"] {
+            fs::remove_file(purelib.join(format!("{BOOTSTRAP_MODULE}.{ext}")))?;
+        }
+        for lib_dir in &self.lib_dirs {
+            link_tree(lib_dir, &purelib, true)?;
+        }
+
+        let windows = self.platform_core_tag.starts_with("win");
+        let trampoline_maker = TrampolineMaker::new(
+            FindPython::SameDir,
+            if windows {
+                ScriptPlatform::Windows
+            } else {
+                ScriptPlatform::Unix
+            },
+        )
+        .for_platform(&PybiPlatform::new(&self.platform_core_tag))
+        .under_rosetta(self.rosetta);
+        if self.rosetta {
+            // the trampolines take care of it for scripts, but python itself is the
+            // pybi's own universal2 binary
+            warn!(
+                "This environment's packages are x86_64, so run its python with \
+                 '/usr/bin/arch -x86_64 {}'",
+                scripts.join("python").display()
+            );
+        }
+        // bin_dirs[0] is the pybi's own, which we already have
+        for bin_dir in self.bin_dirs.iter().skip(1) {
+            if !bin_dir.exists() {
+                continue;
+            }
+            for entry in fs::read_dir(bin_dir)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let to = scripts.join(&name);
+                // first one wins, same as on $PATH
+                if to.symlink_metadata().is_ok() || entry.file_type()?.is_dir() {
+                    continue;
+                }
+                let contents = fs::read(entry.path())?;
+                // The forest has both kinds of trampoline for every script; we only
+                // want the ones for this platform. (make_trampoline adds the .exe
+                // back on.)
+                let unwrapped = if windows {
+                    if unwrap_unix_trampoline(&contents).is_some() {
+                        continue;
+                    }
+                    unwrap_windows_trampoline(&contents)?.map(
+                        |(script, script_type)| {
+                            (name.trim_end_matches(".exe"), script, script_type)
+                        },
+                    )
+                } else {
+                    if is_windows_trampoline(&contents) {
+                        continue;
+                    }
+                    unwrap_unix_trampoline(&contents).map(|(script, script_type)| {
+                        (name.as_str(), script.to_vec(), script_type)
+                    })
+                };
+                match unwrapped {
+                    Some((script_name, script, script_type)) => trampoline_maker
+                        .make_trampoline(
+                            &script_name.try_into()?,
+                            &script,
+                            script_type,
+                            WriteTreeFS::new(&scripts),
+                        )?,
+                    // e.g. a compiled binary; nothing to rewrite
+                    None => {
+                        fs::copy(entry.path(), to)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 // pub trait PyEnvMaker {
@@ -360,18 +1875,10 @@ impl Env {
 //     // references so can do GC
 // }
 
-// pub struct ProjectWorkspace {}
+// (persistent named environments for a project are in workspace.rs)
 
 // pub struct TempWorkspace {}
 
-// // represents a _posy directory with persistent named environments
-// // I guess needs some locking?
-// impl ProjectWorkspace {
-//     pub fn get_env(name: &str, blueprint: &Blueprint) -> PyEnv {
-//         todo!()
-//     }
-// }
-
 // // represents a temp collection of environments, maybe can do everything with env
 // // manipulation + share copies of python/packages, including concurrently?
 // impl TempWorkspace {
@@ -379,3 +1886,705 @@ impl Env {
 //         todo!()
 //     }
 // }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::FakePybi;
+    use crate::trampolines::ScriptType;
+
+    #[test]
+    fn test_snapshot_restore() {
+        let tmp = tempfile::tempdir().unwrap();
+        let one = EnvForest::new(&tmp.path().join("one")).unwrap();
+        let pybi_key = b"pybi".as_slice();
+        let pybi_root = one
+            .store
+            .get_or_set(&pybi_key, |path| {
+                fs::create_dir_all(path.join("bin"))?;
+                fs::write(path.join("bin/python3.11"), b"python")?;
+                #[cfg(unix)]
+                std::os::unix::fs::symlink("python3.11", path.join("bin/python"))?;
+                Ok(())
+            })
+            .unwrap();
+        // like a wheel we built from an sdist, which lives inside the sdist's entry
+        let sdist_key = b"sdist".as_slice();
+        let wheel_root = one
+            .store
+            .get_or_set(&sdist_key, |path| {
+                let lib = path.join("foo-1.0-py3-none-any.whl/lib");
+                fs::create_dir_all(&lib)?;
+                fs::write(lib.join("foo.py"), b"foo")?;
+                Ok(())
+            })
+            .unwrap()
+            .join("foo-1.0-py3-none-any.whl");
+        let env = Env {
+            platform_core_tag: "manylinux_2_17_x86_64".into(),
+            wheel_platform: WheelPlatform::pure_python(&"3.11".try_into().unwrap())
+                .unwrap(),
+            python: pybi_root.join("bin/python"),
+            pythonw: pybi_root.join("bin/python"),
+            pybi_root: pybi_root.clone(),
+            bin_dirs: vec![pybi_root.join("bin")],
+            lib_dirs: vec![wheel_root.join("lib")],
+            installed: Vec::new(),
+            selections: Vec::new(),
+            store_paths: vec![pybi_root, wheel_root],
+            python_flags: Vec::new(),
+            rosetta: false,
+            warnings: Vec::new(),
+        };
+        let snapshot = tmp.path().join("env.tar.gz");
+        assert_eq!(one.snapshot(&env, &snapshot).unwrap(), 2);
+
+        let two = EnvForest::new(&tmp.path().join("two")).unwrap();
+        assert_eq!(two.restore_snapshot(&snapshot).unwrap(), 2);
+        let pybi_root = two.store.lock(&pybi_key).unwrap();
+        assert_eq!(
+            fs::read(pybi_root.join("bin/python3.11")).unwrap(),
+            b"python"
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read_link(pybi_root.join("bin/python")).unwrap(),
+            Path::new("python3.11")
+        );
+        drop(pybi_root);
+        let sdist_root = two.store.lock(&sdist_key).unwrap();
+        assert_eq!(
+            fs::read(sdist_root.join("foo-1.0-py3-none-any.whl/lib/foo.py")).unwrap(),
+            b"foo"
+        );
+        drop(sdist_root);
+        // nothing left to do the second time
+        assert_eq!(two.restore_snapshot(&snapshot).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_get_env_after_restore() {
+        use crate::test_util::{index_page, StaticHTTPServer};
+
+        let fake = FakePybi::new("3.11.2");
+        let pybi_bytes = fake.to_bytes();
+        let digest = ring::digest::digest(&ring::digest::SHA256, &pybi_bytes);
+        let hash: ArtifactHash =
+            format!("sha256={}", data_encoding::HEXLOWER.encode(digest.as_ref()))
+                .parse()
+                .unwrap();
+        let page = || index_page(&[&format!(r#"href="{}#{hash}""#, fake.filename())]);
+        let blueprint = Blueprint {
+            pybi: PinnedPackage {
+                name: "cpython".parse().unwrap(),
+                version: fake.version.clone(),
+                hashes: vec![hash.clone()],
+                url: None,
+            },
+            wheels: vec![],
+            local: vec![],
+            marker_expressions: Default::default(),
+            python_fallback: None,
+            annotations: Default::default(),
+            claimed_publishers: Default::default(),
+            platform_tags: Default::default(),
+            source_builds: Default::default(),
+            warnings: Vec::new(),
+            policy: Default::default(),
+            python_flags: vec!["-I".into()],
+        };
+        let platform = PybiPlatform::new(&fake.platform);
+        let tmp = tempfile::tempdir().unwrap();
+        let build_store = KVDirStore::new(&tmp.path().join("build")).unwrap();
+        let get_env = |server: &StaticHTTPServer, name: &str| {
+            let forest = EnvForest::new(&tmp.path().join(name).join("forest")).unwrap();
+            let db = PackageDB::new(
+                &[server.url("/simple/")],
+                &tmp.path().join(name).join("cache"),
+                &forest,
+                &build_store,
+                None,
+            )
+            .unwrap();
+            let env = forest.get_env(&db, &blueprint, &[&platform], &[]);
+            (forest, env)
+        };
+
+        let pybi_path = format!("/simple/cpython/{}", fake.filename());
+        let response = http::Response::builder().body(pybi_bytes).unwrap();
+        let server = StaticHTTPServer::with_routes(vec![
+            ("/simple/cpython/", page()),
+            (&pybi_path, response),
+        ]);
+        let (one, env) = get_env(&server, "one");
+        let snapshot = tmp.path().join("env.tar.gz");
+        one.snapshot(&env.unwrap(), &snapshot).unwrap();
+
+        // the index still has to be there to look the pin up, but the pybi itself
+        // can't be downloaded anymore
+        let server = StaticHTTPServer::with_routes(vec![("/simple/cpython/", page())]);
+        let (two, env) = get_env(&server, "two");
+        assert!(env.is_err());
+        assert_eq!(two.restore_snapshot(&snapshot).unwrap(), 1);
+        drop(two);
+        let (two, env) = get_env(&server, "two");
+        let env = env.unwrap();
+        assert!(env.pybi_root.starts_with(two.store.base()));
+        assert!(env.python.exists());
+        // no project around to ask, but the flags came along with the blueprint
+        assert_eq!(env.python_flags, vec!["-I"]);
+    }
+
+    #[test]
+    fn test_selection_display() {
+        let candidate = |filename: &str, tags: &str, verdict| Candidate {
+            filename: filename.into(),
+            tags: tags.into(),
+            verdict,
+        };
+        let mut selection = Selection {
+            package: "foo".into(),
+            version: "1.0".try_into().unwrap(),
+            chosen: Some("foo-1.0-cp311-cp311-manylinux_2_17_x86_64.whl".into()),
+            platform: Some("cp311-cp311-manylinux_2_35_x86_64".into()),
+            matched_tag: Some("cp311-cp311-manylinux_2_17_x86_64".into()),
+            candidates: vec![
+                candidate(
+                    "foo-1.0-cp311-cp311-manylinux_2_17_x86_64.whl",
+                    "cp311-cp311-manylinux_2_17_x86_64",
+                    Verdict::Chosen,
+                ),
+                candidate(
+                    "foo-1.0-cp311-cp311-musllinux_1_1_x86_64.whl",
+                    "cp311-cp311-musllinux_1_1_x86_64",
+                    Verdict::WrongTags,
+                ),
+                candidate("foo-1.0-py3-none-any.whl", "py3-none-any", Verdict::Yanked),
+            ],
+        };
+        assert_eq!(
+            selection.to_string(),
+            "foo 1.0: picked foo-1.0-cp311-cp311-manylinux_2_17_x86_64.whl (tag \
+             cp311-cp311-manylinux_2_17_x86_64 for platform \
+             cp311-cp311-manylinux_2_35_x86_64)\n  \
+             wrong tags: foo-1.0-cp311-cp311-musllinux_1_1_x86_64.whl\n  \
+             yanked: foo-1.0-py3-none-any.whl"
+        );
+        let json = serde_json::to_value(&selection).unwrap();
+        assert_eq!(json["candidates"][1]["verdict"], "wrong-tags");
+        assert_eq!(json["matched-tag"], "cp311-cp311-manylinux_2_17_x86_64");
+
+        selection.chosen = None;
+        selection.candidates.truncate(0);
+        assert_eq!(selection.to_string(), "foo 1.0: no usable binaries");
+    }
+
+    // Sets up a PackageDB whose index is a snapshot with one page, for "foo" 1.0, that
+    // links to `links` (raw <a> attributes, e.g. href and data-yanked).
+    fn with_foo_db(links: &[String], f: impl FnOnce(&mut PackageDB)) {
+        let tmp = tempfile::tempdir().unwrap();
+        let snapshot = tmp.path().join("snapshot");
+        fs::create_dir(&snapshot).unwrap();
+        fs::write(
+            snapshot.join("posy-snapshot.json"),
+            r#"{"projects": {"foo": {"url": "https://example.com/simple/foo/",
+                                     "saved-at": 0}}}"#,
+        )
+        .unwrap();
+        let page = links
+            .iter()
+            .map(|attrs| format!("<a {attrs}>file</a>\n"))
+            .collect::<String>();
+        fs::write(snapshot.join("foo.html"), page).unwrap();
+        let forest = EnvForest::new(&tmp.path().join("forest")).unwrap();
+        let build_store = KVDirStore::new(&tmp.path().join("build")).unwrap();
+        let mut db = PackageDB::from_snapshot(
+            crate::package_db::SimpleApiSnapshot::open(&snapshot, None).unwrap(),
+            &tmp.path().join("cache"),
+            &forest,
+            &build_store,
+            None,
+        )
+        .unwrap();
+        f(&mut db);
+    }
+
+    fn fake_hash(n: u8) -> ArtifactHash {
+        format!("sha256={}", format!("{n:02x}").repeat(32))
+            .parse()
+            .unwrap()
+    }
+
+    fn foo_link(tag: &str, hash: Option<u8>) -> String {
+        match hash {
+            Some(n) => format!(r#"href="foo-1.0-{tag}.whl#{}""#, fake_hash(n)),
+            None => format!(r#"href="foo-1.0-{tag}.whl""#),
+        }
+    }
+
+    fn foo_pin(hashes: &[u8]) -> PinnedPackage {
+        PinnedPackage {
+            name: "foo".parse().unwrap(),
+            version: "1.0".try_into().unwrap(),
+            hashes: hashes.iter().map(|n| fake_hash(*n)).collect(),
+            url: None,
+        }
+    }
+
+    #[test]
+    fn test_select_pinned_binary_prefers_best_score() {
+        // py311 is the most specific tag for 3.11, then py3, then py310, py39, ...
+        let platform =
+            WheelPlatform::pure_python(&"3.11.0".try_into().unwrap()).unwrap();
+        let links = [
+            foo_link("py38-none-any", Some(1)),
+            foo_link("py310-none-any", Some(2)),
+            foo_link("py39-none-any", Some(3)),
+        ];
+        with_foo_db(&links, |db| {
+            let (found, selection) =
+                select_pinned_binary::<Wheel>(db, &[&platform], &foo_pin(&[1, 2, 3]))
+                    .unwrap();
+            let (ai, _) = found.unwrap();
+            assert_eq!(ai.name.to_string(), "foo-1.0-py310-none-any.whl");
+            assert_eq!(selection.matched_tag.as_deref(), Some("py310-none-any"));
+            // and pick_pinned_binary agrees
+            let (ai, _) =
+                pick_pinned_binary::<Wheel>(db, &[&platform], &foo_pin(&[1, 2, 3]))
+                    .unwrap();
+            assert_eq!(ai.name.to_string(), "foo-1.0-py310-none-any.whl");
+        });
+    }
+
+    #[test]
+    fn test_select_pinned_binary_verdicts() {
+        let platform =
+            WheelPlatform::pure_python(&"3.11.0".try_into().unwrap()).unwrap();
+        let links = [
+            // best score, but we can't check it
+            foo_link("py311-none-any", None),
+            // not one of the pin's hashes
+            foo_link("py3-none-any", Some(3)),
+            format!(
+                r#"{} data-yanked="oops""#,
+                foo_link("py310-none-any", Some(4))
+            ),
+            foo_link("py39-none-any", Some(1)),
+            foo_link("py38-none-any", Some(2)),
+            foo_link("py2-none-any", Some(5)),
+        ];
+        with_foo_db(&links, |db| {
+            db.yanked_policy = crate::package_db::YankedPolicy::Forbid;
+            let (found, selection) = select_pinned_binary::<Wheel>(
+                db,
+                &[&platform],
+                &foo_pin(&[1, 2, 4, 5]),
+            )
+            .unwrap();
+            assert_eq!(
+                found.unwrap().0.name.to_string(),
+                "foo-1.0-py39-none-any.whl"
+            );
+            let verdicts = selection
+                .candidates
+                .iter()
+                .map(|c| (c.tags.as_str(), c.verdict))
+                .collect::<HashMap<_, _>>();
+            assert_eq!(
+                verdicts,
+                HashMap::from([
+                    ("py311-none-any", Verdict::MissingHash),
+                    ("py3-none-any", Verdict::NotLocked),
+                    ("py310-none-any", Verdict::Yanked),
+                    ("py39-none-any", Verdict::Chosen),
+                    ("py38-none-any", Verdict::Outranked),
+                    ("py2-none-any", Verdict::WrongTags),
+                ])
+            );
+
+            // nothing usable at all
+            let (found, selection) =
+                select_pinned_binary::<Wheel>(db, &[&platform], &foo_pin(&[5]))
+                    .unwrap();
+            assert!(found.is_none());
+            assert!(selection.chosen.is_none());
+            assert!(
+                pick_pinned_binary::<Wheel>(db, &[&platform], &foo_pin(&[5])).is_err()
+            );
+        });
+    }
+
+    #[test]
+    fn test_find_file_conflicts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let write = |root: &str, path: &str, contents: &str| {
+            let full = tmp.path().join(root).join(path);
+            fs::create_dir_all(full.parent().unwrap()).unwrap();
+            fs::write(full, contents).unwrap();
+        };
+        write("a", "lib/six.py", "one six");
+        write("a", "lib/ns/__init__.py", "namespace");
+        write("b", "lib/six.py", "another six");
+        write("b", "lib/ns/__init__.py", "namespace");
+        write("b", "bin/tool", "b's tool");
+        write("c", "bin/tool", "c's tool");
+
+        let roots = ["a", "b", "c"]
+            .iter()
+            .map(|n| (n.parse().unwrap(), tmp.path().join(n)))
+            .collect::<Vec<_>>();
+        let mut conflicts = find_file_conflicts(&roots)
+            .unwrap()
+            .into_iter()
+            .map(|c| {
+                (
+                    c.path.to_string_lossy().replace('\\', "/"),
+                    c.winner.as_given().to_string(),
+                    c.loser.as_given().to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        conflicts.sort();
+        assert_eq!(
+            conflicts,
+            vec![
+                ("bin/tool".into(), "b".into(), "c".into()),
+                ("lib/six.py".into(), "a".into(), "b".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cached_file_conflicts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let forest = EnvForest::new(&tmp.path().join("forest")).unwrap();
+        for (root, contents) in [("a", "one six"), ("b", "another six")] {
+            fs::create_dir_all(tmp.path().join(root).join("lib")).unwrap();
+            fs::write(tmp.path().join(root).join("lib/six.py"), contents).unwrap();
+        }
+        let roots = ["a", "b"]
+            .iter()
+            .map(|n| (n.parse().unwrap(), tmp.path().join(n)))
+            .collect::<Vec<_>>();
+        let conflicts = forest.cached_file_conflicts(&roots).unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].loser.as_given(), "b");
+
+        // store entries don't change, so the second time we don't look at the files
+        // at all
+        fs::write(tmp.path().join("b/lib/six.py"), "one six").unwrap();
+        assert_eq!(forest.cached_file_conflicts(&roots).unwrap().len(), 1);
+        // but different roots are a different env
+        assert!(forest
+            .cached_file_conflicts(&roots[..1])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_link_tree() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = tmp.path().join("src");
+        fs::create_dir_all(src.join("lib/pkg")).unwrap();
+        fs::write(src.join("lib/pkg/__init__.py"), "hi").unwrap();
+        fs::write(src.join("top.txt"), "top").unwrap();
+
+        let dst = tmp.path().join("dst");
+        link_tree(&src, &dst, false).unwrap();
+        assert_eq!(
+            fs::read_to_string(dst.join("lib/pkg/__init__.py")).unwrap(),
+            "hi"
+        );
+        assert_eq!(fs::read_to_string(dst.join("top.txt")).unwrap(), "top");
+
+        // stacking another tree on top keeps what's already there, but otherwise
+        // anything in the way is a mistake
+        let other = tmp.path().join("other");
+        fs::create_dir_all(&other).unwrap();
+        fs::write(other.join("top.txt"), "other").unwrap();
+        fs::write(other.join("new.txt"), "new").unwrap();
+        assert!(link_tree(&other, &dst, false).is_err());
+        link_tree(&other, &dst, true).unwrap();
+        assert_eq!(fs::read_to_string(dst.join("top.txt")).unwrap(), "top");
+        assert_eq!(fs::read_to_string(dst.join("new.txt")).unwrap(), "new");
+
+        // the copy survives the original going away
+        fs::remove_dir_all(&src).unwrap();
+        assert_eq!(fs::read_to_string(dst.join("top.txt")).unwrap(), "top");
+    }
+
+    #[test]
+    fn test_renamed_script_name() {
+        let package: PackageName = "Some_Package".parse().unwrap();
+        assert_eq!(
+            renamed_script_name(Path::new("bin/fixit"), &package),
+            "fixit-some-package"
+        );
+        assert_eq!(
+            renamed_script_name(Path::new("bin/fixit.exe"), &package),
+            "fixit-some-package.exe"
+        );
+        assert_eq!(
+            renamed_script_name(Path::new("bin/pip3.10"), &package),
+            "pip3.10-some-package"
+        );
+    }
+
+    #[test]
+    fn test_dist_info_dirs() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert!(dist_info_dirs(&tmp.path().join("nope")).unwrap().is_empty());
+        for name in ["trio-0.22.0.dist-info", "attrs-22.1.0.dist-info", "trio"] {
+            fs::create_dir(tmp.path().join(name)).unwrap();
+        }
+        fs::write(tmp.path().join("six.py"), "").unwrap();
+        assert_eq!(
+            dist_info_dirs(tmp.path()).unwrap(),
+            vec!["attrs-22.1.0.dist-info", "trio-0.22.0.dist-info"]
+        );
+    }
+
+    #[test]
+    fn test_foreign_dist_infos() {
+        let tmp = tempfile::tempdir().unwrap();
+        let metadata: PybiCoreMetadata = indoc::indoc! {br#"
+            Metadata-Version: 2.1
+            Name: cpython
+            Version: 3.11
+            Pybi-Environment-Marker-Variables: {}
+            Pybi-Paths: {"purelib": "lib/site-packages", "platlib": "lib/site-packages"}
+            Pybi-Wheel-Tag: py3-none-any
+        "#}
+        .as_slice()
+        .try_into()
+        .unwrap();
+        let site_packages = tmp.path().join("lib").join("site-packages");
+        for name in ["pip-23.0.dist-info", "requests-2.28.0.dist-info"] {
+            fs::create_dir_all(site_packages.join(name)).unwrap();
+        }
+        fs::create_dir_all(tmp.path().join("pybi-info")).unwrap();
+        fs::write(
+            tmp.path().join("pybi-info").join("RECORD"),
+            "lib/site-packages/pip-23.0.dist-info/RECORD,,\n",
+        )
+        .unwrap();
+        // only reported once, even though purelib and platlib are the same directory
+        assert_eq!(
+            foreign_dist_infos(tmp.path(), &metadata).unwrap(),
+            vec![("requests-2.28.0.dist-info".to_string(), site_packages)]
+        );
+    }
+
+    #[test]
+    fn test_munge_unpacked_pybi() {
+        let tmp = tempfile::tempdir().unwrap();
+        let fake = FakePybi::new("3.11.2").with_file(
+            "lib/python3.11/site-packages/pip-23.0.dist-info/RECORD",
+            b"",
+        );
+        let pybi = fake.open();
+        pybi.unpack(&mut WriteTreeFS::new(tmp.path())).unwrap();
+        let (_, metadata) = pybi.metadata().unwrap();
+        // unpacked but not set up yet, e.g. because we got killed in between
+        assert!(unpacked_pybi_metadata(tmp.path()).is_err());
+        EnvForest::munge_unpacked_pybi(tmp.path(), &metadata).unwrap();
+        let found = unpacked_pybi_metadata(tmp.path()).unwrap();
+        assert_eq!(found.version, metadata.version);
+
+        let stdlib = tmp.path().join(fake.stdlib());
+        let site_py = fs::read_to_string(stdlib.join("site.py")).unwrap();
+        assert!(site_py.contains("ENABLE_USER_SITE = False"));
+        assert!(stdlib.join("EXTERNALLY-MANAGED").exists());
+        let purelib = tmp.path().join(fake.purelib());
+        assert!(purelib.join(format!("{BOOTSTRAP_MODULE}.py")).exists());
+        assert!(purelib.join(format!("{BOOTSTRAP_MODULE}.pth")).exists());
+        // everything that came in the pybi is listed in its RECORD
+        assert!(foreign_dist_infos(tmp.path(), &metadata)
+            .unwrap()
+            .is_empty());
+
+        // and it has to be a site.py we know how to patch
+        fs::write(stdlib.join("site.py"), "import sys\n").unwrap();
+        assert!(EnvForest::munge_unpacked_pybi(tmp.path(), &metadata).is_err());
+    }
+
+    #[test]
+    fn test_env_scripts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let write = |path: &str, contents: &str| {
+            let full = tmp.path().join(path);
+            fs::create_dir_all(full.parent().unwrap()).unwrap();
+            fs::write(full, contents).unwrap();
+        };
+        write(
+            "black/lib/black-23.1.0.dist-info/entry_points.txt",
+            "[console_scripts]\nblack = black:patched_main\nblackd = blackd:patched_main [d]\n",
+        );
+        write(
+            "idle/lib/Idle_Lib-1.0.dist-info/entry_points.txt",
+            "[gui_scripts]\nidle = idlelib\n[console_scripts]\nblack = idlelib:oops\n",
+        );
+        // no entry_points.txt at all
+        write("trio/lib/trio-0.22.0.dist-info/METADATA", "");
+        let env = Env {
+            platform_core_tag: "manylinux_2_17_x86_64".into(),
+            wheel_platform: WheelPlatform::pure_python(&"3.11".try_into().unwrap())
+                .unwrap(),
+            pybi_root: tmp.path().join("pybi"),
+            python: tmp.path().join("pybi/bin/python"),
+            pythonw: tmp.path().join("pybi/bin/python"),
+            bin_dirs: Vec::new(),
+            lib_dirs: ["black", "idle", "trio"]
+                .iter()
+                .map(|name| tmp.path().join(name).join("lib"))
+                .collect(),
+            installed: vec![Installed {
+                name: "idle-lib".parse().unwrap(),
+                version: "1.0".try_into().unwrap(),
+                artifact: "idle_lib-1.0-py3-none-any.whl".into(),
+                source: InstallSource::Cached,
+            }],
+            selections: Vec::new(),
+            store_paths: Vec::new(),
+            python_flags: Vec::new(),
+            rosetta: false,
+            warnings: Vec::new(),
+        };
+        let scripts = env
+            .scripts()
+            .unwrap()
+            .into_iter()
+            .map(|s| format!("{} {} {} {}", s.name, s.package, s.entry_point, s.gui))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            scripts,
+            [
+                "black black black:patched_main false",
+                "black idle-lib idlelib:oops false",
+                "blackd black blackd:patched_main false",
+                "idle idle-lib idlelib true",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_env_export() {
+        let tmp = tempfile::tempdir().unwrap();
+        let write = |path: &str, contents: &[u8]| {
+            let full = tmp.path().join(path);
+            fs::create_dir_all(full.parent().unwrap()).unwrap();
+            fs::write(full, contents).unwrap();
+        };
+        write(
+            "pybi/pybi-info/METADATA",
+            indoc::indoc! {br#"
+                Metadata-Version: 2.1
+                Name: cpython
+                Version: 3.11
+                Pybi-Environment-Marker-Variables: {}
+                Pybi-Paths: {"purelib": "lib/site-packages", "scripts": "bin"}
+                Pybi-Wheel-Tag: py3-none-any
+            "#},
+        );
+        write("pybi/bin/python", b"fake python");
+        write("pybi/lib/site-packages/_posy_bootstrap.py", b"");
+        write("pybi/lib/site-packages/_posy_bootstrap.pth", b"");
+        let forest_maker =
+            TrampolineMaker::new(FindPython::FromEnv, ScriptPlatform::Both);
+        for (root, script) in [("a", "print('a')"), ("b", "print('b')")] {
+            write(&format!("{root}/lib/{root}.py"), b"");
+            write(&format!("{root}/lib/shared.py"), root.as_bytes());
+            forest_maker
+                .make_trampoline(
+                    &"tool".try_into().unwrap(),
+                    script.as_bytes(),
+                    ScriptType::Console,
+                    WriteTreeFS::new(tmp.path().join(root).join("bin")),
+                )
+                .unwrap();
+        }
+        let mut env = Env {
+            platform_core_tag: "manylinux_2_17_x86_64".into(),
+            wheel_platform: WheelPlatform::pure_python(&"3.11".try_into().unwrap())
+                .unwrap(),
+            pybi_root: tmp.path().join("pybi"),
+            python: tmp.path().join("pybi/bin/python"),
+            pythonw: tmp.path().join("pybi/bin/python"),
+            bin_dirs: vec![
+                tmp.path().join("pybi/bin"),
+                tmp.path().join("a/bin"),
+                tmp.path().join("b/bin"),
+            ],
+            lib_dirs: vec![tmp.path().join("a/lib"), tmp.path().join("b/lib")],
+            installed: Vec::new(),
+            selections: Vec::new(),
+            store_paths: Vec::new(),
+            python_flags: Vec::new(),
+            rosetta: false,
+            warnings: Vec::new(),
+        };
+
+        // same as $PATH: the first one wins
+        let tool = env.find_script("tool").unwrap();
+        assert!(tool.starts_with(tmp.path().join("a/bin")));
+        assert_eq!(env.find_script("a/../tool"), None);
+        assert_eq!(env.find_script("missing"), None);
+
+        // pybi/bin is inside pybi, so it only counts once
+        let expected = ["pybi", "a", "b"]
+            .iter()
+            .map(|dir| tree_size(&tmp.path().join(dir)).unwrap())
+            .sum::<u64>();
+        assert_eq!(env.disk_usage().unwrap(), expected);
+
+        let dest = tmp.path().join("exported");
+        env.export(&dest).unwrap();
+        let site_packages = dest.join("lib/site-packages");
+        assert!(!site_packages.join("_posy_bootstrap.pth").exists());
+        assert!(site_packages.join("a.py").exists());
+        assert!(site_packages.join("b.py").exists());
+        // first one wins
+        assert_eq!(fs::read(site_packages.join("shared.py")).unwrap(), b"a");
+        let tool = fs::read_to_string(dest.join("bin/tool")).unwrap();
+        assert!(tool.contains(r#""$(dirname "$0")/python""#));
+        assert!(!tool.contains("POSY_PYTHON}"));
+        assert!(tool.ends_with("print('a')"));
+        assert!(!dest.join("bin/tool.exe").exists());
+        // the forest's copy is untouched
+        assert!(tmp
+            .path()
+            .join("pybi/lib/site-packages/_posy_bootstrap.pth")
+            .exists());
+
+        assert!(env.export(&dest).is_err());
+
+        // an x86_64 env on Apple silicon
+        env.rosetta = true;
+        let dest = tmp.path().join("exported-x86_64");
+        env.export(&dest).unwrap();
+        let tool = fs::read_to_string(dest.join("bin/tool")).unwrap();
+        assert!(tool.contains(r#"exec /usr/bin/arch -x86_64 "$(dirname "$0")/python""#));
+        let vars: HashMap<_, _> = env.env_vars().unwrap().into_iter().collect();
+        assert_eq!(vars["POSY_PYTHON_ARCH"], "x86_64");
+    }
+
+    #[test]
+    fn test_env_problem_json() {
+        let problem = EnvProblem::MetadataMismatch {
+            name: "Trio".parse().unwrap(),
+            version: "0.22.0".try_into().unwrap(),
+            expected_from: "https://example.com/a.whl".into(),
+            found_from: "https://example.com/b.whl".into(),
+        };
+        assert_eq!(
+            serde_json::to_value(&problem).unwrap(),
+            serde_json::json!({
+                "problem": "metadata-mismatch",
+                "name": "Trio",
+                "version": "0.22.0",
+                "expected-from": "https://example.com/a.whl",
+                "found-from": "https://example.com/b.whl",
+            })
+        );
+    }
+}
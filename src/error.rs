@@ -7,6 +7,20 @@ pub enum PosyError {
     NoCompatibleBinaries { name: String, version: Version },
     #[error("no compatible pybis found for requirement and platform")]
     NoPybiFound,
+    #[error("couldn't find a package named '{name}' on the package index")]
+    PackageNotFound { name: String },
+    // e.g. every file was deleted or yanked; `reason` says which
+    #[error(
+        "'{name}' exists on the package index, but has no installable files ({reason})"
+    )]
+    NoInstallableFiles { name: String, reason: String },
+    #[error(
+        "running offline, and these aren't in posy's cache:\n  {}",
+        missing.join("\n  ")
+    )]
+    OfflineCacheMiss { missing: Vec<String> },
     #[error("remote file does not support range requests")]
     LazyRemoteFileNotSupported,
+    #[error("interrupted")]
+    Interrupted,
 }
@@ -0,0 +1,132 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::prelude::*;
+
+// Progress reports from deep inside posy, for whoever's driving it. The CLI turns them
+// into log lines (see output::CliEvents), and `--event-log` writes them out as JSON
+// lines for tools that want to show their own progress UI. Like the counters in
+// stats.rs, the subscriber list is global, because events come from all over the
+// place, including prefetch and unpack threads.
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    /// Starting to resolve a Brief. `for_build` is set when this is the resolve for
+    /// some package's build environment.
+    ResolveStarted {
+        python: String,
+        requirements: usize,
+        for_build: Option<String>,
+    },
+    ResolveFinished {
+        python: String,
+        packages: usize,
+        for_build: Option<String>,
+    },
+    /// Looking up a project's files on an index (possibly answered from our http
+    /// cache).
+    FetchingIndexPage {
+        url: String,
+    },
+    /// Getting hold of a wheel, pybi, or sdist (again, possibly from the cache).
+    FetchingArtifact {
+        artifact: String,
+        url: String,
+    },
+    BuildingWheel {
+        package: String,
+        goal: String,
+    },
+    /// Sent now and then while unpacking a big archive.
+    Unpacking {
+        artifact: String,
+        entries: u64,
+        total_entries: Option<u64>,
+        bytes: u64,
+    },
+    /// A package is going into an env; `source` is cached, downloaded, or built.
+    Installing {
+        package: String,
+        version: String,
+        artifact: String,
+        source: String,
+    },
+}
+
+pub trait Subscriber: Send + Sync {
+    fn event(&self, event: &Event);
+}
+
+static SUBSCRIBERS: RwLock<Vec<Arc<dyn Subscriber>>> = RwLock::new(Vec::new());
+
+/// Start sending every event to `subscriber`, from now until the process exits.
+pub fn subscribe(subscriber: Arc<dyn Subscriber>) {
+    SUBSCRIBERS.write().unwrap().push(subscriber);
+}
+
+pub fn emit(event: Event) {
+    for subscriber in SUBSCRIBERS.read().unwrap().iter() {
+        subscriber.event(&event);
+    }
+}
+
+/// Writes each event as a line of JSON.
+pub struct JsonLines<W: Write + Send> {
+    out: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonLines<W> {
+    pub fn new(out: W) -> JsonLines<W> {
+        JsonLines {
+            out: Mutex::new(out),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out.into_inner().unwrap()
+    }
+}
+
+impl<W: Write + Send> Subscriber for JsonLines<W> {
+    fn event(&self, event: &Event) {
+        let mut out = self.out.lock().unwrap();
+        // flush each one, so tools tailing the file see progress as it happens
+        let result = serde_json::to_writer(&mut *out, event)
+            .map_err(std::io::Error::from)
+            .and_then(|()| out.write_all(b"\n"))
+            .and_then(|()| out.flush());
+        if let Err(err) = result {
+            // not worth failing the whole command over
+            debug!("couldn't write event: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_json_lines() {
+        let subscriber = JsonLines::new(Vec::new());
+        subscriber.event(&Event::FetchingIndexPage {
+            url: "https://pypi.org/simple/trio/".into(),
+        });
+        subscriber.event(&Event::Unpacking {
+            artifact: "torch-2.0.0-cp311-cp311-linux_x86_64.whl".into(),
+            entries: 10,
+            total_entries: None,
+            bytes: 1 << 20,
+        });
+        let written = String::from_utf8(subscriber.into_inner()).unwrap();
+        let lines = written.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                r#"{"event":"fetching-index-page","url":"https://pypi.org/simple/trio/"}"#,
+                r#"{"event":"unpacking","artifact":"torch-2.0.0-cp311-cp311-linux_x86_64.whl","entries":10,"total_entries":null,"bytes":1048576}"#,
+            ]
+        );
+    }
+}
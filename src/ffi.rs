@@ -0,0 +1,122 @@
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::commands::{IndexArgs, Session};
+use crate::prelude::*;
+use crate::resolve::{Blueprint, Brief};
+
+// A minimal C API, for tools (editor plugins etc.) that want to resolve and set up
+// envs without spawning the CLI every time. Everything goes in and out as JSON:
+//
+//   char *posy_resolve(const char *brief_json);
+//       Brief in, Blueprint out.
+//   char *posy_env_vars(const char *blueprint_json);
+//       Installs the Blueprint (if it isn't already), and returns the environment
+//       variables for running things inside it, as a JSON object.
+//   void posy_free_string(char *s);
+//
+// The returned strings are always either {"ok": ...} or {"error": "..."}, and have to
+// be freed with posy_free_string. Each call is independent, and uses the same caches
+// and project config (found from the current directory) as the CLI does.
+
+fn resolve(brief_json: &str) -> Result<serde_json::Value> {
+    let brief: Brief = serde_json::from_str(brief_json)?;
+    let session = Session::new(IndexArgs::default())?;
+    let db = session.package_db()?;
+    let platforms = PybiPlatform::native_platforms()?;
+    let blueprint = brief.resolve(&db, platforms, None, &[])?;
+    Ok(serde_json::to_value(blueprint)?)
+}
+
+fn env_vars(blueprint_json: &str) -> Result<serde_json::Value> {
+    let blueprint: Blueprint = serde_json::from_str(blueprint_json)?;
+    let session = Session::new(IndexArgs::default())?;
+    let db = session.package_db()?;
+    let platforms = PybiPlatform::native_platforms()?;
+    let env = session
+        .env_forest
+        .get_env(&db, &blueprint, platforms, &[])?;
+    let mut vars = serde_json::Map::new();
+    for (key, value) in env.env_vars()? {
+        let value = value
+            .into_string()
+            .map_err(|value| eyre!("{key} isn't valid unicode: {value:?}"))?;
+        vars.insert(key.into(), value.into());
+    }
+    Ok(vars.into())
+}
+
+// Runs f on the input string, and packs up whatever happens as a JSON string for the
+// caller to free later. Panics must not unwind into the caller's code.
+unsafe fn call_json(
+    input: *const c_char,
+    f: fn(&str) -> Result<serde_json::Value>,
+) -> *mut c_char {
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        if input.is_null() {
+            bail!("got a NULL pointer instead of a string");
+        }
+        f(CStr::from_ptr(input).to_str()?)
+    }));
+    let response = match result {
+        Ok(Ok(value)) => serde_json::json!({ "ok": value }),
+        Ok(Err(err)) => serde_json::json!({ "error": format!("{err:#}") }),
+        Err(_) => serde_json::json!({ "error": "internal error: posy panicked" }),
+    };
+    // unwrap is safe b/c serde_json escapes any NULs inside strings
+    CString::new(response.to_string()).unwrap().into_raw()
+}
+
+/// # Safety
+///
+/// `brief_json` must be NULL or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn posy_resolve(brief_json: *const c_char) -> *mut c_char {
+    call_json(brief_json, resolve)
+}
+
+/// # Safety
+///
+/// `blueprint_json` must be NULL or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn posy_env_vars(blueprint_json: *const c_char) -> *mut c_char {
+    call_json(blueprint_json, env_vars)
+}
+
+/// # Safety
+///
+/// `s` must be NULL, or a string returned by one of the functions above that hasn't
+/// been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn posy_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    unsafe fn response(s: *mut c_char) -> serde_json::Value {
+        let value = serde_json::from_str(CStr::from_ptr(s).to_str().unwrap()).unwrap();
+        posy_free_string(s);
+        value
+    }
+
+    #[test]
+    fn test_ffi_errors() {
+        let not_json = CString::new("not json").unwrap();
+        unsafe {
+            let got = response(posy_resolve(not_json.as_ptr()));
+            assert!(got["error"].is_string());
+            assert!(got.get("ok").is_none());
+
+            let got = response(posy_env_vars(std::ptr::null()));
+            assert!(got["error"].as_str().unwrap().contains("NULL"));
+
+            // freeing NULL is a no-op
+            posy_free_string(std::ptr::null_mut());
+        }
+    }
+}
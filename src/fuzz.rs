@@ -0,0 +1,140 @@
+use crate::prelude::*;
+
+// The checks behind the cargo-fuzz targets in fuzz/. They live here, rather than in
+// the targets themselves, so that `cargo test` can run them over the checked-in corpus
+// (fuzz/corpus/<target>/) without needing nightly or libfuzzer.
+//
+// Everything in here is fed bytes that came from an index, a lockfile, or a wheel --
+// i.e., from strangers. Errors are fine. Panics, overflows, and blowing the stack are
+// not. And if we manage to parse something, then writing it back out has to give us
+// something we can parse again, since that's what ends up in lockfiles.
+
+// Display and then re-parse, and make sure that second trip is stable.
+fn check_roundtrip<T>(value: &T)
+where
+    T: Display + for<'a> TryFrom<&'a str, Error = eyre::Report>,
+{
+    let written = value.to_string();
+    match T::try_from(written.as_str()) {
+        Ok(again) => assert_eq!(again.to_string(), written),
+        Err(err) => panic!("couldn't re-parse {written:?}: {err:#}"),
+    }
+}
+
+fn as_str(data: &[u8]) -> Option<&str> {
+    std::str::from_utf8(data).ok()
+}
+
+pub fn artifact_name(data: &[u8]) {
+    if let Some(s) = as_str(data) {
+        if let Ok(name) = ArtifactName::try_from(s) {
+            check_roundtrip(&name);
+        }
+        if let Ok(name) = WheelName::try_from(s) {
+            check_roundtrip(&name);
+            let _ = name.all_tags();
+        }
+        if let Ok(name) = PybiName::try_from(s) {
+            check_roundtrip(&name);
+        }
+        if let Ok(name) = SdistName::try_from(s) {
+            check_roundtrip(&name);
+        }
+    }
+}
+
+pub fn requirement(data: &[u8]) {
+    static ENV: Lazy<HashMap<&str, &str>> = Lazy::new(|| {
+        HashMap::from([
+            ("python_version", "3.11"),
+            ("python_full_version", "3.11.4"),
+            ("sys_platform", "linux"),
+            ("platform_machine", "x86_64"),
+            ("implementation_name", "cpython"),
+            ("extra", "test"),
+        ])
+    });
+    if let Some(s) = as_str(data) {
+        if let Ok(req) = PackageRequirement::try_from(s) {
+            check_roundtrip(&req);
+            check_specifiers(&req.specifiers);
+            if let Some(expr) = &req.env_marker_expr {
+                let _ = expr.eval(&*ENV);
+            }
+        }
+        if let Ok(req) = UserRequirement::try_from(s) {
+            check_roundtrip(&req);
+        }
+        if let Ok(expr) = StandaloneMarkerExpr::try_from(s) {
+            check_roundtrip(&expr);
+            let _ = expr.0.eval(&*ENV);
+        }
+    }
+}
+
+fn check_specifiers(specifiers: &Specifiers) {
+    static PROBE: Lazy<Version> = Lazy::new(|| "1.0".try_into().unwrap());
+    for specifier in &specifiers.0 {
+        if let Ok(ranges) = specifier.to_ranges() {
+            for range in ranges {
+                let _ = range.contains(&*PROBE);
+            }
+        }
+    }
+    let _ = specifiers.satisfied_by(&PROBE);
+}
+
+pub fn specifiers(data: &[u8]) {
+    if let Some(s) = as_str(data) {
+        if let Ok(specifiers) = Specifiers::try_from(s) {
+            check_roundtrip(&specifiers);
+            check_specifiers(&specifiers);
+        }
+    }
+}
+
+pub fn core_metadata(data: &[u8]) {
+    if let Ok(metadata) = WheelCoreMetadata::try_from(data) {
+        for req in &metadata.requires_dist {
+            check_roundtrip(req);
+        }
+        check_specifiers(&metadata.requires_python);
+    }
+    let _ = PybiCoreMetadata::try_from(data);
+}
+
+pub fn nice_path(data: &[u8]) {
+    if let Ok(path) = NicePathBuf::try_from(data) {
+        let again = NicePathBuf::try_from(path.to_string().as_str()).unwrap();
+        assert_eq!(again, path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_fuzz_corpus() {
+        let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus");
+        let targets: &[(&str, fn(&[u8]))] = &[
+            ("artifact_name", artifact_name),
+            ("requirement", requirement),
+            ("specifiers", specifiers),
+            ("core_metadata", core_metadata),
+            ("nice_path", nice_path),
+        ];
+        for (name, check) in targets {
+            let mut seen = 0;
+            for entry in std::fs::read_dir(corpus.join(name)).unwrap() {
+                let path = entry.unwrap().path();
+                let data = std::fs::read(&path).unwrap();
+                let result = std::panic::catch_unwind(|| check(&data));
+                assert!(result.is_ok(), "{} failed", path.display());
+                seen += 1;
+            }
+            assert!(seen > 0, "empty corpus for {name}");
+        }
+    }
+}
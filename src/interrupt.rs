@@ -0,0 +1,98 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::prelude::*;
+
+// Ctrl-C handling. Without a handler, Ctrl-C kills us on the spot. That's safe enough
+// -- store entries get filled in inside a temp dir and only renamed into place once
+// they're complete -- but it can take a while to land if we're in the middle of
+// something slow, and it leaves the half-finished temp dirs lying around until the
+// next gc.
+//
+// So instead, the first Ctrl-C just sets a flag. Anything slow and loopy (like
+// unpacking a multi-GB wheel) calls check() every so often, which turns it into a
+// PosyError::Interrupted that unwinds through the normal error paths, dropping (and
+// so deleting) temp dirs as it goes. A second Ctrl-C exits immediately, in case we're
+// stuck somewhere that never checks.
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+// Set once we're just waiting for a child process that shares our console; see
+// leave_to_child.
+static CHILD_OWNS_CONSOLE: AtomicBool = AtomicBool::new(false);
+
+// what shells use for "killed by SIGINT"
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+pub fn install_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if CHILD_OWNS_CONSOLE.load(Ordering::SeqCst) {
+            return;
+        }
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            crate::process_tree::kill_all();
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        }
+        eprintln!("Interrupted; cleaning up (press Ctrl-C again to quit immediately)");
+    })?;
+    Ok(())
+}
+
+/// Stop reacting to Ctrl-C, because from now on we're just waiting for a child
+/// process. It gets the Ctrl-C too, and gets to decide what it means; if it exits, we
+/// exit with its status. (Only matters where we can't exec, i.e. Windows.)
+pub fn leave_to_child() {
+    CHILD_OWNS_CONSOLE.store(true, Ordering::SeqCst);
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Fails with PosyError::Interrupted if the user has hit Ctrl-C.
+pub fn check() -> Result<()> {
+    if interrupted() {
+        Err(PosyError::Interrupted)?;
+    }
+    Ok(())
+}
+
+/// Lets whoever's driving a PackageDB (e.g. an app embedding posy, on another thread)
+/// cancel whatever it's doing: resolves, builds, downloads. It fails with
+/// PosyError::Interrupted at the next check, same as Ctrl-C -- which also trips every
+/// token, once install_handler has been called. Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst) || interrupted()
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(PosyError::Interrupted)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cancellation_token() {
+        let token = CancellationToken::default();
+        let clone = token.clone();
+        assert!(clone.check().is_ok());
+        token.cancel();
+        assert!(clone.is_cancelled());
+        let err = clone.check().unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(PosyError::Interrupted)));
+        assert!(!CancellationToken::default().is_cancelled());
+    }
+}
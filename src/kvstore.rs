@@ -1,13 +1,14 @@
 use crate::prelude::*;
-use crate::util::retry_interrupted;
+use crate::util::{retry_interrupted, tree_size};
 use auto_impl::auto_impl;
 use fs2::FileExt;
 use ring::digest;
 use std::fs::{self, File};
 use std::io::SeekFrom;
 use std::marker::PhantomData;
-use std::ops::Deref;
+use std::ops::{AddAssign, Deref};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
 // A simple on-disk key-value store for static blobs of data. Each key maps to a
 // different path on disk. Used for stuff like caches, holding a forest of unpacked
@@ -72,21 +73,19 @@ use std::path::{Path, PathBuf};
 // avoiding dogpiling (where multiple independent instances of this program waste energy
 // on computing+writing the same entry at the same time).
 //
-// In the future, I want to add some kind of GC support, for pruning caches and clearing
-// out old no-longer-used wheels. This will require more complex locking strategies,
-// though, so leaving that an XX TODO for now.
-
-// thoughts on adding GC:
-// - when accessing a key should update the mtime on the lock file; that's an easy way
-//   to keep track of what's most recently used for cache cleanup
-// - for cleaning things up... can scan everything and for old files, take the
-//   lock and then delete the payload? but then how do we clean up the lockfile and
-//   directories themselves? I guess we don't have to but accumulating an unbounded
-//   collection of empty inodes seems a bit rude.
-//   maybe better: have a global lockfile at the root of the cache, which we normally
-//   acquire in shared mode. use its mtime to track when the last time GC ran was.
-//   opportunistically try to acquire it in exclusive mode; if succeed can run GC.
-//   otherwise acquire in shared mode and let someone else worry about GC later.
+// GC (pruning caches and clearing out old no-longer-used wheels):
+// - Taking a key's lock touches the lock file, so its mtime tells us when the entry was
+//   last used.
+// - Each store has a store-wide lockfile at its root, which we hold in shared mode for
+//   as long as the store is open. gc() tries to switch that to exclusive mode; if it
+//   succeeds, then no-one else has the store open, so we can delete entries, lock
+//   files, and empty directories without worrying about anyone waiting on a lock
+//   file we're about to delete. If it fails, we just skip GC and let someone else
+//   worry about it later.
+// - This doesn't protect anyone who's using a KVDirStore entry *without* having the
+//   store open -- e.g. a python process running in an env after posy exec'ed it. But
+//   those entries were touched when the env was set up, so they're only at risk if
+//   they've been running for longer than max_age.
 
 // Some filesystems don't cope well with a single directory containing lots of files. So
 // we disperse our files over multiple nested directories. This is the nesting depth, so
@@ -154,17 +153,372 @@ fn lock(path: &Path, mode: LockMode) -> Result<File> {
             // will error out.
         }
     };
-    let lock = open_options.open(&lock_path)?;
+    let mut lock = open_options.open(&lock_path)?;
     // fs2::FileExit::lock_exclusive on Unix is a thin wrapper around flock(2), and in
     // particular doesn't handle EINTR.
     retry_interrupted(|| lock.lock_exclusive())?;
+    // Bump the mtime, so GC knows this entry is still in use. (Setting mtimes directly
+    // needs newer Rust than we support, but writing a byte does the trick.) Truncating
+    // first means the file stays one byte long, however many times it's locked.
+    lock.set_len(0)?;
+    lock.write_all(b"\n")?;
     Ok(lock)
 }
 
+const STORE_LOCK_NAME: &str = "store.lock";
+
+// The store-wide lock; see the comment at the top of the file.
+#[derive(Debug)]
+struct StoreLock(File);
+
+impl StoreLock {
+    fn new(base: &Path) -> Result<StoreLock> {
+        let f = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(base.join(STORE_LOCK_NAME))?;
+        retry_interrupted(|| f.lock_shared())?;
+        Ok(StoreLock(f))
+    }
+
+    // Runs f with the store locked exclusively, or returns None if someone else has
+    // the store open.
+    fn with_exclusive<T, F>(&self, f: F) -> Result<Option<T>>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        // There's no portable way to upgrade a lock in place (flock can, but Windows
+        // can't), so give up our shared lock first.
+        self.0.unlock()?;
+        let got_it = match self.0.try_lock_exclusive() {
+            Ok(()) => true,
+            Err(err) if err.kind() == fs2::lock_contended_error().kind() => false,
+            Err(err) => {
+                retry_interrupted(|| self.0.lock_shared())?;
+                return Err(err.into());
+            }
+        };
+        let result = if got_it {
+            let result = f();
+            self.0.unlock()?;
+            Some(result)
+        } else {
+            None
+        };
+        retry_interrupted(|| self.0.lock_shared())?;
+        result.transpose()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GcStats {
+    pub removed: usize,
+    pub kept: usize,
+    // stores that we couldn't GC because another process had them open
+    pub skipped_stores: usize,
+}
+
+impl AddAssign for GcStats {
+    fn add_assign(&mut self, other: GcStats) {
+        self.removed += other.removed;
+        self.kept += other.kept;
+        self.skipped_stores += other.skipped_stores;
+    }
+}
+
+// Both kinds of store have the same layout: every entry is a "NAME.lock" file next to
+// a "NAME" file or directory (or nothing, if it was locked but never written), inside
+// some number of nesting directories. Anything else is a nesting directory.
+struct Entry {
+    lock: PathBuf,
+    payload: PathBuf,
+    last_used: SystemTime,
+}
+
+// Other processes might be adding or removing entries while we look, so anything that
+// vanishes under us just doesn't count.
+fn list_entries(dir: &Path, skip: &[&Path], entries: &mut Vec<Entry>) -> Result<()> {
+    let mut locks = Vec::new();
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if skip.contains(&path.as_path()) {
+            continue;
+        }
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            // we never create non-UTF-8 names, so this isn't ours
+            Err(_) => continue,
+        };
+        if let Some(entry_name) = name.strip_suffix(".lock") {
+            locks.push((path, entry_name.to_string()));
+        } else if entry.file_type()?.is_dir() {
+            dirs.push((name, path));
+        }
+    }
+    for (name, path) in dirs {
+        // entry payloads are directories too, but we don't look inside those
+        if !locks.iter().any(|(_, entry_name)| *entry_name == name) {
+            list_entries(&path, &[], entries)?;
+        }
+    }
+    for (lock, entry_name) in locks {
+        let last_used = match fs::metadata(&lock) {
+            Ok(metadata) => metadata.modified()?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+        entries.push(Entry {
+            lock,
+            payload: dir.join(entry_name),
+            last_used,
+        });
+    }
+    Ok(())
+}
+
+// Nesting directories with nothing left in them
+fn remove_empty_dirs(dir: &Path, skip: &[&Path]) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if skip.contains(&path.as_path()) || !path.is_dir() {
+            continue;
+        }
+        let mut lock_name = path.file_name().unwrap().to_os_string();
+        lock_name.push(".lock");
+        if dir.join(lock_name).exists() {
+            continue;
+        }
+        remove_empty_dirs(&path, &[])?;
+        if fs::read_dir(&path)?.next().is_none() {
+            fs::remove_dir(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Which entries `prune` removes from a store. The default removes nothing.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Prune {
+    /// Entries that haven't been used since this.
+    pub unused_since: Option<SystemTime>,
+    /// Then the least recently used entries, until what's left takes up at most this
+    /// many bytes. (So Some(0) empties the store.)
+    pub max_bytes: Option<u64>,
+}
+
+fn prune_store(
+    base: &Path,
+    tmp: &Path,
+    store_lock: &StoreLock,
+    prune: &Prune,
+) -> Result<GcStats> {
+    context!("Cleaning up {}", base.display());
+    let stats = store_lock.with_exclusive(|| {
+        // nothing can be in the middle of writing, so anything in tmp is left over
+        // from a crash
+        clear_tmp(tmp, SystemTime::now())?;
+        let store_lock_path = base.join(STORE_LOCK_NAME);
+        let skip = [tmp, &store_lock_path];
+        let mut entries = Vec::new();
+        list_entries(base, &skip, &mut entries)?;
+        // least recently used first
+        entries.sort_by_key(|entry| entry.last_used);
+
+        let mut doomed = 0;
+        if let Some(cutoff) = prune.unused_since {
+            doomed = entries.partition_point(|entry| entry.last_used < cutoff);
+        }
+        if let Some(max_bytes) = prune.max_bytes {
+            let sizes = entries[doomed..]
+                .iter()
+                .map(|entry| tree_size(&entry.payload))
+                .collect::<Result<Vec<_>>>()?;
+            let mut total: u64 = sizes.iter().sum();
+            for size in sizes {
+                if total <= max_bytes {
+                    break;
+                }
+                total -= size;
+                doomed += 1;
+            }
+        }
+
+        for entry in &entries[..doomed] {
+            trace!("removing {}", entry.payload.display());
+            remove_payload(&entry.payload)?;
+            fs::remove_file(&entry.lock)?;
+        }
+        remove_empty_dirs(base, &skip)?;
+        Ok(GcStats {
+            removed: doomed,
+            kept: entries.len() - doomed,
+            skipped_stores: 0,
+        })
+    })?;
+    Ok(stats.unwrap_or(GcStats {
+        skipped_stores: 1,
+        ..Default::default()
+    }))
+}
+
+// Writes go through tmp, and if we crash (or get killed) partway through, whatever we
+// were writing stays there forever. So whenever we open a store and nobody else has it
+// open, we clear out the leftovers.
+//
+// When nobody else has the store open, nothing in tmp should be live, but we leave
+// recent entries alone anyway, in case the locks aren't working -- e.g. a cache on a
+// network filesystem that ignores them, shared with another machine.
+const TMP_SWEEP_AGE: Duration = Duration::from_secs(60 * 60);
+
+// Removes entries in tmp that were last modified before `cutoff`, and returns how many.
+fn clear_tmp(tmp: &Path, cutoff: SystemTime) -> Result<usize> {
+    let mut removed = 0;
+    for entry in fs::read_dir(tmp)? {
+        let entry = entry?;
+        let modified = match entry.metadata() {
+            Ok(metadata) => metadata.modified()?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+        if modified < cutoff {
+            remove_payload(&entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+fn sweep_tmp(tmp: &Path, store_lock: &StoreLock, cutoff: SystemTime) -> Result<()> {
+    context!("Cleaning up {}", tmp.display());
+    if let Some(removed) = store_lock.with_exclusive(|| clear_tmp(tmp, cutoff))? {
+        if removed > 0 {
+            debug!(
+                "removed {removed} leftover temporary files from {}",
+                tmp.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn gc_store(
+    base: &Path,
+    tmp: &Path,
+    store_lock: &StoreLock,
+    cutoff: SystemTime,
+) -> Result<GcStats> {
+    let prune = Prune {
+        unused_since: Some(cutoff),
+        max_bytes: None,
+    };
+    prune_store(base, tmp, store_lock, &prune)
+}
+
+// For 'posy cache info': we sort entries by how many days ago they were last used,
+// into the first of these that they fit under, or else an extra "older" bucket.
+pub const LAST_USED_DAYS: [u64; 3] = [1, 7, 30];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StoreStats {
+    pub entries: usize,
+    // Everything in the entries' payloads, not counting lock files or nesting
+    // directories. Hardlinks count in full wherever they are, so e.g. an env store and
+    // the shared wheel store it links to both count the same files.
+    pub bytes: u64,
+    pub last_used: [usize; LAST_USED_DAYS.len() + 1],
+}
+
+impl AddAssign for StoreStats {
+    fn add_assign(&mut self, other: StoreStats) {
+        self.entries += other.entries;
+        self.bytes += other.bytes;
+        for (ours, theirs) in self.last_used.iter_mut().zip(other.last_used) {
+            *ours += theirs;
+        }
+    }
+}
+
+fn store_stats(base: &Path, tmp: &Path) -> Result<StoreStats> {
+    context!("Measuring {}", base.display());
+    let store_lock_path = base.join(STORE_LOCK_NAME);
+    let mut entries = Vec::new();
+    list_entries(base, &[tmp, &store_lock_path], &mut entries)?;
+    let now = SystemTime::now();
+    let mut stats = StoreStats::default();
+    for entry in entries {
+        let age = now.duration_since(entry.last_used).unwrap_or_default();
+        let days = age.as_secs() / 86400;
+        let bucket = LAST_USED_DAYS
+            .iter()
+            .position(|limit| days < *limit)
+            .unwrap_or(LAST_USED_DAYS.len());
+        stats.entries += 1;
+        stats.last_used[bucket] += 1;
+        stats.bytes += tree_size(&entry.payload)?;
+    }
+    Ok(stats)
+}
+
+fn remove_payload(path: &Path) -> Result<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(err) => Err(err),
+    };
+    match result {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Deletes `path`, which is a KVDirStore entry or something inside one (see
+/// KVDirStore::import), while holding its lock -- so at least we never delete it while
+/// someone's in the middle of looking it up or filling it in.
+pub fn remove_dir_entry(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let _lock = lock(path, LockMode::Lock)?;
+    remove_payload(path)
+}
+
+pub fn gc_cutoff(max_age: Duration) -> SystemTime {
+    SystemTime::now()
+        .checked_sub(max_age)
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+// So callers can treat all the different stores in the cache the same way, e.g. to
+// report on them or clean them.
+#[derive(Debug, Clone, Copy)]
+pub enum AnyStore<'a> {
+    File(&'a KVFileStore),
+    Dir(&'a KVDirStore),
+}
+
+impl AnyStore<'_> {
+    pub fn stats(&self) -> Result<StoreStats> {
+        match self {
+            AnyStore::File(store) => store.stats(),
+            AnyStore::Dir(store) => store.stats(),
+        }
+    }
+
+    pub fn prune(&self, prune: &Prune) -> Result<GcStats> {
+        match self {
+            AnyStore::File(store) => store.prune(prune),
+            AnyStore::Dir(store) => store.prune(prune),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct KVFileStore {
     base: PathBuf,
     tmp: PathBuf,
+    store_lock: StoreLock,
 }
 
 impl KVFileStore {
@@ -173,12 +527,37 @@ impl KVFileStore {
         let tmp = base.join("tmp");
         fs::create_dir_all(&base)?;
         fs::create_dir_all(&tmp)?;
+        let store_lock = StoreLock::new(&base)?;
+        sweep_tmp(&tmp, &store_lock, gc_cutoff(TMP_SWEEP_AGE))?;
         Ok(KVFileStore {
+            store_lock,
             base,
             tmp,
         })
     }
 
+    /// Deletes entries that haven't been used in `max_age`. Does nothing if another
+    /// process has the store open.
+    pub fn gc(&self, max_age: Duration) -> Result<GcStats> {
+        gc_store(&self.base, &self.tmp, &self.store_lock, gc_cutoff(max_age))
+    }
+
+    /// Deletes entries according to `prune`. Does nothing if another process has the
+    /// store open.
+    pub fn prune(&self, prune: &Prune) -> Result<GcStats> {
+        prune_store(&self.base, &self.tmp, &self.store_lock, prune)
+    }
+
+    pub fn stats(&self) -> Result<StoreStats> {
+        store_stats(&self.base, &self.tmp)
+    }
+
+    /// For when you can't know the key until you've written the data (e.g. because
+    /// the key is its hash): write it here, then persist it to a locked entry's path.
+    pub fn tempfile(&self) -> Result<tempfile::NamedTempFile> {
+        Ok(tempfile::NamedTempFile::new_in(&self.tmp)?)
+    }
+
     pub fn get_or_set<K: PathKey, F>(
         &self,
         key: &K,
@@ -240,6 +619,10 @@ pub struct KVFileLock {
 }
 
 impl KVFileLock {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
     pub fn reader<'a>(&self) -> Option<LockedRead<'a>> {
         Some(LockedRead {
             f: File::open(&self.path).ok()?,
@@ -326,9 +709,11 @@ impl<'a> LockedWrite<'a> {
 
 ////////////////////////////////////////////////////////////////
 
+#[derive(Debug)]
 pub struct KVDirStore {
     base: PathBuf,
     tmp: PathBuf,
+    store_lock: StoreLock,
 }
 
 impl KVDirStore {
@@ -337,12 +722,31 @@ impl KVDirStore {
         let tmp = base.join("tmp");
         fs::create_dir_all(&base)?;
         fs::create_dir_all(&tmp)?;
+        let store_lock = StoreLock::new(&base)?;
+        sweep_tmp(&tmp, &store_lock, gc_cutoff(TMP_SWEEP_AGE))?;
         Ok(KVDirStore {
+            store_lock,
             base,
             tmp,
         })
     }
 
+    /// Deletes entries that haven't been used in `max_age`. Does nothing if another
+    /// process has the store open.
+    pub fn gc(&self, max_age: Duration) -> Result<GcStats> {
+        gc_store(&self.base, &self.tmp, &self.store_lock, gc_cutoff(max_age))
+    }
+
+    /// Deletes entries according to `prune`. Does nothing if another process has the
+    /// store open.
+    pub fn prune(&self, prune: &Prune) -> Result<GcStats> {
+        prune_store(&self.base, &self.tmp, &self.store_lock, prune)
+    }
+
+    pub fn stats(&self) -> Result<StoreStats> {
+        store_stats(&self.base, &self.tmp)
+    }
+
     pub fn lock<K: PathKey>(&self, key: &K) -> Result<KVDirLock> {
         let path = self.base.join(key.key());
         let lock = lock(&path, LockMode::Lock)?;
@@ -366,6 +770,87 @@ impl KVDirStore {
         }
         Ok(lock.path)
     }
+
+    /// Like get_or_set, except that a new entry only gets as far as the store's tmp
+    /// dir. Nothing shows up in the store until you commit() it, so you can build
+    /// several entries, check them over, and then add them all -- or drop them all, and
+    /// leave the store the way it was.
+    ///
+    /// Unlike get_or_set, we don't hold the entry's lock while `f` runs (holding
+    /// several at once could deadlock with another process doing the same), so two
+    /// processes can end up building the same entry. Whoever commits second throws
+    /// theirs away.
+    pub fn stage<K, F>(&self, key: &K, f: F) -> Result<StagedDir>
+    where
+        K: PathKey,
+        F: FnOnce(&Path) -> Result<()>,
+    {
+        let lock = self.lock(key)?;
+        if lock.exists() {
+            return Ok(StagedDir::existing(lock.path));
+        }
+        let tmp = lock.tempdir()?;
+        let path = lock.path.clone();
+        drop(lock);
+        f(tmp.path())?;
+        Ok(StagedDir {
+            entry: path.clone(),
+            dest: path,
+            tmp: Some(tmp),
+        })
+    }
+
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    pub fn tempdir(&self) -> Result<tempfile::TempDir> {
+        Ok(tempfile::tempdir_in(&self.tmp)?)
+    }
+
+    /// Where `path`, which is an entry or something inside one, lives relative to the
+    /// store. The same path in another store holds the same thing, so this plus
+    /// import() lets you copy entries between stores without knowing their keys.
+    pub fn relative<'p>(&self, path: &'p Path) -> Option<&'p Path> {
+        path.strip_prefix(&self.base).ok()
+    }
+
+    /// Moves the directory `src` into the store at `rel` (from relative()), unless
+    /// there's something there already. Returns whether we moved it.
+    pub fn import(&self, rel: &Path, src: &Path) -> Result<bool> {
+        if !rel
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)))
+        {
+            bail!("{} isn't a path inside the store", rel.display());
+        }
+        // For a whole entry, this is the entry's own lock. For something inside one
+        // (like a wheel we built from an sdist), it's a lock of its own, which is
+        // enough to keep two imports from colliding; whoever fills the entry in
+        // normally doesn't care about other things showing up next to theirs.
+        let dest = self.base.join(rel);
+        let _lock = lock(&dest, LockMode::Lock)?;
+        if dest.exists() {
+            return Ok(false);
+        }
+        fs::rename(src, &dest)?;
+        Ok(true)
+    }
+
+    // Like KVFileStore::lock_if_exists. NB the lock can exist without the directory,
+    // if whoever took it never finished filling it in.
+    pub fn lock_if_exists<K: PathKey>(&self, key: &K) -> Option<KVDirLock> {
+        let path = self.base.join(key.key());
+        if let Ok(lock) = lock(&path, LockMode::IfExists) {
+            Some(KVDirLock {
+                tmp: self.tmp.clone(),
+                _lock: lock,
+                path,
+            })
+        } else {
+            None
+        }
+    }
 }
 
 pub struct KVDirLock {
@@ -378,6 +863,73 @@ impl KVDirLock {
     pub fn tempdir(&self) -> Result<tempfile::TempDir> {
         Ok(tempfile::tempdir_in(&self.tmp)?)
     }
+
+    /// Stages `tmp` (from tempdir()) to be renamed to `dest`, which is inside this
+    /// entry, when it's committed. For entries that hold several things.
+    pub fn stage(&self, tmp: tempfile::TempDir, dest: PathBuf) -> StagedDir {
+        StagedDir {
+            entry: self.path.clone(),
+            dest,
+            tmp: Some(tmp),
+        }
+    }
+
+    /// Deletes whatever's in the entry, e.g. because it turned out to be broken.
+    pub fn remove(&self) -> Result<()> {
+        remove_payload(&self.path)
+    }
+}
+
+/// A directory that's either already in a KVDirStore, or waiting in its tmp dir to be
+/// committed; see KVDirStore::stage. Dropping it without committing throws it away.
+#[derive(Debug)]
+pub struct StagedDir {
+    // the entry we have to lock to move things into it
+    entry: PathBuf,
+    dest: PathBuf,
+    tmp: Option<tempfile::TempDir>,
+}
+
+impl StagedDir {
+    pub fn existing(path: PathBuf) -> StagedDir {
+        StagedDir {
+            entry: path.clone(),
+            dest: path,
+            tmp: None,
+        }
+    }
+
+    pub fn is_new(&self) -> bool {
+        self.tmp.is_some()
+    }
+
+    /// Where the contents are right now.
+    pub fn path(&self) -> &Path {
+        match &self.tmp {
+            Some(tmp) => tmp.path(),
+            None => &self.dest,
+        }
+    }
+
+    /// Where the contents will be once they're committed.
+    pub fn dest(&self) -> &Path {
+        &self.dest
+    }
+
+    /// Moves the contents into place. Returns where they ended up, and whether it was
+    /// us who put them there (as opposed to them already being there).
+    pub fn commit(self) -> Result<(PathBuf, bool)> {
+        let tmp = match self.tmp {
+            Some(tmp) => tmp,
+            None => return Ok((self.dest, false)),
+        };
+        let _lock = lock(&self.entry, LockMode::Lock)?;
+        if self.dest.exists() {
+            return Ok((self.dest, false));
+        }
+        fs::rename(tmp.into_path(), &self.dest)?;
+        Ok((self.dest, true))
+    }
 }
 
 impl Deref for KVDirLock {
@@ -516,4 +1068,223 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_kvdirstore_stage() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let store = KVDirStore::new(tmp.path())?;
+        let write = |contents: &'static str| {
+            move |t: &Path| -> Result<()> {
+                fs::write(t.join("file"), contents)?;
+                Ok(())
+            }
+        };
+
+        // dropped without committing -> never happened
+        let a = b"a".as_slice();
+        let staged = store.stage(&a, write("dropped"))?;
+        assert!(staged.is_new());
+        assert_eq!(fs::read(staged.path().join("file"))?, b"dropped");
+        assert!(!staged.dest().exists());
+        drop(staged);
+        assert_eq!(fs::read_dir(&store.tmp)?.count(), 0);
+
+        let first = store.stage(&a, write("first"))?;
+        let second = store.stage(&a, write("second"))?;
+        let (path, created) = first.commit()?;
+        assert!(created);
+        assert_eq!(fs::read(path.join("file"))?, b"first");
+        // someone beat us to it, so ours goes away
+        let (path, created) = second.commit()?;
+        assert!(!created);
+        assert_eq!(fs::read(path.join("file"))?, b"first");
+        assert_eq!(fs::read_dir(&store.tmp)?.count(), 0);
+
+        let again = store.stage(&a, write("again"))?;
+        assert!(!again.is_new());
+        assert_eq!(again.path(), path);
+        assert_eq!(again.commit()?, (path.clone(), false));
+
+        store.lock(&a)?.remove()?;
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_kvdirstore_import() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let one = KVDirStore::new(&tmp.path().join("one"))?;
+        let two = KVDirStore::new(&tmp.path().join("two"))?;
+        let key = b"key".as_slice();
+        let path =
+            one.get_or_set(&key, |t| Ok(fs::write(t.join("file"), b"hello")?))?;
+        let rel = one.relative(&path).unwrap();
+        assert!(one.relative(tmp.path()).is_none());
+
+        let src = two.tempdir()?;
+        fs::write(src.path().join("file"), b"hello")?;
+        assert!(two.import(rel, src.path())?);
+        assert!(!src.path().exists());
+        assert_eq!(fs::read(two.lock(&key)?.join("file"))?, b"hello");
+
+        // already there
+        let src = two.tempdir()?;
+        assert!(!two.import(rel, src.path())?);
+        assert!(src.path().exists());
+
+        assert!(two.import(Path::new("../escape"), src.path()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let store = KVDirStore::new(tmp.path())?;
+        for key in [b"a".as_slice(), b"b".as_slice()] {
+            store.get_or_set(&key, |t| {
+                fs::write(t.join("file"), b"hello")?;
+                Ok(())
+            })?;
+        }
+        // something left behind by a crashed write
+        fs::write(store.tmp.join("junk"), b"junk")?;
+
+        let far_past = SystemTime::UNIX_EPOCH;
+        let far_future = SystemTime::now() + Duration::from_secs(3600);
+
+        // while someone else has the store open, we leave it alone
+        {
+            let _other = KVDirStore::new(tmp.path())?;
+            let stats =
+                gc_store(&store.base, &store.tmp, &store.store_lock, far_future)?;
+            assert_eq!(stats.skipped_stores, 1);
+            assert_eq!(stats.removed, 0);
+        }
+
+        let stats = gc_store(&store.base, &store.tmp, &store.store_lock, far_past)?;
+        assert_eq!(stats.removed, 0);
+        assert_eq!(stats.kept, 2);
+        assert!(!store.tmp.join("junk").exists());
+
+        let stats = gc_store(&store.base, &store.tmp, &store.store_lock, far_future)?;
+        assert_eq!(stats.removed, 2);
+        // and all the nesting directories are gone too
+        let mut left = fs::read_dir(tmp.path())?
+            .map(|e| Ok(e?.file_name().into_string().unwrap()))
+            .collect::<Result<Vec<_>>>()?;
+        left.sort();
+        assert_eq!(left, vec![STORE_LOCK_NAME, "tmp"]);
+
+        // and the store still works afterwards
+        let path = store.get_or_set(&b"a".as_slice(), |t| {
+            fs::write(t.join("file"), b"again")?;
+            Ok(())
+        })?;
+        assert_eq!(fs::read(path.join("file"))?, b"again");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sweep_tmp() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let store = KVFileStore::new(tmp.path())?;
+        fs::write(store.tmp.join("junk"), b"junk")?;
+        fs::create_dir(store.tmp.join("junkdir"))?;
+        fs::write(store.tmp.join("junkdir").join("file"), b"junk")?;
+
+        let far_past = SystemTime::UNIX_EPOCH;
+        let far_future = SystemTime::now() + Duration::from_secs(3600);
+
+        // too new
+        sweep_tmp(&store.tmp, &store.store_lock, far_past)?;
+        assert_eq!(fs::read_dir(&store.tmp)?.count(), 2);
+
+        // someone else has the store open, so it might be theirs
+        {
+            let _other = KVFileStore::new(tmp.path())?;
+            sweep_tmp(&store.tmp, &store.store_lock, far_future)?;
+            assert_eq!(fs::read_dir(&store.tmp)?.count(), 2);
+        }
+
+        sweep_tmp(&store.tmp, &store.store_lock, far_future)?;
+        assert_eq!(fs::read_dir(&store.tmp)?.count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let store = KVFileStore::new(tmp.path())?;
+        for key in [b"a".as_slice(), b"b".as_slice(), b"c".as_slice()] {
+            store.get_or_set(&key, |w| {
+                w.write_all(b"12345")?;
+                Ok(())
+            })?;
+            // make sure the mtimes are distinct
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        // using "a" again makes "b" the least recently used
+        store.get_or_set(&b"a".as_slice(), |_| panic!("should be cached"))?;
+        // (which touches its lock file without making it any bigger)
+        let mut lock_path = store.lock(&b"a".as_slice())?.path().as_os_str().to_owned();
+        lock_path.push(".lock");
+        assert_eq!(fs::metadata(&lock_path)?.len(), 1);
+
+        let stats = store.prune(&Prune::default())?;
+        assert_eq!((stats.removed, stats.kept), (0, 3));
+
+        let stats = store.prune(&Prune {
+            unused_since: None,
+            max_bytes: Some(12),
+        })?;
+        assert_eq!((stats.removed, stats.kept), (1, 2));
+        assert!(store.lock_if_exists(&b"b".as_slice()).is_none());
+        assert!(store.lock_if_exists(&b"a".as_slice()).is_some());
+
+        let stats = store.prune(&Prune {
+            unused_since: None,
+            max_bytes: Some(0),
+        })?;
+        assert_eq!((stats.removed, stats.kept), (2, 0));
+        assert_eq!(store.stats()?, StoreStats::default());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let store = KVDirStore::new(tmp.path())?;
+        assert_eq!(store.stats()?, StoreStats::default());
+        store.get_or_set(&b"a".as_slice(), |t| {
+            fs::create_dir(t.join("sub"))?;
+            fs::write(t.join("sub").join("file"), b"hello")?;
+            fs::write(t.join("other"), b"hi")?;
+            Ok(())
+        })?;
+        // locked but never written
+        drop(store.lock(&b"b".as_slice())?);
+        // leftovers in tmp don't count
+        fs::write(store.tmp.join("junk"), b"junk")?;
+
+        let stats = store.stats()?;
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.bytes, 7);
+        assert_eq!(stats.last_used, [2, 0, 0, 0]);
+
+        let tmp2 = tempfile::tempdir()?;
+        let files = KVFileStore::new(tmp2.path())?;
+        files.get_or_set(&b"x".as_slice(), |w| {
+            w.write_all(b"12345")?;
+            Ok(())
+        })?;
+        let mut total = stats;
+        total += files.stats()?;
+        assert_eq!(total.entries, 3);
+        assert_eq!(total.bytes, 12);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,60 @@
+#![allow(
+    clippy::declare_interior_mutable_const,
+    clippy::borrow_interior_mutable_const,
+    clippy::len_without_is_empty,
+    clippy::module_inception,
+    clippy::result_large_err,
+    clippy::type_complexity,
+    clippy::upper_case_acronyms,
+    clippy::wrong_self_convention
+)]
+// posy is mostly used as a command-line tool (see main.rs), but it's also a library,
+// and ffi.rs exposes a small C API on top of that.
+pub mod blob_store;
+pub mod blueprint_diff;
+pub mod bundle;
+pub mod commands;
+pub mod config_edit;
+pub mod dep_report;
+pub mod dep_tree;
+pub mod ffi;
+// Only for the fuzz targets (fuzz/Cargo.toml turns on the feature) and their corpus test
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzz;
+pub mod kvstore;
+pub mod package_db;
+pub mod prelude;
+pub mod resolve;
+pub mod resolve_report;
+pub mod util;
+pub mod vocab;
+
+pub mod env;
+pub mod error;
+pub mod events;
+pub mod interrupt;
+pub mod output;
+pub mod platform_tags;
+pub mod poetry;
+pub mod process_tree;
+pub mod project;
+pub mod seek_slice;
+pub mod stats;
+#[cfg(test)]
+mod test_util;
+pub mod trampolines;
+pub mod transcript;
+pub mod tree;
+pub mod workspace;
+
+// The main entry points, for tools that want to drive posy directly: describe what
+// you want with a Brief, resolve it against a PackageDB to get a Blueprint, and then
+// have an EnvForest turn that into an Env you can run things in. main.rs's demo() goes
+// through the whole thing. The rest of the modules are public too, but these are the
+// parts we try not to break.
+pub use env::{Env, EnvForest};
+pub use error::PosyError;
+pub use package_db::PackageDB;
+pub use platform_tags::{Platform, PybiPlatform, WheelPlatform};
+pub use resolve::{AllowPre, Blueprint, Brief};
+pub use vocab::{PackageName, PythonRequirement, UserRequirement, Version};
@@ -1,58 +1,94 @@
-#![allow(clippy::declare_interior_mutable_const, clippy::borrow_interior_mutable_const, clippy::module_inception, clippy::result_large_err, clippy::type_complexity, clippy::upper_case_acronyms, clippy::wrong_self_convention)]
-mod kvstore;
-mod package_db;
-mod prelude;
-mod resolve;
-mod util;
-mod vocab;
-
-mod env;
-pub mod error;
-mod output;
-mod platform_tags;
-mod seek_slice;
-#[cfg(test)]
-mod test_util;
-mod trampolines;
-mod tree;
-
-use std::path::Path;
-
-use crate::{env::EnvForest, prelude::*, resolve::Brief};
+use posy::commands::{self, Command, IndexArgs, Session};
+use posy::events;
+use posy::interrupt;
+use posy::output;
+use posy::prelude::*;
+use posy::resolve::{AllowPre, Brief};
+use posy::resolve_report::NoSolution;
 
 use clap::Parser;
-use kvstore::KVDirStore;
-use resolve::AllowPre;
 
 #[derive(Parser)]
 #[command(author, version, about)]
 struct Cli {
     #[command(flatten)]
     output_args: output::OutputArgs,
+    #[command(flatten)]
+    index_args: IndexArgs,
+    /// On an Apple silicon Mac, make x86_64 environments that run under Rosetta 2,
+    /// instead of native arm64 ones.
+    #[arg(long, global = true)]
+    rosetta: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
-    output::init(&cli.output_args);
+    output::init(&cli.output_args)?;
+    if let Some(path) = &cli.output_args.event_log {
+        let file = std::fs::File::create(path)
+            .wrap_err_with(|| format!("couldn't create {}", path.display()))?;
+        events::subscribe(std::sync::Arc::new(events::JsonLines::new(file)));
+    }
+    interrupt::install_handler()?;
+    if cli.rosetta {
+        posy::platform_tags::use_rosetta();
+    }
 
-    let env_forest = EnvForest::new(Path::new("posy-test-forest"))?;
-    let build_tmp = tempfile::TempDir::new()?;
-    let build_store = KVDirStore::new(build_tmp.path())?;
+    let session = Session::new(cli.index_args)?;
+    let name = cli
+        .command
+        .as_ref()
+        .map_or("demo", |command| command.name());
+    let result = match cli.command {
+        Some(command) => command.run(&session),
+        None => demo(&session),
+    };
+    // even (especially) if the command failed, so 'posy rollback' can clean up after it
+    if let Err(err) = session.save_transcript(name) {
+        warn!("couldn't save transcript: {err:#}");
+    }
+    if let Some(summary) = output::stats_summary(&posy::stats::take()) {
+        info!("{summary}");
+    }
+    let warnings = output::take_warnings();
+    if let Some(summary) = output::warning_summary(&warnings) {
+        info!("Finished with {summary}");
+    }
+    let denied = warnings
+        .iter()
+        .filter(|warning| cli.output_args.deny_warnings.contains(&warning.class))
+        .collect::<Vec<_>>();
+    let result = result.and_then(|()| match denied.first() {
+        Some(first) => bail!(
+            "{} warning(s) matched --deny-warnings, starting with: {}",
+            denied.len(),
+            first.message
+        ),
+        None => Ok(()),
+    });
+    let result = result.map_err(|err| session.explain_error(err));
+    if let Err(err) = &result {
+        if let Some(PosyError::Interrupted) = err.downcast_ref() {
+            // the user knows; no need for an error message. But drop the session
+            // first, so its temp dirs get cleaned up.
+            drop(session);
+            std::process::exit(interrupt::INTERRUPTED_EXIT_CODE);
+        }
+        if cli.output_args.error_format == output::ErrorFormat::Json {
+            if let Some(no_solution) = err.downcast_ref::<NoSolution>() {
+                println!("{}", serde_json::to_string_pretty(no_solution)?);
+            }
+        }
+    }
+    result
+}
 
-    let db = package_db::PackageDB::new(
-        &[Url::parse("https://pybi.vorpus.org")?,
-            Url::parse("https://pypi.org/simple/")?],
-        PROJECT_DIRS.cache_dir(),
-        // PackageDB needs a place to install packages, in case it has to build some
-        // sdists. Using a shared env_forest is efficient, because it means different
-        // builds can share the same package installs.
-        &env_forest,
-        // This is the temporary directory we use for sdist builds. It's also a
-        // content-addressed store, so if we want to build the same package twice (e.g.
-        // first to get metadata, and then to get a wheel), we can re-use the same build
-        // directory.
-        &build_store,
-    )?;
+// What we do when run without a subcommand, at least until we have a real UI.
+fn demo(session: &Session) -> Result<()> {
+    let env_forest = &session.env_forest;
+    let db = session.package_db()?;
     // We can resolve and install for arbitrary platforms. But for this demo we'll just
     // use the platform of the machine we're running on. Or platforms, in case it
     // supports several (e.g. macOS arm64+x86_64, Windows 32bit+64bit, Linux
@@ -77,6 +113,13 @@ fn main() -> Result<()> {
             "peewee".try_into().unwrap(),
         ],
         allow_pre: AllowPre::Some(HashSet::new()),
+        strategy: Default::default(),
+        keep_pinned_prereleases: session.project_kind().keep_pinned_prereleases(),
+        constraints: vec![],
+        local_requirements: vec![],
+        no_source_builds: false,
+        python_flags: Vec::new(),
+        python_fallbacks: vec![],
     };
     // A "blueprint" is a set of fully-resolved package pins describing an environment,
     // like a lock-file.
@@ -84,28 +127,8 @@ fn main() -> Result<()> {
 
     // And an "env" of course is an installed environment.
     let env = env_forest.get_env(&db, &blueprint, platforms, &[])?;
+    commands::print_install_summary(&env);
+    session.save_transcript("demo")?;
 
-    let mut cmd = std::process::Command::new("python");
-    // env.env_vars() gives us the magic environment variables needed to run a command
-    // in our new environment.
-    cmd.envs(env.env_vars()?);
-
-    #[cfg(unix)]
-    {
-        use std::os::unix::process::CommandExt;
-        Err(cmd.exec())?;
-        unreachable!();
-    }
-    #[cfg(windows)]
-    {
-        // XX FIXME: factor out the windows trampoline code and reuse it here.
-        //
-        // unwrap() is safe b/c this branch only runs on windows, and Windows doesn't
-        // have special exit statuses; that's a special thing for Unix signals.
-        std::process::exit(cmd.status()?.code().unwrap());
-    }
-    #[cfg(not(any(unix, windows)))]
-    {
-        not_supported
-    }
+    commands::exec_in_env(&env, std::process::Command::new("python"))
 }
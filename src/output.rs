@@ -1,16 +1,18 @@
 use crate::prelude::*;
 use std::fmt::Debug;
+use std::sync::Mutex;
 
 use console::{Emoji, Style, StyledObject};
 use tracing::{
     field::{Field, Visit},
     metadata::LevelFilter,
     span::Attributes,
-    Event, Id, Level, Subscriber,
+    subscriber::Interest,
+    Event, Id, Level, Metadata, Subscriber,
 };
 use tracing_subscriber::{
     filter::{EnvFilter, Targets},
-    layer::{Context, Layer},
+    layer::{Context, Filter, Layer},
     prelude::*,
     registry::{LookupSpan, SpanRef},
 };
@@ -24,6 +26,155 @@ enum ColorChoice {
     Never,
 }
 
+/// How to write log messages to the terminal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Human,
+    /// One JSON object per line, with the level, module, fields, and context.
+    Json,
+}
+
+/// How to report errors that we know how to describe in detail (currently just
+/// resolver failures).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ErrorFormat {
+    Human,
+    /// Also print them to stdout as JSON, for tools.
+    Json,
+}
+
+/// The kinds of warning we keep track of, so that we can sum them up at the end and CI
+/// can fail on the ones it cares about (see --deny-warnings). To classify a warning,
+/// give it a class field, e.g. `warn!(class = "yanked", ...)`; anything without one
+/// counts as Other.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ValueEnum, Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum WarningClass {
+    /// A file we installed has no hash to check it against.
+    UnhashedArtifact,
+    /// The best file for this platform isn't in the lock file.
+    UnlockedArtifact,
+    /// We used (or refused to use) a file that was yanked.
+    Yanked,
+    /// We couldn't resolve with the requested Python, and fell back to another.
+    PythonFallback,
+    /// Two packages install the same file.
+    FileConflict,
+    /// The environment has packages in it that posy didn't install.
+    ForeignPackage,
+    /// Something we installed earlier was incomplete or damaged, so we redid it.
+    DamagedInstall,
+    /// We translated a Poetry-style version constraint (like "^1.2") into PEP 440.
+    PoetryConstraint,
+    /// A wheel we built had the wrong tags.
+    WheelTags,
+    /// The environment is bigger than the project's size-budget.
+    SizeBudget,
+    Other,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Warning {
+    pub class: WarningClass,
+    pub message: String,
+}
+
+static WARNINGS: Lazy<Mutex<Vec<Warning>>> = Lazy::new(Default::default);
+
+/// Every warning we've shown since the last call, in order.
+pub fn take_warnings() -> Vec<Warning> {
+    std::mem::take(&mut *WARNINGS.lock().unwrap())
+}
+
+/// How many warnings we've collected so far. Save this before doing something, and
+/// then `warnings_since` tells you which warnings it caused -- e.g. so a Blueprint or
+/// Env can carry the warnings from making it. (Anything another thread warns about
+/// in the meantime gets counted too, but we don't do much of that.)
+pub fn warnings_mark() -> usize {
+    WARNINGS.lock().unwrap().len()
+}
+
+pub fn warnings_since(mark: usize) -> Vec<Warning> {
+    let warnings = WARNINGS.lock().unwrap();
+    // (if someone took them in between, they're gone)
+    warnings.get(mark..).unwrap_or_default().to_vec()
+}
+
+/// E.g. "3 warnings (2 unhashed-artifact, 1 other)", or None if there weren't any.
+pub fn warning_summary(warnings: &[Warning]) -> Option<String> {
+    if warnings.is_empty() {
+        return None;
+    }
+    let mut counts = std::collections::BTreeMap::<WarningClass, usize>::new();
+    for warning in warnings {
+        *counts.entry(warning.class).or_default() += 1;
+    }
+    let counts = counts
+        .iter()
+        .map(|(class, count)| {
+            // unwrap is safe b/c none of our values are skipped
+            format!("{count} {}", class.to_possible_value().unwrap().get_name())
+        })
+        .collect::<Vec<_>>();
+    let plural = if warnings.len() == 1 { "" } else { "s" };
+    Some(format!(
+        "{} warning{plural} ({})",
+        warnings.len(),
+        counts.join(", ")
+    ))
+}
+
+/// E.g. "40 metadata lookups (38 from cache), 1.2 MiB downloaded, 1 wheel built from
+/// source; resolve 2.1s, build 14.0s, install 0.8s". None if we didn't do anything
+/// worth mentioning. (Lookups, not packages: the resolver can look at several versions
+/// of the same package, and builds resolve environments of their own.)
+pub fn stats_summary(stats: &crate::stats::Stats) -> Option<String> {
+    let plural = |n: u64| if n == 1 { "" } else { "s" };
+    let mut counts = Vec::new();
+    if stats.metadata_lookups > 0 {
+        counts.push(format!(
+            "{} metadata lookup{} ({} from cache)",
+            stats.metadata_lookups,
+            plural(stats.metadata_lookups),
+            stats.metadata_cached
+        ));
+    }
+    if stats.bytes_downloaded > 0 {
+        counts.push(format!("{} downloaded", human_size(stats.bytes_downloaded)));
+    }
+    if stats.wheels_built > 0 {
+        counts.push(format!(
+            "{} wheel{} built from source",
+            stats.wheels_built,
+            plural(stats.wheels_built)
+        ));
+    }
+    // a warm 'posy sync' still technically installs, but there's nothing to say
+    if counts.is_empty()
+        && stats
+            .phases
+            .iter()
+            .all(|(_, time)| *time < std::time::Duration::from_secs(1))
+    {
+        return None;
+    }
+    let mut summary = counts.join(", ");
+    if !stats.phases.is_empty() {
+        let phases = stats
+            .phases
+            .iter()
+            .map(|(name, time)| format!("{name} {:.1}s", time.as_secs_f64()))
+            .collect::<Vec<_>>();
+        if !summary.is_empty() {
+            summary.push_str("; ");
+        }
+        summary.push_str(&phases.join(", "));
+    }
+    Some(summary)
+}
+
 #[derive(Args)]
 pub struct OutputArgs {
     /// Increase verbosity. (Can be repeated.)
@@ -32,8 +183,279 @@ pub struct OutputArgs {
     /// Reduce verbosity. (Can be repeated.)
     #[arg(short, long, action = clap::ArgAction::Count, global = true)]
     quiet: u8,
+    /// Log levels for particular modules, on top of -v/-q, e.g.
+    /// 'resolve=trace,http=info'. A bare level applies to everything else.
+    #[arg(long, value_name = "FILTER", value_parser = parse_log_filter, global = true)]
+    log_filter: Option<LogFilter>,
+    #[arg(long, default_value_t = LogFormat::Human, value_enum, value_name = "FORMAT", global = true)]
+    log_format: LogFormat,
+    /// Also write every log message, at every level, to FILE as JSON lines.
+    #[arg(long, value_name = "FILE", global = true)]
+    log_file: Option<std::path::PathBuf>,
     #[arg(long, default_value_t = ColorChoice::Auto, value_enum, value_name = "WHEN", global = true)]
     color: ColorChoice,
+    #[arg(long, default_value_t = ErrorFormat::Human, value_enum, value_name = "FORMAT", global = true)]
+    pub error_format: ErrorFormat,
+    /// Exit with an error if there were any warnings of this CLASS, e.g.
+    /// 'unhashed-artifact'. (Can be repeated.)
+    #[arg(long, value_enum, value_name = "CLASS", global = true)]
+    pub deny_warnings: Vec<WarningClass>,
+    /// Also write progress events to FILE, one JSON object per line, for tools.
+    #[arg(long, value_name = "FILE", global = true)]
+    pub event_log: Option<std::path::PathBuf>,
+}
+
+// The CLI's view of crate::events: the ones people want to watch go by are info!, the
+// rest only show up with -v. Each one gets logged under the target of the module that
+// sends it, so --log-filter works on them the same as on that module's own messages.
+// (tracing needs targets to be constants, hence spelling them out.)
+struct CliEvents;
+
+impl crate::events::Subscriber for CliEvents {
+    fn event(&self, event: &crate::events::Event) {
+        use crate::events::Event::*;
+        match event {
+            ResolveStarted {
+                python,
+                requirements,
+                for_build,
+            } => match for_build {
+                Some(package) => debug!(
+                    target: "posy::resolve",
+                    "Resolving build requirements for {package} with {python}"
+                ),
+                None => debug!(
+                    target: "posy::resolve",
+                    "Resolving {requirements} requirements with {python}"
+                ),
+            },
+            ResolveFinished {
+                python, packages, ..
+            } => debug!(
+                target: "posy::resolve",
+                "Resolved {packages} packages with {python}"
+            ),
+            FetchingIndexPage { url } => {
+                debug!(target: "posy::package_db::simple_api::fetch", "Fetching {url}")
+            }
+            FetchingArtifact { url, .. } => {
+                debug!(target: "posy::package_db::package_db", "Fetching {url}")
+            }
+            BuildingWheel { package, goal } => info!(
+                target: "posy::package_db::build_wheel",
+                "Building {goal} for {package}"
+            ),
+            Unpacking {
+                artifact,
+                entries,
+                total_entries,
+                bytes,
+            } => {
+                let total = match total_entries {
+                    Some(total) => format!("/{total}"),
+                    None => "".into(),
+                };
+                info!(
+                    target: "posy::tree",
+                    "Unpacking {artifact}: {entries}{total} files, {} MiB so far",
+                    bytes >> 20
+                );
+            }
+            Installing {
+                package,
+                version,
+                source,
+                ..
+            } => debug!(
+                target: "posy::env",
+                "Installing {package} {version} ({source})"
+            ),
+        }
+    }
+}
+
+/// Per-module log levels, from --log-filter. Each module can be any part of a module
+/// path inside posy, so 'http' covers posy::package_db::http and everything under it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LogFilter {
+    default: Option<LevelFilter>,
+    modules: Vec<(Vec<String>, LevelFilter)>,
+}
+
+pub fn parse_log_filter(text: &str) -> std::result::Result<LogFilter, String> {
+    let mut filter = LogFilter::default();
+    for directive in text.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        let (module, level) = match directive.split_once('=') {
+            Some((module, level)) => (Some(module.trim()), level.trim()),
+            None => (None, directive),
+        };
+        let level = level
+            .parse::<LevelFilter>()
+            .map_err(|_| format!("{level:?} isn't a log level"))?;
+        match module {
+            Some(module) => {
+                let path = module.split("::").map(String::from).collect::<Vec<_>>();
+                if path.iter().any(|part| {
+                    part.is_empty()
+                        || !part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                }) {
+                    return Err(format!("{module:?} isn't a module name"));
+                }
+                filter.modules.push((path, level));
+            }
+            None => filter.default = Some(level),
+        }
+    }
+    Ok(filter)
+}
+
+impl LogFilter {
+    // The directive that matches deepest into `target` wins (the later one, if
+    // there's a tie), then the bare level, then `default`.
+    fn level_for(&self, target: &str, default: LevelFilter) -> LevelFilter {
+        let target = target.split("::").collect::<Vec<_>>();
+        let mut best = None;
+        for (path, level) in &self.modules {
+            let depth = target
+                .windows(path.len())
+                .position(|window| window == path.as_slice())
+                .map(|start| start + path.len());
+            if let Some(depth) = depth {
+                if best.map_or(true, |(best_depth, _)| depth >= best_depth) {
+                    best = Some((depth, *level));
+                }
+            }
+        }
+        match best {
+            Some((_, level)) => level,
+            None => self.default.unwrap_or(default),
+        }
+    }
+}
+
+fn is_posy_target(target: &str) -> bool {
+    target == "posy" || target.starts_with("posy::")
+}
+
+// What goes to the terminal: posy's messages, at `level` except where `log_filter` says
+// otherwise. Directives can match any part of a module path, which Targets can't
+// express, but the answer only depends on the callsite, so tracing only asks us once
+// per callsite.
+struct UiFilter {
+    level: LevelFilter,
+    log_filter: LogFilter,
+}
+
+impl UiFilter {
+    fn allows(&self, metadata: &Metadata<'_>) -> bool {
+        is_posy_target(metadata.target())
+            && *metadata.level()
+                <= self.log_filter.level_for(metadata.target(), self.level)
+    }
+}
+
+impl<S> Filter<S> for UiFilter {
+    fn enabled(&self, metadata: &Metadata<'_>, _: &Context<'_, S>) -> bool {
+        self.allows(metadata)
+    }
+
+    fn callsite_enabled(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if self.allows(metadata) {
+            Interest::always()
+        } else {
+            Interest::never()
+        }
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        let directives = self.log_filter.modules.iter().map(|(_, level)| *level);
+        let default = self.log_filter.default.unwrap_or(self.level);
+        directives.chain([default]).max()
+    }
+}
+
+fn ui_filter(level: LevelFilter, log_filter: LogFilter) -> UiFilter {
+    UiFilter { level, log_filter }
+}
+
+// Renders every context!(...) span's message into a String, and stashes it in the
+// tracing_subscriber registry entry for the span, for current_context and
+// collect_context to find later.
+struct ContextRecorder;
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for ContextRecorder {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("span should already exist!");
+        if span.metadata().target() == POSY_CONTEXT_TARGET {
+            attrs.record(&mut WithMessage(&|msg| {
+                let as_string = MessageAsString(format!("{:?}", msg));
+                span.extensions_mut().insert(as_string);
+            }));
+        }
+    }
+}
+
+// For --log-format json and --log-file.
+struct JsonLayer<W> {
+    out: Mutex<W>,
+}
+
+impl<W> JsonLayer<W> {
+    fn new(out: W) -> JsonLayer<W> {
+        JsonLayer {
+            out: Mutex::new(out),
+        }
+    }
+}
+
+#[derive(Default)]
+struct JsonFields(serde_json::Map<String, serde_json::Value>);
+
+impl Visit for JsonFields {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .insert(field.name().into(), format!("{:?}", value).into());
+    }
+}
+
+impl<S, W> Layer<S> for JsonLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: std::io::Write + Send + 'static,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = JsonFields::default();
+        event.record(&mut fields);
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0.0, |since| since.as_secs_f64());
+        let record = serde_json::json!({
+            "timestamp": timestamp,
+            "level": event.metadata().level().as_str(),
+            "target": event.metadata().target(),
+            "fields": fields.0,
+            "context": collect_context(ctx.event_span(event)),
+        });
+        // nowhere to report it if this fails, so we just keep going
+        let mut out = self.out.lock().unwrap();
+        let _ = writeln!(out, "{record}").and_then(|()| out.flush());
+    }
 }
 
 struct PosyUILayer;
@@ -55,6 +477,47 @@ where
 
 struct MessageAsString(String);
 
+// Keeps track of every warning, no matter how quiet the UI is, for take_warnings.
+struct WarningCollector;
+
+#[derive(Default)]
+struct WarningFields {
+    class: Option<String>,
+    message: String,
+}
+
+impl Visit for WarningFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "class" {
+            self.class = Some(value.into());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for WarningCollector {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if *event.metadata().level() != Level::WARN {
+            return;
+        }
+        let mut fields = WarningFields::default();
+        event.record(&mut fields);
+        let class = fields
+            .class
+            .and_then(|class| <WarningClass as ValueEnum>::from_str(&class, false).ok())
+            .unwrap_or(WarningClass::Other);
+        WARNINGS.lock().unwrap().push(Warning {
+            class,
+            message: fields.message,
+        });
+    }
+}
+
 const WARNING: Lazy<StyledObject<Emoji<'static, 'static>>> = Lazy::new(|| {
     Style::new()
         .yellow()
@@ -71,6 +534,8 @@ const ERROR: Lazy<StyledObject<Emoji<'static, 'static>>> = Lazy::new(|| {
         .apply_to(Emoji("🛑  Error:", "Error:"))
 });
 
+const DIM: Lazy<Style> = Lazy::new(|| Style::new().dim().for_stderr());
+
 fn collect_context<S>(leaf: Option<SpanRef<S>>) -> Vec<String>
 where
     S: Subscriber + for<'a> LookupSpan<'a>,
@@ -106,18 +571,6 @@ pub fn current_context() -> Vec<String> {
 }
 
 impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for PosyUILayer {
-    /// For every context!(...) span, render the message into a String and stash it
-    /// inside the tracing_subscriber registry entry for this Span.
-    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
-        let span = ctx.span(id).expect("span should already exist!");
-        if span.metadata().target() == POSY_CONTEXT_TARGET {
-            attrs.record(&mut WithMessage(&|msg| {
-                let as_string = MessageAsString(format!("{:?}", msg));
-                span.extensions_mut().insert(as_string);
-            }));
-        }
-    }
-
     fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
         // let leaf = ctx.event_span(&event);
         // for span_render in collect_context(leaf) {
@@ -126,7 +579,8 @@ impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for PosyUILayer {
         event.record(&mut WithMessage(&|msg| match *event.metadata().level() {
             Level::ERROR => eprintln!("{} {:?}", &*ERROR, msg),
             Level::WARN => eprintln!("{} {:?}", &*WARNING, msg),
-            _ => eprintln!("{:?}", msg),
+            Level::INFO => eprintln!("{:?}", msg),
+            _ => eprintln!("{}", DIM.apply_to(format!("{:?}", msg))),
         }));
     }
 }
@@ -171,7 +625,7 @@ impl eyre::EyreHandler for PosyEyreHandler {
     }
 }
 
-pub fn init(args: &OutputArgs) {
+pub fn init(args: &OutputArgs) -> Result<()> {
     eyre::set_hook(Box::new(|_| Box::new(PosyEyreHandler::new())))
         .expect("eyre handler already installed?");
 
@@ -182,22 +636,61 @@ pub fn init(args: &OutputArgs) {
         .saturating_sub(args.quiet.try_into().unwrap_or(i8::MAX));
 
     let global_level = match verbosity {
-        2.. => Level::DEBUG,
-        1 => Level::TRACE,
-        0 => Level::INFO,
-        -1 => Level::WARN,
+        2.. => LevelFilter::DEBUG,
+        1 => LevelFilter::TRACE,
+        0 => LevelFilter::INFO,
+        -1 => LevelFilter::WARN,
         // https://github.com/rust-lang/rust/issues/67264
-        i8::MIN..=-2 => Level::ERROR,
+        i8::MIN..=-2 => LevelFilter::ERROR,
+    };
+    let log_filter = args.log_filter.clone().unwrap_or_default();
+    let (ui, json_ui) = match args.log_format {
+        LogFormat::Human => (
+            Some(PosyUILayer.with_filter(ui_filter(global_level, log_filter))),
+            None,
+        ),
+        LogFormat::Json => (
+            None,
+            Some(
+                JsonLayer::new(std::io::stderr())
+                    .with_filter(ui_filter(global_level, log_filter)),
+            ),
+        ),
+    };
+    let log_file = match &args.log_file {
+        Some(path) => {
+            let file = std::fs::File::create(path)
+                .wrap_err_with(|| format!("couldn't create {}", path.display()))?;
+            Some(
+                JsonLayer::new(file)
+                    .with_filter(Targets::new().with_target("posy", Level::TRACE)),
+            )
+        }
+        None => None,
     };
 
     match args.color {
-        ColorChoice::Auto => (),
+        // https://no-color.org/
+        ColorChoice::Auto => {
+            if std::env::var_os("NO_COLOR").map_or(false, |v| !v.is_empty()) {
+                console::set_colors_enabled(false);
+                console::set_colors_enabled_stderr(false);
+            }
+        }
         ColorChoice::Always => console::set_colors_enabled_stderr(true),
         ColorChoice::Never => console::set_colors_enabled_stderr(false),
     }
 
+    let context_filter = Targets::new().with_target(POSY_CONTEXT_TARGET, Level::ERROR);
     let s = tracing_subscriber::registry()
-        .with(PosyUILayer.with_filter(Targets::new().with_target("posy", global_level)))
+        .with(ContextRecorder.with_filter(context_filter))
+        .with(ui)
+        .with(json_ui)
+        .with(log_file)
+        .with(
+            WarningCollector
+                .with_filter(Targets::new().with_target("posy", Level::WARN)),
+        )
         .with(
             tracing_subscriber::fmt::layer().with_filter(
                 EnvFilter::builder()
@@ -207,4 +700,346 @@ pub fn init(args: &OutputArgs) {
             ),
         );
     s.init();
+    crate::events::subscribe(std::sync::Arc::new(CliEvents));
+    Ok(())
+}
+
+/// The width of the terminal we're writing UI to, or None if it's not a terminal (in
+/// which case there's no reason to truncate anything).
+pub fn terminal_width() -> Option<usize> {
+    console::Term::stderr()
+        .size_checked()
+        .map(|(_, cols)| cols as usize)
+}
+
+/// "1.5 MiB" and friends.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+// "500M", "2G", "1.5GiB", "1024": binary units, same as human_size.
+pub fn parse_size(text: &str) -> std::result::Result<u64, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("expected a size like '500M' or '2G', not {text:?}"))?;
+    let shift = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        "T" | "TB" | "TIB" => 40,
+        _ => return Err(format!("unknown size unit {unit:?}")),
+    };
+    Ok((number * (1u64 << shift) as f64) as u64)
+}
+
+/// A simple table, with columns sized to fit their contents.
+pub struct Table {
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+// Columns never get truncated below this, even if that means overflowing the terminal.
+const MIN_COLUMN_WIDTH: usize = 8;
+const COLUMN_GAP: &str = "  ";
+
+impl Table {
+    pub fn new<S: Into<String>>(header: impl IntoIterator<Item = S>) -> Table {
+        Table {
+            header: header.into_iter().map(|h| h.into()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn push_row<S: Into<String>>(&mut self, row: impl IntoIterator<Item = S>) {
+        self.rows.push(row.into_iter().map(|c| c.into()).collect());
+    }
+
+    /// Lays out the table as a list of lines, the first of which is the header. If
+    /// `max_width` is given, the widest columns get truncated until it fits.
+    pub fn render(&self, max_width: Option<usize>) -> Vec<String> {
+        let mut widths = vec![0; self.header.len()];
+        for row in std::iter::once(&self.header).chain(self.rows.iter()) {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(console::measure_text_width(cell));
+            }
+        }
+        if let Some(max_width) = max_width {
+            let gaps = COLUMN_GAP.len() * widths.len().saturating_sub(1);
+            while widths.iter().sum::<usize>() + gaps > max_width {
+                // unwrap is safe b/c a table with no columns has width 0
+                let widest = widths.iter_mut().max().unwrap();
+                if *widest <= MIN_COLUMN_WIDTH {
+                    break;
+                }
+                *widest -= 1;
+            }
+        }
+        std::iter::once(&self.header)
+            .chain(self.rows.iter())
+            .map(|row| {
+                let cells = row
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, width)| {
+                        // pad_str truncates cells that fit exactly, too
+                        let truncate = if console::measure_text_width(cell) > *width {
+                            Some("…")
+                        } else {
+                            None
+                        };
+                        console::pad_str(
+                            cell,
+                            *width,
+                            console::Alignment::Left,
+                            truncate,
+                        )
+                        .into_owned()
+                    })
+                    .collect::<Vec<_>>();
+                cells.join(COLUMN_GAP).trim_end().to_string()
+            })
+            .collect()
+    }
+
+    /// Writes the table to the UI, sized to fit the terminal.
+    pub fn print(&self) {
+        let mut lines = self.render(terminal_width()).into_iter();
+        if let Some(header) = lines.next() {
+            eprintln!("{}", Style::new().bold().for_stderr().apply_to(header));
+        }
+        for line in lines {
+            eprintln!("{line}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("500M"), Ok(500 * 1024 * 1024));
+        assert_eq!(parse_size("2G"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("1.5 KiB"), Ok(1536));
+        assert_eq!(parse_size("3kb"), Ok(3 * 1024));
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("12 parsecs").is_err());
+    }
+
+    #[test]
+    fn test_log_filter() {
+        let filter =
+            parse_log_filter("resolve=trace, http=warn,package_db=debug").unwrap();
+        let level = |target| filter.level_for(target, LevelFilter::INFO);
+        assert_eq!(level("posy::resolve"), LevelFilter::TRACE);
+        assert_eq!(
+            level("posy::package_db::http::ureq_glue"),
+            LevelFilter::WARN
+        );
+        assert_eq!(level("posy::package_db::build_wheel"), LevelFilter::DEBUG);
+        assert_eq!(level("posy::env"), LevelFilter::INFO);
+        // module names have to match a whole part of the path
+        assert_eq!(level("posy::resolve_report"), LevelFilter::INFO);
+
+        let filter = parse_log_filter("error,posy::env=debug").unwrap();
+        let level = |target| filter.level_for(target, LevelFilter::INFO);
+        assert_eq!(level("posy::env"), LevelFilter::DEBUG);
+        assert_eq!(level("posy::resolve"), LevelFilter::ERROR);
+
+        assert_eq!(parse_log_filter(""), Ok(LogFilter::default()));
+        assert!(parse_log_filter("resolve=loud").is_err());
+        assert!(parse_log_filter("=debug").is_err());
+        assert!(parse_log_filter("package_db::=debug").is_err());
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(std::sync::Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_layer() {
+        let buf = SharedBuf::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(ContextRecorder)
+            .with(JsonLayer::new(buf.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            context!("resolving {}", "trio");
+            warn!(class = "yanked", "trio 0.1 was yanked");
+        });
+        let written = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let lines = written.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1);
+        let record: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(record["level"], "WARN");
+        assert_eq!(record["target"], "posy::output::test");
+        assert_eq!(record["fields"]["message"], "trio 0.1 was yanked");
+        assert_eq!(record["fields"]["class"], "yanked");
+        assert_eq!(record["context"], serde_json::json!(["resolving trio"]));
+    }
+
+    #[test]
+    fn test_ui_filter() {
+        use crate::events::{Event, Subscriber};
+
+        let filter =
+            |text| ui_filter(LevelFilter::INFO, parse_log_filter(text).unwrap());
+        let hint = |text| {
+            Filter::<tracing_subscriber::Registry>::max_level_hint(&filter(text))
+        };
+        assert_eq!(hint(""), Some(LevelFilter::INFO));
+        assert_eq!(hint("resolve=debug"), Some(LevelFilter::DEBUG));
+        assert_eq!(hint("error"), Some(LevelFilter::ERROR));
+
+        let buf = SharedBuf::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(JsonLayer::new(buf.clone()).with_filter(filter("resolve=debug")));
+        tracing::subscriber::with_default(subscriber, || {
+            // events show up under the module that sent them
+            CliEvents.event(&Event::ResolveFinished {
+                python: "cpython 3.11.2".into(),
+                packages: 3,
+                for_build: None,
+            });
+            CliEvents.event(&Event::Installing {
+                package: "trio".into(),
+                version: "0.22.0".into(),
+                artifact: "trio-0.22.0-py3-none-any.whl".into(),
+                source: "cached".into(),
+            });
+            debug!("not from resolve");
+        });
+        let written = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let lines = written.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1);
+        let record: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(record["target"], "posy::resolve");
+        assert_eq!(
+            record["fields"]["message"],
+            "Resolved 3 packages with cpython 3.11.2"
+        );
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+
+    #[test]
+    fn test_stats_summary() {
+        use crate::stats::Stats;
+        use std::time::Duration;
+
+        assert_eq!(stats_summary(&Stats::default()), None);
+        let warm = Stats {
+            phases: vec![("install", Duration::from_millis(40))],
+            ..Default::default()
+        };
+        assert_eq!(stats_summary(&warm), None);
+        let stats = Stats {
+            metadata_lookups: 40,
+            metadata_cached: 38,
+            bytes_downloaded: 1536,
+            wheels_built: 1,
+            phases: vec![
+                ("resolve", Duration::from_millis(2100)),
+                ("build", Duration::from_secs(14)),
+            ],
+        };
+        assert_eq!(
+            stats_summary(&stats).unwrap(),
+            "40 metadata lookups (38 from cache), 1.5 KiB downloaded, 1 wheel built \
+             from source; resolve 2.1s, build 14.0s"
+        );
+    }
+
+    #[test]
+    fn test_table_render() {
+        let mut table = Table::new(["package", "version", "source"]);
+        table.push_row(["trio", "0.22.0", "downloaded"]);
+        table.push_row(["a-package-with-a-long-name", "1.0", "cached"]);
+
+        assert_eq!(
+            table.render(None),
+            vec![
+                "package                     version  source",
+                "trio                        0.22.0   downloaded",
+                "a-package-with-a-long-name  1.0      cached",
+            ]
+        );
+
+        // the widest column gets truncated first
+        assert_eq!(
+            table.render(Some(40)),
+            vec![
+                "package              version  source",
+                "trio                 0.22.0   downloaded",
+                "a-package-with-a-l…  1.0      cached",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_warnings() {
+        let subscriber = tracing_subscriber::registry().with(WarningCollector);
+        let since = tracing::subscriber::with_default(subscriber, || {
+            let name = "foo-1.0.whl";
+            warn!(class = "unhashed-artifact", "{name} has no hash");
+            let mark = warnings_mark();
+            warn!("something else");
+            warn!(class = "made-up", "unknown classes count as other");
+            info!("not a warning");
+            warnings_since(mark)
+        });
+        assert_eq!(
+            since.iter().map(|w| w.message.as_str()).collect::<Vec<_>>(),
+            ["something else", "unknown classes count as other"]
+        );
+        let warnings = take_warnings();
+        assert_eq!(
+            warnings[0],
+            Warning {
+                class: WarningClass::UnhashedArtifact,
+                message: "foo-1.0.whl has no hash".into(),
+            }
+        );
+        assert_eq!(
+            warning_summary(&warnings).unwrap(),
+            "3 warnings (1 unhashed-artifact, 2 other)"
+        );
+        assert!(take_warnings().is_empty());
+        assert_eq!(warning_summary(&[]), None);
+    }
 }
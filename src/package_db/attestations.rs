@@ -0,0 +1,235 @@
+use http::Request;
+
+use super::http::{CacheMode, Http};
+use super::ArtifactInfo;
+use crate::prelude::*;
+use crate::util::percent_decode;
+
+// PEP 740 lets an index publish "provenance" for each file: attestations, signed
+// through Sigstore by whoever uploaded it (usually a CI workflow using trusted
+// publishing), saying that they produced a file with this name and this sha256. The
+// simple API points to it with a data-provenance attribute (or "provenance" key in
+// JSON).
+//
+// What we do with them so far: check that every attestation the index gives us is
+// actually about the file we're using -- same filename, same sha256 that we pin and
+// check downloads against -- and record who the publisher was, so the lock file can
+// say where each file claims to come from.
+//
+// That's *not* verification. We don't check the Sigstore signatures or certificate
+// chains, or match the publisher against the signing certificate, so this catches an
+// index or mirror whose provenance doesn't match its files, but not a forged
+// attestation. Hence "record", and not "verify", in the policy names.
+//
+// XX TODO: actually verify them.
+
+/// Whether to read the PEP 740 attestations for the files we use. (We don't verify
+/// their signatures; see above.)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum AttestationPolicy {
+    /// Don't look at them.
+    #[default]
+    Ignore,
+    /// For files that have them, make sure they match the file, and record the
+    /// publisher they name.
+    Record,
+    /// Like 'record', but files without attestations are errors.
+    Require,
+}
+
+#[derive(Debug, Deserialize)]
+struct Provenance {
+    version: u32,
+    attestation_bundles: Vec<AttestationBundle>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AttestationBundle {
+    publisher: Publisher,
+    attestations: Vec<Attestation>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Publisher {
+    kind: String,
+    repository: Option<String>,
+    // GitHub calls it "workflow", GitLab "workflow_filepath"
+    #[serde(alias = "workflow_filepath")]
+    workflow: Option<String>,
+}
+
+impl Display for Publisher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(repository) = &self.repository {
+            write!(f, ": {repository}")?;
+        }
+        if let Some(workflow) = &self.workflow {
+            write!(f, " ({workflow})")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Attestation {
+    envelope: Envelope,
+}
+
+#[derive(Debug, Deserialize)]
+struct Envelope {
+    // base64-encoded in-toto statement
+    statement: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Statement {
+    subject: Vec<Subject>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Subject {
+    name: String,
+    digest: HashMap<String, String>,
+}
+
+// Returns a description of the publisher(s)
+fn check_provenance(
+    provenance: &Provenance,
+    filename: &str,
+    hash: &ArtifactHash,
+) -> Result<String> {
+    if provenance.version != 1 {
+        bail!("unsupported provenance version {}", provenance.version);
+    }
+    let mut publishers = Vec::new();
+    for bundle in &provenance.attestation_bundles {
+        for attestation in &bundle.attestations {
+            let statement = data_encoding::BASE64
+                .decode(attestation.envelope.statement.as_bytes())
+                .wrap_err("bad base64 in attestation statement")?;
+            let statement: Statement = serde_json::from_slice(&statement)?;
+            if statement.subject.len() != 1 {
+                bail!(
+                    "attestation should be about one file, not {}",
+                    statement.subject.len()
+                );
+            }
+            let subject = &statement.subject[0];
+            if subject.name != filename {
+                bail!("attestation is for {}, not {filename}", subject.name);
+            }
+            let attested = match subject.digest.get("sha256") {
+                Some(hex) => ArtifactHash::from_hex("sha256", hex)?,
+                None => bail!("attestation for {filename} has no sha256 digest"),
+            };
+            if &attested != hash {
+                bail!(
+                    "attestation for {filename} says its hash is {attested}, not \
+                     {hash}"
+                );
+            }
+        }
+        if !bundle.attestations.is_empty() {
+            publishers.push(bundle.publisher.to_string());
+        }
+    }
+    if publishers.is_empty() {
+        bail!("provenance for {filename} doesn't contain any attestations");
+    }
+    Ok(publishers.join(", "))
+}
+
+/// Checks `ai`'s attestations according to `policy`, and returns who published it, or
+/// None if we're ignoring them or (with 'record') the index doesn't have any.
+pub fn check_attestations(
+    http: &Http,
+    ai: &ArtifactInfo,
+    policy: AttestationPolicy,
+) -> Result<Option<String>> {
+    let url = match (&ai.provenance, policy) {
+        (_, AttestationPolicy::Ignore) => return Ok(None),
+        (Some(url), _) => url,
+        (None, AttestationPolicy::Record) => return Ok(None),
+        (None, AttestationPolicy::Require) => {
+            bail!("{} doesn't have any attestations", ai.url)
+        }
+    };
+    context!("Checking attestations for {} from {url}", ai.url);
+    let hash = ai.require_hash()?;
+    let filename = ai
+        .url
+        .path_segments()
+        .and_then(|mut s| s.next_back())
+        .map(percent_decode)
+        .ok_or_else(|| eyre!("no filename in URL"))??;
+    let request = Request::builder().uri(url.as_str()).body(())?;
+    let response = http.request(request, CacheMode::Default)?;
+    if response.status().as_u16() >= 400 {
+        bail!("error fetching {url}: {}", response.status().as_str());
+    }
+    let provenance: Provenance = serde_json::from_reader(response.into_body())?;
+    Ok(Some(check_provenance(&provenance, &filename, hash)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn provenance(name: &str, sha256: &str) -> Provenance {
+        let statement = serde_json::json!({
+            "_type": "https://in-toto.io/Statement/v1",
+            "subject": [{"name": name, "digest": {"sha256": sha256}}],
+            "predicateType": "https://docs.pypi.org/attestations/publish/v1",
+            "predicate": null,
+        });
+        serde_json::from_value(serde_json::json!({
+            "version": 1,
+            "attestation_bundles": [{
+                "publisher": {
+                    "kind": "GitHub",
+                    "repository": "example/foo",
+                    "workflow": "release.yml",
+                },
+                "attestations": [{
+                    "version": 1,
+                    "verification_material": {},
+                    "envelope": {
+                        "statement": data_encoding::BASE64
+                            .encode(statement.to_string().as_bytes()),
+                        "signature": "",
+                    },
+                }],
+            }],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_check_provenance() {
+        let ones = "1".repeat(64);
+        let hash = ArtifactHash::from_hex("sha256", &ones).unwrap();
+        let filename = "foo-1.0-py3-none-any.whl";
+        assert_eq!(
+            check_provenance(&provenance(filename, &ones), filename, &hash).unwrap(),
+            "GitHub: example/foo (release.yml)"
+        );
+        assert!(check_provenance(
+            &provenance("foo-1.0.tar.gz", &ones),
+            filename,
+            &hash
+        )
+        .is_err());
+        assert!(check_provenance(
+            &provenance(filename, &"2".repeat(64)),
+            filename,
+            &hash
+        )
+        .is_err());
+
+        let mut empty = provenance(filename, &ones);
+        empty.attestation_bundles[0].attestations.clear();
+        assert!(check_provenance(&empty, filename, &hash).is_err());
+    }
+}
@@ -1,15 +1,54 @@
-use std::{ffi::OsString, fs, io, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fs,
+    io::{self, BufRead},
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use crate::{
     env::Env,
+    interrupt::CancellationToken,
     kvstore::KVDirLock,
     package_db::PackageDB,
     prelude::*,
+    process_tree::ProcessTree,
     resolve::{AllowPre, Blueprint, Brief},
     tree::WriteTreeFS,
 };
 
-use super::ArtifactInfo;
+use super::{wheel_tags::check_wheel_tags, ArtifactInfo};
+
+/// The file in a locally built wheel's .dist-info directory that records how we built
+/// it.
+pub const BUILD_PROVENANCE_NAME: &str = "posy-build.json";
+
+/// Where a locally built wheel came from. We embed this into every wheel we build (see
+/// BUILD_PROVENANCE_NAME), so it stays traceable to its build inputs even after it's
+/// been copied out of the wheel cache.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BuildProvenance {
+    pub posy_version: String,
+    // the sdist's filename, or the path for builds from a local source tree
+    pub sdist: String,
+    // None for local source trees, which don't have a hash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sdist_hash: Option<ArtifactHash>,
+    // Blueprint::identity for the build environment
+    pub build_blueprint_hash: ArtifactHash,
+    pub build_backend: String,
+    // None if we couldn't figure out which package provides the backend
+    pub build_backend_version: Option<Version>,
+    pub build_python: String,
+    pub build_platform: String,
+    // seconds since the epoch
+    pub built_at: u64,
+    pub recorded_at: u64,
+}
 
 // Wheel build context lifecycle:
 //
@@ -51,6 +90,135 @@ pub struct WheelBuilder<'a> {
     build_stack: Vec<&'a PackageName>,
 }
 
+// What pep517() builds from.
+#[derive(Clone, Copy)]
+enum BuildSource<'s> {
+    Sdist(&'s ArtifactInfo),
+    Local(&'s PackageName, &'s LocalTree),
+}
+
+impl<'s> BuildSource<'s> {
+    fn name(&self) -> &'s PackageName {
+        match *self {
+            BuildSource::Sdist(sdist_ai) => sdist_ai.name.distribution(),
+            BuildSource::Local(name, _) => name,
+        }
+    }
+}
+
+const BUILD_FRONTEND_PY: &[u8] = include_bytes!("data-files/build-frontend.py");
+
+// Everything that pep517_step and build-frontend.py leave in a build directory, except
+// for saved-blueprint.json (which is only a hint, so it never goes stale). Includes the
+// files that older versions of build-frontend.py used instead of FRONTEND_RESULT.
+const BUILD_OUTPUTS: &[&str] = &[
+    "editable",
+    FRONTEND_RESULT,
+    "get_requires_for_build_wheel",
+    "prepare_metadata_for_build_wheel",
+    "prepare_metadata_for_build_wheel.out",
+    "build_wheel",
+    "build_wheel.out",
+    "build_wheel.binary_wheel_tag",
+    "local_wheel",
+    PIP_SHIM,
+];
+
+// Some old setup.py's shell out to pip, or import it, assuming that it's always there,
+// the way it is in a virtualenv. If PackageDB::build_pip_shim is set and a build dies
+// like that, we drop this file in the build directory and try again with
+// PIP_SHIM_REQUIREMENT added to the build requirements. It gets resolved like any other
+// build requirement, so it ends up in saved-blueprint.json, and from there in the
+// lockfile.
+const PIP_SHIM: &str = "pip-shim";
+// the last pip that still runs on Python 3.7
+const PIP_SHIM_REQUIREMENT: &str = "pip == 24.0";
+
+// Where build-frontend.py reports what it's done so far, and the version of the format
+// it uses. Has to match PROTOCOL in build-frontend.py.
+const FRONTEND_RESULT: &str = "frontend-result.json";
+const FRONTEND_PROTOCOL: u32 = 1;
+
+// The contents of FRONTEND_RESULT. Every field except the protocol is optional, so
+// that build directories left by an older posy still parse; fields we don't know
+// about are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+struct FrontendResult {
+    protocol: u32,
+    backend: Option<String>,
+    // from get_requires_for_build_wheel/editable; None if it hasn't run yet
+    requires: Option<Vec<String>>,
+    // relative to the prepare_metadata_for_build_wheel directory
+    metadata_dist_info: Option<String>,
+    // relative to the build_wheel directory
+    wheel: Option<String>,
+    // the most-restrictive wheel tag compatible with the build environment
+    binary_wheel_tag: Option<String>,
+    // seconds
+    hook_durations: BTreeMap<String, f64>,
+}
+
+impl FrontendResult {
+    fn read(handle: &Path) -> Result<Option<FrontendResult>> {
+        match fs::read(handle.join(FRONTEND_RESULT)) {
+            Ok(bytes) => {
+                context!("parsing {FRONTEND_RESULT}");
+                Ok(Some(serde_json::from_slice(&bytes)?))
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                FrontendResult::read_loose_files(handle)
+            }
+            Err(e) => Err(e)?,
+        }
+    }
+
+    // Before FRONTEND_RESULT, build-frontend.py left one file per output. We call that
+    // protocol 0.
+    fn read_loose_files(handle: &Path) -> Result<Option<FrontendResult>> {
+        let read = |name: &str| match fs::read_to_string(handle.join(name)) {
+            Ok(s) => Ok(Some(s)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        };
+        let requires = match read("get_requires_for_build_wheel")? {
+            Some(s) => Some(serde_json::from_str(&s)?),
+            None => return Ok(None),
+        };
+        Ok(Some(FrontendResult {
+            protocol: 0,
+            requires,
+            metadata_dist_info: read("prepare_metadata_for_build_wheel.out")?,
+            wheel: read("build_wheel.out")?,
+            binary_wheel_tag: read("build_wheel.binary_wheel_tag")?,
+            ..Default::default()
+        }))
+    }
+
+    fn finished(&self, goal: Pep517Goal) -> bool {
+        let have_wheel = self.wheel.is_some() && self.binary_wheel_tag.is_some();
+        match goal {
+            Pep517Goal::Wheel => have_wheel,
+            Pep517Goal::WheelMetadata => {
+                have_wheel || self.metadata_dist_info.is_some()
+            }
+        }
+    }
+
+    fn log_durations(&self, name: &PackageName) {
+        let backend = self.backend.as_deref().unwrap_or("unknown backend");
+        for (hook, seconds) in &self.hook_durations {
+            debug!("{}: {backend} {hook} took {seconds:.2}s", name.as_given());
+        }
+    }
+}
+
+// Everything the build frontend and backend print, for all the steps of the most
+// recent build in this directory.
+const BUILD_LOG: &str = "build.log";
+// How much of the log goes into the error when a build fails.
+const BUILD_LOG_TAIL_LINES: usize = 40;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Pep517Goal {
     WheelMetadata,
@@ -115,7 +283,6 @@ impl<'a> WheelBuilder<'a> {
         wheel_platform: &WheelPlatform,
     ) -> Result<Wheel> {
         trace!("Building wheel from source for {} {}", sdist_ai.name.distribution().as_given(), sdist_ai.name.version());
-        let new_build_stack = self.new_build_stack(sdist_ai.name.distribution())?;
 
         // check if we already have a usable wheel cached; and if so, find the best one
         let handle = self.db.wheel_cache.lock(sdist_ai.require_hash()?)?;
@@ -136,7 +303,7 @@ impl<'a> WheelBuilder<'a> {
                 continue;
             }
             let name: WheelName = str_name.parse()?;
-            let maybe_score = wheel_platform.max_compatibility(name.all_tags());
+            let maybe_score = wheel_platform.binary_compatibility(&name);
             if let Some(score) = maybe_score {
                 if best.is_none() || best.as_ref().unwrap().0 < score {
                     best = Some((score, os_name, name))
@@ -151,17 +318,55 @@ impl<'a> WheelBuilder<'a> {
 
         // nothing in cache -- we'll have to build it ourselves (which will implicitly
         // add to the cache)
+        self.built_wheel(BuildSource::Sdist(sdist_ai), wheel_platform, Some(handle))
+    }
+
+    pub fn locally_built_metadata(
+        &self,
+        sdist_ai: &ArtifactInfo,
+    ) -> Result<(Vec<u8>, WheelCoreMetadata)> {
+        trace!("Getting metadata from source for {} {}", sdist_ai.name.distribution().as_given(), sdist_ai.name.version());
+        self.built_metadata(BuildSource::Sdist(sdist_ai))
+    }
+
+    /// Builds a wheel from a source tree on the local filesystem (an editable one, if
+    /// `tree.editable`). Unlike sdists, we rebuild every time we're asked, because the
+    /// tree might have changed since last time.
+    pub fn local_tree_wheel(
+        &self,
+        name: &PackageName,
+        tree: &LocalTree,
+        wheel_platform: &WheelPlatform,
+    ) -> Result<Wheel> {
+        trace!("Building wheel from local tree {}", tree.path.display());
+        self.built_wheel(BuildSource::Local(name, tree), wheel_platform, None)
+    }
+
+    /// Like local_tree_wheel, but only gets the metadata.
+    pub fn local_tree_metadata(
+        &self,
+        name: &PackageName,
+        tree: &LocalTree,
+    ) -> Result<(Vec<u8>, WheelCoreMetadata)> {
+        trace!("Getting metadata from local tree {}", tree.path.display());
+        self.built_metadata(BuildSource::Local(name, tree))
+    }
+
+    fn built_wheel(
+        &self,
+        source: BuildSource,
+        wheel_platform: &WheelPlatform,
+        wheel_cache_handle: Option<KVDirLock>,
+    ) -> Result<Wheel> {
+        let new_build_stack = self.new_build_stack(source.name())?;
         match self.pep517(
-            sdist_ai,
+            source,
             Pep517Goal::Wheel,
-            Some(handle),
+            wheel_cache_handle,
             &new_build_stack,
         )? {
             Pep517Succeeded::Wheel { wheel } => {
-                if wheel_platform
-                    .max_compatibility(wheel.name().all_tags())
-                    .is_some()
-                {
+                if wheel_platform.binary_compatibility(wheel.name()).is_some() {
                     Ok(wheel)
                 } else {
                     bail!("built wheel is not compatible with target environment");
@@ -171,19 +376,12 @@ impl<'a> WheelBuilder<'a> {
         }
     }
 
-    pub fn locally_built_metadata(
+    fn built_metadata(
         &self,
-        sdist_ai: &ArtifactInfo,
+        source: BuildSource,
     ) -> Result<(Vec<u8>, WheelCoreMetadata)> {
-        trace!("Getting metadata from source for {} {}", sdist_ai.name.distribution().as_given(), sdist_ai.name.version());
-        let new_build_stack = self.new_build_stack(sdist_ai.name.distribution())?;
-
-        match self.pep517(
-            sdist_ai,
-            Pep517Goal::WheelMetadata,
-            None,
-            &new_build_stack,
-        )? {
+        let new_build_stack = self.new_build_stack(source.name())?;
+        match self.pep517(source, Pep517Goal::WheelMetadata, None, &new_build_stack)? {
             Pep517Succeeded::WheelMetadata {
                 handle: _handle,
                 dist_info,
@@ -196,6 +394,42 @@ impl<'a> WheelBuilder<'a> {
         }
     }
 
+    // The build environment the lockfile pinned for `source`, if there is one and it has
+    // everything the build asks for. The lock was made from the same pyproject.toml,
+    // but the backend's dynamic requirements can come out differently on another
+    // machine, and the pip shim gets added on the fly. If it falls short, we resolve a
+    // fresh one like we would without a lock.
+    fn locked_build_env(
+        &self,
+        source: BuildSource,
+        build_requires: &[UserRequirement],
+    ) -> Result<Option<Blueprint>> {
+        let locked = match source {
+            BuildSource::Sdist(sdist_ai) => {
+                self.db.locked_build(sdist_ai.require_hash()?)
+            }
+            BuildSource::Local(..) => None,
+        };
+        let blueprint = match locked {
+            Some(blueprint) => blueprint,
+            None => return Ok(None),
+        };
+        let unmet = blueprint.unmet_requirements(build_requires)?;
+        if unmet.is_empty() {
+            return Ok(Some(blueprint));
+        }
+        warn!(
+            "the locked build environment for {} doesn't have {}, so using a new one",
+            source.name().as_given(),
+            unmet
+                .iter()
+                .map(|req| req.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        Ok(None)
+    }
+
     fn get_env_for_build(
         &self,
         reqs: &[UserRequirement],
@@ -215,6 +449,13 @@ impl<'a> WheelBuilder<'a> {
                 .unwrap(),
                 requirements: reqs.into(),
                 allow_pre: Default::default(),
+                strategy: Default::default(),
+                keep_pinned_prereleases: false,
+                constraints: Vec::new(),
+                local_requirements: Vec::new(),
+                no_source_builds: false,
+                python_flags: Vec::new(),
+                python_fallbacks: Vec::new(),
             }
             .resolve(
                 self.db,
@@ -289,6 +530,13 @@ impl<'a> WheelBuilder<'a> {
                 python: candidate,
                 requirements: Vec::new(),
                 allow_pre,
+                strategy: Default::default(),
+                keep_pinned_prereleases: false,
+                constraints: Vec::new(),
+                local_requirements: Vec::new(),
+                no_source_builds: false,
+                python_flags: Vec::new(),
+                python_fallbacks: Vec::new(),
             };
             let result =
                 brief.resolve(self.db, &self.build_platforms, None, new_build_stack);
@@ -314,6 +562,13 @@ impl<'a> WheelBuilder<'a> {
             python: pyreq,
             requirements: reqs.into(),
             allow_pre: Default::default(),
+            strategy: Default::default(),
+            keep_pinned_prereleases: false,
+            constraints: Vec::new(),
+            local_requirements: Vec::new(),
+            no_source_builds: false,
+            python_flags: Vec::new(),
+            python_fallbacks: Vec::new(),
         };
         let blueprint = brief.resolve(
             self.db,
@@ -332,93 +587,150 @@ impl<'a> WheelBuilder<'a> {
 
     fn pep517(
         &self,
-        sdist_ai: &ArtifactInfo,
+        source: BuildSource,
         goal: Pep517Goal,
         wheel_cache_handle: Option<KVDirLock>,
         new_build_stack: &[&PackageName],
     ) -> Result<Pep517Succeeded> {
-        let sdist_hash = sdist_ai.require_hash()?;
-        let handle = self.db.build_store.lock(&sdist_hash)?;
-
-        if !handle.exists() {
-            let tempdir = handle.tempdir()?;
-            let sdist = self.db.get_artifact::<Sdist>(sdist_ai)?;
-            let unpack_path = tempdir.path().join("sdist");
-            sdist.unpack(&mut WriteTreeFS::new(&unpack_path))?;
-            const BUILD_FRONTEND_PY: &[u8] =
-                include_bytes!("data-files/build-frontend.py");
-            fs::write(tempdir.path().join("build-frontend.py"), BUILD_FRONTEND_PY)?;
-            fs::rename(&tempdir.into_path(), &*handle)?;
+        let _timer = crate::stats::time_phase("build");
+        crate::events::emit(crate::events::Event::BuildingWheel {
+            package: source.name().as_given().to_owned(),
+            goal: match goal {
+                Pep517Goal::WheelMetadata => "metadata",
+                Pep517Goal::Wheel => "wheel",
+            }
+            .into(),
+        });
+        let (handle, source_root) = match source {
+            BuildSource::Sdist(sdist_ai) => {
+                let handle = self.db.build_store.lock(sdist_ai.require_hash()?)?;
+                if !handle.exists() {
+                    let tempdir = handle.tempdir()?;
+                    let sdist = self.db.get_artifact::<Sdist>(sdist_ai)?;
+                    let unpack_path = tempdir.path().join("sdist");
+                    sdist.unpack(&mut WriteTreeFS::new(&unpack_path))?;
+                    fs::rename(&tempdir.into_path(), &*handle)?;
+                }
+                let mut sdist_entries = fs::read_dir(handle.join("sdist"))?
+                    .collect::<Result<Vec<_>, io::Error>>()?;
+                if sdist_entries.len() != 1 {
+                    bail!("expected sdist to contain exactly one top-level directory");
+                }
+                let sdist_root = sdist_entries.pop().unwrap().path();
+                (handle, sdist_root)
+            }
+            BuildSource::Local(_, tree) => {
+                // We build in the tree itself, and anything left over from last time
+                // might be stale.
+                let handle = self.db.build_store.lock(&tree.store_key().as_slice())?;
+                fs::create_dir_all(&handle)?;
+                clear_build_outputs(&handle)?;
+                if tree.editable {
+                    // tells build-frontend.py to use the PEP 660 hooks
+                    fs::write(handle.join("editable"), b"")?;
+                }
+                (handle, tree.path.clone())
+            }
+        };
+
+        // Always our own copy of the frontend, even in a build directory that an older
+        // posy left behind. If that posy got partway through a build, we can't pick up
+        // where its frontend left off, so start over -- unless it already got as far as
+        // we need.
+        fs::write(handle.join("build-frontend.py"), BUILD_FRONTEND_PY)?;
+        if let Some(result) = FrontendResult::read(&handle)? {
+            if result.protocol != FRONTEND_PROTOCOL
+                && !(result.protocol < FRONTEND_PROTOCOL && result.finished(goal))
+            {
+                debug!(
+                    "restarting build in {} (frontend protocol {})",
+                    handle.display(),
+                    result.protocol
+                );
+                clear_build_outputs(&handle)?;
+            }
         }
 
+        // a fresh log for each build; the steps append to it
+        fs::File::create(handle.join(BUILD_LOG))?;
+
         let build_wheel = handle.join("build_wheel");
         let prepare_metadata_for_build_wheel =
             handle.join("prepare_metadata_for_build_wheel");
         loop {
+            let result = FrontendResult::read(&handle)?.unwrap_or_default();
             // If we have a wheel, we're definitely done, no matter what our goal was
-            if build_wheel.exists() {
-                // Get the name the build backend returned
-                let name =
-                    String::from_utf8(fs::read(handle.join("build_wheel.out"))?)?;
+            if let (Some(name), Some(build_env_tag)) =
+                (&result.wheel, &result.binary_wheel_tag)
+            {
+                result.log_durations(source.name());
+                crate::stats::wheel_built();
                 let mut wheel_name: WheelName = name.parse()?;
-                let wheel_path = build_wheel.join(&name);
-                // Get the most-restrictive wheel tag compatible with the build
-                // environment.
-                let build_env_tag = String::from_utf8(fs::read(
-                    handle.join("build_wheel.binary_wheel_tag"),
-                )?)?;
+                let wheel_path = build_wheel.join(name);
+                let (_, build_arch) = build_env_tag.rsplit_once('-').unwrap();
+                check_wheel_tags(&wheel_path, &mut wheel_name, build_arch)?;
                 // If this is a binary wheel, then tag it with the platform we built on
                 // (so e.g. "linux_x86_64" might become "manylinux_2_32_x86_64")
-                let (_, build_arch) = build_env_tag.rsplit_once('-').unwrap();
                 if !wheel_name.arch_tags.iter().all(|t| t == "any") {
                     wheel_name.arch_tags = vec![build_arch.into()]
                 }
-                // Store the wheel in the wheel cache
-                let wheel_cache_handle = match wheel_cache_handle {
-                    Some(h) => h,
-                    None => self.db.wheel_cache.lock(&sdist_hash)?,
+                let provenance =
+                    build_provenance(source, &handle, &wheel_path, build_env_tag)?;
+                let (_wheel_cache_handle, target_dir) = match source {
+                    // Store the wheel in the wheel cache
+                    BuildSource::Sdist(sdist_ai) => {
+                        let wheel_cache_handle = match wheel_cache_handle {
+                            Some(h) => h,
+                            None => {
+                                self.db.wheel_cache.lock(sdist_ai.require_hash()?)?
+                            }
+                        };
+                        let target_dir = wheel_cache_handle.to_path_buf();
+                        (Some(wheel_cache_handle), target_dir)
+                    }
+                    // ...except for local trees, where it'd go stale as soon as the
+                    // user edits something. It only has to last until our caller
+                    // opens it.
+                    BuildSource::Local(..) => (None, handle.join("local_wheel")),
                 };
-                fs::create_dir_all(&wheel_cache_handle)?;
-                let target_path = wheel_cache_handle.join(wheel_name.to_string());
-                if fs::rename(&wheel_path, &target_path).is_err() {
-                    fs::copy(&wheel_path, &target_path)?;
-                }
+                fs::create_dir_all(&target_dir)?;
+                let target_path = target_dir.join(wheel_name.to_string());
+                embed_provenance(&wheel_path, &target_path, &wheel_name, &provenance)?;
                 let opened = fs::File::open(target_path)?;
                 let wheel = Wheel::new(wheel_name, Box::new(opened))?;
                 return Ok(Pep517Succeeded::Wheel { wheel });
             }
 
             // Or if our goal is metadata and we have it, we're done
-            if goal == Pep517Goal::WheelMetadata
-                && prepare_metadata_for_build_wheel.exists()
+            if let (Pep517Goal::WheelMetadata, Some(name)) =
+                (goal, &result.metadata_dist_info)
             {
-                let name = String::from_utf8(fs::read(
-                    handle.join("prepare_metadata_for_build_wheel.out"),
-                )?)?;
-                return Ok(Pep517Succeeded::WheelMetadata {
-                    handle,
-                    dist_info: prepare_metadata_for_build_wheel.join(name),
-                });
+                result.log_durations(source.name());
+                let dist_info = prepare_metadata_for_build_wheel.join(name);
+                return Ok(Pep517Succeeded::WheelMetadata { handle, dist_info });
             }
             // Otherwise, we're not done. Turn the crank again.
-            self.pep517_step(&handle, goal, new_build_stack)?;
+            self.pep517_step(
+                source,
+                &handle,
+                &source_root,
+                goal,
+                result.requires.unwrap_or_default(),
+                new_build_stack,
+            )?;
         }
     }
 
     fn pep517_step(
         &self,
+        source: BuildSource,
         handle: &KVDirLock,
+        source_root: &Path,
         goal: Pep517Goal,
+        dynamic_requires: Vec<String>,
         new_build_stack: &[&PackageName],
     ) -> Result<()> {
-        let mut sdist_entries = fs::read_dir(handle.join("sdist"))?
-            .collect::<Result<Vec<_>, io::Error>>()?;
-        if sdist_entries.len() != 1 {
-            bail!("expected sdist to contain exactly one top-level directory");
-        }
-        let sdist_root = sdist_entries.pop().unwrap().path();
-
-        let build_system = match fs::read(sdist_root.join("pyproject.toml")) {
+        let build_system = match fs::read(source_root.join("pyproject.toml")) {
             Ok(pyproject_bytes) => {
                 context!("parsing pyproject.toml");
                 let pyproject_str = String::from_utf8(pyproject_bytes)?;
@@ -430,14 +742,6 @@ impl<'a> WheelBuilder<'a> {
         let build_system_path = handle.join("build-system.json");
         serde_json::to_writer(fs::File::create(build_system_path)?, &build_system)?;
 
-        let get_requires_for_build_wheel = handle.join("get_requires_for_build_wheel");
-        let dynamic_requires: Vec<String> =
-            match fs::File::open(get_requires_for_build_wheel) {
-                Ok(f) => serde_json::from_reader(f)?,
-                Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
-                Err(e) => Err(e)?,
-            };
-
         let saved_blueprint_path = handle.join("saved-blueprint.json");
         let saved_blueprint: Option<Blueprint> = fs::File::open(&saved_blueprint_path)
             .ok()
@@ -445,16 +749,32 @@ impl<'a> WheelBuilder<'a> {
 
         let mut build_requires = build_system.requires;
         build_requires.extend(dynamic_requires);
-        let build_requires = build_requires
+        let mut build_requires = build_requires
             .into_iter()
             .map(|s| s.parse())
-            .collect::<Result<Vec<_>>>()?;
+            .collect::<Result<Vec<UserRequirement>>>()?;
+        let pip_shim_path = handle.join(PIP_SHIM);
+        if pip_shim_path.exists() {
+            build_requires.push(PIP_SHIM_REQUIREMENT.parse()?);
+        }
 
-        let (blueprint, env) = self.get_env_for_build(
-            &build_requires,
-            saved_blueprint.as_ref(),
-            new_build_stack,
-        )?;
+        let (blueprint, env) = match self.locked_build_env(source, &build_requires)? {
+            // the lockfile says exactly what to build it with, so no resolving
+            Some(blueprint) => {
+                let env = self.db.build_forest.get_env(
+                    self.db,
+                    &blueprint,
+                    &self.build_platforms,
+                    new_build_stack,
+                )?;
+                (blueprint, env)
+            }
+            None => self.get_env_for_build(
+                &build_requires,
+                saved_blueprint.as_ref(),
+                new_build_stack,
+            )?,
+        };
 
         let binary_wheel_tag = env
             .wheel_platform
@@ -464,27 +784,265 @@ impl<'a> WheelBuilder<'a> {
 
         serde_json::to_writer(fs::File::create(&saved_blueprint_path)?, &blueprint)?;
 
-        let mut child = std::process::Command::new("python")
-            .args([
-                handle.join("build-frontend.py").as_os_str(),
-                handle.as_os_str(),
-                OsString::from(format!("{:?}", goal)).as_ref(),
-                OsString::from(binary_wheel_tag).as_ref(),
-            ])
-            .stdin(std::process::Stdio::null())
-            .current_dir(&sdist_root)
-            .envs(env.env_vars()?)
-            .spawn()?;
-
-        let status = child.wait()?;
+        let mut cmd = std::process::Command::new("python");
+        cmd.args([
+            handle.join("build-frontend.py").as_os_str(),
+            handle.as_os_str(),
+            OsString::from(format!("{:?}", goal)).as_ref(),
+            OsString::from(binary_wheel_tag).as_ref(),
+        ]);
+        let mut cmd = env.wrap_command(cmd)?;
+        cmd.stdin(Stdio::null()).current_dir(source_root);
+
+        let log_path = handle.join(BUILD_LOG);
+        let status = run_logged(
+            cmd,
+            &log_path,
+            source.name().as_given(),
+            self.db.cancellation_token(),
+        )?;
         if !status.success() {
-            bail!("Build failed (exit status: {status})");
+            // (if the build env is locked, and doesn't have pip, then this means we
+            // stop using it; see locked_build_env)
+            let could_add_pip = self.db.build_pip_shim
+                && !build_requires.iter().any(|r| r.name.normalized() == "pip");
+            if could_add_pip && missing_pip(&log_path) {
+                info!(
+                    "{} needs pip to build; trying again with {PIP_SHIM_REQUIREMENT}",
+                    source.name().as_given()
+                );
+                fs::write(&pip_shim_path, b"")?;
+                // the frontend didn't record anything for the step that failed, so
+                // pep517() will run it again
+                return Ok(());
+            }
+            bail!(
+                "Build failed (exit status: {status}). Last lines of output (full log \
+                 in {}):\n{}",
+                log_path.display(),
+                log_tail(&log_path, BUILD_LOG_TAIL_LINES)
+            );
         }
 
         Ok(())
     }
 }
 
+// Runs `cmd`, appending everything it prints to the log at `log_path`, and also passing
+// it along to the user a line at a time, tagged with `prefix` -- builds can nest (a
+// build dependency might need building too), so otherwise it's not clear whose output
+// is whose.
+fn run_logged(
+    mut cmd: std::process::Command,
+    log_path: &Path,
+    prefix: &str,
+    cancel: &CancellationToken,
+) -> Result<ExitStatus> {
+    context!("Running {:?}", cmd);
+    let log = Mutex::new(
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_path)?,
+    );
+    let mut tree =
+        ProcessTree::spawn(cmd.stdout(Stdio::piped()).stderr(Stdio::piped()))?;
+    // unwraps are safe b/c we asked for pipes
+    let stdout = tree.child_mut().stdout.take().unwrap();
+    let stderr = tree.child_mut().stderr.take().unwrap();
+    let span = tracing::Span::current();
+    let (status, copied) = std::thread::scope(|scope| {
+        let (log, span) = (&log, &span);
+        let copy = move |pipe: Box<dyn Read + Send>| {
+            scope.spawn(move || {
+                let _entered = span.enter();
+                copy_lines(pipe, log, prefix)
+            })
+        };
+        let workers = [copy(Box::new(stdout)), copy(Box::new(stderr))];
+        // Wait while they copy: if we get cancelled, this kills the build, which is
+        // what closes the pipes and lets them finish.
+        let status = tree.wait(cancel);
+        let copied = workers
+            .into_iter()
+            .map(|worker| match worker.join() {
+                Ok(result) => result,
+                Err(panic) => std::panic::resume_unwind(panic),
+            })
+            .collect::<io::Result<Vec<()>>>();
+        (status, copied)
+    });
+    let status = status?;
+    copied?;
+    Ok(status)
+}
+
+fn copy_lines(pipe: impl Read, log: &Mutex<fs::File>, prefix: &str) -> io::Result<()> {
+    let mut reader = io::BufReader::new(pipe);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+        log.lock().unwrap().write_all(&line)?;
+        info!("[{prefix}] {}", String::from_utf8_lossy(&line).trim_end());
+    }
+}
+
+// The last `lines` lines of the log, or as much of it as we can get. We're already
+// reporting a failure, so a missing or mangled log isn't worth another error.
+fn log_tail(log_path: &Path, lines: usize) -> String {
+    let log = fs::read(log_path).unwrap_or_default();
+    let log = String::from_utf8_lossy(&log);
+    let all = log.lines().collect::<Vec<_>>();
+    all[all.len().saturating_sub(lines)..].join("\n")
+}
+
+// Whether the build failed because it tried to use pip, either with 'import pip' or
+// 'python -m pip'.
+fn missing_pip(log_path: &Path) -> bool {
+    let log = fs::read(log_path).unwrap_or_default();
+    let log = String::from_utf8_lossy(&log);
+    log.lines().any(|line| {
+        line.ends_with("No module named 'pip'") || line.ends_with("No module named pip")
+    })
+}
+
+fn unix_time(t: SystemTime) -> Result<u64> {
+    Ok(t.duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+fn clear_build_outputs(handle: &Path) -> Result<()> {
+    for name in BUILD_OUTPUTS {
+        let path = handle.join(name);
+        if path.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else if path.exists() {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+fn build_provenance(
+    source: BuildSource,
+    handle: &KVDirLock,
+    wheel_path: &Path,
+    build_env_tag: &str,
+) -> Result<BuildProvenance> {
+    // both written by pep517_step before it ran the build
+    let build_system: PyprojectBuildSystemStanza =
+        serde_json::from_slice(&fs::read(handle.join("build-system.json"))?)?;
+    let blueprint: Blueprint =
+        serde_json::from_slice(&fs::read(handle.join("saved-blueprint.json"))?)?;
+
+    let (sdist, sdist_hash) = match source {
+        BuildSource::Sdist(sdist_ai) => (
+            sdist_ai.name.to_string(),
+            Some(sdist_ai.require_hash()?.clone()),
+        ),
+        BuildSource::Local(_, tree) => (tree.path.display().to_string(), None),
+    };
+
+    Ok(BuildProvenance {
+        posy_version: env!("CARGO_PKG_VERSION").into(),
+        sdist,
+        sdist_hash,
+        build_blueprint_hash: blueprint.identity(build_env_tag),
+        build_backend_version: backend_version(&build_system.build_backend, &blueprint),
+        build_backend: build_system.build_backend,
+        build_python: format!(
+            "{} {}",
+            blueprint.pybi.name.as_given(),
+            blueprint.pybi.version
+        ),
+        build_platform: build_env_tag.into(),
+        built_at: unix_time(fs::metadata(wheel_path)?.modified()?)?,
+        recorded_at: unix_time(SystemTime::now())?,
+    })
+}
+
+// The backend is a module path like "flit_core.buildapi" or "poetry.core.masonry.api",
+// and there's no reliable way to map that to the package that provides it without
+// running Python. But in practice the package is named after the first component or
+// two, so we look for those in the build environment.
+fn backend_version(backend: &str, blueprint: &Blueprint) -> Option<Version> {
+    let module = backend.split(':').next().unwrap_or_default();
+    let parts = module.split('.').collect::<Vec<_>>();
+    for n in (1..=parts.len().min(2)).rev() {
+        if let Ok(name) = parts[..n].join("-").parse::<PackageName>() {
+            if let Some((pin, _)) =
+                blueprint.wheels.iter().find(|(p, _)| p.name == name)
+            {
+                return Some(pin.version.clone());
+            }
+        }
+    }
+    None
+}
+
+// Copies the wheel at `src` to `dest`, adding the provenance file to its .dist-info
+// (and to its RECORD, so that the wheel stays valid).
+fn embed_provenance(
+    src: &Path,
+    dest: &Path,
+    wheel_name: &WheelName,
+    provenance: &BuildProvenance,
+) -> Result<()> {
+    context!("Recording build provenance in {wheel_name}");
+    let mut z = zip::ZipArchive::new(fs::File::open(src)?)?;
+    let top_levels = z
+        .file_names()
+        .map(|n| n.split_once('/').map_or(n, |(base, _)| base))
+        .collect::<HashSet<_>>();
+    let dist_info = Wheel::find_special_wheel_dir(
+        top_levels,
+        &wheel_name.distribution,
+        &wheel_name.version,
+        ".dist-info",
+    )?
+    .ok_or(eyre!(".dist-info/ missing"))?
+    .to_string();
+    let record_path = format!("{dist_info}/RECORD");
+    let provenance_path = format!("{dist_info}/{BUILD_PROVENANCE_NAME}");
+    let provenance_json = serde_json::to_vec_pretty(provenance)?;
+
+    // unwrap is safe because dest is always inside the wheel cache
+    let tmp = tempfile::NamedTempFile::new_in(dest.parent().unwrap())?;
+    let mut out = zip::ZipWriter::new(tmp);
+    let mut record = None;
+    for i in 0..z.len() {
+        let name = z.by_index_raw(i)?.name().to_string();
+        if name == record_path {
+            record = Some(slurp(&mut z.by_index(i)?)?);
+        } else if name != provenance_path {
+            out.raw_copy_file(z.by_index_raw(i)?)?;
+        }
+    }
+    let mut record = record.ok_or_else(|| eyre!("{record_path} missing"))?;
+    if !record.is_empty() && !record.ends_with(b"\n") {
+        record.push(b'\n');
+    }
+    let digest = ring::digest::digest(&ring::digest::SHA256, &provenance_json);
+    writeln!(
+        record,
+        "{provenance_path},sha256={},{}",
+        data_encoding::BASE64URL_NOPAD.encode(digest.as_ref()),
+        provenance_json.len()
+    )?;
+
+    let options = zip::write::FileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+    out.start_file(&provenance_path, options)?;
+    out.write_all(&provenance_json)?;
+    // RECORD goes last, like it would in a freshly built wheel
+    out.start_file(&record_path, options)?;
+    out.write_all(&record)?;
+    out.finish()?.persist(dest)?;
+    Ok(())
+}
+
 /// Used to parse the `[build-system]` table in pyproject.toml.
 #[derive(Deserialize, Debug, Serialize)]
 #[serde(rename_all = "kebab-case", default)]
@@ -514,3 +1072,230 @@ impl PyprojectBuildSystemStanza {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::with_index_db;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_logged() {
+        let tmp = tempfile::tempdir().unwrap();
+        let log = tmp.path().join(BUILD_LOG);
+        fs::write(&log, "from an earlier step\n").unwrap();
+        let mut cmd = std::process::Command::new("sh");
+        cmd.args(["-c", "echo out; echo err >&2; printf 'no newline'; exit 3"]);
+        let status = run_logged(cmd, &log, "foo", &Default::default()).unwrap();
+        assert_eq!(status.code(), Some(3));
+        let text = fs::read_to_string(&log).unwrap();
+        // stdout and stderr are read separately, so their order isn't guaranteed
+        assert!(text.starts_with("from an earlier step\n"));
+        for expected in ["out\n", "err\n", "no newline"] {
+            assert!(text.contains(expected));
+        }
+
+        fs::write(&log, "one\ntwo\nthree\n").unwrap();
+        assert_eq!(log_tail(&log, 2), "two\nthree");
+        assert_eq!(log_tail(&log, 100), "one\ntwo\nthree");
+        assert_eq!(log_tail(&tmp.path().join("missing.log"), 5), "");
+    }
+
+    #[test]
+    fn test_missing_pip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let log = tmp.path().join(BUILD_LOG);
+        for (text, expected) in [
+            ("ModuleNotFoundError: No module named 'pip'\n", true),
+            ("/tmp/env/bin/python: No module named pip\n", true),
+            ("ModuleNotFoundError: No module named 'pipdeptree'\n", false),
+            ("ImportError: No module named 'pip._vendor'\n", false),
+            ("error: could not find Cython\n", false),
+        ] {
+            fs::write(&log, text).unwrap();
+            assert_eq!(missing_pip(&log), expected, "{text}");
+        }
+        assert!(!missing_pip(&tmp.path().join("missing.log")));
+    }
+
+    #[test]
+    fn test_frontend_result() {
+        let tmp = tempfile::tempdir().unwrap();
+        let handle = tmp.path();
+        assert_eq!(FrontendResult::read(handle).unwrap(), None);
+
+        // left by an older posy
+        fs::write(handle.join("get_requires_for_build_wheel"), "[\"cython\"]").unwrap();
+        fs::write(handle.join("build_wheel.out"), "foo-1.0-py3-none-any.whl").unwrap();
+        let result = FrontendResult::read(handle).unwrap().unwrap();
+        assert_eq!(result.protocol, 0);
+        assert_eq!(result.requires, Some(vec!["cython".to_string()]));
+        assert_eq!(result.metadata_dist_info, None);
+        assert!(!result.finished(Pep517Goal::WheelMetadata));
+        fs::write(handle.join("build_wheel.binary_wheel_tag"), "py3-none-any").unwrap();
+        let result = FrontendResult::read(handle).unwrap().unwrap();
+        assert!(result.finished(Pep517Goal::Wheel));
+
+        // the result file wins, and unknown fields are ignored
+        fs::write(
+            handle.join(FRONTEND_RESULT),
+            r#"{
+                "protocol": 1,
+                "backend": "flit_core.buildapi",
+                "requires": [],
+                "metadata-dist-info": "foo-1.0.dist-info",
+                "hook-durations": {"prepare_metadata_for_build_wheel": 0.5},
+                "from-the-future": true
+            }"#,
+        )
+        .unwrap();
+        let result = FrontendResult::read(handle).unwrap().unwrap();
+        assert_eq!(result.protocol, FRONTEND_PROTOCOL);
+        assert_eq!(result.backend.as_deref(), Some("flit_core.buildapi"));
+        assert_eq!(result.wheel, None);
+        assert!(result.finished(Pep517Goal::WheelMetadata));
+        assert!(!result.finished(Pep517Goal::Wheel));
+        assert_eq!(
+            result.hook_durations["prepare_metadata_for_build_wheel"],
+            0.5
+        );
+
+        clear_build_outputs(handle).unwrap();
+        assert_eq!(FrontendResult::read(handle).unwrap(), None);
+    }
+
+    #[test]
+    fn test_locked_build_env() {
+        use crate::resolve::{
+            PinnedPackage, WheelResolveMetadata, WheelResolveMetadataInner,
+        };
+
+        let pin = |name: &str, version: &str| PinnedPackage {
+            name: name.parse().unwrap(),
+            version: version.try_into().unwrap(),
+            hashes: vec![],
+            url: None,
+        };
+        let metadata = WheelResolveMetadata {
+            provenance: "test".into(),
+            inner: WheelResolveMetadataInner {
+                requires_dist: vec![],
+                requires_python: Default::default(),
+                extras: Default::default(),
+            },
+        };
+        let win32 = "sys_platform == 'win32'".try_into().unwrap();
+        let locked = Blueprint {
+            pybi: pin("cpython", "3.11.4"),
+            wheels: vec![(pin("setuptools", "68.0.0"), metadata)],
+            local: vec![],
+            marker_expressions: [(win32, false)].into(),
+            python_fallback: None,
+            annotations: Default::default(),
+            claimed_publishers: Default::default(),
+            platform_tags: Default::default(),
+            source_builds: Default::default(),
+            warnings: Vec::new(),
+            policy: Default::default(),
+            python_flags: Vec::new(),
+        };
+        with_index_db(vec![], |db| {
+            let sdist_url = |name: &str, hex: &str| {
+                let file = format!("{name}-1.0.tar.gz#sha256={}", hex.repeat(32));
+                db.index_urls[0].join(&file).unwrap()
+            };
+            let sdist = db.direct_artifact(&sdist_url("foo", "11")).unwrap().clone();
+            let unlocked = db.direct_artifact(&sdist_url("bar", "22")).unwrap().clone();
+            let hash = sdist.hash.as_ref().unwrap().to_string();
+            db.use_locked_builds(&[(hash, locked.clone())].into())
+                .unwrap();
+
+            let python = "cpython".parse().unwrap();
+            let version = "3.11.4".try_into().unwrap();
+            let builder = WheelBuilder::new(db, &python, &version, &[], &[]).unwrap();
+            let locked_env = |source: &ArtifactInfo, reqs: &[&str]| {
+                let reqs = reqs
+                    .iter()
+                    .map(|r| r.parse().unwrap())
+                    .collect::<Vec<UserRequirement>>();
+                builder
+                    .locked_build_env(BuildSource::Sdist(source), &reqs)
+                    .unwrap()
+            };
+
+            // what 'posy lock' pinned is what we build with
+            let static_requires =
+                ["setuptools >= 40", "pywin32; sys_platform == 'win32'"];
+            let used = locked_env(&sdist, &static_requires).unwrap();
+            assert_eq!(used.wheels[0].0, locked.wheels[0].0);
+            assert!(locked_env(&unlocked, &static_requires).is_none());
+            // ...unless the build turns out to want something it doesn't have
+            assert!(locked_env(&sdist, &["setuptools < 60"]).is_none());
+            // a dynamic requirement from get_requires_for_build_wheel
+            assert!(locked_env(&sdist, &["setuptools", "wheel"]).is_none());
+            assert!(locked_env(&sdist, &["setuptools", PIP_SHIM_REQUIREMENT]).is_none());
+            // a marker the lock was never resolved with could apply here
+            assert!(locked_env(&sdist, &["cython; python_version < '3.12'"]).is_none());
+        });
+    }
+
+    #[test]
+    fn test_embed_provenance() {
+        let tmp = tempfile::tempdir().unwrap();
+        let wheel_name: WheelName = "foo-1.0-py3-none-any.whl".parse().unwrap();
+        let src = tmp.path().join("src.whl");
+        let dest = tmp.path().join(wheel_name.to_string());
+        {
+            let mut z = zip::ZipWriter::new(fs::File::create(&src).unwrap());
+            let options = zip::write::FileOptions::default();
+            for (path, contents) in [
+                ("foo.py", "print('hi')\n"),
+                (
+                    "foo-1.0.dist-info/WHEEL",
+                    "Wheel-Version: 1.0\nRoot-Is-Purelib: true\n",
+                ),
+                (
+                    "foo-1.0.dist-info/METADATA",
+                    "Metadata-Version: 2.1\nName: foo\nVersion: 1.0\n",
+                ),
+                (
+                    "foo-1.0.dist-info/RECORD",
+                    "foo.py,,\nfoo-1.0.dist-info/RECORD,,",
+                ),
+            ] {
+                z.start_file(path, options).unwrap();
+                z.write_all(contents.as_bytes()).unwrap();
+            }
+            z.finish().unwrap();
+        }
+        let hash: ArtifactHash = format!("sha256={}", "00".repeat(32)).parse().unwrap();
+        let provenance = BuildProvenance {
+            posy_version: "0.1.0".into(),
+            sdist: "foo-1.0.tar.gz".into(),
+            sdist_hash: Some(hash.clone()),
+            build_blueprint_hash: hash,
+            build_backend: "setuptools.build_meta:__legacy__".into(),
+            build_backend_version: Some("65.5.0".try_into().unwrap()),
+            build_python: "cpython_unofficial 3.10.8".into(),
+            build_platform: "cp310-cp310-manylinux_2_17_x86_64".into(),
+            built_at: 1674000000,
+            recorded_at: 1674000001,
+        };
+
+        embed_provenance(&src, &dest, &wheel_name, &provenance).unwrap();
+
+        let wheel =
+            Wheel::new(wheel_name, Box::new(fs::File::open(&dest).unwrap())).unwrap();
+        assert_eq!(wheel.build_provenance().unwrap(), Some(provenance));
+        // the rest of the wheel is untouched
+        assert_eq!(wheel.metadata().unwrap().1.name.as_given(), "foo");
+        let mut z = zip::ZipArchive::new(fs::File::open(&dest).unwrap()).unwrap();
+        let record = String::from_utf8(
+            slurp(&mut z.by_name("foo-1.0.dist-info/RECORD").unwrap()).unwrap(),
+        )
+        .unwrap();
+        let lines = record.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[2].starts_with("foo-1.0.dist-info/posy-build.json,sha256="));
+    }
+}
@@ -6,6 +6,7 @@ from pathlib import Path
 from importlib import import_module
 from sys import exit
 from json import loads, dumps
+from time import perf_counter
 
 ################################################################
 # Begin janky attempt to workaround
@@ -33,6 +34,43 @@ distutils.sysconfig.get_python_inc = get_python_inc
 work_dir = Path(work_dir)
 build_system = loads((work_dir / "build-system.json").read_text("utf-8"))
 
+# Everything we tell posy goes into one JSON file, which we rewrite after each hook
+# finishes. Bump the protocol if the meaning of any existing field changes; adding
+# fields is fine, posy ignores ones it doesn't know.
+PROTOCOL = 1
+result_path = work_dir / "frontend-result.json"
+try:
+    result = loads(result_path.read_text("utf-8"))
+except FileNotFoundError:
+    result = None
+if result is None or result.get("protocol") != PROTOCOL:
+    result = {
+        "protocol": PROTOCOL,
+        "backend": build_system["build-backend"],
+        # None until we've asked the backend
+        "requires": None,
+        "metadata-dist-info": None,
+        "wheel": None,
+        "binary-wheel-tag": None,
+        # hook name -> seconds
+        "hook-durations": {},
+    }
+
+
+def save_result():
+    # write-then-rename, so posy never sees a half-written file
+    tmp_path = work_dir / "frontend-result.json.tmp"
+    tmp_path.write_text(dumps(result), "utf-8")
+    tmp_path.replace(result_path)
+
+
+def call_hook(hook, *args, **kwargs):
+    start = perf_counter()
+    value = getattr(backend, hook)(*args, **kwargs)
+    result["hook-durations"][hook] = perf_counter() - start
+    return value
+
+
 backend_paths = []
 cwd = Path.cwd().absolute()
 for backend_path in build_system["backend-path"]:
@@ -50,14 +88,22 @@ if qualname_separator:
     for attr in qualname.split("."):
         backend = getattr(backend, attr)
 
-if not (work_dir / "get_requires_for_build_wheel").exists():
-    try:
-        f = backend.get_requires_for_build_wheel
-    except AttributeError:
-        requires = []
+# posy leaves this file in the work dir when it wants an editable wheel (PEP 660). The
+# filenames below all say "wheel" either way, to keep things simple on the posy side.
+editable = (work_dir / "editable").exists()
+
+if editable and not hasattr(backend, "build_editable"):
+    print(f"Build backend {build_system['build-backend']} doesn't support editable installs")
+    exit(1)
+
+if result["requires"] is None:
+    hook = "get_requires_for_build_editable" if editable else "get_requires_for_build_wheel"
+    if hasattr(backend, hook):
+        requires = call_hook(hook)
     else:
-        requires = f()
-    (work_dir / "get_requires_for_build_wheel").write_text(dumps(requires), "utf-8")
+        requires = []
+    result["requires"] = list(requires)
+    save_result()
     if requires:
         exit(0)
 
@@ -65,17 +111,25 @@ metadata_dir = work_dir / "prepare_metadata_for_build_wheel"
 
 if goal == "WheelMetadata" and hasattr(backend, "prepare_metadata_for_build_wheel"):
     metadata_dir.mkdir()
-    dist_info = backend.prepare_metadata_for_build_wheel(str(metadata_dir))
-    (work_dir / "prepare_metadata_for_build_wheel.out").write_text(dist_info, "utf-8")
+    dist_info = call_hook("prepare_metadata_for_build_wheel", str(metadata_dir))
+    result["metadata-dist-info"] = dist_info
+    save_result()
     exit(0)
 
 wheel_dir = work_dir / "build_wheel"
 wheel_dir.mkdir()
-wheel_basename = backend.build_wheel(
-    str(wheel_dir),
-    metadata_directory=str(metadata_dir) if metadata_dir.exists() else None,
-)
-
-(work_dir / "build_wheel.out").write_text(wheel_basename, "utf-8")
-(work_dir / "build_wheel.binary_wheel_tag").write_text(binary_wheel_tag, "utf-8")
+if editable:
+    # PEP 660 says metadata_directory has to come from
+    # prepare_metadata_for_build_editable, which we never call
+    wheel_basename = call_hook("build_editable", str(wheel_dir))
+else:
+    wheel_basename = call_hook(
+        "build_wheel",
+        str(wheel_dir),
+        metadata_directory=str(metadata_dir) if metadata_dir.exists() else None,
+    )
+
+result["wheel"] = wheel_basename
+result["binary-wheel-tag"] = binary_wheel_tag
+save_result()
 exit(0)
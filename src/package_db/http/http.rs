@@ -2,13 +2,21 @@ use crate::prelude::*;
 use crate::seek_slice::SeekSlice;
 
 use http_cache_semantics::{AfterResponse, BeforeRequest, CachePolicy};
+use std::collections::BTreeSet;
 use std::io::SeekFrom;
-use std::time::SystemTime;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
 use super::super::ArtifactInfo;
-use super::ureq_glue::{do_request_ureq, new_ureq_agent};
+use super::auth::Authenticator;
+use super::partial::PartialEntry;
+use super::range_support::RangeSupport;
+use super::ureq_glue::{
+    do_request_ureq, new_ureq_agent, timeout_from_env, RetryPolicy,
+};
 use super::LazyRemoteFile;
-use crate::kvstore::{KVFileLock, KVFileStore};
+use crate::interrupt::CancellationToken;
+use crate::kvstore::{AnyStore, GcStats, KVDirStore, KVFileLock, KVFileStore};
 
 const MAX_REDIRECTS: u16 = 5;
 const REDIRECT_STATUSES: &[u16] = &[301, 302, 303, 307, 308];
@@ -44,6 +52,24 @@ impl Display for NotCached {
 
 impl std::error::Error for NotCached {}
 
+/// With --offline, every Http shares one of these, to keep track of everything we
+/// needed but didn't have cached. Listing them all at once is a lot more useful than
+/// reporting the first one and making the user go fetch things one at a time.
+#[derive(Debug, Default)]
+pub struct OfflineMisses(Mutex<BTreeSet<String>>);
+
+impl OfflineMisses {
+    fn record(&self, url: &str) {
+        self.0.lock().unwrap().insert(url.to_string());
+    }
+
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+            .into_iter()
+            .collect()
+    }
+}
+
 pub enum ReadPlusMaybeSeek {
     CanSeek(Box<dyn ReadPlusSeek>),
     CannotSeek(Box<dyn Read>),
@@ -81,11 +107,25 @@ fn make_response(
     response
 }
 
-pub struct Http(Rc<HttpInner>);
+// Cheap to clone, and can be shared between threads
+#[derive(Clone)]
+pub struct Http(Arc<HttpInner>);
 
 impl Http {
-    pub fn new(http_cache: KVFileStore, hash_cache: KVFileStore) -> Http {
-        Http(Rc::new(HttpInner::new(http_cache, hash_cache)))
+    pub fn new(
+        http_cache: KVFileStore,
+        hash_cache: KVFileStore,
+        partial_cache: KVDirStore,
+        auth: Vec<Box<dyn Authenticator>>,
+        offline: Option<Arc<OfflineMisses>>,
+    ) -> Http {
+        Http(Arc::new(HttpInner::new(
+            http_cache,
+            hash_cache,
+            partial_cache,
+            auth,
+            offline,
+        )))
     }
 
     pub fn request(
@@ -96,6 +136,14 @@ impl Http {
         self.0.request(request, cache_mode)
     }
 
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.0.cancel
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.0.offline.is_some()
+    }
+
     pub fn get_hashed(
         &self,
         url: &Url,
@@ -105,16 +153,46 @@ impl Http {
         self.0.get_hashed(url, maybe_hash, cache_mode)
     }
 
+    pub fn gc(&self, max_age: Duration) -> Result<GcStats> {
+        let mut stats = self.0.http_cache.gc(max_age)?;
+        stats += self.0.hash_cache.gc(max_age)?;
+        stats += self.0.partial_cache.gc(max_age)?;
+        Ok(stats)
+    }
+
+    pub fn stores(&self) -> Vec<(&'static str, AnyStore)> {
+        vec![
+            ("http", AnyStore::File(&self.0.http_cache)),
+            ("by-hash", AnyStore::File(&self.0.hash_cache)),
+            ("partial", AnyStore::Dir(&self.0.partial_cache)),
+        ]
+    }
+
     pub fn get_lazy(&self, ai: &ArtifactInfo) -> Result<Box<dyn ReadPlusSeek>> {
-        match LazyRemoteFile::new(self.0.clone(), &ai.url) {
+        // range requests can't be cached, so offline the whole file is our only hope
+        if self.0.offline.is_some() {
+            return self.get_hashed(&ai.url, ai.hash.as_ref(), CacheMode::Default);
+        }
+        let inner = &self.0;
+        if !inner.range_support.worth_trying(&inner.http_cache, &ai.url) {
+            return self.get_hashed(&ai.url, ai.hash.as_ref(), CacheMode::Default);
+        }
+        match LazyRemoteFile::new(self.0.clone(), &ai.url, ai.hash.as_ref()) {
             Ok(lazy) => Ok(Box::new(lazy)),
             Err(err) => {
                 match err.downcast_ref::<PosyError>() {
                     // Doesn't support Range: requests, or similar issue. Fall back on
                     // fetching the whole file via the normal path.
-                    Some(PosyError::LazyRemoteFileNotSupported) => Ok(
-                        self.get_hashed(&ai.url, ai.hash.as_ref(), CacheMode::Default)?
-                    ),
+                    Some(PosyError::LazyRemoteFileNotSupported) => {
+                        inner
+                            .range_support
+                            .note_unsupported(&inner.http_cache, &ai.url);
+                        Ok(self.get_hashed(
+                            &ai.url,
+                            ai.hash.as_ref(),
+                            CacheMode::Default,
+                        )?)
+                    }
                     _ => Err(err)?,
                 }
             }
@@ -126,6 +204,15 @@ pub struct HttpInner {
     agent: ureq::Agent,
     http_cache: KVFileStore,
     hash_cache: KVFileStore,
+    // pieces of artifacts, by hash; see partial.rs
+    pub(super) partial_cache: KVDirStore,
+    // tried in order; see auth.rs
+    auth: Vec<Box<dyn Authenticator>>,
+    retry: RetryPolicy,
+    cancel: CancellationToken,
+    // if set, we never touch the network
+    offline: Option<Arc<OfflineMisses>>,
+    range_support: RangeSupport,
 }
 
 // pass in Option<ArtifactHash> to request/request_if_cached, thread through to fill_cache
@@ -194,21 +281,63 @@ fn key_for_request<T>(req: &http::Request<T>) -> Vec<u8> {
 }
 
 impl HttpInner {
-    pub fn new(http_cache: KVFileStore, hash_cache: KVFileStore) -> HttpInner {
+    pub fn new(
+        http_cache: KVFileStore,
+        hash_cache: KVFileStore,
+        partial_cache: KVDirStore,
+        auth: Vec<Box<dyn Authenticator>>,
+        offline: Option<Arc<OfflineMisses>>,
+    ) -> HttpInner {
         HttpInner {
-            agent: new_ureq_agent(),
+            agent: new_ureq_agent(timeout_from_env()),
             http_cache,
             hash_cache,
+            partial_cache,
+            auth,
+            retry: RetryPolicy::from_env(),
+            cancel: Default::default(),
+            offline,
+            range_support: Default::default(),
         }
     }
 
+    // When offline, everything turns into OnlyIfCached. If that's what the caller
+    // asked for anyway, they're just probing and will fall back on something else, so
+    // a miss only counts if we're the ones who changed the mode.
+    fn effective_cache_mode(&self, cache_mode: CacheMode) -> CacheMode {
+        if self.offline.is_some() {
+            CacheMode::OnlyIfCached
+        } else {
+            cache_mode
+        }
+    }
+
+    fn note_miss<T>(&self, url: &str, cache_mode: CacheMode, result: &Result<T>) {
+        if let (Some(misses), Err(err)) = (&self.offline, result) {
+            if cache_mode != CacheMode::OnlyIfCached && err.is::<NotCached>() {
+                misses.record(url);
+            }
+        }
+    }
+
+    fn authorization(&self, url: &Url) -> Result<Option<String>> {
+        for authenticator in &self.auth {
+            if let Some(value) = authenticator.authorization(url)? {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
     fn one_request(
         &self,
         request: &http::Request<()>,
         cache_mode: CacheMode,
     ) -> Result<http::Response<ReadPlusMaybeSeek>> {
         if cache_mode == CacheMode::NoStore {
-            let (parts, body) = do_request_ureq(&self.agent, request)?.into_parts();
+            let (parts, body) =
+                do_request_ureq(&self.agent, &self.retry, &self.cancel, request)?
+                    .into_parts();
             Ok(make_response(
                 parts,
                 ReadPlusMaybeSeek::CannotSeek(Box::new(body)),
@@ -262,7 +391,12 @@ impl HttpInner {
                             return Err(NotCached {}.into());
                         }
                         let request = http::Request::from_parts(new_parts, ());
-                        let response = do_request_ureq(&self.agent, &request)?;
+                        let response = do_request_ureq(
+                            &self.agent,
+                            &self.retry,
+                            &self.cancel,
+                            &request,
+                        )?;
                         match old_policy.after_response(
                             &request,
                             &response,
@@ -294,7 +428,8 @@ impl HttpInner {
                 if cache_mode == CacheMode::OnlyIfCached {
                     return Err(NotCached {}.into());
                 }
-                let response = do_request_ureq(&self.agent, request)?;
+                let response =
+                    do_request_ureq(&self.agent, &self.retry, &self.cancel, request)?;
                 let new_policy = CachePolicy::new(request, &response);
                 let (parts, body) = response.into_parts();
                 handle_new(new_policy, parts, body, CacheStatus::Miss, lock)
@@ -306,6 +441,29 @@ impl HttpInner {
         &self,
         mut request: http::Request<()>,
         cache_mode: CacheMode,
+    ) -> Result<http::Response<ReadPlusMaybeSeek>> {
+        if self.offline.is_some() {
+            // Any cache entry is better than nothing, however old. (Except that
+            // http-cache-semantics still won't use entries the server marked
+            // must-revalidate; those count as misses.)
+            request.headers_mut().insert(
+                http::header::CACHE_CONTROL,
+                http::HeaderValue::from_static("max-stale"),
+            );
+        }
+        let url = request.uri().to_string();
+        let result = self.request_following_redirects(
+            request,
+            self.effective_cache_mode(cache_mode),
+        );
+        self.note_miss(&url, cache_mode, &result);
+        result
+    }
+
+    fn request_following_redirects(
+        &self,
+        mut request: http::Request<()>,
+        cache_mode: CacheMode,
     ) -> Result<http::Response<ReadPlusMaybeSeek>> {
         let max_redirects = if request.method() == http::method::Method::GET {
             MAX_REDIRECTS
@@ -314,6 +472,18 @@ impl HttpInner {
         };
         for attempt in 0..=max_redirects {
             let url = Url::parse(&request.uri().to_string())?;
+            // Redo this on every hop, so a redirect to some other host doesn't get our
+            // credentials. (Note that http-cache-semantics won't store most responses
+            // to authenticated requests, so private indexes get re-fetched every time.
+            // Artifacts are fine, since they go through the hash cache.)
+            request.headers_mut().remove(http::header::AUTHORIZATION);
+            if let Some(value) = self.authorization(&url)? {
+                let mut value: http::HeaderValue = value.try_into()?;
+                value.set_sensitive(true);
+                request
+                    .headers_mut()
+                    .insert(http::header::AUTHORIZATION, value);
+            }
             let mut response = self.one_request(&request, cache_mode)?;
             if REDIRECT_STATUSES.contains(&response.status().as_u16()) {
                 if attempt < max_redirects {
@@ -342,24 +512,220 @@ impl HttpInner {
         cache_mode: CacheMode,
     ) -> Result<Box<dyn ReadPlusSeek>> {
         let request = http::Request::builder().uri(url.as_str()).body(())?;
-        match (maybe_hash, cache_mode) {
+        let result = match (maybe_hash, self.effective_cache_mode(cache_mode)) {
             (Some(hash), CacheMode::Default) => {
                 Ok(self.hash_cache.get_or_set(&hash, |mut w| {
-                    let mut body =
-                        self.request(request, CacheMode::NoStore)?.into_body();
+                    let partial = PartialEntry::open(&self.partial_cache, hash)?;
                     let mut checker = hash.checker(&mut w)?;
-                    std::io::copy(&mut body, &mut checker)?;
-                    checker.finish()?;
+                    self.download_resumable(url, &mut checker, &partial)?;
+                    let result = checker.finish();
+                    // either we're done with it, or it was bad (or at least, the
+                    // server disagrees with it now). Not a big deal if it sticks
+                    // around, though; e.g. on Windows, someone might have it open.
+                    if let Err(err) = partial.remove() {
+                        debug!("couldn't clean up partial download of {url}: {err:#}");
+                    }
+                    result?;
                     Ok(())
                 })?)
             }
             (Some(hash), CacheMode::OnlyIfCached) => {
                 self.hash_cache.get(&hash).ok_or_else(|| NotCached.into())
             }
-            (_, CacheMode::NoStore) | (None, _) => Ok(self
-                .request(request, cache_mode)?
-                .into_body()
-                .force_seek()?),
+            (_, mode @ CacheMode::NoStore) | (None, mode) => {
+                Ok(self.request(request, mode)?.into_body().force_seek()?)
+            }
+        };
+        self.note_miss(url.as_str(), cache_mode, &result);
+        result
+    }
+
+    // Streams `url` into `w`. ureq already retries if it can't connect, but if the
+    // connection dies in the middle of a big wheel, we pick up where we left off with
+    // a Range: request instead of starting over (which matters for e.g. 500 MB
+    // pytorch wheels on a flaky connection). That's only safe because `w` is checking
+    // the hash as it goes, so if the server hands us a different file the second time,
+    // we'll notice.
+    //
+    // Everything also goes into `partial`, and if we give up (or get interrupted),
+    // the next try starts from what's in there.
+    fn download_resumable(
+        &self,
+        url: &Url,
+        w: &mut dyn Write,
+        partial: &PartialEntry,
+    ) -> Result<()> {
+        let mut ranges = partial.ranges()?;
+        let mut saved = partial.data()?;
+        let mut written = ranges.prefix_len();
+        if written > 0 {
+            debug!("resuming download of {url} from byte {written}");
+            std::io::copy(&mut (&mut saved).take(written), w)?;
+        }
+        saved.seek(SeekFrom::Start(written))?;
+        let result = self.download_from(url, written, |data| {
+            w.write_all(data)?;
+            saved.write_all(data)?;
+            written += data.len() as u64;
+            Ok(())
+        });
+        if result.is_err() {
+            ranges.add(0, written);
+            partial.set_ranges(&ranges)?;
+        }
+        result
+    }
+
+    // Passes everything from byte `start` onwards to `sink`, retrying as needed.
+    fn download_from(
+        &self,
+        url: &Url,
+        start: u64,
+        mut sink: impl FnMut(&[u8]) -> Result<()>,
+    ) -> Result<()> {
+        let mut written = start;
+        let mut attempt = 0;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let mut builder = http::Request::builder().uri(url.as_str());
+            if written > 0 {
+                builder = builder.header("Range", format!("bytes={written}-"));
+            }
+            let response = self.request(builder.body(())?, CacheMode::NoStore)?;
+            let status = response.status().as_u16();
+            if status >= 400 {
+                bail!("error fetching {url}: {}", response.status().as_str());
+            }
+            if written > 0 && status != 206 {
+                // the server ignored our Range:, so it'll be sending from the start,
+                // and we can't un-write what we've already written
+                bail!(
+                    "lost connection while downloading {url}, and the server doesn't \
+                     support resuming"
+                );
+            }
+            let mut body = response.into_body();
+            let err = loop {
+                self.cancel.check()?;
+                match body.read(&mut buf) {
+                    Ok(0) => return Ok(()),
+                    Ok(n) => {
+                        sink(&buf[..n])?;
+                        written += n as u64;
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::Interrupted => (),
+                    Err(err) => break err,
+                }
+            };
+            match self.retry.backoff(attempt) {
+                Some(sleep_time) => {
+                    warn!(
+                        "download of {url} interrupted after {written} bytes ({err}); \
+                         resuming in {sleep_time:?}"
+                    );
+                    std::thread::sleep(sleep_time);
+                    attempt += 1;
+                }
+                None => return Err(err).wrap_err_with(|| format!("downloading {url}")),
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_offline_misses() {
+        let caches = tempfile::tempdir().unwrap();
+        let misses = Arc::new(OfflineMisses::default());
+        let http = HttpInner::new(
+            KVFileStore::new(&caches.path().join("http")).unwrap(),
+            KVFileStore::new(&caches.path().join("hashed")).unwrap(),
+            KVDirStore::new(&caches.path().join("partial")).unwrap(),
+            Vec::new(),
+            Some(misses.clone()),
+        );
+        // nothing listens on port 1, so if we tried the network this would be a
+        // connection error instead of NotCached
+        let page = Url::parse("http://127.0.0.1:1/simple/trio/").unwrap();
+        let wheel =
+            Url::parse("http://127.0.0.1:1/trio-0.22.0-py3-none-any.whl").unwrap();
+        let hash: ArtifactHash =
+            "sha256=0000000000000000000000000000000000000000000000000000000000000000"
+                .try_into()
+                .unwrap();
+
+        let request = http::Request::builder()
+            .uri(page.as_str())
+            .body(())
+            .unwrap();
+        let err = http.request(request, CacheMode::Default).err().unwrap();
+        assert!(err.is::<NotCached>());
+        let err = http
+            .get_hashed(&wheel, Some(&hash), CacheMode::Default)
+            .err()
+            .unwrap();
+        assert!(err.is::<NotCached>());
+        // probes that were already OnlyIfCached don't count
+        let other = Url::parse("http://127.0.0.1:1/other.whl").unwrap();
+        assert!(http
+            .get_hashed(&other, Some(&hash), CacheMode::OnlyIfCached)
+            .is_err());
+
+        assert_eq!(misses.take(), vec![page.to_string(), wheel.to_string()]);
+        assert!(misses.take().is_empty());
+    }
+
+    #[test]
+    fn test_resume_from_partial() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let contents: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(tempdir.path().join("blobby"), &contents).unwrap();
+        let server = crate::test_util::StaticHTTPServer::new(tempdir.path());
+        let url = server.url("blobby");
+        let hash = ArtifactHash {
+            mode: "sha256".into(),
+            raw_data: ring::digest::digest(&ring::digest::SHA256, &contents)
+                .as_ref()
+                .to_vec(),
+        };
+
+        let caches = tempfile::tempdir().unwrap();
+        let http = HttpInner::new(
+            KVFileStore::new(&caches.path().join("http")).unwrap(),
+            KVFileStore::new(&caches.path().join("hashed")).unwrap(),
+            KVDirStore::new(&caches.path().join("partial")).unwrap(),
+            Vec::new(),
+            None,
+        );
+        let partial_cache = &http.partial_cache;
+
+        // a bad prefix gets caught by the hash check, and thrown away
+        let entry = PartialEntry::open(partial_cache, &hash).unwrap();
+        entry.save(contents.len() as u64, 0, b"garbage").unwrap();
+        drop(entry);
+        assert!(http
+            .get_hashed(&url, Some(&hash), CacheMode::Default)
+            .is_err());
+        assert!(!super::super::partial::exists(partial_cache, &hash));
+
+        // a good one gets used (along with a chunk from somewhere in the middle)
+        let entry = PartialEntry::open(partial_cache, &hash).unwrap();
+        entry
+            .save(contents.len() as u64, 0, &contents[..1234])
+            .unwrap();
+        entry
+            .save(contents.len() as u64, 5000, &contents[5000..6000])
+            .unwrap();
+        drop(entry);
+        let mut body = http
+            .get_hashed(&url, Some(&hash), CacheMode::Default)
+            .unwrap();
+        let mut got = Vec::new();
+        body.read_to_end(&mut got).unwrap();
+        assert_eq!(got, contents);
+        assert!(!super::super::partial::exists(partial_cache, &hash));
+    }
+}
@@ -1,20 +1,48 @@
 use crate::prelude::*;
 
 use super::http::{CacheMode, HttpInner};
+use super::partial::PartialEntry;
 use std::cmp;
 use std::collections::BTreeMap;
+use std::fs::File;
 use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Arc;
 
 // semi-arbitrary, but ideally should be large enough to catch all the zip index +
 // dist-info data at the end of common wheel files
 const LAZY_FETCH_SIZE: u64 = 10_000;
 
+// Usually we only touch a few chunks of each file (the zip index and the dist-info),
+// but something that wanders around a multi-GB wheel could otherwise end up with the
+// whole thing in memory, once per file. So past this much, the least recently used
+// chunks get moved out to disk: into the artifact's entry in the partial cache (see
+// partial.rs), if we know its hash, so the next LazyRemoteFile or full download can
+// start from them; otherwise into a temp file.
+const MAX_IN_MEMORY: usize = 4 << 20;
+
+struct Chunk {
+    len: u64,
+    // None if it's been spilled (or was in the partial cache to begin with); then it's
+    // in the spill file at the same offset
+    data: Option<Vec<u8>>,
+    last_used: u64,
+}
+
 pub struct LazyRemoteFile {
-    http: Rc<HttpInner>,
+    http: Arc<HttpInner>,
     url: Url,
-    loaded: BTreeMap<u64, Vec<u8>>,
+    loaded: BTreeMap<u64, Chunk>,
     length: u64,
     seek_pos: u64,
+    // for LRU tracking; bumped every time we touch a chunk
+    tick: u64,
+    in_memory: usize,
+    max_in_memory: usize,
+    // if set, we spill into (and start from) its partial cache entry
+    hash: Option<ArtifactHash>,
+    // opened the first time we need it; if it's a temp file, it gets deleted
+    // automatically when dropped
+    spill: Option<File>,
 }
 
 impl Seek for LazyRemoteFile {
@@ -148,7 +176,18 @@ impl LazyRemoteFile {
             RangeResponse::Partial {
                 offset, mut data, ..
             } => {
-                self.loaded.insert(offset, slurp(&mut data)?);
+                let data = slurp(&mut data)?;
+                self.in_memory += data.len();
+                self.tick += 1;
+                self.loaded.insert(
+                    offset,
+                    Chunk {
+                        len: data.len() as u64,
+                        data: Some(data),
+                        last_used: self.tick,
+                    },
+                );
+                self.spill_until_under_limit()?;
                 Ok(())
             }
             RangeResponse::Complete(_) => {
@@ -156,37 +195,77 @@ impl LazyRemoteFile {
             }
         }
     }
-}
 
-impl Read for LazyRemoteFile {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        fn copy_loaded(
-            offset: u64,
-            loaded: &BTreeMap<u64, Vec<u8>>,
-            buf: &mut [u8],
-        ) -> Option<usize> {
-            // find the btree entry that's closest to the requested offset, if any
-            match loaded.range(..=offset).next_back() {
-                None => None,
-                Some((loaded_offset, loaded_data)) => {
-                    if let Ok(slide) =
-                        usize::try_from(offset.saturating_sub(*loaded_offset))
-                    {
-                        if slide < loaded_data.len() {
-                            let usable_loaded_data = &loaded_data[slide..];
-                            let len = cmp::min(buf.len(), usable_loaded_data.len());
-                            buf[..len].copy_from_slice(&usable_loaded_data[..len]);
-                            Some(len)
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
+    fn spill_until_under_limit(&mut self) -> Result<()> {
+        while self.in_memory > self.max_in_memory {
+            // the chunk we just loaded is always the most recently used, so it only
+            // gets spilled if it's too big to keep by itself, which is fine
+            let lru = self
+                .loaded
+                .iter_mut()
+                .filter(|(_, chunk)| chunk.data.is_some())
+                .min_by_key(|(_, chunk)| chunk.last_used);
+            let (offset, chunk) = match lru {
+                Some(lru) => lru,
+                None => break,
+            };
+            // unwrap safe because we just checked
+            let data = chunk.data.take().unwrap();
+            match &self.hash {
+                Some(hash) => {
+                    let entry = PartialEntry::open(&self.http.partial_cache, hash)?;
+                    entry.save(self.length, *offset, &data)?;
+                    if self.spill.is_none() {
+                        self.spill = Some(entry.data()?);
                     }
                 }
+                None => {
+                    if self.spill.is_none() {
+                        self.spill = Some(tempfile::tempfile()?);
+                    }
+                    // unwrap safe because we just checked
+                    let spill = self.spill.as_mut().unwrap();
+                    spill.seek(SeekFrom::Start(*offset))?;
+                    spill.write_all(&data)?;
+                }
+            }
+            self.in_memory -= data.len();
+        }
+        Ok(())
+    }
+
+    // Copies as much as we have already loaded at seek_pos into buf, if anything.
+    fn copy_loaded(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        // find the btree entry that's closest to the requested offset, if any
+        let (loaded_offset, chunk) =
+            match self.loaded.range_mut(..=self.seek_pos).next_back() {
+                Some(found) => found,
+                None => return Ok(None),
+            };
+        let slide = self.seek_pos - loaded_offset;
+        if slide >= chunk.len {
+            return Ok(None);
+        }
+        let len = cmp::min(buf.len() as u64, chunk.len - slide) as usize;
+        self.tick += 1;
+        chunk.last_used = self.tick;
+        match (&chunk.data, &mut self.spill) {
+            (Some(data), _) => {
+                let slide = slide as usize;
+                buf[..len].copy_from_slice(&data[slide..slide + len]);
+            }
+            (None, Some(spill)) => {
+                spill.seek(SeekFrom::Start(self.seek_pos))?;
+                spill.read_exact(&mut buf[..len])?;
             }
+            (None, None) => unreachable!("spilled chunk but no spill file"),
         }
+        Ok(Some(len))
+    }
+}
 
+impl Read for LazyRemoteFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         fn fix_err<T, E>(input: std::result::Result<T, E>) -> std::io::Result<T>
         where
             E: Into<Box<dyn std::error::Error + Send + Sync>>,
@@ -204,16 +283,14 @@ impl Read for LazyRemoteFile {
             return Ok(0);
         }
         // maybe we already have it in cache?
-        if let Some(len) = copy_loaded(self.seek_pos, &self.loaded, buf) {
+        if let Some(len) = self.copy_loaded(buf)? {
             self.seek_pos = self.seek_pos.saturating_add(fix_err(len.try_into())?);
             return Ok(len);
         }
         // otherwise, we need to fetch + fill in the cache
         // first find the empty gap around our current position
         let gap_start = match self.loaded.range(..=self.seek_pos).next_back() {
-            Some((loaded_offset, loaded_data)) => {
-                loaded_offset + (loaded_data.len() as u64)
-            }
+            Some((loaded_offset, chunk)) => loaded_offset + chunk.len,
             None => 0,
         };
         let gap_end = match self.loaded.range(self.seek_pos + 1..).next() {
@@ -230,7 +307,7 @@ impl Read for LazyRemoteFile {
         let fetch_end = fetch_end.clamp(gap_start, gap_end);
         fix_err(self.load_range(fetch_start, fetch_end - fetch_start))?;
         // now it's definitely in cache
-        if let Some(len) = copy_loaded(self.seek_pos, &self.loaded, buf) {
+        if let Some(len) = self.copy_loaded(buf)? {
             self.seek_pos = self.seek_pos.saturating_add(fix_err(len.try_into())?);
             return Ok(len);
         }
@@ -239,7 +316,13 @@ impl Read for LazyRemoteFile {
 }
 
 impl LazyRemoteFile {
-    pub fn new(http: Rc<HttpInner>, url: &Url) -> Result<LazyRemoteFile> {
+    /// If we know the file's `hash`, then we use whatever's in the partial cache for
+    /// it, and add to it.
+    pub fn new(
+        http: Arc<HttpInner>,
+        url: &Url,
+        hash: Option<&ArtifactHash>,
+    ) -> Result<LazyRemoteFile> {
         context!("Fetching metadata for {url}");
         // Instead of doing a HEAD request to get the length, it would be more efficient
         // to fetch the end of the file and the length in a single Range: request
@@ -256,12 +339,35 @@ impl LazyRemoteFile {
             RangeResponse::Partial { total_len, .. } => total_len,
             RangeResponse::Complete(_) => Err(PosyError::LazyRemoteFileNotSupported)?,
         };
+        let mut loaded = BTreeMap::new();
+        let mut spill = None;
+        if let Some(hash) = hash {
+            let entry = PartialEntry::open(&http.partial_cache, hash)?;
+            let ranges = entry.ranges()?;
+            // (if the length is different, we'll replace them the first time we spill)
+            if ranges.length == Some(length) && !ranges.ranges.is_empty() {
+                for (start, end) in ranges.ranges {
+                    let chunk = Chunk {
+                        len: end - start,
+                        data: None,
+                        last_used: 0,
+                    };
+                    loaded.insert(start, chunk);
+                }
+                spill = Some(entry.data()?);
+            }
+        }
         Ok(LazyRemoteFile {
             http,
             url: url.clone(),
-            loaded: BTreeMap::new(),
+            loaded,
             length,
             seek_pos: 0,
+            tick: 0,
+            in_memory: 0,
+            max_in_memory: MAX_IN_MEMORY,
+            hash: hash.cloned(),
+            spill,
         })
     }
 }
@@ -271,17 +377,20 @@ mod test {
     use std::fs::File;
     use std::io::prelude::*;
 
-    use crate::kvstore::KVFileStore;
+    use crate::kvstore::{KVDirStore, KVFileStore};
 
     use super::*;
 
-    fn tmp_http() -> (tempfile::TempDir, Rc<HttpInner>) {
+    fn tmp_http() -> (tempfile::TempDir, Arc<HttpInner>) {
         let caches = tempfile::tempdir().unwrap();
         let http = HttpInner::new(
             KVFileStore::new(&caches.path().join("http")).unwrap(),
             KVFileStore::new(&caches.path().join("hashed")).unwrap(),
+            KVDirStore::new(&caches.path().join("partial")).unwrap(),
+            Vec::new(),
+            None,
         );
-        (caches, Rc::new(http))
+        (caches, Arc::new(http))
     }
 
     #[test]
@@ -362,7 +471,7 @@ mod test {
         }
         let (_caches, http) = tmp_http();
 
-        let mut lazy = LazyRemoteFile::new(http, &server.url("blobby")).unwrap();
+        let mut lazy = LazyRemoteFile::new(http, &server.url("blobby"), None).unwrap();
 
         assert_eq!(lazy.seek(SeekFrom::End(0)).unwrap(), 3 * 13000);
         assert_eq!(lazy.seek(SeekFrom::Start(0)).unwrap(), 0);
@@ -427,11 +536,27 @@ mod test {
             }
         }
 
-        for seed in 0..5 {
+        // (it doesn't matter that this isn't really blobby's hash, since we never
+        // get the whole file)
+        let hash: ArtifactHash = format!("sha256={}", "00".repeat(32))
+            .as_str()
+            .try_into()
+            .unwrap();
+        for seed in 0..8 {
             let rng = fastrand::Rng::with_seed(seed);
             let mut f = File::open(tempdir.path().join("blobby")).unwrap();
+            // on odd seeds, make it spill chunks to disk a lot; on seeds 1 and 5, into
+            // the partial cache, which the later ones then start from
+            let capped = seed % 2 == 1;
+            let hash = (seed % 4 == 1).then_some(&hash);
             let mut lazy =
-                LazyRemoteFile::new(http.clone(), &server.url("blobby")).unwrap();
+                LazyRemoteFile::new(http.clone(), &server.url("blobby"), hash).unwrap();
+            if seed == 5 {
+                assert!(!lazy.loaded.is_empty());
+            }
+            if capped {
+                lazy.max_in_memory = 3 * LAZY_FETCH_SIZE as usize;
+            }
 
             for _ in 0..100 {
                 let seek = if rng.bool() {
@@ -445,7 +570,9 @@ mod test {
                 let lazy_buf = read_exactish(&mut lazy, seek, read_size);
 
                 assert_eq!(f_buf, lazy_buf);
+                assert!(lazy.in_memory <= lazy.max_in_memory);
             }
+            assert_eq!(lazy.spill.is_some(), capped || seed == 5);
         }
     }
 }
@@ -1,7 +1,11 @@
+pub mod auth;
 mod http;
 pub mod lazy_remote_file;
+mod partial;
+mod range_support;
 pub mod ureq_glue;
 pub mod user_agent;
 
-pub use self::http::{CacheMode, Http, HttpInner, NotCached};
+pub use self::auth::{default_authenticators, UrlCredentials};
+pub use self::http::{CacheMode, Http, HttpInner, NotCached, OfflineMisses};
 pub use self::lazy_remote_file::LazyRemoteFile;
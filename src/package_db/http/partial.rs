@@ -0,0 +1,200 @@
+use crate::prelude::*;
+
+use std::fs::{self, File};
+use std::io::SeekFrom;
+
+use crate::kvstore::{KVDirLock, KVDirStore};
+
+// Bits of artifacts that we've only downloaded part of, keyed by the artifact's hash:
+// whatever chunks LazyRemoteFile pulled out of a big wheel to read its metadata, or
+// however far a full download got before the connection (or the user) gave up. Next
+// time we want the same file, we start from there.
+//
+// Each entry is a directory with:
+// - "data": a sparse file, with every piece we have at its real offset
+// - "ranges.json": which [start, end) ranges of "data" are actually filled in
+//
+// We can't check any of this against the hash until we have the whole file, so it's
+// only as trustworthy as a range request would be. Full downloads replay it through
+// the hash checker, so a bad entry gets caught there (and thrown away).
+//
+// Entries change over time, unlike most stores, so everything that touches one holds
+// its lock.
+
+const DATA: &str = "data";
+const RANGES: &str = "ranges.json";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ranges {
+    // the whole file's length, if we know it
+    pub length: Option<u64>,
+    // sorted, non-overlapping, non-adjacent
+    pub ranges: Vec<(u64, u64)>,
+}
+
+impl Ranges {
+    pub fn add(&mut self, start: u64, end: u64) {
+        if start >= end {
+            return;
+        }
+        self.ranges.push((start, end));
+        self.ranges.sort_unstable();
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(self.ranges.len());
+        for &(start, end) in &self.ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        self.ranges = merged;
+    }
+
+    /// How many bytes we have from the start of the file, without any gaps.
+    pub fn prefix_len(&self) -> u64 {
+        match self.ranges.first() {
+            Some(&(0, end)) => end,
+            _ => 0,
+        }
+    }
+}
+
+/// A locked entry in the partial cache. Unlocked when dropped.
+pub struct PartialEntry {
+    lock: KVDirLock,
+}
+
+impl PartialEntry {
+    pub fn open(store: &KVDirStore, hash: &ArtifactHash) -> Result<PartialEntry> {
+        let lock = store.lock(hash)?;
+        fs::create_dir_all(&*lock)?;
+        Ok(PartialEntry { lock })
+    }
+
+    pub fn data_path(&self) -> std::path::PathBuf {
+        self.lock.join(DATA)
+    }
+
+    /// What we have. If the entry looks damaged (e.g. the data file is shorter than
+    /// the ranges say), we start over.
+    pub fn ranges(&self) -> Result<Ranges> {
+        let path = self.lock.join(RANGES);
+        let ranges: Ranges = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Ranges::default())
+            }
+            Err(err) => Err(err)?,
+        };
+        let have = fs::metadata(self.data_path()).map_or(0, |m| m.len());
+        match ranges.ranges.last() {
+            Some(&(_, end)) if end > have => Ok(Ranges::default()),
+            _ => Ok(ranges),
+        }
+    }
+
+    pub fn set_ranges(&self, ranges: &Ranges) -> Result<()> {
+        // tempfile + rename, so a crash leaves either the old list or the new one
+        let mut out = tempfile::NamedTempFile::new_in(&*self.lock)?;
+        serde_json::to_writer(&mut out, ranges)?;
+        out.persist(self.lock.join(RANGES))?;
+        Ok(())
+    }
+
+    /// Opens the data file for reading and writing, creating it if needed.
+    pub fn data(&self) -> Result<File> {
+        Ok(fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(self.data_path())?)
+    }
+
+    /// Saves `data`, which came from `offset` in a file of `length` bytes.
+    pub fn save(&self, length: u64, offset: u64, data: &[u8]) -> Result<()> {
+        let mut ranges = self.ranges()?;
+        if ranges.length.map_or(false, |l| l != length) {
+            // must be a different file; hopefully the other one's gone for good
+            ranges = Ranges::default();
+        }
+        ranges.length = Some(length);
+        let mut file = self.data()?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+        ranges.add(offset, offset + data.len() as u64);
+        self.set_ranges(&ranges)
+    }
+
+    /// We got the whole file, or found out that what we had was bad.
+    pub fn remove(self) -> Result<()> {
+        self.lock.remove()
+    }
+}
+
+#[cfg(test)]
+pub fn exists(store: &KVDirStore, hash: &ArtifactHash) -> bool {
+    store
+        .lock_if_exists(hash)
+        .map_or(false, |lock| lock.exists())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ranges() {
+        let mut ranges = Ranges::default();
+        assert_eq!(ranges.prefix_len(), 0);
+        ranges.add(10, 20);
+        ranges.add(30, 40);
+        assert_eq!(ranges.prefix_len(), 0);
+        ranges.add(0, 5);
+        assert_eq!(ranges.prefix_len(), 5);
+        // overlapping and adjacent ones get merged
+        ranges.add(15, 30);
+        ranges.add(5, 10);
+        ranges.add(7, 7);
+        assert_eq!(ranges.ranges, vec![(0, 40)]);
+        assert_eq!(ranges.prefix_len(), 40);
+        ranges.add(50, 60);
+        ranges.add(45, 55);
+        assert_eq!(ranges.ranges, vec![(0, 40), (45, 60)]);
+    }
+
+    #[test]
+    fn test_partial_entry() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = KVDirStore::new(tmp.path()).unwrap();
+        let hash: ArtifactHash = format!("sha256={}", "ab".repeat(32))
+            .as_str()
+            .try_into()
+            .unwrap();
+        assert!(!exists(&store, &hash));
+
+        let entry = PartialEntry::open(&store, &hash).unwrap();
+        assert_eq!(entry.ranges().unwrap(), Ranges::default());
+        entry.save(100, 50, b"hello").unwrap();
+        entry.save(100, 0, b"abc").unwrap();
+        drop(entry);
+        assert!(exists(&store, &hash));
+
+        let entry = PartialEntry::open(&store, &hash).unwrap();
+        let ranges = entry.ranges().unwrap();
+        assert_eq!(ranges.length, Some(100));
+        assert_eq!(ranges.ranges, vec![(0, 3), (50, 55)]);
+        let data = fs::read(entry.data_path()).unwrap();
+        assert_eq!(&data[..3], b"abc");
+        assert_eq!(&data[50..], b"hello");
+
+        // a different length means a different file
+        entry.save(200, 10, b"xy").unwrap();
+        assert_eq!(entry.ranges().unwrap().ranges, vec![(10, 12)]);
+
+        // if the data got truncated, the ranges are no good
+        fs::write(entry.data_path(), b"").unwrap();
+        assert_eq!(entry.ranges().unwrap(), Ranges::default());
+
+        entry.remove().unwrap();
+        assert!(!exists(&store, &hash));
+    }
+}
@@ -0,0 +1,110 @@
+use crate::prelude::*;
+
+use crate::kvstore::KVFileStore;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Some servers ignore Range: headers and send back the whole file with a 200. When
+// that happens, LazyRemoteFile gives up and get_lazy falls back on downloading the
+// whole thing -- which is fine once, but without this we'd also waste a round-trip
+// rediscovering it for every single file on that host, on every run.
+//
+// So we remember which origins (scheme+host+port) don't do ranges: in memory for this
+// session, and in the http cache for future ones. The on-disk memory expires after a
+// while, in case the server gets fixed.
+
+const FORGET_AFTER: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Default)]
+pub struct RangeSupport {
+    // origin -> whether it supports ranges
+    known: Mutex<HashMap<String, bool>>,
+}
+
+fn store_key(origin: &str) -> Vec<u8> {
+    format!("no-range-support:{origin}").into_bytes()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl RangeSupport {
+    /// Whether it's worth trying a Range: request against `url`.
+    pub fn worth_trying(&self, store: &KVFileStore, url: &Url) -> bool {
+        let origin = url.origin().ascii_serialization();
+        if let Some(&supported) = self.known.lock().unwrap().get(&origin) {
+            return supported;
+        }
+        let noted_at = store
+            .get(&store_key(&origin).as_slice())
+            .and_then(|mut r| slurp(&mut r).ok())
+            .and_then(|b| String::from_utf8(b).ok())
+            .and_then(|s| s.trim().parse::<u64>().ok());
+        let supported = match noted_at {
+            Some(at) => now().saturating_sub(at) > FORGET_AFTER.as_secs(),
+            None => true,
+        };
+        self.known.lock().unwrap().insert(origin, supported);
+        supported
+    }
+
+    pub fn note_unsupported(&self, store: &KVFileStore, url: &Url) {
+        let origin = url.origin().ascii_serialization();
+        debug!("{origin} doesn't support range requests; not trying them again");
+        self.known.lock().unwrap().insert(origin.clone(), false);
+        // overwrite, so an expired note gets refreshed
+        let result = (|| -> Result<()> {
+            let lock = store.lock(&store_key(&origin).as_slice())?;
+            let mut w = lock.begin()?;
+            w.write_all(now().to_string().as_bytes())?;
+            w.commit()?;
+            Ok(())
+        })();
+        // it's only an optimization, so not worth failing over
+        if let Err(err) = result {
+            debug!("couldn't save range support for {origin}: {err:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_range_support() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = KVFileStore::new(tmp.path()).unwrap();
+        let bad = Url::parse("https://files.example.com/a/b.whl").unwrap();
+        let bad_too = Url::parse("https://files.example.com/c/d.whl").unwrap();
+        let good = Url::parse("https://files.pythonhosted.org/a/b.whl").unwrap();
+
+        let support = RangeSupport::default();
+        assert!(support.worth_trying(&store, &bad));
+        support.note_unsupported(&store, &bad);
+        assert!(!support.worth_trying(&store, &bad_too));
+        assert!(support.worth_trying(&store, &good));
+
+        // remembered across sessions
+        let support = RangeSupport::default();
+        assert!(!support.worth_trying(&store, &bad_too));
+        assert!(support.worth_trying(&store, &good));
+
+        // ...but not forever
+        let old = (now() - FORGET_AFTER.as_secs() - 1).to_string();
+        let lock = store
+            .lock(&store_key("https://files.example.com").as_slice())
+            .unwrap();
+        let mut w = lock.begin().unwrap();
+        w.write_all(old.as_bytes()).unwrap();
+        w.commit().unwrap();
+        drop(lock);
+        let support = RangeSupport::default();
+        assert!(support.worth_trying(&store, &bad));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::interrupt::CancellationToken;
 use crate::prelude::*;
 
 use std::io::Read;
@@ -6,19 +7,92 @@ use ureq::{Agent, AgentBuilder, Error::*, OrAnyStatus};
 
 use super::user_agent::user_agent;
 
-pub fn new_ureq_agent() -> Agent {
+pub fn new_ureq_agent(timeout: Duration) -> Agent {
     AgentBuilder::new()
         .user_agent(&user_agent())
         // we handle redirects in the caching layer
         .redirects(0)
-        .timeout_read(Duration::from_secs(15))
-        .timeout_write(Duration::from_secs(15))
+        .timeout_connect(timeout)
+        .timeout_read(timeout)
+        .timeout_write(timeout)
         .build()
 }
 
-const SLEEP_TIMES: &[u64] = &[250, 500, 1000, 2000, 4000]; // milliseconds
-                                                           // Copied from pip/_internal/network/session.py
-const RETRY_STATUS: &[u16] = &[500, 503, 520, 527];
+const TIMEOUT_VAR: &str = "POSY_HTTP_TIMEOUT";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long each request can go without making progress -- connecting, or between
+/// reads/writes -- before we give up on it (and maybe retry). It's not a limit on the
+/// whole request, since big wheels can legitimately take ages to download.
+/// Configurable in seconds with $POSY_HTTP_TIMEOUT.
+pub fn timeout_from_env() -> Duration {
+    match std::env::var(TIMEOUT_VAR) {
+        Ok(value) => match value.trim().parse::<f64>() {
+            Ok(secs) if secs > 0.0 && secs.is_finite() => Duration::from_secs_f64(secs),
+            _ => {
+                warn!(
+                    "ignoring ${TIMEOUT_VAR}={value:?}; expected a number of seconds"
+                );
+                DEFAULT_TIMEOUT
+            }
+        },
+        Err(_) => DEFAULT_TIMEOUT,
+    }
+}
+
+/// How hard to try when the network is flaky. Configurable with
+/// $POSY_HTTP_RETRIES, for people on bad connections (or CI systems that would rather
+/// fail fast).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    // same as pip: 0.25s, 0.5s, 1s, 2s, 4s
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 5,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(120),
+        }
+    }
+}
+
+const RETRIES_VAR: &str = "POSY_HTTP_RETRIES";
+
+impl RetryPolicy {
+    pub fn from_env() -> RetryPolicy {
+        let mut policy = RetryPolicy::default();
+        if let Ok(value) = std::env::var(RETRIES_VAR) {
+            match value.trim().parse() {
+                Ok(retries) => policy.retries = retries,
+                Err(_) => warn!("ignoring ${RETRIES_VAR}={value:?}; expected a number"),
+            }
+        }
+        policy
+    }
+
+    /// How long to wait before retry number `attempt` (counting from 0), or None if
+    /// we've run out.
+    pub fn backoff(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.retries {
+            return None;
+        }
+        let factor = 2u32.saturating_pow(attempt);
+        Some(
+            self.initial_backoff
+                .saturating_mul(factor)
+                .min(self.max_backoff),
+        )
+    }
+}
+
+// Mostly copied from pip/_internal/network/session.py, plus the usual "a proxy or load
+// balancer is having a bad day" codes.
+const RETRY_STATUS: &[u16] = &[429, 500, 502, 503, 504, 520, 527];
 // https://docs.rs/ureq/2.1.1/ureq/enum.ErrorKind.html
 // This is my attempt to pick out the ones that seem (potentially) transient
 use ureq::ErrorKind::*;
@@ -27,6 +101,8 @@ const RETRY_ERRORKIND: &[ureq::ErrorKind] =
 
 fn call_with_retry(
     req: ureq::Request,
+    retry: &RetryPolicy,
+    cancel: &CancellationToken,
 ) -> std::result::Result<ureq::Response, ureq::Error> {
     // Pip's retry logic is in
     //    pip/_internal/network/session.py
@@ -38,7 +114,7 @@ fn call_with_retry(
     // - also retries on connect-related errors, read errors, "other errors"
     // - default 5 attempts, can be overridden by cmdline option
 
-    let mut iterator = SLEEP_TIMES.iter();
+    let mut attempt = 0;
     loop {
         let this_req = req.clone();
         let result = this_req.call();
@@ -55,8 +131,16 @@ fn call_with_retry(
                 }
             }
         }
-        match iterator.next() {
-            Some(sleep_time) => std::thread::sleep(Duration::from_millis(*sleep_time)),
+        // no point retrying if nobody wants the answer anymore
+        if cancel.is_cancelled() {
+            return result;
+        }
+        match retry.backoff(attempt) {
+            Some(sleep_time) => {
+                debug!("retrying {} in {sleep_time:?}", req.url());
+                std::thread::sleep(sleep_time);
+                attempt += 1;
+            }
             None => return result,
         }
     }
@@ -64,19 +148,62 @@ fn call_with_retry(
 
 pub fn do_request_ureq(
     agent: &Agent,
+    retry: &RetryPolicy,
+    cancel: &CancellationToken,
     req: &http::Request<()>,
 ) -> Result<http::Response<impl Read>> {
+    cancel.check()?;
     let mut ureq_req =
         agent.request_url(req.method().as_str(), &Url::parse(&req.uri().to_string())?);
     for (name, value) in req.headers().into_iter() {
         ureq_req = ureq_req.set(name.as_str(), std::str::from_utf8(value.as_bytes())?);
     }
-    let ureq_response = call_with_retry(ureq_req).or_any_status()?;
+    let ureq_response = call_with_retry(ureq_req, retry, cancel);
+    cancel.check()?;
+    let ureq_response = ureq_response.or_any_status()?;
     let mut response = http::Response::builder().status(ureq_response.status());
     for name in ureq_response.headers_names() {
         for value in ureq_response.all(&name) {
             response = response.header(&name, value);
         }
     }
-    Ok(response.body(ureq_response.into_reader())?)
+    Ok(response.body(Counted(ureq_response.into_reader()))?)
+}
+
+// Adds up what comes over the wire, for the summary at the end (see stats.rs).
+struct Counted<R>(R);
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.0.read(buf)?;
+        crate::stats::downloaded(n as u64);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_retry_backoff() {
+        let policy = RetryPolicy::default();
+        let backoffs = (0..10)
+            .map_while(|attempt| policy.backoff(attempt))
+            .map(|d| d.as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(backoffs, vec![250, 500, 1000, 2000, 4000]);
+
+        let policy = RetryPolicy {
+            retries: 40,
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff(39), Some(policy.max_backoff));
+
+        let policy = RetryPolicy {
+            retries: 0,
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff(0), None);
+    }
 }
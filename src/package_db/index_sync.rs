@@ -0,0 +1,126 @@
+use std::path::Path;
+
+use indexmap::IndexMap;
+
+use super::package_db::PackageDB;
+use super::simple_api::{
+    fetch_simple_api_page, pack_by_version, SimpleApiPage, SnapshotWriter,
+};
+use crate::prelude::*;
+
+// Keeping an index snapshot up to date for a fixed allowlist of packages, for 'posy
+// index sync'. The idea is that an organization runs this on a schedule (say, hourly)
+// into a shared directory, and developers resolve with --index-snapshot pointing at
+// it. Then their resolves never touch the network during the day, but still see new
+// releases of the allowlisted packages as of the last sync.
+//
+// Along with the simple API pages, we save core metadata for the newest few releases
+// of each package, since that's what the resolver needs to look at most. Older
+// releases still work, but the resolver will have to fetch their metadata from the
+// network the old-fashioned way.
+
+#[derive(Debug, Default)]
+pub struct IndexSyncReport {
+    pub synced: usize,
+    pub metadata_saved: usize,
+    pub dropped: usize,
+    pub failed: Vec<(PackageName, eyre::Report)>,
+}
+
+impl<'db> PackageDB<'db> {
+    /// Refreshes the index snapshot at `dir` so that it has exactly `packages` in it,
+    /// plus metadata for the newest `metadata_versions` releases of each. If some
+    /// packages can't be fetched, we keep their old pages (if any) and report them in
+    /// `failed`.
+    pub fn sync_index_snapshot(
+        &self,
+        dir: &Path,
+        packages: &[PackageName],
+        metadata_versions: usize,
+    ) -> Result<IndexSyncReport> {
+        let mut writer = SnapshotWriter::open(dir)?;
+        let mut report = IndexSyncReport::default();
+        // XX TODO: with ~hundreds of packages this is crying out to be done in
+        // parallel, like we do for unpacking wheels in EnvForest::get_env
+        for package in packages {
+            match self.sync_one(&mut writer, package, metadata_versions) {
+                Ok(metadata_saved) => {
+                    report.synced += 1;
+                    report.metadata_saved += metadata_saved;
+                }
+                Err(err) => {
+                    warn!("couldn't sync {}: {err:#}", package.as_given());
+                    report.failed.push((package.clone(), err));
+                }
+            }
+        }
+        report.dropped = writer.retain(&packages.iter().cloned().collect());
+        writer.finish()?;
+        Ok(report)
+    }
+
+    fn sync_one(
+        &self,
+        writer: &mut SnapshotWriter,
+        package: &PackageName,
+        metadata_versions: usize,
+    ) -> Result<usize> {
+        context!("Syncing {} into the index snapshot", package.as_given());
+        let page = self.fetch_page(package)?.ok_or_else(|| {
+            eyre!("couldn't find {} on any package index", package.as_given())
+        })?;
+        // do the metadata first, so that it's already there when the new page appears
+        let metadata_saved = self.sync_metadata(writer, &page, metadata_versions)?;
+        writer.save_page(package, &page)?;
+        Ok(metadata_saved)
+    }
+
+    // A snapshot only has one page per package, so unlike available_artifacts, we don't
+    // merge the indexes together; the first index that has the package wins.
+    fn fetch_page(&self, package: &PackageName) -> Result<Option<SimpleApiPage>> {
+        for index_url in self.index_urls_for(package) {
+            let url = index_url.join(&format!("{}/", package.normalized()))?;
+            if let Some(page) = fetch_simple_api_page(&self.http, &url)? {
+                return Ok(Some(page));
+            }
+        }
+        Ok(None)
+    }
+
+    fn sync_metadata(
+        &self,
+        writer: &SnapshotWriter,
+        page: &SimpleApiPage,
+        metadata_versions: usize,
+    ) -> Result<usize> {
+        let mut packed = IndexMap::new();
+        pack_by_version(page.parse()?, &mut packed)?;
+        packed.sort_unstable_by(|v1, _, v2, _| v2.cmp(v1));
+
+        let mut saved = 0;
+        for ais in packed.values().take(metadata_versions) {
+            let already_saved = ais.iter().any(|ai| match &ai.hash {
+                Some(hash) => writer.has_metadata(hash),
+                None => false,
+            });
+            if already_saved {
+                continue;
+            }
+            let ai = if ais.iter().any(|ai| ai.is::<Wheel>()) {
+                self.get_metadata::<Wheel, _>(ais, None)?.0
+            } else if ais.iter().any(|ai| ai.is::<Pybi>()) {
+                self.get_metadata::<Pybi, _>(ais, None)?.0
+            } else {
+                // sdist-only releases need a build to get their metadata, which is way
+                // too slow to do for a whole allowlist
+                continue;
+            };
+            // get_metadata leaves a copy in our metadata cache
+            if let (Some(hash), Some(blob)) = (&ai.hash, self.metadata_from_cache(ai)) {
+                writer.save_metadata(hash, &blob)?;
+                saved += 1;
+            }
+        }
+        Ok(saved)
+    }
+}
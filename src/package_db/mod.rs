@@ -1,8 +1,15 @@
+mod attestations;
 mod build_wheel;
 mod http;
+mod index_sync;
 mod package_db;
+mod prefetch;
 mod simple_api;
+mod wheel_tags;
 
-pub use build_wheel::WheelBuilder;
-pub use package_db::PackageDB;
-pub use simple_api::ArtifactInfo;
+pub use self::http::OfflineMisses;
+pub use attestations::AttestationPolicy;
+pub use build_wheel::{BuildProvenance, WheelBuilder, BUILD_PROVENANCE_NAME};
+pub use index_sync::IndexSyncReport;
+pub use package_db::{ArtifactFetcher, IndexStrategy, PackageDB, YankedPolicy};
+pub use simple_api::{ArtifactInfo, SimpleApiSnapshot};
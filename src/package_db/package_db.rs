@@ -1,49 +1,239 @@
 use crate::env::EnvForest;
+use crate::interrupt::CancellationToken;
 use crate::prelude::*;
 use elsa::FrozenMap;
 use indexmap::IndexMap;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
-use super::http::{CacheMode, Http, NotCached};
-use super::simple_api::{fetch_simple_api, pack_by_version, ArtifactInfo};
-use crate::kvstore::{KVDirStore, KVFileStore};
+use super::attestations::{check_attestations, AttestationPolicy};
+use super::http::{
+    default_authenticators, CacheMode, Http, NotCached, OfflineMisses, UrlCredentials,
+};
+use super::prefetch::Prefetcher;
+use super::simple_api::{
+    fetch_simple_api, pack_by_version, ArtifactInfo, ProjectInfo, SimpleApiSnapshot,
+};
+use crate::kvstore::{AnyStore, GcStats, KVDirStore, KVFileStore};
+use crate::resolve::{Blueprint, ResolveMemo};
+use crate::util::percent_decode;
 
 static NO_ARTIFACTS: [ArtifactInfo; 0] = [];
 
+/// What to do when more than one index has a page for the same project.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum IndexStrategy {
+    /// Use files from every index that has the project (like pip's
+    /// --extra-index-url).
+    #[default]
+    Merge,
+    /// Only use the first index that has the project at all, so later indexes can't
+    /// slip in files under the same name.
+    FirstMatch,
+}
+
+/// What to do with files that the index says have been yanked (PEP 592).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum YankedPolicy {
+    /// Never use them, even if the lock file pins one.
+    Forbid,
+    /// Only use them if the lock file already pins them, like PEP 592 says.
+    #[default]
+    AllowPinned,
+    /// Treat them like any other file.
+    Allow,
+}
+
+impl YankedPolicy {
+    /// Whether we can use `ai`. `pinned` is whether some lock file already has it.
+    pub fn allows(&self, ai: &ArtifactInfo, pinned: bool) -> bool {
+        !ai.yanked.yanked
+            || match self {
+                YankedPolicy::Forbid => false,
+                YankedPolicy::AllowPinned => pinned,
+                YankedPolicy::Allow => true,
+            }
+    }
+}
+
 pub struct PackageDB<'a> {
-    http: Http,
+    pub(super) http: Http,
+    prefetcher: Prefetcher<Vec<u8>>,
+    // index pages get their own, so a burst of metadata fetches can't crowd them out
+    page_prefetcher: Prefetcher<Option<ProjectInfo>>,
     metadata_cache: KVFileStore,
-    index_urls: Vec<Url>,
+    pub(super) index_urls: Vec<Url>,
+    // the credentials that were in any of our index URLs, which http sends for us
+    url_credentials: UrlCredentials,
+    // if set, we use this instead of index_urls
+    snapshot: Option<SimpleApiSnapshot>,
+    // these are only consulted for index_urls, not the snapshot
+    pub index_strategy: IndexStrategy,
+    // projects that must only ever come from one particular index (which doesn't have
+    // to be in index_urls), e.g. internal packages that live on a private index and
+    // shouldn't be shadowed by a same-named upload to PyPI (see set_index_pins)
+    index_pins: HashMap<PackageName, Url>,
+    // Private indexes often carry patched builds of public packages, like 1.2.3+corp1.
+    // For the packages in here, local versions from the given index outrank every other
+    // version (see is_preferred_local_version). The index gets consulted for them even
+    // if it's not in index_urls. (See set_prefer_local_versions.)
+    prefer_local_versions: HashMap<PackageName, Url>,
+    // Indexes that advertise PEP 658 metadata files they can't actually serve, like
+    // some mirrors do (see IndexQuirks::looks_like_mirror). We'd fall back on range
+    // requests anyway, but only after a wasted round-trip per wheel. (See
+    // set_no_metadata_files.)
+    no_metadata_files: HashSet<Url>,
+    pub yanked_policy: YankedPolicy,
+    pub attestation_policy: AttestationPolicy,
+    // retry builds that fail for want of pip, with pip added (see
+    // build_wheel::PIP_SHIM)
+    pub build_pip_shim: bool,
 
     pub(super) wheel_cache: KVDirStore,
     pub(super) build_forest: &'a EnvForest,
     pub(super) build_store: &'a KVDirStore,
+    // shared by every resolve that uses this db
+    pub resolve_memo: ResolveMemo,
+    // build environments that a lockfile pinned, by sdist hash (see use_locked_builds)
+    locked_builds: RefCell<HashMap<ArtifactHash, Blueprint>>,
 
     // memo table to make sure we're internally consistent within a single invocation,
     // and to let us return references instead of copying everything everywhere
     artifacts: FrozenMap<PackageName, Box<IndexMap<Version, Vec<ArtifactInfo>>>>,
+    // projects that no index had a page for at all, as opposed to a page with no files
+    missing_projects: RefCell<HashSet<PackageName>>,
+    // the versions that prefer_local_versions applies to, as we find them
+    preferred_local_versions: RefCell<HashMap<PackageName, HashSet<Version>>>,
+    // files referred to by URL, e.g. a custom pybi, instead of found on an index
+    direct_artifacts: FrozenMap<Url, Box<ArtifactInfo>>,
+    // publisher (if any) for each artifact whose attestations we've already checked
+    attestations: RefCell<HashMap<Url, Option<String>>>,
 }
 
 impl<'db> PackageDB<'db> {
+    /// If `offline` is given, we never touch the network, and record anything we
+    /// needed but couldn't find in the cache there.
     pub fn new(
         index_urls: &[Url],
         cache_path: &Path,
         build_forest: &'db EnvForest,
         build_store: &'db KVDirStore,
+        offline: Option<Arc<OfflineMisses>>,
+    ) -> Result<PackageDB<'db>> {
+        PackageDB::new_inner(
+            index_urls,
+            None,
+            cache_path,
+            build_forest,
+            build_store,
+            offline,
+        )
+    }
+
+    /// Like `new`, but looks up packages in a directory of saved simple API pages,
+    /// instead of on a real index.
+    pub fn from_snapshot(
+        snapshot: SimpleApiSnapshot,
+        cache_path: &Path,
+        build_forest: &'db EnvForest,
+        build_store: &'db KVDirStore,
+        offline: Option<Arc<OfflineMisses>>,
+    ) -> Result<PackageDB<'db>> {
+        PackageDB::new_inner(
+            &[],
+            Some(snapshot),
+            cache_path,
+            build_forest,
+            build_store,
+            offline,
+        )
+    }
+
+    fn new_inner(
+        index_urls: &[Url],
+        snapshot: Option<SimpleApiSnapshot>,
+        cache_path: &Path,
+        build_forest: &'db EnvForest,
+        build_store: &'db KVDirStore,
+        offline: Option<Arc<OfflineMisses>>,
     ) -> Result<PackageDB<'db>> {
         let http_cache = KVFileStore::new(&cache_path.join("http"))?;
         let hash_cache = KVFileStore::new(&cache_path.join("by-hash"))?;
+        let (index_urls, url_credentials, auth) = default_authenticators(index_urls)?;
+        let partial_cache = KVDirStore::new(&cache_path.join("partial"))?;
+        let http = Http::new(http_cache, hash_cache, partial_cache, auth, offline);
         Ok(PackageDB {
-            http: Http::new(http_cache, hash_cache),
+            prefetcher: Prefetcher::new(http.clone()),
+            page_prefetcher: Prefetcher::new(http.clone()),
+            http,
             metadata_cache: KVFileStore::new(&cache_path.join("metadata"))?,
             wheel_cache: KVDirStore::new(&cache_path.join("local-wheels"))?,
-            index_urls: index_urls.into(),
+            index_urls,
+            url_credentials,
+            snapshot,
+            index_strategy: Default::default(),
+            index_pins: Default::default(),
+            prefer_local_versions: Default::default(),
+            no_metadata_files: Default::default(),
+            yanked_policy: Default::default(),
+            attestation_policy: Default::default(),
+            build_pip_shim: false,
             build_forest,
             build_store,
+            resolve_memo: Default::default(),
+            locked_builds: Default::default(),
             artifacts: Default::default(),
+            missing_projects: Default::default(),
+            preferred_local_versions: Default::default(),
+            direct_artifacts: Default::default(),
+            attestations: Default::default(),
         })
     }
 
+    /// Sets the index_pins. Like index_urls, any credentials in the URLs are taken out
+    /// and sent as headers instead.
+    pub fn set_index_pins(&mut self, pins: &HashMap<PackageName, Url>) -> Result<()> {
+        self.index_pins = pins
+            .iter()
+            .map(|(name, url)| Ok((name.clone(), self.url_credentials.strip(url)?)))
+            .collect::<Result<_>>()?;
+        Ok(())
+    }
+
+    /// Same as set_index_pins, but for prefer_local_versions.
+    pub fn set_prefer_local_versions(
+        &mut self,
+        indexes: &HashMap<PackageName, Url>,
+    ) -> Result<()> {
+        self.prefer_local_versions = indexes
+            .iter()
+            .map(|(name, url)| Ok((name.clone(), self.url_credentials.strip(url)?)))
+            .collect::<Result<_>>()?;
+        Ok(())
+    }
+
+    /// Same as set_index_pins, but for no_metadata_files.
+    pub fn set_no_metadata_files(&mut self, indexes: &[Url]) -> Result<()> {
+        self.no_metadata_files = indexes
+            .iter()
+            .map(|url| self.url_credentials.strip(url))
+            .collect::<Result<_>>()?;
+        Ok(())
+    }
+
+    /// Cancelling this makes whatever this db is in the middle of -- resolving,
+    /// building, downloading -- fail with PosyError::Interrupted. It's safe to do
+    /// from another thread; the caches never see anything half-written.
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        self.http.cancellation_token()
+    }
+
     pub fn artifacts_for_version(
         &self,
         p: &PackageName,
@@ -56,6 +246,37 @@ impl<'db> PackageDB<'db> {
         }
     }
 
+    /// The artifact at `url`, for direct references like 'NAME @ URL'. Since there's no
+    /// index page to get a hash from, the URL's fragment has to have one.
+    pub fn direct_artifact(&self, url: &Url) -> Result<&ArtifactInfo> {
+        if let Some(ai) = self.direct_artifacts.get(url) {
+            return Ok(ai);
+        }
+        context!("Looking at direct reference {url}");
+        let filename = url
+            .path_segments()
+            .and_then(|mut s| s.next_back())
+            .ok_or_else(|| eyre!("no filename in URL"))?;
+        let name: ArtifactName = percent_decode(filename)?.as_str().try_into()?;
+        let hash = url
+            .fragment()
+            .and_then(|f| ArtifactHash::try_from(f).ok())
+            .ok_or_else(|| eyre!("direct references need a hash, like #sha256=..."))?;
+        Ok(self.direct_artifacts.insert(
+            url.clone(),
+            Box::new(ArtifactInfo {
+                name,
+                url: url.clone(),
+                hash: Some(hash),
+                requires_python: None,
+                dist_info_metadata: Default::default(),
+                yanked: Default::default(),
+                size: None,
+                provenance: None,
+            }),
+        ))
+    }
+
     // always sorted from most recent to least recent
     pub fn available_artifacts(
         &self,
@@ -65,17 +286,63 @@ impl<'db> PackageDB<'db> {
         if let Some(cached) = self.artifacts.get(p) {
             Ok(cached)
         } else {
+            self.cancellation_token().check()?;
             let mut packed: IndexMap<Version, Vec<ArtifactInfo>> = Default::default();
+            let mut found = false;
 
-            for index_url in self.index_urls.iter() {
-                let maybe_pi = fetch_simple_api(
-                    &self.http,
-                    &index_url.join(&format!("{}/", p.normalized()))?,
-                )?;
-                if let Some(pi) = maybe_pi {
+            if let Some(snapshot) = &self.snapshot {
+                pack_by_version(snapshot.project_info(p)?, &mut packed)?;
+                found = true;
+            }
+            for index_url in self.index_urls_for(p) {
+                let preferred = self.prefer_local_versions.get(p) == Some(index_url);
+                let first_match = self.index_strategy == IndexStrategy::FirstMatch;
+                if found && first_match && !preferred {
+                    continue;
+                }
+                let url = index_url.join(&format!("{}/", p.normalized()))?;
+                // if prefetch_projects already started on this, wait for that
+                let maybe_pi = match self.page_prefetcher.take(&url) {
+                    Some(Ok(pi)) => pi,
+                    prefetched => {
+                        if let Some(Err(err)) = prefetched {
+                            debug!("prefetching {url} failed: {err:#}");
+                        }
+                        fetch_simple_api(&self.http, &url)?
+                    }
+                };
+                if let Some(mut pi) = maybe_pi {
+                    if self.no_metadata_files.contains(index_url) {
+                        for ai in &mut pi.artifacts {
+                            ai.dist_info_metadata = Default::default();
+                        }
+                    }
+                    if preferred {
+                        if found && first_match {
+                            // an earlier index has the project, so that's where
+                            // everything else comes from
+                            pi.artifacts
+                                .retain(|ai| !ai.name.version().0.local.is_empty());
+                        }
+                        let local = pi
+                            .artifacts
+                            .iter()
+                            .map(|ai| ai.name.version())
+                            .filter(|version| !version.0.local.is_empty())
+                            .cloned();
+                        self.preferred_local_versions
+                            .borrow_mut()
+                            .entry(p.clone())
+                            .or_default()
+                            .extend(local);
+                    }
                     pack_by_version(pi, &mut packed)?;
+                    found = true;
                 }
             }
+            if !found {
+                self.missing_projects.borrow_mut().insert(p.clone());
+            }
 
             // sort artifact-infos (arbitrarily) by name, just to have a consistent
             // order from run-to-run (and make resolution output more consistent)
@@ -89,8 +356,57 @@ impl<'db> PackageDB<'db> {
         }
     }
 
-    fn metadata_from_cache(&self, ai: &ArtifactInfo) -> Option<Vec<u8>> {
-        slurp(&mut self.metadata_cache.get(&ai.hash.as_ref()?)?).ok()
+    /// The indexes to look for `p` in, in priority order.
+    pub(super) fn index_urls_for(&self, p: &PackageName) -> Vec<&Url> {
+        let mut urls = match self.index_pins.get(p) {
+            Some(pinned) => vec![pinned],
+            None => self.index_urls.iter().collect(),
+        };
+        // The index with the preferred local builds goes last. Its local builds win no
+        // matter where it is (see fetch_and_sort_versions), and if it went first, then
+        // with FirstMatch it would hide every upstream version. (available_artifacts
+        // still takes the local builds from it, even with FirstMatch.)
+        if let Some(preferred) = self.prefer_local_versions.get(p) {
+            if !urls.contains(&preferred) {
+                urls.push(preferred);
+            }
+        }
+        urls
+    }
+
+    /// Whether `version` of `p` is one of the local builds that prefer_local_versions
+    /// says should win over everything else.
+    pub fn is_preferred_local_version(
+        &self,
+        p: &PackageName,
+        version: &Version,
+    ) -> bool {
+        self.preferred_local_versions
+            .borrow()
+            .get(p)
+            .map_or(false, |versions| versions.contains(version))
+    }
+
+    /// The index that `p`'s preferred local builds come from, if it has any.
+    pub fn preferred_local_index(&self, p: &PackageName) -> Option<&Url> {
+        self.prefer_local_versions.get(p)
+    }
+
+    /// Whether any of our indexes know about `p` at all. A project can exist and still
+    /// have no files, e.g. if they've all been deleted.
+    pub fn project_exists(&self, p: &PackageName) -> Result<bool> {
+        self.available_artifacts(p)?;
+        Ok(!self.missing_projects.borrow().contains(p))
+    }
+
+    pub(super) fn metadata_from_cache(&self, ai: &ArtifactInfo) -> Option<Vec<u8>> {
+        let hash = ai.hash.as_ref()?;
+        if let Some(snapshot) = &self.snapshot {
+            if let Some(blob) = snapshot.metadata(hash) {
+                return Some(blob);
+            }
+        }
+        slurp(&mut self.metadata_cache.get(&hash)?).ok()
     }
 
     fn put_metadata_in_cache(&self, ai: &ArtifactInfo, blob: &[u8]) -> Result<()> {
@@ -101,24 +417,6 @@ impl<'db> PackageDB<'db> {
         Ok(())
     }
 
-    fn open_artifact<T>(
-        &self,
-        ai: &ArtifactInfo,
-        body: Box<dyn ReadPlusSeek>,
-    ) -> Result<T>
-    where
-        T: Artifact,
-    {
-        let artifact_name = ai
-            .name
-            .inner_as::<T::Name>()
-            .ok_or_else(|| {
-                eyre!("{} is not a {}", ai.name, std::any::type_name::<T>())
-            })?
-            .clone();
-        T::new(artifact_name, body)
-    }
-
     pub fn get_metadata<'a, T, B>(
         &self,
         artifacts: &'a [B],
@@ -135,11 +433,14 @@ impl<'db> PackageDB<'db> {
                 .filter(|ai| ai.is::<T>())
         };
 
+        self.cancellation_token().check()?;
+
         // have we cached any of these artifacts' metadata before?
         // don't use matching() here because that filters for binary artifacts, and we
         // cache metadata for wheels as well.
         for ai in artifacts.iter().map(|b| b.borrow()) {
             if let Some(cm) = self.metadata_from_cache(ai) {
+                crate::stats::metadata_lookup(true);
                 return Ok((ai, T::parse_metadata(cm.as_slice())?));
             }
         }
@@ -151,6 +452,7 @@ impl<'db> PackageDB<'db> {
                 Ok(artifact) => {
                     let (blob, metadata) = artifact.metadata()?;
                     self.put_metadata_in_cache(ai, &blob)?;
+                    crate::stats::metadata_lookup(true);
                     return Ok((ai, metadata));
                 }
                 Err(err) => match err.downcast_ref::<NotCached>() {
@@ -162,31 +464,31 @@ impl<'db> PackageDB<'db> {
 
         // okay, we don't have it locally; gotta actually hit the network.
 
-        // XX TODO: PEP 658 support
-        // also, extra complication: when dist_info_metadata is available, we might also
-        // have a hash for the metadata. Should we check it, and how does that interact
-        // with caching? I guess that when TUF arrives we'll need to look carefully to
-        // make sure all that data we fetch is TUF-protected, and in the mean time we're
-        // relying on the index+https being trustworthy anyway -- both to give us the
-        // hashes, and also for the lazy_remote_file path that can't validate any
-        // hashes. (But then why are we validating hashes when we download artifacts? I
-        // guess it's really only important when *installing* where we want to confirm
-        // hashes haven't changed since someone else resolved, not *resolving*, where we
-        // collect the hashes in the first place, and this function is on the resolve
-        // path?)
-        //
-        // for ai in matching() {
-        //     if ai.dist_info_metadata.available {
-        //         todo!()
-        //     }
-        // }
-
-        // try pulling the metadata out of a remote wheel, and cache it for later
+        // When TUF arrives we'll need to look carefully to make sure all that data we
+        // fetch is TUF-protected, and in the mean time we're relying on the index+https
+        // being trustworthy anyway -- both to give us the hashes, and also for the
+        // lazy_remote_file path that can't validate any hashes. (But then why are we
+        // validating hashes when we download artifacts? I guess it's really only
+        // important when *installing* where we want to confirm hashes haven't changed
+        // since someone else resolved, not *resolving*, where we collect the hashes in
+        // the first place, and this function is on the resolve path?)
+
+        // try getting the metadata for a remote wheel, and cache it for later
         if let Some(ai) = matching().next() {
-            let body = self.http.get_lazy(ai)?;
-            let artifact = self.open_artifact::<T>(ai, body)?;
-            let (blob, metadata) = artifact.metadata()?;
+            // if prefetch_metadata already started on this, then wait for that instead
+            // of starting over
+            let blob = match self.prefetcher.take(&ai.url) {
+                Some(Ok(blob)) => blob,
+                prefetched => {
+                    if let Some(Err(err)) = prefetched {
+                        debug!("prefetching metadata from {} failed: {err:#}", ai.url);
+                    }
+                    fetch_remote_metadata::<T>(&self.http, ai)?
+                }
+            };
+            let metadata = T::parse_metadata(blob.as_slice())?;
             self.put_metadata_in_cache(ai, &blob)?;
+            crate::stats::metadata_lookup(false);
             return Ok((ai, metadata));
         }
 
@@ -197,6 +499,7 @@ impl<'db> PackageDB<'db> {
                 if let Some(result) = T::locally_built_metadata(builder, ai) {
                     let (blob, metadata) = result?;
                     self.put_metadata_in_cache(ai, &blob)?;
+                    crate::stats::metadata_lookup(false);
                     return Ok((ai, metadata));
                 }
             }
@@ -209,23 +512,168 @@ impl<'db> PackageDB<'db> {
         );
     }
 
+    /// Starts fetching the index pages that `available_artifacts` will want for
+    /// `projects` in the background, so that they can all be in flight at once.
+    /// Best-effort, like `prefetch_metadata`.
+    pub fn prefetch_projects<'p, I>(&self, projects: I)
+    where
+        I: IntoIterator<Item = &'p PackageName>,
+    {
+        if self.http.is_offline() {
+            return;
+        }
+        for p in projects {
+            if self.artifacts.get(p).is_some() {
+                continue;
+            }
+            let mut index_urls = self.index_urls_for(p);
+            // with FirstMatch, the later indexes only matter if the first one doesn't
+            // have the project, which is the unusual case -- except for the one with
+            // preferred local builds, which we always look at
+            if self.index_strategy == IndexStrategy::FirstMatch {
+                let preferred = self.prefer_local_versions.get(p);
+                let mut i = 0;
+                index_urls.retain(|&url| {
+                    i += 1;
+                    i == 1 || Some(url) == preferred
+                });
+            }
+            for index_url in index_urls {
+                if let Ok(url) = index_url.join(&format!("{}/", p.normalized())) {
+                    let job_url = url.clone();
+                    self.page_prefetcher.submit(
+                        &url,
+                        Box::new(move |http| fetch_simple_api(http, &job_url)),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Starts fetching the metadata that `get_metadata::<T>(artifacts, ...)` will
+    /// probably want in the background, so that hopefully it's ready by the time
+    /// someone asks. Best-effort: may decide not to bother.
+    pub fn prefetch_metadata<T, B>(&self, artifacts: &[B])
+    where
+        B: std::borrow::Borrow<ArtifactInfo>,
+        T: BinaryArtifact + 'static,
+    {
+        // offline, a speculative fetch would just show up as a spurious cache miss
+        if self.http.is_offline() {
+            return;
+        }
+        let mut artifacts = artifacts.iter().map(|b| b.borrow());
+        if artifacts
+            .clone()
+            .any(|ai| self.metadata_from_cache(ai).is_some())
+        {
+            return;
+        }
+        // the same one get_metadata would fetch from
+        if let Some(ai) = artifacts.find(|ai| ai.is::<T>()) {
+            let ai = ai.clone();
+            let url = ai.url.clone();
+            self.prefetcher.submit(
+                &url,
+                Box::new(move |http| fetch_remote_metadata::<T>(http, &ai)),
+            );
+        }
+    }
+
     fn _get_artifact<T>(&self, ai: &ArtifactInfo, cache_mode: CacheMode) -> Result<T>
     where
         T: Artifact,
     {
-        let body = self
-            .http
-            .get_hashed(&ai.url, ai.hash.as_ref(), cache_mode)?;
-        self.open_artifact::<T>(ai, body)
+        get_artifact_via(&self.http, ai, cache_mode)
+    }
+
+    /// Who `ai`'s PEP 740 attestations say published it, if we're reading them (see
+    /// `attestation_policy`). Errors out if they don't match the file.
+    pub fn attestation(&self, ai: &ArtifactInfo) -> Result<Option<String>> {
+        if let Some(publisher) = self.attestations.borrow().get(&ai.url) {
+            return Ok(publisher.clone());
+        }
+        let publisher = check_attestations(&self.http, ai, self.attestation_policy)?;
+        self.attestations
+            .borrow_mut()
+            .insert(ai.url.clone(), publisher.clone());
+        Ok(publisher)
     }
 
     pub fn get_artifact<T>(&self, ai: &ArtifactInfo) -> Result<T>
     where
         T: Artifact,
     {
+        self.attestation(ai)?;
         self._get_artifact(ai, CacheMode::Default)
     }
 
+    /// Prunes everything in our caches that hasn't been used in `max_age`.
+    pub fn gc(&self, max_age: Duration) -> Result<GcStats> {
+        let mut stats = self.http.gc(max_age)?;
+        stats += self.metadata_cache.gc(max_age)?;
+        stats += self.wheel_cache.gc(max_age)?;
+        Ok(stats)
+    }
+
+    /// Each of our caches, by name, for 'posy cache'.
+    pub fn cache_stores(&self) -> Vec<(&'static str, AnyStore)> {
+        let mut stores = self.http.stores();
+        stores.push(("metadata", AnyStore::File(&self.metadata_cache)));
+        stores.push(("local-wheels", AnyStore::Dir(&self.wheel_cache)));
+        stores
+    }
+
+    /// A handle for downloading artifacts from worker threads. (The PackageDB itself
+    /// has to stay on one thread.)
+    pub fn fetcher(&self) -> ArtifactFetcher {
+        ArtifactFetcher {
+            http: self.http.clone(),
+            attestation_policy: self.attestation_policy,
+        }
+    }
+
+    /// From now on, building any of these sdists (keyed by hash, like
+    /// Lockfile::builds) uses exactly the given build environment, instead of
+    /// resolving its build requirements again.
+    pub fn use_locked_builds(
+        &self,
+        builds: &BTreeMap<String, Blueprint>,
+    ) -> Result<()> {
+        let mut locked = self.locked_builds.borrow_mut();
+        for (hash, blueprint) in builds {
+            locked.insert(hash.as_str().try_into()?, blueprint.clone());
+        }
+        Ok(())
+    }
+
+    pub fn locked_build(&self, sdist_hash: &ArtifactHash) -> Option<Blueprint> {
+        self.locked_builds.borrow().get(sdist_hash).cloned()
+    }
+
+    /// The build environment that building `sdist_ai` uses: the locked one if there is
+    /// one, or else whatever we resolved when we built it earlier in this run. None if
+    /// we haven't built it.
+    pub fn build_blueprint(
+        &self,
+        sdist_ai: &ArtifactInfo,
+    ) -> Result<Option<Blueprint>> {
+        let hash = sdist_ai.require_hash()?;
+        if let Some(blueprint) = self.locked_build(hash) {
+            return Ok(Some(blueprint));
+        }
+        // build_store only lives as long as we do, so this is always from this run
+        let handle = match self.build_store.lock_if_exists(hash) {
+            Some(handle) => handle,
+            None => return Ok(None),
+        };
+        match fs::File::open(handle.join("saved-blueprint.json")) {
+            Ok(f) => Ok(Some(serde_json::from_reader(f)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e)?,
+        }
+    }
+
     pub fn get_locally_built_binary<T: BinaryArtifact>(
         &self,
         ai: &ArtifactInfo,
@@ -235,3 +683,212 @@ impl<'db> PackageDB<'db> {
         T::locally_built_binary(builder, ai, platform)
     }
 }
+
+#[derive(Clone)]
+pub struct ArtifactFetcher {
+    http: Http,
+    attestation_policy: AttestationPolicy,
+}
+
+impl ArtifactFetcher {
+    pub fn get_artifact<T>(&self, ai: &ArtifactInfo) -> Result<T>
+    where
+        T: Artifact,
+    {
+        check_attestations(&self.http, ai, self.attestation_policy)?;
+        get_artifact_via(&self.http, ai, CacheMode::Default)
+    }
+}
+
+fn get_artifact_via<T>(
+    http: &Http,
+    ai: &ArtifactInfo,
+    cache_mode: CacheMode,
+) -> Result<T>
+where
+    T: Artifact,
+{
+    crate::events::emit(crate::events::Event::FetchingArtifact {
+        artifact: ai.name.to_string(),
+        url: ai.url.to_string(),
+    });
+    let body = http.get_hashed(&ai.url, ai.hash.as_ref(), cache_mode)?;
+    open_artifact::<T>(ai, body)
+}
+
+// Gets the raw core metadata for a remote binary artifact, as cheaply as we can: the
+// index's standalone copy if it has one (PEP 658), or else by poking at the artifact
+// itself with range requests (or downloading the whole thing, if the server doesn't do
+// ranges).
+fn fetch_remote_metadata<T>(http: &Http, ai: &ArtifactInfo) -> Result<Vec<u8>>
+where
+    T: BinaryArtifact,
+{
+    // offline, we only have whatever get_metadata already found in the caches
+    if ai.dist_info_metadata.available && !http.is_offline() {
+        match fetch_pep658_metadata(http, ai) {
+            Ok(blob) => return Ok(blob),
+            Err(err) => {
+                debug!("couldn't get PEP 658 metadata for {}: {err:#}", ai.name)
+            }
+        }
+    }
+    let body = http.get_lazy(ai)?;
+    Ok(open_artifact::<T>(ai, body)?.metadata()?.0)
+}
+
+fn fetch_pep658_metadata(http: &Http, ai: &ArtifactInfo) -> Result<Vec<u8>> {
+    let mut url = ai.url.clone();
+    // the #sha256=... fragment goes on the end, not in the middle
+    url.set_fragment(None);
+    let url = Url::parse(&format!("{url}.metadata"))?;
+    let mut body = http.get_hashed(
+        &url,
+        ai.dist_info_metadata.hash.as_ref(),
+        CacheMode::Default,
+    )?;
+    slurp(&mut body)
+}
+
+fn open_artifact<T>(ai: &ArtifactInfo, body: Box<dyn ReadPlusSeek>) -> Result<T>
+where
+    T: Artifact,
+{
+    let artifact_name = ai
+        .name
+        .inner_as::<T::Name>()
+        .ok_or_else(|| eyre!("{} is not a {}", ai.name, std::any::type_name::<T>()))?
+        .clone();
+    T::new(artifact_name, body)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package_db::http::auth::Authenticator;
+    use crate::package_db::simple_api::Yanked;
+    use crate::test_util::{index_page as page, with_index_db};
+
+    #[test]
+    fn test_project_exists() {
+        with_index_db(
+            vec![
+                ("/simple/empty/", page(&[])),
+                ("/simple/trio/", page(&[r#"href="trio-0.22.0.tar.gz""#])),
+            ],
+            |db| {
+                // no files is still different from no project
+                assert!(db.project_exists(&"empty".parse().unwrap()).unwrap());
+                assert!(db.project_exists(&"Trio".parse().unwrap()).unwrap());
+                assert!(!db.project_exists(&"missing".parse().unwrap()).unwrap());
+            },
+        );
+    }
+
+    #[test]
+    fn test_no_metadata_files() {
+        let files = [r#"href="foo-1.0-py3-none-any.whl" data-core-metadata="true""#];
+        let routes = vec![
+            ("/simple/foo/", page(&files)),
+            ("/simple/bar/", page(&[&files[0].replace("foo", "bar")])),
+        ];
+        with_index_db(routes, |db| {
+            let available = |db: &PackageDB, name: &str| {
+                let name: PackageName = name.parse().unwrap();
+                db.available_artifacts(&name).unwrap()[0][0]
+                    .dist_info_metadata
+                    .available
+            };
+            assert!(available(db, "foo"));
+            let index = db.index_urls[0].clone();
+            db.set_no_metadata_files(&[index]).unwrap();
+            assert!(!available(db, "bar"));
+        });
+    }
+
+    #[test]
+    fn test_prefer_local_versions() {
+        let upstream = [r#"href="foo-1.0.tar.gz""#, r#"href="foo-2.0.tar.gz""#];
+        let corp = [
+            r#"href="foo-1.0+corp1.tar.gz""#,
+            // with FirstMatch, only the local builds come from here
+            r#"href="foo-3.0.tar.gz""#,
+        ];
+        let routes = vec![
+            ("/simple/foo/", page(&upstream)),
+            ("/corp/foo/", page(&corp)),
+        ];
+        with_index_db(routes, |db| {
+            let name: PackageName = "foo".parse().unwrap();
+            let corp = db.index_urls[0].join("/corp/").unwrap();
+            let prefer = HashMap::from([(name.clone(), corp.clone())]);
+            db.set_prefer_local_versions(&prefer).unwrap();
+            db.index_strategy = IndexStrategy::FirstMatch;
+            // the preferred index goes last, so it can't hide upstream versions
+            assert_eq!(db.index_urls_for(&name), vec![&db.index_urls[0], &corp]);
+
+            let versions = db
+                .available_artifacts(&name)
+                .unwrap()
+                .keys()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(versions, vec!["2.0", "1.0+corp1", "1.0"]);
+            let local = "1.0+corp1".try_into().unwrap();
+            assert!(db.is_preferred_local_version(&name, &local));
+            assert!(!db.is_preferred_local_version(&name, &"2.0".try_into().unwrap()));
+        });
+    }
+
+    #[test]
+    fn test_index_pin_credentials() {
+        with_index_db(vec![], |db| {
+            let name: PackageName = "internal-utils".parse().unwrap();
+            let pinned = Url::parse("https://bob:pw@corp.example.com/simple/").unwrap();
+            db.set_index_pins(&HashMap::from([(name.clone(), pinned)]))
+                .unwrap();
+            let clean = Url::parse("https://corp.example.com/simple/").unwrap();
+            assert_eq!(db.index_urls_for(&name), vec![&clean]);
+            let file = clean.join("/files/internal_utils-1.0.tar.gz").unwrap();
+            // base64 of "bob:pw"
+            assert_eq!(
+                db.url_credentials.authorization(&file).unwrap(),
+                Some("Basic Ym9iOnB3".into())
+            );
+        });
+    }
+
+    #[test]
+    fn test_yanked_policy() {
+        let ai = |yanked: bool| ArtifactInfo {
+            name: "trio-0.22.0.tar.gz".try_into().unwrap(),
+            url: Url::parse("https://example.com/trio-0.22.0.tar.gz").unwrap(),
+            hash: None,
+            requires_python: None,
+            dist_info_metadata: Default::default(),
+            yanked: Yanked {
+                yanked,
+                reason: None,
+            },
+            size: None,
+            provenance: None,
+        };
+        let (fine, yanked) = (ai(false), ai(true));
+        for policy in [
+            YankedPolicy::Forbid,
+            YankedPolicy::AllowPinned,
+            YankedPolicy::Allow,
+        ] {
+            // non-yanked files are always fine
+            assert!(policy.allows(&fine, false));
+            assert!(policy.allows(&fine, true));
+        }
+        assert!(!YankedPolicy::Forbid.allows(&yanked, false));
+        assert!(!YankedPolicy::Forbid.allows(&yanked, true));
+        assert!(!YankedPolicy::AllowPinned.allows(&yanked, false));
+        assert!(YankedPolicy::AllowPinned.allows(&yanked, true));
+        assert!(YankedPolicy::Allow.allows(&yanked, false));
+        assert!(YankedPolicy::Allow.allows(&yanked, true));
+        assert_eq!(YankedPolicy::default(), YankedPolicy::AllowPinned);
+    }
+}
@@ -0,0 +1,150 @@
+use std::cell::RefCell;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+
+use super::http::Http;
+use crate::prelude::*;
+
+// Background fetching for the resolver. PackageDB has to stay on one thread, so
+// without this every index page and metadata fetch is a network round-trip that the
+// solver sits and waits for, one after another. Instead, whenever the resolver learns
+// about some new dependencies, it tells us which projects' pages and which files'
+// metadata it'll probably want next, and a small pool of worker threads starts on them
+// right away. By the time the solver gets around to asking, the answer is often
+// already here; if not, the PackageDB waits for the fetch that's already running
+// instead of starting a second one.
+//
+// It's all speculative: if the solver ends up going a different way, we've wasted a
+// little bandwidth, nothing more. Which is also why we cap how much can be in flight
+// at once.
+
+const WORKERS: usize = 8;
+const MAX_IN_FLIGHT: usize = 32;
+
+pub type PrefetchJob<T> = Box<dyn FnOnce(&Http) -> Result<T> + Send>;
+
+type Reply<T> = mpsc::SyncSender<Result<T>>;
+
+enum Prefetch<T> {
+    InFlight(mpsc::Receiver<Result<T>>),
+    Done(Result<T>),
+}
+
+pub struct Prefetcher<T> {
+    http: Http,
+    // the workers get started the first time someone submits a job, so commands that
+    // never resolve anything don't pay for them. Dropping this shuts them down.
+    jobs: RefCell<Option<mpsc::Sender<(PrefetchJob<T>, Reply<T>)>>>,
+    // keyed by URL, which is what identifies a fetch from Http's point of view
+    fetches: RefCell<HashMap<Url, Prefetch<T>>>,
+}
+
+impl<T: Send + 'static> Prefetcher<T> {
+    pub fn new(http: Http) -> Prefetcher<T> {
+        Prefetcher {
+            http,
+            jobs: Default::default(),
+            fetches: Default::default(),
+        }
+    }
+
+    fn start_workers(&self) -> mpsc::Sender<(PrefetchJob<T>, Reply<T>)> {
+        let (tx, rx) = mpsc::channel::<(PrefetchJob<T>, Reply<T>)>();
+        let rx = Arc::new(Mutex::new(rx));
+        for _ in 0..WORKERS {
+            let rx = rx.clone();
+            let http = self.http.clone();
+            std::thread::spawn(move || loop {
+                // only hold the lock while waiting, not while working
+                let next = rx.lock().unwrap().recv();
+                match next {
+                    // if nobody's listening for the answer anymore, that's fine
+                    Ok((job, reply)) => drop(reply.send(job(&http))),
+                    // the Prefetcher is gone
+                    Err(_) => break,
+                }
+            });
+        }
+        tx
+    }
+
+    fn in_flight(&self) -> usize {
+        let mut fetches = self.fetches.borrow_mut();
+        for fetch in fetches.values_mut() {
+            if let Prefetch::InFlight(rx) = fetch {
+                match rx.try_recv() {
+                    Ok(result) => *fetch = Prefetch::Done(result),
+                    Err(mpsc::TryRecvError::Empty) => (),
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        *fetch = Prefetch::Done(Err(eyre!("prefetch worker died")))
+                    }
+                }
+            }
+        }
+        fetches
+            .values()
+            .filter(|f| matches!(f, Prefetch::InFlight(_)))
+            .count()
+    }
+
+    /// Starts running `job` in the background, unless we're already fetching `url`, or
+    /// already have too much going on.
+    pub fn submit(&self, url: &Url, job: PrefetchJob<T>) {
+        // (separate statement so the borrow is gone before in_flight() borrows again)
+        let known = self.fetches.borrow().contains_key(url);
+        if known || self.in_flight() >= MAX_IN_FLIGHT {
+            return;
+        }
+        let mut jobs = self.jobs.borrow_mut();
+        let tx = jobs.get_or_insert_with(|| self.start_workers());
+        let (reply_tx, reply_rx) = mpsc::sync_channel(1);
+        if tx.send((job, reply_tx)).is_ok() {
+            trace!("prefetching {url}");
+            self.fetches
+                .borrow_mut()
+                .insert(url.clone(), Prefetch::InFlight(reply_rx));
+        }
+    }
+
+    /// If we've started fetching `url`, waits for it to finish and returns the result.
+    pub fn take(&self, url: &Url) -> Option<Result<T>> {
+        let fetch = self.fetches.borrow_mut().remove(url)?;
+        Some(match fetch {
+            Prefetch::Done(result) => result,
+            Prefetch::InFlight(rx) => rx
+                .recv()
+                .unwrap_or_else(|_| Err(eyre!("prefetch worker died"))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kvstore::{KVDirStore, KVFileStore};
+
+    #[test]
+    fn test_prefetcher() {
+        let caches = tempfile::tempdir().unwrap();
+        let http = Http::new(
+            KVFileStore::new(&caches.path().join("http")).unwrap(),
+            KVFileStore::new(&caches.path().join("hashed")).unwrap(),
+            KVDirStore::new(&caches.path().join("partial")).unwrap(),
+            Vec::new(),
+            None,
+        );
+        let prefetcher = Prefetcher::new(http);
+        let a = Url::parse("https://example.com/a.whl").unwrap();
+        let b = Url::parse("https://example.com/b.whl").unwrap();
+
+        assert!(prefetcher.take(&a).is_none());
+        prefetcher.submit(&a, Box::new(|_| Ok(b"metadata for a".to_vec())));
+        // a second submit for the same URL is ignored
+        prefetcher.submit(&a, Box::new(|_| panic!("shouldn't run")));
+        prefetcher.submit(&b, Box::new(|_| Err(eyre!("nope"))));
+
+        assert_eq!(prefetcher.take(&a).unwrap().unwrap(), b"metadata for a");
+        assert!(prefetcher.take(&a).is_none());
+        assert!(prefetcher.take(&b).unwrap().is_err());
+    }
+}
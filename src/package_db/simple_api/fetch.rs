@@ -1,5 +1,6 @@
 use super::super::http::{CacheMode, Http};
 use super::project_info::ProjectInfo;
+use super::quirks::IndexQuirks;
 use crate::prelude::*;
 
 use http::Request;
@@ -12,8 +13,45 @@ struct CacheEntry {
     body: String,
 }
 
-pub fn fetch_simple_api(http: &Http, url: &Url) -> Result<Option<ProjectInfo>> {
+// A simple API page exactly as the index sent it, for when we want to save it
+// somewhere instead of (or as well as) parsing it.
+pub struct SimpleApiPage {
+    pub url: Url,
+    pub content_type: String,
+    // from the response headers; see IndexQuirks
+    pub quirks: IndexQuirks,
+    pub body: Vec<u8>,
+}
+
+impl SimpleApiPage {
+    /// PEP 691's application/vnd.pypi.simple.v1+json, or anything else JSON-ish.
+    pub fn is_json(&self) -> bool {
+        match self.content_type.parse::<mime::Mime>() {
+            Ok(mime) => {
+                mime.subtype() == mime::JSON || mime.suffix() == Some(mime::JSON)
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub fn parse(&self) -> Result<ProjectInfo> {
+        if self.is_json() {
+            return super::parse_json(&self.url, self.quirks, self.body.as_slice());
+        }
+        super::parse_html(
+            &self.url,
+            &self.content_type,
+            self.quirks,
+            self.body.as_slice(),
+        )
+    }
+}
+
+pub fn fetch_simple_api_page(http: &Http, url: &Url) -> Result<Option<SimpleApiPage>> {
     context!("Fetching simple API page at {}", url);
+    crate::events::emit(crate::events::Event::FetchingIndexPage {
+        url: url.to_string(),
+    });
     let request = Request::builder()
         .uri(url.as_str())
         .header("Cache-Control", "max-age=0")
@@ -23,6 +61,13 @@ pub fn fetch_simple_api(http: &Http, url: &Url) -> Result<Option<ProjectInfo>> {
     if response.status().as_u16() == 404 {
         return Ok(None);
     }
+    if [401, 403].contains(&response.status().as_u16()) {
+        bail!(
+            "error fetching {url}: {} (if this is a private index, put credentials in \
+             the index URL, ~/.netrc, or $POSY_INDEX_TOKENS)",
+            response.status().as_str()
+        );
+    }
     if response.status().as_u16() >= 400 {
         bail!("error fetching {url}: {}", response.status().as_str());
     }
@@ -33,10 +78,113 @@ pub fn fetch_simple_api(http: &Http, url: &Url) -> Result<Option<ProjectInfo>> {
         "text/html"
     }
     .to_owned();
+    let quirks = IndexQuirks::from_headers(response.headers());
+    if !quirks.is_default() {
+        debug!("{url} needs workarounds: {quirks:?}");
+    }
+    if IndexQuirks::looks_like_mirror(response.headers()) {
+        debug!(
+            "{url} looks like a mirror; if it doesn't serve the .metadata files it \
+             advertises, add it to the project's no-metadata-files"
+        );
+    }
+
+    Ok(Some(SimpleApiPage {
+        url,
+        content_type,
+        quirks,
+        body: slurp(&mut response.into_body())?,
+    }))
+}
+
+pub fn fetch_simple_api(http: &Http, url: &Url) -> Result<Option<ProjectInfo>> {
+    fetch_simple_api_page(http, url)?
+        .map(|page| page.parse())
+        .transpose()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kvstore::{KVDirStore, KVFileStore};
+    use crate::test_util::StaticHTTPServer;
+
+    fn html(body: &str) -> http::Response<Vec<u8>> {
+        http::Response::builder()
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(format!("<html><body>{body}</body></html>").into_bytes())
+            .unwrap()
+    }
+
+    fn redirect(location: &str) -> http::Response<Vec<u8>> {
+        http::Response::builder()
+            .status(301)
+            .header("Location", location)
+            .body(Vec::new())
+            .unwrap()
+    }
 
-    Ok(Some(super::parse_html(
-        &url,
-        &content_type,
-        response.into_body(),
-    )?))
+    // The kinds of things that devpi, pydist, Artifactory, etc. do that PyPI doesn't.
+    #[test]
+    fn test_index_quirks() {
+        let hex = "0123456789ABCDEF".repeat(4);
+        let trio_page = html(&format!(
+            r#"
+            <a href="../../packages/Trio-0.22.0-py3-none-any.whl#SHA256={hex}">x</a>
+            <a href="../../packages/trio-0.21.0.tar.gz#sha256={}">x</a>
+            <a href="../../packages/trio-0.20.0%2Blocal-py3-none-any.whl">x</a>
+            "#,
+            hex.to_ascii_lowercase(),
+        ));
+        let server = StaticHTTPServer::with_routes(vec![
+            // redirects to the canonical, trailing-slash URL, which the relative links
+            // have to be resolved against
+            ("/simple/trio", redirect("/simple/trio/")),
+            ("/simple/trio/", trio_page),
+            ("/simple/Empty/", redirect("/simple/empty/")),
+            ("/simple/empty/", html("")),
+        ]);
+        let caches = tempfile::tempdir().unwrap();
+        let http = Http::new(
+            KVFileStore::new(&caches.path().join("http")).unwrap(),
+            KVFileStore::new(&caches.path().join("hashed")).unwrap(),
+            KVDirStore::new(&caches.path().join("partial")).unwrap(),
+            Vec::new(),
+            None,
+        );
+
+        let pi = fetch_simple_api(&http, &server.url("/simple/trio"))
+            .unwrap()
+            .unwrap();
+        let names: Vec<String> =
+            pi.artifacts.iter().map(|ai| ai.name.to_string()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "Trio-0.22.0-py3-none-any.whl",
+                "trio-0.21.0.tar.gz",
+                "trio-0.20.0+local-py3-none-any.whl",
+            ]
+        );
+        for ai in &pi.artifacts {
+            assert_eq!(ai.name.distribution().normalized(), "trio");
+            assert!(ai.url.path().starts_with("/packages/"));
+        }
+        let expected_hash = ArtifactHash::from_hex("sha256", &hex).unwrap();
+        assert_eq!(pi.artifacts[0].hash.as_ref(), Some(&expected_hash));
+        assert_eq!(pi.artifacts[1].hash.as_ref(), Some(&expected_hash));
+        // and it's a hash we can actually check
+        assert!(expected_hash.checker(std::io::sink()).is_ok());
+        assert_eq!(pi.artifacts[2].name.version().to_string(), "0.20.0+local");
+
+        // A project that exists but has no files is different from one that doesn't
+        // exist at all
+        let empty = fetch_simple_api(&http, &server.url("/simple/Empty/"))
+            .unwrap()
+            .unwrap();
+        assert!(empty.artifacts.is_empty());
+        assert!(fetch_simple_api(&http, &server.url("/simple/missing/"))
+            .unwrap()
+            .is_none());
+    }
 }
@@ -12,6 +12,7 @@
 // except according to those terms.
 
 use crate::prelude::*;
+use crate::util::percent_decode;
 
 use std::borrow::Borrow;
 use std::borrow::Cow;
@@ -26,6 +27,7 @@ use html5ever::{Attribute, ExpandedName, LocalNameStaticSet, QualName};
 use string_cache::Atom;
 
 use super::project_info::{ArtifactInfo, DistInfoMetadata, ProjectInfo, Yanked};
+use super::quirks::IndexQuirks;
 
 const META_TAG: ExpandedName = expanded_name!(html "meta");
 const BASE_TAG: ExpandedName = expanded_name!(html "base");
@@ -37,14 +39,21 @@ static REQUIRES_PYTHON_ATTR: Lazy<Atom<LocalNameStaticSet>> =
     Lazy::new(|| Atom::from("data-requires-python"));
 static YANKED_ATTR: Lazy<Atom<LocalNameStaticSet>> =
     Lazy::new(|| Atom::from("data-yanked"));
+// PEP 714 renamed data-dist-info-metadata to data-core-metadata; indexes send either
+// or both, depending on their vintage
+static DATA_CORE_METADATA: Lazy<Atom<LocalNameStaticSet>> =
+    Lazy::new(|| Atom::from("data-core-metadata"));
 static DATA_DIST_INFO_METADATA: Lazy<Atom<LocalNameStaticSet>> =
     Lazy::new(|| Atom::from("data-dist-info-metadata"));
+static PROVENANCE_ATTR: Lazy<Atom<LocalNameStaticSet>> =
+    Lazy::new(|| Atom::from("data-provenance"));
 
 struct Sink {
     next_id: usize,
     names: HashMap<usize, QualName>,
     base: Url,
     changed_base: bool,
+    quirks: IndexQuirks,
     project_info: ProjectInfo,
 }
 
@@ -76,6 +85,24 @@ fn parse_hash(s: &str) -> Option<ArtifactHash> {
     }
 }
 
+fn parse_metadata_attr(value: &str) -> DistInfoMetadata {
+    match value {
+        // not allowed by the spec, but it's obvious what it means
+        "false" => DistInfoMetadata {
+            available: false,
+            hash: None,
+        },
+        "true" => DistInfoMetadata {
+            available: true,
+            hash: None,
+        },
+        _ => DistInfoMetadata {
+            available: true,
+            hash: parse_hash(value),
+        },
+    }
+}
+
 impl Sink {
     fn try_parse_link(
         &self,
@@ -83,27 +110,19 @@ impl Sink {
         attrs: &Vec<Attribute>,
     ) -> Option<Vec<ArtifactInfo>> {
         let url = self.base.join(url_str).ok()?;
-        let name: ArtifactName = url.path_segments()?.next_back()?.try_into().ok()?;
+        // devpi and friends percent-encode the '+' in local versions, and sometimes
+        // other characters too
+        let filename = percent_decode(url.path_segments()?.next_back()?).ok()?;
+        let name: ArtifactName = filename.as_str().try_into().ok()?;
         let names = name.split_multiplatform_pybis();
         // We found a valid link
         let hash = url.fragment().and_then(parse_hash);
         let requires_python =
             get_attr(REQUIRES_PYTHON_ATTR.borrow(), attrs).map(String::from);
-        let metadata_attr = get_attr(DATA_DIST_INFO_METADATA.borrow(), attrs);
-        let dist_info_metadata = match metadata_attr {
-            None => DistInfoMetadata {
-                available: false,
-                hash: None,
-            },
-            Some("true") => DistInfoMetadata {
-                available: true,
-                hash: None,
-            },
-            Some(value) => DistInfoMetadata {
-                available: true,
-                hash: parse_hash(value),
-            },
-        };
+        let dist_info_metadata = self.quirks.metadata(
+            get_attr(DATA_CORE_METADATA.borrow(), attrs).map(parse_metadata_attr),
+            get_attr(DATA_DIST_INFO_METADATA.borrow(), attrs).map(parse_metadata_attr),
+        );
         let yanked_reason = get_attr(YANKED_ATTR.borrow(), attrs);
         let yanked = match yanked_reason {
             None => Yanked {
@@ -115,6 +134,8 @@ impl Sink {
                 reason: Some(reason.into()),
             },
         };
+        let provenance = get_attr(PROVENANCE_ATTR.borrow(), attrs)
+            .and_then(|value| self.base.join(value).ok());
         let template = ArtifactInfo {
             name,
             url,
@@ -122,6 +143,8 @@ impl Sink {
             requires_python,
             dist_info_metadata,
             yanked,
+            size: None,
+            provenance,
         };
         Some(
             names
@@ -244,7 +267,12 @@ impl TreeSink for Sink {
     fn mark_script_already_started(&mut self, _node: &usize) {}
 }
 
-pub fn parse_html<T>(url: &Url, content_type: &str, mut body: T) -> Result<ProjectInfo>
+pub fn parse_html<T>(
+    url: &Url,
+    content_type: &str,
+    quirks: IndexQuirks,
+    mut body: T,
+) -> Result<ProjectInfo>
 where
     T: Read,
 {
@@ -264,6 +292,7 @@ where
         next_id: 1,
         base: url.clone(),
         changed_base: false,
+        quirks,
         names: HashMap::new(),
         project_info: Default::default(),
     };
@@ -286,13 +315,14 @@ mod test {
         let parsed = parse_html(
             &Url::parse("https://example.com/old-base/").unwrap(),
             "text/html",
+            IndexQuirks::default(),
             br#"<html>
                 <head>
                   <meta name="pypi:repository-version" content="1.0">
                   <base href="https://example.com/new-base/">
                 </head>
                 <body>
-                  <a href="link1-1.0.tar.gz#sha256=0000000000000000000000000000000000000000000000000000000000000000">link1</a>
+                  <a href="link1-1.0.tar.gz#sha256=0000000000000000000000000000000000000000000000000000000000000000" data-provenance="link1-1.0.tar.gz.provenance">link1</a>
                   <a href="/elsewhere/link2-2.0.zip" data-yanked="some reason">link2</a>
                   <a href="link3-3.0.tar.gz" data-requires-python=">= 3.17">link3</a>
                 </body>
@@ -319,6 +349,8 @@ mod test {
                 yanked: false,
                 reason: None,
               ),
+              size: None,
+              provenance: Some("https://example.com/new-base/link1-1.0.tar.gz.provenance"),
             ),
             ArtifactInfo(
               name: "link2-2.0.zip",
@@ -333,6 +365,7 @@ mod test {
                 yanked: true,
                 reason: Some("some reason"),
               ),
+              size: None,
             ),
             ArtifactInfo(
               name: "link3-3.0.tar.gz",
@@ -347,9 +380,51 @@ mod test {
                 yanked: false,
                 reason: None,
               ),
+              size: None,
             ),
           ],
         )
         "###);
     }
+
+    #[test]
+    fn test_metadata_attrs() {
+        let hex = "00".repeat(32);
+        let body = format!(
+            r#"
+            <a href="new-1.0-py3-none-any.whl" data-core-metadata="sha256={hex}">x</a>
+            <a href="old-1.0-py3-none-any.whl" data-dist-info-metadata="true">x</a>
+            <a href="both-1.0-py3-none-any.whl" data-core-metadata="true"
+               data-dist-info-metadata="sha256={hex}">x</a>
+            <a href="neither-1.0-py3-none-any.whl">x</a>
+            "#
+        );
+        let metadata = |quirks: IndexQuirks| {
+            parse_html(
+                &Url::parse("https://example.com/simple/").unwrap(),
+                "text/html",
+                quirks,
+                body.as_bytes(),
+            )
+            .unwrap()
+            .artifacts
+            .into_iter()
+            .map(|ai| {
+                let metadata = ai.dist_info_metadata;
+                (metadata.available, metadata.hash.is_some())
+            })
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            metadata(IndexQuirks::default()),
+            [(true, true), (true, false), (true, false), (false, false)]
+        );
+        let warehouse = IndexQuirks {
+            ignore_legacy_metadata_key: true,
+        };
+        assert_eq!(
+            metadata(warehouse),
+            [(true, true), (false, false), (true, false), (false, false)]
+        );
+    }
 }
@@ -0,0 +1,186 @@
+use crate::prelude::*;
+
+use super::project_info::{ArtifactInfo, DistInfoMetadata, Meta, ProjectInfo, Yanked};
+use super::quirks::IndexQuirks;
+
+// PEP 691 JSON simple API responses. We don't ask for these over the network yet, but
+// they're the natural format for saved snapshots of an index.
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct JsonMeta {
+    api_version: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct JsonFile {
+    filename: String,
+    url: String,
+    #[serde(default)]
+    hashes: HashMap<String, String>,
+    requires_python: Option<String>,
+    // PEP 714's name, and then the original PEP 691 one
+    #[serde(default)]
+    core_metadata: Option<DistInfoMetadata>,
+    #[serde(default)]
+    dist_info_metadata: Option<DistInfoMetadata>,
+    #[serde(default)]
+    yanked: Yanked,
+    size: Option<u64>,
+    provenance: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonProject {
+    meta: JsonMeta,
+    files: Vec<JsonFile>,
+}
+
+pub fn parse_json<T>(url: &Url, quirks: IndexQuirks, body: T) -> Result<ProjectInfo>
+where
+    T: Read,
+{
+    let project: JsonProject = serde_json::from_reader(body)?;
+    let mut artifacts = Vec::new();
+    for file in project.files {
+        // same as for HTML: silently skip anything that we don't recognize
+        let name: ArtifactName = match file.filename.as_str().try_into() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        let template = ArtifactInfo {
+            name: name.clone(),
+            url: url.join(&file.url)?,
+            // sha256 is the only algorithm we know how to check anyway
+            hash: match file.hashes.get("sha256") {
+                Some(hex) => Some(ArtifactHash::from_hex("sha256", hex)?),
+                None => None,
+            },
+            requires_python: file.requires_python,
+            dist_info_metadata: quirks
+                .metadata(file.core_metadata, file.dist_info_metadata),
+            yanked: file.yanked,
+            size: file.size,
+            provenance: file
+                .provenance
+                .map(|provenance| url.join(&provenance))
+                .transpose()?,
+        };
+        artifacts.extend(name.split_multiplatform_pybis().into_iter().map(|name| {
+            ArtifactInfo {
+                name,
+                ..template.clone()
+            }
+        }));
+    }
+    Ok(ProjectInfo {
+        meta: Meta {
+            version: project.meta.api_version,
+        },
+        artifacts,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_json() {
+        let parsed = parse_json(
+            &Url::parse("https://example.com/simple/link/").unwrap(),
+            IndexQuirks::default(),
+            br#"{
+                "meta": {"api-version": "1.0"},
+                "name": "link",
+                "files": [
+                  {
+                    "filename": "link-1.0.tar.gz",
+                    "url": "../../files/link-1.0.tar.gz",
+                    "hashes": {"sha256": "0000000000000000000000000000000000000000000000000000000000000000"},
+                    "requires-python": ">= 3.17",
+                    "yanked": "some reason",
+                    "size": 1234,
+                    "provenance": "../../provenance/link-1.0.tar.gz"
+                  },
+                  {
+                    "filename": "not-an-artifact.txt",
+                    "url": "whatever",
+                    "hashes": {}
+                  }
+                ]
+            }"# as &[u8],
+        )
+        .unwrap();
+
+        insta::assert_ron_snapshot!(parsed, @r###"
+        ProjectInfo(
+          meta: Meta(
+            version: "1.0",
+          ),
+          artifacts: [
+            ArtifactInfo(
+              name: "link-1.0.tar.gz",
+              url: "https://example.com/files/link-1.0.tar.gz",
+              hash: Some("sha256=0000000000000000000000000000000000000000000000000000000000000000"),
+              requires_python: Some(">= 3.17"),
+              dist_info_metadata: DistInfoMetadata(
+                available: false,
+                hash: None,
+              ),
+              yanked: Yanked(
+                yanked: true,
+                reason: Some("some reason"),
+              ),
+              size: Some(1234),
+              provenance: Some("https://example.com/provenance/link-1.0.tar.gz"),
+            ),
+          ],
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_parse_json_metadata() {
+        let hex = "00".repeat(32);
+        let body = format!(
+            r#"{{
+                "meta": {{"api-version": "1.1"}},
+                "files": [
+                  {{
+                    "filename": "new-1.0-py3-none-any.whl",
+                    "url": "new-1.0-py3-none-any.whl",
+                    "core-metadata": {{"sha256": "{hex}"}},
+                    "dist-info-metadata": {{"sha256": "{hex}"}}
+                  }},
+                  {{
+                    "filename": "old-1.0-py3-none-any.whl",
+                    "url": "old-1.0-py3-none-any.whl",
+                    "dist-info-metadata": true
+                  }},
+                  {{
+                    "filename": "none-1.0-py3-none-any.whl",
+                    "url": "none-1.0-py3-none-any.whl",
+                    "core-metadata": false
+                  }}
+                ]
+            }}"#
+        );
+        let url = Url::parse("https://example.com/simple/").unwrap();
+        let parsed = parse_json(&url, IndexQuirks::default(), body.as_bytes()).unwrap();
+        let metadata = parsed
+            .artifacts
+            .iter()
+            .map(|ai| &ai.dist_info_metadata)
+            .collect::<Vec<_>>();
+        assert!(metadata[0].available);
+        assert_eq!(
+            metadata[0].hash,
+            Some(ArtifactHash::from_hex("sha256", &hex).unwrap())
+        );
+        assert!(metadata[1].available);
+        assert_eq!(metadata[1].hash, None);
+        assert!(!metadata[2].available);
+    }
+}
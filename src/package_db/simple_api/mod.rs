@@ -1,7 +1,15 @@
 mod fetch;
 mod html;
+mod json;
 mod project_info;
+mod quirks;
+mod snapshot;
 
-pub use fetch::fetch_simple_api;
+pub use fetch::{fetch_simple_api, fetch_simple_api_page, SimpleApiPage};
 use html::parse_html;
+use json::parse_json;
+#[cfg(test)]
+pub use project_info::Yanked;
 pub use project_info::{pack_by_version, ArtifactInfo, ProjectInfo};
+use quirks::IndexQuirks;
+pub use snapshot::{SimpleApiSnapshot, SnapshotWriter};
@@ -46,13 +46,13 @@ impl From<Option<RawDistInfoMetadata>> for DistInfoMetadata {
                     available,
                     hash: None,
                 },
-                RawDistInfoMetadata::WithHashes(_) => {
-                    // XX FIXME metadata hash support w/ PEP 691
-                    Self {
-                        available: true,
-                        hash: None,
-                    }
-                }
+                // sha256 is the only one we can check, same as for artifacts
+                RawDistInfoMetadata::WithHashes(hashes) => Self {
+                    available: true,
+                    hash: hashes
+                        .get("sha256")
+                        .and_then(|hex| ArtifactHash::from_hex("sha256", hex).ok()),
+                },
             },
         }
     }
@@ -73,6 +73,18 @@ pub struct Yanked {
     pub reason: Option<String>,
 }
 
+impl Yanked {
+    /// For messages: "was yanked", plus the index's explanation if it gave one.
+    pub fn describe(&self) -> String {
+        match &self.reason {
+            Some(reason) if !reason.trim().is_empty() => {
+                format!("was yanked ({})", reason.trim())
+            }
+            _ => "was yanked".into(),
+        }
+    }
+}
+
 impl From<RawYanked> for Yanked {
     fn from(raw: RawYanked) -> Self {
         match raw {
@@ -105,6 +117,11 @@ pub struct ArtifactInfo {
     pub dist_info_metadata: DistInfoMetadata,
     //    #[serde(default)]
     pub yanked: Yanked,
+    // PEP 700; only available from the JSON API
+    pub size: Option<u64>,
+    // PEP 740: where the index keeps the file's attestations, if it has any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Url>,
 }
 
 impl ArtifactInfo {
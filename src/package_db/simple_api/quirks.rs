@@ -0,0 +1,105 @@
+use super::project_info::DistInfoMetadata;
+use crate::prelude::*;
+
+// Workarounds for particular index servers, picked based on the headers they send
+// with their simple API pages. The parsers accept every spelling of everything that
+// we know about regardless; these are for when an index says something that isn't
+// true.
+//
+// Index snapshots save these along with each page, since they don't keep the headers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct IndexQuirks {
+    // Only trust PEP 658 metadata that's advertised under its PEP 714 name
+    // (data-core-metadata / core-metadata). Warehouse used to advertise metadata files
+    // under the old name whose hashes didn't match, which is why PEP 714 renamed it.
+    #[serde(default)]
+    pub ignore_legacy_metadata_key: bool,
+}
+
+impl IndexQuirks {
+    pub fn from_headers(headers: &http::HeaderMap) -> IndexQuirks {
+        IndexQuirks {
+            // Warehouse, or something pretending to be it
+            ignore_legacy_metadata_key: headers.contains_key("x-pypi-last-serial"),
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == IndexQuirks::default()
+    }
+
+    // Mirrors (Artifactory remote repos, devpi) tend to copy PyPI's PEP 658 attributes
+    // without proxying the .metadata files, but plenty of them are set up fine, so we
+    // don't turn the metadata files off for them unless asked (see
+    // PackageDB::set_no_metadata_files). This is just so we can suggest it.
+    pub fn looks_like_mirror(headers: &http::HeaderMap) -> bool {
+        let server = headers
+            .get(http::header::SERVER)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let has = |name: &str| headers.contains_key(name);
+        has("x-artifactory-id")
+            || server.starts_with("artifactory")
+            || has("x-devpi-server-version")
+            || has("x-devpi-uuid")
+    }
+
+    // Picks between what a file's entry said under the PEP 714 name (`core`) and the
+    // PEP 658 one (`legacy`). Either one can be missing.
+    pub fn metadata(
+        &self,
+        core: Option<DistInfoMetadata>,
+        legacy: Option<DistInfoMetadata>,
+    ) -> DistInfoMetadata {
+        match (core, legacy) {
+            (Some(core), _) => core,
+            (None, Some(legacy)) if !self.ignore_legacy_metadata_key => legacy,
+            _ => Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_index_quirks_from_headers() {
+        let headers = |headers: &[(&'static str, &'static str)]| {
+            let mut map = http::HeaderMap::new();
+            for (name, value) in headers {
+                map.insert(*name, http::HeaderValue::from_static(value));
+            }
+            map
+        };
+        let quirks =
+            |h: &[(&'static str, &'static str)]| IndexQuirks::from_headers(&headers(h));
+        assert_eq!(quirks(&[]), IndexQuirks::default());
+        assert_eq!(quirks(&[("server", "nginx")]), IndexQuirks::default());
+        assert!(quirks(&[("x-pypi-last-serial", "123")]).ignore_legacy_metadata_key);
+        // mirrors only get a hint; their metadata files are opt-out
+        let artifactory = [("server", "Artifactory/7.41.12")];
+        assert!(quirks(&artifactory).is_default());
+        assert!(IndexQuirks::looks_like_mirror(&headers(&artifactory)));
+        let devpi = [("x-devpi-server-version", "6.8.0")];
+        assert!(IndexQuirks::looks_like_mirror(&headers(&devpi)));
+        assert!(!IndexQuirks::looks_like_mirror(&headers(&[(
+            "server", "nginx"
+        )])));
+
+        let available = DistInfoMetadata {
+            available: true,
+            hash: None,
+        };
+        let warehouse = quirks(&[("x-pypi-last-serial", "123")]);
+        assert_eq!(warehouse.metadata(Some(available.clone()), None), available);
+        assert!(!warehouse.metadata(None, Some(available.clone())).available);
+        let plain = IndexQuirks::default();
+        assert!(plain.metadata(None, Some(available.clone())).available);
+        let unavailable = DistInfoMetadata::default();
+        // the new name wins
+        assert!(!plain.metadata(Some(unavailable), Some(available)).available);
+    }
+}
@@ -0,0 +1,393 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::fetch::SimpleApiPage;
+use super::project_info::ProjectInfo;
+use super::{parse_html, parse_json, IndexQuirks};
+use crate::prelude::*;
+
+// A directory of saved simple API responses, for resolving without touching the
+// network (e.g. in hermetic CI). The layout is:
+//
+//   <dir>/posy-snapshot.json
+//   <dir>/<normalized-name>.html   (PEP 503)
+//   <dir>/<normalized-name>.json   (PEP 691)
+//   <dir>/metadata/<hash>.metadata (optional; core metadata for artifacts)
+//
+// and posy-snapshot.json records where and when each page was saved:
+//
+//   {"projects": {"trio": {"url": "https://pypi.org/simple/trio/",
+//                          "saved-at": 1674000000,
+//                          "quirks": {"ignore-legacy-metadata-key": true}}}}
+//
+// The url is needed to resolve relative links, and saved-at (seconds since the epoch)
+// lets us refuse to use stale pages. quirks is optional: we don't keep the response
+// headers, so it's whatever IndexQuirks they called for when the page was saved.
+//
+// Snapshots can be written by hand, or kept up to date by 'posy index sync' (see
+// SnapshotWriter). The metadata directory is there so that a sync can save the
+// metadata for recent releases too, and resolving from the snapshot doesn't need to
+// go poking at remote wheels to find out their dependencies.
+
+const MANIFEST_NAME: &str = "posy-snapshot.json";
+const LOCK_NAME: &str = "posy-snapshot.lock";
+const METADATA_DIR: &str = "metadata";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct SnapshotEntry {
+    url: Url,
+    saved_at: u64,
+    #[serde(default, skip_serializing_if = "IndexQuirks::is_default")]
+    quirks: IndexQuirks,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct SnapshotManifest {
+    projects: HashMap<String, SnapshotEntry>,
+}
+
+fn metadata_path(dir: &Path, hash: &ArtifactHash) -> PathBuf {
+    dir.join(METADATA_DIR).join(format!("{hash}.metadata"))
+}
+
+pub struct SimpleApiSnapshot {
+    dir: PathBuf,
+    manifest: SnapshotManifest,
+    max_age: Option<Duration>,
+}
+
+impl SimpleApiSnapshot {
+    pub fn open(dir: &Path, max_age: Option<Duration>) -> Result<SimpleApiSnapshot> {
+        let manifest_path = dir.join(MANIFEST_NAME);
+        context!(
+            "Reading index snapshot manifest {}",
+            manifest_path.display()
+        );
+        let manifest = serde_json::from_slice(&fs::read(&manifest_path)?)?;
+        Ok(SimpleApiSnapshot {
+            dir: dir.into(),
+            manifest,
+            max_age,
+        })
+    }
+
+    pub fn metadata(&self, hash: &ArtifactHash) -> Option<Vec<u8>> {
+        fs::read(metadata_path(&self.dir, hash)).ok()
+    }
+
+    // Unlike a real index, "not found" is an error here: if the snapshot doesn't have a
+    // package, it's much more likely that the snapshot is out of date than that the
+    // package doesn't exist.
+    pub fn project_info(&self, name: &PackageName) -> Result<ProjectInfo> {
+        let key = name.normalized();
+        let entry = self.manifest.projects.get(key).ok_or_else(|| {
+            eyre!(
+                "package {} is missing from the index snapshot at {}",
+                name.as_given(),
+                self.dir.display()
+            )
+        })?;
+        if let Some(max_age) = self.max_age {
+            let saved_at = UNIX_EPOCH + Duration::from_secs(entry.saved_at);
+            // if the clock went backwards, treat it as fresh
+            let age = SystemTime::now()
+                .duration_since(saved_at)
+                .unwrap_or_default();
+            if age > max_age {
+                bail!(
+                    "the index snapshot of {} is {} hours old, which is older than the \
+                     allowed maximum of {} hours",
+                    name.as_given(),
+                    age.as_secs() / 3600,
+                    max_age.as_secs() / 3600,
+                );
+            }
+        }
+        let html_path = self.dir.join(format!("{key}.html"));
+        let json_path = self.dir.join(format!("{key}.json"));
+        let quirks = entry.quirks;
+        if json_path.exists() {
+            context!("Reading {}", json_path.display());
+            parse_json(&entry.url, quirks, fs::File::open(json_path)?)
+        } else if html_path.exists() {
+            context!("Reading {}", html_path.display());
+            parse_html(&entry.url, "text/html", quirks, fs::File::open(html_path)?)
+        } else {
+            bail!(
+                "index snapshot manifest lists {}, but neither {} nor {} exists",
+                name.as_given(),
+                html_path.display(),
+                json_path.display()
+            );
+        }
+    }
+}
+
+// Replaces `path` all at once, so that readers never see a half-written file.
+fn write_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    // unwrap is safe because we only write to paths we built with .join()
+    let mut tmp = tempfile::NamedTempFile::new_in(path.parent().unwrap())?;
+    tmp.write_all(data)?;
+    tmp.persist(path)?;
+    Ok(())
+}
+
+/// Creates or updates a snapshot directory in place.
+///
+/// Resolves might be reading the snapshot while we update it, so each file is
+/// replaced atomically, and the manifest gets written last by `finish`. Until then,
+/// readers keep using the old manifest, and the only thing they can notice is that a
+/// page got newer.
+pub struct SnapshotWriter {
+    dir: PathBuf,
+    manifest: SnapshotManifest,
+    // only one writer at a time; released on drop
+    _lock: fs::File,
+}
+
+impl SnapshotWriter {
+    pub fn open(dir: &Path) -> Result<SnapshotWriter> {
+        context!("Opening index snapshot {} for writing", dir.display());
+        fs::create_dir_all(dir.join(METADATA_DIR))?;
+        let lock = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(dir.join(LOCK_NAME))?;
+        if fs2::FileExt::try_lock_exclusive(&lock).is_err() {
+            bail!("someone else is already updating this index snapshot");
+        }
+        let manifest_path = dir.join(MANIFEST_NAME);
+        let manifest = if manifest_path.exists() {
+            serde_json::from_slice(&fs::read(&manifest_path)?)?
+        } else {
+            SnapshotManifest::default()
+        };
+        Ok(SnapshotWriter {
+            dir: dir.into(),
+            manifest,
+            _lock: lock,
+        })
+    }
+
+    pub fn save_page(
+        &mut self,
+        name: &PackageName,
+        page: &SimpleApiPage,
+    ) -> Result<()> {
+        let key = name.normalized();
+        let (ext, other) = if page.is_json() {
+            ("json", "html")
+        } else {
+            ("html", "json")
+        };
+        write_atomically(&self.dir.join(format!("{key}.{ext}")), &page.body)?;
+        // don't leave a stale page in the other format lying around (a .json one would
+        // even take priority over an .html one we just saved)
+        let other_path = self.dir.join(format!("{key}.{other}"));
+        if other_path.exists() {
+            fs::remove_file(other_path)?;
+        }
+        let saved_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        self.manifest.projects.insert(
+            key.into(),
+            SnapshotEntry {
+                url: page.url.clone(),
+                saved_at,
+                quirks: page.quirks,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn has_metadata(&self, hash: &ArtifactHash) -> bool {
+        metadata_path(&self.dir, hash).exists()
+    }
+
+    pub fn save_metadata(&self, hash: &ArtifactHash, blob: &[u8]) -> Result<()> {
+        write_atomically(&metadata_path(&self.dir, hash), blob)
+    }
+
+    /// Drops every project that isn't in `keep` from the manifest, and returns how many
+    /// were dropped. (Their pages stay on disk until `finish`, in case a resolve is
+    /// still using the old manifest.)
+    pub fn retain(&mut self, keep: &HashSet<PackageName>) -> usize {
+        let keep: HashSet<&str> = keep.iter().map(|name| name.normalized()).collect();
+        let before = self.manifest.projects.len();
+        self.manifest
+            .projects
+            .retain(|key, _| keep.contains(key.as_str()));
+        before - self.manifest.projects.len()
+    }
+
+    pub fn finish(self) -> Result<()> {
+        // go through Value so the keys come out sorted, and the manifest diffs nicely
+        let manifest = serde_json::to_value(&self.manifest)?;
+        write_atomically(
+            &self.dir.join(MANIFEST_NAME),
+            &serde_json::to_vec_pretty(&manifest)?,
+        )?;
+        // now that nothing refers to them, clean up pages for dropped projects
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let stem = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default();
+            let is_page = matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("html" | "json")
+            ) && path.file_name() != Some(MANIFEST_NAME.as_ref());
+            if is_page && !self.manifest.projects.contains_key(stem) {
+                fs::remove_file(&path)?;
+            }
+        }
+        // XX TODO: also clean out metadata for artifacts that no longer appear on any
+        // page. Needs the pages parsed to know which hashes are still live.
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let tmp = tempfile::tempdir().unwrap();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        fs::write(
+            tmp.path().join(MANIFEST_NAME),
+            format!(
+                r#"{{"projects": {{
+                    "fresh": {{"url": "https://example.com/simple/fresh/",
+                              "saved-at": {now}}},
+                    "stale": {{"url": "https://example.com/simple/stale/",
+                              "saved-at": 0}},
+                    "lost": {{"url": "https://example.com/simple/lost/",
+                             "saved-at": {now}}}
+                }}}}"#
+            ),
+        )
+        .unwrap();
+        for name in ["fresh", "stale"] {
+            fs::write(
+                tmp.path().join(format!("{name}.html")),
+                format!(r#"<a href="{name}-1.0.tar.gz">{name}</a>"#),
+            )
+            .unwrap();
+        }
+
+        let snapshot =
+            SimpleApiSnapshot::open(tmp.path(), Some(Duration::from_secs(3600)))
+                .unwrap();
+        let fresh = snapshot.project_info(&"Fresh".parse().unwrap()).unwrap();
+        assert_eq!(fresh.artifacts.len(), 1);
+        assert_eq!(
+            fresh.artifacts[0].url.as_str(),
+            "https://example.com/simple/fresh/fresh-1.0.tar.gz"
+        );
+        let stale_err = snapshot
+            .project_info(&"stale".parse().unwrap())
+            .unwrap_err();
+        assert!(stale_err.to_string().contains("hours old"));
+        assert!(snapshot.project_info(&"lost".parse().unwrap()).is_err());
+        let missing_err = snapshot
+            .project_info(&"missing".parse().unwrap())
+            .unwrap_err();
+        assert!(missing_err
+            .to_string()
+            .contains("missing from the index snapshot"));
+
+        // without a max age, anything goes
+        let snapshot = SimpleApiSnapshot::open(tmp.path(), None).unwrap();
+        assert!(snapshot.project_info(&"stale".parse().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_snapshot_writer() {
+        let tmp = tempfile::tempdir().unwrap();
+        let page = |name: &str| SimpleApiPage {
+            url: Url::parse(&format!("https://example.com/simple/{name}/")).unwrap(),
+            content_type: "text/html".into(),
+            quirks: Default::default(),
+            body: format!(r#"<a href="{name}-1.0.tar.gz">{name}</a>"#).into_bytes(),
+        };
+        let trio: PackageName = "Trio".parse().unwrap();
+        let attrs: PackageName = "attrs".parse().unwrap();
+
+        let mut writer = SnapshotWriter::open(tmp.path()).unwrap();
+        // only one writer at a time
+        assert!(SnapshotWriter::open(tmp.path()).is_err());
+        writer.save_page(&trio, &page("trio")).unwrap();
+        writer.save_page(&attrs, &page("attrs")).unwrap();
+        let hash: ArtifactHash = format!("sha256={}", "00".repeat(32)).parse().unwrap();
+        assert!(!writer.has_metadata(&hash));
+        writer.save_metadata(&hash, b"Name: trio\n").unwrap();
+        assert!(writer.has_metadata(&hash));
+        writer.finish().unwrap();
+
+        let snapshot = SimpleApiSnapshot::open(tmp.path(), None).unwrap();
+        let info = snapshot.project_info(&trio).unwrap();
+        assert_eq!(
+            info.artifacts[0].url.as_str(),
+            "https://example.com/simple/trio/trio-1.0.tar.gz"
+        );
+        assert!(snapshot.project_info(&attrs).is_ok());
+        assert_eq!(snapshot.metadata(&hash).unwrap(), b"Name: trio\n");
+
+        // re-syncing with a smaller allowlist drops the rest
+        let mut writer = SnapshotWriter::open(tmp.path()).unwrap();
+        assert_eq!(writer.retain(&[trio.clone()].into()), 1);
+        writer.finish().unwrap();
+        let snapshot = SimpleApiSnapshot::open(tmp.path(), None).unwrap();
+        assert!(snapshot.project_info(&trio).is_ok());
+        assert!(snapshot.project_info(&attrs).is_err());
+        assert!(!tmp.path().join("attrs.html").exists());
+
+        // a PEP 691 page gets saved as JSON, replacing the HTML one
+        let json = SimpleApiPage {
+            content_type: "application/vnd.pypi.simple.v1+json".into(),
+            body: br#"{"meta": {"api-version": "1.0"}, "name": "trio",
+                       "files": [{"filename": "trio-2.0.tar.gz",
+                                  "url": "trio-2.0.tar.gz"}]}"#
+                .to_vec(),
+            ..page("trio")
+        };
+        let mut writer = SnapshotWriter::open(tmp.path()).unwrap();
+        writer.save_page(&trio, &json).unwrap();
+        writer.finish().unwrap();
+        assert!(tmp.path().join("trio.json").exists());
+        assert!(!tmp.path().join("trio.html").exists());
+        let snapshot = SimpleApiSnapshot::open(tmp.path(), None).unwrap();
+        let info = snapshot.project_info(&trio).unwrap();
+        assert_eq!(
+            info.artifacts[0].url.as_str(),
+            "https://example.com/simple/trio/trio-2.0.tar.gz"
+        );
+
+        // the quirks the page came with still apply when it's read back
+        let warehouse = SimpleApiPage {
+            quirks: IndexQuirks {
+                ignore_legacy_metadata_key: true,
+            },
+            body: br#"<a href="trio-3.0-py3-none-any.whl"
+                         data-dist-info-metadata="true">x</a>"#
+                .to_vec(),
+            ..page("trio")
+        };
+        let mut writer = SnapshotWriter::open(tmp.path()).unwrap();
+        writer.save_page(&trio, &warehouse).unwrap();
+        writer.save_page(&attrs, &page("attrs")).unwrap();
+        writer.finish().unwrap();
+        let manifest = fs::read_to_string(tmp.path().join(MANIFEST_NAME)).unwrap();
+        assert_eq!(manifest.matches("ignore-legacy-metadata-key").count(), 1);
+        let snapshot = SimpleApiSnapshot::open(tmp.path(), None).unwrap();
+        let info = snapshot.project_info(&trio).unwrap();
+        assert!(!info.artifacts[0].dist_info_metadata.available);
+    }
+}
@@ -0,0 +1,310 @@
+use std::{fs, path::Path};
+
+use crate::prelude::*;
+
+// Build backends don't always tag wheels correctly: some slap the platform tag on
+// anything they build, pure-Python or not, and some will call a wheel abi3 even though
+// its extension modules were built against one specific Python. Either way, once it's
+// in the wheel cache under the wrong name, it either never gets picked or gets picked
+// for an interpreter that can't load it, and nobody can tell why. So before we cache a
+// wheel we built, we look at what's actually inside, and fix up the tags where we can
+// tell what they should be. Where we can't, we at least say something.
+
+// Enough to find the headers we look at. (PE's can be anywhere, but in practice it's
+// always in the first few hundred bytes.)
+const HEADER_BYTES: u64 = 4096;
+
+// The CPU architecture of a compiled binary, named to match the end of the platform
+// tags that run it (modulo spelling -- see tag_arch). None if it's not a binary at all.
+// "universal" is a macOS fat binary, which could be anything.
+fn binary_arch(header: &[u8]) -> Option<&'static str> {
+    let u16_at = |offset: usize, big_endian: bool| {
+        let bytes: [u8; 2] = header.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let u32_at = |offset: usize, big_endian: bool| {
+        let bytes: [u8; 4] = header.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+    match header.get(..4)? {
+        // ELF: e_machine, in whatever byte order EI_DATA says
+        b"\x7fELF" => match u16_at(18, header.get(5) == Some(&2))? {
+            0x03 => Some("x86"),
+            0x3e => Some("x86_64"),
+            0x28 => Some("arm"),
+            0xb7 => Some("aarch64"),
+            0x15 => Some("ppc64"),
+            0x16 => Some("s390x"),
+            _ => Some("unknown"),
+        },
+        // Mach-O (little-endian, 32 and 64 bit): cputype
+        b"\xce\xfa\xed\xfe" | b"\xcf\xfa\xed\xfe" => match u32_at(4, false)? {
+            0x0000_0007 => Some("x86"),
+            0x0100_0007 => Some("x86_64"),
+            0x0100_000c => Some("aarch64"),
+            _ => Some("unknown"),
+        },
+        // Mach-O fat binary. Java class files start with the same magic number, but
+        // they have a version number where the fat header has its (small) arch count.
+        b"\xca\xfe\xba\xbe" if u32_at(4, true)? < 20 => Some("universal"),
+        // PE: the "MZ" DOS stub points to the real header, which has the machine type
+        [b'M', b'Z', ..] => {
+            let pe = u32_at(0x3c, false)? as usize;
+            if header.get(pe..pe + 4)? != b"PE\0\0" {
+                return None;
+            }
+            match u16_at(pe + 4, false)? {
+                0x014c => Some("x86"),
+                0x8664 => Some("x86_64"),
+                0x01c4 => Some("arm"),
+                0xaa64 => Some("aarch64"),
+                _ => Some("unknown"),
+            }
+        }
+        _ => None,
+    }
+}
+
+// The architecture a platform tag like "manylinux_2_17_x86_64" or "win_amd64" is for,
+// in binary_arch's terms.
+fn tag_arch(tag: &str) -> Option<&'static str> {
+    static SUFFIXES: &[(&str, &str)] = &[
+        ("x86_64", "x86_64"),
+        ("amd64", "x86_64"),
+        ("intel", "universal"),
+        ("universal2", "universal"),
+        ("universal", "universal"),
+        ("i386", "x86"),
+        ("i686", "x86"),
+        ("win32", "x86"),
+        ("aarch64", "aarch64"),
+        ("arm64", "aarch64"),
+        ("armv7l", "arm"),
+        ("ppc64le", "ppc64"),
+        ("ppc64", "ppc64"),
+        ("s390x", "s390x"),
+    ];
+    SUFFIXES
+        .iter()
+        .find(|(suffix, _)| tag.ends_with(suffix))
+        .map(|(_, arch)| *arch)
+}
+
+// Extension modules built for one specific CPython say so in their filename, e.g.
+// "_foo.cpython-311-x86_64-linux-gnu.so" or "_foo.cp311-win_amd64.pyd". (abi3 ones are
+// "_foo.abi3.so" or plain "_foo.pyd".) Returns the matching interpreter tag.
+fn version_specific_extension(path: &str) -> Option<String> {
+    static EXTENSION_RE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"\.(?:cpython-|cp)(\d)(\d+)(?:[-.][^/]*)?\.(?:so|pyd)$").unwrap()
+    });
+    let captures = EXTENSION_RE.captures(path)?;
+    Some(format!("cp{}{}", &captures[1], &captures[2]))
+}
+
+/// Checks the tags in `wheel_name` against the contents of the wheel at `wheel_path`,
+/// which we just built on a `build_arch` machine, and corrects them if they're clearly
+/// wrong.
+pub fn check_wheel_tags(
+    wheel_path: &Path,
+    wheel_name: &mut WheelName,
+    build_arch: &str,
+) -> Result<()> {
+    context!("Checking the contents of {wheel_name}");
+    let mut z = zip::ZipArchive::new(fs::File::open(wheel_path)?)?;
+    // (path, arch) for every compiled binary in the wheel
+    let mut binaries = Vec::new();
+    for i in 0..z.len() {
+        let entry = z.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let path = entry.name().to_string();
+        let header = slurp(&mut entry.take(HEADER_BYTES))?;
+        if let Some(arch) = binary_arch(&header) {
+            binaries.push((path, arch));
+        }
+    }
+
+    let tagged_any = wheel_name.arch_tags.iter().all(|t| t == "any");
+    if binaries.is_empty() {
+        if !tagged_any {
+            warn!(
+                class = "wheel-tags",
+                "{wheel_name} contains no compiled code, but the build backend gave it \
+                 a platform tag; treating it as a pure-Python wheel"
+            );
+            wheel_name.abi_tags = vec!["none".into()];
+            wheel_name.arch_tags = vec!["any".into()];
+        }
+        return Ok(());
+    }
+    if tagged_any {
+        // maybe it's bundling helper binaries for several platforms on purpose, so
+        // leave it alone
+        warn!(
+            class = "wheel-tags",
+            "{wheel_name} is tagged as pure-Python, but contains compiled code (e.g. {})",
+            binaries[0].0
+        );
+        return Ok(());
+    }
+
+    let expected = tag_arch(build_arch);
+    for (path, arch) in &binaries {
+        let fits = match (expected, *arch) {
+            (_, "unknown") | (None, _) => true,
+            (_, "universal") | (Some("universal"), _) => true,
+            (Some(expected), arch) => expected == arch,
+        };
+        if !fits {
+            warn!(
+                class = "wheel-tags",
+                "{wheel_name}: {path} is compiled for {arch}, but we built it on \
+                 {build_arch}; it probably won't work here"
+            );
+        }
+    }
+
+    if wheel_name.abi_tags.iter().any(|t| t == "abi3") {
+        let specific = binaries
+            .iter()
+            .find_map(|(path, _)| version_specific_extension(path).map(|t| (path, t)));
+        if let Some((path, interpreter)) = specific {
+            warn!(
+                class = "wheel-tags",
+                "{wheel_name} is tagged abi3, but {path} only works on {interpreter}; \
+                 retagging it to match"
+            );
+            wheel_name.py_tags = vec![interpreter.clone()];
+            wheel_name.abi_tags = vec![interpreter];
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn elf(machine: u16) -> Vec<u8> {
+        let mut header = b"\x7fELF\x02\x01\x01".to_vec();
+        header.resize(18, 0);
+        header.extend(machine.to_le_bytes());
+        header.resize(64, 0);
+        header
+    }
+
+    fn pe(machine: u16) -> Vec<u8> {
+        let mut header = b"MZ".to_vec();
+        header.resize(0x3c, 0);
+        header.extend(0x80u32.to_le_bytes());
+        header.resize(0x80, 0);
+        header.extend(b"PE\0\0");
+        header.extend(machine.to_le_bytes());
+        header
+    }
+
+    fn write_wheel(path: &Path, files: &[(&str, &[u8])]) {
+        let mut z = zip::ZipWriter::new(fs::File::create(path).unwrap());
+        for (name, contents) in files {
+            z.start_file(*name, Default::default()).unwrap();
+            z.write_all(contents).unwrap();
+        }
+        z.finish().unwrap();
+    }
+
+    #[test]
+    fn test_binary_arch() {
+        assert_eq!(binary_arch(&elf(0x3e)), Some("x86_64"));
+        assert_eq!(binary_arch(&elf(0xb7)), Some("aarch64"));
+        assert_eq!(binary_arch(&pe(0x8664)), Some("x86_64"));
+        assert_eq!(
+            binary_arch(b"\xcf\xfa\xed\xfe\x0c\x00\x00\x01"),
+            Some("aarch64")
+        );
+        assert_eq!(
+            binary_arch(b"\xca\xfe\xba\xbe\x00\x00\x00\x02"),
+            Some("universal")
+        );
+        // a Java class file
+        assert_eq!(binary_arch(b"\xca\xfe\xba\xbe\x00\x00\x00\x34"), None);
+        assert_eq!(binary_arch(b"MZ but not really"), None);
+        assert_eq!(binary_arch(b"import sys\n"), None);
+        assert_eq!(binary_arch(b""), None);
+
+        assert_eq!(tag_arch("manylinux_2_17_x86_64"), Some("x86_64"));
+        assert_eq!(tag_arch("win_amd64"), Some("x86_64"));
+        assert_eq!(tag_arch("macosx_11_0_arm64"), Some("aarch64"));
+        assert_eq!(tag_arch("linux_armv7l"), Some("arm"));
+        assert_eq!(tag_arch("linux_riscv64"), None);
+
+        assert_eq!(
+            version_specific_extension("foo/_speedups.cpython-311-x86_64-linux-gnu.so"),
+            Some("cp311".into())
+        );
+        assert_eq!(
+            version_specific_extension("foo/_speedups.cp310-win_amd64.pyd"),
+            Some("cp310".into())
+        );
+        assert_eq!(
+            version_specific_extension("foo/_speedups.cpython-311.so"),
+            Some("cp311".into())
+        );
+        assert_eq!(version_specific_extension("foo/_speedups.abi3.so"), None);
+        assert_eq!(version_specific_extension("foo/_speedups.pyd"), None);
+    }
+
+    #[test]
+    fn test_check_wheel_tags() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("foo.whl");
+        let check = |files: &[(&str, &[u8])], name: &str| {
+            write_wheel(&path, files);
+            let mut name: WheelName = name.parse().unwrap();
+            check_wheel_tags(&path, &mut name, "linux_x86_64").unwrap();
+            name.to_string()
+        };
+
+        let pure: &[(&str, &[u8])] = &[("foo/__init__.py", b"import sys\n")];
+        assert_eq!(
+            check(pure, "foo-1.0-cp311-cp311-linux_x86_64.whl"),
+            "foo-1.0-cp311-none-any.whl"
+        );
+        assert_eq!(
+            check(pure, "foo-1.0-py3-none-any.whl"),
+            "foo-1.0-py3-none-any.whl"
+        );
+
+        let x86_64 = elf(0x3e);
+        let abi3: &[(&str, &[u8])] = &[("foo/_foo.abi3.so", &x86_64)];
+        assert_eq!(
+            check(abi3, "foo-1.0-cp37-abi3-linux_x86_64.whl"),
+            "foo-1.0-cp37-abi3-linux_x86_64.whl"
+        );
+        let not_abi3: &[(&str, &[u8])] =
+            &[("foo/_foo.cpython-311-x86_64-linux-gnu.so", &x86_64)];
+        assert_eq!(
+            check(not_abi3, "foo-1.0-cp37-abi3-linux_x86_64.whl"),
+            "foo-1.0-cp311-cp311-linux_x86_64.whl"
+        );
+        // only warns
+        let aarch64 = elf(0xb7);
+        let wrong_arch: &[(&str, &[u8])] = &[("foo/_foo.abi3.so", &aarch64)];
+        assert_eq!(
+            check(wrong_arch, "foo-1.0-cp37-abi3-linux_x86_64.whl"),
+            "foo-1.0-cp37-abi3-linux_x86_64.whl"
+        );
+        assert_eq!(
+            check(abi3, "foo-1.0-py3-none-any.whl"),
+            "foo-1.0-py3-none-any.whl"
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use crate::prelude::*;
+
+/// CPython 3.13 added a "free-threaded" build (no GIL), which has a different ABI from
+/// the regular build, with its own abi tags: cp313t instead of cp313. Extension
+/// modules built for one will crash or fail to import on the other, so we have to make
+/// sure that an env never mixes them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AbiVariant {
+    Default,
+    FreeThreaded,
+}
+
+static CPYTHON_ABI_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^cp[0-9]+(?P<flags>[a-z]*)$").unwrap());
+
+impl AbiVariant {
+    /// Which variant an abi tag (like "cp313t" or "abi3") requires, or None if it
+    /// works with either (like "none").
+    pub fn of_abi_tag(abi: &str) -> Option<AbiVariant> {
+        if abi == "abi3" {
+            // the limited API isn't supported on free-threaded builds (yet?)
+            Some(AbiVariant::Default)
+        } else if let Some(captures) = CPYTHON_ABI_RE.captures(abi) {
+            // flags can also include old-style suffixes like "m" (cp37m) or "d" for
+            // debug builds (cp313td)
+            if captures["flags"].contains('t') {
+                Some(AbiVariant::FreeThreaded)
+            } else {
+                Some(AbiVariant::Default)
+            }
+        } else {
+            None
+        }
+    }
+
+    /// Same as `of_abi_tag`, but for a full "python-abi-platform" tag.
+    pub fn of_tag(tag: &str) -> Option<AbiVariant> {
+        tag.split('-').nth(1).and_then(AbiVariant::of_abi_tag)
+    }
+
+    /// Whether something with this abi tag can be used with this variant.
+    pub fn accepts_abi_tag(&self, abi: &str) -> bool {
+        match AbiVariant::of_abi_tag(abi) {
+            Some(variant) => variant == *self,
+            None => true,
+        }
+    }
+}
+
+impl Display for AbiVariant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AbiVariant::Default => write!(f, "regular"),
+            AbiVariant::FreeThreaded => write!(f, "free-threaded"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_abi_variant() {
+        use AbiVariant::*;
+        assert_eq!(AbiVariant::of_abi_tag("cp313"), Some(Default));
+        assert_eq!(AbiVariant::of_abi_tag("cp37m"), Some(Default));
+        assert_eq!(AbiVariant::of_abi_tag("abi3"), Some(Default));
+        assert_eq!(AbiVariant::of_abi_tag("cp313t"), Some(FreeThreaded));
+        assert_eq!(AbiVariant::of_abi_tag("cp313td"), Some(FreeThreaded));
+        assert_eq!(AbiVariant::of_abi_tag("none"), None);
+        assert_eq!(AbiVariant::of_abi_tag("pypy39_pp73"), None);
+
+        assert_eq!(
+            AbiVariant::of_tag("cp313-cp313t-manylinux_2_17_x86_64"),
+            Some(FreeThreaded)
+        );
+        assert_eq!(AbiVariant::of_tag("py3-none-any"), None);
+
+        assert!(FreeThreaded.accepts_abi_tag("cp313t"));
+        assert!(FreeThreaded.accepts_abi_tag("none"));
+        assert!(!FreeThreaded.accepts_abi_tag("cp313"));
+        assert!(!FreeThreaded.accepts_abi_tag("abi3"));
+        assert!(Default.accepts_abi_tag("abi3"));
+        assert!(!Default.accepts_abi_tag("cp313t"));
+    }
+}
@@ -1,5 +1,6 @@
 use std::borrow::Cow;
 
+use super::wasm::expand_wasm_tag;
 use crate::prelude::*;
 
 static LINUX_RE: Lazy<Regex> = Lazy::new(|| {
@@ -9,6 +10,21 @@ static LINUX_RE: Lazy<Regex> = Lazy::new(|| {
 static LEGACY_MANYLINUX_RE: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"^manylinux(2014|2010|1)_([a-zA-Z0-9_]*)").unwrap());
 
+// The legacy manylinux tags only ever existed for some arches (PEPs 513, 571, 599);
+// everything newer, like riscv64, only has the PEP 600 kind.
+fn legacy_manylinux_alias(major: u32, minor: u32, arch: &str) -> Option<&'static str> {
+    const X86: &[&str] = &["x86_64", "i686"];
+    const MANYLINUX2014: &[&str] = &[
+        "x86_64", "i686", "aarch64", "armv7l", "ppc64", "ppc64le", "s390x",
+    ];
+    match (major, minor) {
+        (2, 17) if MANYLINUX2014.contains(&arch) => Some("manylinux2014"),
+        (2, 12) if X86.contains(&arch) => Some("manylinux2010"),
+        (2, 5) if X86.contains(&arch) => Some("manylinux1"),
+        _ => None,
+    }
+}
+
 static MACOSX_RE: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"^macosx_([0-9]+)_([0-9]+)_([a-zA-Z0-9_]*)$").unwrap());
 
@@ -16,9 +32,18 @@ static MACOSX_RE: Lazy<Regex> =
 // other platform tags that are guaranteed to be supported on any machine that supports
 // the given tag. The vector is sorted so "better" tags come before "worse" tags.
 //
-// Unrecognized tags are passed through unchanged.
+// Also accepts the spelling that sysconfig.get_platform() uses, like "macosx-11.0-arm64"
+// or "emscripten-3.1.32-wasm32". Unrecognized tags are passed through unchanged (well,
+// with '-' and '.' turned into '_', same as packaging does).
 pub fn expand_platform_tag(tag: &str) -> Vec<String> {
-    let mut tag = Cow::Borrowed(tag);
+    if let Some(tags) = expand_wasm_tag(tag) {
+        return tags;
+    }
+
+    let mut tag = match tag.contains(['-', '.']) {
+        true => Cow::Owned(tag.replace(['-', '.'], "_")),
+        false => Cow::Borrowed(tag),
+    };
     if let Some(captures) = LEGACY_MANYLINUX_RE.captures(tag.as_ref()) {
         let which = captures.get(1).unwrap().as_str();
         let arch = captures.get(2).unwrap().as_str();
@@ -41,11 +66,8 @@ pub fn expand_platform_tag(tag: &str) -> Vec<String> {
         for minor in (0..=max_minor).rev() {
             tags.push(format!("{variant}linux_{major}_{minor}_{arch}"));
             if variant == "many" {
-                match (major, minor) {
-                    (2, 17) => tags.push(format!("manylinux2014_{}", arch)),
-                    (2, 12) => tags.push(format!("manylinux2010_{}", arch)),
-                    (2, 5) => tags.push(format!("manylinux1_{}", arch)),
-                    _ => (),
+                if let Some(alias) = legacy_manylinux_alias(major, minor, arch) {
+                    tags.push(format!("{alias}_{arch}"));
                 }
             }
         }
@@ -107,6 +129,11 @@ mod test {
           "win_amd64",
         ]
         "###);
+        insta::assert_ron_snapshot!(expand_platform_tag("emscripten_3_1_32_wasm32"), @r###"
+        [
+          "emscripten_3_1_32_wasm32",
+        ]
+        "###);
 
         insta::assert_ron_snapshot!(expand_platform_tag("macosx_10_10_x86_64"), @r###"
         [
@@ -262,5 +289,29 @@ mod test {
           "musllinux_1_0_x86_64",
         ]
         "###);
+        insta::assert_ron_snapshot!(expand_platform_tag("musllinux_1_1_riscv64"), @r###"
+        [
+          "musllinux_1_1_riscv64",
+          "musllinux_1_0_riscv64",
+        ]
+        "###);
+
+        // manylinux2014 covers ppc64le and s390x, but the older ones don't
+        let ppc64le = expand_platform_tag("manylinux_2_17_ppc64le");
+        assert_eq!(
+            ppc64le[..2],
+            ["manylinux_2_17_ppc64le", "manylinux2014_ppc64le"]
+        );
+        assert!(!ppc64le.iter().any(|t| t.starts_with("manylinux2010")));
+        assert!(!ppc64le.iter().any(|t| t.starts_with("manylinux1")));
+        assert_eq!(
+            expand_platform_tag("manylinux2014_s390x")[..2],
+            ["manylinux_2_17_s390x", "manylinux2014_s390x"]
+        );
+        // riscv64 wheels only ever use the new-style tags
+        let riscv64 = expand_platform_tag("manylinux_2_31_riscv64");
+        assert_eq!(riscv64.len(), 32);
+        assert!(riscv64.iter().all(|t| t.starts_with("manylinux_2_")));
+        assert_eq!(riscv64[14], "manylinux_2_17_riscv64");
     }
 }
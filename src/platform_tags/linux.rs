@@ -35,7 +35,8 @@ static GLIBC_DETECTORS: Lazy<Vec<(&str, &[u8])>> = Lazy::new(|| {
         ));
     }
 
-    #[cfg(any(target_arch = "powerpc64"))]
+    // (big-endian ppc64 can't run it, and there are no wheels for it anyway)
+    #[cfg(all(target_arch = "powerpc64", target_endian = "little"))]
     {
         glibc_detectors.push((
             "ppc64le",
@@ -57,6 +58,17 @@ static GLIBC_DETECTORS: Lazy<Vec<(&str, &[u8])>> = Lazy::new(|| {
 static GLIBC_VERSION_RE: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"^([0-9]+)\.([0-9]+)").unwrap());
 
+fn parse_glibc_version(text: &str) -> Result<(u32, u32)> {
+    match GLIBC_VERSION_RE.captures(text.trim()) {
+        None => bail!("unexpected glibc version number: {:?}", text),
+        Some(captures) => {
+            let major: u32 = captures.get(1).unwrap().as_str().parse()?;
+            let minor: u32 = captures.get(2).unwrap().as_str().parse()?;
+            Ok((major, minor))
+        }
+    }
+}
+
 fn glibc_version(py_arch: &str, detector: &[u8]) -> Result<Option<(u32, u32)>> {
     // This is a stupid hack to run 'detector' as an executable, with the guarantees
     // that (1) we can't accidentally leak it (the OS will clean it up for us if we
@@ -74,23 +86,91 @@ fn glibc_version(py_arch: &str, detector: &[u8]) -> Result<Option<(u32, u32)>> {
     // Have to re-open because exec() requires that the file has no open writers
     let f_readonly = File::open(format!("/proc/self/fd/{}", f.as_raw_fd()))?;
     drop(f);
-    let output =
-        Command::new(format!("/proc/self/fd/{}", f_readonly.as_raw_fd())).output()?;
+    let output = match Command::new(format!("/proc/self/fd/{}", f_readonly.as_raw_fd()))
+        .output()
+    {
+        Ok(output) => output,
+        // The kernel reports a missing ELF interpreter as ENOENT -- that's what
+        // happens on musl-only systems like Alpine, where there's no ld-linux to
+        // run the detector with, so it just means "no glibc here".
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            debug!("can't run glibc detector for {}: {}", py_arch, e);
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    };
     if !output.status.success() {
         debug!("non-zero return for {}: {}", py_arch, output.status);
         Ok(None)
     } else {
         let output_text = String::from_utf8_lossy(&output.stdout);
-        match GLIBC_VERSION_RE.captures(&output_text) {
-            None => {
-                bail!("unexpected glibc version number: {:?}", output.stdout)
-            }
-            Some(captures) => {
-                let major: u32 = captures.get(1).unwrap().as_str().parse()?;
-                let minor: u32 = captures.get(2).unwrap().as_str().parse()?;
-                Ok(Some((major, minor)))
-            }
-        }
+        Ok(Some(parse_glibc_version(&output_text)?))
+    }
+}
+
+// The python arch tag for the arch that posy itself was built for.
+fn native_py_arch() -> Option<&'static str> {
+    match std::env::consts::ARCH {
+        "x86_64" => Some("x86_64"),
+        "x86" => Some("i686"),
+        "aarch64" => Some("aarch64"),
+        "arm" => Some("armv7l"),
+        "powerpc64" if cfg!(target_endian = "little") => Some("ppc64le"),
+        "s390x" => Some("s390x"),
+        "riscv64" => Some("riscv64"),
+        _ => None,
+    }
+}
+
+// What 'getconf GNU_LIBC_VERSION' says, e.g. "glibc 2.36". Anything else (like musl's
+// getconf, which doesn't know the name) means no glibc.
+#[cfg(any(test, not(target_env = "gnu")))]
+fn parse_gnu_libc_version(text: &str) -> Result<Option<(u32, u32)>> {
+    match text.trim().strip_prefix("glibc ") {
+        Some(version) => Ok(Some(parse_glibc_version(version)?)),
+        None => Ok(None),
+    }
+}
+
+// The glibc that the system has for the arch we're running as, asked for directly
+// instead of via a detector: through gnu_get_libc_version if we're linked against
+// glibc ourselves, or else through getconf. It only knows about our own arch, but it
+// works where the detectors can't (no detector for this arch, e.g. riscv64 -- the old
+// distro we build them on doesn't support it -- or no /proc, or a noexec temp dir).
+#[cfg(target_env = "gnu")]
+fn host_glibc_version() -> Result<Option<(u32, u32)>> {
+    // just the number, e.g. "2.36"
+    let version = unsafe { std::ffi::CStr::from_ptr(libc::gnu_get_libc_version()) };
+    Ok(Some(parse_glibc_version(&version.to_string_lossy())?))
+}
+
+#[cfg(not(target_env = "gnu"))]
+fn host_glibc_version() -> Result<Option<(u32, u32)>> {
+    let output = match Command::new("getconf").arg("GNU_LIBC_VERSION").output() {
+        Ok(output) => output,
+        // e.g. a musl-only system
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if !output.status.success() {
+        return Ok(None);
+    }
+    parse_gnu_libc_version(&String::from_utf8_lossy(&output.stdout))
+}
+
+// For our own arch, we have two opinions about the glibc version: the detector's and
+// the host's. Ideally they agree, but the host's is the one that's actually going to
+// load the wheels, so if the detector claims more than that, we don't believe it --
+// PEP 600 says a manylinux_x_y wheel is only installable with glibc >= x.y, and
+// expand_platform_tag will happily count every older tag too.
+fn cap_glibc_version(
+    detected: Option<(u32, u32)>,
+    host: Option<(u32, u32)>,
+) -> Option<(u32, u32)> {
+    match (detected, host) {
+        (Some(detected), Some(host)) => Some(std::cmp::min(detected, host)),
+        (detected, None) => detected,
+        (None, host) => host,
     }
 }
 
@@ -103,50 +183,121 @@ static MUSL_ARCH_MAP: &[(&str, &str)] = &[
     ("armhf", "armv7l"),
     ("powerpc64le", "ppc64le"),
     ("s390x", "s390x"),
+    ("riscv64", "riscv64"),
 ];
 
 static MUSL_VERSION_RE: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"Version ([0-9]+)\.([0-9]+)").unwrap());
 
+static MUSL_ARCH_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"musl libc \(([a-zA-Z0-9_]+)\)").unwrap());
+
+// Running musl's dynamic loader with no arguments (or its ldd, which is the same
+// binary) prints something like this to stderr, and exits non-zero:
+//
+//   musl libc (x86_64)
+//   Version 1.2.4
+//   Dynamic Program Path: /lib/ld-musl-x86_64.so.1
+//   Usage: ...
+fn parse_musl_version(text: &str) -> Result<(u32, u32)> {
+    match MUSL_VERSION_RE.captures(text) {
+        None => bail!("couldn't find version string in output"),
+        Some(captures) => {
+            let major: u32 = captures.get(1).unwrap().as_str().parse()?;
+            let minor: u32 = captures.get(2).unwrap().as_str().parse()?;
+            Ok((major, minor))
+        }
+    }
+}
+
 fn musl_version(loader: &PathBuf) -> Result<(u32, u32)> {
     match Command::new(loader).output() {
         Err(e) => bail!("failed to execute: {}", e),
-        Ok(output) => {
-            // don't check output.status, because it's expected to return
-            // non-zero
-            let output_text = String::from_utf8_lossy(&output.stderr);
-            match MUSL_VERSION_RE.captures(&output_text) {
-                None => bail!("couldn't find version string in output"),
-                Some(captures) => {
-                    let major: u32 = captures.get(1).unwrap().as_str().parse()?;
-                    let minor: u32 = captures.get(2).unwrap().as_str().parse()?;
-                    Ok((major, minor))
-                }
-            }
+        // don't check output.status, because it's expected to return non-zero
+        Ok(output) => parse_musl_version(&String::from_utf8_lossy(&output.stderr)),
+    }
+}
+
+// Returns (python arch, musl version), if the text is from musl at all.
+fn parse_musl_ldd(text: &str) -> Result<Option<(&'static str, (u32, u32))>> {
+    let musl_arch = match MUSL_ARCH_RE.captures(text) {
+        None => return Ok(None),
+        Some(captures) => captures.get(1).unwrap().as_str(),
+    };
+    match MUSL_ARCH_MAP.iter().find(|(m, _)| *m == musl_arch) {
+        None => {
+            debug!("unrecognized musl arch {}", musl_arch);
+            Ok(None)
         }
+        Some((_, py_arch)) => Ok(Some((*py_arch, parse_musl_version(text)?))),
     }
 }
 
+// Fallback for when the loader isn't where we expect (e.g. distros that put it in
+// /usr/lib, or only have a symlink under some other name): ask ldd, which on musl
+// systems is the loader itself. On glibc systems this prints glibc's banner instead,
+// which we ignore.
+fn musl_from_ldd() -> Result<Option<(&'static str, (u32, u32))>> {
+    let output = match Command::new("ldd").arg("--version").output() {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    parse_musl_ldd(&String::from_utf8_lossy(&output.stderr))
+}
+
 pub fn core_platform_tags() -> Result<Vec<String>> {
     let mut all_tags: Vec<String> = Vec::new();
 
+    let native_arch = native_py_arch();
+    let host_glibc = match host_glibc_version() {
+        Ok(version) => version,
+        Err(e) => {
+            debug!("error asking for the host's glibc version: {}", e);
+            None
+        }
+    };
+    let mut native_done = false;
     for (py_arch, detector) in GLIBC_DETECTORS.iter() {
-        match glibc_version(py_arch, detector) {
-            Err(e) => warn!("error checking glibc version on {}: {}", py_arch, e),
-            Ok(None) => {}
-            Ok(Some((major, minor))) => {
-                all_tags.push(format!("manylinux_{}_{}_{}", major, minor, py_arch))
+        let detected = match glibc_version(py_arch, detector) {
+            Err(e) => {
+                warn!("error checking glibc version on {}: {}", py_arch, e);
+                None
             }
+            Ok(detected) => detected,
+        };
+        let version = if Some(*py_arch) == native_arch {
+            native_done = true;
+            if detected.is_some() && host_glibc.is_some() && detected != host_glibc {
+                debug!(
+                    "glibc detector says {:?} on {}, but the host says {:?}",
+                    detected, py_arch, host_glibc
+                );
+            }
+            cap_glibc_version(detected, host_glibc)
+        } else {
+            detected
+        };
+        if let Some((major, minor)) = version {
+            all_tags.push(format!("manylinux_{}_{}_{}", major, minor, py_arch))
         }
     }
+    // no detector for our arch at all
+    if let (false, Some(py_arch), Some((major, minor))) =
+        (native_done, native_arch, host_glibc)
+    {
+        all_tags.push(format!("manylinux_{}_{}_{}", major, minor, py_arch))
+    }
 
     // Put musllinux after manylinux, since at least for now, manylinux is a smoother
     // path (more wheels available etc.) Plus the only distros I know of that make it
     // easy to install both are like, Debian, not Alpine, so glibc is the preferred
     // default anyway.
+    let mut found_musl = false;
     for (musl_arch, py_arch) in MUSL_ARCH_MAP {
         let loader: PathBuf = format!("/lib/ld-musl-{}.so.1", musl_arch).into();
         if loader.exists() {
+            found_musl = true;
             match musl_version(&loader) {
                 Ok((major, minor)) => {
                     all_tags.push(format!("musllinux_{}_{}_{}", major, minor, py_arch))
@@ -160,6 +311,63 @@ pub fn core_platform_tags() -> Result<Vec<String>> {
             }
         }
     }
+    if !found_musl {
+        match musl_from_ldd() {
+            Err(e) => debug!("error checking ldd for musl: {}", e),
+            Ok(None) => {}
+            Ok(Some((py_arch, (major, minor)))) => {
+                all_tags.push(format!("musllinux_{}_{}_{}", major, minor, py_arch))
+            }
+        }
+    }
 
     Ok(all_tags)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_glibc_version() {
+        assert_eq!(parse_glibc_version("2.17\n").unwrap(), (2, 17));
+        assert_eq!(parse_glibc_version("2.36").unwrap(), (2, 36));
+        assert!(parse_glibc_version("glibc 2.36").is_err());
+        assert!(parse_glibc_version("").is_err());
+    }
+
+    #[test]
+    fn test_host_glibc_version() {
+        assert_eq!(
+            parse_gnu_libc_version("glibc 2.36\n").unwrap(),
+            Some((2, 36))
+        );
+        assert_eq!(parse_gnu_libc_version("").unwrap(), None);
+        assert!(parse_gnu_libc_version("glibc ???").is_err());
+
+        assert_eq!(
+            cap_glibc_version(Some((2, 35)), Some((2, 31))),
+            Some((2, 31))
+        );
+        assert_eq!(
+            cap_glibc_version(Some((2, 17)), Some((2, 31))),
+            Some((2, 17))
+        );
+        assert_eq!(cap_glibc_version(None, Some((2, 31))), Some((2, 31)));
+        assert_eq!(cap_glibc_version(Some((2, 17)), None), Some((2, 17)));
+        assert_eq!(cap_glibc_version(None, None), None);
+    }
+
+    #[test]
+    fn test_parse_musl_ldd() {
+        let alpine = "musl libc (x86_64)\nVersion 1.2.4\n\
+                      Dynamic Program Path: /lib/ld-musl-x86_64.so.1\n\
+                      Usage: ldd [options] [--] pathname\n";
+        assert_eq!(parse_musl_ldd(alpine).unwrap(), Some(("x86_64", (1, 2))));
+        let arm = "musl libc (armhf)\nVersion 1.1.24\n";
+        assert_eq!(parse_musl_ldd(arm).unwrap(), Some(("armv7l", (1, 1))));
+        let glibc = "ldd (Debian GLIBC 2.36-9) 2.36\nCopyright (C) 2022\n";
+        assert_eq!(parse_musl_ldd(glibc).unwrap(), None);
+        assert!(parse_musl_ldd("musl libc (aarch64)\n").is_err());
+    }
+}
@@ -69,9 +69,13 @@ fn running_under_rosetta_2() -> bool {
     }
 }
 
+pub fn is_apple_silicon() -> bool {
+    cfg!(target_arch = "aarch64") || running_under_rosetta_2()
+}
+
 fn arches() -> &'static [&'static str] {
     // all in-support macs support x86-64, either natively or emulated
-    if cfg!(target_arch = "aarch64") || running_under_rosetta_2() {
+    if is_apple_silicon() {
         &["arm64", "x86_64"]
     } else {
         &["x86_64"]
@@ -29,7 +29,18 @@ use linux::core_platform_tags;
 mod macos;
 #[cfg(target_os = "macos")]
 use macos::core_platform_tags;
+#[cfg(target_os = "macos")]
+use macos::is_apple_silicon;
+#[cfg(not(target_os = "macos"))]
+fn is_apple_silicon() -> bool {
+    false
+}
 
+mod abi;
 mod expand;
 mod platform;
-pub use platform::{Platform, PybiPlatform, WheelPlatform};
+mod wasm;
+pub use abi::AbiVariant;
+pub use platform::{
+    set_custom_tag_priorities, use_rosetta, Platform, PybiPlatform, WheelPlatform,
+};
@@ -1,20 +1,154 @@
 use super::expand::expand_platform_tag;
+use super::wasm::is_wasm_tag;
 use crate::prelude::*;
 use indexmap::IndexSet;
 use once_cell::sync::OnceCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
 fn compatibility(tags: &IndexSet<String>, tag: &str) -> Option<i32> {
     tags.get_index_of(tag).map(|score| -(score as i32))
 }
 
+// Resolves and installs ask how well the same files fit the same platforms over and
+// over: every artifact of every candidate version, once while resolving and again
+// while installing. Answering from scratch means formatting every tag combination the
+// filename expands to, so each platform remembers its scores, keyed by the filename's
+// compressed tags. (Lots of files share those, too -- think "py3-none-any".) The
+// scores only depend on the platform's tags, so every platform with the same tags
+// shares one cache, instead of each clone or each call to wheel_platform starting over.
+#[derive(Debug, Default)]
+struct ScoreCache(Mutex<HashMap<String, Option<i32>>>);
+
+impl ScoreCache {
+    fn get_or_score<B: BinaryName>(
+        &self,
+        tags: &IndexSet<String>,
+        name: &B,
+    ) -> Option<i32> {
+        let key = name.compressed_tags();
+        if let Some(&score) = self.0.lock().unwrap().get(&key) {
+            return score;
+        }
+        let score = name
+            .all_tags()
+            .iter()
+            .filter_map(|tag| compatibility(tags, tag))
+            .max();
+        self.0.lock().unwrap().insert(key, score);
+        score
+    }
+}
+
+// WheelPlatforms' score caches, keyed by their tags
+static WHEEL_SCORES: Lazy<Mutex<HashMap<Vec<String>, Arc<ScoreCache>>>> =
+    Lazy::new(Default::default);
+
+// Expanding a core tag can mean generating every older glibc or macOS version, and the
+// same few core tags get turned into PybiPlatforms again and again, so we only do it
+// once per tag.
+static EXPANDED_TAGS: Lazy<Mutex<HashMap<String, Expanded>>> =
+    Lazy::new(Default::default);
+
+#[derive(Debug, Clone)]
+struct Expanded {
+    // the core tag, normalized (e.g. "manylinux2014_x86_64" -> "manylinux_2_17_x86_64")
+    core_tag: String,
+    tags: Arc<IndexSet<String>>,
+    custom_tags: Option<Arc<Vec<String>>>,
+    scores: Arc<ScoreCache>,
+    wheel_platforms: Arc<Mutex<HashMap<Vec<String>, WheelPlatform>>>,
+}
+
+// Tag priorities for exotic hardware, keyed by core tag; see set_custom_tag_priorities
+static CUSTOM_TAG_PRIORITIES: Lazy<Mutex<HashMap<String, Arc<Vec<String>>>>> =
+    Lazy::new(Default::default);
+
+// In a custom priority list, stands for the tags we'd come up with ourselves
+const STANDARD_TAGS: &str = "*";
+
+/// Lets people on unusual hardware (vendor-specific wheels on an HPC cluster, custom
+/// Linux tags, ...) say which extra platform tags their platforms can run, and how they
+/// rank against the standard ones. Maps each core tag to a list of tags, best first,
+/// where "*" stands for the usual expansion of the core tag; if there's no "*", the
+/// extra tags go after the usual ones.
+///
+/// Has to be called before making any PybiPlatforms with these core tags.
+pub fn set_custom_tag_priorities(
+    priorities: &HashMap<String, Vec<String>>,
+) -> Result<()> {
+    // '-' and '.' for sysconfig-style spellings like "emscripten-3.1.32-wasm32", which
+    // we normalize the same way PybiPlatform::new does
+    static TAG_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-z0-9_.-]+$").unwrap());
+    let mut custom = HashMap::new();
+    for (core_tag, tags) in priorities {
+        context!("Checking custom platform tags for {core_tag}");
+        if !TAG_RE.is_match(core_tag) {
+            bail!("{core_tag:?} isn't a valid platform tag");
+        }
+        let mut seen = HashSet::new();
+        let mut normalized = Vec::new();
+        for tag in tags {
+            if tag != STANDARD_TAGS && !TAG_RE.is_match(tag) {
+                bail!("{tag:?} isn't a valid platform tag");
+            }
+            let tag = tag.replace(['-', '.'], "_");
+            if !seen.insert(tag.clone()) {
+                bail!("{tag:?} is listed more than once");
+            }
+            normalized.push(tag);
+        }
+        let core_tag = expand_platform_tag(core_tag).swap_remove(0);
+        custom.insert(core_tag, Arc::new(normalized));
+    }
+    // forget anything we expanded under the old priorities
+    let mut changed: HashSet<String> = custom.keys().cloned().collect();
+    let old = std::mem::replace(&mut *CUSTOM_TAG_PRIORITIES.lock().unwrap(), custom);
+    changed.extend(old.into_keys());
+    EXPANDED_TAGS
+        .lock()
+        .unwrap()
+        .retain(|_, expanded| !changed.contains(&expanded.core_tag));
+    Ok(())
+}
+
+fn expand_with_custom(
+    standard: Vec<String>,
+    custom: Option<&Vec<String>>,
+) -> IndexSet<String> {
+    let custom = match custom {
+        Some(custom) => custom,
+        None => return standard.into_iter().collect(),
+    };
+    let mut tags = IndexSet::new();
+    if !custom.iter().any(|tag| tag == STANDARD_TAGS) {
+        tags.extend(standard.iter().cloned());
+    }
+    for tag in custom {
+        if tag == STANDARD_TAGS {
+            tags.extend(standard.iter().cloned());
+        } else {
+            tags.insert(tag.clone());
+        }
+    }
+    tags
+}
+
 #[derive(Debug, Clone)]
 pub struct PybiPlatform {
-    tags: IndexSet<String>,
+    // usually also tags[0], unless a custom priority list puts something ahead of it
+    core_tag: String,
+    tags: Arc<IndexSet<String>>,
+    custom_tags: Option<Arc<Vec<String>>>,
+    scores: Arc<ScoreCache>,
+    // wheel_platform's answers, keyed by the pybi's Pybi-Wheel-Tag list
+    wheel_platforms: Arc<Mutex<HashMap<Vec<String>, WheelPlatform>>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct WheelPlatform {
-    tags: IndexSet<String>,
+    tags: Arc<IndexSet<String>>,
+    scores: Arc<ScoreCache>,
 }
 
 pub trait Platform {
@@ -31,6 +165,9 @@ pub trait Platform {
             .filter_map(|t| self.compatibility(t.as_ref()))
             .max()
     }
+
+    /// Same as `max_compatibility(name.all_tags())`, but remembered.
+    fn binary_compatibility<B: BinaryName>(&self, name: &B) -> Option<i32>;
 }
 
 impl Platform for PybiPlatform {
@@ -41,6 +178,10 @@ impl Platform for PybiPlatform {
     fn compatibility(&self, tag: &str) -> Option<i32> {
         compatibility(&self.tags, tag)
     }
+
+    fn binary_compatibility<B: BinaryName>(&self, name: &B) -> Option<i32> {
+        self.scores.get_or_score(&self.tags, name)
+    }
 }
 
 impl Platform for WheelPlatform {
@@ -51,12 +192,39 @@ impl Platform for WheelPlatform {
     fn compatibility(&self, tag: &str) -> Option<i32> {
         compatibility(&self.tags, tag)
     }
+
+    fn binary_compatibility<B: BinaryName>(&self, name: &B) -> Option<i32> {
+        self.scores.get_or_score(&self.tags, name)
+    }
+}
+
+impl WheelPlatform {
+    fn from_tags(tags: Arc<IndexSet<String>>) -> WheelPlatform {
+        let scores = WHEEL_SCORES
+            .lock()
+            .unwrap()
+            .entry(tags.iter().cloned().collect())
+            .or_default()
+            .clone();
+        WheelPlatform { tags, scores }
+    }
 }
 
 static NATIVE_PLATFORMS: OnceCell<Vec<PybiPlatform>> = OnceCell::new();
 
 static NATIVE_PLATFORM_REFS: OnceCell<Vec<&'static PybiPlatform>> = OnceCell::new();
 
+static ROSETTA: AtomicBool = AtomicBool::new(false);
+
+/// On an Apple silicon Mac, only count x86_64 as native, so that everything gets
+/// resolved and installed to run under Rosetta 2 -- e.g. for packages that don't have
+/// arm64 wheels yet.
+///
+/// Has to be called before anything asks for the native platforms.
+pub fn use_rosetta() {
+    ROSETTA.store(true, Ordering::Relaxed);
+}
+
 /// A PybiPlatform represents a family of mutually-consistent ABIs; e.g. "win32" or
 /// "x86-64 manylinux 2.20 or less".
 ///
@@ -66,24 +234,66 @@ static NATIVE_PLATFORM_REFS: OnceCell<Vec<&'static PybiPlatform>> = OnceCell::ne
 ///
 /// A PybiPlatform is "native" if all the ABIs it represents can be run on the local
 /// system (the one that posy is running on). `native_platforms` returns (our best
-/// attempt to figure out) all the ABIs that can be run on the current system.
+/// attempt to figure out) all the ABIs that can be run on the current system. Anything
+/// else is a cross-target: we can resolve for it and download files for it, but not
+/// run it or build sdists for it.
 impl PybiPlatform {
     pub fn new(core_tag: &str) -> PybiPlatform {
+        let expanded = EXPANDED_TAGS
+            .lock()
+            .unwrap()
+            .entry(core_tag.into())
+            .or_insert_with(|| {
+                let standard = expand_platform_tag(core_tag);
+                // look up the normalized tag, so e.g. custom tags for
+                // "manylinux_2_17_x86_64" apply to "manylinux2014_x86_64" too
+                let custom_tags = CUSTOM_TAG_PRIORITIES
+                    .lock()
+                    .unwrap()
+                    .get(&standard[0])
+                    .cloned();
+                let core_tag = standard[0].clone();
+                let tags = expand_with_custom(standard, custom_tags.as_deref());
+                Expanded {
+                    core_tag,
+                    tags: Arc::new(tags),
+                    custom_tags,
+                    scores: Default::default(),
+                    wheel_platforms: Default::default(),
+                }
+            })
+            .clone();
         PybiPlatform {
-            tags: expand_platform_tag(core_tag).into_iter().collect(),
+            core_tag: expanded.core_tag,
+            tags: expanded.tags,
+            custom_tags: expanded.custom_tags,
+            scores: expanded.scores,
+            wheel_platforms: expanded.wheel_platforms,
         }
     }
 
     pub fn core_tag(&self) -> &str {
-        &self.tags[0]
+        &self.core_tag
+    }
+
+    /// The custom tag priority list this platform uses (see
+    /// `set_custom_tag_priorities`), if any.
+    pub fn custom_tags(&self) -> Option<&[String]> {
+        self.custom_tags.as_deref().map(|tags| tags.as_slice())
     }
 
     pub fn native_platforms() -> Result<&'static [&'static PybiPlatform]> {
         let platforms = NATIVE_PLATFORMS.get_or_try_init(|| -> Result<_> {
-            let tags = super::core_platform_tags()?
+            let mut tags: Vec<PybiPlatform> = super::core_platform_tags()?
                 .iter()
                 .map(|s| PybiPlatform::new(s))
                 .collect();
+            if ROSETTA.load(Ordering::Relaxed) {
+                if !super::is_apple_silicon() {
+                    bail!("Rosetta 2 is only for Apple silicon Macs");
+                }
+                tags.retain(|p| p.macos_arch() == Some("x86_64"));
+            }
 
             Ok(tags)
         })?;
@@ -91,18 +301,74 @@ impl PybiPlatform {
         Ok(refs.as_slice())
     }
 
+    /// WebAssembly targets, like "emscripten_3_1_32_wasm32" or "wasi_wasm32".
+    pub fn is_wasm(&self) -> bool {
+        is_wasm_tag(self.core_tag())
+    }
+
     pub fn is_native(&self) -> Result<bool> {
+        // no need to go poking around the system to figure this one out
+        if self.is_wasm() {
+            return Ok(false);
+        }
         let natives = PybiPlatform::native_platforms()?;
         Ok(natives
             .iter()
             .any(|native| native.compatibility(self.core_tag()).is_some()))
     }
 
+    /// If this is a macOS platform for one particular arch, which one: "arm64" or
+    /// "x86_64". That's also which half of a universal2 pybi it runs.
+    pub fn macos_arch(&self) -> Option<&'static str> {
+        let is_arm64 = self.compatibility("macosx_10_0_arm64").is_some();
+        let is_x86_64 = self.compatibility("macosx_10_0_x86_64").is_some();
+        match (is_arm64, is_x86_64) {
+            (true, false) => Some("arm64"),
+            (false, true) => Some("x86_64"),
+            _ => None,
+        }
+    }
+
+    /// Whether running this platform's binaries here means going through Rosetta 2.
+    pub fn needs_rosetta(&self) -> bool {
+        self.macos_arch() == Some("x86_64") && super::is_apple_silicon()
+    }
+
+    /// The environment marker variables for `metadata`'s pybi running on this
+    /// platform. That's mostly just whatever the pybi says, except a universal2 pybi
+    /// can't say what its platform_machine is, since that depends on which arch it
+    /// runs as.
+    pub fn environment_marker_variables(
+        &self,
+        metadata: &PybiCoreMetadata,
+    ) -> HashMap<String, String> {
+        let mut vars = metadata.environment_marker_variables.clone();
+        if !vars.contains_key("platform_machine") {
+            if let Some(arch) = self.macos_arch() {
+                vars.insert("platform_machine".into(), arch.into());
+            }
+        }
+        vars
+    }
+
     pub fn wheel_platform(&self, metadata: &PybiCoreMetadata) -> Result<WheelPlatform> {
+        if let Some(platform) = self.wheel_platforms.lock().unwrap().get(&metadata.tags)
+        {
+            return Ok(platform.clone());
+        }
+        let abi_variant = metadata.abi_variant()?;
         let mut wheel_tags = IndexSet::new();
         for wheel_tag_template in &metadata.tags {
+            if let Some(tag_variant) = AbiVariant::of_tag(wheel_tag_template) {
+                if tag_variant != abi_variant {
+                    debug!(
+                        "{abi_variant} python can't use {wheel_tag_template} wheels"
+                    );
+                    continue;
+                }
+            }
             if let Some(prefix) = wheel_tag_template.strip_suffix("-PLATFORM") {
-                for platform_tag in &self.tags {
+                for platform_tag in self.tags.iter() {
                     wheel_tags.insert(format!("{prefix}-{platform_tag}"));
                 }
             } else {
@@ -110,7 +376,33 @@ impl PybiPlatform {
             }
         }
 
-        Ok(WheelPlatform { tags: wheel_tags })
+        let platform = WheelPlatform::from_tags(Arc::new(wheel_tags));
+        self.wheel_platforms
+            .lock()
+            .unwrap()
+            .insert(metadata.tags.clone(), platform.clone());
+        Ok(platform)
+    }
+}
+
+impl WheelPlatform {
+    /// A WheelPlatform that only accepts pure-Python wheels (`pyXY-none-any` and
+    /// friends) that can run on the given Python version, regardless of what OS or
+    /// interpreter it ends up running on.
+    pub fn pure_python(python_version: &Version) -> Result<WheelPlatform> {
+        let release = &python_version.0.release;
+        if release.len() < 2 {
+            bail!("need a X.Y python version, not {python_version}");
+        }
+        let (major, minor) = (release[0], release[1]);
+        let mut tags = IndexSet::new();
+        // same order as packaging.tags.compatible_tags: most specific first
+        tags.insert(format!("py{major}{minor}-none-any"));
+        tags.insert(format!("py{major}-none-any"));
+        for older_minor in (0..minor).rev() {
+            tags.insert(format!("py{major}{older_minor}-none-any"));
+        }
+        Ok(WheelPlatform::from_tags(Arc::new(tags)))
     }
 }
 
@@ -134,6 +426,154 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_cached_scores() {
+        let platform = PybiPlatform::new("manylinux2014_x86_64");
+        // same core tag, same expansion
+        let again = PybiPlatform::new("manylinux2014_x86_64");
+        assert!(Arc::ptr_eq(&platform.tags, &again.tags));
+        assert!(Arc::ptr_eq(&platform.scores, &again.scores));
+
+        // made-up version, so no other test shares this cache
+        let pure = WheelPlatform::pure_python(&"3.99".try_into().unwrap()).unwrap();
+        // another platform with the same tags shares its scores
+        let same = WheelPlatform::pure_python(&"3.99.1".try_into().unwrap()).unwrap();
+        assert!(Arc::ptr_eq(&pure.scores, &same.scores));
+        for name in [
+            "foo-1.0-py2.py3-none-any.whl",
+            "foo-1.0-cp310-cp310-manylinux_2_17_x86_64.manylinux2014_x86_64.whl",
+            "foo-1.0-py311-none-any.whl",
+        ] {
+            let name: WheelName = name.parse().unwrap();
+            let expected = pure.max_compatibility(name.all_tags());
+            for _ in 0..2 {
+                assert_eq!(pure.binary_compatibility(&name), expected);
+            }
+        }
+        assert_eq!(pure.scores.0.lock().unwrap().len(), 3);
+        // ...and a different file with the same tags gets the same answer
+        let other: WheelName = "bar-2.0-py2.py3-none-any.whl".parse().unwrap();
+        assert!(pure.binary_compatibility(&other).is_some());
+        assert_eq!(pure.scores.0.lock().unwrap().len(), 3);
+
+        let pybi: PybiName =
+            "cpython-3.11.1-manylinux_2_17_x86_64.manylinux_2_10_x86_64.pybi"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            platform.binary_compatibility(&pybi),
+            platform.max_compatibility(pybi.all_tags())
+        );
+        assert!(platform.binary_compatibility(&pybi).is_some());
+    }
+
+    #[test]
+    fn test_custom_tag_priorities() {
+        // made-up tags, so we don't disturb other tests' platforms
+        let before = PybiPlatform::new("linux_fancy64");
+        assert!(before.custom_tags().is_none());
+        set_custom_tag_priorities(&HashMap::from([
+            (
+                "linux_fancy64".to_string(),
+                vec![
+                    "vendor_fancy64".to_string(),
+                    "*".into(),
+                    "generic_fancy".into(),
+                ],
+            ),
+            ("linux_plain64".to_string(), vec!["plain_extra".to_string()]),
+            // sysconfig spelling, for the key and the tags
+            ("linux-dashed.64".to_string(), vec!["dashed-extra".into()]),
+        ]))
+        .unwrap();
+
+        let platform = PybiPlatform::new("linux_fancy64");
+        assert_eq!(platform.core_tag(), "linux_fancy64");
+        assert_eq!(
+            platform.tags().collect::<Vec<_>>(),
+            vec!["vendor_fancy64", "linux_fancy64", "generic_fancy"]
+        );
+        assert_eq!(
+            platform.custom_tags().unwrap(),
+            ["vendor_fancy64", "*", "generic_fancy"]
+        );
+        assert!(
+            platform.compatibility("vendor_fancy64").unwrap()
+                > platform.compatibility("linux_fancy64").unwrap()
+        );
+        // no "*" means they go after the standard tags
+        assert_eq!(
+            PybiPlatform::new("linux_plain64")
+                .tags()
+                .collect::<Vec<_>>(),
+            vec!["linux_plain64", "plain_extra"]
+        );
+        assert_eq!(
+            PybiPlatform::new("linux_dashed_64")
+                .tags()
+                .collect::<Vec<_>>(),
+            vec!["linux_dashed_64", "dashed_extra"]
+        );
+
+        for bad in [vec!["Not A Tag"], vec!["a_tag", "a_tag"]] {
+            let bad = bad.into_iter().map(String::from).collect();
+            assert!(set_custom_tag_priorities(&HashMap::from([(
+                "linux_fancy64".to_string(),
+                bad
+            )]))
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn test_wasm_pybi_platform() {
+        let platform = PybiPlatform::new("emscripten-3.1.32-wasm32");
+        assert_eq!(platform.core_tag(), "emscripten_3_1_32_wasm32");
+        assert!(platform.is_wasm());
+        assert!(!platform.is_native().unwrap());
+        assert!(platform.compatibility("emscripten_3_1_32_wasm32").is_some());
+        assert!(platform.compatibility("emscripten_3_1_14_wasm32").is_none());
+        assert!(!PybiPlatform::new("win_amd64").is_wasm());
+    }
+
+    #[test]
+    fn test_universal2_environment_marker_variables() {
+        let universal2: PybiCoreMetadata = indoc! {br#"
+            Metadata-Version: 2.1
+            Name: cpython
+            Version: 3.11
+            Pybi-Environment-Marker-Variables: {"sys_platform": "darwin"}
+            Pybi-Paths: {}
+        "#}
+        .as_slice()
+        .try_into()
+        .unwrap();
+        let arm64 = PybiPlatform::new("macosx_11_0_arm64");
+        let x86_64 = PybiPlatform::new("macosx_11_0_x86_64");
+        assert_eq!(arm64.macos_arch(), Some("arm64"));
+        assert_eq!(x86_64.macos_arch(), Some("x86_64"));
+        assert_eq!(
+            PybiPlatform::new("macosx_11_0_universal2").macos_arch(),
+            None
+        );
+        assert_eq!(PybiPlatform::new("win_arm64").macos_arch(), None);
+        assert!(!arm64.needs_rosetta());
+
+        let vars = x86_64.environment_marker_variables(&universal2);
+        assert_eq!(vars["platform_machine"], "x86_64");
+        assert_eq!(vars["sys_platform"], "darwin");
+        let vars = arm64.environment_marker_variables(&universal2);
+        assert_eq!(vars["platform_machine"], "arm64");
+
+        // a single-arch pybi already knows
+        let mut single = universal2;
+        single
+            .environment_marker_variables
+            .insert("platform_machine".into(), "arm64".into());
+        let vars = x86_64.environment_marker_variables(&single);
+        assert_eq!(vars["platform_machine"], "arm64");
+    }
+
     #[test]
     fn test_pybi_platform_to_wheel_platform() {
         let pybi_platform = PybiPlatform::new("macosx_11_0_arm64");
@@ -155,6 +595,8 @@ mod test {
         // given a pybi that can handle both, on a platform that can handle both, pick
         // the preferred platform and restrict to it.
         let wheel_platform = pybi_platform.wheel_platform(&fake_metadata).unwrap();
+        let again = pybi_platform.wheel_platform(&fake_metadata).unwrap();
+        assert!(Arc::ptr_eq(&wheel_platform.tags, &again.tags));
         assert!(wheel_platform
             .compatibility("foo-bar-macosx_11_0_arm64")
             .is_some());
@@ -184,4 +626,49 @@ mod test {
                     .unwrap()
         );
     }
+
+    #[test]
+    fn test_freethreaded_wheel_platform() {
+        let pybi_platform = PybiPlatform::new("manylinux_2_17_x86_64");
+        let fake_metadata: PybiCoreMetadata = indoc! {b"
+            Metadata-Version: 2.1
+            Name: cpython
+            Version: 3.13
+            Pybi-Environment-Marker-Variables: {}
+            Pybi-Paths: {}
+            Pybi-Wheel-Tag: cp313-cp313t-PLATFORM
+            Pybi-Wheel-Tag: cp313-abi3-PLATFORM
+            Pybi-Wheel-Tag: py3-none-any
+        "}
+        .as_slice()
+        .try_into()
+        .unwrap();
+
+        let wheel_platform = pybi_platform.wheel_platform(&fake_metadata).unwrap();
+        assert!(wheel_platform
+            .compatibility("cp313-cp313t-manylinux_2_17_x86_64")
+            .is_some());
+        assert!(wheel_platform.compatibility("py3-none-any").is_some());
+        // regular-ABI wheels are never usable, even if the pybi lists them
+        assert!(wheel_platform
+            .compatibility("cp313-cp313-manylinux_2_17_x86_64")
+            .is_none());
+        assert!(wheel_platform
+            .compatibility("cp313-abi3-manylinux_2_17_x86_64")
+            .is_none());
+    }
+
+    #[test]
+    fn test_pure_python_wheel_platform() {
+        let platform =
+            WheelPlatform::pure_python(&"3.10.2".try_into().unwrap()).unwrap();
+        assert!(platform.compatibility("py3-none-any").is_some());
+        assert!(platform.compatibility("py38-none-any").is_some());
+        assert!(platform.compatibility("py311-none-any").is_none());
+        assert!(platform.compatibility("cp310-cp310-linux_x86_64").is_none());
+        assert!(
+            platform.compatibility("py310-none-any").unwrap()
+                > platform.compatibility("py3-none-any").unwrap()
+        );
+    }
 }
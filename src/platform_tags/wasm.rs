@@ -0,0 +1,58 @@
+use crate::prelude::*;
+
+// WebAssembly platforms. These are never native -- posy can't run a wasm interpreter
+// itself -- but it's still useful to resolve and download environments for them, e.g.
+// to ship to a browser with Pyodide, or to a WASI runtime.
+//
+// Unlike manylinux or macOS, there's no backwards compatibility between versions:
+// emscripten changes its ABI from release to release, so a pybi built with emscripten
+// 3.1.32 can only use wheels built with exactly 3.1.32. Same for Pyodide's ABI
+// versions (pyodide_2024_0 etc.). And WASI preview 1 doesn't put a version in its tag
+// at all. So none of these expand to anything but themselves.
+
+static WASM_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(wasi|emscripten_[0-9]+_[0-9]+_[0-9]+|pyodide_[0-9]+_[0-9]+)_wasm32$")
+        .unwrap()
+});
+
+pub fn is_wasm_tag(tag: &str) -> bool {
+    WASM_RE.is_match(tag)
+}
+
+/// If `tag` is a wasm platform, returns the tags it supports (i.e., just itself).
+///
+/// Also accepts the spelling that `sysconfig.get_platform()` uses, like
+/// "emscripten-3.1.32-wasm32", since that's what people tend to copy-paste.
+pub fn expand_wasm_tag(tag: &str) -> Option<Vec<String>> {
+    let tag = tag.replace(['-', '.'], "_");
+    if is_wasm_tag(&tag) {
+        Some(vec![tag])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wasm_tags() {
+        assert!(is_wasm_tag("wasi_wasm32"));
+        assert!(is_wasm_tag("emscripten_3_1_32_wasm32"));
+        assert!(is_wasm_tag("pyodide_2024_0_wasm32"));
+        assert!(!is_wasm_tag("emscripten_3_1_wasm32"));
+        assert!(!is_wasm_tag("wasi_wasm64"));
+        assert!(!is_wasm_tag("manylinux_2_17_x86_64"));
+
+        assert_eq!(
+            expand_wasm_tag("emscripten-3.1.32-wasm32"),
+            Some(vec!["emscripten_3_1_32_wasm32".to_string()])
+        );
+        assert_eq!(
+            expand_wasm_tag("wasi_wasm32"),
+            Some(vec!["wasi_wasm32".to_string()])
+        );
+        assert_eq!(expand_wasm_tag("win_amd64"), None);
+    }
+}
@@ -0,0 +1,321 @@
+use crate::prelude::*;
+
+// Reading the requirements out of a Poetry project's pyproject.toml, so it can use posy
+// without rewriting them all first.
+//
+// Poetry's version constraints are mostly PEP 440, plus a few extensions: "^1.2"
+// (compatible with 1.2 by semver rules, so >=1.2,<2), "~1.2" (>=1.2,<1.3), bare
+// versions meaning ==, and "*" meaning anything. We translate those into PEP 440 here,
+// before anything gets parsed, rather than teaching the real specifier parser a
+// second syntax that would then be accepted everywhere else too.
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum PoetryDependency {
+    Constraint(String),
+    Detailed(DetailedDependency),
+    // different constraints for different Pythons or platforms
+    Several(Vec<DetailedDependency>),
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DetailedDependency {
+    version: Option<String>,
+    #[serde(default)]
+    extras: Vec<String>,
+    markers: Option<String>,
+    // constraints on the Python version and sys_platform, which become markers
+    python: Option<String>,
+    platform: Option<String>,
+    // only installed with one of the project's extras
+    #[serde(default)]
+    optional: bool,
+    // local or VCS dependencies, which we don't handle
+    path: Option<String>,
+    git: Option<String>,
+    url: Option<String>,
+}
+
+const OPERATORS: &[&str] =
+    &["===", "==", "!=", "~=", "<=", ">=", "<", ">", "^", "~", "="];
+
+/// Translates a Poetry version constraint into PEP 440 specifiers (possibly none, for
+/// "*"). Returns whether it used any of Poetry's extensions, too.
+pub fn translate_constraint(constraint: &str) -> Result<(String, bool)> {
+    if constraint.contains('|') {
+        bail!("can't translate {constraint:?}: posy can't express 'or' constraints");
+    }
+    // Poetry allows both ">=1.2,<2" and ">= 1.2 < 2", so split on commas and
+    // whitespace, and then glue any operators back on to the version after them.
+    let mut tokens = Vec::<String>::new();
+    let mut pending_op = None;
+    for token in constraint.split(|c: char| c == ',' || c.is_whitespace()) {
+        if token.is_empty() {
+            continue;
+        }
+        if OPERATORS.contains(&token) {
+            pending_op = Some(token);
+            continue;
+        }
+        tokens.push(format!("{}{token}", pending_op.take().unwrap_or_default()));
+    }
+    if let Some(op) = pending_op {
+        bail!("can't translate {constraint:?}: {op:?} without a version");
+    }
+
+    let mut specifiers = Vec::new();
+    let mut extended = false;
+    for token in &tokens {
+        if token == "*" {
+            continue;
+        }
+        if let Some(version) = token.strip_prefix('^') {
+            extended = true;
+            let release = split_release(constraint, version)?;
+            // bump the first non-zero piece, or the last one if they're all zeros
+            let bump = release
+                .iter()
+                .position(|&n| n != 0)
+                .unwrap_or(release.len() - 1);
+            specifiers.push(format!(">= {version}"));
+            specifiers.push(format!("< {}", upper_bound(&release, bump)));
+            continue;
+        }
+        if let Some(version) = token.strip_prefix('~').filter(|v| !v.starts_with('=')) {
+            extended = true;
+            let release = split_release(constraint, version)?;
+            let bump = std::cmp::min(1, release.len() - 1);
+            specifiers.push(format!(">= {version}"));
+            specifiers.push(format!("< {}", upper_bound(&release, bump)));
+            continue;
+        }
+        match OPERATORS.iter().find(|op| token.starts_with(**op)) {
+            Some(&"=") => {
+                extended = true;
+                specifiers.push(format!("== {}", &token[1..]));
+            }
+            Some(op) => specifiers.push(format!("{op} {}", &token[op.len()..])),
+            None => {
+                extended = true;
+                specifiers.push(format!("== {token}"));
+            }
+        }
+    }
+    let specifiers = specifiers.join(", ");
+    // make sure we made something real (the parser only checks the operators; the
+    // versions get checked when they're turned into ranges)
+    let parsed = Specifiers::try_from(specifiers.as_str())
+        .wrap_err_with(|| format!("can't translate {constraint:?}"))?;
+    for specifier in &parsed.0 {
+        specifier
+            .to_ranges()
+            .wrap_err_with(|| format!("can't translate {constraint:?}"))?;
+    }
+    Ok((specifiers, extended))
+}
+
+// "1.2.3b1" -> [1, 2, 3]
+fn split_release(constraint: &str, version: &str) -> Result<Vec<u64>> {
+    let end = version
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(version.len());
+    // the trim is for "1.2.post1"; "1.2." on its own fails the final check in
+    // translate_constraint
+    version[..end]
+        .trim_end_matches('.')
+        .split('.')
+        .map(|piece| piece.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .wrap_err_with(|| format!("can't translate {constraint:?}"))
+}
+
+fn upper_bound(release: &[u64], bump: usize) -> String {
+    let mut upper = release[..=bump].to_vec();
+    upper[bump] += 1;
+    upper
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Turns a table like [tool.poetry.dependencies] into requirements. Skips the entry
+/// for Python itself, optional dependencies (which belong to extras), and anything
+/// that isn't from an index, since there's no requirement string for those.
+///
+/// Also returns a note for every constraint that needed Poetry's extensions (like
+/// "^1.2") to translate, so the user can check we read it the way they meant.
+pub fn requirements(
+    table: &dyn toml_edit::TableLike,
+) -> Result<(Vec<UserRequirement>, Vec<String>)> {
+    let mut requirements = Vec::new();
+    let mut notes = Vec::new();
+    for (name, item) in table.iter() {
+        if name == "python" {
+            continue;
+        }
+        context!("translating Poetry dependency {name:?}");
+        let dependency: PoetryDependency = toml_edit::de::from_item(item.clone())?;
+        let variants = match dependency {
+            PoetryDependency::Constraint(version) => vec![DetailedDependency {
+                version: Some(version),
+                ..Default::default()
+            }],
+            PoetryDependency::Detailed(detailed) => vec![detailed],
+            PoetryDependency::Several(several) => several,
+        };
+        for variant in variants {
+            if variant.optional {
+                debug!("skipping {name}, which is optional");
+                continue;
+            }
+            if variant.path.is_some() || variant.git.is_some() || variant.url.is_some()
+            {
+                warn!(
+                    "skipping {name}, which isn't from a package index; add it by hand"
+                );
+                continue;
+            }
+            requirements.push(translate_dependency(name, &variant, &mut notes)?);
+        }
+    }
+    Ok((requirements, notes))
+}
+
+fn translate_dependency(
+    name: &str,
+    dependency: &DetailedDependency,
+    notes: &mut Vec<String>,
+) -> Result<UserRequirement> {
+    let mut requirement = name.to_string();
+    if !dependency.extras.is_empty() {
+        requirement.push_str(&format!("[{}]", dependency.extras.join(",")));
+    }
+    // this runs every time the project gets loaded, so instead of warning here we
+    // hand back notes for 'posy lock' to show
+    let mut translated = |constraint: &str| -> Result<String> {
+        let (specifiers, extended) = translate_constraint(constraint)?;
+        if extended {
+            notes.push(format!(
+                "translated {name}'s Poetry constraint {constraint:?} to {specifiers:?}"
+            ));
+        }
+        Ok(specifiers)
+    };
+    if let Some(version) = &dependency.version {
+        let specifiers = translated(version)?;
+        if !specifiers.is_empty() {
+            requirement.push(' ');
+            requirement.push_str(&specifiers);
+        }
+    }
+    let mut markers = Vec::new();
+    if let Some(python) = &dependency.python {
+        let specifiers = translated(python)?;
+        for specifier in Specifiers::try_from(specifiers.as_str())?.0 {
+            markers.push(format!(
+                "python_full_version {} \"{}\"",
+                specifier.op, specifier.value
+            ));
+        }
+    }
+    if let Some(platform) = &dependency.platform {
+        markers.push(format!("sys_platform == \"{platform}\""));
+    }
+    if let Some(expr) = &dependency.markers {
+        markers.push(format!("({expr})"));
+    }
+    if !markers.is_empty() {
+        requirement.push_str("; ");
+        requirement.push_str(&markers.join(" and "));
+    }
+    requirement.as_str().try_into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn test_translate_constraint() {
+        for (poetry, pep440, extended) in [
+            ("^1.2.3", ">= 1.2.3, < 2", true),
+            ("^1.2", ">= 1.2, < 2", true),
+            ("^0.2.3", ">= 0.2.3, < 0.3", true),
+            ("^0.0.3", ">= 0.0.3, < 0.0.4", true),
+            ("^0.0", ">= 0.0, < 0.1", true),
+            ("^0", ">= 0, < 1", true),
+            ("^1.2.3b1", ">= 1.2.3b1, < 2", true),
+            ("~1.2.3", ">= 1.2.3, < 1.3", true),
+            ("~1.2", ">= 1.2, < 1.3", true),
+            ("~1", ">= 1, < 2", true),
+            ("~=1.2", "~= 1.2", false),
+            ("1.2.3", "== 1.2.3", true),
+            ("=1.2.3", "== 1.2.3", true),
+            ("1.2.*", "== 1.2.*", true),
+            ("*", "", false),
+            (">=1.2,<2", ">= 1.2, < 2", false),
+            (">= 1.2 < 2", ">= 1.2, < 2", false),
+            ("^1.2, !=1.5", ">= 1.2, < 2, != 1.5", true),
+        ] {
+            assert_eq!(
+                translate_constraint(poetry).unwrap(),
+                (pep440.to_string(), extended),
+                "{poetry}"
+            );
+        }
+        for bad in ["^1.2 || ^2.0", "^", ">=", "^x", "~1.2.", "==what"] {
+            assert!(translate_constraint(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_requirements() {
+        let doc = indoc! {r#"
+            [tool.poetry.dependencies]
+            python = "^3.8"
+            requests = "^2.28"
+            attrs = "*"
+            black = { version = "~23.1", extras = ["d", "jupyter"] }
+            pywin32 = { version = ">=305", platform = "win32" }
+            tomli = { version = "^2.0", python = "<3.11" }
+            numpy = [
+                { version = "~1.24", python = "<3.9" },
+                { version = "^1.26", python = ">=3.9", markers = "platform_machine != 'armv7l'" },
+            ]
+            docs-theme = { version = "^1.0", optional = true }
+            mylib = { path = "../mylib" }
+        "#}
+        .parse::<toml_edit::Document>()
+        .unwrap();
+        let table = doc["tool"]["poetry"]["dependencies"]
+            .as_table_like()
+            .unwrap();
+        let (requirements, notes) = requirements(table).unwrap();
+        let requirements = requirements
+            .iter()
+            .map(|r| r.to_string())
+            .collect::<Vec<_>>();
+        let expected = [
+            "requests >= 2.28, < 3",
+            "attrs",
+            "black[d,jupyter] >= 23.1, < 23.2",
+            "pywin32 >= 305; sys_platform == \"win32\"",
+            "tomli >= 2.0, < 3; python_full_version < \"3.11\"",
+            "numpy >= 1.24, < 1.25; python_full_version < \"3.9\"",
+            "numpy >= 1.26, < 2; python_full_version >= \"3.9\" and \
+             platform_machine != \"armv7l\"",
+        ]
+        .iter()
+        .map(|s| UserRequirement::try_from(*s).unwrap().to_string())
+        .collect::<Vec<_>>();
+        assert_eq!(requirements, expected);
+        // just the carets and tildes, not attrs = "*" or the plain comparisons
+        assert_eq!(notes.len(), 5);
+        assert_eq!(
+            notes[0],
+            r#"translated requests's Poetry constraint "^2.28" to ">= 2.28, < 3""#
+        );
+    }
+}
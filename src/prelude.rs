@@ -16,7 +16,7 @@ pub use tracing::{debug, info, trace, warn};
 pub use url::Url;
 
 pub use crate::error::PosyError;
-pub use crate::platform_tags::{Platform, PybiPlatform, WheelPlatform};
+pub use crate::platform_tags::{AbiVariant, Platform, PybiPlatform, WheelPlatform};
 
 pub use crate::tree::NicePathBuf;
 pub use crate::try_from_str_boilerplate;
@@ -0,0 +1,222 @@
+use std::process::{Child, Command, ExitStatus};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::interrupt::CancellationToken;
+use crate::prelude::*;
+
+// Child processes that we wait on (as opposed to exec), like sdist builds. A build
+// backend can start a whole tree of processes -- compilers, cmake, another python --
+// and if we give up on the build (an error, or Ctrl-C), all of them have to go too.
+// Otherwise an orphaned python can hang on to the build dir's KVDirStore lock, and
+// every later build of that sdist blocks waiting for it.
+//
+// On Unix, the child goes in its own process group, so we can kill the group. That
+// also means the terminal's Ctrl-C only reaches us, and we decide what happens. On
+// Windows, the child goes in a job object that kills everything in it when it's
+// closed -- which happens even if we exit without cleaning up.
+
+// Process groups that are still running, so that if the user gives up on a clean
+// shutdown (see interrupt.rs), we can still take them down with us.
+static LIVE_GROUPS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+// How often wait() checks for Ctrl-C
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub struct ProcessTree {
+    child: Child,
+    #[cfg(windows)]
+    job: job::Job,
+    finished: bool,
+}
+
+impl ProcessTree {
+    pub fn spawn(cmd: &mut Command) -> Result<ProcessTree> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            cmd.process_group(0);
+        }
+        // XX TODO: on Windows, the child can start its own children before we get it
+        // into the job. The trampolines avoid that by creating it suspended, but std
+        // doesn't give us the thread handle we'd need to resume it.
+        #[cfg(windows)]
+        let job = job::Job::new()?;
+        let child = cmd.spawn()?;
+        #[cfg(windows)]
+        job.assign(&child)?;
+        LIVE_GROUPS.lock().unwrap().push(child.id());
+        Ok(ProcessTree {
+            child,
+            #[cfg(windows)]
+            job,
+            finished: false,
+        })
+    }
+
+    pub fn child_mut(&mut self) -> &mut Child {
+        &mut self.child
+    }
+
+    /// Waits for the child to exit. If `cancel` gets cancelled in the meantime (e.g. by
+    /// Ctrl-C), kills the whole tree and fails with PosyError::Interrupted.
+    pub fn wait(&mut self, cancel: &CancellationToken) -> Result<ExitStatus> {
+        loop {
+            if let Some(status) = self.child.try_wait()? {
+                self.finished = true;
+                forget_group(self.child.id());
+                return Ok(status);
+            }
+            if cancel.is_cancelled() {
+                self.kill();
+                Err(PosyError::Interrupted)?;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Kills the child and everything it started, and reaps the child.
+    pub fn kill(&mut self) {
+        if self.finished {
+            return;
+        }
+        #[cfg(unix)]
+        kill_group(self.child.id());
+        #[cfg(windows)]
+        self.job.terminate();
+        // can only fail if it's already been reaped
+        let _ = self.child.wait();
+        self.finished = true;
+        forget_group(self.child.id());
+    }
+}
+
+impl Drop for ProcessTree {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+fn forget_group(pid: u32) {
+    LIVE_GROUPS.lock().unwrap().retain(|&live| live != pid);
+}
+
+#[cfg(unix)]
+fn kill_group(pgid: u32) {
+    // a negative pid means the whole group
+    let pgid = -(pgid as libc::pid_t);
+    if unsafe { libc::kill(pgid, libc::SIGKILL) } != 0 {
+        debug!(
+            "couldn't kill process group {}: {}",
+            -pgid,
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+/// Kills every process tree that's still running, for when we're about to exit
+/// without unwinding. (On Windows, exiting closes the job objects, which does it for
+/// us.)
+pub fn kill_all() {
+    #[cfg(unix)]
+    for &pgid in LIVE_GROUPS.lock().unwrap().iter() {
+        kill_group(pgid);
+    }
+}
+
+#[cfg(windows)]
+mod job {
+    use std::mem::size_of;
+    use std::os::windows::io::AsRawHandle;
+    use std::process::Child;
+
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows_sys::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation,
+        SetInformationJobObject, TerminateJobObject,
+        JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+        JOB_OBJECT_LIMIT_SILENT_BREAKAWAY_OK,
+    };
+
+    // Same settings as the trampolines use: everything in the job dies when the last
+    // handle to it closes, except for processes that explicitly ask to break away.
+    pub struct Job(HANDLE);
+
+    impl Job {
+        pub fn new() -> std::io::Result<Job> {
+            unsafe {
+                let handle = CreateJobObjectW(std::ptr::null(), std::ptr::null());
+                if handle == 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                let job = Job(handle);
+                let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+                info.BasicLimitInformation.LimitFlags =
+                    JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE
+                        | JOB_OBJECT_LIMIT_SILENT_BREAKAWAY_OK;
+                let ok = SetInformationJobObject(
+                    job.0,
+                    JobObjectExtendedLimitInformation,
+                    &info as *const _ as *const _,
+                    size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+                );
+                if ok == 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(job)
+            }
+        }
+
+        pub fn assign(&self, child: &Child) -> std::io::Result<()> {
+            let process = child.as_raw_handle() as HANDLE;
+            if unsafe { AssignProcessToJobObject(self.0, process) } == 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        pub fn terminate(&self) {
+            unsafe {
+                TerminateJobObject(self.0, 1);
+            }
+        }
+    }
+
+    impl Drop for Job {
+        fn drop(&mut self) {
+            unsafe {
+                CloseHandle(self.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_process_tree_kill() {
+        let tmp = tempfile::tempdir().unwrap();
+        let started = tmp.path().join("started");
+        let finished = tmp.path().join("finished");
+        // the grandchild would outlive the shell, if it didn't get killed with it
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(format!(
+            "(sleep 0.5; touch {}) & touch {}; wait",
+            finished.display(),
+            started.display()
+        ));
+        let mut tree = ProcessTree::spawn(&mut cmd).unwrap();
+        let pid = tree.child_mut().id();
+        while !started.exists() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(LIVE_GROUPS.lock().unwrap().contains(&pid));
+        drop(tree);
+        assert!(!LIVE_GROUPS.lock().unwrap().contains(&pid));
+        std::thread::sleep(Duration::from_secs(1));
+        assert!(!finished.exists());
+    }
+}
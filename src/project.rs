@@ -0,0 +1,1303 @@
+use std::collections::hash_map::Entry;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::blob_store::LinkMode;
+use crate::output::parse_size;
+use crate::package_db::{AttestationPolicy, IndexStrategy, PackageDB, YankedPolicy};
+use crate::prelude::*;
+use crate::resolve::{
+    ArtifactDownload, Blueprint, BlueprintSet, Brief, ResolutionStrategy,
+    ResolverPolicy,
+};
+
+// Project-level configuration. It lives in the [tool.posy] table of pyproject.toml, or
+// in a standalone posy.toml (same contents, minus the [tool.posy] prefix) for projects
+// that don't otherwise need a pyproject.toml.
+
+/// Libraries and applications want different things from their environments. An app
+/// wants every install to be exactly what was tested; a library wants to keep testing
+/// against whatever its users would get from a fresh install.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProjectKind {
+    #[default]
+    App,
+    Library,
+}
+
+impl ProjectKind {
+    /// When re-resolving, does a pre-release that's already pinned stay allowed, even
+    /// if the user never asked for pre-releases of that package?
+    pub fn keep_pinned_prereleases(&self) -> bool {
+        *self == ProjectKind::App
+    }
+
+    /// Does 'posy lock' refuse to pin files that the index didn't give hashes for? An
+    /// app's lockfile is what gets deployed, so yes. A library's is only for its own
+    /// tests.
+    pub fn requires_hashes(&self) -> bool {
+        *self == ProjectKind::App
+    }
+
+    /// Does 'posy sync' refuse a lockfile that's out of date with the requirements?
+    /// Libraries don't really have a lockfile to keep fresh, so for them we re-resolve
+    /// instead.
+    pub fn requires_fresh_lock(&self) -> bool {
+        *self == ProjectKind::App
+    }
+}
+
+/// What to do when an environment comes out bigger than the project's size-budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SizeBudgetPolicy {
+    #[default]
+    Warn,
+    Error,
+}
+
+fn deserialize_size<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<u64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    parse_size(&text)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ProjectConfig {
+    #[serde(default)]
+    pub kind: ProjectKind,
+    // which Python to use, e.g. "cpython >= 3.10"
+    pub python: Option<PythonRequirement>,
+    // what to try if resolving with `python` fails, in order, e.g. ["cpython >= 3.11"]
+    #[serde(default)]
+    pub python_fallbacks: Vec<PythonRequirement>,
+    // what `posy run` installs
+    #[serde(default)]
+    pub requirements: Vec<UserRequirement>,
+    // interpreter flags for scripts in the env, e.g. ["-I", "-X", "utf8"]
+    #[serde(default)]
+    pub python_flags: Vec<String>,
+    // see EnvForest::rename_colliding_scripts
+    #[serde(default)]
+    pub rename_colliding_scripts: bool,
+    // share unpacked wheels with other projects; see EnvForest::shared_wheels. Off by
+    // default, because the hardlinks mean that editing a file inside one env edits it
+    // everywhere.
+    #[serde(default)]
+    pub share_wheels: bool,
+    // "hardlink" or "reflink" to share identical files between envs; see
+    // blob_store::LinkMode
+    #[serde(default)]
+    pub link_mode: LinkMode,
+    // for old sdists whose builds assume pip is installed; see PackageDB::build_pip_shim
+    #[serde(default)]
+    pub build_pip_shim: bool,
+    // see PackageDB::index_strategy and PackageDB::index_pins
+    #[serde(default)]
+    pub index_strategy: IndexStrategy,
+    #[serde(default)]
+    pub index_pins: HashMap<PackageName, Url>,
+    // see PackageDB::prefer_local_versions
+    #[serde(default)]
+    pub prefer_local_versions: HashMap<PackageName, Url>,
+    // indexes whose PEP 658 .metadata files we shouldn't bother asking for; see
+    // PackageDB::set_no_metadata_files
+    #[serde(default)]
+    pub no_metadata_files: Vec<Url>,
+    // see PackageDB::yanked_policy
+    #[serde(default)]
+    pub yanked: YankedPolicy,
+    // see PackageDB::attestation_policy
+    #[serde(default)]
+    pub attestations: AttestationPolicy,
+    // "lowest" to lock the oldest versions the requirements allow; see
+    // ResolutionStrategy
+    #[serde(default)]
+    pub resolution: ResolutionStrategy,
+    // pybi platform tags to list exact files for in posy.lock; see Lockfile::artifacts
+    #[serde(default)]
+    pub audit_platforms: Vec<String>,
+    // pybi platform tags to lock for, besides whatever 'posy lock' runs on; see
+    // Lockfile::platforms
+    #[serde(default)]
+    pub lock_platforms: Vec<String>,
+    // extra platform tags for exotic hardware, keyed by core tag, e.g.
+    // {"manylinux_2_28_aarch64" = ["mycluster_aarch64", "*", "linux_aarch64"]}; see
+    // platform_tags::set_custom_tag_priorities
+    #[serde(default)]
+    pub platform_tags: HashMap<String, Vec<String>>,
+    // the most space the env should take up, e.g. "250M" to fit in a container or
+    // serverless layer; see commands::check_size_budget
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub size_budget: Option<u64>,
+    #[serde(default)]
+    pub size_budget_policy: SizeBudgetPolicy,
+}
+
+// PEP 735 dependency groups: named lists of requirements in pyproject.toml, which can
+// also pull in other groups. Group names follow the same rules (and normalization) as
+// package names.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum DependencyGroupItem {
+    // parsed later, so we can give better error messages
+    Requirement(String),
+    Include(IncludeGroup),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct IncludeGroup {
+    include_group: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DependencyGroups(HashMap<String, Vec<DependencyGroupItem>>);
+
+impl DependencyGroups {
+    fn parse(item: toml_edit::Item) -> Result<DependencyGroups> {
+        context!("Parsing [dependency-groups]");
+        let raw: HashMap<String, Vec<DependencyGroupItem>> =
+            toml_edit::de::from_item(item)?;
+        let mut groups = HashMap::new();
+        for (name, items) in raw {
+            let name: PackageName = name.as_str().try_into()?;
+            if groups.insert(name.normalized().to_owned(), items).is_some() {
+                bail!(
+                    "more than one dependency group named {:?}",
+                    name.normalized()
+                );
+            }
+        }
+        Ok(DependencyGroups(groups))
+    }
+
+    /// All the requirements in the given group, including any it pulls in from other
+    /// groups.
+    pub fn requirements(&self, group: &str) -> Result<Vec<UserRequirement>> {
+        let mut requirements = Vec::new();
+        self.expand(group, &mut Vec::new(), &mut requirements)?;
+        Ok(requirements)
+    }
+
+    fn expand(
+        &self,
+        group: &str,
+        stack: &mut Vec<String>,
+        out: &mut Vec<UserRequirement>,
+    ) -> Result<()> {
+        let name: PackageName = group.try_into()?;
+        let key = name.normalized().to_owned();
+        if stack.contains(&key) {
+            bail!(
+                "dependency group {:?} includes itself ({} -> {key})",
+                key,
+                stack.join(" -> ")
+            );
+        }
+        let items = self
+            .0
+            .get(&key)
+            .ok_or_else(|| eyre!("no dependency group named {group:?}"))?;
+        stack.push(key);
+        for item in items {
+            match item {
+                DependencyGroupItem::Requirement(req) => out.push(
+                    req.as_str()
+                        .try_into()
+                        .wrap_err_with(|| format!("in dependency group {group:?}"))?,
+                ),
+                DependencyGroupItem::Include(include) => {
+                    self.expand(&include.include_group, stack, out)?
+                }
+            }
+        }
+        stack.pop();
+        Ok(())
+    }
+}
+
+pub struct Project {
+    pub root: PathBuf,
+    pub config: ProjectConfig,
+    pub dependency_groups: DependencyGroups,
+    // if we got our requirements from [tool.poetry], the Poetry constraints we had to
+    // translate (see poetry::requirements); 'posy lock' warns about them
+    pub poetry_notes: Vec<String>,
+}
+
+const LOCKFILE_NAME: &str = "posy.lock";
+
+/// Where a project's config can live, in order of preference.
+pub const CONFIG_FILE_NAMES: &[&str] = &["posy.toml", "pyproject.toml"];
+
+/// The config file for the project rooted at `dir`, if there is one.
+pub fn config_path(dir: &Path) -> Option<PathBuf> {
+    CONFIG_FILE_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.exists())
+}
+
+/// What `posy lock` writes: a Blueprint, plus the Brief it was resolved from, so we can
+/// tell when the project has changed underneath it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Lockfile {
+    pub brief: Brief,
+    // what we resolved on the machine that ran 'posy lock'
+    pub blueprint: Blueprint,
+    /// For projects with `lock-platforms` set: a blueprint for each of those
+    /// platforms. Machines that match one of them use it instead of `blueprint`.
+    #[serde(default, skip_serializing_if = "BlueprintSet::is_empty")]
+    pub platforms: BlueprintSet,
+    /// For projects with `audit-platforms` set: every file that installing on each of
+    /// those platforms would download, keyed by platform tag. Hashes alone are enough
+    /// for posy, but auditors want to see filenames and URLs they can check against
+    /// an approved list. Everything here follows from the blueprint, so it's
+    /// regenerated on every 'posy lock'.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub artifacts: BTreeMap<String, Vec<ArtifactDownload>>,
+    /// The build environment for each sdist that `blueprint` builds from source, keyed
+    /// by the sdist's hash, so rebuilding it later gets the same setuptools, cython,
+    /// etc. as whoever ran 'posy lock'.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub builds: BTreeMap<String, Blueprint>,
+}
+
+impl Lockfile {
+    pub fn new(brief: Brief, mut blueprint: Blueprint) -> Lockfile {
+        sort_pins(&mut blueprint);
+        Lockfile {
+            brief,
+            blueprint,
+            platforms: BlueprintSet::default(),
+            artifacts: BTreeMap::new(),
+            builds: BTreeMap::new(),
+        }
+    }
+
+    pub fn set_platforms(&mut self, mut platforms: BlueprintSet) {
+        for blueprint in platforms.blueprints.values_mut() {
+            sort_pins(blueprint);
+        }
+        self.platforms = platforms;
+    }
+
+    /// Switches every blueprint to the current ResolverPolicy, so re-resolving like
+    /// them uses it. Returns whether any of them weren't on it already.
+    pub fn refresh_policies(&mut self) -> bool {
+        let current = ResolverPolicy::current();
+        let mut changed = false;
+        for blueprint in self.blueprints_mut() {
+            if blueprint.policy != current {
+                blueprint.policy = current.clone();
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    // the main blueprint, and then any per-platform ones
+    fn blueprints_mut(&mut self) -> impl Iterator<Item = &mut Blueprint> {
+        std::iter::once(&mut self.blueprint)
+            .chain(self.platforms.blueprints.values_mut())
+    }
+
+    // Moves every local source tree the lockfile mentions, along with the provenance of
+    // any metadata that came from one. (Build environments come from the index, so
+    // they never have any.)
+    fn rebase_local_trees(&mut self, rebase: impl Fn(&Path) -> PathBuf) {
+        // the tree's URL, or for a relative path, just the path
+        fn provenance(tree: &LocalTree) -> String {
+            if tree.path.is_absolute() {
+                tree.url().to_string()
+            } else {
+                tree.path.display().to_string()
+            }
+        }
+        for req in &mut self.brief.local_requirements {
+            req.tree.path = rebase(&req.tree.path);
+        }
+        for blueprint in self.blueprints_mut() {
+            for (pin, metadata) in &mut blueprint.local {
+                let from_tree = metadata.provenance == provenance(&pin.tree);
+                pin.tree.path = rebase(&pin.tree.path);
+                if from_tree {
+                    metadata.provenance = provenance(&pin.tree);
+                }
+            }
+        }
+    }
+
+    /// Attaches `note` to `name`'s pin, or removes its note if `note` is None, in every
+    /// blueprint that pins it.
+    pub fn annotate(&mut self, name: &PackageName, note: Option<&str>) -> Result<()> {
+        let mut found = false;
+        for blueprint in self.blueprints_mut() {
+            if blueprint.pinned_version(name.normalized()).is_none() {
+                continue;
+            }
+            found = true;
+            let key = name.normalized().to_string();
+            match note {
+                Some(note) => blueprint.annotations.insert(key, note.into()),
+                None => blueprint.annotations.remove(&key),
+            };
+        }
+        if !found {
+            bail!("{LOCKFILE_NAME} doesn't have a pin for {}", name.as_given());
+        }
+        Ok(())
+    }
+
+    /// Blueprint::carry_annotations, for the main blueprint and each platform's.
+    /// Returns the notes that got dropped, by package.
+    pub fn carry_annotations(&mut self, old: &Lockfile) -> BTreeMap<String, String> {
+        let mut dropped: BTreeMap<_, _> = self
+            .blueprint
+            .carry_annotations(&old.blueprint)
+            .into_iter()
+            .collect();
+        for (tag, blueprint) in &mut self.platforms.blueprints {
+            if let Some(old) = old.platforms.blueprints.get(tag) {
+                dropped.extend(blueprint.carry_annotations(old));
+            }
+        }
+        dropped
+    }
+
+    /// Which platforms each package will be built from source on, keyed by normalized
+    /// name. `main_platform` is what to call the one `blueprint` was resolved for.
+    pub fn source_builds(&self, main_platform: &str) -> BTreeMap<String, Vec<String>> {
+        let mut builds = BTreeMap::<String, Vec<String>>::new();
+        let blueprints = std::iter::once((main_platform, &self.blueprint)).chain(
+            self.platforms
+                .blueprints
+                .iter()
+                .map(|(tag, blueprint)| (tag.as_str(), blueprint)),
+        );
+        for (tag, blueprint) in blueprints {
+            for name in &blueprint.source_builds {
+                builds
+                    .entry(name.clone())
+                    .or_default()
+                    .push(tag.to_string());
+            }
+        }
+        builds
+    }
+
+    /// The blueprint to install on a machine that can run `platforms`.
+    pub fn blueprint_for(&self, platforms: &[&PybiPlatform]) -> &Blueprint {
+        self.platforms
+            .for_platforms(platforms)
+            .unwrap_or(&self.blueprint)
+    }
+
+    /// Fills in `artifacts` for the given pybi platform tags.
+    pub fn list_artifacts(
+        &mut self,
+        db: &PackageDB,
+        platform_tags: &[String],
+    ) -> Result<()> {
+        self.artifacts.clear();
+        for tag in platform_tags {
+            context!("Listing files to install on {tag}");
+            let platform = PybiPlatform::new(tag);
+            let downloads = self
+                .blueprint_for(&[&platform])
+                .artifact_urls(db, &[&platform])?;
+            self.artifacts.insert(tag.clone(), downloads);
+        }
+        Ok(())
+    }
+
+    /// Fills in `builds` for the sdists that the main blueprint builds from source,
+    /// from the builds `db` did while resolving, or failing that, from `old` if it
+    /// pinned the same sdist.
+    // XX TODO: the per-platform blueprints' sdists aren't covered, since we never build
+    // them here.
+    pub fn record_builds(
+        &mut self,
+        db: &PackageDB,
+        old: Option<&Lockfile>,
+    ) -> Result<()> {
+        self.builds.clear();
+        for (pin, _) in &self.blueprint.wheels {
+            if !self.blueprint.source_builds.contains(pin.name.normalized()) {
+                continue;
+            }
+            let artifacts = match &pin.url {
+                Some(url) => std::slice::from_ref(db.direct_artifact(url)?),
+                None => db.artifacts_for_version(&pin.name, &pin.version)?,
+            };
+            for ai in artifacts.iter().filter(|ai| ai.is::<Sdist>()) {
+                let hash = match &ai.hash {
+                    Some(hash) if pin.hashes.contains(hash) => hash.to_string(),
+                    _ => continue,
+                };
+                let build = match db.build_blueprint(ai)? {
+                    Some(build) => Some(build),
+                    None => old.and_then(|old| old.builds.get(&hash)).cloned(),
+                };
+                match build {
+                    Some(mut build) => {
+                        sort_pins(&mut build);
+                        self.builds.insert(hash, build);
+                    }
+                    // e.g. we had its metadata cached, so we never had to build it
+                    None => warn!(
+                        "didn't build {} while locking, so its build environment isn't \
+                         pinned",
+                        ai.name
+                    ),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Errors out if anything in the lockfile is pinned without a hash (see
+    /// ProjectKind::requires_hashes).
+    pub fn check_hashes(&self) -> Result<()> {
+        let blueprints = std::iter::once(&self.blueprint)
+            .chain(self.platforms.blueprints.values())
+            .chain(self.builds.values());
+        let mut unhashed = Vec::new();
+        for blueprint in blueprints {
+            let pins = std::iter::once(&blueprint.pybi)
+                .chain(blueprint.wheels.iter().map(|(pin, _)| pin));
+            for pin in pins {
+                // direct URL references don't come from an index
+                if pin.url.is_none() && pin.hashes.is_empty() {
+                    let pin = format!("{} {}", pin.name.as_given(), pin.version);
+                    if !unhashed.contains(&pin) {
+                        unhashed.push(pin);
+                    }
+                }
+            }
+        }
+        if !unhashed.is_empty() {
+            bail!(
+                "the index didn't give hashes for {}, and apps need a hash for every \
+                 pin (set kind = \"library\" to allow this)",
+                unhashed.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Errors out if this lockfile wasn't made from `brief`, or if what it would
+    /// install on `platforms` was resolved with different custom platform tags than
+    /// the project has now (see ProjectConfig::platform_tags) -- since then the
+    /// resolve might have checked different wheels than we'd install.
+    pub fn check_fresh(
+        &self,
+        brief: &Brief,
+        platforms: &[&PybiPlatform],
+    ) -> Result<()> {
+        if &self.brief != brief {
+            bail!(
+                "{LOCKFILE_NAME} is out of date with the project's requirements; run \
+                 'posy lock' to update it"
+            );
+        }
+        let current = platforms
+            .iter()
+            .find_map(|platform| platform.custom_tags())
+            .unwrap_or_default();
+        if self.blueprint_for(platforms).platform_tags != current {
+            bail!(
+                "{LOCKFILE_NAME} was made with different custom platform tags than the \
+                 project has now; run 'posy lock' to update it"
+            );
+        }
+        Ok(())
+    }
+}
+
+// the resolver hands these back in arbitrary order, which would make for noisy diffs
+fn sort_pins(blueprint: &mut Blueprint) {
+    blueprint
+        .wheels
+        .sort_by(|(a, _), (b, _)| a.name.normalized().cmp(b.name.normalized()));
+    blueprint
+        .local
+        .sort_by(|(a, _), (b, _)| a.name.normalized().cmp(b.name.normalized()));
+}
+
+// `path` relative to `base`, with ".."s if it's outside it. Both have to be absolute.
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let mut path_parts = path.components().peekable();
+    let mut base_parts = base.components().peekable();
+    while let (Some(a), Some(b)) = (path_parts.peek(), base_parts.peek()) {
+        if a != b {
+            break;
+        }
+        path_parts.next();
+        base_parts.next();
+    }
+    // e.g. a different drive on Windows; nothing relative about that
+    if matches!(path_parts.peek(), Some(Component::Prefix(_))) {
+        return path.into();
+    }
+    let relative: PathBuf = base_parts
+        .map(|_| Component::ParentDir)
+        .chain(path_parts)
+        .collect();
+    // e.g. '-e .'
+    if relative.as_os_str().is_empty() {
+        return ".".into();
+    }
+    relative
+}
+
+// The other way around: `base` joined with `path`, resolving any ".."s (so the result is
+// the same canonical path that relative_path started with).
+fn join_lexically(base: &Path, path: &Path) -> PathBuf {
+    let mut joined = base.to_path_buf();
+    for part in path.components() {
+        match part {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                joined.pop();
+            }
+            // (an absolute path replaces everything so far, same as with push)
+            part => joined.push(part),
+        }
+    }
+    joined
+}
+
+impl Project {
+    fn from_posy_toml(root: &Path, s: &str) -> Result<Project> {
+        Ok(Project {
+            root: root.into(),
+            config: toml_edit::de::from_str(s)?,
+            dependency_groups: Default::default(),
+            poetry_notes: Vec::new(),
+        })
+    }
+
+    fn from_pyproject_toml(root: &Path, s: &str) -> Result<Project> {
+        let mut d = s.parse::<toml_edit::Document>()?;
+        let mut config: ProjectConfig = match d
+            .get_mut("tool")
+            .and_then(|tool| tool.as_table_like_mut())
+            .and_then(|tool| tool.remove("posy"))
+        {
+            Some(item) => toml_edit::de::from_item(item)?,
+            None => Default::default(),
+        };
+        let mut dependency_groups = match d.remove("dependency-groups") {
+            Some(item) => DependencyGroups::parse(item)?,
+            None => Default::default(),
+        };
+        // A Poetry project that hasn't been told about posy yet: use Poetry's
+        // requirements (and groups), unless we have our own.
+        let mut poetry_notes = Vec::new();
+        if let Some(poetry) = d.get("tool").and_then(|tool| tool.get("poetry")) {
+            let deps = poetry.get("dependencies").and_then(|d| d.as_table_like());
+            if let Some(deps) = deps.filter(|_| config.requirements.is_empty()) {
+                debug!("using requirements from [tool.poetry.dependencies]");
+                let (requirements, notes) = crate::poetry::requirements(deps)?;
+                config.requirements = requirements;
+                poetry_notes.extend(notes);
+            }
+            let groups = poetry.get("group").and_then(|g| g.as_table_like());
+            for (name, group) in groups.iter().flat_map(|groups| groups.iter()) {
+                let deps =
+                    match group.get("dependencies").and_then(|d| d.as_table_like()) {
+                        Some(deps) => deps,
+                        None => continue,
+                    };
+                let key = PackageName::try_from(name)?.normalized().to_owned();
+                if let Entry::Vacant(slot) = dependency_groups.0.entry(key) {
+                    let (requirements, notes) = crate::poetry::requirements(deps)?;
+                    poetry_notes.extend(notes);
+                    let items = requirements
+                        .iter()
+                        .map(|req| DependencyGroupItem::Requirement(req.to_string()))
+                        .collect();
+                    slot.insert(items);
+                }
+            }
+        }
+        Ok(Project {
+            root: root.into(),
+            config,
+            dependency_groups,
+            poetry_notes,
+        })
+    }
+
+    /// Parses `s` as the contents of `root`'s config file, whose name is `file_name`
+    /// (one of CONFIG_FILE_NAMES).
+    pub fn from_config(root: &Path, file_name: &str, s: &str) -> Result<Project> {
+        match file_name {
+            "posy.toml" => Project::from_posy_toml(root, s),
+            "pyproject.toml" => Project::from_pyproject_toml(root, s),
+            _ => bail!("don't know how to read project config from {file_name}"),
+        }
+    }
+
+    /// Looks for a posy.toml or pyproject.toml in `start` or any of its parents.
+    pub fn find(start: &Path) -> Result<Option<Project>> {
+        for dir in start.ancestors() {
+            if let Some(path) = config_path(dir) {
+                context!("Reading {}", path.display());
+                let s = fs::read_to_string(&path)?;
+                // unwrap is safe because config_path only returns CONFIG_FILE_NAMES
+                let file_name = path.file_name().unwrap().to_str().unwrap();
+                return Ok(Some(Project::from_config(dir, file_name, &s)?));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn lockfile_path(&self) -> PathBuf {
+        self.root.join(LOCKFILE_NAME)
+    }
+
+    // LocalTree paths are canonical, so compare them against a canonical root
+    fn canonical_root(&self) -> PathBuf {
+        fs::canonicalize(&self.root).unwrap_or_else(|_| self.root.clone())
+    }
+
+    pub fn read_lockfile(&self) -> Result<Option<Lockfile>> {
+        let path = self.lockfile_path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        context!("Reading {}", path.display());
+        let mut lockfile: Lockfile = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        let root = self.canonical_root();
+        lockfile.rebase_local_trees(|path| join_lexically(&root, path));
+        Ok(Some(lockfile))
+    }
+
+    pub fn write_lockfile(&self, lockfile: &Lockfile) -> Result<()> {
+        let path = self.lockfile_path();
+        context!("Writing {}", path.display());
+        // local trees are stored relative to the project, so the lockfile still works
+        // in someone else's checkout
+        let mut lockfile = lockfile.clone();
+        let root = self.canonical_root();
+        lockfile.rebase_local_trees(|path| relative_path(path, &root));
+        // going through Value sorts all the object keys, so the output is stable
+        let mut s = serde_json::to_string_pretty(&serde_json::to_value(&lockfile)?)?;
+        s.push('\n');
+        // write to a tempfile + rename, so we never leave a half-written lockfile
+        let mut out = tempfile::NamedTempFile::new_in(&self.root)?;
+        out.write_all(s.as_bytes())?;
+        out.persist(&path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resolve::{
+        LocalPin, PinnedPackage, WheelResolveMetadata, WheelResolveMetadataInner,
+    };
+    use indoc::indoc;
+
+    #[test]
+    fn test_parse_project_config() {
+        let root = Path::new("/project");
+        let parse_pyproject =
+            |s| Project::from_pyproject_toml(root, s).map(|p| p.config);
+        let parse_posy = |s| Project::from_posy_toml(root, s).map(|p| p.config);
+
+        let config = parse_pyproject(indoc! {r#"
+            [project]
+            name = "whatever"
+
+            [tool.posy]
+            kind = "library"
+            python = "cpython >= 3.10"
+            requirements = ["trio", "attrs >= 22"]
+        "#})
+        .unwrap();
+        assert_eq!(config.kind, ProjectKind::Library);
+        assert_eq!(config.python.unwrap().to_string(), "cpython >= 3.10");
+        assert!(config.python_fallbacks.is_empty());
+        assert_eq!(
+            config
+                .requirements
+                .iter()
+                .map(|r| r.to_string())
+                .collect::<Vec<_>>(),
+            vec!["trio", "attrs >= 22"]
+        );
+
+        let config = parse_pyproject(indoc! {r#"
+            [tool.black]
+            line-length = 88
+        "#})
+        .unwrap();
+        assert_eq!(config.kind, ProjectKind::App);
+        assert!(config.python.is_none());
+        assert!(config.requirements.is_empty());
+
+        let config = parse_posy(indoc! {r#"
+            python = "cpython >= 3.12"
+            python-fallbacks = ["cpython >= 3.11", "cpython >= 3.10"]
+        "#})
+        .unwrap();
+        assert_eq!(
+            config
+                .python_fallbacks
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>(),
+            vec!["cpython >= 3.11", "cpython >= 3.10"]
+        );
+        assert!(parse_posy("python-fallbacks = 'cpython >= 3.11'").is_err());
+
+        let config = parse_posy("kind = 'app'").unwrap();
+        assert_eq!(config.kind, ProjectKind::App);
+        assert_eq!(config.index_strategy, IndexStrategy::Merge);
+        assert!(config.index_pins.is_empty());
+        assert_eq!(config.resolution, ResolutionStrategy::Highest);
+        let config = parse_posy("resolution = 'lowest'").unwrap();
+        assert_eq!(config.resolution, ResolutionStrategy::Lowest);
+        assert_eq!(config.link_mode, LinkMode::Copy);
+        let config = parse_posy("link-mode = 'reflink'").unwrap();
+        assert_eq!(config.link_mode, LinkMode::Reflink);
+        assert!(!config.share_wheels);
+        let config = parse_posy("share-wheels = true").unwrap();
+        assert!(config.share_wheels);
+        assert_eq!(config.yanked, YankedPolicy::AllowPinned);
+        let config = parse_posy("yanked = 'forbid'").unwrap();
+        assert_eq!(config.yanked, YankedPolicy::Forbid);
+        assert_eq!(config.attestations, AttestationPolicy::Ignore);
+        let config = parse_posy("attestations = 'require'").unwrap();
+        assert_eq!(config.attestations, AttestationPolicy::Require);
+        assert_eq!(config.size_budget, None);
+        assert_eq!(config.size_budget_policy, SizeBudgetPolicy::Warn);
+        let config = parse_posy(indoc! {r#"
+            size-budget = "250M"
+            size-budget-policy = "error"
+        "#})
+        .unwrap();
+        assert_eq!(config.size_budget, Some(250 * 1024 * 1024));
+        assert_eq!(config.size_budget_policy, SizeBudgetPolicy::Error);
+        assert!(parse_posy("size-budget = 'huge'").is_err());
+
+        let config = parse_posy(indoc! {r#"
+            index-strategy = "first-match"
+
+            [index-pins]
+            Internal_Utils = "https://pypi.example.com/simple/"
+        "#})
+        .unwrap();
+        assert_eq!(config.index_strategy, IndexStrategy::FirstMatch);
+        assert_eq!(
+            config.index_pins[&"internal-utils".parse().unwrap()].as_str(),
+            "https://pypi.example.com/simple/"
+        );
+        assert!(parse_posy("index-strategy = 'random'").is_err());
+        assert!(config.no_metadata_files.is_empty());
+        let config =
+            parse_posy("no-metadata-files = ['https://mirror.example.com/simple/']")
+                .unwrap();
+        assert_eq!(
+            config.no_metadata_files[0].as_str(),
+            "https://mirror.example.com/simple/"
+        );
+        assert!(parse_posy("[index-pins]\nfoo = 'not a url'").is_err());
+
+        let config = parse_posy(indoc! {r#"
+            [prefer-local-versions]
+            NumPy = "https://corp.example.com/simple/"
+        "#})
+        .unwrap();
+        assert_eq!(
+            config.prefer_local_versions[&"numpy".parse().unwrap()].as_str(),
+            "https://corp.example.com/simple/"
+        );
+
+        let config = parse_posy(indoc! {r#"
+            [platform-tags]
+            manylinux_2_28_aarch64 = ["mycluster_aarch64", "*", "linux_aarch64"]
+        "#})
+        .unwrap();
+        assert_eq!(
+            config.platform_tags["manylinux_2_28_aarch64"],
+            vec!["mycluster_aarch64", "*", "linux_aarch64"]
+        );
+
+        assert!(parse_posy("kind = 'framework'").is_err());
+        assert!(parse_posy("knid = 'app'").is_err());
+    }
+
+    #[test]
+    fn test_dependency_groups() {
+        let project = Project::from_pyproject_toml(
+            Path::new("/project"),
+            indoc! {r#"
+                [dependency-groups]
+                Test = ["pytest >= 7", "coverage"]
+                typing = ["mypy"]
+                all = [{include-group = "test"}, {include-group = "TYPING"}, "tox"]
+                loop-a = [{include-group = "loop-b"}]
+                loop-b = [{include-group = "loop-a"}]
+                bad = ["not a requirement !!"]
+            "#},
+        )
+        .unwrap();
+        let groups = &project.dependency_groups;
+        let as_strings = |group| {
+            groups
+                .requirements(group)
+                .unwrap()
+                .iter()
+                .map(|r| r.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(as_strings("test"), vec!["pytest >= 7", "coverage"]);
+        assert_eq!(
+            as_strings("all"),
+            vec!["pytest >= 7", "coverage", "mypy", "tox"]
+        );
+        assert!(groups.requirements("loop-a").is_err());
+        assert!(groups.requirements("bad").is_err());
+        assert!(groups.requirements("missing").is_err());
+
+        // two spellings of the same name
+        assert!(Project::from_pyproject_toml(
+            Path::new("/project"),
+            "[dependency-groups]\nfoo-bar = []\nfoo_bar = []\n",
+        )
+        .is_err());
+        // unknown table keys
+        assert!(Project::from_pyproject_toml(
+            Path::new("/project"),
+            "[dependency-groups]\nfoo = [{include = 'bar'}]\n",
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_poetry_project() {
+        let poetry = indoc! {r#"
+            [tool.poetry.dependencies]
+            python = "^3.9"
+            requests = "^2.28"
+
+            [tool.poetry.group.Test.dependencies]
+            pytest = "~7.4"
+
+            [tool.poetry.group.docs.dependencies]
+            sphinx = "*"
+        "#};
+        let project =
+            Project::from_pyproject_toml(Path::new("/project"), poetry).unwrap();
+        let as_strings = |reqs: &[UserRequirement]| {
+            reqs.iter().map(|r| r.to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            as_strings(&project.config.requirements),
+            vec!["requests >= 2.28, < 3"]
+        );
+        let groups = &project.dependency_groups;
+        assert_eq!(
+            as_strings(&groups.requirements("test").unwrap()),
+            vec!["pytest >= 7.4, < 7.5"]
+        );
+        // the caret and the tilde, but not the python entry
+        assert_eq!(project.poetry_notes.len(), 2);
+
+        // our own settings win
+        let project = Project::from_pyproject_toml(
+            Path::new("/project"),
+            &format!(
+                "{poetry}\n[tool.posy]\nrequirements = ['requests']\n\n\
+                 [dependency-groups]\ndocs = ['mkdocs']\n"
+            ),
+        )
+        .unwrap();
+        assert_eq!(as_strings(&project.config.requirements), vec!["requests"]);
+        let groups = &project.dependency_groups;
+        assert_eq!(
+            as_strings(&groups.requirements("docs").unwrap()),
+            vec!["mkdocs"]
+        );
+        assert_eq!(
+            as_strings(&groups.requirements("test").unwrap()),
+            vec!["pytest >= 7.4, < 7.5"]
+        );
+    }
+
+    #[test]
+    fn test_lockfile() {
+        let tmp = tempfile::tempdir().unwrap();
+        let project = Project::from_posy_toml(tmp.path(), "").unwrap();
+        assert!(project.read_lockfile().unwrap().is_none());
+
+        let brief = Brief {
+            python: "cpython >= 3.10".parse().unwrap(),
+            requirements: vec!["trio".parse().unwrap()],
+            allow_pre: Default::default(),
+            strategy: Default::default(),
+            keep_pinned_prereleases: true,
+            constraints: vec!["trio < 1".parse().unwrap()],
+            local_requirements: vec![],
+            no_source_builds: false,
+            python_flags: Vec::new(),
+            python_fallbacks: vec![],
+        };
+        let blueprint = Blueprint {
+            pybi: PinnedPackage {
+                name: "cpython".parse().unwrap(),
+                version: "3.10.8".try_into().unwrap(),
+                hashes: vec![],
+                url: None,
+            },
+            wheels: vec![],
+            local: vec![],
+            marker_expressions: Default::default(),
+            python_fallback: None,
+            annotations: Default::default(),
+            claimed_publishers: Default::default(),
+            platform_tags: Default::default(),
+            source_builds: Default::default(),
+            warnings: Vec::new(),
+            policy: Default::default(),
+            python_flags: Vec::new(),
+        };
+        project
+            .write_lockfile(&Lockfile::new(brief.clone(), blueprint.clone()))
+            .unwrap();
+        // no audit-platforms, no artifacts section
+        let written = fs::read_to_string(project.lockfile_path()).unwrap();
+        assert!(!written.contains("artifacts"));
+        assert!(!written.contains("builds"));
+
+        let mut with_artifacts = Lockfile::new(brief.clone(), blueprint.clone());
+        let download = ArtifactDownload {
+            name: "cpython".parse().unwrap(),
+            version: "3.10.8".try_into().unwrap(),
+            filename: "cpython-3.10.8-manylinux_2_17_x86_64.pybi".into(),
+            url: Url::parse("https://example.com/cpython-3.10.8-manylinux_2_17_x86_64.pybi")
+                .unwrap(),
+            hash: "sha256=0000000000000000000000000000000000000000000000000000000000000000"
+                .try_into()
+                .unwrap(),
+            size: Some(1234),
+        };
+        with_artifacts
+            .artifacts
+            .insert("manylinux_2_17_x86_64".into(), vec![download.clone()]);
+        let sdist_hash =
+            "sha256=1111111111111111111111111111111111111111111111111111111111111111";
+        with_artifacts
+            .builds
+            .insert(sdist_hash.into(), blueprint.clone());
+        project.write_lockfile(&with_artifacts).unwrap();
+        let lockfile = project.read_lockfile().unwrap().unwrap();
+        assert_eq!(lockfile.artifacts["manylinux_2_17_x86_64"], vec![download]);
+        assert_eq!(lockfile.builds[sdist_hash].pybi, blueprint.pybi);
+        assert_eq!(lockfile.brief, brief);
+        let version: Version = "3.10.8".try_into().unwrap();
+        assert_eq!(lockfile.blueprint.pybi.version, version);
+        let platform = PybiPlatform::new("manylinux_2_17_x86_64");
+        lockfile.check_fresh(&brief, &[&platform]).unwrap();
+
+        let mut changed = brief.clone();
+        changed.requirements.push("attrs".parse().unwrap());
+        assert!(lockfile.check_fresh(&changed, &[&platform]).is_err());
+
+        // locked with custom tags that we don't have anymore
+        let mut custom = lockfile.clone();
+        custom.blueprint.platform_tags = vec!["vendor_linux_x86_64".into()];
+        assert!(custom.check_fresh(&brief, &[&platform]).is_err());
+
+        // the pybi has no hashes, so an app couldn't have locked this
+        let err = lockfile.check_hashes().unwrap_err();
+        assert!(err.to_string().contains("cpython"));
+        let mut hashed = lockfile;
+        hashed.blueprint.pybi.hashes = vec![sdist_hash.try_into().unwrap()];
+        for build in hashed.builds.values_mut() {
+            build.pybi.hashes = hashed.blueprint.pybi.hashes.clone();
+        }
+        hashed.check_hashes().unwrap();
+    }
+
+    #[test]
+    fn test_lockfile_platforms() {
+        let tmp = tempfile::tempdir().unwrap();
+        let project = Project::from_posy_toml(tmp.path(), "").unwrap();
+        let brief = Brief {
+            python: "cpython >= 3.10".parse().unwrap(),
+            requirements: vec![],
+            allow_pre: Default::default(),
+            strategy: Default::default(),
+            keep_pinned_prereleases: true,
+            constraints: vec![],
+            local_requirements: vec![],
+            no_source_builds: false,
+            python_flags: Vec::new(),
+            python_fallbacks: vec![],
+        };
+        let blueprint = |version: &str| Blueprint {
+            pybi: PinnedPackage {
+                name: "cpython".parse().unwrap(),
+                version: version.try_into().unwrap(),
+                hashes: vec![],
+                url: None,
+            },
+            wheels: vec![],
+            local: vec![],
+            marker_expressions: Default::default(),
+            python_fallback: None,
+            annotations: Default::default(),
+            claimed_publishers: Default::default(),
+            platform_tags: Default::default(),
+            source_builds: Default::default(),
+            warnings: Vec::new(),
+            policy: Default::default(),
+            python_flags: Vec::new(),
+        };
+        let mut main = blueprint("3.10.8");
+        main.source_builds.insert("psycopg2".into());
+        let mut lockfile = Lockfile::new(brief, main);
+        let mut set = BlueprintSet::default();
+        let mut macos = blueprint("3.10.9");
+        macos.source_builds.insert("psycopg2".into());
+        macos.source_builds.insert("pyobjc-core".into());
+        set.blueprints.insert("macosx_11_0_arm64".into(), macos);
+        set.blueprints
+            .insert("manylinux_2_17_x86_64".into(), blueprint("3.10.10"));
+        lockfile.set_platforms(set);
+        project.write_lockfile(&lockfile).unwrap();
+        let lockfile = project.read_lockfile().unwrap().unwrap();
+
+        let pybi_version = |tag: &str| {
+            let platform = PybiPlatform::new(tag);
+            lockfile
+                .blueprint_for(&[&platform])
+                .pybi
+                .version
+                .to_string()
+        };
+        assert_eq!(pybi_version("macosx_12_0_arm64"), "3.10.9");
+        assert_eq!(pybi_version("manylinux_2_35_x86_64"), "3.10.10");
+        // not covered, so falls back on the main blueprint
+        assert_eq!(pybi_version("win_amd64"), "3.10.8");
+
+        let builds = lockfile.source_builds("win_amd64");
+        assert_eq!(
+            builds.into_iter().collect::<Vec<_>>(),
+            [
+                (
+                    "psycopg2".to_string(),
+                    vec!["win_amd64".to_string(), "macosx_11_0_arm64".to_string()]
+                ),
+                (
+                    "pyobjc-core".to_string(),
+                    vec!["macosx_11_0_arm64".to_string()]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lockfile_local_paths() {
+        let tmp = tempfile::tempdir().unwrap();
+        let checkout = tmp.path().join("checkout");
+        let root = checkout.join("project");
+        for (dir, name) in
+            [(root.join("mypkg"), "mypkg"), (checkout.join("lib"), "lib")]
+        {
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(
+                dir.join("pyproject.toml"),
+                format!("[project]\nname = \"{name}\"\n"),
+            )
+            .unwrap();
+        }
+        let brief_in = |root: &Path| Brief {
+            python: "cpython >= 3.10".parse().unwrap(),
+            requirements: vec![],
+            allow_pre: Default::default(),
+            strategy: Default::default(),
+            keep_pinned_prereleases: true,
+            constraints: vec![],
+            local_requirements: ["-e ./mypkg", "-e ../lib"]
+                .iter()
+                .map(|r| LocalRequirement::parse(r, root).unwrap().unwrap())
+                .collect(),
+            no_source_builds: false,
+            python_flags: Vec::new(),
+            python_fallbacks: vec![],
+        };
+        let brief = brief_in(&root);
+        let local = brief
+            .local_requirements
+            .iter()
+            .map(|req| {
+                let pin = LocalPin {
+                    name: req.name.clone(),
+                    version: "1.0".try_into().unwrap(),
+                    tree: req.tree.clone(),
+                };
+                let metadata = WheelResolveMetadata {
+                    provenance: req.tree.url().to_string(),
+                    inner: WheelResolveMetadataInner {
+                        requires_dist: vec![],
+                        requires_python: Default::default(),
+                        extras: Default::default(),
+                    },
+                };
+                (pin, metadata)
+            })
+            .collect();
+        let blueprint = Blueprint {
+            pybi: PinnedPackage {
+                name: "cpython".parse().unwrap(),
+                version: "3.10.8".try_into().unwrap(),
+                hashes: vec![],
+                url: None,
+            },
+            wheels: vec![],
+            local,
+            marker_expressions: Default::default(),
+            python_fallback: None,
+            annotations: Default::default(),
+            claimed_publishers: Default::default(),
+            platform_tags: Default::default(),
+            source_builds: Default::default(),
+            warnings: Vec::new(),
+            policy: Default::default(),
+            python_flags: Vec::new(),
+        };
+        let project = Project::from_posy_toml(&root, "").unwrap();
+        project
+            .write_lockfile(&Lockfile::new(brief, blueprint))
+            .unwrap();
+        let written = fs::read_to_string(project.lockfile_path()).unwrap();
+        let tmp_path = fs::canonicalize(tmp.path()).unwrap();
+        assert!(!written.contains(tmp_path.to_str().unwrap()), "{written}");
+        assert!(written.contains(r#""../lib""#), "{written}");
+
+        // someone else's checkout, somewhere else
+        let moved = tmp.path().join("elsewhere");
+        fs::rename(&checkout, &moved).unwrap();
+        let project = Project::from_posy_toml(&moved.join("project"), "").unwrap();
+        let lockfile = project.read_lockfile().unwrap().unwrap();
+        let brief = brief_in(&project.root);
+        let platform = PybiPlatform::new("manylinux_2_17_x86_64");
+        lockfile.check_fresh(&brief, &[&platform]).unwrap();
+        assert_eq!(lockfile.blueprint.local.len(), 2);
+        for (pin, metadata) in &lockfile.blueprint.local {
+            let req = brief
+                .local_requirements
+                .iter()
+                .find(|req| req.name == pin.name)
+                .unwrap();
+            assert_eq!(pin.tree, req.tree);
+            assert_eq!(metadata.provenance, req.tree.url().to_string());
+        }
+    }
+
+    #[test]
+    fn test_lockfile_annotations() {
+        let tmp = tempfile::tempdir().unwrap();
+        let project = Project::from_posy_toml(tmp.path(), "").unwrap();
+        let brief = Brief {
+            python: "cpython >= 3.10".parse().unwrap(),
+            requirements: vec![],
+            allow_pre: Default::default(),
+            strategy: Default::default(),
+            keep_pinned_prereleases: true,
+            constraints: vec![],
+            local_requirements: vec![],
+            no_source_builds: false,
+            python_flags: Vec::new(),
+            python_fallbacks: vec![],
+        };
+        let pin = |name: &str, version: &str| PinnedPackage {
+            name: name.parse().unwrap(),
+            version: version.try_into().unwrap(),
+            hashes: vec![],
+            url: None,
+        };
+        let metadata = WheelResolveMetadata {
+            provenance: "test".into(),
+            inner: WheelResolveMetadataInner {
+                requires_dist: vec![],
+                requires_python: Default::default(),
+                extras: Default::default(),
+            },
+        };
+        let blueprint = |trio: &str, attrs: &str| Blueprint {
+            pybi: pin("cpython", "3.10.8"),
+            wheels: vec![
+                (pin("trio", trio), metadata.clone()),
+                (pin("attrs", attrs), metadata.clone()),
+            ],
+            local: vec![],
+            marker_expressions: Default::default(),
+            python_fallback: None,
+            annotations: Default::default(),
+            claimed_publishers: Default::default(),
+            platform_tags: Default::default(),
+            source_builds: Default::default(),
+            warnings: Vec::new(),
+            policy: Default::default(),
+            python_flags: Vec::new(),
+        };
+
+        let mut lockfile = Lockfile::new(brief.clone(), blueprint("0.21.0", "22.2.0"));
+        lockfile
+            .annotate(
+                &"Trio".parse().unwrap(),
+                Some("0.22 breaks our CI, see #123"),
+            )
+            .unwrap();
+        lockfile
+            .annotate(&"attrs".parse().unwrap(), Some("waiting on a fix"))
+            .unwrap();
+        lockfile
+            .annotate(&"cpython".parse().unwrap(), Some("temporary"))
+            .unwrap();
+        lockfile
+            .annotate(&"cpython".parse().unwrap(), None)
+            .unwrap();
+        assert!(lockfile
+            .annotate(&"numpy".parse().unwrap(), Some("nope"))
+            .is_err());
+        project.write_lockfile(&lockfile).unwrap();
+        let old = project.read_lockfile().unwrap().unwrap();
+        assert_eq!(
+            old.blueprint.annotations.keys().collect::<Vec<_>>(),
+            vec!["attrs", "trio"]
+        );
+
+        // re-lock: trio stays put and keeps its note, attrs moves and loses it
+        let mut new = Lockfile::new(brief, blueprint("0.21.0", "23.1.0"));
+        let dropped = new.carry_annotations(&old);
+        assert_eq!(
+            new.blueprint.annotations.get("trio").map(|s| s.as_str()),
+            Some("0.22 breaks our CI, see #123")
+        );
+        assert!(!new.blueprint.annotations.contains_key("attrs"));
+        assert_eq!(
+            dropped.into_iter().collect::<Vec<_>>(),
+            vec![("attrs".to_string(), "waiting on a fix".to_string())]
+        );
+    }
+}
@@ -1,16 +1,19 @@
+use crate::dep_report::{DependencyReport, ExploreSource, ReleaseInfo};
+use crate::env::pick_pinned_binary;
 use crate::package_db::WheelBuilder;
 use crate::prelude::*;
 use elsa::FrozenMap;
 use pubgrub::range::Range;
 use pubgrub::report::DerivationTree;
-use pubgrub::report::Reporter;
 use pubgrub::solver::{Dependencies, DependencyConstraints};
 use std::borrow::Borrow;
 use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 
-use crate::package_db::{ArtifactInfo, PackageDB};
+use crate::package_db::{ArtifactInfo, AttestationPolicy, PackageDB};
+use crate::resolve_report::{self, ExcludedVersion};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(try_from = "AllowPreSerdeHelper", into = "AllowPreSerdeHelper")]
 pub enum AllowPre {
     Some(HashSet<PackageName>),
@@ -73,19 +76,118 @@ fn allow_pre_is_empty(value: &AllowPre) -> bool {
     }
 }
 
+/// Which versions the resolver goes for, when more than one would work.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResolutionStrategy {
+    /// The newest versions that work.
+    #[default]
+    Highest,
+    /// The oldest versions that work, e.g. to check that a library's lower bounds
+    /// are really enough.
+    Lowest,
+}
+
+impl ResolutionStrategy {
+    fn is_highest(&self) -> bool {
+        *self == ResolutionStrategy::Highest
+    }
+}
+
+// Bump this whenever ResolverPolicy::current() changes.
+pub const RESOLVER_POLICY_REVISION: u32 = 1;
+
+/// The resolver heuristics a blueprint was made with. When we tweak them, old
+/// lockfiles should still re-resolve the same way they did before, so every blueprint
+/// records what it used, and re-resolving "like" a blueprint keeps its policy. Moving
+/// a lockfile to the current defaults is an explicit 'posy lock --refresh-policies'.
+///
+/// The Default is what blueprints got before we started recording this, i.e. what a
+/// blueprint with no policy at all means.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResolverPolicy {
+    pub revision: u32,
+    pub hint_order: HintOrder,
+    /// For packages that have nothing but pre-releases, allow those even without
+    /// allow_pre.
+    pub prerelease_fallback: bool,
+}
+
+/// When there's a version hint (from an older blueprint) but we can't use the hinted
+/// version, which ones to try next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HintOrder {
+    /// Whatever the brief's strategy says, same as if there were no hint.
+    #[default]
+    Strategy,
+    /// The ones closest to the hint: newer ones from oldest to newest, then older
+    /// ones from newest to oldest. So if we had 1.1, we try 1.2 before 1.3, and 1.0
+    /// before 0.9.
+    Nearest,
+}
+
+impl Default for ResolverPolicy {
+    fn default() -> ResolverPolicy {
+        ResolverPolicy {
+            revision: 0,
+            hint_order: HintOrder::Strategy,
+            prerelease_fallback: true,
+        }
+    }
+}
+
+impl ResolverPolicy {
+    /// What new resolves get.
+    pub fn current() -> ResolverPolicy {
+        ResolverPolicy {
+            revision: RESOLVER_POLICY_REVISION,
+            ..Default::default()
+        }
+    }
+}
+
 /// A high-level description of an environment that a user would like to be able to
 /// build. Doesn't necessarily have to be what the user types in exactly, but has to
 /// represent their intentions, and you have to be able to build the whole structure
 /// without looking at a package index.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Brief {
     pub python: PythonRequirement,
+    // Other pythons to try, in order, if we can't resolve with `python` -- e.g. a
+    // brand-new CPython that some key package doesn't have wheels for yet.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub python_fallbacks: Vec<PythonRequirement>,
     // don't need python_constraints because we always install exactly one python
     pub requirements: Vec<UserRequirement>,
-    #[serde(skip_serializing_if = "allow_pre_is_empty")]
+    #[serde(default, skip_serializing_if = "allow_pre_is_empty")]
     pub allow_pre: AllowPre,
-    // XX TODO
-    //pub constraints: Vec<UserRequirement>,
+    // Only applies to packages; we always pick the best Python we can.
+    #[serde(default, skip_serializing_if = "ResolutionStrategy::is_highest")]
+    pub strategy: ResolutionStrategy,
+    // When re-resolving with hints from an older blueprint, a pre-release that the old
+    // blueprint pinned stays allowed, even without allow_pre. See ProjectKind.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub keep_pinned_prereleases: bool,
+    // Like pip's -c constraints.txt: these narrow down which versions we'll accept for
+    // a package, but never cause it to be installed if nothing else depends on it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub constraints: Vec<UserRequirement>,
+    // Source trees on the local filesystem, e.g. from '-e ./mypkg'. These always win
+    // over anything on the index with the same name.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub local_requirements: Vec<LocalRequirement>,
+    // Fail the resolve instead of pinning anything that would have to be built from
+    // an sdist on the platform we're resolving for (see Blueprint::source_builds).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_source_builds: bool,
+    // Interpreter flags for the env's scripts, e.g. ["-I"]. They don't change what we
+    // resolve; they just get passed along to the Blueprint.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub python_flags: Vec<String>,
 }
 
 #[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
@@ -93,6 +195,34 @@ pub struct PinnedPackage {
     pub name: PackageName,
     pub version: Version,
     pub hashes: Vec<ArtifactHash>,
+    // set if this came from a direct 'NAME @ URL' reference instead of an index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<Url>,
+}
+
+/// A package that gets built from a local source tree. There's no hash to pin, just the
+/// version the tree had when we resolved.
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LocalPin {
+    pub name: PackageName,
+    pub version: Version,
+    #[serde(flatten)]
+    pub tree: LocalTree,
+}
+
+impl PinnedPackage {
+    /// The sdist in `artifacts` that this pin locked, if there is one. An sdist with a
+    /// hash that isn't in the pin isn't what got locked, even if it has the right name
+    /// and version (e.g. it was re-uploaded to a different index).
+    pub fn pinned_sdist<'a>(
+        &self,
+        artifacts: &'a [ArtifactInfo],
+    ) -> Option<&'a ArtifactInfo> {
+        artifacts.iter().find(|ai| {
+            ai.is::<Sdist>()
+                && matches!(&ai.hash, Some(hash) if self.hashes.contains(hash))
+        })
+    }
 }
 
 impl Display for PinnedPackage {
@@ -107,28 +237,50 @@ impl Display for PinnedPackage {
     }
 }
 
-struct VersionHints<'a>(
-    HashMap<&'a PackageName, (&'a Version, HashSet<&'a ArtifactHash>)>,
-);
+struct VersionHints<'a> {
+    pins: HashMap<&'a PackageName, (&'a Version, HashSet<&'a ArtifactHash>)>,
+    // comes along with the pins, so re-resolving like an old blueprint also
+    // re-resolves the way it did
+    policy: ResolverPolicy,
+}
 
 impl<'a> VersionHints<'a> {
     fn new() -> VersionHints<'a> {
-        VersionHints(HashMap::new())
+        VersionHints::with_policy(ResolverPolicy::current())
+    }
+
+    fn with_policy(policy: ResolverPolicy) -> VersionHints<'a> {
+        VersionHints {
+            pins: HashMap::new(),
+            policy,
+        }
     }
 
     fn add_pinned(&mut self, pin: &'a PinnedPackage) {
-        self.0
+        self.pins
             .insert(&pin.name, (&pin.version, pin.hashes.iter().collect()));
     }
 
     fn from(blueprint: &'a Blueprint) -> VersionHints<'a> {
-        let mut hints = VersionHints::new();
+        if blueprint.policy.revision > RESOLVER_POLICY_REVISION {
+            warn!(
+                "this lock was made by a newer posy, with resolver policy revision {} \
+                 (we only know up to {RESOLVER_POLICY_REVISION}), so re-resolving it \
+                 might not match",
+                blueprint.policy.revision
+            );
+        }
+        let mut hints = VersionHints::with_policy(blueprint.policy.clone());
         hints.add_pinned(&blueprint.pybi);
         for (wheel, _) in &blueprint.wheels {
             hints.add_pinned(wheel);
         }
         hints
     }
+
+    fn forget(&mut self, names: &[PackageName]) {
+        self.pins.retain(|name, _| !names.contains(name));
+    }
 }
 
 /// This is the subset of WheelCoreMetadata that the resolver actually uses.
@@ -163,7 +315,17 @@ pub struct WheelResolveMetadataInner {
 
 impl WheelResolveMetadata {
     pub fn from(ai: &ArtifactInfo, m: &WheelCoreMetadata) -> WheelResolveMetadata {
-        let provenance = ai.url.to_string();
+        WheelResolveMetadata::with_provenance(ai.url.to_string(), m)
+    }
+
+    pub fn from_local(tree: &LocalTree, m: &WheelCoreMetadata) -> WheelResolveMetadata {
+        WheelResolveMetadata::with_provenance(tree.url().to_string(), m)
+    }
+
+    fn with_provenance(
+        provenance: String,
+        m: &WheelCoreMetadata,
+    ) -> WheelResolveMetadata {
         let inner = WheelResolveMetadataInner {
             requires_dist: m.requires_dist.clone(),
             requires_python: m.requires_python.clone(),
@@ -178,8 +340,51 @@ impl WheelResolveMetadata {
 pub struct Blueprint {
     pub pybi: PinnedPackage,
     pub wheels: Vec<(PinnedPackage, WheelResolveMetadata)>,
+    // Kept separate from `wheels`, so code that only knows how to deal with pinned
+    // artifacts from an index can skip them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub local: Vec<(LocalPin, WheelResolveMetadata)>,
     #[serde(serialize_with = "serialize_marker_exprs")]
     pub marker_expressions: HashMap<StandaloneMarkerExpr, bool>,
+    // Which of the Brief's python_fallbacks we ended up using, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub python_fallback: Option<PythonRequirement>,
+    /// Notes people have attached to pins with 'posy annotate' -- "capped because of
+    /// incident #123", that kind of thing -- keyed by normalized package name. A note
+    /// sticks around for as long as the package stays at the same version (see
+    /// `carry_annotations`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    /// Who published each file we'd install, according to its PEP 740 attestations,
+    /// keyed by hash. Only filled in if we're reading them; see
+    /// PackageDB::attestation_policy. These are claims, not proof: we don't verify the
+    /// attestations' signatures.
+    #[serde(
+        default,
+        alias = "attestations",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub claimed_publishers: BTreeMap<String, String>,
+    /// The custom platform tag priorities we resolved with, if the project has any
+    /// for this platform (see platform_tags::set_custom_tag_priorities).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub platform_tags: Vec<String>,
+    /// Pinned packages (normalized names) that have no wheel for the platform we
+    /// resolved for, so installing there means building them from their sdist. We
+    /// figure this out at lock time so it doesn't come as a surprise on some deploy
+    /// box that has no compiler.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub source_builds: BTreeSet<String>,
+    #[serde(default)]
+    pub policy: ResolverPolicy,
+    /// The Brief's python_flags, so whoever installs this later gets the same ones
+    /// (see Env::python_flags).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub python_flags: Vec<String>,
+    /// Whatever we warned about while resolving this (see output::WarningClass).
+    /// Only for whoever asked for the resolve; it doesn't go in the lock file.
+    #[serde(skip)]
+    pub warnings: Vec<crate::output::Warning>,
 }
 
 fn serialize_marker_exprs<S>(
@@ -200,13 +405,307 @@ where
 impl Display for Blueprint {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "pybi: {}", self.pybi)?;
+        if let Some(python) = &self.python_fallback {
+            writeln!(f, "(fell back to python requirement {python})")?;
+        }
         for (wheel, em) in &self.wheels {
             writeln!(f, "wheel: {} (metadata from {})", wheel, em.provenance)?;
         }
+        for (pin, _) in &self.local {
+            writeln!(
+                f,
+                "local: {} {} ({}{})",
+                pin.name.as_given(),
+                pin.version,
+                if pin.tree.editable { "editable, " } else { "" },
+                pin.tree.path.display()
+            )?;
+        }
+        for name in &self.source_builds {
+            writeln!(f, "built from source: {name}")?;
+        }
+        for (name, note) in &self.annotations {
+            writeln!(f, "note on {name}: {note}")?;
+        }
         Ok(())
     }
 }
 
+/// A file that installing a Blueprint would download.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ArtifactDownload {
+    pub name: PackageName,
+    pub version: Version,
+    pub filename: String,
+    pub url: Url,
+    pub hash: ArtifactHash,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+impl Blueprint {
+    /// Whether a requirement with this marker applied when we resolved the blueprint,
+    /// for the package's base requirements (`extra` = None) or one of its extras. We
+    /// only recorded the markers the resolver actually looked at, so anything else
+    /// comes out false.
+    pub fn marker_applies(
+        &self,
+        expr: &marker::EnvMarkerExpr,
+        extra: Option<&Extra>,
+    ) -> Result<bool> {
+        let simplified = simplify_out_extra(expr, extra.map(|e| e.normalized()))?;
+        Ok(match simplified {
+            Simplified::True => true,
+            Simplified::False => false,
+            Simplified::Expr(expr) => self
+                .marker_expressions
+                .get(&StandaloneMarkerExpr(expr))
+                .copied()
+                .unwrap_or(false),
+        })
+    }
+
+    /// The version this blueprint has `name` (normalized) pinned at, if any.
+    pub fn pinned_version(&self, name: &str) -> Option<&Version> {
+        let pybi = std::iter::once((&self.pybi.name, &self.pybi.version));
+        let wheels = self.wheels.iter().map(|(pin, _)| (&pin.name, &pin.version));
+        let local = self.local.iter().map(|(pin, _)| (&pin.name, &pin.version));
+        pybi.chain(wheels)
+            .chain(local)
+            .find(|(pin_name, _)| pin_name.normalized() == name)
+            .map(|(_, version)| version)
+    }
+
+    /// The requirements in `reqs` that this blueprint doesn't pin a matching version
+    /// for. (Extras aren't checked.) It might never have been resolved with some of
+    /// their markers, and those could go either way, so they count as applying.
+    pub fn unmet_requirements<'r>(
+        &self,
+        reqs: &'r [UserRequirement],
+    ) -> Result<Vec<&'r UserRequirement>> {
+        let mut unmet = Vec::new();
+        for req in reqs {
+            if let Some(expr) = &req.env_marker_expr {
+                let applies = match simplify_out_extra(expr, None)? {
+                    Simplified::True => true,
+                    Simplified::False => false,
+                    Simplified::Expr(expr) => self
+                        .marker_expressions
+                        .get(&StandaloneMarkerExpr(expr))
+                        .copied()
+                        .unwrap_or(true),
+                };
+                if !applies {
+                    continue;
+                }
+            }
+            let met = match self.pinned_version(req.name.normalized()) {
+                Some(version) => req.specifiers.satisfied_by(version)?,
+                None => false,
+            };
+            if !met {
+                unmet.push(req);
+            }
+        }
+        Ok(unmet)
+    }
+
+    /// Copies over `old`'s annotations for the packages that are still pinned at the
+    /// same version. (If the pin moved, then whatever the note said probably isn't
+    /// true anymore.) Returns the ones that got left behind.
+    pub fn carry_annotations(&mut self, old: &Blueprint) -> Vec<(String, String)> {
+        let mut dropped = Vec::new();
+        for (name, note) in &old.annotations {
+            if self.pinned_version(name).is_some()
+                && self.pinned_version(name) == old.pinned_version(name)
+            {
+                self.annotations.insert(name.clone(), note.clone());
+            } else {
+                dropped.push((name.clone(), note.clone()));
+            }
+        }
+        dropped
+    }
+
+    /// A digest that identifies the environment this blueprint makes on `platform` (a
+    /// platform tag): if two blueprints have the same identity, they install exactly
+    /// the same files. Use it for naming env directories, CI cache keys, and the like.
+    ///
+    /// It only covers the things that make a difference to the env -- not, say, where
+    /// we got metadata from while resolving, or how the Blueprint happens to be
+    /// serialized this week. For local source trees, it covers which tree, but not
+    /// what's in it.
+    pub fn identity(&self, platform: &str) -> ArtifactHash {
+        fn pin_line(kind: &str, pin: &PinnedPackage) -> String {
+            let mut hashes =
+                pin.hashes.iter().map(|h| h.to_string()).collect::<Vec<_>>();
+            hashes.sort_unstable();
+            format!(
+                "{kind} {} {} {}",
+                pin.name.normalized(),
+                pin.version,
+                hashes.join(" ")
+            )
+        }
+        let mut packages = self
+            .wheels
+            .iter()
+            .map(|(pin, _)| pin_line("wheel", pin))
+            .collect::<Vec<_>>();
+        packages.extend(self.local.iter().map(|(pin, _)| {
+            format!(
+                "local {} {} {} {}",
+                pin.name.normalized(),
+                pin.version,
+                if pin.tree.editable {
+                    "editable"
+                } else {
+                    "built"
+                },
+                pin.tree.path.display()
+            )
+        }));
+        packages.sort_unstable();
+        // Line-based, so it's easy to keep stable. If what goes in here ever changes
+        // meaning, bump the version on the first line.
+        let mut lines = vec![
+            "posy-env-identity 1".to_string(),
+            format!("platform {platform}"),
+            pin_line("pybi", &self.pybi),
+        ];
+        lines.extend(packages);
+        let digest =
+            ring::digest::digest(&ring::digest::SHA256, lines.join("\n").as_bytes());
+        ArtifactHash {
+            mode: "sha256".into(),
+            raw_data: digest.as_ref().into(),
+        }
+    }
+
+    /// Lists the files that EnvForest::get_env would fetch to install this blueprint
+    /// on the given platforms, e.g. so they can be mirrored ahead of time. This makes
+    /// the same choices as the installer, except that for packages that have to be
+    /// built from an sdist, we list the sdist but not whatever its build pulls in.
+    /// Packages from local source trees aren't downloaded, so they don't appear.
+    pub fn artifact_urls(
+        &self,
+        db: &PackageDB,
+        platforms: &[&PybiPlatform],
+    ) -> Result<Vec<ArtifactDownload>> {
+        let mut downloads = Vec::new();
+        let mut add = |pin: &PinnedPackage, ai: &ArtifactInfo| -> Result<()> {
+            downloads.push(ArtifactDownload {
+                name: pin.name.clone(),
+                version: pin.version.clone(),
+                filename: ai.name.to_string(),
+                url: ai.url.clone(),
+                hash: ai.require_hash()?.clone(),
+                size: ai.size,
+            });
+            Ok(())
+        };
+
+        let (pybi_ai, pybi_platform) =
+            pick_pinned_binary::<Pybi>(db, platforms, &self.pybi)?;
+        add(&self.pybi, pybi_ai)?;
+        // we need the pybi's metadata to know which wheels it can use, but we can get
+        // that without downloading the whole thing
+        let (_, pybi_metadata) = db.get_metadata::<Pybi, _>(&[pybi_ai], None)?;
+        let wheel_platform = pybi_platform.wheel_platform(&pybi_metadata)?;
+
+        for (pin, _) in &self.wheels {
+            context!("finding files for {} {}", pin.name.as_given(), pin.version);
+            match pick_pinned_binary::<Wheel>(db, &[&wheel_platform], pin) {
+                Ok((wheel_ai, _)) => add(pin, wheel_ai)?,
+                Err(err) => {
+                    match err.downcast_ref::<PosyError>() {
+                        Some(PosyError::NoCompatibleBinaries { .. }) => (),
+                        _ => return Err(err),
+                    };
+                    let artifacts =
+                        db.artifacts_for_version(&pin.name, &pin.version)?;
+                    match pin.pinned_sdist(artifacts) {
+                        Some(sdist_ai) => add(pin, sdist_ai)?,
+                        None => bail!("no compatible wheel or sdist found"),
+                    }
+                }
+            }
+        }
+        Ok(downloads)
+    }
+}
+
+/// Blueprints for the same Brief on several platforms, so that e.g. one lockfile can
+/// serve a team on a mix of OSes. Each platform gets its own pins, since the same
+/// requirements can legitimately resolve differently (a different pybi build,
+/// platform-specific dependencies, ...). But they're resolved together, each one
+/// hinting the next, so they only differ where they have to.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct BlueprintSet {
+    // keyed by pybi platform core tag
+    pub blueprints: BTreeMap<String, Blueprint>,
+}
+
+impl BlueprintSet {
+    pub fn is_empty(&self) -> bool {
+        self.blueprints.is_empty()
+    }
+
+    /// The blueprint to use on a machine that can run `platforms` (most preferred
+    /// first), if any of them are covered.
+    pub fn for_platforms(&self, platforms: &[&PybiPlatform]) -> Option<&Blueprint> {
+        for platform in platforms {
+            // e.g. a manylinux_2_35 machine can use the manylinux_2_17 blueprint
+            let best = self
+                .blueprints
+                .iter()
+                .filter_map(|(tag, blueprint)| {
+                    platform.compatibility(tag).map(|score| (score, blueprint))
+                })
+                .max_by_key(|(score, _)| *score);
+            if let Some((_, blueprint)) = best {
+                return Some(blueprint);
+            }
+        }
+        None
+    }
+}
+
+impl Brief {
+    /// Like `resolve`, but separately for each of `platforms`. The first platform that
+    /// `like` has nothing for gets resolved with `policy`.
+    pub fn resolve_set(
+        &self,
+        db: &PackageDB,
+        platforms: &[&PybiPlatform],
+        like: Option<&BlueprintSet>,
+        policy: &ResolverPolicy,
+        build_stack: &[&PackageName],
+    ) -> Result<BlueprintSet> {
+        let mut set = BlueprintSet::default();
+        let mut previous: Option<Blueprint> = None;
+        for &platform in platforms {
+            let tag = platform.core_tag();
+            context!("resolving for {tag}");
+            // stick to what we had for this platform before, if anything; otherwise
+            // to what we just picked for the last one
+            let hint = like
+                .and_then(|like| like.blueprints.get(tag))
+                .or(previous.as_ref());
+            let platform = [platform];
+            let blueprint = match hint {
+                Some(_) => self.resolve(db, &platform, hint, build_stack)?,
+                None => self.resolve_with_policy(db, &platform, policy, build_stack)?,
+            };
+            set.blueprints.insert(tag.into(), blueprint.clone());
+            previous = Some(blueprint);
+        }
+        Ok(set)
+    }
+}
+
 fn pick_best_pybi<'a, 'b>(
     artifact_infos: &'a [ArtifactInfo],
     platforms: &[&'b PybiPlatform],
@@ -218,9 +717,7 @@ fn pick_best_pybi<'a, 'b>(
             .iter()
             .filter_map(|ai| {
                 if let ArtifactName::Pybi(name) = &ai.name {
-                    platform
-                        .max_compatibility(name.arch_tags.iter())
-                        .map(|score| (ai, score))
+                    platform.binary_compatibility(name).map(|score| (ai, score))
                 } else {
                     None
                 }
@@ -237,22 +734,126 @@ fn pick_best_pybi<'a, 'b>(
 fn resolve_pybi<'a, 'b>(
     db: &'a PackageDB,
     brief: &Brief,
+    python: &PythonRequirement,
     platforms: &[&'b PybiPlatform],
     hints: &VersionHints,
 ) -> Result<(&'a ArtifactInfo, &'b PybiPlatform)> {
-    let name = &brief.python.name;
-    let versions = fetch_and_sort_versions(db, brief, name, None, hints)?;
+    if let Some(url) = &python.url {
+        let ai = db.direct_artifact(url)?;
+        return pick_best_pybi(std::slice::from_ref(ai), platforms).ok_or_else(|| {
+            eyre!("{} isn't compatible with this platform", ai.name)
+        });
+    }
+    let name = &python.name;
+    let check = PythonCheck::Pybi(&python.specifiers);
+    let versions = fetch_and_sort_versions(db, brief, name, check, hints)?;
     for version in versions.iter() {
-        if brief.python.specifiers.satisfied_by(version)? {
-            let artifact_infos = db.artifacts_for_version(name, version)?;
-            if let Some((ai, platform)) = pick_best_pybi(artifact_infos, platforms) {
-                return Ok((ai, platform));
-            }
+        let artifact_infos = db.artifacts_for_version(name, version)?;
+        if let Some((ai, platform)) = pick_best_pybi(artifact_infos, platforms) {
+            return Ok((ai, platform));
         }
     }
     Err(PosyError::NoPybiFound)?
 }
 
+// The environment marker variables that packages installed with this pybi will see,
+// plus which kind of CPython it is.
+fn pybi_environment(
+    db: &PackageDB,
+    pybi_ai: &ArtifactInfo,
+    platform: &PybiPlatform,
+) -> Result<(HashMap<String, String>, AbiVariant)> {
+    let (_, pybi_metadata) = db
+        .get_metadata::<Pybi, _>(&[pybi_ai], None)
+        .wrap_err_with(|| format!("fetching metadata for {}", pybi_ai.url))?;
+    Ok((
+        platform.environment_marker_variables(&pybi_metadata),
+        pybi_metadata.abi_variant()?,
+    ))
+}
+
+// Which of `wheels` have nothing for `wheel_platform` (the pybi's, on `tag`) except an
+// sdist. Ones that don't even have an sdist can't be installed there at all, but all
+// we can do about that is say so; the resolver picked them because their metadata was
+// fine.
+fn source_builds(
+    db: &PackageDB,
+    tag: &str,
+    wheel_platform: &WheelPlatform,
+    wheels: &[(PinnedPackage, WheelResolveMetadata)],
+) -> Result<BTreeSet<String>> {
+    let mut source_builds = BTreeSet::new();
+    for (pin, _) in wheels {
+        let artifacts = match &pin.url {
+            Some(url) => std::slice::from_ref(db.direct_artifact(url)?),
+            None => db.artifacts_for_version(&pin.name, &pin.version)?,
+        };
+        let has_wheel = artifacts.iter().any(|ai| {
+            ai.name.inner_as::<WheelName>().map_or(false, |name| {
+                wheel_platform.binary_compatibility(name).is_some()
+            })
+        });
+        if has_wheel {
+            continue;
+        }
+        if artifacts.iter().any(|ai| ai.is::<Sdist>()) {
+            source_builds.insert(pin.name.normalized().to_string());
+        } else {
+            warn!(
+                "{} {} has no wheels for {tag} and no sdist, so it can't be installed there",
+                pin.name.as_given(),
+                pin.version,
+            );
+        }
+    }
+    Ok(source_builds)
+}
+
+// The file we'd install for `pin` on `wheel_platform`: its best wheel, or else its
+// sdist. (The same one select_pinned_binary picks at install time, give or take
+// yanked files.)
+fn selected_artifact<'a>(
+    wheel_platform: &WheelPlatform,
+    pin: &PinnedPackage,
+    artifacts: &'a [ArtifactInfo],
+) -> Option<&'a ArtifactInfo> {
+    artifacts
+        .iter()
+        .filter(|ai| matches!(&ai.hash, Some(hash) if pin.hashes.contains(hash)))
+        .filter_map(|ai| {
+            let name = ai.name.inner_as::<WheelName>()?;
+            Some((wheel_platform.binary_compatibility(name)?, ai))
+        })
+        .max_by_key(|(score, _)| *score)
+        .map(|(_, ai)| ai)
+        .or_else(|| pin.pinned_sdist(artifacts))
+}
+
+// Only the files we'd actually install here: anything else is some other platform's
+// business (and would be a lot of extra fetches). Pybis don't come from anywhere that
+// publishes attestations, so we leave them out too.
+fn claimed_publishers(
+    db: &PackageDB,
+    wheel_platform: &WheelPlatform,
+    wheels: &[(PinnedPackage, WheelResolveMetadata)],
+) -> Result<BTreeMap<String, String>> {
+    let mut publishers = BTreeMap::new();
+    if db.attestation_policy == AttestationPolicy::Ignore {
+        return Ok(publishers);
+    }
+    // direct references aren't on any index, so there's nowhere to get attestations
+    for (pin, _) in wheels.iter().filter(|(pin, _)| pin.url.is_none()) {
+        let artifacts = db.artifacts_for_version(&pin.name, &pin.version)?;
+        if let Some(ai) = selected_artifact(wheel_platform, pin, artifacts) {
+            if let Some(publisher) = db.attestation(ai)? {
+                // unwrap is safe b/c selected_artifact only picks hashed files
+                publishers.insert(ai.hash.as_ref().unwrap().to_string(), publisher);
+            }
+        }
+    }
+    Ok(publishers)
+}
+
 fn pinned(
     db: &PackageDB,
     name: PackageName,
@@ -267,10 +868,51 @@ fn pinned(
         name,
         version,
         hashes,
+        url: None,
+    })
+}
+
+fn python_might_help(err: &eyre::Report) -> bool {
+    err.chain().any(|cause| {
+        cause.is::<resolve_report::NoSolution>()
+            || matches!(
+                cause.downcast_ref::<PosyError>(),
+                Some(PosyError::NoPybiFound)
+            )
     })
 }
 
 impl Brief {
+    /// Calls `f` with `python`, and then each of `python_fallbacks` in turn, until one
+    /// works. Returns the first success, along with the requirement it used.
+    ///
+    /// Only errors that a different python might fix -- there's no such pybi, or no
+    /// solution with it -- move us on to the next one. Anything else (network trouble,
+    /// a broken sdist, Ctrl-C) would just happen again, so it comes straight back.
+    fn with_python_fallbacks<T, F>(&self, mut f: F) -> Result<(T, &PythonRequirement)>
+    where
+        F: FnMut(&PythonRequirement) -> Result<T>,
+    {
+        let mut pythons = std::iter::once(&self.python)
+            .chain(self.python_fallbacks.iter())
+            .peekable();
+        // unwrap is safe because there's always at least one, and we return on the
+        // last one no matter what
+        loop {
+            let python = pythons.next().unwrap();
+            match (f(python), pythons.peek()) {
+                (Ok(result), _) => return Ok((result, python)),
+                (Err(err), Some(next)) if python_might_help(&err) => {
+                    warn!(
+                        class = "python-fallback",
+                        "couldn't resolve with {python}: {err:#}; falling back to {next}"
+                    );
+                }
+                (Err(err), _) => return Err(err),
+            }
+        }
+    }
+
     pub fn resolve(
         &self,
         db: &PackageDB,
@@ -281,7 +923,180 @@ impl Brief {
         let version_hints = like
             .map(VersionHints::from)
             .unwrap_or_else(VersionHints::new);
-        let (pybi_ai, platform) = resolve_pybi(db, self, platforms, &version_hints)?;
+        self.resolve_with_hints(db, platforms, &version_hints, build_stack)
+    }
+
+    /// Like `resolve` with no old blueprint to go on, except using `policy` instead
+    /// of the current one -- e.g. for when the new blueprint is going to end up in
+    /// the same lockfile as some old ones.
+    pub fn resolve_with_policy(
+        &self,
+        db: &PackageDB,
+        platforms: &[&PybiPlatform],
+        policy: &ResolverPolicy,
+        build_stack: &[&PackageName],
+    ) -> Result<Blueprint> {
+        let version_hints = VersionHints::with_policy(policy.clone());
+        self.resolve_with_hints(db, platforms, &version_hints, build_stack)
+    }
+
+    fn resolve_with_hints(
+        &self,
+        db: &PackageDB,
+        platforms: &[&PybiPlatform],
+        version_hints: &VersionHints,
+        build_stack: &[&PackageName],
+    ) -> Result<Blueprint> {
+        let for_build = build_stack.last().map(|p| p.as_given().to_owned());
+        let warnings = crate::output::warnings_mark();
+        crate::events::emit(crate::events::Event::ResolveStarted {
+            python: self.python.to_string(),
+            requirements: self.requirements.len(),
+            for_build: for_build.clone(),
+        });
+        let (mut blueprint, python) = self.with_python_fallbacks(|python| {
+            self.resolve_with_python(db, python, platforms, version_hints, build_stack)
+        })?;
+        if python != &self.python {
+            blueprint.python_fallback = Some(python.clone());
+        }
+        blueprint.warnings = crate::output::warnings_since(warnings);
+        crate::events::emit(crate::events::Event::ResolveFinished {
+            python: format!(
+                "{} {}",
+                blueprint.pybi.name.as_given(),
+                blueprint.pybi.version
+            ),
+            packages: blueprint.wheels.len(),
+            for_build,
+        });
+        Ok(blueprint)
+    }
+
+    // A copy of self, with extra constraints that hold everything `old` pinned at its
+    // pinned version, except for the packages in `changed`.
+    fn held_at(&self, old: &Blueprint, changed: &[PackageName]) -> Result<Brief> {
+        let mut held = self.clone();
+        if !changed.contains(&old.pybi.name) && old.pybi.url.is_none() {
+            held.python =
+                format!("{} == {}", old.pybi.name.as_given(), old.pybi.version)
+                    .parse()?;
+            // we already know which one works
+            held.python_fallbacks.clear();
+        }
+        for (pin, _) in &old.wheels {
+            if !changed.contains(&pin.name) {
+                held.constraints.push(
+                    format!("{} == {}", pin.name.as_given(), pin.version).parse()?,
+                );
+            }
+        }
+        Ok(held)
+    }
+
+    /// Like `resolve` with `like: Some(old)`, except that instead of just preferring
+    /// old's pins, it *holds* everything except the packages in `changed` at their
+    /// pinned versions, and fails if it can't. So e.g. adding a new requirement can't
+    /// shuffle around unrelated pins. (Packages `old` didn't have at all can still go
+    /// anywhere, and nothing gets installed just because it was in `old`.)
+    pub fn resolve_incremental(
+        &self,
+        db: &PackageDB,
+        platforms: &[&PybiPlatform],
+        old: &Blueprint,
+        changed: &[PackageName],
+        build_stack: &[&PackageName],
+    ) -> Result<Blueprint> {
+        let held = self.held_at(old, changed)?;
+        let mut blueprint = held
+            .resolve(db, platforms, Some(old), build_stack)
+            .wrap_err("couldn't resolve without changing any other pinned versions")?;
+        if held.python != self.python {
+            // we held on to whichever python we were using
+            blueprint.python_fallback = old.python_fallback.clone();
+        }
+        Ok(blueprint)
+    }
+
+    /// For 'posy update': re-resolves without any preference for what `old` had
+    /// pinned for the packages in `targets`, so they move up to the newest versions
+    /// that work. Everything else still prefers its old pin; if `hold` is set, then
+    /// everything else stays exactly where it was (like `resolve_incremental`), and
+    /// this fails if the targets can't move without dragging something else along.
+    pub fn resolve_upgrade(
+        &self,
+        db: &PackageDB,
+        platforms: &[&PybiPlatform],
+        old: &Blueprint,
+        targets: &[PackageName],
+        hold: bool,
+        build_stack: &[&PackageName],
+    ) -> Result<Blueprint> {
+        let mut version_hints = VersionHints::from(old);
+        version_hints.forget(targets);
+        if !hold {
+            return self.resolve_with_hints(db, platforms, &version_hints, build_stack);
+        }
+        let held = self.held_at(old, targets)?;
+        let mut blueprint = held
+            .resolve_with_hints(db, platforms, &version_hints, build_stack)
+            .wrap_err("couldn't upgrade without changing any other pinned versions")?;
+        if held.python != self.python {
+            blueprint.python_fallback = old.python_fallback.clone();
+        }
+        Ok(blueprint)
+    }
+
+    /// For 'posy lock --metadata-only': walks the requirements' dependency graph as
+    /// it looks from the first of `platforms`, without pinning anything. (See
+    /// dep_report for how that differs from a real resolve.)
+    pub fn explore(
+        &self,
+        db: &PackageDB,
+        platforms: &[&PybiPlatform],
+    ) -> Result<DependencyReport> {
+        let hints = VersionHints::new();
+        let (report, _) = self.with_python_fallbacks(|python| {
+            let (pybi_ai, platform) =
+                resolve_pybi(db, self, python, platforms, &hints)?;
+            let pybi_name = pybi_ai.name.inner_as::<PybiName>().unwrap();
+            let wheel_builder = WheelBuilder::new(
+                db,
+                pybi_ai.name.distribution(),
+                pybi_ai.name.version(),
+                PybiPlatform::native_platforms()?,
+                &[],
+            )?;
+            let (env, abi_variant) = pybi_environment(db, pybi_ai, platform)?;
+            let python_desc = format!(
+                "{} {}",
+                pybi_name.distribution.as_given(),
+                pybi_name.version
+            );
+            let explore = |state: &PubgrubState| {
+                DependencyReport::explore(
+                    state,
+                    python_desc,
+                    &self.requirements,
+                    &self.local_requirements,
+                    &self.constraints,
+                )
+            };
+            with_state(db, self, &env, &hints, &wheel_builder, abi_variant, explore)
+        })?;
+        Ok(report)
+    }
+
+    fn resolve_with_python(
+        &self,
+        db: &PackageDB,
+        python: &PythonRequirement,
+        platforms: &[&PybiPlatform],
+        version_hints: &VersionHints,
+        build_stack: &[&PackageName],
+    ) -> Result<Blueprint> {
+        let (pybi_ai, platform) =
+            resolve_pybi(db, self, python, platforms, version_hints)?;
         let wheel_builder = WheelBuilder::new(
             db,
             pybi_ai.name.distribution(),
@@ -289,42 +1104,69 @@ impl Brief {
             PybiPlatform::native_platforms()?,
             build_stack,
         )?;
-        let (_, pybi_metadata) = db
-            .get_metadata::<Pybi, _>(&[pybi_ai], None)
-            .wrap_err_with(|| format!("fetching metadata for {}", pybi_ai.url))?;
+        let (env_marker_vars, abi_variant) = pybi_environment(db, pybi_ai, platform)?;
         let pybi_name = pybi_ai.name.inner_as::<PybiName>().unwrap();
 
-        let mut env_marker_vars = pybi_metadata.environment_marker_variables;
-        if !env_marker_vars.contains_key("platform_machine") {
-            let is_arm64 = platform.compatibility("macosx_10_0_arm64").is_some();
-            let is_x86_64 = platform.compatibility("macosx_10_0_x86_64").is_some();
-            match (is_arm64, is_x86_64) {
-                (true, false) => {
-                    env_marker_vars.insert("platform_machine".into(), "arm64".into());
-                }
-                (false, true) => {
-                    env_marker_vars.insert("platform_machine".into(), "x86_64".into());
-                }
-                _ => (),
-            };
-        }
-
-        let (wheels, marker_exprs) = resolve_wheels(
+        let (wheels, local, marker_exprs) = resolve_wheels(
             db,
             self,
             &env_marker_vars,
-            &version_hints,
+            version_hints,
             &wheel_builder,
+            abi_variant,
         )?;
+        for (pin, _) in &wheels {
+            if db.is_preferred_local_version(&pin.name, &pin.version) {
+                // unwrap is safe b/c it wouldn't be preferred otherwise
+                let index = db.preferred_local_index(&pin.name).unwrap();
+                info!(
+                    "using local build {} {} from {index}",
+                    pin.name.as_given(),
+                    pin.version
+                );
+            }
+        }
 
-        Ok(Blueprint {
-            pybi: pinned(
+        let pybi = match &python.url {
+            Some(url) => PinnedPackage {
+                name: pybi_name.distribution.to_owned(),
+                version: pybi_name.version.to_owned(),
+                hashes: vec![pybi_ai.require_hash()?.clone()],
+                url: Some(url.clone()),
+            },
+            None => pinned(
                 db,
                 pybi_name.distribution.to_owned(),
                 pybi_name.version.to_owned(),
             )?,
+        };
+
+        let (_, pybi_metadata) = db.get_metadata::<Pybi, _>(&[pybi_ai], None)?;
+        let wheel_platform = platform.wheel_platform(&pybi_metadata)?;
+        let tag = platform.core_tag();
+        let source_builds = source_builds(db, tag, &wheel_platform, &wheels)?;
+        let claimed_publishers = claimed_publishers(db, &wheel_platform, &wheels)?;
+        if self.no_source_builds && !source_builds.is_empty() {
+            bail!(
+                "no wheels for {} on {}, and source builds aren't allowed",
+                source_builds.iter().cloned().collect::<Vec<_>>().join(", "),
+                tag
+            );
+        }
+
+        Ok(Blueprint {
+            pybi,
             wheels,
+            local,
             marker_expressions: marker_exprs,
+            python_fallback: None,
+            annotations: Default::default(),
+            claimed_publishers,
+            platform_tags: platform.custom_tags().unwrap_or_default().to_vec(),
+            source_builds,
+            policy: version_hints.policy.clone(),
+            python_flags: self.python_flags.clone(),
+            warnings: Vec::new(),
         })
     }
 }
@@ -336,16 +1178,69 @@ struct PubgrubState<'a> {
     brief: &'a Brief,
     version_hints: &'a VersionHints<'a>,
     wheel_builder: &'a WheelBuilder<'a>,
+    abi_variant: AbiVariant,
 
     marker_exprs: RefCell<HashMap<StandaloneMarkerExpr, bool>>,
     python_full_version: Version,
+    requires_python: RequiresPythonCheck,
+    // Big resolves see the same markers over and over in different packages'
+    // metadata, so we only evaluate each one once. (Specifiers are the same, but
+    // those don't depend on the env, so they're in db.resolve_memo.)
+    marker_values:
+        RefCell<HashMap<Option<Extra>, HashMap<marker::EnvMarkerExpr, bool>>>,
+    // brief.constraints, boiled down to one range per package
+    constraints: HashMap<PackageName, Range<Version>>,
+    // brief.local_requirements, and the version each tree turned out to have
+    local_versions: HashMap<PackageName, Version>,
     // record of the metadata we used, so we can record it and validate it later when
-    // using the pins
+    // using the pins (mostly copied out of db.resolve_memo, except for local trees)
     expected_metadata: FrozenMap<(PackageName, Version), Box<WheelResolveMetadata>>,
     // These are sorted with most-preferred first.
     versions: FrozenMap<PackageName, Vec<&'a Version>>,
 }
 
+/// The parts of resolving that don't depend on the brief, the python, or the
+/// platform. Locking a project can mean a dozen resolves that mostly look at the same
+/// packages -- one per lock platform, dependency group, python fallback -- so these
+/// live on the PackageDB and get shared by all of them, instead of each resolve
+/// re-reading and re-parsing the same metadata.
+#[derive(Default)]
+pub struct ResolveMemo {
+    // Keyed by ABI variant too, since that decides which wheels we read it from (see
+    // PubgrubState::metadata_candidates). Only ever inserted into, so resolves can hand
+    // out references into it.
+    metadata: FrozenMap<(AbiVariant, PackageName, Version), Box<WheelResolveMetadata>>,
+    ranges: RefCell<HashMap<Specifiers, Range<Version>>>,
+}
+
+// Whether our python satisfies an artifact's requires-python. Thousands of artifacts
+// share the same handful of requires-python strings, so we remember the answer for each.
+struct RequiresPythonCheck {
+    python_version: Version,
+    seen: RefCell<HashMap<String, bool>>,
+}
+
+impl RequiresPythonCheck {
+    fn new(python_version: Version) -> RequiresPythonCheck {
+        RequiresPythonCheck {
+            python_version,
+            seen: Default::default(),
+        }
+    }
+
+    fn satisfied_by(&self, requires_python: &str) -> Result<bool> {
+        if let Some(&ok) = self.seen.borrow().get(requires_python) {
+            return Ok(ok);
+        }
+        let specifiers: Specifiers = requires_python.parse()?;
+        let ok = specifiers.satisfied_by(&self.python_version)?;
+        self.seen
+            .borrow_mut()
+            .insert(requires_python.to_owned(), ok);
+        Ok(ok)
+    }
+}
+
 fn get_or_fill<'a, K, V, F>(
     map: &'a FrozenMap<K, V>,
     key: &K,
@@ -363,43 +1258,71 @@ where
     }
 }
 
+// What fetch_and_sort_versions holds versions (and their files' requires-python) to.
+#[derive(Clone, Copy)]
+enum PythonCheck<'a> {
+    // nothing; e.g. we just want to know what's out there
+    Unchecked,
+    // a package, to be installed into this python
+    For(&'a RequiresPythonCheck),
+    // a pybi, which *is* the python: its version has to match what the brief asked
+    // for, and so does each file's requires-python, if it has one
+    Pybi(&'a Specifiers),
+}
+
 fn fetch_and_sort_versions<'a>(
     db: &'a PackageDB,
     brief: &Brief,
     package: &PackageName,
-    python_version: Option<&Version>,
+    python: PythonCheck,
     hints: &VersionHints,
 ) -> Result<Vec<&'a Version>> {
     let artifacts = db.available_artifacts(package)?;
     let mut versions = Vec::new();
     let all_pre = artifacts.iter().all(|(version, _)| version.is_prerelease());
-    let allow_prerelease = all_pre || brief.allow_pre.allow_pre_for(package);
-    let (version_hint, hash_hints) = match hints.0.get(&package) {
+    let allow_prerelease = (all_pre && hints.policy.prerelease_fallback)
+        || brief.allow_pre.allow_pre_for(package);
+    let (version_hint, hash_hints) = match hints.pins.get(&package) {
         Some((version, hash)) => (Some(version), Some(hash)),
         None => (None, None),
     };
 
     for (version, ais) in artifacts.iter() {
-        if !allow_prerelease && version.is_prerelease() {
+        // e.g. 1.0.post4294967295: pubgrub would have nowhere to go when it bumps it
+        // (see Version::next)
+        if version.next().is_err() {
+            debug!("skipping {} {version}: too large", package.as_given());
             continue;
         }
+        if let PythonCheck::Pybi(specifiers) = python {
+            if !specifiers.satisfied_by(version)? {
+                continue;
+            }
+        }
+        if !allow_prerelease && version.is_prerelease() {
+            let pinned =
+                brief.keep_pinned_prereleases && version_hint == Some(&version);
+            if !pinned {
+                continue;
+            }
+        }
         for ai in ais {
-            if ai.yanked.yanked {
-                let is_pinned = match (&hash_hints, &ai.hash) {
-                    (Some(hints), Some(hash)) => hints.contains(&hash),
-                    _ => false,
-                };
-                if !is_pinned {
-                    continue;
-                }
+            let is_pinned = match (&hash_hints, &ai.hash) {
+                (Some(hints), Some(hash)) => hints.contains(&hash),
+                _ => false,
+            };
+            if !db.yanked_policy.allows(ai, is_pinned) {
+                continue;
             }
-            if let (Some(python_version), Some(requires_python)) =
-                (python_version, &ai.requires_python)
-            {
-                let requires_python: Specifiers = requires_python.parse()?;
-                if !requires_python.satisfied_by(python_version)? {
-                    continue;
+            let python_ok = match (python, &ai.requires_python) {
+                (PythonCheck::Unchecked, _) | (_, None) => true,
+                (PythonCheck::For(check), Some(rp)) => check.satisfied_by(rp)?,
+                (PythonCheck::Pybi(_), Some(rp)) => {
+                    rp.parse::<Specifiers>()?.satisfied_by(version)?
                 }
+            };
+            if !python_ok {
+                continue;
             }
             // we found a valid artifact for this version. So this version is valid, and
             // we can save it and move on to the next.
@@ -407,50 +1330,151 @@ fn fetch_and_sort_versions<'a>(
             break;
         }
     }
-    if let Some(version_hint) = version_hint {
-        // if we have a version hint, then our preference ordering is:
-        // - the hinted version
-        // - the versions greater than the hinted version, in order from smallest to
-        //   largest (so if our hint is 1.1, we prefer 1.2 over 1.3)
-        // - the versions smaller than our hinted version, from largest to smallest (so
-        //   if our hint is 1.1, we prefer 1.0 over 0.9).
-        versions.sort_unstable_by_key(|v| {
-            if v >= version_hint {
-                (None, Some(*v))
+    // The hinted version goes first, then the project's preferred local builds (see
+    // PackageDB::prefer_local_versions). After that, if the policy says so, the
+    // versions nearest the hint (see HintOrder::Nearest); and then highest to lowest,
+    // or the other way around, if that's what the brief wants.
+    let strategy = match python {
+        // see Brief::strategy
+        PythonCheck::Pybi(_) => ResolutionStrategy::Highest,
+        _ => brief.strategy,
+    };
+    let preferred = |v: &Version| db.is_preferred_local_version(package, v);
+    let nearest = |v: &'a Version| match version_hint {
+        Some(hint) if hints.policy.hint_order == HintOrder::Nearest => {
+            if v >= *hint {
+                (None, Some(v))
             } else {
-                (Some(std::cmp::Reverse(*v)), None)
+                (Some(std::cmp::Reverse(v)), None)
             }
-        });
-    } else {
-        versions.sort_unstable_by_key(|v| std::cmp::Reverse(*v));
-    }
-
-    // sort from highest to lowest
-    versions.sort_unstable_by_key(|v| {
-        (
-            // false sorts before true, so version_hint = v sorts first
-            version_hint != Some(v),
-            // and otherwise, high versions come before low versions
-            std::cmp::Reverse(*v),
-        )
+        }
+        _ => (None, None),
+    };
+    versions.sort_unstable_by(|a, b| {
+        // false sorts before true, so version_hint = v sorts first
+        let hinted = (version_hint != Some(a)).cmp(&(version_hint != Some(b)));
+        hinted
+            .then_with(|| preferred(b).cmp(&preferred(a)))
+            .then_with(|| nearest(a).cmp(&nearest(b)))
+            .then_with(|| match strategy {
+                ResolutionStrategy::Highest => b.cmp(a),
+                ResolutionStrategy::Lowest => a.cmp(b),
+            })
     });
 
     Ok(versions)
 }
 
-impl<'a> PubgrubState<'a> {
-    fn metadata(
-        &self,
-        release: &(PackageName, Version),
-    ) -> Result<&WheelResolveMetadataInner> {
-        Ok(&get_or_fill(&self.expected_metadata, release, || {
-            let ais = self.db.artifacts_for_version(&release.0, &release.1)?;
-            let (ai, wheel_metadata) = self
-                .db
-                .get_metadata::<Wheel, _>(ais, Some(self.wheel_builder))?;
-            Ok(Box::new(WheelResolveMetadata::from(ai, &wheel_metadata)))
-        })?
-        .inner)
+impl<'a> PubgrubState<'a> {
+    fn metadata(
+        &self,
+        release: &(PackageName, Version),
+    ) -> Result<&WheelResolveMetadataInner> {
+        Ok(&get_or_fill(&self.expected_metadata, release, || {
+            let (name, version) = release;
+            let key = (self.abi_variant, name.clone(), version.clone());
+            let shared = get_or_fill(&self.db.resolve_memo.metadata, &key, || {
+                let ais = self.metadata_candidates(release)?;
+                let (ai, wheel_metadata) = self
+                    .db
+                    .get_metadata::<Wheel, _>(&ais, Some(self.wheel_builder))?;
+                Ok(Box::new(WheelResolveMetadata::from(ai, &wheel_metadata)))
+            })?;
+            Ok(Box::new(shared.clone()))
+        })?
+        .inner)
+    }
+
+    fn metadata_candidates(
+        &self,
+        release: &(PackageName, Version),
+    ) -> Result<Vec<&ArtifactInfo>> {
+        // Don't look at wheels for the other kind of CPython (regular vs
+        // free-threaded) -- their metadata could legitimately be different, and
+        // then the install-time consistency check would fail.
+        Ok(self
+            .db
+            .artifacts_for_version(&release.0, &release.1)?
+            .iter()
+            .filter(|ai| match ai.name.inner_as::<WheelName>() {
+                Some(name) => name
+                    .abi_tags
+                    .iter()
+                    .any(|abi| self.abi_variant.accepts_abi_tag(abi)),
+                None => true,
+            })
+            .collect())
+    }
+
+    // Pubgrub asks for metadata strictly one package at a time, so when we hand it a
+    // new batch of dependencies, guess which version of each it'll pick (the same way
+    // choose_package_version does) and get the db started fetching their metadata in
+    // the background. If we guess wrong, oh well.
+    //
+    // Guessing means looking at each package's index page, so first we get all of
+    // those going at once. Then we only wait for about as long as the slowest one,
+    // instead of one round-trip after another.
+    fn prefetch_dependencies(&self, dc: &DependencyConstraints<ResPkg, Version>) {
+        let names = dc.keys().filter_map(|respkg| match respkg {
+            ResPkg::Package(name, _) if !self.local_versions.contains_key(name) => {
+                Some(name)
+            }
+            _ => None,
+        });
+        self.db.prefetch_projects(names);
+        for (respkg, range) in dc {
+            if let ResPkg::Package(name, _) = respkg {
+                if let Err(err) = self.prefetch(name, range) {
+                    // not our problem; if it matters, we'll hit it again for real
+                    trace!("not prefetching {}: {err:#}", name.as_given());
+                }
+            }
+        }
+    }
+
+    fn prefetch(&self, name: &PackageName, range: &Range<Version>) -> Result<()> {
+        if self.local_versions.contains_key(name) {
+            return Ok(());
+        }
+        let version = self.versions(name)?.iter().find(|v| range.contains(v));
+        if let Some(&version) = version {
+            let release = (name.clone(), version.clone());
+            if self.expected_metadata.get(&release).is_none() {
+                let ais = self.metadata_candidates(&release)?;
+                self.db.prefetch_metadata::<Wheel, _>(&ais);
+            }
+        }
+        Ok(())
+    }
+
+    // Only catches the cases where there's nothing at all we could install. (If
+    // there are files but none work for our python, the resolver explains that fine.)
+    fn check_has_files(&self, package: &PackageName) -> Result<()> {
+        if !self.versions(package)?.is_empty() {
+            return Ok(());
+        }
+        let name = package.as_given().to_owned();
+        let artifacts = self.db.available_artifacts(package)?;
+        if artifacts.is_empty() {
+            if self.db.project_exists(package)? {
+                Err(PosyError::NoInstallableFiles {
+                    name,
+                    reason: "it doesn't have any files".into(),
+                })?;
+            } else {
+                Err(PosyError::PackageNotFound { name })?;
+            }
+        } else if artifacts
+            .values()
+            .flatten()
+            .all(|ai| !self.db.yanked_policy.allows(ai, false))
+        {
+            Err(PosyError::NoInstallableFiles {
+                name,
+                reason: "all of its files have been yanked".into(),
+            })?;
+        }
+        Ok(())
     }
 
     fn versions(&self, package: &PackageName) -> Result<&[&Version]> {
@@ -459,58 +1483,182 @@ impl<'a> PubgrubState<'a> {
                 self.db,
                 self.brief,
                 package,
-                Some(&self.python_full_version),
+                PythonCheck::For(&self.requires_python),
                 self.version_hints,
             )
         })
     }
 }
 
+type WheelResolution = (
+    Vec<(PinnedPackage, WheelResolveMetadata)>,
+    Vec<(LocalPin, WheelResolveMetadata)>,
+    HashMap<StandaloneMarkerExpr, bool>,
+);
+
 fn resolve_wheels(
     db: &PackageDB,
     brief: &Brief,
     env: &HashMap<String, String>,
     version_hints: &VersionHints,
     wheel_builder: &WheelBuilder,
-) -> Result<(
-    Vec<(PinnedPackage, WheelResolveMetadata)>,
-    HashMap<StandaloneMarkerExpr, bool>,
-)> {
-    let state = PubgrubState {
+    abi_variant: AbiVariant,
+) -> Result<WheelResolution> {
+    with_state(
+        db,
+        brief,
+        env,
+        version_hints,
+        wheel_builder,
+        abi_variant,
+        solve,
+    )
+}
+
+// Sets up a PubgrubState for `brief` -- which includes asking the local trees' build
+// backends for their metadata -- and hands it to `f`.
+fn with_state<T, F>(
+    db: &PackageDB,
+    brief: &Brief,
+    env: &HashMap<String, String>,
+    version_hints: &VersionHints,
+    wheel_builder: &WheelBuilder,
+    abi_variant: AbiVariant,
+    f: F,
+) -> Result<T>
+where
+    F: FnOnce(&PubgrubState) -> Result<T>,
+{
+    let _timer = crate::stats::time_phase("resolve");
+    let python_full_version: Version = env
+        .get("python_full_version")
+        .ok_or(eyre!(
+            "Missing 'python_full_version' environment marker variable"
+        ))?
+        .parse()?;
+
+    // Local trees have exactly one version -- whatever they have right now -- and we
+    // have to ask the build backend what it is.
+    let mut local_builds = Vec::new();
+    for req in &brief.local_requirements {
+        context!("getting metadata for {}", req);
+        let (_, metadata) = wheel_builder.local_tree_metadata(&req.name, &req.tree)?;
+        if metadata.name != req.name {
+            bail!(
+                "{} contains {}, not {}",
+                req.tree.path.display(),
+                metadata.name.as_given(),
+                req.name.as_given()
+            );
+        }
+        let resolve_metadata = WheelResolveMetadata::from_local(&req.tree, &metadata);
+        local_builds.push((req, metadata.version, resolve_metadata));
+    }
+
+    let mut state = PubgrubState {
         db,
         env,
         brief,
         version_hints,
         wheel_builder,
+        abi_variant,
         marker_exprs: Default::default(),
-        python_full_version: env
-            .get("python_full_version")
-            .ok_or(eyre!(
-                "Missing 'python_full_version' environment marker variable"
-            ))?
-            .parse()?,
+        requires_python: RequiresPythonCheck::new(python_full_version.clone()),
+        python_full_version,
+        marker_values: Default::default(),
+        constraints: Default::default(),
+        local_versions: Default::default(),
         expected_metadata: Default::default(),
         versions: Default::default(),
     };
+    state.constraints = state.constraint_ranges()?;
+    for (req, version, resolve_metadata) in &local_builds {
+        if state.local_versions.contains_key(&req.name) {
+            bail!("got more than one local tree for {}", req.name.as_given());
+        }
+        state
+            .local_versions
+            .insert(req.name.clone(), version.clone());
+        // Pre-filling these means the resolver never goes looking on the index.
+        state.versions.insert(req.name.clone(), vec![version]);
+        state.expected_metadata.insert(
+            (req.name.clone(), version.clone()),
+            Box::new(resolve_metadata.clone()),
+        );
+    }
+    f(&state)
+}
+
+fn solve(state: &PubgrubState) -> Result<WheelResolution> {
+    let (db, brief) = (state.db, state.brief);
+
+    // A package with nothing to install tends to turn up as a baffling "no versions"
+    // error somewhere deep in the derivation tree, so check the user's own
+    // requirements up front.
+    for req in &brief.requirements {
+        if let Some(expr) = &req.env_marker_expr {
+            if !state.eval_marker(expr, None)? {
+                continue;
+            }
+        }
+        if !state.local_versions.contains_key(&req.name) {
+            state.check_has_files(&req.name)?;
+        }
+    }
 
     // XX this error reporting is terrible. It's a hack to work around PubGrubError not
     // being convertible to eyre::Report, because eyre::Report requires Send.
-    let result = pubgrub::solver::resolve(&state, ResPkg::Root, ROOT_VERSION.clone());
+    let result = pubgrub::solver::resolve(state, ResPkg::Root, ROOT_VERSION.clone());
 
     use pubgrub::error::PubGrubError::*;
 
     match result {
+        // whatever went wrong, it's because we stopped it halfway
+        Err(_) if state.db.cancellation_token().is_cancelled() => {
+            Err(PosyError::Interrupted.into())
+        }
         Ok(solution) => {
+            // only informational, so not worth failing the resolve over
+            match find_held_back(state, &solution) {
+                Ok(held_back) => {
+                    for held_back in held_back {
+                        info!("{held_back}");
+                    }
+                }
+                Err(_) if state.db.cancellation_token().is_cancelled() => {
+                    return Err(PosyError::Interrupted.into());
+                }
+                Err(err) => debug!("couldn't check for held-back packages: {err:#}"),
+            }
             let mut pins = Vec::new();
+            let mut local_pins = Vec::new();
             for (pkg, v) in solution {
                 if let ResPkg::Package(name, None) = pkg {
-                    pins.push((
-                        pinned(db, name.clone(), v.clone())?,
-                        state.expected_metadata.get(&(name, v)).unwrap().clone(),
-                    ))
+                    let expected_metadata = state
+                        .expected_metadata
+                        .get(&(name.clone(), v.clone()))
+                        .unwrap();
+                    if state.local_versions.contains_key(&name) {
+                        // unwrap is safe b/c local_versions came from local_requirements
+                        let req = brief
+                            .local_requirements
+                            .iter()
+                            .find(|req| req.name == name)
+                            .unwrap();
+                        local_pins.push((
+                            LocalPin {
+                                name,
+                                version: v,
+                                tree: req.tree.clone(),
+                            },
+                            expected_metadata.clone(),
+                        ));
+                    } else {
+                        pins.push((pinned(db, name, v)?, expected_metadata.clone()));
+                    }
                 }
             }
-            Ok((pins, state.marker_exprs.into_inner()))
+            Ok((pins, local_pins, state.marker_exprs.take()))
         }
         Err(err) => Err(match err {
             ErrorRetrievingDependencies {
@@ -546,42 +1694,261 @@ fn resolve_wheels(
                 eyre!("{} v{} depends on itself", package, version)
             }
 
-            NoSolution(mut derivation_tree) => {
+            NoSolution(derivation_tree) => {
                 fn dump_tree(tree: &DerivationTree<ResPkg, Version>, depth: usize) {
                     let indent = "   ".repeat(depth);
                     match tree {
                         DerivationTree::External(inner) => {
-                            println!("{}external: {}", indent, inner);
+                            trace!("{}external: {}", indent, inner);
                         }
                         DerivationTree::Derived(inner) => {
-                            println!("{}derived (id={:?})", indent, inner.shared_id);
+                            trace!("{}derived (id={:?})", indent, inner.shared_id);
                             for (pkg, term) in inner.terms.iter() {
-                                println!("{}  {} -> {}", indent, pkg, term);
+                                trace!("{}  {} -> {}", indent, pkg, term);
                             }
-                            println!("{}cause 1:", indent);
+                            trace!("{}cause 1:", indent);
                             dump_tree(&inner.cause1, depth + 1);
-                            println!("{}cause 2:", indent);
+                            trace!("{}cause 2:", indent);
                             dump_tree(&inner.cause2, depth + 1);
                         }
                     }
                 }
 
-                println!("\n-------- derivation tree --------");
-                //println!("{:?}", derivation_tree);
-                dump_tree(&derivation_tree, 0);
-                derivation_tree.collapse_no_versions();
-                println!("\n-------- derivation tree (collapsed) --------");
-                //println!("{:?}", derivation_tree);
+                trace!("-------- derivation tree --------");
                 dump_tree(&derivation_tree, 0);
-                eyre!(
-                    "{}",
-                    pubgrub::report::DefaultStringReporter::report(&derivation_tree)
-                )
+                // (NoSolution alone would be pubgrub's, thanks to the glob import)
+                let report =
+                    resolve_report::NoSolution::new(derivation_tree, |pkg, range| {
+                        excluded_versions(state, pkg, range)
+                    });
+                eyre::Report::new(report)
             }
         }),
     }
 }
 
+// For the error message when there's no solution: the versions of `pkg` in `range` that
+// exist, but that fetch_and_sort_versions wouldn't let us use. Doesn't try to account
+// for hints, since those only ever let *more* versions through.
+fn excluded_versions(
+    state: &PubgrubState,
+    pkg: &ResPkg,
+    range: &Range<Version>,
+) -> Vec<ExcludedVersion> {
+    let name = match pkg {
+        ResPkg::Package(name, _) => name,
+        ResPkg::Root => return Vec::new(),
+    };
+    // we're already reporting an error; if we can't look things up, then we just
+    // don't say anything extra
+    let artifacts = match state.db.available_artifacts(name) {
+        Ok(artifacts) => artifacts,
+        Err(_) => return Vec::new(),
+    };
+    let all_pre = artifacts.keys().all(|version| version.is_prerelease());
+    let allow_prerelease = all_pre || state.brief.allow_pre.allow_pre_for(name);
+    let usable = |ai: &ArtifactInfo| state.db.yanked_policy.allows(ai, false);
+    let mut excluded = Vec::new();
+    for (version, ais) in artifacts.iter() {
+        if !range.contains(version) {
+            continue;
+        }
+        let reason = if !allow_prerelease && version.is_prerelease() {
+            "is a pre-release, and pre-releases aren't allowed".to_string()
+        } else if !ais.iter().any(usable) {
+            // indexing is safe b/c there are no versions without files
+            ais.iter()
+                .find(|ai| ai.yanked.reason.is_some())
+                .unwrap_or(&ais[0])
+                .yanked
+                .describe()
+        } else {
+            let python_ok = |ai: &ArtifactInfo| match &ai.requires_python {
+                Some(rp) => state.requires_python.satisfied_by(rp).unwrap_or(false),
+                None => true,
+            };
+            if ais.iter().any(|ai| usable(ai) && python_ok(ai)) {
+                continue;
+            }
+            format!("doesn't support Python {}", state.python_full_version)
+        };
+        excluded.push(ExcludedVersion {
+            version: version.to_string(),
+            reason,
+        });
+    }
+    excluded.reverse();
+    excluded
+}
+
+/// A package where we ended up with something older than the newest version on the
+/// index, along with our best guess at why.
+pub struct HeldBack {
+    pub name: PackageName,
+    pub chosen: Version,
+    pub newest: Version,
+    pub reasons: Vec<HoldReason>,
+}
+
+pub enum HoldReason {
+    /// Newer versions exist, but they don't support the python we're resolving for.
+    RequiresPython {
+        version: Version,
+        requires_python: String,
+    },
+    /// Some requirement that's active in the final solution excludes the newest
+    /// version that supports our python.
+    Capped {
+        by: ResPkg,
+        by_version: Version,
+        specifiers: Specifiers,
+    },
+}
+
+impl Display for HeldBack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "using {} {}, even though {} is available",
+            self.name.as_given(),
+            self.chosen,
+            self.newest
+        )?;
+        if self.reasons.is_empty() {
+            // e.g. two requirements that are fine on their own, but the newest
+            // versions of their dependencies conflict with each other
+            write!(
+                f,
+                "\n  - no direct cap found; probably an indirect conflict"
+            )?;
+        }
+        for reason in &self.reasons {
+            match reason {
+                HoldReason::RequiresPython {
+                    version,
+                    requires_python,
+                } => write!(
+                    f,
+                    "\n  - {} {version} has requires-python {requires_python}",
+                    self.name.as_given()
+                )?,
+                HoldReason::Capped {
+                    by: ResPkg::Root,
+                    specifiers,
+                    ..
+                } => write!(
+                    f,
+                    "\n  - your requirements say {} {specifiers}",
+                    self.name.as_given()
+                )?,
+                HoldReason::Capped {
+                    by,
+                    by_version,
+                    specifiers,
+                } => write!(
+                    f,
+                    "\n  - {by} {by_version} requires {} {specifiers}",
+                    self.name.as_given()
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Looks at a finished resolution, and for each package where we didn't get the newest
+// version, tries to figure out who's to blame. Overly-tight requires-python or
+// requires-dist caps can force the resolver way back in history, and it's nice to be
+// able to tell users about it so they can nag upstream.
+fn find_held_back(
+    state: &PubgrubState,
+    solution: &pubgrub::type_aliases::SelectedDependencies<ResPkg, Version>,
+) -> Result<Vec<HeldBack>> {
+    // every requirement that's in effect in the final solution, plus who asked for it
+    let mut active: Vec<(&ResPkg, &Version, &Requirement)> = Vec::new();
+    for (pkg, version) in solution.iter() {
+        let (reqs, extra): (Vec<&Requirement>, _) = match pkg {
+            ResPkg::Root => (
+                state.brief.requirements.iter().map(|r| &**r).collect(),
+                None,
+            ),
+            ResPkg::Package(name, extra) => (
+                state
+                    .metadata(&(name.clone(), version.clone()))?
+                    .requires_dist
+                    .iter()
+                    .map(|r| &**r)
+                    .collect(),
+                extra.as_ref(),
+            ),
+        };
+        for req in reqs {
+            if let Some(expr) = &req.env_marker_expr {
+                if !state.eval_marker(expr, extra)? {
+                    continue;
+                }
+            }
+            active.push((pkg, version, req));
+        }
+    }
+
+    let mut held_back = Vec::new();
+    for (pkg, chosen) in solution.iter() {
+        let name = match pkg {
+            // local trees only ever have the one version
+            ResPkg::Package(name, None) if !state.local_versions.contains_key(name) => {
+                name
+            }
+            _ => continue,
+        };
+        let all_versions = fetch_and_sort_versions(
+            state.db,
+            state.brief,
+            name,
+            PythonCheck::Unchecked,
+            &VersionHints::new(),
+        )?;
+        let newest = match all_versions.iter().max() {
+            Some(&newest) if newest > chosen => newest,
+            _ => continue,
+        };
+        let newest_compatible = state.versions(name)?.iter().max().copied();
+
+        let mut reasons = Vec::new();
+        if newest_compatible != Some(newest) {
+            let requires_python = state
+                .db
+                .artifacts_for_version(name, newest)?
+                .iter()
+                .find_map(|ai| ai.requires_python.clone())
+                .unwrap_or_default();
+            reasons.push(HoldReason::RequiresPython {
+                version: newest.clone(),
+                requires_python,
+            });
+        }
+        if let Some(target) = newest_compatible.filter(|&v| v > chosen) {
+            for (by, by_version, req) in &active {
+                if &req.name == name && !req.specifiers.satisfied_by(target)? {
+                    reasons.push(HoldReason::Capped {
+                        by: (*by).clone(),
+                        by_version: (*by_version).clone(),
+                        specifiers: req.specifiers.clone(),
+                    });
+                }
+            }
+        }
+        held_back.push(HeldBack {
+            name: name.clone(),
+            chosen: chosen.clone(),
+            newest: newest.clone(),
+            reasons,
+        });
+    }
+    held_back.sort_unstable_by(|a, b| a.name.normalized().cmp(b.name.normalized()));
+    Ok(held_back)
+}
+
 struct ExtraEnv<'a> {
     extra: Option<&'a str>,
 }
@@ -719,15 +2086,7 @@ impl<'a> PubgrubState<'a> {
     {
         for req in reqs {
             if let Some(expr) = &req.env_marker_expr {
-                let simplified =
-                    simplify_out_extra(expr, extra.map(|e| e.normalized()))?;
-                let value = simplified.eval(self.env)?;
-                if let Simplified::Expr(expr) = simplified {
-                    self.marker_exprs
-                        .borrow_mut()
-                        .insert(StandaloneMarkerExpr(expr), value);
-                }
-                if !value {
+                if !self.eval_marker(expr, extra)? {
                     continue;
                 }
             }
@@ -740,13 +2099,121 @@ impl<'a> PubgrubState<'a> {
 
             for maybe_extra in maybe_extras {
                 let pkg = ResPkg::Package(req.name.clone(), maybe_extra);
-                let range = specifiers_to_pubgrub(&req.specifiers)?;
+                let mut range = self.pubgrub_range(&req.specifiers)?;
+                // Constraints never show up as dependencies of their own; instead they
+                // get folded into every edge that points at the constrained package.
+                if let Some(constraint) = self.constraints.get(&req.name) {
+                    range = range.intersection(constraint);
+                }
                 trace!("adding dependency: {} {}", pkg, range);
                 dc.insert(pkg, range);
             }
         }
         Ok(())
     }
+
+    fn eval_marker(
+        &self,
+        expr: &marker::EnvMarkerExpr,
+        extra: Option<&Extra>,
+    ) -> Result<bool> {
+        let extra = extra.cloned();
+        if let Some(values) = self.marker_values.borrow().get(&extra) {
+            if let Some(&value) = values.get(expr) {
+                return Ok(value);
+            }
+        }
+        let simplified =
+            simplify_out_extra(expr, extra.as_ref().map(|e| e.normalized()))?;
+        let value = simplified.eval(self.env)?;
+        if let Simplified::Expr(expr) = simplified {
+            self.marker_exprs
+                .borrow_mut()
+                .insert(StandaloneMarkerExpr(expr), value);
+        }
+        self.marker_values
+            .borrow_mut()
+            .entry(extra)
+            .or_default()
+            .insert(expr.clone(), value);
+        Ok(value)
+    }
+
+    fn constraint_ranges(&self) -> Result<HashMap<PackageName, Range<Version>>> {
+        let mut constraints: HashMap<PackageName, Range<Version>> = HashMap::new();
+        for constraint in &self.brief.constraints {
+            context!("Applying constraint {}", constraint);
+            if let Some(expr) = &constraint.env_marker_expr {
+                if !self.eval_marker(expr, None)? {
+                    continue;
+                }
+            }
+            if !constraint.extras.is_empty() {
+                bail!("constraints can't have extras");
+            }
+            let range = self.pubgrub_range(&constraint.specifiers)?;
+            let entry = constraints
+                .entry(constraint.name.clone())
+                .or_insert_with(Range::any);
+            *entry = entry.intersection(&range);
+        }
+        Ok(constraints)
+    }
+
+    fn pubgrub_range(&self, specifiers: &Specifiers) -> Result<Range<Version>> {
+        let ranges = &self.db.resolve_memo.ranges;
+        if let Some(range) = ranges.borrow().get(specifiers) {
+            return Ok(range.clone());
+        }
+        let range = specifiers_to_pubgrub(specifiers)?;
+        ranges
+            .borrow_mut()
+            .insert(specifiers.clone(), range.clone());
+        Ok(range)
+    }
+}
+
+impl<'a> ExploreSource for PubgrubState<'a> {
+    fn candidates(&self, name: &PackageName) -> Result<Vec<Version>> {
+        Ok(self.versions(name)?.iter().map(|&v| v.clone()).collect())
+    }
+
+    fn release_metadata(
+        &self,
+        name: &PackageName,
+        version: &Version,
+    ) -> Result<WheelResolveMetadataInner> {
+        Ok(self.metadata(&(name.clone(), version.clone()))?.clone())
+    }
+
+    fn release_info(
+        &self,
+        name: &PackageName,
+        version: &Version,
+    ) -> Result<ReleaseInfo> {
+        // local trees get built for their metadata; not worth doing again just for this
+        if self.local_versions.contains_key(name) {
+            return Ok(Default::default());
+        }
+        // the resolver only keeps what it needs, but the db has the whole thing cached
+        // by now
+        let ais = self.metadata_candidates(&(name.clone(), version.clone()))?;
+        let (_, metadata) = self
+            .db
+            .get_metadata::<Wheel, _>(&ais, Some(self.wheel_builder))?;
+        Ok(ReleaseInfo {
+            license: metadata.license,
+            classifiers: metadata.classifiers,
+        })
+    }
+
+    fn marker_applies(
+        &self,
+        expr: &marker::EnvMarkerExpr,
+        extra: Option<&Extra>,
+    ) -> Result<bool> {
+        self.eval_marker(expr, extra)
+    }
 }
 
 fn specifiers_to_pubgrub(specs: &Specifiers) -> Result<Range<Version>> {
@@ -841,6 +2308,20 @@ impl<'a> pubgrub::solver::DependencyProvider<ResPkg, Version> for PubgrubState<'
                     &mut dc,
                     None,
                 )?;
+                for req in &self.brief.local_requirements {
+                    // unwrap is safe b/c resolve_wheels filled in every local version
+                    let version = &self.local_versions[&req.name];
+                    let mut maybe_extras: Vec<Option<Extra>> =
+                        req.extras.iter().map(|e| Some(e.clone())).collect();
+                    maybe_extras.push(None);
+                    for maybe_extra in maybe_extras {
+                        dc.insert(
+                            ResPkg::Package(req.name.clone(), maybe_extra),
+                            Range::exact(version.clone()),
+                        );
+                    }
+                }
+                self.prefetch_dependencies(&dc);
                 trace!("<---- dependencies complete");
                 Ok(Dependencies::Known(dc))
             }
@@ -869,11 +2350,18 @@ impl<'a> pubgrub::solver::DependencyProvider<ResPkg, Version> for PubgrubState<'
                     );
                 }
 
+                self.prefetch_dependencies(&dc);
                 trace!("<---- dependencies complete");
                 Ok(Dependencies::Known(dc))
             }
         }
     }
+
+    // pubgrub calls this between steps, so cancelling doesn't have to wait until we
+    // happen to need something from the db
+    fn should_cancel(&self) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(self.db.cancellation_token().check()?)
+    }
 }
 
 #[cfg(test)]
@@ -890,6 +2378,381 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_blueprint_identity() {
+        let hash = |c: &str| -> ArtifactHash {
+            format!("sha256={}", c.repeat(64)).parse().unwrap()
+        };
+        let pin =
+            |name: &str, version: &str, hashes: Vec<ArtifactHash>| PinnedPackage {
+                name: name.parse().unwrap(),
+                version: version.try_into().unwrap(),
+                hashes,
+                url: None,
+            };
+        let metadata = |provenance: &str| WheelResolveMetadata {
+            provenance: provenance.into(),
+            inner: WheelResolveMetadataInner {
+                requires_dist: vec![],
+                requires_python: Default::default(),
+                extras: HashSet::new(),
+            },
+        };
+        let blueprint = Blueprint {
+            pybi: pin("cpython", "3.11.2", vec![hash("0")]),
+            wheels: vec![
+                (
+                    pin("Trio", "0.22.0", vec![hash("1"), hash("2")]),
+                    metadata("https://a.example.com/trio.whl"),
+                ),
+                (pin("attrs", "22.1.0", vec![hash("3")]), metadata("x")),
+            ],
+            local: vec![],
+            marker_expressions: Default::default(),
+            python_fallback: None,
+            annotations: Default::default(),
+            claimed_publishers: Default::default(),
+            platform_tags: Default::default(),
+            source_builds: Default::default(),
+            warnings: Vec::new(),
+            policy: Default::default(),
+            python_flags: Vec::new(),
+        };
+        let id = blueprint.identity("manylinux_2_17_x86_64");
+
+        // order, spelling of names, and where metadata came from don't matter
+        let mut same = blueprint.clone();
+        same.wheels.reverse();
+        same.wheels[1].0 = pin("trio", "0.22.0", vec![hash("2"), hash("1")]);
+        same.wheels[1].1 = metadata("https://b.example.com/trio.whl");
+        assert_eq!(same.identity("manylinux_2_17_x86_64"), id);
+
+        // ...but the platform and the pins do
+        assert_ne!(blueprint.identity("macosx_11_0_arm64"), id);
+        let mut different = blueprint.clone();
+        different.wheels[1].0.version = "22.2.0".try_into().unwrap();
+        assert_ne!(different.identity("manylinux_2_17_x86_64"), id);
+        let mut different = blueprint;
+        different.pybi.hashes.push(hash("4"));
+        assert_ne!(different.identity("manylinux_2_17_x86_64"), id);
+    }
+
+    #[test]
+    fn test_python_fallbacks() {
+        let brief: Brief = serde_json::from_str(
+            r#"{"python": "cpython >= 3.12", "requirements": ["trio"]}"#,
+        )
+        .unwrap();
+        // older briefs don't have fallbacks or a strategy, and don't grow them when
+        // written back out
+        assert!(brief.python_fallbacks.is_empty());
+        assert_eq!(brief.strategy, ResolutionStrategy::Highest);
+        let json = serde_json::to_string(&brief).unwrap();
+        assert!(!json.contains("python_fallbacks"));
+        assert!(!json.contains("strategy"));
+        let (result, python) = brief
+            .with_python_fallbacks(|python| Ok(python.to_string()))
+            .unwrap();
+        assert_eq!(result, "cpython >= 3.12");
+        assert_eq!(python, &brief.python);
+
+        let brief = Brief {
+            python_fallbacks: vec![
+                "cpython >= 3.11".parse().unwrap(),
+                "cpython >= 3.10".parse().unwrap(),
+            ],
+            ..brief
+        };
+        let mut tried = Vec::new();
+        let (_, python) = brief
+            .with_python_fallbacks(|python| {
+                tried.push(python.to_string());
+                if python.to_string() == "cpython >= 3.12" {
+                    Err(PosyError::NoPybiFound)?
+                } else {
+                    Ok(())
+                }
+            })
+            .unwrap();
+        assert_eq!(python.to_string(), "cpython >= 3.11");
+        assert_eq!(tried, vec!["cpython >= 3.12", "cpython >= 3.11"]);
+
+        // if nothing works, we get the last error
+        let err = brief
+            .with_python_fallbacks(|python| -> Result<()> {
+                Err(PosyError::NoPybiFound).wrap_err(python.to_string())
+            })
+            .unwrap_err();
+        assert_eq!(err.to_string(), "cpython >= 3.10");
+
+        // but other errors don't get a second try
+        let mut tried = 0;
+        let err = brief
+            .with_python_fallbacks(|_| -> Result<()> {
+                tried += 1;
+                Err(PosyError::Interrupted)?
+            })
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PosyError>(),
+            Some(PosyError::Interrupted)
+        ));
+        assert_eq!(tried, 1);
+    }
+
+    #[test]
+    fn test_resolver_policy() {
+        let blueprint = Blueprint {
+            pybi: PinnedPackage {
+                name: "cpython".parse().unwrap(),
+                version: "3.11.2".try_into().unwrap(),
+                hashes: vec![],
+                url: None,
+            },
+            wheels: vec![],
+            local: vec![],
+            marker_expressions: Default::default(),
+            python_fallback: None,
+            annotations: Default::default(),
+            claimed_publishers: Default::default(),
+            platform_tags: Default::default(),
+            source_builds: Default::default(),
+            warnings: Vec::new(),
+            policy: ResolverPolicy::current(),
+            python_flags: Vec::new(),
+        };
+        let mut json = serde_json::to_value(&blueprint).unwrap();
+        assert_eq!(
+            json["policy"]["revision"],
+            serde_json::json!(RESOLVER_POLICY_REVISION)
+        );
+        // blueprints from before we recorded policies get the old behavior
+        json.as_object_mut().unwrap().remove("policy");
+        let old: Blueprint = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(old.policy, ResolverPolicy::default());
+        assert_eq!(old.policy.revision, 0);
+        // and so do partial policies, for whatever they leave out
+        json["policy"] = serde_json::json!({"revision": 7, "hint_order": "nearest"});
+        let newer: Blueprint = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            newer.policy,
+            ResolverPolicy {
+                revision: 7,
+                hint_order: HintOrder::Nearest,
+                ..Default::default()
+            }
+        );
+        assert_eq!(VersionHints::from(&newer).policy, newer.policy);
+        assert_eq!(VersionHints::new().policy, ResolverPolicy::current());
+    }
+
+    #[test]
+    fn test_held_at() {
+        let brief = Brief {
+            python: "cpython >= 3.10".parse().unwrap(),
+            python_fallbacks: vec!["cpython >= 3.9".parse().unwrap()],
+            requirements: vec!["trio".parse().unwrap(), "numpy".parse().unwrap()],
+            allow_pre: Default::default(),
+            strategy: Default::default(),
+            keep_pinned_prereleases: false,
+            constraints: vec!["attrs < 30".parse().unwrap()],
+            local_requirements: vec![],
+            no_source_builds: false,
+            python_flags: Vec::new(),
+        };
+        let pin = |name: &str, version: &str| PinnedPackage {
+            name: name.parse().unwrap(),
+            version: version.try_into().unwrap(),
+            hashes: vec![],
+            url: None,
+        };
+        let metadata = WheelResolveMetadata {
+            provenance: "test".into(),
+            inner: WheelResolveMetadataInner {
+                requires_dist: vec![],
+                requires_python: Default::default(),
+                extras: Default::default(),
+            },
+        };
+        let old = Blueprint {
+            pybi: pin("cpython", "3.10.8"),
+            wheels: vec![
+                (pin("trio", "0.22.0"), metadata.clone()),
+                (pin("attrs", "22.2.0"), metadata.clone()),
+                (pin("Sniff_IO", "1.3.0"), metadata),
+            ],
+            local: vec![],
+            marker_expressions: Default::default(),
+            python_fallback: None,
+            annotations: Default::default(),
+            claimed_publishers: Default::default(),
+            platform_tags: Default::default(),
+            source_builds: Default::default(),
+            warnings: Vec::new(),
+            policy: Default::default(),
+            python_flags: Vec::new(),
+        };
+        let strings = |reqs: &[UserRequirement]| {
+            reqs.iter().map(|r| r.to_string()).collect::<Vec<_>>()
+        };
+
+        let held = brief.held_at(&old, &["trio".parse().unwrap()]).unwrap();
+        assert_eq!(held.python.to_string(), "cpython == 3.10.8");
+        assert!(held.python_fallbacks.is_empty());
+        // requirements are unchanged; holding is done with constraints, so nothing
+        // gets installed just because it used to be
+        assert_eq!(held.requirements, brief.requirements);
+        assert_eq!(
+            strings(&held.constraints),
+            vec!["attrs < 30", "attrs == 22.2.0", "Sniff_IO == 1.3.0"]
+        );
+
+        // names are compared normalized
+        let held = brief
+            .held_at(
+                &old,
+                &["CPython".parse().unwrap(), "sniff-io".parse().unwrap()],
+            )
+            .unwrap();
+        assert_eq!(held.python, brief.python);
+        assert_eq!(held.python_fallbacks, brief.python_fallbacks);
+        assert_eq!(
+            strings(&held.constraints),
+            vec!["attrs < 30", "trio == 0.22.0", "attrs == 22.2.0"]
+        );
+
+        // resolve_upgrade's hints: no opinion on the targets
+        let mut hints = VersionHints::from(&old);
+        hints.forget(&["TRIO".parse().unwrap(), "cpython".parse().unwrap()]);
+        let mut hinted = hints
+            .pins
+            .keys()
+            .map(|name| name.normalized().to_string())
+            .collect::<Vec<_>>();
+        hinted.sort();
+        assert_eq!(hinted, vec!["attrs", "sniff-io"]);
+    }
+
+    #[test]
+    fn test_held_back_display() {
+        let held_back = HeldBack {
+            name: "Foo".parse().unwrap(),
+            chosen: "1.0".parse().unwrap(),
+            newest: "3.0".parse().unwrap(),
+            reasons: vec![
+                HoldReason::RequiresPython {
+                    version: "3.0".parse().unwrap(),
+                    requires_python: ">= 3.12".into(),
+                },
+                HoldReason::Capped {
+                    by: ResPkg::Package("bar".parse().unwrap(), None),
+                    by_version: "0.5".parse().unwrap(),
+                    specifiers: "< 2".parse().unwrap(),
+                },
+                HoldReason::Capped {
+                    by: ResPkg::Root,
+                    by_version: ROOT_VERSION.clone(),
+                    specifiers: "== 1.*".parse().unwrap(),
+                },
+            ],
+        };
+        insta::assert_snapshot!(held_back.to_string(), @r###"
+        using Foo 1.0, even though 3.0 is available
+          - Foo 3.0 has requires-python >= 3.12
+          - bar 0.5 requires Foo < 2
+          - your requirements say Foo == 1.*
+        "###);
+    }
+
+    #[test]
+    fn test_find_held_back() {
+        use crate::test_util::{index_page as page, with_index_db};
+
+        // (filename, data-requires-python, requires-dist)
+        let wheels: &[(&str, Option<&str>, &[&str])] = &[
+            ("foo-1.0-py3-none-any.whl", None, &[]),
+            ("foo-2.0-py3-none-any.whl", Some(">= 3.12"), &[]),
+            ("bar-1.0-py3-none-any.whl", None, &[]),
+            ("bar-2.0-py3-none-any.whl", None, &[]),
+            ("baz-1.0-py3-none-any.whl", None, &["bar < 2"]),
+        ];
+        let mut pages: HashMap<&str, Vec<String>> = HashMap::new();
+        let (mut paths, mut responses) = (Vec::new(), Vec::new());
+        for (filename, requires_python, requires_dist) in wheels {
+            let (name, rest) = filename.split_once('-').unwrap();
+            let version = rest.split_once('-').unwrap().0;
+            let mut link = format!(r#"href="{filename}" data-core-metadata="true""#);
+            if let Some(requires_python) = requires_python {
+                link.push_str(&format!(r#" data-requires-python="{requires_python}""#));
+            }
+            pages.entry(name).or_default().push(link);
+            let mut metadata =
+                format!("Metadata-Version: 2.1\nName: {name}\nVersion: {version}\n");
+            for req in *requires_dist {
+                metadata.push_str(&format!("Requires-Dist: {req}\n"));
+            }
+            let response = http::Response::builder()
+                .body(metadata.into_bytes())
+                .unwrap();
+            paths.push(format!("/simple/{name}/{filename}.metadata"));
+            responses.push(response);
+        }
+        for (name, links) in &pages {
+            let links = links.iter().map(|l| l.as_str()).collect::<Vec<_>>();
+            paths.push(format!("/simple/{name}/"));
+            responses.push(page(&links));
+        }
+        let routes = paths.iter().map(|p| p.as_str()).zip(responses).collect();
+        with_index_db(routes, |db| {
+            let brief: Brief = serde_json::from_str(
+                r#"{"python": "cpython", "requirements": ["foo", "bar", "baz"]}"#,
+            )
+            .unwrap();
+            let env = HashMap::from([(
+                "python_full_version".to_string(),
+                "3.11.0".to_string(),
+            )]);
+            let python = "cpython".parse().unwrap();
+            let python_version = "3.11.0".try_into().unwrap();
+            let platform = PybiPlatform::new("manylinux_2_17_x86_64");
+            let platforms = [&platform];
+            let builder =
+                WheelBuilder::new(db, &python, &python_version, &platforms, &[])
+                    .unwrap();
+            let hints = VersionHints::new();
+            let held_back = with_state(
+                db,
+                &brief,
+                &env,
+                &hints,
+                &builder,
+                AbiVariant::Default,
+                |state| {
+                    let solution = pubgrub::solver::resolve(
+                        state,
+                        ResPkg::Root,
+                        ROOT_VERSION.clone(),
+                    )
+                    .map_err(|err| eyre!("{err:?}"))?;
+                    find_held_back(state, &solution)
+                },
+            )
+            .unwrap();
+            let held_back = held_back
+                .iter()
+                .map(|held_back| held_back.to_string())
+                .collect::<Vec<_>>()
+                .join("\n");
+            // bar 2.0 is excluded by baz's cap, foo 2.0 by its requires-python
+            insta::assert_snapshot!(held_back, @r###"
+            using bar 1.0, even though 2.0 is available
+              - baz 1.0 requires bar < 2
+            using foo 1.0, even though 2.0 is available
+              - foo 2.0 has requires-python >= 3.12
+            "###);
+        });
+    }
+
     #[test]
     fn test_marker_simplify() {
         fn doit(req: &str, extra: Option<&str>) -> String {
@@ -935,4 +2798,232 @@ mod test {
             simplify_out_extra(req.env_marker_expr.as_ref().unwrap(), None).is_err()
         );
     }
+
+    #[test]
+    fn test_requires_python_check() {
+        let check = RequiresPythonCheck::new("3.10.4".parse().unwrap());
+        for _ in 0..2 {
+            assert!(check.satisfied_by(">= 3.7").unwrap());
+            assert!(!check.satisfied_by(">= 3.11").unwrap());
+            assert!(check.satisfied_by(">= 3, != 3.10.1").unwrap());
+        }
+        assert_eq!(check.seen.borrow().len(), 3);
+        assert!(check.satisfied_by(">= three").is_err());
+    }
+
+    #[test]
+    fn test_pinned_sdist() {
+        let hash = |n: u8| -> ArtifactHash {
+            format!("sha256={}", format!("{n:02x}").repeat(32))
+                .parse()
+                .unwrap()
+        };
+        let ai = |filename: &str, hash: Option<ArtifactHash>| ArtifactInfo {
+            name: filename.try_into().unwrap(),
+            url: Url::parse("https://example.com/files/")
+                .unwrap()
+                .join(filename)
+                .unwrap(),
+            hash,
+            requires_python: None,
+            dist_info_metadata: Default::default(),
+            yanked: Default::default(),
+            size: None,
+            provenance: None,
+        };
+        let pin = PinnedPackage {
+            name: "foo".parse().unwrap(),
+            version: "1.0".try_into().unwrap(),
+            hashes: vec![hash(1), hash(2)],
+            url: None,
+        };
+        let artifacts = vec![
+            ai("foo-1.0-py3-none-any.whl", Some(hash(1))),
+            // same name, but not the file we locked
+            ai("foo-1.0.tar.gz", Some(hash(3))),
+            ai("foo-1.0.zip", None),
+            ai("foo-1.0.tar.gz", Some(hash(2))),
+        ];
+        let sdist = pin.pinned_sdist(&artifacts).unwrap();
+        assert_eq!(sdist.hash, Some(hash(2)));
+        assert!(pin.pinned_sdist(&artifacts[..3]).is_none());
+
+        // what we'd install: the locked wheel if there's one for this platform, even if
+        // there's a better one that isn't locked, or else the sdist
+        let platform = WheelPlatform::pure_python(&"3.11".try_into().unwrap()).unwrap();
+        let mut artifacts = artifacts;
+        artifacts.push(ai("foo-1.0-py311-none-any.whl", Some(hash(3))));
+        let selected = |artifacts| selected_artifact(&platform, &pin, artifacts);
+        assert_eq!(selected(&artifacts).unwrap().hash, Some(hash(1)));
+        assert_eq!(selected(&artifacts[1..]).unwrap().hash, Some(hash(2)));
+        assert!(selected(&artifacts[1..3]).is_none());
+    }
+
+    #[test]
+    fn test_check_has_files() {
+        use crate::test_util::{index_page as page, with_index_db};
+
+        let fine = r#"href="fine-1.0-py3-none-any.whl""#;
+        let yanked = r#"href="gone-1.0-py3-none-any.whl" data-yanked="oops""#;
+        let missing = http::Response::builder()
+            .status(404)
+            .body(Vec::new())
+            .unwrap();
+        let routes = vec![
+            ("/simple/empty/", page(&[])),
+            ("/simple/gone/", page(&[yanked])),
+            ("/simple/missing/", missing),
+            ("/simple/fine/", page(&[fine])),
+        ];
+        with_index_db(routes, |db| {
+            let brief: Brief =
+                serde_json::from_str(r#"{"python": "cpython", "requirements": []}"#)
+                    .unwrap();
+            let env = HashMap::from([(
+                "python_full_version".to_string(),
+                "3.11.0".to_string(),
+            )]);
+            let python = "cpython".parse().unwrap();
+            let python_version = "3.11.0".try_into().unwrap();
+            let platform = PybiPlatform::new("manylinux_2_17_x86_64");
+            let platforms = [&platform];
+            let builder =
+                WheelBuilder::new(db, &python, &python_version, &platforms, &[])
+                    .unwrap();
+            let hints = VersionHints::new();
+            with_state(
+                db,
+                &brief,
+                &env,
+                &hints,
+                &builder,
+                AbiVariant::Default,
+                |state| {
+                    let check = |name: &str| state.check_has_files(&name.parse()?);
+                    let reason = |name: &str| match check(name)
+                        .unwrap_err()
+                        .downcast_ref::<PosyError>()
+                    {
+                        Some(PosyError::NoInstallableFiles { reason, .. }) => {
+                            reason.clone()
+                        }
+                        other => panic!("unexpected error: {other:?}"),
+                    };
+                    assert_eq!(reason("empty"), "it doesn't have any files");
+                    assert_eq!(reason("gone"), "all of its files have been yanked");
+                    assert!(matches!(
+                        check("missing").unwrap_err().downcast_ref::<PosyError>(),
+                        Some(PosyError::PackageNotFound { .. })
+                    ));
+                    check("fine")?;
+                    Ok(())
+                },
+            )
+            .unwrap();
+        });
+    }
+
+    #[test]
+    fn test_pybi_version_filter() {
+        use crate::test_util::{index_page as page, with_index_db};
+
+        let pybis = [
+            r#"href="cpython-3.10.9-manylinux_2_17_x86_64.pybi""#,
+            r#"href="cpython-3.11.1-manylinux_2_17_x86_64.pybi""#,
+            // a pybi's requires-python is about its own version, so this can never
+            // be used
+            r#"href="cpython-3.11.2-manylinux_2_17_x86_64.pybi" data-requires-python=">= 3.12""#,
+            r#"href="cpython-3.11.3-manylinux_2_17_x86_64.pybi" data-requires-python=">= 3.11""#,
+            r#"href="cpython-3.12.0a1-manylinux_2_17_x86_64.pybi""#,
+        ];
+        with_index_db(vec![("/simple/cpython/", page(&pybis))], |db| {
+            let brief: Brief = serde_json::from_str(
+                r#"{"python": "cpython >= 3.11", "requirements": []}"#,
+            )
+            .unwrap();
+            let name = "cpython".parse().unwrap();
+            let specifiers = brief.python.specifiers.clone();
+            let versions = fetch_and_sort_versions(
+                db,
+                &brief,
+                &name,
+                PythonCheck::Pybi(&specifiers),
+                &VersionHints::new(),
+            )
+            .unwrap()
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>();
+            assert_eq!(versions, vec!["3.11.3", "3.11.1"]);
+        });
+    }
+
+    #[test]
+    fn test_policy_version_order() {
+        use crate::test_util::{index_page as page, with_index_db};
+
+        let wheels = |name: &str, versions: &[&str]| {
+            let links = versions
+                .iter()
+                .map(|v| format!(r#"href="{name}-{v}-py3-none-any.whl""#))
+                .collect::<Vec<_>>();
+            page(&links.iter().map(|l| l.as_str()).collect::<Vec<_>>())
+        };
+        let routes = vec![
+            ("/simple/foo/", wheels("foo", &["1.0", "1.1", "1.2", "1.3"])),
+            ("/simple/bar/", wheels("bar", &["2.0a1", "2.0b1"])),
+        ];
+        with_index_db(routes, |db| {
+            let brief: Brief = serde_json::from_str(
+                r#"{"python": "cpython >= 3.11", "requirements": []}"#,
+            )
+            .unwrap();
+            let hinted = PinnedPackage {
+                name: "foo".parse().unwrap(),
+                version: "1.1".try_into().unwrap(),
+                hashes: vec![],
+                url: None,
+            };
+            let versions = |package: &str, policy: ResolverPolicy| {
+                let mut hints = VersionHints::with_policy(policy);
+                hints.add_pinned(&hinted);
+                fetch_and_sort_versions(
+                    db,
+                    &brief,
+                    &package.parse().unwrap(),
+                    PythonCheck::Unchecked,
+                    &hints,
+                )
+                .unwrap()
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+            };
+
+            // after the hint, the brief's strategy says highest first...
+            let strategy = ResolverPolicy {
+                hint_order: HintOrder::Strategy,
+                ..Default::default()
+            };
+            assert_eq!(versions("foo", strategy), vec!["1.1", "1.3", "1.2", "1.0"]);
+            // ...but Nearest tries the smallest step up first, and then steps down
+            let nearest = ResolverPolicy {
+                hint_order: HintOrder::Nearest,
+                ..Default::default()
+            };
+            assert_eq!(versions("foo", nearest), vec!["1.1", "1.2", "1.3", "1.0"]);
+
+            // a package with nothing but pre-releases
+            let fallback = ResolverPolicy {
+                prerelease_fallback: true,
+                ..Default::default()
+            };
+            assert_eq!(versions("bar", fallback), vec!["2.0b1", "2.0a1"]);
+            let no_fallback = ResolverPolicy {
+                prerelease_fallback: false,
+                ..Default::default()
+            };
+            assert!(versions("bar", no_fallback).is_empty());
+        });
+    }
 }
@@ -0,0 +1,417 @@
+use pubgrub::range::Range;
+use pubgrub::report::{DefaultStringReporter, DerivationTree, External, Reporter};
+use std::collections::VecDeque;
+
+use crate::prelude::*;
+use crate::resolve::ResPkg;
+
+// What we say when the resolver can't find a solution. Pubgrub hands us a derivation
+// tree, which is a proof that there's no solution: correct, thorough, and about as
+// readable as proofs usually are. What people actually want to know is which of
+// *their* requirements are fighting, through which chains of dependencies, and what
+// was wrong with the versions that would have worked. So we flatten the tree down to
+// the facts at its leaves, find the dead ends, and trace each one back to the root.
+// Pubgrub's own write-up is still there at the end, for the hard cases.
+//
+// All of this is Serialize, so tools can get it as JSON ('--error-format json')
+// instead of scraping our error messages.
+
+// Versions are listed newest first, so this mostly means "the ones you were hoping for"
+const MAX_EXCLUDED_SHOWN: usize = 5;
+
+/// One thing the resolver learned, straight from the leaves of the derivation tree.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Fact {
+    /// `package` (at `versions`) depends on `dependency` at `dependency_versions`.
+    Depends {
+        package: String,
+        versions: String,
+        dependency: String,
+        dependency_versions: String,
+    },
+    /// There's nothing we can use for `package` in `versions`.
+    NoVersions { package: String, versions: String },
+    /// We couldn't find out what `package` at `versions` depends on.
+    UnavailableDependencies { package: String, versions: String },
+}
+
+/// A version that exists on the index, but that we couldn't use.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ExcludedVersion {
+    pub version: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Problem {
+    /// The requirements on the package don't have any versions in common.
+    Incompatible,
+    /// No usable version matches. `excluded` are the ones that matched, but that we
+    /// couldn't use (if it's empty, then nothing matched at all).
+    NoVersions {
+        versions: String,
+        excluded: Vec<ExcludedVersion>,
+    },
+    UnavailableDependencies {
+        versions: String,
+    },
+}
+
+/// A package we got stuck on, and how we got there.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Conflict {
+    pub package: String,
+    /// Each path from the top-level requirements to this package, as a list of
+    /// "PACKAGE VERSIONS" steps: e.g. ["trio >=0.22", "outcome >=2"] means we
+    /// required some trio >=0.22, which required outcome >=2.
+    pub chains: Vec<Vec<String>>,
+    pub problem: Problem,
+}
+
+/// Why resolution failed.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NoSolution {
+    pub conflicts: Vec<Conflict>,
+    pub facts: Vec<Fact>,
+    /// Pubgrub's step-by-step explanation.
+    pub explanation: String,
+}
+
+fn collect_facts(tree: &DerivationTree<ResPkg, Version>, facts: &mut Vec<Fact>) {
+    let fact = match tree {
+        DerivationTree::Derived(derived) => {
+            collect_facts(&derived.cause1, facts);
+            collect_facts(&derived.cause2, facts);
+            return;
+        }
+        DerivationTree::External(External::NotRoot(..)) => return,
+        DerivationTree::External(External::FromDependencyOf(p1, r1, p2, r2)) => {
+            Fact::Depends {
+                package: p1.to_string(),
+                versions: r1.to_string(),
+                dependency: p2.to_string(),
+                dependency_versions: r2.to_string(),
+            }
+        }
+        DerivationTree::External(External::NoVersions(p, r)) => Fact::NoVersions {
+            package: p.to_string(),
+            versions: r.to_string(),
+        },
+        DerivationTree::External(External::UnavailableDependencies(p, r)) => {
+            Fact::UnavailableDependencies {
+                package: p.to_string(),
+                versions: r.to_string(),
+            }
+        }
+    };
+    // subtrees with a shared_id show up more than once
+    if !facts.contains(&fact) {
+        facts.push(fact);
+    }
+}
+
+// Everything in the tree that points at `package`, as (parent, "PACKAGE VERSIONS").
+fn dependents<'a>(facts: &'a [Fact], package: &'a str) -> Vec<(&'a str, String)> {
+    facts
+        .iter()
+        .filter_map(|fact| match fact {
+            Fact::Depends {
+                package: parent,
+                dependency,
+                dependency_versions,
+                ..
+            } if dependency == package => Some((
+                parent.as_str(),
+                format!("{dependency} {dependency_versions}"),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+// The shortest chain of steps from the root down to `package`.
+fn path_from_root<'a>(facts: &'a [Fact], package: &'a str) -> Vec<String> {
+    let root = ResPkg::Root.to_string();
+    // BFS backwards, up towards the root. `next` maps each package we reach to the
+    // step that leads back down towards `package`.
+    let mut next: HashMap<&str, (&str, String)> = HashMap::new();
+    let mut queue = VecDeque::from([package]);
+    while let Some(current) = queue.pop_front() {
+        if current == root {
+            let mut path = Vec::new();
+            let mut at = current;
+            while at != package {
+                let (child, step) = &next[at];
+                path.push(step.clone());
+                at = *child;
+            }
+            return path;
+        }
+        for (parent, step) in dependents(facts, current) {
+            if parent != package && !next.contains_key(parent) {
+                next.insert(parent, (current, step));
+                queue.push_back(parent);
+            }
+        }
+    }
+    // shouldn't happen, but if it does, the chain just starts from the middle
+    Vec::new()
+}
+
+// One chain per package that depends directly on `package`.
+fn chains_to(facts: &[Fact], package: &str) -> Vec<Vec<String>> {
+    dependents(facts, package)
+        .into_iter()
+        .map(|(parent, step)| {
+            let mut chain = path_from_root(facts, parent);
+            chain.push(step);
+            chain
+        })
+        .collect()
+}
+
+impl NoSolution {
+    /// `excluded` lists the versions of a package in a range that exist, but that we
+    /// couldn't use, and why.
+    pub fn new<F>(mut tree: DerivationTree<ResPkg, Version>, excluded: F) -> NoSolution
+    where
+        F: Fn(&ResPkg, &Range<Version>) -> Vec<ExcludedVersion>,
+    {
+        let mut facts = Vec::new();
+        collect_facts(&tree, &mut facts);
+        let mut conflicts = Vec::new();
+        let mut seen = HashSet::new();
+
+        // dead ends first
+        fn leaves(
+            tree: &DerivationTree<ResPkg, Version>,
+            out: &mut Vec<External<ResPkg, Version>>,
+        ) {
+            match tree {
+                DerivationTree::Derived(derived) => {
+                    leaves(&derived.cause1, out);
+                    leaves(&derived.cause2, out);
+                }
+                DerivationTree::External(external) => out.push(external.clone()),
+            }
+        }
+        let mut externals = Vec::new();
+        leaves(&tree, &mut externals);
+        for external in &externals {
+            let (package, problem) = match external {
+                External::NoVersions(p, r) => (
+                    p.to_string(),
+                    Problem::NoVersions {
+                        versions: r.to_string(),
+                        excluded: excluded(p, r),
+                    },
+                ),
+                External::UnavailableDependencies(p, r) => (
+                    p.to_string(),
+                    Problem::UnavailableDependencies {
+                        versions: r.to_string(),
+                    },
+                ),
+                _ => continue,
+            };
+            if seen.insert(package.clone()) {
+                conflicts.push(Conflict {
+                    chains: chains_to(&facts, &package),
+                    package,
+                    problem,
+                });
+            }
+        }
+
+        // then packages where the requirements on them just don't overlap
+        let mut required: Vec<(&ResPkg, Range<Version>)> = Vec::new();
+        for external in &externals {
+            if let External::FromDependencyOf(_, _, p, r) = external {
+                match required.iter_mut().find(|(q, _)| q == &p) {
+                    Some((_, range)) => *range = range.intersection(r),
+                    None => required.push((p, r.clone())),
+                }
+            }
+        }
+        for (p, range) in required {
+            let package = p.to_string();
+            if range == Range::none() && seen.insert(package.clone()) {
+                conflicts.push(Conflict {
+                    chains: chains_to(&facts, &package),
+                    package,
+                    problem: Problem::Incompatible,
+                });
+            }
+        }
+
+        tree.collapse_no_versions();
+        NoSolution {
+            conflicts,
+            facts,
+            explanation: DefaultStringReporter::report(&tree),
+        }
+    }
+}
+
+impl std::error::Error for NoSolution {}
+
+impl Display for NoSolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "couldn't find versions that satisfy all the requirements"
+        )?;
+        for conflict in &self.conflicts {
+            let package = &conflict.package;
+            match &conflict.problem {
+                Problem::Incompatible => {
+                    write!(f, "\n  - conflicting requirements on {package}:")?;
+                }
+                Problem::NoVersions { versions, excluded } if excluded.is_empty() => {
+                    write!(
+                        f,
+                        "\n  - nothing matches {package} {versions}, needed by:"
+                    )?;
+                }
+                Problem::NoVersions { versions, excluded } => {
+                    write!(f, "\n  - no usable {package} {versions}, needed by:")?;
+                    for ex in excluded.iter().take(MAX_EXCLUDED_SHOWN) {
+                        write!(f, "\n      ({package} {} {})", ex.version, ex.reason)?;
+                    }
+                    if excluded.len() > MAX_EXCLUDED_SHOWN {
+                        let more = excluded.len() - MAX_EXCLUDED_SHOWN;
+                        write!(f, "\n      (...and {more} more)")?;
+                    }
+                }
+                Problem::UnavailableDependencies { versions } => {
+                    write!(
+                        f,
+                        "\n  - couldn't get the dependencies of {package} {versions}, \
+                         needed by:"
+                    )?;
+                }
+            }
+            for chain in &conflict.chains {
+                write!(f, "\n      your requirements -> {}", chain.join(" -> "))?;
+            }
+        }
+        write!(f, "\n\nIn detail:")?;
+        for line in self.explanation.lines() {
+            write!(f, "\n  {line}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pubgrub::report::Derived;
+    use pubgrub::type_aliases::Map;
+
+    fn pkg(name: &str) -> ResPkg {
+        ResPkg::Package(name.parse().unwrap(), None)
+    }
+
+    fn v(s: &str) -> Version {
+        s.try_into().unwrap()
+    }
+
+    fn depends(
+        p1: ResPkg,
+        r1: Range<Version>,
+        p2: &str,
+        r2: Range<Version>,
+    ) -> DerivationTree<ResPkg, Version> {
+        DerivationTree::External(External::FromDependencyOf(p1, r1, pkg(p2), r2))
+    }
+
+    fn derived(
+        cause1: DerivationTree<ResPkg, Version>,
+        cause2: DerivationTree<ResPkg, Version>,
+    ) -> DerivationTree<ResPkg, Version> {
+        DerivationTree::Derived(Derived {
+            terms: Map::default(),
+            shared_id: None,
+            cause1: Box::new(cause1),
+            cause2: Box::new(cause2),
+        })
+    }
+
+    #[test]
+    fn test_no_solution() {
+        let root = || Range::exact(v("0"));
+        // root -> a >= 1 -> c < 2, and root -> b -> c >= 2
+        let tree = derived(
+            derived(
+                depends(ResPkg::Root, root(), "a", Range::higher_than(v("1"))),
+                depends(
+                    pkg("a"),
+                    Range::higher_than(v("1")),
+                    "c",
+                    Range::strictly_lower_than(v("2")),
+                ),
+            ),
+            derived(
+                depends(ResPkg::Root, root(), "b", Range::any()),
+                depends(pkg("b"), Range::any(), "c", Range::higher_than(v("2"))),
+            ),
+        );
+        let report = NoSolution::new(tree, |_, _| panic!("no NoVersions here"));
+        assert_eq!(report.facts.len(), 4);
+        assert_eq!(report.conflicts.len(), 1);
+        let conflict = &report.conflicts[0];
+        assert_eq!(conflict.package, "c");
+        assert_eq!(conflict.problem, Problem::Incompatible);
+        assert_eq!(conflict.chains.len(), 2);
+        assert_eq!(conflict.chains[0].len(), 2);
+        assert!(conflict.chains[0][0].starts_with("a "));
+        assert!(conflict.chains[1][0].starts_with("b "));
+        let shown = report.to_string();
+        assert!(shown.contains("conflicting requirements on c:"));
+        assert!(shown.contains("\n\nIn detail:\n  "));
+
+        // root -> trio -> outcome >= 2, but the only outcome 2 is yanked
+        let tree = derived(
+            derived(
+                depends(ResPkg::Root, root(), "trio", Range::any()),
+                depends(
+                    pkg("trio"),
+                    Range::any(),
+                    "outcome",
+                    Range::higher_than(v("2")),
+                ),
+            ),
+            DerivationTree::External(External::NoVersions(
+                pkg("outcome"),
+                Range::higher_than(v("2")),
+            )),
+        );
+        let report = NoSolution::new(tree, |p, range| {
+            assert_eq!(p, &pkg("outcome"));
+            assert!(range.contains(&v("2.0")));
+            vec![ExcludedVersion {
+                version: "2.0".into(),
+                reason: "was yanked".into(),
+            }]
+        });
+        assert_eq!(report.conflicts.len(), 1);
+        let conflict = &report.conflicts[0];
+        assert_eq!(conflict.package, "outcome");
+        assert_eq!(conflict.chains.len(), 1);
+        assert_eq!(conflict.chains[0].len(), 2);
+        assert!(report.to_string().contains("(outcome 2.0 was yanked)"));
+
+        let json = serde_json::to_value(&report).unwrap();
+        let problem = &json["conflicts"][0]["problem"];
+        assert_eq!(problem["kind"], "no-versions");
+        assert_eq!(problem["excluded"][0]["reason"], "was yanked");
+        assert_eq!(json["facts"][2]["kind"], "no-versions");
+        assert!(json["explanation"].is_string());
+    }
+}
@@ -0,0 +1,198 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Counters for the summary at the end of a command (see output::stats_summary), so
+// users can see how much of what we did came out of the cache. They're global, like
+// the warnings in output.rs, because the things they count happen all over the place,
+// including on prefetch threads.
+
+static COUNTERS: Counters = Counters::new();
+
+struct Counters {
+    metadata_lookups: AtomicU64,
+    metadata_cached: AtomicU64,
+    bytes_downloaded: AtomicU64,
+    wheels_built: AtomicU64,
+    phases: Mutex<Vec<Phase>>,
+}
+
+struct Phase {
+    name: &'static str,
+    total: Duration,
+}
+
+// The timers running on this thread, innermost last, and when each one last started
+// getting the credit (see time_phase)
+thread_local! {
+    static RUNNING: RefCell<Vec<(&'static Counters, &'static str, Instant)>> =
+        RefCell::new(Vec::new());
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    // times we needed some release's metadata, and how many of those we had locally
+    pub metadata_lookups: u64,
+    pub metadata_cached: u64,
+    pub bytes_downloaded: u64,
+    pub wheels_built: u64,
+    // in the order they first started; each one's time doesn't include the other
+    // phases that ran inside it
+    pub phases: Vec<(&'static str, Duration)>,
+}
+
+impl Counters {
+    const fn new() -> Counters {
+        Counters {
+            metadata_lookups: AtomicU64::new(0),
+            metadata_cached: AtomicU64::new(0),
+            bytes_downloaded: AtomicU64::new(0),
+            wheels_built: AtomicU64::new(0),
+            phases: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn metadata_lookup(&self, cached: bool) {
+        self.metadata_lookups.fetch_add(1, Ordering::Relaxed);
+        if cached {
+            self.metadata_cached.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn take(&self) -> Stats {
+        let phases = std::mem::take(&mut *self.phases.lock().unwrap());
+        Stats {
+            metadata_lookups: self.metadata_lookups.swap(0, Ordering::Relaxed),
+            metadata_cached: self.metadata_cached.swap(0, Ordering::Relaxed),
+            bytes_downloaded: self.bytes_downloaded.swap(0, Ordering::Relaxed),
+            wheels_built: self.wheels_built.swap(0, Ordering::Relaxed),
+            phases: phases
+                .into_iter()
+                .map(|phase| (phase.name, phase.total))
+                .collect(),
+        }
+    }
+
+    fn time_phase(&'static self, name: &'static str) -> PhaseTimer {
+        {
+            let mut phases = self.phases.lock().unwrap();
+            if !phases.iter().any(|phase| phase.name == name) {
+                phases.push(Phase {
+                    name,
+                    total: Duration::ZERO,
+                });
+            }
+        }
+        let now = Instant::now();
+        RUNNING.with(|running| {
+            let mut running = running.borrow_mut();
+            // whatever we're inside of stops getting the credit until we're done
+            if let Some((counters, outer, since)) = running.last() {
+                counters.charge(outer, now - *since);
+            }
+            running.push((self, name, now));
+        });
+        PhaseTimer {
+            _not_send: std::marker::PhantomData,
+        }
+    }
+
+    fn charge(&self, name: &str, time: Duration) {
+        let mut phases = self.phases.lock().unwrap();
+        // missing if someone called take() while it was running
+        if let Some(phase) = phases.iter_mut().find(|phase| phase.name == name) {
+            phase.total += time;
+        }
+    }
+}
+
+pub fn metadata_lookup(cached: bool) {
+    COUNTERS.metadata_lookup(cached);
+}
+
+pub fn downloaded(bytes: u64) {
+    COUNTERS
+        .bytes_downloaded
+        .fetch_add(bytes, Ordering::Relaxed);
+}
+
+pub fn wheel_built() {
+    COUNTERS.wheels_built.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Everything counted since the last call.
+pub fn take() -> Stats {
+    COUNTERS.take()
+}
+
+/// Times `name` until the returned guard is dropped. Phases nest -- resolving can mean
+/// building an sdist, which means resolving its build environment -- and each moment
+/// only counts for the innermost one running on this thread. So the times add up,
+/// instead of "resolve" including every build that it set off.
+pub fn time_phase(name: &'static str) -> PhaseTimer {
+    COUNTERS.time_phase(name)
+}
+
+pub struct PhaseTimer {
+    // it's this thread's timers that it's nested in
+    _not_send: std::marker::PhantomData<*const ()>,
+}
+
+impl Drop for PhaseTimer {
+    fn drop(&mut self) {
+        let now = Instant::now();
+        RUNNING.with(|running| {
+            let mut running = running.borrow_mut();
+            // guards get dropped in the opposite order they were made, so that's us
+            if let Some((counters, name, since)) = running.pop() {
+                counters.charge(name, now - since);
+            }
+            // and whatever we were inside of picks up again from here
+            if let Some((_, _, since)) = running.last_mut() {
+                *since = now;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        // not the global ones, which other tests might be adding to
+        static COUNTERS: Counters = Counters::new();
+        {
+            let _resolve = COUNTERS.time_phase("resolve");
+            std::thread::sleep(Duration::from_millis(20));
+            let _build = COUNTERS.time_phase("build");
+            std::thread::sleep(Duration::from_millis(10));
+            // the build's environment
+            let _inner = COUNTERS.time_phase("resolve");
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        COUNTERS.metadata_lookup(true);
+        COUNTERS.metadata_lookup(false);
+        COUNTERS.bytes_downloaded.fetch_add(1000, Ordering::Relaxed);
+        let stats = COUNTERS.take();
+        assert_eq!(stats.metadata_lookups, 2);
+        assert_eq!(stats.metadata_cached, 1);
+        assert_eq!(stats.bytes_downloaded, 1000);
+        assert_eq!(stats.wheels_built, 0);
+        let names = stats
+            .phases
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["resolve", "build"]);
+        // the build's time isn't in the resolve's, but its resolve's is
+        let (resolve, build) = (stats.phases[0].1, stats.phases[1].1);
+        assert!(resolve >= Duration::from_millis(70));
+        assert!(build >= Duration::from_millis(10));
+        assert!(build < Duration::from_millis(50));
+
+        assert_eq!(COUNTERS.take(), Stats::default());
+    }
+}
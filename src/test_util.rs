@@ -1,8 +1,15 @@
 use std::{
     net::SocketAddr,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
+use warp::{filters::BoxedFilter, Filter, Reply};
+use zip::write::FileOptions;
+
+use crate::env::EnvForest;
+use crate::kvstore::KVDirStore;
+use crate::package_db::PackageDB;
 use crate::prelude::*;
 
 pub fn from_commented_json<T>(input: &str) -> T
@@ -29,13 +36,57 @@ impl StaticHTTPServer {
     pub fn new<P: AsRef<Path>>(path: P) -> StaticHTTPServer {
         let mut actual_path: PathBuf = env!("CARGO_MANIFEST_DIR").parse().unwrap();
         actual_path.push(path);
+        StaticHTTPServer::serve(
+            warp::fs::dir(actual_path)
+                .map(|file: warp::fs::File| file.into_response())
+                .boxed(),
+        )
+    }
+
+    /// Spins up a server that sends back canned responses, for imitating the quirks of
+    /// particular index implementations. Paths that aren't in `routes` get a 404.
+    pub fn with_routes(
+        routes: Vec<(&str, http::Response<Vec<u8>>)>,
+    ) -> StaticHTTPServer {
+        let routes: Arc<HashMap<String, http::Response<Vec<u8>>>> = Arc::new(
+            routes
+                .into_iter()
+                .map(|(path, response)| (path.to_string(), response))
+                .collect(),
+        );
+        StaticHTTPServer::serve(
+            warp::path::full()
+                .map(move |path: warp::path::FullPath| {
+                    let mut builder = http::Response::builder();
+                    let body = match routes.get(path.as_str()) {
+                        Some(canned) => {
+                            builder = builder.status(canned.status());
+                            for (name, value) in canned.headers() {
+                                builder = builder.header(name, value);
+                            }
+                            canned.body().clone()
+                        }
+                        None => {
+                            builder = builder.status(404);
+                            Vec::new()
+                        }
+                    };
+                    builder.body(body.into()).unwrap()
+                })
+                .boxed(),
+        )
+    }
+
+    fn serve(filter: BoxedFilter<(warp::reply::Response,)>) -> StaticHTTPServer {
         let runtime = tokio::runtime::Runtime::new().unwrap();
         let _guard = runtime.enter();
         let (tx, rx) = tokio::sync::oneshot::channel();
-        let (address, server) = warp::serve(warp::fs::dir(actual_path))
-            .bind_with_graceful_shutdown(([127, 0, 0, 1], 0), async {
+        let (address, server) = warp::serve(filter).bind_with_graceful_shutdown(
+            ([127, 0, 0, 1], 0),
+            async {
                 rx.await.ok();
-            });
+            },
+        );
         let join_handle = runtime.spawn(server);
 
         StaticHTTPServer {
@@ -64,6 +115,184 @@ impl Drop for StaticHTTPServer {
     }
 }
 
+/// A simple API page with a link for each of `links` (raw <a> attributes, e.g. href
+/// and data-yanked), for StaticHTTPServer::with_routes.
+pub fn index_page(links: &[&str]) -> http::Response<Vec<u8>> {
+    let body = links
+        .iter()
+        .map(|attrs| format!("<a {attrs}>file</a>\n"))
+        .collect::<String>();
+    http::Response::builder()
+        .header("Content-Type", "text/html")
+        .body(body.into_bytes())
+        .unwrap()
+}
+
+/// Runs `f` with a PackageDB whose only index is a throwaway server with `routes`,
+/// at /simple/.
+pub fn with_index_db(
+    routes: Vec<(&str, http::Response<Vec<u8>>)>,
+    f: impl FnOnce(&mut PackageDB),
+) {
+    let server = StaticHTTPServer::with_routes(routes);
+    let tmp = tempfile::tempdir().unwrap();
+    let forest = EnvForest::new(&tmp.path().join("forest")).unwrap();
+    let build_store = KVDirStore::new(&tmp.path().join("build")).unwrap();
+    let mut db = PackageDB::new(
+        &[server.url("/simple/")],
+        &tmp.path().join("cache"),
+        &forest,
+        &build_store,
+        None,
+    )
+    .unwrap();
+    f(&mut db);
+}
+
+/// Builds a tiny fake pybi, for tests that need to unpack one and set up environments
+/// on top of it, without downloading a real interpreter. It has the pybi-info/
+/// metadata, a stdlib that's just a site.py, an empty site-packages, and a "python"
+/// in bin/ that's really a shell script: `--version` works, and anything else just
+/// echoes its arguments back.
+pub struct FakePybi {
+    pub version: Version,
+    pub platform: String,
+    files: Vec<(String, Vec<u8>, bool)>,
+}
+
+impl FakePybi {
+    pub fn new(version: &str) -> FakePybi {
+        FakePybi {
+            version: version.try_into().unwrap(),
+            platform: "manylinux_2_17_x86_64".into(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Adds an extra file, e.g. a package pre-installed into site-packages.
+    pub fn with_file(mut self, path: &str, contents: &[u8]) -> FakePybi {
+        self.files.push((path.into(), contents.into(), false));
+        self
+    }
+
+    pub fn filename(&self) -> String {
+        format!("cpython-{}-{}.pybi", self.version, self.platform)
+    }
+
+    /// "3.11", for the version-specific paths
+    pub fn short_version(&self) -> String {
+        let release = &self.version.0.release;
+        format!("{}.{}", release[0], release.get(1).unwrap_or(&0))
+    }
+
+    pub fn stdlib(&self) -> String {
+        format!("lib/python{}", self.short_version())
+    }
+
+    pub fn purelib(&self) -> String {
+        format!("{}/site-packages", self.stdlib())
+    }
+
+    fn metadata(&self) -> String {
+        let short = self.short_version();
+        let xy = short.replace('.', "");
+        let markers = serde_json::json!({
+            "implementation_name": "cpython",
+            "implementation_version": self.version.to_string(),
+            "os_name": "posix",
+            "platform_machine": "x86_64",
+            "platform_python_implementation": "CPython",
+            "platform_system": "Linux",
+            "python_full_version": self.version.to_string(),
+            "python_version": short,
+            "sys_platform": "linux",
+        });
+        let paths = serde_json::json!({
+            "stdlib": self.stdlib(),
+            "purelib": self.purelib(),
+            "platlib": self.purelib(),
+            "scripts": "bin",
+            "include": format!("include/python{short}"),
+            "data": ".",
+        });
+        indoc::formatdoc! {"
+            Metadata-Version: 2.1
+            Name: cpython
+            Version: {version}
+            Pybi-Environment-Marker-Variables: {markers}
+            Pybi-Wheel-Tag: cp{xy}-cp{xy}-PLATFORM
+            Pybi-Wheel-Tag: cp{xy}-abi3-PLATFORM
+            Pybi-Wheel-Tag: cp{xy}-none-PLATFORM
+            Pybi-Wheel-Tag: py3-none-any
+            Pybi-Paths: {paths}
+            ",
+            version = self.version,
+        }
+    }
+
+    /// The contents of the .pybi file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let python = indoc::formatdoc! {r#"
+            #!/bin/sh
+            if [ "$1" = "--version" ]; then
+                echo "Python {version}"
+            else
+                echo "fake python: $*"
+            fi
+            "#,
+            version = self.version,
+        };
+        let mut files: Vec<(String, Vec<u8>, bool)> = vec![
+            ("bin/python".into(), python.clone().into(), true),
+            (
+                format!("bin/python{}", self.short_version()),
+                python.into(),
+                true,
+            ),
+            (
+                format!("{}/site.py", self.stdlib()),
+                b"import sys\n\nENABLE_USER_SITE = None\n".to_vec(),
+                false,
+            ),
+            (
+                "pybi-info/PYBI".into(),
+                b"Pybi-Version: 1.0\nGenerator: posy test_util\n".to_vec(),
+                false,
+            ),
+            ("pybi-info/METADATA".into(), self.metadata().into(), false),
+        ];
+        files.extend(self.files.iter().cloned());
+        let mut record: String = files
+            .iter()
+            .map(|(path, _, _)| format!("{path},,\n"))
+            .collect();
+        record.push_str("pybi-info/RECORD,,\n");
+        files.push(("pybi-info/RECORD".into(), record.into(), false));
+
+        let mut z = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        z.add_directory(format!("{}/", self.purelib()), FileOptions::default())
+            .unwrap();
+        for (path, contents, executable) in files {
+            let options = FileOptions::default().unix_permissions(if executable {
+                0o755
+            } else {
+                0o644
+            });
+            z.start_file(path, options).unwrap();
+            z.write_all(&contents).unwrap();
+        }
+        z.finish().unwrap().into_inner()
+    }
+
+    pub fn open(&self) -> Pybi {
+        Pybi::new(
+            self.filename().parse().unwrap(),
+            Box::new(std::io::Cursor::new(self.to_bytes())),
+        )
+        .unwrap()
+    }
+}
+
 mod test {
     use super::*;
 
@@ -79,4 +308,35 @@ mod test {
         let data: HashMap<String, u32> = response.into_json().unwrap();
         assert_eq!(data.get("hi"), Some(&1));
     }
+
+    #[test]
+    fn test_fake_pybi() {
+        let fake = FakePybi::new("3.11.2")
+            .with_file("lib/python3.11/site-packages/preinstalled.py", b"");
+        assert_eq!(fake.filename(), "cpython-3.11.2-manylinux_2_17_x86_64.pybi");
+        let pybi = fake.open();
+        let (_, metadata) = pybi.metadata().unwrap();
+        assert_eq!(metadata.version, fake.version);
+        assert_eq!(
+            metadata.path("purelib").unwrap().to_string(),
+            "lib/python3.11/site-packages"
+        );
+
+        let tmp = tempfile::tempdir().unwrap();
+        pybi.unpack(&mut crate::tree::WriteTreeFS::new(tmp.path()))
+            .unwrap();
+        assert!(tmp.path().join("pybi-info/RECORD").exists());
+        assert!(tmp
+            .path()
+            .join("lib/python3.11/site-packages/preinstalled.py")
+            .exists());
+        #[cfg(unix)]
+        {
+            let output = std::process::Command::new(tmp.path().join("bin/python"))
+                .arg("--version")
+                .output()
+                .unwrap();
+            assert_eq!(output.stdout, b"Python 3.11.2\n");
+        }
+    }
 }
@@ -1,5 +1,8 @@
 use crate::{prelude::*, tree::WriteTree};
 
+mod prebuilt;
+use prebuilt::PREBUILT;
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum ScriptType {
     GUI,
@@ -9,8 +12,8 @@ pub enum ScriptType {
 pub enum FindPython {
     // from $POSY_PYTHON{,W}
     FromEnv,
-    // XX TODO
-    //SameDir,
+    // `python` in the same directory as the script, for relocatable envs
+    SameDir,
 }
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum ScriptPlatform {
@@ -19,14 +22,63 @@ pub enum ScriptPlatform {
     Both,
 }
 
+/// Which CPU the .exe trampolines are compiled for.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum WindowsArch {
+    X86,
+    X86_64,
+    Arm64,
+}
+
+impl WindowsArch {
+    pub fn of_platform_tag(tag: &str) -> Option<WindowsArch> {
+        match tag {
+            "win32" => Some(WindowsArch::X86),
+            "win_amd64" => Some(WindowsArch::X86_64),
+            "win_arm64" => Some(WindowsArch::Arm64),
+            _ => None,
+        }
+    }
+
+    /// The arch of the first Windows tag `platform` supports, if any.
+    pub fn of_platform<P: Platform>(platform: &P) -> Option<WindowsArch> {
+        platform
+            .tags()
+            .find_map(|tag| WindowsArch::of_platform_tag(tag))
+    }
+}
+
 pub struct TrampolineMaker {
     strategy: FindPython,
     platform: ScriptPlatform,
+    windows_arch: WindowsArch,
+    rosetta: bool,
 }
 
 impl TrampolineMaker {
     pub fn new(strategy: FindPython, platform: ScriptPlatform) -> TrampolineMaker {
-        TrampolineMaker { strategy, platform }
+        TrampolineMaker {
+            strategy,
+            platform,
+            windows_arch: WindowsArch::X86_64,
+            rosetta: false,
+        }
+    }
+
+    /// Start python as x86_64 (see Env::rosetta). Only needed for FindPython::SameDir;
+    /// FromEnv trampolines get told by $POSY_PYTHON_ARCH.
+    pub fn under_rosetta(mut self, rosetta: bool) -> TrampolineMaker {
+        self.rosetta = rosetta;
+        self
+    }
+
+    /// Use the .exe trampolines for `platform`'s CPU, if it's a Windows platform.
+    /// (Otherwise, and by default, we use x86-64 ones.)
+    pub fn for_platform<P: Platform>(mut self, platform: &P) -> TrampolineMaker {
+        if let Some(arch) = WindowsArch::of_platform(platform) {
+            self.windows_arch = arch;
+        }
+        self
     }
 
     pub fn make_trampoline<W: WriteTree>(
@@ -36,7 +88,6 @@ impl TrampolineMaker {
         script_type: ScriptType,
         mut tree: W,
     ) -> Result<()> {
-        assert_eq!(self.strategy, FindPython::FromEnv);
         if self.platform == ScriptPlatform::Unix
             || self.platform == ScriptPlatform::Both
         {
@@ -46,7 +97,7 @@ impl TrampolineMaker {
         if self.platform == ScriptPlatform::Windows
             || self.platform == ScriptPlatform::Both
         {
-            let out = self.windows_trampoline(script, script_type);
+            let out = self.windows_trampoline(script, script_type)?;
             let mut path_str = path.to_string();
             path_str.push_str(".exe");
             let path_exe: NicePathBuf = path_str.try_into().unwrap();
@@ -56,20 +107,33 @@ impl TrampolineMaker {
     }
 
     fn unix_trampoline(&self, script: &[u8], script_type: ScriptType) -> Vec<u8> {
-        let prefix = match script_type {
-            ScriptType::Console => UNIX_TEMPLATE.into(),
-            ScriptType::GUI => UNIX_TEMPLATE.replace("POSY_PYTHON", "POSY_PYTHONW"),
+        let prefix = match (self.strategy, script_type) {
+            (FindPython::FromEnv, ScriptType::Console) => UNIX_TEMPLATE.into(),
+            (FindPython::FromEnv, ScriptType::GUI) => unix_gui_template(),
+            // there's no separate pythonw on unix anyway
+            (FindPython::SameDir, _) if self.rosetta => UNIX_SAME_DIR_TEMPLATE
+                .replace("exec ", &format!("exec {ROSETTA_LAUNCHER} ")),
+            (FindPython::SameDir, _) => UNIX_SAME_DIR_TEMPLATE.into(),
         };
         let mut out = prefix.into_bytes();
         out.extend_from_slice(script);
         out
     }
 
-    fn windows_trampoline(&self, script: &[u8], script_type: ScriptType) -> Vec<u8> {
+    fn windows_trampoline(
+        &self,
+        script: &[u8],
+        script_type: ScriptType,
+    ) -> Result<Vec<u8>> {
+        let (console, gui) = windows_exes(self.windows_arch)?;
         let prefix = match script_type {
-            ScriptType::Console => WINDOWS_CONSOLE,
-            ScriptType::GUI => WINDOWS_GUI,
+            ScriptType::Console => console,
+            ScriptType::GUI => gui,
         };
+        let mut out: Vec<u8> = prefix.into();
+        if self.strategy == FindPython::SameDir {
+            patch_find_python(&mut out, b"SameDir")?;
+        }
         let mut suffix = std::io::Cursor::new(Vec::<u8>::new());
         {
             let mut z = zip::ZipWriter::new(&mut suffix);
@@ -81,12 +145,19 @@ impl TrampolineMaker {
             z.write_all(script).unwrap();
             z.finish().unwrap();
         }
-        let mut out: Vec<u8> = prefix.into();
         out.extend(suffix.into_inner().into_iter());
-        out
+        Ok(out)
     }
 }
 
+// Both kinds of trampoline pass $POSY_PYTHON_FLAGS (if set) to the interpreter, before
+// the script. It's a space-separated list, deliberately unquoted here so that it gets
+// split into words; Env::env_vars makes sure there's nothing in there that the shell
+// would do anything else with.
+//
+// If $POSY_PYTHON_ARCH is set, python gets started under /usr/bin/arch, for x86_64
+// envs on Apple silicon. Otherwise a universal2 python comes up as arm64, and can't
+// load the extension modules.
 const UNIX_TEMPLATE: &str = indoc::indoc! {r#"
     #!/bin/sh
     ''':'
@@ -94,11 +165,207 @@ const UNIX_TEMPLATE: &str = indoc::indoc! {r#"
         echo 'Expected $POSY_PYTHON to be set' >&2
         exit 1
     fi
-    exec "${POSY_PYTHON}" "$0" "$@"
+    exec ${POSY_PYTHON_ARCH:+/usr/bin/arch "-$POSY_PYTHON_ARCH"} "${POSY_PYTHON}" ${POSY_PYTHON_FLAGS-} "$0" "$@"
+    ' '''
+"#};
+
+// Same, but with $POSY_PYTHONW. The flags and arch are shared, so we can't just replace
+// every POSY_PYTHON.
+fn unix_gui_template() -> String {
+    UNIX_TEMPLATE
+        .replace("${POSY_PYTHON+x}", "${POSY_PYTHONW+x}")
+        .replace("$POSY_PYTHON to", "$POSY_PYTHONW to")
+        .replace("${POSY_PYTHON}", "${POSY_PYTHONW}")
+}
+
+const ROSETTA_LAUNCHER: &str = "/usr/bin/arch -x86_64";
+
+const UNIX_SAME_DIR_TEMPLATE: &str = indoc::indoc! {r#"
+    #!/bin/sh
+    ''':'
+    exec "$(dirname "$0")/python" ${POSY_PYTHON_FLAGS-} "$0" "$@"
     ' '''
 "#};
 
-const WINDOWS_CONSOLE: &[u8] =
-    include_bytes!("windows-trampolines/posy-trampoline-console.exe");
-const WINDOWS_GUI: &[u8] =
-    include_bytes!("windows-trampolines/posy-trampoline-gui.exe");
+/// If `contents` is a trampoline that we made with FindPython::FromEnv, returns the
+/// script inside it. Useful for re-wrapping scripts that are already unpacked.
+pub fn unwrap_unix_trampoline(contents: &[u8]) -> Option<(&[u8], ScriptType)> {
+    if let Some(script) = contents.strip_prefix(UNIX_TEMPLATE.as_bytes()) {
+        return Some((script, ScriptType::Console));
+    }
+    contents
+        .strip_prefix(unix_gui_template().as_bytes())
+        .map(|script| (script, ScriptType::GUI))
+}
+
+// The .exe trampolines decide how to find python by looking at this string inside
+// themselves; see bounce.rs in posy-trampoline.
+const FIND_PYTHON_MARKER: &[u8] = b"POSY_FIND_PYTHON=";
+const FIND_PYTHON_DEFAULT: &[u8] = b"FromEnv";
+
+fn patch_find_python(exe: &mut [u8], mode: &[u8]) -> Result<()> {
+    assert_eq!(mode.len(), FIND_PYTHON_DEFAULT.len());
+    let mut needle = FIND_PYTHON_MARKER.to_vec();
+    needle.extend_from_slice(FIND_PYTHON_DEFAULT);
+    let offset = exe
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .ok_or_else(|| {
+            eyre!(
+                "these Windows trampolines can only find python via $POSY_PYTHON; \
+                 they need to be rebuilt with 'cargo xtask trampolines'"
+            )
+        })?
+        + FIND_PYTHON_MARKER.len();
+    exe[offset..offset + mode.len()].copy_from_slice(mode);
+    Ok(())
+}
+
+// Returns (console, gui)
+fn windows_exes(arch: WindowsArch) -> Result<(&'static [u8], &'static [u8])> {
+    // no falling back to some other arch's: scripts that can't run are worse than a
+    // clear error
+    if let Some((_, console, gui)) = PREBUILT
+        .iter()
+        .find(|(prebuilt_arch, _, _)| *prebuilt_arch == arch)
+    {
+        return Ok((console, gui));
+    }
+    bail!(
+        "this build of posy doesn't have {arch:?} Windows trampolines (see \
+         'cargo xtask trampolines')"
+    );
+}
+
+// If `contents` starts with one of our .exe trampolines, for any arch, which kind it is
+fn windows_trampoline_prefix(contents: &[u8]) -> Option<ScriptType> {
+    PREBUILT.iter().find_map(|(_, console, gui)| {
+        if contents.starts_with(console) {
+            Some(ScriptType::Console)
+        } else if contents.starts_with(gui) {
+            Some(ScriptType::GUI)
+        } else {
+            None
+        }
+    })
+}
+
+pub fn is_windows_trampoline(contents: &[u8]) -> bool {
+    windows_trampoline_prefix(contents).is_some()
+}
+
+/// Like unwrap_unix_trampoline, but for our .exe trampolines.
+pub fn unwrap_windows_trampoline(
+    contents: &[u8],
+) -> Result<Option<(Vec<u8>, ScriptType)>> {
+    let script_type = match windows_trampoline_prefix(contents) {
+        Some(script_type) => script_type,
+        None => return Ok(None),
+    };
+    // the zip reader copes with the .exe in front
+    let mut z = zip::ZipArchive::new(std::io::Cursor::new(contents))?;
+    let script = slurp(&mut z.by_name("__main__.py")?)?;
+    Ok(Some((script, script_type)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tree::WriteTreeFS;
+
+    #[test]
+    fn test_unwrap_trampolines() {
+        let tmp = tempfile::tempdir().unwrap();
+        let maker = TrampolineMaker::new(FindPython::FromEnv, ScriptPlatform::Both);
+        for (name, script_type) in
+            [("cli", ScriptType::Console), ("gui", ScriptType::GUI)]
+        {
+            maker
+                .make_trampoline(
+                    &name.try_into().unwrap(),
+                    b"print('hi')",
+                    script_type,
+                    WriteTreeFS::new(tmp.path()),
+                )
+                .unwrap();
+            let unix = std::fs::read(tmp.path().join(name)).unwrap();
+            let text = String::from_utf8_lossy(&unix);
+            assert!(text.contains("${POSY_PYTHON_FLAGS-}"));
+            assert_eq!(
+                text.contains("${POSY_PYTHONW}"),
+                script_type == ScriptType::GUI
+            );
+            assert!(text.contains("/usr/bin/arch \"-$POSY_PYTHON_ARCH\""));
+            assert_eq!(
+                unwrap_unix_trampoline(&unix),
+                Some((&b"print('hi')"[..], script_type))
+            );
+            let exe = std::fs::read(tmp.path().join(format!("{name}.exe"))).unwrap();
+            assert!(is_windows_trampoline(&exe));
+            assert_eq!(
+                unwrap_windows_trampoline(&exe).unwrap(),
+                Some((b"print('hi')".to_vec(), script_type))
+            );
+            assert!(unwrap_windows_trampoline(&unix).unwrap().is_none());
+        }
+
+        let same_dir = TrampolineMaker::new(FindPython::SameDir, ScriptPlatform::Unix)
+            .unix_trampoline(b"print('hi')", ScriptType::Console);
+        assert!(unwrap_unix_trampoline(&same_dir).is_none());
+        assert!(!String::from_utf8_lossy(&same_dir).contains("/usr/bin/arch"));
+        let rosetta = TrampolineMaker::new(FindPython::SameDir, ScriptPlatform::Unix)
+            .under_rosetta(true)
+            .unix_trampoline(b"print('hi')", ScriptType::Console);
+        assert!(String::from_utf8_lossy(&rosetta)
+            .contains(r#"exec /usr/bin/arch -x86_64 "$(dirname "$0")/python""#));
+    }
+
+    #[test]
+    fn test_windows_arch() {
+        assert_eq!(
+            WindowsArch::of_platform(&PybiPlatform::new("win_arm64")),
+            Some(WindowsArch::Arm64)
+        );
+        assert_eq!(
+            WindowsArch::of_platform(&PybiPlatform::new("win32")),
+            Some(WindowsArch::X86)
+        );
+        assert_eq!(
+            WindowsArch::of_platform(&PybiPlatform::new("manylinux_2_17_x86_64")),
+            None
+        );
+
+        // every arch has its own trampolines, and we can unwrap all of them
+        for arch in [WindowsArch::X86, WindowsArch::X86_64, WindowsArch::Arm64] {
+            let (console, gui) = windows_exes(arch).unwrap();
+            assert_eq!(
+                windows_trampoline_prefix(console),
+                Some(ScriptType::Console)
+            );
+            assert_eq!(windows_trampoline_prefix(gui), Some(ScriptType::GUI));
+        }
+    }
+
+    #[test]
+    fn test_patch_find_python() {
+        let mut exe = b"MZ...POSY_FIND_PYTHON=FromEnv\0...".to_vec();
+        patch_find_python(&mut exe, b"SameDir").unwrap();
+        assert_eq!(exe, b"MZ...POSY_FIND_PYTHON=SameDir\0...");
+        // already patched, or an old trampoline that doesn't support it
+        assert!(patch_find_python(&mut exe, b"SameDir").is_err());
+
+        // and the real ones all have the marker
+        for (arch, console, gui) in PREBUILT {
+            for prebuilt in [console, gui] {
+                let mut exe = prebuilt.to_vec();
+                patch_find_python(&mut exe, b"SameDir")
+                    .wrap_err_with(|| format!("{arch:?}"))
+                    .unwrap();
+                assert_ne!(&exe, prebuilt);
+                assert!(exe
+                    .windows(b"POSY_FIND_PYTHON=SameDir".len())
+                    .any(|window| window == b"POSY_FIND_PYTHON=SameDir"));
+            }
+        }
+    }
+}
@@ -0,0 +1,22 @@
+// Generated by 'cargo xtask trampolines'; don't edit by hand.
+use super::WindowsArch;
+
+// (arch, console trampoline, gui trampoline)
+#[rustfmt::skip]
+pub(super) const PREBUILT: &[(WindowsArch, &[u8], &[u8])] = &[
+    (
+        WindowsArch::X86,
+        include_bytes!("windows-trampolines/x86/posy-trampoline-console.exe"),
+        include_bytes!("windows-trampolines/x86/posy-trampoline-gui.exe"),
+    ),
+    (
+        WindowsArch::X86_64,
+        include_bytes!("windows-trampolines/x86_64/posy-trampoline-console.exe"),
+        include_bytes!("windows-trampolines/x86_64/posy-trampoline-gui.exe"),
+    ),
+    (
+        WindowsArch::Arm64,
+        include_bytes!("windows-trampolines/arm64/posy-trampoline-console.exe"),
+        include_bytes!("windows-trampolines/arm64/posy-trampoline-gui.exe"),
+    ),
+];
@@ -13,6 +13,7 @@ use windows_sys::Win32::{
         Console::*,
         Environment::{GetCommandLineA, GetEnvironmentVariableA, SetCurrentDirectoryA},
         JobObjects::*,
+        LibraryLoader::GetModuleFileNameA,
         Threading::*,
     },
     UI::WindowsAndMessaging::*,
@@ -35,24 +36,69 @@ fn getenv(name: &CStr) -> Option<CString> {
     }
 }
 
+// How we find python. posy picks by patching the last 7 bytes of this in the .exe
+// (see TrampolineMaker), so it has to keep exactly this layout. "FromEnv" means
+// $POSY_PYTHON(W); "SameDir" means python(w).exe in the same directory as us.
+#[used]
+static FIND_PYTHON: [u8; 24] = *b"POSY_FIND_PYTHON=FromEnv";
+
+fn find_python_same_dir() -> bool {
+    unsafe {
+        // volatile, so the compiler can't constant-fold the unpatched value
+        let mode = core::ptr::read_volatile(addr_of!(FIND_PYTHON));
+        mode.get_unchecked(17..) == b"SameDir"
+    }
+}
+
+fn python_next_to_me(is_gui: bool) -> CString {
+    unsafe {
+        let mut path = Vec::<u8>::with_capacity(MAX_PATH as usize);
+        loop {
+            let len = GetModuleFileNameA(0, path.as_mut_ptr(), path.capacity() as u32);
+            if len == 0 {
+                eprintln!("couldn't find the path to this executable");
+                ExitProcess(1);
+            }
+            if (len as usize) < path.capacity() {
+                path.set_len(len as usize);
+                break;
+            }
+            // truncated; try again with more room
+            path.reserve(path.capacity());
+        }
+        while let Some(&byte) = path.last() {
+            if byte == b'\\' || byte == b'/' {
+                break;
+            }
+            path.pop();
+        }
+        path.extend_from_slice(if is_gui { b"pythonw.exe" } else { b"python.exe" });
+        CString::from_vec_unchecked(path)
+    }
+}
+
 fn make_child_cmdline(is_gui: bool) -> Vec<u8> {
     unsafe {
         let my_cmdline = CStr::from_ptr(GetCommandLineA() as _);
 
-        let envvar = if is_gui {
-            c!("POSY_PYTHONW")
+        let python_exe = if find_python_same_dir() {
+            python_next_to_me(is_gui)
         } else {
-            c!("POSY_PYTHON")
+            let envvar = if is_gui {
+                c!("POSY_PYTHONW")
+            } else {
+                c!("POSY_PYTHON")
+            };
+            let python_exe = getenv(envvar);
+            if python_exe.is_none() {
+                eprintln!(
+                    "need {} to be set",
+                    core::str::from_utf8_unchecked(envvar.to_bytes())
+                );
+                ExitProcess(1);
+            }
+            python_exe.unwrap_unchecked()
         };
-        let python_exe = getenv(envvar);
-        if python_exe.is_none() {
-            eprintln!(
-                "need {} to be set",
-                core::str::from_utf8_unchecked(envvar.to_bytes())
-            );
-            ExitProcess(1);
-        }
-        let python_exe = python_exe.unwrap_unchecked();
 
         let mut child_cmdline = Vec::<u8>::new();
         child_cmdline.push(b'"');
@@ -66,6 +112,12 @@ fn make_child_cmdline(is_gui: bool) -> Vec<u8> {
             }
         }
         child_cmdline.extend(br#"" "#);
+        // Interpreter flags like -I go between python and the script. posy makes sure
+        // these are simple enough to paste in without any quoting.
+        if let Some(flags) = getenv(c!("POSY_PYTHON_FLAGS")) {
+            child_cmdline.extend(flags.as_bytes());
+            child_cmdline.push(b' ');
+        }
         child_cmdline.extend(my_cmdline.to_bytes_with_nul());
         //eprintln!("new_cmdline: {}", core::str::from_utf8_unchecked(new_cmdline.as_slice()));
         child_cmdline
@@ -1,6 +1,5 @@
 use core::{
     convert::Infallible,
-    ffi::c_void,
     ptr::{addr_of_mut, null, null_mut},
 };
 
@@ -32,7 +31,7 @@ impl DiagnosticBuffer {
             while !remaining.is_empty() {
                 let ok = WriteFile(
                     handle,
-                    remaining.as_ptr() as *const c_void,
+                    remaining.as_ptr(),
                     remaining.len() as u32,
                     addr_of_mut!(written),
                     null_mut(),
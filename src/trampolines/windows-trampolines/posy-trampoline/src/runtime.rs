@@ -57,3 +57,45 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
     }
 }
 
+
+// On 32-bit x86, MSVC expects the C runtime to provide 64-bit division (__aulldiv and
+// __aullrem, which take their arguments on the stack and pop them, i.e. stdcall). We
+// don't have a C runtime, and LLVM would lower `/` on u64s right back into calls to
+// these, so do it the long way.
+#[cfg(target_arch = "x86")]
+fn udivmod64(n: u64, d: u64) -> (u64, u64) {
+    let mut q = 0u64;
+    let mut r = 0u64;
+    for i in (0..64).rev() {
+        r = (r << 1) | ((n >> i) & 1);
+        if r >= d {
+            r -= d;
+            q |= 1 << i;
+        }
+    }
+    (q, r)
+}
+
+#[cfg(target_arch = "x86")]
+extern "stdcall" fn aulldiv(n: u64, d: u64) -> u64 {
+    udivmod64(n, d).0
+}
+
+#[cfg(target_arch = "x86")]
+extern "stdcall" fn aullrem(n: u64, d: u64) -> u64 {
+    udivmod64(n, d).1
+}
+
+// the helpers' names don't get stdcall's @16 decoration, so they have to be spelled
+// out in asm
+#[cfg(target_arch = "x86")]
+core::arch::global_asm!(
+    ".globl __aulldiv",
+    "__aulldiv:",
+    "jmp {div}",
+    ".globl __aullrem",
+    "__aullrem:",
+    "jmp {rem}",
+    div = sym aulldiv,
+    rem = sym aullrem,
+);
@@ -0,0 +1,188 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::kvstore::remove_dir_entry;
+use crate::prelude::*;
+
+// A transcript is a log of everything one posy command changed on disk: which store
+// entries it created, and which files it overwrote (with their old contents). They
+// live in the project's .posy/transcripts/ directory, one JSON file per command, so
+// when an update goes wrong (or just turns out to be unwanted), 'posy rollback' can
+// undo it, and when something's weird you can see exactly what changed.
+//
+// Store entries are content-addressed and never modified once they're created, so
+// "undo" for them just means deleting them again. (Except in the machine-wide stores,
+// like EnvForest::shared_wheels, which other projects are using too; we never record
+// those.) Files we overwrite are
+// project-level things like posy.lock, so we can afford to keep a full copy.
+
+const TRANSCRIPTS_DIR: &str = ".posy/transcripts";
+const ROLLED_BACK_SUFFIX: &str = ".rolled-back.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Mutation {
+    /// A directory we created, e.g. a new entry in an EnvForest
+    Created { path: PathBuf },
+    /// A (text) file we wrote; `previous` is what it had in it before, if it existed
+    Wrote {
+        path: PathBuf,
+        previous: Option<String>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TranscriptFile {
+    pub command: String,
+    // seconds since the epoch
+    pub finished: u64,
+    pub mutations: Vec<Mutation>,
+}
+
+/// Collects mutations as they happen. Can be shared between threads.
+#[derive(Debug, Default)]
+pub struct Transcript {
+    mutations: Mutex<Vec<Mutation>>,
+}
+
+impl Transcript {
+    pub fn created(&self, path: &Path) {
+        self.mutations.lock().unwrap().push(Mutation::Created {
+            path: path.to_owned(),
+        });
+    }
+
+    /// Call this *before* overwriting `path`, so we can remember what was there.
+    pub fn will_write(&self, path: &Path) -> Result<()> {
+        let previous = match fs::read_to_string(path) {
+            Ok(contents) => Some(contents),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => Err(err)?,
+        };
+        self.mutations.lock().unwrap().push(Mutation::Wrote {
+            path: path.to_owned(),
+            previous,
+        });
+        Ok(())
+    }
+
+    /// Writes out everything recorded so far (if anything) into `project_root`'s
+    /// transcripts directory, and starts over.
+    pub fn save(&self, project_root: &Path, command: &str) -> Result<Option<PathBuf>> {
+        let mutations = std::mem::take(&mut *self.mutations.lock().unwrap());
+        if mutations.is_empty() {
+            return Ok(None);
+        }
+        let dir = project_root.join(TRANSCRIPTS_DIR);
+        fs::create_dir_all(&dir)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let file = TranscriptFile {
+            command: command.into(),
+            finished: now.as_secs(),
+            mutations,
+        };
+        // millis in the name so they sort in order, and two commands in the same
+        // second don't collide
+        let path = dir.join(format!("{}-{command}.json", now.as_millis()));
+        context!("Writing {}", path.display());
+        fs::write(&path, serde_json::to_string_pretty(&file)?)?;
+        Ok(Some(path))
+    }
+}
+
+// The transcripts that haven't been rolled back yet, oldest first.
+fn pending_transcripts(project_root: &Path) -> Result<Vec<PathBuf>> {
+    let dir = project_root.join(TRANSCRIPTS_DIR);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.ends_with(".json") && !name.ends_with(ROLLED_BACK_SUFFIX) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Undoes the most recent command that hasn't been rolled back yet. Running it again
+/// goes back one more step. Returns what got undone, or None if there's nothing left.
+pub fn rollback(project_root: &Path) -> Result<Option<TranscriptFile>> {
+    let path = match pending_transcripts(project_root)?.pop() {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    context!("Rolling back {}", path.display());
+    let transcript: TranscriptFile = serde_json::from_slice(&fs::read(&path)?)?;
+    for mutation in transcript.mutations.iter().rev() {
+        match mutation {
+            // XX TODO: the entry lock only covers lookups, so if another posy is
+            // running out of this entry right now, this still pulls the rug out from
+            // under it
+            Mutation::Created { path } => remove_dir_entry(path)?,
+            Mutation::Wrote { path, previous } => match previous {
+                Some(contents) => fs::write(path, contents)?,
+                None => {
+                    if path.exists() {
+                        fs::remove_file(path)?;
+                    }
+                }
+            },
+        }
+    }
+    // keep it around, for the "what changed on disk" question
+    let done = path.with_extension(&ROLLED_BACK_SUFFIX[1..]);
+    fs::rename(&path, done)?;
+    Ok(Some(transcript))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transcript_rollback() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let lockfile = root.join("posy.lock");
+        let new_file = root.join("new.txt");
+        let entry = root.join("store").join("entry");
+
+        fs::write(&lockfile, "v1").unwrap();
+        let transcript = Transcript::default();
+        assert!(transcript.save(root, "noop").unwrap().is_none());
+
+        // first command: update the lockfile
+        transcript.will_write(&lockfile).unwrap();
+        fs::write(&lockfile, "v2").unwrap();
+        transcript.save(root, "lock").unwrap().unwrap();
+
+        // second command: create some stuff. (Sleep so the transcripts get different
+        // names.)
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        transcript.will_write(&new_file).unwrap();
+        fs::write(&new_file, "hi").unwrap();
+        fs::create_dir_all(&entry).unwrap();
+        fs::write(entry.join("file"), "contents").unwrap();
+        transcript.created(&entry);
+        transcript.save(root, "sync").unwrap().unwrap();
+
+        let undone = rollback(root).unwrap().unwrap();
+        assert_eq!(undone.command, "sync");
+        assert!(!new_file.exists());
+        assert!(!entry.exists());
+        assert_eq!(fs::read_to_string(&lockfile).unwrap(), "v2");
+
+        let undone = rollback(root).unwrap().unwrap();
+        assert_eq!(undone.command, "lock");
+        assert_eq!(fs::read_to_string(&lockfile).unwrap(), "v1");
+
+        assert!(rollback(root).unwrap().is_none());
+    }
+}
@@ -1,11 +1,14 @@
+use crate::blob_store::BlobStore;
 use crate::prelude::*;
 use auto_impl::auto_impl;
+use std::cell::Cell;
 use std::fs;
 use std::io;
 use std::ops::Deref;
 #[cfg(unix)]
 use std::os::unix::fs::OpenOptionsExt;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::slice::SliceIndex;
 use typed_path::unix::UnixComponent;
 use typed_path::UnixPath;
@@ -207,17 +210,26 @@ pub trait WriteTree {
     fn write_symlink(&mut self, symlink: &NiceSymlinkPaths) -> Result<()>;
 }
 
-pub struct WriteTreeFS {
+pub struct WriteTreeFS<'a> {
     root: PathBuf,
+    blobs: Option<&'a BlobStore>,
 }
 
-impl WriteTreeFS {
-    pub fn new<T: AsRef<Path>>(root: T) -> WriteTreeFS {
+impl<'a> WriteTreeFS<'a> {
+    pub fn new<T: AsRef<Path>>(root: T) -> WriteTreeFS<'a> {
         WriteTreeFS {
             root: root.as_ref().into(),
+            blobs: None,
         }
     }
 
+    /// Write files by linking them from `blobs`, if given, instead of writing them
+    /// out directly.
+    pub fn with_blobs(mut self, blobs: Option<&'a BlobStore>) -> WriteTreeFS<'a> {
+        self.blobs = blobs;
+        self
+    }
+
     fn full_path(&self, path: &NicePathBuf) -> Result<PathBuf> {
         let full_path = self.root.join(path.to_native());
         if let Some(parent) = full_path.parent() {
@@ -227,7 +239,7 @@ impl WriteTreeFS {
     }
 }
 
-impl WriteTree for WriteTreeFS {
+impl WriteTree for WriteTreeFS<'_> {
     fn mkdir(&mut self, path: &NicePathBuf) -> Result<()> {
         context!("Creating {path}/");
         Ok(fs::create_dir(self.full_path(path)?)?)
@@ -240,6 +252,9 @@ impl WriteTree for WriteTreeFS {
         executable: bool,
     ) -> Result<()> {
         context!("Writing out {path}");
+        if let Some(blobs) = self.blobs {
+            return blobs.write(&self.full_path(path)?, data, executable);
+        }
         let mut options = fs::OpenOptions::new();
         options.write(true).create_new(true);
         #[cfg(unix)]
@@ -270,36 +285,232 @@ impl WriteTree for WriteTreeFS {
     }
 }
 
+/// Limits on what an archive is allowed to unpack to, so that a malicious (or just
+/// broken) sdist that we trip over while resolving can't fill up the disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnpackLimits {
+    /// The most entries (files, directories, symlinks) an archive can have.
+    pub max_entries: u64,
+    /// The most bytes it can unpack to, in total.
+    pub max_bytes: u64,
+    /// The highest unpacked:compressed size ratio we'll put up with...
+    pub max_ratio: u64,
+    /// ...once it's unpacked at least this many bytes. Small files can legitimately
+    /// compress really well, so there's no point checking until it adds up.
+    pub ratio_grace_bytes: u64,
+}
+
+impl Default for UnpackLimits {
+    fn default() -> Self {
+        UnpackLimits {
+            max_entries: 500_000,
+            // wheels go through here too, and the ones with CUDA in them are huge
+            max_bytes: 32 << 30,
+            max_ratio: 200,
+            ratio_grace_bytes: 256 << 20,
+        }
+    }
+}
+
+const MAX_ENTRIES_VAR: &str = "POSY_UNPACK_MAX_ENTRIES";
+const MAX_BYTES_VAR: &str = "POSY_UNPACK_MAX_BYTES";
+const MAX_RATIO_VAR: &str = "POSY_UNPACK_MAX_RATIO";
+
+impl UnpackLimits {
+    /// The defaults, except where overridden by $POSY_UNPACK_MAX_ENTRIES,
+    /// $POSY_UNPACK_MAX_BYTES, or $POSY_UNPACK_MAX_RATIO.
+    pub fn from_env() -> Result<UnpackLimits> {
+        let mut limits = UnpackLimits::default();
+        for (var, field) in [
+            (MAX_ENTRIES_VAR, &mut limits.max_entries),
+            (MAX_BYTES_VAR, &mut limits.max_bytes),
+            (MAX_RATIO_VAR, &mut limits.max_ratio),
+        ] {
+            match std::env::var(var) {
+                Ok(s) => {
+                    *field = s.trim().parse().wrap_err_with(|| {
+                        format!("${var} should be a whole number, not {s:?}")
+                    })?
+                }
+                Err(std::env::VarError::NotPresent) => (),
+                Err(err) => Err(err)?,
+            }
+        }
+        Ok(limits)
+    }
+}
+
+/// How far along an unpack is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UnpackProgress {
+    pub entries: u64,
+    /// Zip files tell us up front; for tarballs, we don't know until we get there.
+    pub total_entries: Option<u64>,
+    pub bytes: u64,
+}
+
+// Big wheels (torch, tensorflow, ...) can take a while, so every this many bytes we let
+// people know we're still alive.
+const REPORT_EVERY: u64 = 256 << 20;
+
+/// The standard callback for unpack_zip_carefully and unpack_tar_gz_carefully: makes
+/// Ctrl-C abort between entries (see interrupt.rs), and sends an Unpacking event now
+/// and then for big archives.
+pub fn watch_unpack(what: impl Display) -> impl FnMut(&UnpackProgress) -> Result<()> {
+    let mut next_report = REPORT_EVERY;
+    move |progress| {
+        crate::interrupt::check()?;
+        if progress.bytes >= next_report {
+            next_report = progress.bytes + REPORT_EVERY;
+            crate::events::emit(crate::events::Event::Unpacking {
+                artifact: what.to_string(),
+                entries: progress.entries,
+                total_entries: progress.total_entries,
+                bytes: progress.bytes,
+            });
+        }
+        Ok(())
+    }
+}
+
+// Running totals for one archive, to check against its UnpackLimits.
+struct UnpackBudget<'a> {
+    limits: &'a UnpackLimits,
+    entries: u64,
+    bytes: u64,
+}
+
+impl<'a> UnpackBudget<'a> {
+    fn new(limits: &'a UnpackLimits) -> UnpackBudget<'a> {
+        UnpackBudget {
+            limits,
+            entries: 0,
+            bytes: 0,
+        }
+    }
+
+    fn add_entries(&mut self, n: u64) -> Result<()> {
+        self.entries += n;
+        if self.entries > self.limits.max_entries {
+            bail!(
+                "archive has more than {} entries; refusing to unpack it (set \
+                 ${MAX_ENTRIES_VAR} to raise the limit)",
+                self.limits.max_entries
+            );
+        }
+        Ok(())
+    }
+
+    // This gets called from inside Read::read, hence the io::Result
+    fn add_bytes(&mut self, n: u64, compressed: u64) -> io::Result<()> {
+        self.bytes += n;
+        let problem = if self.bytes > self.limits.max_bytes {
+            format!(
+                "archive unpacks to more than {} bytes; refusing to unpack it (set \
+                 ${MAX_BYTES_VAR} to raise the limit)",
+                self.limits.max_bytes
+            )
+        } else if self.bytes > self.limits.ratio_grace_bytes
+            && self.bytes / compressed.max(1) > self.limits.max_ratio
+        {
+            format!(
+                "archive unpacks to more than {}x its compressed size, so it's probably \
+                 a decompression bomb; refusing to unpack it (set ${MAX_RATIO_VAR} to \
+                 raise the limit)",
+                self.limits.max_ratio
+            )
+        } else {
+            return Ok(());
+        };
+        Err(io::Error::new(io::ErrorKind::Other, problem))
+    }
+}
+
+// Charges everything read through it to an UnpackBudget, so we stop as soon as we go
+// over, instead of trusting whatever sizes the archive claims.
+struct BudgetedRead<'a, 'b, R> {
+    inner: R,
+    budget: &'b mut UnpackBudget<'a>,
+    // how many compressed bytes have been consumed so far, for the ratio check
+    compressed: &'b dyn Fn() -> u64,
+}
+
+impl<'a, 'b, R: Read> Read for BudgetedRead<'a, 'b, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.budget.add_bytes(n as u64, (self.compressed)())?;
+        Ok(n)
+    }
+}
+
+// For tarballs, the compressed size is however much we've read from the underlying
+// file.
+struct CountingRead<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// Unpacks `z` into `dest`, refusing anything that goes outside of it or over
+/// `limits`. Calls `watch` between entries; if it returns an error, we stop there.
 pub fn unpack_zip_carefully<T: Read + Seek, W: WriteTree>(
     z: &mut ZipArchive<T>,
     dest: &mut W,
+    limits: &UnpackLimits,
+    watch: &mut dyn FnMut(&UnpackProgress) -> Result<()>,
 ) -> Result<()> {
+    let mut budget = UnpackBudget::new(limits);
+    // zip files have a central directory, so we can reject ones with too many entries
+    // before writing anything
+    budget.add_entries(z.len() as u64)?;
+    let mut compressed_so_far = 0u64;
     // we process symlinks in a batch at the end
     let mut symlinks = Vec::<NiceSymlinkPaths>::new();
     // indices is sorted from end to start; flip it back around when iterating to get
     // better locality on our reads.
+    let mut progress = UnpackProgress {
+        total_entries: Some(z.len() as u64),
+        ..Default::default()
+    };
     for i in 0..z.len() {
+        progress.entries = i as u64;
+        progress.bytes = budget.bytes;
+        watch(&progress)?;
         let mut zip_file = z.by_index(i)?;
         context!("Unpacking zip file member {}", zip_file.name());
-        if let Some(mode) = zip_file.unix_mode() {
+        compressed_so_far += zip_file.compressed_size();
+        let compressed = || compressed_so_far;
+        let name = zip_file.name().to_owned();
+        let unix_mode = zip_file.unix_mode();
+        let is_dir = zip_file.is_dir();
+        let mut reader = BudgetedRead {
+            inner: &mut zip_file,
+            budget: &mut budget,
+            compressed: &compressed,
+        };
+        if let Some(mode) = unix_mode {
             if mode & 0xf000 == 0xa000 {
                 // it's a symlink
                 symlinks.push(NiceSymlinkPaths::new(
-                    &zip_file.name().try_into()?,
-                    slurp(&mut zip_file)?.as_slice(),
+                    &name.as_str().try_into()?,
+                    slurp(&mut reader)?.as_slice(),
                 )?);
                 continue;
             }
         }
-        let path: NicePathBuf = zip_file.name().try_into()?;
-        if zip_file.is_dir() {
+        let path: NicePathBuf = name.as_str().try_into()?;
+        if is_dir {
             dest.mkdir(&path)?;
         } else {
-            let executable = zip_file
-                .unix_mode()
-                .map(|v| v & 0o0111 != 0)
-                .unwrap_or(false);
-            dest.write_file(&path, &mut zip_file, executable)?;
+            let executable = unix_mode.map(|v| v & 0o0111 != 0).unwrap_or(false);
+            dest.write_file(&path, &mut reader, executable)?;
         }
     }
 
@@ -311,17 +522,33 @@ pub fn unpack_zip_carefully<T: Read + Seek, W: WriteTree>(
         dest.write_symlink(&symlink)?;
     }
 
-    Ok(())
+    progress.entries = z.len() as u64;
+    progress.bytes = budget.bytes;
+    watch(&progress)
 }
 
+/// Like unpack_zip_carefully, but for .tar.gz files.
 pub fn unpack_tar_gz_carefully<T: Read + Seek, W: WriteTree>(
     body: T,
     mut dest: W,
+    limits: &UnpackLimits,
+    watch: &mut dyn FnMut(&UnpackProgress) -> Result<()>,
 ) -> Result<()> {
-    let ungz = flate2::read::MultiGzDecoder::new(body);
+    let mut budget = UnpackBudget::new(limits);
+    let count = Rc::new(Cell::new(0));
+    let compressed = || count.get();
+    let ungz = flate2::read::MultiGzDecoder::new(CountingRead {
+        inner: body,
+        count: count.clone(),
+    });
     let mut archive = tar::Archive::new(ungz);
+    let mut progress = UnpackProgress::default();
     for entry in archive.entries()? {
+        progress.entries = budget.entries;
+        progress.bytes = budget.bytes;
+        watch(&progress)?;
         let mut entry = entry?;
+        budget.add_entries(1)?;
         let path: NicePathBuf = entry.path_bytes().deref().try_into()?;
         let kind = entry.header().entry_type();
         let is_executable = entry.header().mode()? & 0o100 != 0;
@@ -336,11 +563,18 @@ pub fn unpack_tar_gz_carefully<T: Read + Seek, W: WriteTree>(
             Directory => dest.mkdir(&path)?,
             GNULongName | GNULongLink | GNUSparse | XGlobalHeader | XHeader => (),
             _ => {
-                dest.write_file(&path, &mut entry, is_executable)?;
+                let mut reader = BudgetedRead {
+                    inner: &mut entry,
+                    budget: &mut budget,
+                    compressed: &compressed,
+                };
+                dest.write_file(&path, &mut reader, is_executable)?;
             }
         }
     }
-    Ok(())
+    progress.entries = budget.entries;
+    progress.bytes = budget.bytes;
+    watch(&progress)
 }
 
 #[cfg(test)]
@@ -405,4 +639,160 @@ mod test {
     }
 
     // XX TODO: write some tests that unpacking invalid zip files are rejected!!
+
+    // (name, contents) -> .tar.gz
+    fn tar_gz(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        let mut builder = tar::Builder::new(gz);
+        for (name, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *contents).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        for (name, contents) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn unpack_both(files: &[(&str, &[u8])], limits: &UnpackLimits) -> [Result<()>; 2] {
+        let tmp = tempfile::tempdir().unwrap();
+        let tar_result = unpack_tar_gz_carefully(
+            io::Cursor::new(tar_gz(files)),
+            WriteTreeFS::new(tmp.path().join("tar")),
+            limits,
+            &mut |_| Ok(()),
+        );
+        let mut z = ZipArchive::new(io::Cursor::new(zip(files))).unwrap();
+        let zip_result = unpack_zip_carefully(
+            &mut z,
+            &mut WriteTreeFS::new(tmp.path().join("zip")),
+            limits,
+            &mut |_| Ok(()),
+        );
+        [tar_result, zip_result]
+    }
+
+    fn assert_rejected(results: [Result<()>; 2], expected: &str) {
+        for result in results {
+            let message = format!("{:#}", result.unwrap_err());
+            assert!(message.contains(expected), "{message}");
+        }
+    }
+
+    #[test]
+    fn test_unpack_limits() {
+        let limits = UnpackLimits {
+            max_entries: 3,
+            max_bytes: 100_000,
+            max_ratio: 50,
+            ratio_grace_bytes: 10_000,
+        };
+        // xorshift, so it doesn't compress
+        let mut state = 1u32;
+        let noise: Vec<u8> = (0..30_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let noise = noise.as_slice();
+        let zeros: &[u8] = &[0; 60_000];
+        let empty: &[u8] = b"";
+
+        // under all the limits
+        for result in unpack_both(&[("a/x", b"hi"), ("a/noise", noise)], &limits) {
+            result.unwrap();
+        }
+
+        // tarbomb: too many files
+        let many = [("1", empty), ("2", empty), ("3", empty), ("4", empty)];
+        assert_rejected(unpack_both(&many, &limits), "more than 3 entries");
+
+        // too big in total, even though it doesn't compress at all
+        let big = [("a", noise), ("b", noise), ("c", noise), ("d", noise)];
+        let mut more_entries = limits.clone();
+        more_entries.max_entries = 10;
+        assert_rejected(unpack_both(&big, &more_entries), "more than 100000 bytes");
+        for result in unpack_both(&big[..3], &more_entries) {
+            result.unwrap();
+        }
+
+        // decompression bomb: small enough, but compresses suspiciously well
+        assert_rejected(
+            unpack_both(&[("zeros", zeros)], &limits),
+            "more than 50x its compressed size",
+        );
+        // ...which is fine if it's not much data
+        let mut more_grace = limits;
+        more_grace.ratio_grace_bytes = 100_000;
+        for result in unpack_both(&[("zeros", zeros)], &more_grace) {
+            result.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_unpack_watch() {
+        let files: &[(&str, &[u8])] = &[("a", b"aaa"), ("b", b"bb"), ("c", b"c")];
+        let tmp = tempfile::tempdir().unwrap();
+        let limits = UnpackLimits::default();
+
+        let mut seen = Vec::new();
+        let mut z = ZipArchive::new(io::Cursor::new(zip(files))).unwrap();
+        unpack_zip_carefully(
+            &mut z,
+            &mut WriteTreeFS::new(tmp.path().join("all")),
+            &limits,
+            &mut |progress| {
+                seen.push(*progress);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            seen.iter()
+                .map(|p| (p.entries, p.bytes))
+                .collect::<Vec<_>>(),
+            vec![(0, 0), (1, 3), (2, 5), (3, 6)]
+        );
+        assert!(seen.iter().all(|p| p.total_entries == Some(3)));
+
+        // stopping partway, like we do on Ctrl-C
+        let mut seen = Vec::new();
+        let result = unpack_tar_gz_carefully(
+            io::Cursor::new(tar_gz(files)),
+            WriteTreeFS::new(tmp.path().join("some")),
+            &limits,
+            &mut |progress| {
+                seen.push(*progress);
+                if progress.entries == 2 {
+                    bail!("stop!");
+                }
+                Ok(())
+            },
+        );
+        assert!(result.is_err());
+        assert!(tmp.path().join("some/b").exists());
+        assert!(!tmp.path().join("some/c").exists());
+        assert_eq!(
+            seen.last(),
+            Some(&UnpackProgress {
+                entries: 2,
+                total_entries: None,
+                bytes: 5,
+            })
+        );
+    }
 }
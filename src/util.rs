@@ -1,3 +1,7 @@
+use std::path::Path;
+
+use crate::prelude::*;
+
 /// Work around an annoyance in Rust's standard traits -- if you define
 /// TryFrom<&str>, then you probably also want TryFrom<String> and FromStr,
 /// and the implementation is trivial in terms of TryFrom<&str>. So this macro
@@ -23,6 +27,15 @@ macro_rules! try_from_str_boilerplate {
     };
 }
 
+// Undoes %XX escapes (and only those -- '+' stays a '+'), e.g. in a filename taken from
+// a URL
+pub fn percent_decode(s: &str) -> Result<String> {
+    Ok(percent_encoding::percent_decode_str(s)
+        .decode_utf8()
+        .wrap_err_with(|| format!("{s:?} isn't valid UTF-8 once it's percent-decoded"))?
+        .into_owned())
+}
+
 pub fn retry_interrupted<F, T>(mut f: F) -> std::io::Result<T>
 where
     F: FnMut() -> std::io::Result<T>,
@@ -39,3 +52,21 @@ where
         }
     }
 }
+
+/// The total size of the files at or under `path`. Symlinks count as themselves, not
+/// whatever they point to, and if there's nothing there at all, that's 0.
+pub fn tree_size(path: &Path) -> Result<u64> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut total = 0;
+    for entry in std::fs::read_dir(path)? {
+        total += tree_size(&entry?.path())?;
+    }
+    Ok(total)
+}
@@ -1,10 +1,14 @@
 use super::rfc822ish::RFC822ish;
-use crate::package_db::ArtifactInfo;
+use crate::package_db::{ArtifactInfo, BuildProvenance, BUILD_PROVENANCE_NAME};
 use crate::prelude::*;
 use crate::trampolines::{ScriptType, TrampolineMaker};
-use crate::tree::{unpack_tar_gz_carefully, unpack_zip_carefully, WriteTree};
+use crate::tree::{
+    unpack_tar_gz_carefully, unpack_zip_carefully, watch_unpack, UnpackLimits,
+    WriteTree,
+};
 use std::cell::RefCell;
 use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 use zip::ZipArchive;
 
 // probably should:
@@ -20,7 +24,8 @@ use zip::ZipArchive;
 // generation/#!python fixing.
 //
 // ...oh yeah there are also direct URL references, which might point to source trees. I
-// guess that's a 4th artifact type? Or a variant on Sdist? not sure.
+// guess that's a 4th artifact type? Or a variant on Sdist? not sure. (For now we only
+// handle source trees on the local filesystem; see LocalTree.)
 
 pub struct Sdist {
     name: SdistName,
@@ -64,15 +69,82 @@ impl Sdist {
         context!("Unpacking {}", self.name);
         let mut boxed = self.body.borrow_mut();
         let body = boxed.as_mut();
+        let limits = UnpackLimits::from_env()?;
+        let mut watch = watch_unpack(&self.name);
         match self.name.format {
-            SdistFormat::Zip => {
-                unpack_zip_carefully(&mut ZipArchive::new(body)?, destination)
+            SdistFormat::Zip => unpack_zip_carefully(
+                &mut ZipArchive::new(body)?,
+                destination,
+                &limits,
+                &mut watch,
+            ),
+            SdistFormat::TarGz => {
+                unpack_tar_gz_carefully(body, destination, &limits, &mut watch)
             }
-            SdistFormat::TarGz => unpack_tar_gz_carefully(body, destination),
         }
     }
 }
 
+/// A source tree on the local filesystem, from a requirement like `-e ./mypkg`. It's
+/// built the same way as an sdist, except there's nothing to download or unpack. It has
+/// no hash either: the user can edit it whenever they like, so anything we build from
+/// it has to stay out of the long-lived caches.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LocalTree {
+    // always absolute
+    pub path: PathBuf,
+    // if set, we install it with PEP 660's build_editable, so the installed package
+    // points back into the tree and picks up changes without a rebuild
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub editable: bool,
+}
+
+impl LocalTree {
+    pub fn new(path: &Path, editable: bool) -> Result<LocalTree> {
+        let path = std::fs::canonicalize(path)
+            .wrap_err_with(|| format!("can't find source tree {}", path.display()))?;
+        if !path.join("pyproject.toml").exists() && !path.join("setup.py").exists() {
+            bail!(
+                "{} doesn't look like a Python project (no pyproject.toml or setup.py)",
+                path.display()
+            );
+        }
+        Ok(LocalTree { path, editable })
+    }
+
+    /// The project name from pyproject.toml's [project] table, if it has one. (PEP 621
+    /// doesn't allow the name to be dynamic, so this is always the real name.)
+    pub fn static_name(&self) -> Result<Option<PackageName>> {
+        let pyproject = match std::fs::read_to_string(self.path.join("pyproject.toml"))
+        {
+            Ok(pyproject) => pyproject,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let doc: toml_edit::Document = pyproject.parse()?;
+        match doc
+            .get("project")
+            .and_then(|project| project.get("name"))
+            .and_then(|name| name.as_str())
+        {
+            Some(name) => Ok(Some(name.parse()?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn url(&self) -> Url {
+        // unwrap is safe b/c the path is absolute
+        Url::from_file_path(&self.path).unwrap()
+    }
+
+    // Key for the build store and EnvForest. Editable and regular builds of the same
+    // tree give different wheels, so they get separate entries.
+    pub fn store_key(&self) -> Vec<u8> {
+        format!("local-tree:{}:{}", self.editable, self.path.display()).into_bytes()
+    }
+}
+
 impl Artifact for Wheel {
     type Name = WheelName;
 
@@ -152,8 +224,15 @@ fn parse_format_metadata_and_check_version(
     let input: &str = std::str::from_utf8(input)?;
     let mut parsed = RFC822ish::parse(input)?;
 
+    // Same rule as for Metadata-Version: a new major version means we can't read it,
+    // a new minor version means we can. Compare the parsed version rather than the
+    // string, so e.g. "1" or "1.10" don't trip us up.
     let version = parsed.take_the(version_field)?;
-    if !version.starts_with("1.") {
+    let parsed_version: Version = version
+        .trim()
+        .try_into()
+        .wrap_err_with(|| format!("bad {version_field}: {version:?}"))?;
+    if parsed_version.0.release.first() != Some(&1) {
         bail!("unsupported {}: {:?}", version_field, version);
     }
 
@@ -416,7 +495,12 @@ impl Pybi {
     pub fn unpack<T: WriteTree>(&self, destination: &mut T) -> Result<()> {
         context!("Unpacking {}", self.name);
         // XX TODO RECORD?
-        unpack_zip_carefully(&mut self.z.borrow_mut(), destination)
+        unpack_zip_carefully(
+            &mut self.z.borrow_mut(),
+            destination,
+            &UnpackLimits::from_env()?,
+            &mut watch_unpack(&self.name),
+        )
     }
 }
 
@@ -461,8 +545,12 @@ impl Wheel {
             dest: &mut dest,
             vitals: &vitals,
         };
-        let mut z = self.z.borrow_mut();
-        unpack_zip_carefully(&mut z, &mut transformer)?;
+        unpack_zip_carefully(
+            &mut self.z.borrow_mut(),
+            &mut transformer,
+            &UnpackLimits::from_env()?,
+            &mut watch_unpack(&self.name),
+        )?;
         let mut installer: &[u8] = b"posy\n";
         transformer.write_file(
             &format!("{}/INSTALLER", vitals.dist_info)
@@ -473,33 +561,52 @@ impl Wheel {
             false,
         )?;
 
-        if let Ok(entry_points) = slurp_from_zip(
-            &mut z,
-            format!("{}/{}", vitals.dist_info, "entry_points.txt").as_str(),
-        ) {
-            let entry_points = parse_entry_points(std::str::from_utf8(&entry_points)?)?;
-
-            let mut write_scripts = |name, script_type| -> Result<()> {
-                if let Some(script_entrypoints) = entry_points.get(name) {
-                    for entrypoint in script_entrypoints {
-                        let body = script_for_entrypoint(entrypoint, script_type);
-                        let name =
-                            format!("{}/scripts/{}", vitals.data, entrypoint.name);
-                        transformer.write_file(
-                            &name.try_into()?,
-                            &mut &body[..],
-                            true,
-                        )?;
-                    }
+        let entry_points = self.read_entry_points(&vitals.dist_info)?;
+
+        let mut write_scripts = |name, script_type| -> Result<()> {
+            if let Some(script_entrypoints) = entry_points.get(name) {
+                for entrypoint in script_entrypoints {
+                    let body = script_for_entrypoint(entrypoint, script_type);
+                    let name = format!("{}/scripts/{}", vitals.data, entrypoint.name);
+                    transformer.write_file(&name.try_into()?, &mut &body[..], true)?;
                 }
-                Ok(())
-            };
+            }
+            Ok(())
+        };
 
-            write_scripts("console_scripts", ScriptType::Console)?;
-            write_scripts("gui_scripts", ScriptType::GUI)?;
-        }
+        write_scripts("console_scripts", ScriptType::Console)?;
+        write_scripts("gui_scripts", ScriptType::GUI)?;
         Ok(())
     }
+
+    /// The parsed contents of the wheel's entry_points.txt, or an empty map if it
+    /// doesn't have one.
+    pub fn entry_points(&self) -> Result<HashMap<String, Vec<Entrypoint>>> {
+        let vitals = self.get_vitals()?;
+        self.read_entry_points(&vitals.dist_info)
+    }
+
+    /// How posy built this wheel, if it's one that posy built from an sdist.
+    pub fn build_provenance(&self) -> Result<Option<BuildProvenance>> {
+        let vitals = self.get_vitals()?;
+        let mut z = self.z.borrow_mut();
+        let path = format!("{}/{BUILD_PROVENANCE_NAME}", vitals.dist_info);
+        match slurp_from_zip(&mut z, &path) {
+            Ok(blob) => Ok(Some(serde_json::from_slice(&blob)?)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn read_entry_points(
+        &self,
+        dist_info: &str,
+    ) -> Result<HashMap<String, Vec<Entrypoint>>> {
+        let mut z = self.z.borrow_mut();
+        match slurp_from_zip(&mut z, &format!("{dist_info}/entry_points.txt")) {
+            Ok(entry_points) => parse_entry_points(std::str::from_utf8(&entry_points)?),
+            Err(_) => Ok(HashMap::new()),
+        }
+    }
 }
 
 struct WheelTreeTransformer<'a, W: WriteTree> {
@@ -604,3 +711,35 @@ where
         bail!("symlinks not supported in wheels");
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_version_check() {
+        for good in ["1.0", "1.1", "1.9", "1", " 1.0 "] {
+            let input = format!("Wheel-Version: {good}\nRoot-Is-Purelib: true\n");
+            let mut parsed = parse_format_metadata_and_check_version(
+                input.as_bytes(),
+                "Wheel-Version",
+            )
+            .unwrap();
+            // the version field is consumed, everything else is left for the caller
+            assert!(parsed.maybe_take_the("Wheel-Version").unwrap().is_none());
+            assert_eq!(parsed.take_the("Root-Is-Purelib").unwrap(), "true");
+        }
+        for bad in ["2.0", "0.9", "banana", ""] {
+            let input = format!("Pybi-Version: {bad}\n");
+            assert!(parse_format_metadata_and_check_version(
+                input.as_bytes(),
+                "Pybi-Version"
+            )
+            .is_err());
+        }
+        assert!(
+            parse_format_metadata_and_check_version(b"Foo: 1.0\n", "Pybi-Version")
+                .is_err()
+        );
+    }
+}
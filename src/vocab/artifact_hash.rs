@@ -9,7 +9,8 @@ pub struct ArtifactHash {
 impl ArtifactHash {
     pub fn from_hex(mode: &str, hex: &str) -> Result<ArtifactHash> {
         Ok(ArtifactHash {
-            mode: mode.into(),
+            // some indexes send "SHA256=..."
+            mode: mode.to_ascii_lowercase(),
             raw_data: data_encoding::HEXLOWER_PERMISSIVE.decode(hex.as_bytes())?,
         })
     }
@@ -88,6 +88,10 @@ pub struct WheelName {
 
 pub trait BinaryName {
     fn all_tags(&self) -> HashSet<String>;
+
+    /// The tags as they appear in the filename, e.g. "py2.py3-none-any". Two names
+    /// with the same compressed tags have the same all_tags().
+    fn compressed_tags(&self) -> String;
 }
 
 impl BinaryName for WheelName {
@@ -102,6 +106,15 @@ impl BinaryName for WheelName {
         }
         retval
     }
+
+    fn compressed_tags(&self) -> String {
+        format!(
+            "{}-{}-{}",
+            self.py_tags.join("."),
+            self.abi_tags.join("."),
+            self.arch_tags.join(".")
+        )
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
@@ -117,6 +130,10 @@ impl BinaryName for PybiName {
     fn all_tags(&self) -> HashSet<String> {
         self.arch_tags.iter().cloned().collect()
     }
+
+    fn compressed_tags(&self) -> String {
+        self.arch_tags.join(".")
+    }
 }
 
 fn generic_parse(
@@ -154,7 +171,17 @@ fn generic_parse(
         }
         // unwrap safe because: the regex cannot fail
         let captures = BUILD_TAG_SPLIT.captures(build_tag).unwrap();
-        build_number = captures.get(1).and_then(|m| m.as_str().parse().ok());
+        // unwrap safe because: same as below
+        let digits = captures.get(1).unwrap().as_str();
+        build_number = if digits.is_empty() {
+            None
+        } else {
+            Some(
+                digits
+                    .parse()
+                    .wrap_err_with(|| format!("build number too large: {value:?}"))?,
+            )
+        };
         // unwrap safe because: this group will always match something, even
         // if only the empty string
         build_name = captures.get(2).unwrap().as_str().into();
@@ -442,4 +469,12 @@ mod test {
 
         assert_eq!(n.to_string(), "foo.bar-0.1b3-1local-win32.win_amd64.pybi");
     }
+
+    #[test]
+    fn test_huge_build_number() {
+        assert!(WheelName::try_from("foo-1.0-99999999999x-py3-none-any.whl").is_err());
+        let n: WheelName = "foo-1.0-4294967295x-py3-none-any.whl".try_into().unwrap();
+        assert_eq!(n.build_number, Some(u32::MAX));
+        assert_eq!(n.build_name, "x");
+    }
 }
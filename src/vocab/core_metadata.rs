@@ -12,6 +12,10 @@ pub struct WheelCoreMetadata {
     pub requires_dist: Vec<PackageRequirement>,
     pub requires_python: Specifiers,
     pub extras: HashSet<Extra>,
+    // Not used for resolving, just for telling people what they're getting
+    // (e.g. 'posy lock --metadata-only').
+    pub license: Option<String>,
+    pub classifiers: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -31,6 +35,30 @@ impl PybiCoreMetadata {
             .get(key)
             .ok_or(eyre!("bad pybi: no '{key}' path"))
     }
+
+    /// Whether this is a regular or free-threaded build, going by the abi tags in its
+    /// Pybi-Wheel-Tag list.
+    pub fn abi_variant(&self) -> Result<AbiVariant> {
+        let variants: HashSet<AbiVariant> = self
+            .tags
+            .iter()
+            // abi3 doesn't tell us anything: free-threaded builds shouldn't list it,
+            // but if one does, wheel_platform will drop it anyway.
+            .filter(|tag| tag.split('-').nth(1) != Some("abi3"))
+            .filter_map(|tag| AbiVariant::of_tag(tag))
+            .collect();
+        if variants.len() > 1 {
+            bail!(
+                "bad pybi: wheel tags mix free-threaded and regular ABIs: {}",
+                self.tags.join(", ")
+            );
+        }
+        Ok(if variants.contains(&AbiVariant::FreeThreaded) {
+            AbiVariant::FreeThreaded
+        } else {
+            AbiVariant::Default
+        })
+    }
 }
 
 fn parse_common(input: &[u8]) -> Result<(PackageName, Version, RFC822ish)> {
@@ -57,7 +85,15 @@ fn parse_common(input: &[u8]) -> Result<(PackageName, Version, RFC822ish)> {
     // pip does), and new metadata releases are so rare and so
     // much-discussed beforehand that if a tool's authors don't know
     // about it it's because the tool is abandoned anyway.
-    let metadata_version: Version = parsed.take_the("Metadata-Version")?.try_into()?;
+    //
+    // As of this writing the latest is 2.4. None of the fields added since 2.1 change
+    // anything we read: 2.2 added Dynamic (which only means something in sdists),
+    // 2.3 made extra names normalized (we normalize them anyway), and 2.4 added
+    // License-Expression and License-File. We read License-Expression if it's there,
+    // and otherwise they just get ignored along with everything else we don't care
+    // about.
+    let metadata_version: Version =
+        parsed.take_the("Metadata-Version")?.trim().try_into()?;
     if metadata_version >= *NEXT_MAJOR_METADATA_VERSION {
         bail!("unsupported Metadata-Version {}", metadata_version);
     }
@@ -80,9 +116,10 @@ impl TryFrom<&[u8]> for WheelCoreMetadata {
             requires_dist.push(req_str.try_into()?);
         }
 
+        // an empty Requires-Python: is the same as not having one
         let requires_python = match parsed.maybe_take_the("Requires-Python")? {
-            Some(rp_str) => rp_str.try_into()?,
-            None => Specifiers(Vec::new()),
+            Some(rp_str) if !rp_str.trim().is_empty() => rp_str.trim().try_into()?,
+            _ => Specifiers(Vec::new()),
         };
 
         let mut extras: HashSet<Extra> = HashSet::new();
@@ -90,12 +127,22 @@ impl TryFrom<&[u8]> for WheelCoreMetadata {
             extras.insert(extra.parse()?);
         }
 
+        // License-Expression is the SPDX one from 2.4; License is free text, and
+        // setuptools used to fill it in with "UNKNOWN"
+        let license_expression = parsed.maybe_take_the("License-Expression")?;
+        let license = license_expression
+            .or(parsed.maybe_take_the("License")?)
+            .map(|license| license.trim().to_string())
+            .filter(|license| !license.is_empty() && license != "UNKNOWN");
+
         Ok(WheelCoreMetadata {
             name,
             version,
             requires_dist,
             requires_python,
             extras,
+            license,
+            classifiers: parsed.take_all("Classifier"),
         })
     }
 }
@@ -106,7 +153,7 @@ impl TryFrom<&[u8]> for PybiCoreMetadata {
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
         let (name, version, mut parsed) = parse_common(value)?;
 
-        Ok(PybiCoreMetadata {
+        let metadata = PybiCoreMetadata {
             name,
             version,
             environment_marker_variables: serde_json::from_str(
@@ -114,7 +161,10 @@ impl TryFrom<&[u8]> for PybiCoreMetadata {
             )?,
             tags: parsed.take_all("Pybi-Wheel-Tag"),
             paths: serde_json::from_str(&parsed.take_the("Pybi-Paths")?)?,
-        })
+        };
+        // check now, so a broken pybi can't get as far as picking wheels
+        metadata.abi_variant()?;
+        Ok(metadata)
     }
 }
 
@@ -153,10 +203,80 @@ mod test {
           ],
           requires_python: ">= 3.6",
           extras: [],
+          license: None,
+          classifiers: [
+            "Framework :: Trio",
+          ],
         )
         "###);
     }
 
+    #[test]
+    fn test_metadata_versions() {
+        // what different Metadata-Versions look like in the wild, more or less
+        let header = |version: &str| {
+            format!("Metadata-Version: {version}\nName: Some_Pkg\nVersion: 1.0\n")
+        };
+        let cases = [
+            ("1.0", "Summary: old school\n"),
+            ("1.1", "Classifier: Programming Language :: Python\n"),
+            ("1.2", "Requires-Python: >=3.7\nRequires-Dist: attrs\n"),
+            (
+                "2.1",
+                "Requires-Dist: attrs\nProvides-Extra: Test_Stuff\n\
+                 Description-Content-Type: text/markdown\n",
+            ),
+            ("2.2", "Requires-Dist: attrs\nDynamic: Requires-Dist\n"),
+            (
+                "2.3",
+                "Requires-Dist: attrs\nRequires-Dist: pytest; extra == 'test-stuff'\n\
+                 Provides-Extra: test-stuff\n",
+            ),
+            (
+                "2.4",
+                "Requires-Python: >=3.8 \nRequires-Dist: attrs\n\
+                 License-Expression: MIT OR Apache-2.0\n\
+                 License-File: LICENSE.MIT\nLicense-File: LICENSE.APACHE\n",
+            ),
+        ];
+        for (version, rest) in cases {
+            let text = format!("{}{rest}\nLong description here.\n", header(version));
+            let metadata = WheelCoreMetadata::try_from(text.as_bytes()).unwrap();
+            assert_eq!(metadata.name.normalized(), "some-pkg");
+            assert_eq!(metadata.version, "1.0".try_into().unwrap());
+            if version >= "1.2" {
+                assert_eq!(metadata.requires_dist[0].name.normalized(), "attrs");
+            }
+            if version == "2.1" || version == "2.3" {
+                let extra: Extra = "test-stuff".parse().unwrap();
+                assert!(metadata.extras.contains(&extra));
+            }
+            if version == "2.4" {
+                assert_eq!(metadata.license.as_deref(), Some("MIT OR Apache-2.0"));
+            }
+        }
+
+        // the free-text kind, and setuptools' placeholder for "didn't say"
+        let text = header("2.1") + "License: BSD\nClassifier: Framework :: Trio\n";
+        let metadata = WheelCoreMetadata::try_from(text.as_bytes()).unwrap();
+        assert_eq!(metadata.license.as_deref(), Some("BSD"));
+        assert_eq!(metadata.classifiers, ["Framework :: Trio"]);
+        let text = header("2.1") + "License: UNKNOWN\n";
+        let metadata = WheelCoreMetadata::try_from(text.as_bytes()).unwrap();
+        assert_eq!(metadata.license, None);
+
+        // empty Requires-Python is the same as none
+        let text = header("2.1") + "Requires-Python: \n";
+        let metadata = WheelCoreMetadata::try_from(text.as_bytes()).unwrap();
+        assert!(metadata.requires_python.0.is_empty());
+
+        // a new major version could mean anything, so we have to give up
+        for version in ["3.0", "3", "10.1"] {
+            let text = header(version);
+            assert!(WheelCoreMetadata::try_from(text.as_bytes()).is_err());
+        }
+    }
+
     #[test]
     fn test_basic_pybi_parse() {
         let metadata_text = indoc! {r#"
@@ -198,5 +318,34 @@ mod test {
         )
         "###
         );
+        assert_eq!(metadata.abi_variant().unwrap(), AbiVariant::Default);
+    }
+
+    #[test]
+    fn test_freethreaded_pybi_parse() {
+        let metadata_text = indoc! {r#"
+            Metadata-Version: 2.1
+            Name: CPython
+            Version: 3.13.0
+            Pybi-Environment-Marker-Variables: {}
+            Pybi-Wheel-Tag: cp313-cp313t-PLATFORM
+            Pybi-Wheel-Tag: py3-none-any
+            Pybi-Paths: {}
+        "#}
+        .as_bytes();
+        let metadata: PybiCoreMetadata = metadata_text.try_into().unwrap();
+        assert_eq!(metadata.abi_variant().unwrap(), AbiVariant::FreeThreaded);
+
+        let mixed_text = indoc! {r#"
+            Metadata-Version: 2.1
+            Name: CPython
+            Version: 3.13.0
+            Pybi-Environment-Marker-Variables: {}
+            Pybi-Wheel-Tag: cp313-cp313t-PLATFORM
+            Pybi-Wheel-Tag: cp313-cp313-PLATFORM
+            Pybi-Paths: {}
+        "#}
+        .as_bytes();
+        assert!(PybiCoreMetadata::try_from(mixed_text).is_err());
     }
 }
@@ -13,7 +13,9 @@ mod version;
 
 // All this stuff is also re-exported from crate::prelude::*
 
-pub use self::artifact_formats::{Artifact, BinaryArtifact, Pybi, Sdist, Wheel};
+pub use self::artifact_formats::{
+    Artifact, BinaryArtifact, LocalTree, Pybi, Sdist, Wheel,
+};
 pub use self::artifact_hash::ArtifactHash;
 pub use self::artifact_name::{
     ArtifactName, BinaryName, PybiName, SdistFormat, SdistName, UnwrapFromArtifactName,
@@ -24,8 +26,8 @@ pub use self::entry_points::{parse_entry_points, Entrypoint};
 pub use self::extra::Extra;
 pub use self::package_name::PackageName;
 pub use self::requirement::{
-    marker, PackageRequirement, PythonRequirement, Requirement, StandaloneMarkerExpr,
-    UserRequirement,
+    marker, LocalRequirement, PackageRequirement, PythonRequirement, Requirement,
+    StandaloneMarkerExpr, UserRequirement,
 };
 pub use self::specifier::{CompareOp, Specifier, Specifiers};
 pub use self::version::{Version, VERSION_INFINITY, VERSION_ZERO};
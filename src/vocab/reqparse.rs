@@ -3,6 +3,37 @@ use crate::prelude::*;
 pub use self::parser::{marker, requirement, versionspec};
 use super::requirement::ParseExtra;
 
+// Metadata comes from strangers, and everything that walks a marker expression
+// (parsing it, evaluating it, printing it, even dropping it) recurses once per level.
+// So we limit both how deeply the parentheses nest, and how tall the resulting tree
+// is. Real markers only need a handful of levels. The tree limit also keeps Display,
+// which parenthesizes every 'and'/'or', from writing out anything we can't read back.
+const MAX_MARKER_NESTING: usize = 256;
+
+type Combine =
+    fn(Box<marker::EnvMarkerExpr>, Box<marker::EnvMarkerExpr>) -> marker::EnvMarkerExpr;
+
+// a and b and c -> And(a, And(b, c)), without recursing once per term while parsing.
+// The usizes are tree heights.
+fn right_nested(
+    first: (marker::EnvMarkerExpr, usize),
+    rest: Vec<(marker::EnvMarkerExpr, usize)>,
+    combine: Combine,
+) -> Result<(marker::EnvMarkerExpr, usize), &'static str> {
+    let mut exprs = rest;
+    exprs.insert(0, first);
+    // unwrap safe because: we just put something in
+    let (mut nested, mut height) = exprs.pop().unwrap();
+    while let Some((expr, expr_height)) = exprs.pop() {
+        nested = combine(Box::new(expr), Box::new(nested));
+        height = 1 + height.max(expr_height);
+        if height > MAX_MARKER_NESTING {
+            return Err("marker expression nested too deeply");
+        }
+    }
+    Ok((nested, height))
+}
+
 peg::parser! {
     grammar parser() for str {
         rule wsp()
@@ -109,9 +140,17 @@ peg::parser! {
                    / python_str())
               { v }
 
-        rule marker_expr(parse_extra: ParseExtra) -> marker::EnvMarkerExpr
-            = _ "(" m:marker(parse_extra) _ ")" { m }
-              / lhs:marker_value(parse_extra) op:marker_op() rhs:marker_value(parse_extra)
+        rule nested(depth: usize) -> usize
+            = {?
+                if depth < MAX_MARKER_NESTING {
+                    Ok(depth + 1)
+                } else {
+                    Err("marker expression nested too deeply")
+                }
+              }
+
+        rule marker_comparison(parse_extra: ParseExtra) -> marker::EnvMarkerExpr
+            = lhs:marker_value(parse_extra) op:marker_op() rhs:marker_value(parse_extra)
               {
                   use marker::EnvMarkerExpr::Operator;
                   use CompareOp::*;
@@ -130,18 +169,27 @@ peg::parser! {
                   }
               }
 
-        rule marker_and(parse_extra: ParseExtra) -> marker::EnvMarkerExpr
-            = lhs:marker_expr(parse_extra) _ "and" _ rhs:marker_and(parse_extra)
-                 { marker::EnvMarkerExpr::And(Box::new(lhs), Box::new(rhs)) }
-              / marker_expr(parse_extra)
-
-        rule marker_or(parse_extra: ParseExtra) -> marker::EnvMarkerExpr
-            = lhs:marker_and(parse_extra) _ "or" _ rhs:marker_or(parse_extra)
-                 { marker::EnvMarkerExpr::Or(Box::new(lhs), Box::new(rhs)) }
-              / marker_and(parse_extra)
+        rule marker_expr(parse_extra: ParseExtra, depth: usize)
+            -> (marker::EnvMarkerExpr, usize)
+            = _ "(" d:nested(depth) m:marker_or(parse_extra, d) _ ")" { m }
+              / c:marker_comparison(parse_extra) { (c, 1) }
+
+        // Written as lists rather than 'lhs and rhs / lhs', because backtracking
+        // like that re-parses lhs, which doubles at each level of parentheses.
+        rule marker_and(parse_extra: ParseExtra, depth: usize)
+            -> (marker::EnvMarkerExpr, usize)
+            = first:marker_expr(parse_extra, depth)
+              rest:(_ "and" _ e:marker_expr(parse_extra, depth) { e })*
+              {? right_nested(first, rest, marker::EnvMarkerExpr::And) }
+
+        rule marker_or(parse_extra: ParseExtra, depth: usize)
+            -> (marker::EnvMarkerExpr, usize)
+            = first:marker_and(parse_extra, depth)
+              rest:(_ "or" _ e:marker_and(parse_extra, depth) { e })*
+              {? right_nested(first, rest, marker::EnvMarkerExpr::Or) }
 
         pub rule marker(parse_extra: ParseExtra) -> marker::EnvMarkerExpr
-            = marker_or(parse_extra)
+            = m:marker_or(parse_extra, 0) { m.0 }
 
         rule quoted_marker(parse_extra: ParseExtra) -> marker::EnvMarkerExpr
             = ";" _ m:marker(parse_extra) { m }
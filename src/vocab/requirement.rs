@@ -1,4 +1,5 @@
 use crate::prelude::*;
+use std::path::{Path, PathBuf};
 
 // There are two kinds of special exact version constraints that aren't often
 // used, and whose semantics are a bit unclear:
@@ -314,11 +315,20 @@ try_from_str_boilerplate!(UserRequirement);
 #[derive(
     Shrinkwrap, Debug, Clone, PartialEq, Eq, DeserializeFromStr, SerializeDisplay,
 )]
-pub struct PythonRequirement(Requirement);
+pub struct PythonRequirement {
+    #[shrinkwrap(main_field)]
+    req: Requirement,
+    // From 'NAME @ URL': use this exact pybi, instead of looking for one on the index.
+    // E.g. for teams that build their own interpreters. Always has a #sha256= hash.
+    pub url: Option<Url>,
+}
 
 impl Display for PythonRequirement {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        match &self.url {
+            Some(url) => write!(f, "{} @ {}", self.req.name.as_given(), url),
+            None => self.req.fmt(f),
+        }
     }
 }
 
@@ -335,7 +345,7 @@ impl TryFrom<Requirement> for PythonRequirement {
                 r
             );
         }
-        Ok(PythonRequirement(r))
+        Ok(PythonRequirement { req: r, url: None })
     }
 }
 
@@ -343,6 +353,29 @@ impl TryFrom<&str> for PythonRequirement {
     type Error = eyre::Report;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
+        if let Some((name_part, url)) = value.split_once('@') {
+            let r = Requirement::parse(name_part.trim(), ParseExtra::NotAllowed)?;
+            if !r.specifiers.0.is_empty() {
+                bail!("expected 'NAME @ URL', not {value:?}");
+            }
+            let url = Url::parse(url.trim())?;
+            // check these up front, so mistakes show up when reading the config, not
+            // halfway through a resolve
+            let filename = url.path_segments().and_then(|mut s| s.next_back());
+            if PybiName::try_from(filename.unwrap_or_default()).is_err() {
+                bail!("{url} doesn't point to a .pybi file");
+            }
+            if url
+                .fragment()
+                .and_then(|f| ArtifactHash::try_from(f).ok())
+                .is_none()
+            {
+                bail!("{url} needs a hash, like #sha256=...");
+            }
+            let mut python: PythonRequirement = r.try_into()?;
+            python.url = Some(url);
+            return Ok(python);
+        }
         let r = Requirement::parse(value, ParseExtra::NotAllowed)?;
         r.try_into()
     }
@@ -350,6 +383,114 @@ impl TryFrom<&str> for PythonRequirement {
 
 try_from_str_boilerplate!(PythonRequirement);
 
+/// A requirement on a source tree on the local filesystem, like `-e ./mypkg` or
+/// `mypkg[extra] @ file:///path/to/mypkg`. These can't be Requirements, because they
+/// name a specific tree instead of a set of versions (see the comment on '@' at the
+/// top of this file).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LocalRequirement {
+    pub name: PackageName,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extras: Vec<Extra>,
+    #[serde(flatten)]
+    pub tree: LocalTree,
+}
+
+impl LocalRequirement {
+    /// Returns None if `input` isn't a local requirement at all, so the caller can
+    /// parse it as a regular one instead. Relative paths are relative to `base`.
+    pub fn parse(input: &str, base: &Path) -> Result<Option<LocalRequirement>> {
+        let input = input.trim();
+        let (editable, rest) = match input
+            .strip_prefix("--editable")
+            .or_else(|| input.strip_prefix("-e"))
+        {
+            Some(rest) => (true, rest.trim_start_matches('=').trim()),
+            None => (false, input),
+        };
+        context!("Parsing local requirement {:?}", input);
+
+        let (name_part, location) = match rest.split_once('@') {
+            Some((name_part, location)) => (Some(name_part.trim()), location.trim()),
+            None => (None, rest),
+        };
+        let (path, path_extras) = if location.starts_with("file:") {
+            let path = Url::parse(location)?
+                .to_file_path()
+                .map_err(|()| eyre!("{location} isn't a local path"))?;
+            (path, None)
+        } else if name_part.is_none() && (editable || looks_like_path(location)) {
+            // pip allows extras on bare paths, like './mypkg[test]'
+            match location.strip_suffix(']').and_then(|l| l.rsplit_once('[')) {
+                Some((path, extras)) => (PathBuf::from(path), Some(extras)),
+                None => (PathBuf::from(location), None),
+            }
+        } else if editable {
+            bail!("editable requirements need a local path or file:// URL");
+        } else {
+            // could be a regular requirement, or some other kind of direct reference
+            // that the regular parser will reject with a better message
+            return Ok(None);
+        };
+        let tree = LocalTree::new(&base.join(path), editable)?;
+
+        let (name, extras) = match (name_part, path_extras) {
+            (Some(name_part), _) => {
+                let req = Requirement::parse(name_part, ParseExtra::NotAllowed)?;
+                if !req.specifiers.0.is_empty() || req.env_marker_expr.is_some() {
+                    bail!("expected 'NAME @ URL' or 'NAME[EXTRAS] @ URL'");
+                }
+                (req.name, req.extras)
+            }
+            (None, extras) => {
+                let name = tree.static_name()?.ok_or_else(|| {
+                    eyre!(
+                        "couldn't tell what package is in {}; try 'NAME @ {}' instead",
+                        tree.path.display(),
+                        tree.url(),
+                    )
+                })?;
+                let extras = match extras {
+                    Some(extras) => extras
+                        .split(',')
+                        .map(|e| e.trim().try_into())
+                        .collect::<Result<Vec<_>>>()?,
+                    None => Vec::new(),
+                };
+                (name, extras)
+            }
+        };
+        Ok(Some(LocalRequirement { name, extras, tree }))
+    }
+}
+
+fn looks_like_path(s: &str) -> bool {
+    s.starts_with('.')
+        || s.contains('/')
+        || s.contains(std::path::MAIN_SEPARATOR)
+        || Path::new(s).is_absolute()
+}
+
+impl Display for LocalRequirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.tree.editable {
+            write!(f, "-e ")?;
+        }
+        write!(f, "{}", self.name.as_given())?;
+        if !self.extras.is_empty() {
+            let extras = self
+                .extras
+                .iter()
+                .map(|e| e.as_given())
+                .collect::<Vec<_>>()
+                .join(",");
+            write!(f, "[{extras}]")?;
+        }
+        write!(f, " @ {}", self.tree.url())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -422,6 +563,87 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_python_requirement_direct_url() {
+        let url = format!(
+            "https://example.com/builds/cpython-3.12.2-manylinux_2_28_x86_64.pybi\
+             #sha256={}",
+            "ab".repeat(32)
+        );
+        let pr: PythonRequirement = format!("cpython-custom @ {url}").parse().unwrap();
+        assert_eq!(pr.name.as_given(), "cpython-custom");
+        assert_eq!(pr.url.as_ref().unwrap().as_str(), url);
+        assert!(pr.specifiers.0.is_empty());
+        assert_eq!(pr, pr.to_string().parse().unwrap());
+
+        let pr: PythonRequirement = "cpython >= 3.10".parse().unwrap();
+        assert!(pr.url.is_none());
+
+        // no hash
+        assert!(PythonRequirement::try_from(
+            "cpython @ https://example.com/cpython-3.12.2-manylinux_2_28_x86_64.pybi"
+        )
+        .is_err());
+        // not a pybi
+        assert!(PythonRequirement::try_from(
+            format!(
+                "cpython @ https://example.com/cpython-3.12.2.tar.gz#sha256={}",
+                "ab".repeat(32)
+            )
+            .as_str()
+        )
+        .is_err());
+        assert!(
+            PythonRequirement::try_from(format!("cpython >= 3 @ {url}").as_str())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_local_requirement_parse() {
+        let tmp = tempfile::tempdir().unwrap();
+        let pkg = tmp.path().join("mypkg");
+        std::fs::create_dir(&pkg).unwrap();
+        std::fs::write(
+            pkg.join("pyproject.toml"),
+            "[project]\nname = \"My_Pkg\"\nversion = \"1.0\"\n",
+        )
+        .unwrap();
+        let pkg = std::fs::canonicalize(pkg).unwrap();
+        let parse = |s: &str| LocalRequirement::parse(s, tmp.path());
+
+        let r = parse("-e ./mypkg").unwrap().unwrap();
+        assert_eq!(r.name, "my-pkg".parse().unwrap());
+        assert!(r.tree.editable);
+        assert_eq!(r.tree.path, pkg);
+        assert!(r.extras.is_empty());
+
+        let r = parse("./mypkg[test, docs]").unwrap().unwrap();
+        assert!(!r.tree.editable);
+        assert_eq!(r.extras.len(), 2);
+
+        let url = Url::from_file_path(&pkg).unwrap();
+        let r = parse(&format!("other[x] @ {url}")).unwrap().unwrap();
+        assert_eq!(r.name, "other".parse().unwrap());
+        assert_eq!(r.extras, vec![Extra::try_from("x").unwrap()]);
+        assert_eq!(r.tree.path, pkg);
+        assert_eq!(r.to_string(), format!("other[x] @ {url}"));
+
+        // round-trips through Display
+        let r = parse("--editable=mypkg").unwrap().unwrap();
+        assert_eq!(parse(&r.to_string()).unwrap().unwrap(), r);
+
+        // regular requirements are left alone
+        assert!(parse("trio >= 0.20").unwrap().is_none());
+        assert!(parse("foo @ https://example.com/foo.tar.gz")
+            .unwrap()
+            .is_none());
+
+        assert!(parse("./missing").is_err());
+        assert!(parse("-e foo @ https://example.com/foo.tar.gz").is_err());
+        assert!(parse(&format!("other >= 1 @ {url}")).is_err());
+    }
+
     #[test]
     fn test_extra_normalization() {
         let r: PackageRequirement = "foo; extra == 'HeLlO' and extra in 'hElLoWorld'"
@@ -430,4 +652,31 @@ mod test {
         let env = HashMap::from([("extra", "hello")]);
         assert!(r.env_marker_expr.as_ref().unwrap().eval(&env).unwrap());
     }
+
+    #[test]
+    fn test_marker_nesting_limit() {
+        let nested = |depth: usize| {
+            format!(
+                "foo; {}os_name == 'nt'{}",
+                "(".repeat(depth),
+                ")".repeat(depth)
+            )
+        };
+        assert!(PackageRequirement::try_from(nested(100).as_str()).is_ok());
+        assert!(PackageRequirement::try_from(nested(100_000).as_str()).is_err());
+
+        // chains turn into trees just as tall, so they have the same limit
+        let chain =
+            |n: usize| format!("foo; {}", vec!["os_name == 'nt'"; n].join(" and "));
+        let r: PackageRequirement = chain(200).as_str().try_into().unwrap();
+        assert!(!r
+            .env_marker_expr
+            .as_ref()
+            .unwrap()
+            .eval(&HashMap::from([("os_name", "posix")]))
+            .unwrap());
+        // ...and Display's extra parentheses don't push it over
+        assert_eq!(r, r.to_string().as_str().try_into().unwrap());
+        assert!(PackageRequirement::try_from(chain(100_000).as_str()).is_err());
+    }
 }
@@ -1,7 +1,7 @@
 use crate::prelude::*;
 use std::ops::Range;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Specifier {
     pub op: CompareOp,
     pub value: String,
@@ -24,7 +24,7 @@ impl Display for Specifier {
 }
 
 #[derive(
-    Debug, Clone, PartialEq, Eq, SerializeDisplay, DeserializeFromStr, Default,
+    Debug, Clone, PartialEq, Eq, Hash, SerializeDisplay, DeserializeFromStr, Default,
 )]
 pub struct Specifiers(pub Vec<Specifier>);
 
@@ -57,6 +57,11 @@ impl TryFrom<&str> for Specifiers {
     type Error = eyre::Report;
 
     fn try_from(input: &str) -> Result<Self, Self::Error> {
+        // no specifiers displays as "", so that has to parse back (e.g. when a wheel
+        // with no Requires-Python goes through posy.lock)
+        if input.trim().is_empty() {
+            return Ok(Specifiers(Vec::new()));
+        }
         let specifiers_or_err = super::reqparse::versionspec(input);
         specifiers_or_err.wrap_err_with(|| {
             format!("failed to parse versions specifiers from {:?}", input)
@@ -127,6 +132,12 @@ fn parse_version_wildcard(input: &str) -> Result<(Version, bool)> {
     Ok((version, wildcard))
 }
 
+// Version pieces are u32s, and nothing stops an index from handing us 4294967295.
+pub(super) fn plus_one(n: u32) -> Result<u32> {
+    n.checked_add(1)
+        .ok_or_else(|| eyre!("version component {n} is too large"))
+}
+
 /// Converts a comparison like ">= 1.2" into a union of [half, open) ranges.
 ///
 /// Has to take a string, not a Version, because == and != can take "wildcards", which
@@ -148,16 +159,18 @@ impl CompareOp {
             // .* can actually appear after .postX or .aX, so we need to find the last
             // numeric entry in the version, and increment that.
             if let Some(post) = high.0.post {
-                high.0.post = Some(post + 1)
+                high.0.post = Some(plus_one(post)?)
             } else if let Some(pre) = high.0.pre {
                 use pep440::PreRelease::*;
                 high.0.pre = Some(match pre {
-                    RC(n) => RC(n + 1),
-                    A(n) => A(n + 1),
-                    B(n) => B(n + 1),
+                    RC(n) => RC(plus_one(n)?),
+                    A(n) => A(plus_one(n)?),
+                    B(n) => B(plus_one(n)?),
                 })
             } else {
-                *high.0.release.last_mut().unwrap() += 1;
+                // unwrap safe because: pep440 versions always have a release segment
+                let last = high.0.release.last_mut().unwrap();
+                *last = plus_one(*last)?;
             }
             high.0.dev = Some(0);
             match self {
@@ -174,23 +187,23 @@ impl CompareOp {
             }
             match self {
                 // These two are simple
-                LessThanEqual => vec![VERSION_ZERO.clone()..version.next()],
+                LessThanEqual => vec![VERSION_ZERO.clone()..version.next()?],
                 GreaterThanEqual => vec![version..VERSION_INFINITY.clone()],
                 // These are also pretty simple, because we took care of the wildcard
                 // cases up above.
-                Equal => vec![version.clone()..version.next()],
+                Equal => vec![version.clone()..version.next()?],
                 NotEqual => vec![
                     VERSION_ZERO.clone()..version.clone(),
-                    version.next()..VERSION_INFINITY.clone(),
+                    version.next()?..VERSION_INFINITY.clone(),
                 ],
                 // "The exclusive ordered comparison >V MUST NOT allow a post-release of
                 // the given version unless V itself is a post release."
                 StrictlyGreaterThan => {
                     let mut low = version.clone();
-                    if let Some(dev) = &version.0.dev {
-                        low.0.dev = Some(dev + 1);
-                    } else if let Some(post) = &version.0.post {
-                        low.0.post = Some(post + 1);
+                    if let Some(dev) = version.0.dev {
+                        low.0.dev = Some(plus_one(dev)?);
+                    } else if let Some(post) = version.0.post {
+                        low.0.post = Some(plus_one(post)?);
                     } else {
                         // Otherwise, want to increment either the pre-release (a0 ->
                         // a1), or the "last" release segment. But working with
@@ -235,7 +248,8 @@ impl CompareOp {
                     // Unwraps here are safe because we confirmed that the vector has at
                     // least 2 elements above.
                     new_max.0.release.pop().unwrap();
-                    *new_max.0.release.last_mut().unwrap() += 1;
+                    let last = new_max.0.release.last_mut().unwrap();
+                    *last = plus_one(*last)?;
                     vec![version..new_max]
                 }
             }
@@ -297,4 +311,35 @@ mod test {
             assert!(!specs.satisfied_by(&version).unwrap());
         }
     }
+
+    #[test]
+    fn test_specifier_overflow() {
+        for spec in [
+            "== 1.4294967295.*",
+            "== 1.0.post4294967295.*",
+            "> 1.0.dev4294967295",
+            "~= 1.4294967295.0",
+            // these need the version right after it (see Version::next)
+            "<= 1.0.post4294967295",
+            "== 1.0.dev4294967295",
+            "!= 1.0.post4294967295",
+        ] {
+            let specs: Specifiers = spec.try_into().unwrap();
+            assert!(specs.0[0].to_ranges().is_err(), "{spec}");
+        }
+        let next = |v: &str| Version::try_from(v)?.next().map(|v| v.to_string());
+        assert_eq!(next("1.0").unwrap(), "1.0.post0.dev0");
+        assert_eq!(next("1.0.post4294967294").unwrap(), "1.0.post4294967295");
+        assert!(next("1.0.post4294967295").is_err());
+        // the other pieces can be as big as they like
+        assert!(next("4294967295.0").is_ok());
+    }
+
+    #[test]
+    fn test_empty_specifiers_roundtrip() {
+        let empty = Specifiers::default();
+        let parsed: Specifiers = empty.to_string().as_str().try_into().unwrap();
+        assert_eq!(parsed, empty);
+        assert!(parsed.satisfied_by(&"1.0".try_into().unwrap()).unwrap());
+    }
 }
@@ -1,3 +1,4 @@
+use super::specifier::plus_one;
 use crate::prelude::*;
 
 // We lean on the 'pep440' crate for the heavy lifting part of representing versions,
@@ -37,7 +38,7 @@ impl Version {
     }
 
     /// Returns the smallest PEP 440 version that is larger than self.
-    pub fn next(&self) -> Version {
+    pub fn next(&self) -> Result<Version> {
         let mut new = self.clone();
         // The rules are here:
         //
@@ -53,15 +54,18 @@ impl Version {
         //
         // - You *can* attach a .postN after anything else. And a .devN after that. So
         // to get the next possible value, attach a .post0.dev0.
+        //
+        // (If N is already u32::MAX, there's no next version we can represent, so
+        // that's an error, same as for the wildcard bounds in specifier.rs.)
         if let Some(dev) = &mut new.0.dev {
-            *dev += 1;
+            *dev = plus_one(*dev)?;
         } else if let Some(post) = &mut new.0.post {
-            *post += 1;
+            *post = plus_one(*post)?;
         } else {
             new.0.post = Some(0);
             new.0.dev = Some(0);
         }
-        new
+        Ok(new)
     }
 }
 
@@ -89,6 +93,9 @@ impl pubgrub::version::Version for Version {
     }
 
     fn bump(&self) -> Self {
-        self.next()
+        // Pubgrub can't take an error here. It bumps the versions it picks, and
+        // fetch_and_sort_versions never offers it one without a next version, so this
+        // is just so that it can't crash.
+        self.next().unwrap_or_else(|_| self.clone())
     }
 }
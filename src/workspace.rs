@@ -0,0 +1,336 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use fs2::FileExt;
+
+use crate::env::{Env, EnvForest};
+use crate::package_db::PackageDB;
+use crate::prelude::*;
+use crate::resolve::{Blueprint, Brief};
+use crate::transcript::Transcript;
+use crate::util::retry_interrupted;
+
+// A project's named environments, like "default", "docs", or "test" ('posy run --env
+// NAME'). Each one is a .posy/envs/NAME.json recording the Brief it was set up for,
+// the Blueprint we resolved that to, and that Blueprint's identity on the platform it
+// was set up for. The files themselves all live in the
+// EnvForest, so a named env is cheap -- the point is that asking for the same env with
+// the same Brief gets you the same Blueprint again, instead of a fresh resolve that
+// picks up whatever got released since yesterday.
+//
+// Each name also gets a NAME.lock, which we hold while deciding what the env should be
+// and recording it, so two posys running in the same project don't both resolve and
+// then clobber each other's record.
+
+const ENVS_DIR: &str = ".posy/envs";
+pub const DEFAULT_ENV_NAME: &str = "default";
+
+static ENV_NAME_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[A-Za-z0-9][A-Za-z0-9._-]*$").unwrap());
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NamedEnv {
+    pub brief: Brief,
+    pub blueprint: Blueprint,
+    // Blueprint::identity, for the platform we set it up on. A record from some other
+    // platform (e.g. a checkout that's shared with a VM) doesn't get reused.
+    pub identity: ArtifactHash,
+}
+
+pub struct ProjectWorkspace {
+    dir: PathBuf,
+    // if set, changes to the env records get recorded here, so they can be undone
+    pub transcript: Option<Arc<Transcript>>,
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if !ENV_NAME_RE.is_match(name) {
+        bail!(
+            "invalid environment name {name:?}; use letters, digits, '.', '-', and '_'"
+        );
+    }
+    Ok(())
+}
+
+impl ProjectWorkspace {
+    pub fn new(project_root: &Path) -> ProjectWorkspace {
+        ProjectWorkspace {
+            dir: project_root.join(ENVS_DIR),
+            transcript: None,
+        }
+    }
+
+    fn record_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.json"))
+    }
+
+    // Unlocked when the File is dropped.
+    fn lock(&self, name: &str) -> Result<File> {
+        fs::create_dir_all(&self.dir)?;
+        let lock = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(self.dir.join(format!("{name}.lock")))?;
+        if lock.try_lock_exclusive().is_err() {
+            info!("Waiting for another posy to finish with environment {name:?}...");
+            retry_interrupted(|| lock.lock_exclusive())?;
+        }
+        Ok(lock)
+    }
+
+    /// The names of all the environments that have been set up so far, sorted.
+    pub fn names(&self) -> Result<Vec<String>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension() == Some("json".as_ref()) {
+                if let Some(stem) = path.file_stem() {
+                    names.push(stem.to_string_lossy().into_owned());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// What `name` was last set up with, if anything.
+    pub fn read(&self, name: &str) -> Result<Option<NamedEnv>> {
+        check_name(name)?;
+        let path = self.record_path(name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        context!("Reading {}", path.display());
+        Ok(Some(serde_json::from_str(&fs::read_to_string(&path)?)?))
+    }
+
+    // caller has to hold the lock
+    fn write(&self, name: &str, record: &NamedEnv) -> Result<()> {
+        let path = self.record_path(name);
+        context!("Writing {}", path.display());
+        if let Some(transcript) = &self.transcript {
+            transcript.will_write(&path)?;
+        }
+        // tempfile + rename, so readers don't need the lock
+        let mut out = tempfile::NamedTempFile::new_in(&self.dir)?;
+        serde_json::to_writer_pretty(&mut out, record)?;
+        out.persist(&path)?;
+        Ok(())
+    }
+
+    /// Forgets about `name`. Returns false if there was no such env.
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        check_name(name)?;
+        let _lock = self.lock(name)?;
+        let path = self.record_path(name);
+        if !path.exists() {
+            return Ok(false);
+        }
+        if let Some(transcript) = &self.transcript {
+            transcript.will_write(&path)?;
+        }
+        fs::remove_file(&path)?;
+        Ok(true)
+    }
+
+    /// The blueprint for `name` on `platform` (a core tag), given that we want it to
+    /// match `brief`. If it was last set up for the same brief on the same platform,
+    /// that's the blueprint we used then. Otherwise, calls `resolve` with the old
+    /// blueprint (if any), so it can be used for hints, and records the result.
+    pub fn blueprint<F>(
+        &self,
+        name: &str,
+        brief: &Brief,
+        platform: &str,
+        resolve: F,
+    ) -> Result<Blueprint>
+    where
+        F: FnOnce(Option<&Blueprint>) -> Result<Blueprint>,
+    {
+        check_name(name)?;
+        let _lock = self.lock(name)?;
+        let old = self.read(name)?;
+        if let Some(record) = &old {
+            if &record.brief == brief
+                && record.blueprint.identity(platform) == record.identity
+            {
+                return Ok(record.blueprint.clone());
+            }
+        }
+        let blueprint = resolve(old.as_ref().map(|r| &r.blueprint))?;
+        self.write(
+            name,
+            &NamedEnv {
+                brief: brief.clone(),
+                blueprint: blueprint.clone(),
+                identity: blueprint.identity(platform),
+            },
+        )?;
+        Ok(blueprint)
+    }
+
+    /// Sets up the env called `name` for `brief` (see `blueprint`).
+    pub fn get_env(
+        &self,
+        name: &str,
+        brief: &Brief,
+        db: &PackageDB,
+        forest: &EnvForest,
+        platforms: &[&PybiPlatform],
+    ) -> Result<Env> {
+        context!("Setting up environment {name:?}");
+        let platform = platforms
+            .first()
+            .ok_or_else(|| eyre!("no platforms to set up the environment for"))?;
+        let blueprint = self.blueprint(name, brief, platform.core_tag(), |like| {
+            brief.resolve(db, platforms, like, &[])
+        })?;
+        forest.get_env(db, &blueprint, platforms, &[])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resolve::PinnedPackage;
+    use std::cell::Cell;
+
+    fn blueprint(version: &str) -> Blueprint {
+        Blueprint {
+            pybi: PinnedPackage {
+                name: "cpython".parse().unwrap(),
+                version: version.try_into().unwrap(),
+                hashes: vec![],
+                url: None,
+            },
+            wheels: vec![],
+            local: vec![],
+            marker_expressions: Default::default(),
+            python_fallback: None,
+            annotations: Default::default(),
+            claimed_publishers: Default::default(),
+            platform_tags: Default::default(),
+            source_builds: Default::default(),
+            warnings: Vec::new(),
+            policy: Default::default(),
+            python_flags: Vec::new(),
+        }
+    }
+
+    const LINUX: &str = "manylinux_2_17_x86_64";
+
+    #[test]
+    fn test_project_workspace() {
+        let tmp = tempfile::tempdir().unwrap();
+        let workspace = ProjectWorkspace::new(tmp.path());
+        assert!(workspace.names().unwrap().is_empty());
+        assert!(workspace.read(DEFAULT_ENV_NAME).unwrap().is_none());
+
+        let brief = Brief {
+            python: "cpython >= 3.10".parse().unwrap(),
+            requirements: vec!["trio".parse().unwrap()],
+            allow_pre: Default::default(),
+            strategy: Default::default(),
+            keep_pinned_prereleases: false,
+            constraints: vec![],
+            local_requirements: vec![],
+            no_source_builds: false,
+            python_flags: Vec::new(),
+            python_fallbacks: vec![],
+        };
+        let resolves = Cell::new(0);
+        let resolve_to = |version: &'static str| {
+            let resolves = &resolves;
+            move |_: Option<&Blueprint>| {
+                resolves.set(resolves.get() + 1);
+                Ok(blueprint(version))
+            }
+        };
+        let version = |b: &Blueprint| b.pybi.version.to_string();
+
+        let got = workspace
+            .blueprint(DEFAULT_ENV_NAME, &brief, LINUX, resolve_to("3.10.8"))
+            .unwrap();
+        assert_eq!(version(&got), "3.10.8");
+        assert_eq!(resolves.get(), 1);
+
+        // same brief: reused, even though a resolve would give something newer now
+        let got = workspace
+            .blueprint(DEFAULT_ENV_NAME, &brief, LINUX, resolve_to("3.10.9"))
+            .unwrap();
+        assert_eq!(version(&got), "3.10.8");
+        assert_eq!(resolves.get(), 1);
+
+        // a different platform gets its own resolve
+        let got = workspace
+            .blueprint(DEFAULT_ENV_NAME, &brief, "win_amd64", resolve_to("3.10.9"))
+            .unwrap();
+        assert_eq!(version(&got), "3.10.9");
+        assert_eq!(resolves.get(), 2);
+        let record = workspace.read(DEFAULT_ENV_NAME).unwrap().unwrap();
+        assert_eq!(record.identity, got.identity("win_amd64"));
+        workspace
+            .blueprint(DEFAULT_ENV_NAME, &brief, LINUX, resolve_to("3.10.8"))
+            .unwrap();
+        assert_eq!(resolves.get(), 3);
+
+        // a different name is a different env
+        let got = workspace
+            .blueprint("docs", &brief, LINUX, resolve_to("3.10.9"))
+            .unwrap();
+        assert_eq!(version(&got), "3.10.9");
+        assert_eq!(resolves.get(), 4);
+
+        // new brief: re-resolved, with the old blueprint as hints
+        let mut changed = brief.clone();
+        changed.requirements.push("attrs".parse().unwrap());
+        let got = workspace
+            .blueprint(DEFAULT_ENV_NAME, &changed, LINUX, |like| {
+                assert_eq!(version(like.unwrap()), "3.10.8");
+                Ok(blueprint("3.11.0"))
+            })
+            .unwrap();
+        assert_eq!(version(&got), "3.11.0");
+        let record = workspace.read(DEFAULT_ENV_NAME).unwrap().unwrap();
+        assert_eq!(record.brief, changed);
+
+        assert_eq!(workspace.names().unwrap(), vec!["default", "docs"]);
+        assert!(workspace.remove("docs").unwrap());
+        assert!(!workspace.remove("docs").unwrap());
+        assert_eq!(workspace.names().unwrap(), vec!["default"]);
+
+        // a failed resolve doesn't record anything
+        assert!(workspace
+            .blueprint("test", &brief, LINUX, |_| Err(eyre!("no luck")))
+            .is_err());
+        assert!(workspace.read("test").unwrap().is_none());
+
+        for bad in ["", "../escape", "a/b", ".hidden", "has space"] {
+            assert!(workspace.read(bad).is_err());
+            assert!(workspace
+                .blueprint(bad, &brief, LINUX, resolve_to("3.10.8"))
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_project_workspace_locking() {
+        let tmp = tempfile::tempdir().unwrap();
+        let workspace = ProjectWorkspace::new(tmp.path());
+        let held = workspace.lock("test").unwrap();
+        let other = File::options()
+            .write(true)
+            .open(tmp.path().join(ENVS_DIR).join("test.lock"))
+            .unwrap();
+        assert!(other.try_lock_exclusive().is_err());
+        // other names aren't affected
+        drop(workspace.lock("docs").unwrap());
+        drop(held);
+        other.try_lock_exclusive().unwrap();
+    }
+}
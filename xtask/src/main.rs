@@ -0,0 +1,138 @@
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+const USAGE: &str = "\
+usage: cargo xtask <task>
+
+tasks:
+  trampolines [ARCH...]   rebuild the Windows trampoline .exes (default: all of x86,
+                          x86_64, arm64), and regenerate src/trampolines/prebuilt.rs
+";
+
+// (directory name, and the WindowsArch variant in src/trampolines/mod.rs, and the rust
+// target triple)
+const TRAMPOLINE_ARCHES: &[(&str, &str, &str)] = &[
+    ("x86", "X86", "i686-pc-windows-msvc"),
+    ("x86_64", "X86_64", "x86_64-pc-windows-msvc"),
+    ("arm64", "Arm64", "aarch64-pc-windows-msvc"),
+];
+
+const TRAMPOLINE_KINDS: &[&str] = &["console", "gui"];
+
+// Pinned, so that rebuilding from unchanged source gives the same .exes we have
+// checked in; CI builds them with this and fails if anything changed.
+const TRAMPOLINE_TOOLCHAIN: &str = "nightly-2024-01-15";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(|s| s.as_str()) {
+        Some("trampolines") => trampolines(&args[1..]),
+        _ => {
+            eprint!("{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    if let Err(err) = result {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
+}
+
+fn root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .unwrap()
+        .to_path_buf()
+}
+
+// The trampolines are #![no_std] and size-optimized, which currently means they need
+// nightly's -Z build-std (see posy-trampoline/README.md), and an MSVC linker for each
+// target -- so in practice, a Windows machine with the Visual Studio build tools for
+// x86/x64/ARM64, plus:
+//
+//   rustup toolchain install nightly-2024-01-15 --component rust-src \
+//     --target i686-pc-windows-msvc,x86_64-pc-windows-msvc,aarch64-pc-windows-msvc
+//
+// (Or from Linux: windows-targets ships the import libraries, so setting
+// CARGO_TARGET_<TRIPLE>_LINKER=rust-lld works, as long as there's something called
+// mt.exe on $PATH for lld-link to merge the manifest with.)
+//
+// That's more than we want to ask of everyone who builds posy, so the results get
+// checked in, and this is how to regenerate them after changing the source.
+fn trampolines(only: &[String]) -> Result<()> {
+    for arch in only {
+        if !TRAMPOLINE_ARCHES.iter().any(|(name, _, _)| name == arch) {
+            return Err(format!("unknown architecture {arch:?}").into());
+        }
+    }
+    let dir = root().join("src/trampolines/windows-trampolines");
+    let crate_dir = dir.join("posy-trampoline");
+    for (arch, _, target) in TRAMPOLINE_ARCHES {
+        if !only.is_empty() && !only.iter().any(|a| a == arch) {
+            continue;
+        }
+        eprintln!("building trampolines for {arch} ({target})");
+        let status = Command::new("cargo")
+            .arg(format!("+{TRAMPOLINE_TOOLCHAIN}"))
+            .args(["build", "--profile", "release", "--target", target])
+            .args(["-Z", "build-std=core,panic_abort,alloc"])
+            .args(["-Z", "build-std-features=compiler-builtins-mem"])
+            .current_dir(&crate_dir)
+            .status()?;
+        if !status.success() {
+            return Err(format!("building for {target} failed ({status})").into());
+        }
+        let out_dir = dir.join(arch);
+        fs::create_dir_all(&out_dir)?;
+        for kind in TRAMPOLINE_KINDS {
+            let exe = format!("posy-trampoline-{kind}.exe");
+            let built = crate_dir
+                .join("target")
+                .join(target)
+                .join("release")
+                .join(&exe);
+            fs::copy(&built, out_dir.join(&exe))?;
+        }
+    }
+    write_prebuilt(&dir)
+}
+
+// Lists whichever architectures we have .exes for, so posy builds with whatever's
+// there.
+fn write_prebuilt(dir: &Path) -> Result<()> {
+    let mut out = String::from(
+        "// Generated by 'cargo xtask trampolines'; don't edit by hand.\n\
+         use super::WindowsArch;\n\
+         \n\
+         // (arch, console trampoline, gui trampoline)\n\
+         #[rustfmt::skip]\n\
+         pub(super) const PREBUILT: &[(WindowsArch, &[u8], &[u8])] = &[\n",
+    );
+    for (arch, variant, _) in TRAMPOLINE_ARCHES {
+        let have_all = TRAMPOLINE_KINDS.iter().all(|kind| {
+            dir.join(arch)
+                .join(format!("posy-trampoline-{kind}.exe"))
+                .exists()
+        });
+        if !have_all {
+            eprintln!("no trampolines for {arch}; leaving it out");
+            continue;
+        }
+        writeln!(out, "    (\n        WindowsArch::{variant},")?;
+        for kind in TRAMPOLINE_KINDS {
+            writeln!(
+                out,
+                "        include_bytes!(\"windows-trampolines/{arch}/posy-trampoline-{kind}.exe\"),"
+            )?;
+        }
+        writeln!(out, "    ),")?;
+    }
+    out.push_str("];\n");
+    fs::write(root().join("src/trampolines/prebuilt.rs"), out)?;
+    Ok(())
+}